@@ -0,0 +1,12 @@
+-- Schema ban đầu của ExportRequests. Các cột khác được thêm ở những migration sau.
+CREATE TABLE IF NOT EXISTS ExportRequests (
+    id UUID PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    request_payload JSONB NOT NULL,
+    requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    status TEXT NOT NULL DEFAULT 'PENDING',
+    file_path TEXT NULL,
+    completed_at TIMESTAMPTZ NULL,
+    error_message TEXT NULL,
+    notification_sent BOOLEAN NOT NULL DEFAULT FALSE
+);
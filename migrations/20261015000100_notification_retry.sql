@@ -0,0 +1,8 @@
+-- Theo dõi số lần gửi lại thông báo thất bại và thời điểm được phép thử lại.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS notification_attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS notification_next_retry_at TIMESTAMPTZ NULL;
+
+CREATE INDEX IF NOT EXISTS idx_exportrequests_unsent_notifications
+    ON ExportRequests (completed_at)
+    WHERE notification_sent = FALSE;
@@ -0,0 +1,3 @@
+-- Thời điểm file export bị xóa bởi retention job (status chuyển sang EXPIRED).
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS expired_at TIMESTAMPTZ NULL;
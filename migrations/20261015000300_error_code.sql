@@ -0,0 +1,6 @@
+-- Mã lỗi máy đọc được (ví dụ QUOTA_EXCEEDED) bên cạnh error_message.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS error_code TEXT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_exportrequests_user_requested_at
+    ON ExportRequests (user_id, requested_at);
@@ -0,0 +1,4 @@
+-- Checksum và kích thước file được ghi ngay sau khi tạo, dùng để tái sử dụng file khi request bị xử lý lại.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS file_checksum TEXT NULL,
+    ADD COLUMN IF NOT EXISTS file_size_bytes BIGINT NULL;
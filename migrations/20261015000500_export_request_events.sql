@@ -0,0 +1,12 @@
+-- Lịch sử chuyển trạng thái của từng ExportRequest.
+CREATE TABLE IF NOT EXISTS export_request_events (
+    id BIGSERIAL PRIMARY KEY,
+    request_id UUID NOT NULL REFERENCES ExportRequests (id) ON DELETE CASCADE,
+    from_status TEXT NULL,
+    to_status TEXT NOT NULL,
+    detail TEXT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_export_request_events_request_id
+    ON export_request_events (request_id, created_at);
@@ -0,0 +1,3 @@
+-- Thời điểm link download hết hạn, được ghi khi export hoàn thành.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ NULL;
@@ -0,0 +1,7 @@
+-- Hash của (user_id, tham số report) để tái sử dụng file của request trùng lặp.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS params_hash TEXT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_exportrequests_params_hash
+    ON ExportRequests (params_hash, completed_at)
+    WHERE params_hash IS NOT NULL;
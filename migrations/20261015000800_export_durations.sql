@@ -0,0 +1,9 @@
+-- Thời gian xử lý, số dòng và ETA của export, dùng cho thông báo và ước lượng thời gian hoàn thành.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS duration_ms BIGINT NULL,
+    ADD COLUMN IF NOT EXISTS row_count BIGINT NULL,
+    ADD COLUMN IF NOT EXISTS estimated_completion_at TIMESTAMPTZ NULL;
+
+CREATE INDEX IF NOT EXISTS idx_exportrequests_completed_durations
+    ON ExportRequests (completed_at DESC)
+    WHERE duration_ms IS NOT NULL AND row_count IS NOT NULL;
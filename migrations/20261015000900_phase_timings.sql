@@ -0,0 +1,6 @@
+-- Thời gian (ms) của từng giai đoạn xử lý, phục vụ điều tra request chậm.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS db_fetch_ms BIGINT NULL,
+    ADD COLUMN IF NOT EXISTS db_query_ms BIGINT NULL,
+    ADD COLUMN IF NOT EXISTS file_generation_ms BIGINT NULL,
+    ADD COLUMN IF NOT EXISTS notify_ms BIGINT NULL;
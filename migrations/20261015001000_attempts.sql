@@ -0,0 +1,6 @@
+-- Số lần xử lý và lỗi gần nhất của từng request (phục vụ retry và điều tra lỗi),
+-- tách biệt với error_message của trạng thái cuối.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS last_attempt_at TIMESTAMPTZ NULL,
+    ADD COLUMN IF NOT EXISTS last_error TEXT NULL;
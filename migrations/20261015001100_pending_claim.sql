@@ -0,0 +1,8 @@
+-- Claim request trực tiếp từ bảng (khi không dùng Kafka): ưu tiên và thời điểm được thử lại.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS priority INT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS next_retry_at TIMESTAMPTZ NULL;
+
+CREATE INDEX IF NOT EXISTS idx_exportrequests_pending_claim
+    ON ExportRequests (priority DESC, requested_at)
+    WHERE status = 'PENDING';
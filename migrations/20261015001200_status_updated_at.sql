@@ -0,0 +1,10 @@
+-- Thời điểm chuyển trạng thái gần nhất, dùng để phát hiện request bị kẹt ở PROCESSING.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS status_updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+UPDATE ExportRequests
+SET status_updated_at = COALESCE(expired_at, completed_at, requested_at);
+
+CREATE INDEX IF NOT EXISTS idx_exportrequests_stuck_processing
+    ON ExportRequests (status_updated_at)
+    WHERE status = 'PROCESSING';
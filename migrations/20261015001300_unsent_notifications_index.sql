@@ -0,0 +1,3 @@
+-- Keyset pagination của danh sách thông báo chưa gửi theo (completed_at, id).
+CREATE INDEX IF NOT EXISTS idx_exportrequests_notification_sent_completed_at
+    ON ExportRequests (notification_sent, completed_at, id);
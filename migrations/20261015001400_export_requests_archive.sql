@@ -0,0 +1,14 @@
+-- Request FAILED/EXPIRED cũ được chuyển sang bảng này (cùng cột, cùng thứ tự với ExportRequests)
+-- để bảng live nhỏ lại. Migration thêm cột vào ExportRequests phải thêm cột tương ứng vào đây.
+-- Lịch sử trạng thái (export_request_events) bị xóa theo request (ON DELETE CASCADE).
+CREATE TABLE IF NOT EXISTS export_requests_archive (LIKE ExportRequests INCLUDING DEFAULTS);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_export_requests_archive_id
+    ON export_requests_archive (id);
+
+CREATE INDEX IF NOT EXISTS idx_export_requests_archive_user_requested_at
+    ON export_requests_archive (user_id, requested_at);
+
+CREATE INDEX IF NOT EXISTS idx_exportrequests_archive_candidates
+    ON ExportRequests (status_updated_at)
+    WHERE status IN ('FAILED', 'EXPIRED') AND notification_sent = TRUE;
@@ -0,0 +1,6 @@
+-- File chỉ chứa một phần kết quả vì payload có `limit` (khác với vượt MAX_EXPORT_ROWS, khi đó request lỗi).
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS truncated_by_limit BOOLEAN NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN IF NOT EXISTS truncated_by_limit BOOLEAN NULL;
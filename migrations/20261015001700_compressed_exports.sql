@@ -0,0 +1,8 @@
+-- File export được nén thành `{request_id}.zip`: tên và kích thước của file gốc trước khi nén.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS original_file_name TEXT NULL,
+    ADD COLUMN IF NOT EXISTS original_size_bytes BIGINT NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN IF NOT EXISTS original_file_name TEXT NULL,
+    ADD COLUMN IF NOT EXISTS original_size_bytes BIGINT NULL;
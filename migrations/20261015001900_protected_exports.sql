@@ -0,0 +1,6 @@
+-- File export được bảo vệ bằng mật khẩu (`protection` trong payload). Chỉ lưu cờ, không bao giờ lưu mật khẩu.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS is_protected BOOLEAN NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN IF NOT EXISTS is_protected BOOLEAN NULL;
@@ -0,0 +1,6 @@
+-- Tên file hiển thị cho người tải về (FILENAME_TEMPLATE), gửi kèm thông báo để đặt Content-Disposition.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS file_name TEXT NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN IF NOT EXISTS file_name TEXT NULL;
@@ -0,0 +1,8 @@
+-- File export được mã hóa khi lưu (ENCRYPTION_KEY) và key id đã dùng, để giải mã sau khi đổi khóa.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS is_encrypted BOOLEAN NULL,
+    ADD COLUMN IF NOT EXISTS encryption_key_id TEXT NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN IF NOT EXISTS is_encrypted BOOLEAN NULL,
+    ADD COLUMN IF NOT EXISTS encryption_key_id TEXT NULL;
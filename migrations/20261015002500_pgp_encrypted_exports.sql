@@ -0,0 +1,6 @@
+-- Fingerprint khóa PGP của người nhận mà file export được mã hóa tới (`encryption` của payload).
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS pgp_key_fingerprint TEXT NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN IF NOT EXISTS pgp_key_fingerprint TEXT NULL;
@@ -0,0 +1,6 @@
+-- Đường dẫn các phần từ phần 2 trở đi của file bị chia theo MAX_FILE_SIZE_BYTES (mảng JSON).
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS file_parts JSONB NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN IF NOT EXISTS file_parts JSONB NULL;
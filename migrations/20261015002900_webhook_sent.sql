@@ -0,0 +1,6 @@
+-- Webhook đã gửi được nhưng email (delivery.type = email) thất bại: lần gửi lại chỉ gửi email.
+ALTER TABLE ExportRequests
+    ADD COLUMN IF NOT EXISTS webhook_sent BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN IF NOT EXISTS webhook_sent BOOLEAN NOT NULL DEFAULT FALSE;
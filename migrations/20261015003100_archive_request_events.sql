@@ -0,0 +1,14 @@
+-- Lịch sử trạng thái được chuyển sang export_request_events_archive cùng với request (archive_requests)
+-- thay vì bị xóa theo ON DELETE CASCADE. Khóa ngoại không còn CASCADE: xóa request mà chưa chuyển
+-- lịch sử của nó đi sẽ thất bại thay vì âm thầm mất lịch sử.
+CREATE TABLE IF NOT EXISTS export_request_events_archive (LIKE export_request_events INCLUDING DEFAULTS);
+
+CREATE INDEX IF NOT EXISTS idx_export_request_events_archive_request_id
+    ON export_request_events_archive (request_id, created_at);
+
+ALTER TABLE export_request_events
+    DROP CONSTRAINT IF EXISTS export_request_events_request_id_fkey;
+
+ALTER TABLE export_request_events
+    ADD CONSTRAINT export_request_events_request_id_fkey
+    FOREIGN KEY (request_id) REFERENCES ExportRequests (id);
@@ -0,0 +1,43 @@
+-- Schema của ExportRequests cho MySQL/MariaDB (cargo feature `mysql`), tương đương các migration Postgres.
+-- UUID được lưu dạng BINARY(16), thời gian lưu theo UTC.
+CREATE TABLE IF NOT EXISTS ExportRequests (
+    id BINARY(16) NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    request_payload JSON NOT NULL,
+    requested_at DATETIME(6) NOT NULL,
+    status VARCHAR(32) NOT NULL,
+    file_path TEXT NULL,
+    completed_at DATETIME(6) NULL,
+    error_message TEXT NULL,
+    notification_sent BOOLEAN NOT NULL DEFAULT FALSE,
+    notification_attempts INT NOT NULL DEFAULT 0,
+    notification_next_retry_at DATETIME(6) NULL,
+    expired_at DATETIME(6) NULL,
+    error_code VARCHAR(64) NULL,
+    file_checksum CHAR(64) NULL,
+    file_size_bytes BIGINT NULL,
+    expires_at DATETIME(6) NULL,
+    params_hash CHAR(64) NULL,
+    duration_ms BIGINT NULL,
+    row_count BIGINT NULL,
+    estimated_completion_at DATETIME(6) NULL,
+    db_fetch_ms BIGINT NULL,
+    db_query_ms BIGINT NULL,
+    file_generation_ms BIGINT NULL,
+    notify_ms BIGINT NULL,
+    INDEX idx_exportrequests_unsent_notifications (notification_sent, completed_at),
+    INDEX idx_exportrequests_user_requested_at (user_id, requested_at),
+    INDEX idx_exportrequests_params_hash (params_hash, completed_at)
+);
+
+CREATE TABLE IF NOT EXISTS export_request_events (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    request_id BINARY(16) NOT NULL,
+    from_status VARCHAR(32) NULL,
+    to_status VARCHAR(32) NOT NULL,
+    detail TEXT NULL,
+    created_at DATETIME(6) NOT NULL,
+    INDEX idx_export_request_events_request_id (request_id, created_at),
+    CONSTRAINT fk_export_request_events_request
+        FOREIGN KEY (request_id) REFERENCES ExportRequests (id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- Tương đương migrations/20261015001000_attempts.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN last_attempt_at DATETIME(6) NULL,
+    ADD COLUMN last_error TEXT NULL;
@@ -0,0 +1,5 @@
+-- Tương đương migrations/20261015001100_pending_claim.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN priority INT NOT NULL DEFAULT 0,
+    ADD COLUMN next_retry_at DATETIME(6) NULL,
+    ADD INDEX idx_exportrequests_pending_claim (status, priority, requested_at);
@@ -0,0 +1,7 @@
+-- Tương đương migrations/20261015001200_status_updated_at.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN status_updated_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    ADD INDEX idx_exportrequests_stuck_processing (status, status_updated_at);
+
+UPDATE ExportRequests
+SET status_updated_at = COALESCE(expired_at, completed_at, requested_at);
@@ -0,0 +1,3 @@
+-- Tương đương migrations/20261015001300_unsent_notifications_index.sql.
+CREATE INDEX idx_exportrequests_notification_sent_completed_at
+    ON ExportRequests (notification_sent, completed_at, id);
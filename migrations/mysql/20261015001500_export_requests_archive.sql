@@ -0,0 +1,5 @@
+-- Tương đương migrations/20261015001400_export_requests_archive.sql.
+CREATE TABLE IF NOT EXISTS export_requests_archive LIKE ExportRequests;
+
+ALTER TABLE ExportRequests
+    ADD INDEX idx_exportrequests_archive_candidates (status, notification_sent, status_updated_at);
@@ -0,0 +1,6 @@
+-- Tương đương migrations/20261015001500_truncated_by_limit.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN truncated_by_limit BOOLEAN NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN truncated_by_limit BOOLEAN NULL;
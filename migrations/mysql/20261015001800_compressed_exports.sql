@@ -0,0 +1,8 @@
+-- Tương đương migrations/20261015001700_compressed_exports.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN original_file_name VARCHAR(255) NULL,
+    ADD COLUMN original_size_bytes BIGINT NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN original_file_name VARCHAR(255) NULL,
+    ADD COLUMN original_size_bytes BIGINT NULL;
@@ -0,0 +1,6 @@
+-- Tương đương migrations/20261015001900_protected_exports.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN is_protected BOOLEAN NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN is_protected BOOLEAN NULL;
@@ -0,0 +1,6 @@
+-- Tương đương migrations/20261015002100_export_file_names.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN file_name VARCHAR(255) NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN file_name VARCHAR(255) NULL;
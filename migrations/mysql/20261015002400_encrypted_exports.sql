@@ -0,0 +1,8 @@
+-- Tương đương migrations/20261015002300_encrypted_exports.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN is_encrypted BOOLEAN NULL,
+    ADD COLUMN encryption_key_id VARCHAR(64) NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN is_encrypted BOOLEAN NULL,
+    ADD COLUMN encryption_key_id VARCHAR(64) NULL;
@@ -0,0 +1,6 @@
+-- Tương đương migrations/20261015002500_pgp_encrypted_exports.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN pgp_key_fingerprint VARCHAR(64) NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN pgp_key_fingerprint VARCHAR(64) NULL;
@@ -0,0 +1,6 @@
+-- Tương đương migrations/20261015002700_split_export_files.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN file_parts JSON NULL;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN file_parts JSON NULL;
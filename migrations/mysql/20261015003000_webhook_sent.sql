@@ -0,0 +1,6 @@
+-- Tương đương migrations/20261015002900_webhook_sent.sql.
+ALTER TABLE ExportRequests
+    ADD COLUMN webhook_sent BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE export_requests_archive
+    ADD COLUMN webhook_sent BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,9 @@
+-- Tương đương migrations/20261015003100_archive_request_events.sql.
+CREATE TABLE IF NOT EXISTS export_request_events_archive LIKE export_request_events;
+
+ALTER TABLE export_request_events
+    DROP FOREIGN KEY fk_export_request_events_request;
+
+ALTER TABLE export_request_events
+    ADD CONSTRAINT fk_export_request_events_request
+    FOREIGN KEY (request_id) REFERENCES ExportRequests (id);
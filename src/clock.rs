@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use std::time::{Duration, Instant};
+
+/// Nguồn thời gian của service. Mọi logic phụ thuộc thời gian (hết hạn, backoff, đo thời gian xử lý)
+/// đi qua trait này thay vì gọi trực tiếp `Utc::now()` / `Instant::now()`, để có thể thay bằng đồng hồ giả lập.
+pub trait Clock: Send + Sync + 'static {
+    fn now_utc(&self) -> DateTime<Utc>;
+
+    fn now_instant(&self) -> Instant;
+
+    /// Thời gian đã trôi qua kể từ `since`, tính theo chính đồng hồ này.
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now_instant().saturating_duration_since(since)
+    }
+}
+
+/// Đồng hồ hệ thống, dùng khi chạy thật.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Đồng hồ giả lập cho test: đứng yên cho tới khi được `advance`, để kiểm tra hết hạn và backoff chính xác.
+#[cfg(test)]
+pub struct MockClock {
+    now: std::sync::Mutex<(DateTime<Utc>, Instant)>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(now_utc: DateTime<Utc>) -> Self {
+        Self {
+            now: std::sync::Mutex::new((now_utc, Instant::now())),
+        }
+    }
+
+    /// Tiến cả hai đồng hồ (UTC và Instant) thêm `by`.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += chrono::Duration::from_std(by).expect("duration fits in chrono::Duration");
+        now.1 += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now_utc(&self) -> DateTime<Utc> {
+        self.now.lock().unwrap().0
+    }
+
+    fn now_instant(&self) -> Instant {
+        self.now.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn mock_clock_stands_still_until_advanced() {
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let clock = MockClock::new(start);
+        let since = clock.now_instant();
+
+        assert_eq!(clock.now_utc(), start);
+        assert_eq!(clock.elapsed(since), Duration::ZERO);
+
+        clock.advance(Duration::from_secs(90));
+
+        assert_eq!(clock.now_utc(), Utc.with_ymd_and_hms(2024, 5, 1, 12, 1, 30).unwrap());
+        assert_eq!(clock.elapsed(since), Duration::from_secs(90));
+    }
+}
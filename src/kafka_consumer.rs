@@ -1,6 +1,8 @@
 use anyhow::{Context, Result};
 use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::OwnedMessage;
 use rdkafka::ClientConfig;
+use std::any::Any;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{error, info, instrument, Span, warn};
@@ -74,22 +76,10 @@ where
                 let span_clone = Span::current(); // Capture the current span context
 
                 tokio::spawn(async move {
-                    if let Err(e) = export_service_clone
-                        .process_export_request(request_id, span_clone)
-                        .await
-                    {
-                        error!("❌ Error processing export request {}: {:?}", request_id, e);
-                    }
+                    handle_request(export_service_clone, request_id, span_clone).await;
 
                     // Quan trọng: Commit offset Kafka sau khi xử lý hoàn tất (thành công hoặc thất bại)
-                    if let Err(e) = consumer_clone
-                        .commit_message(&owned_message, rdkafka::consumer::CommitMode::Async)
-                        .await
-                    {
-                        error!("Failed to commit Kafka message offset for request {}: {:?}", request_id, e);
-                    } else {
-                        info!("🔗 Committed Kafka message for request {}.", request_id);
-                    }
+                    commit_offset(&consumer_clone, &owned_message, request_id).await;
                 });
             }
             Err(e) => {
@@ -98,4 +88,184 @@ where
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Xử lý một request: lấy khóa, chạy export trong task riêng rồi trả khóa.
+/// Worker khác đang xử lý cùng request (message bị giao lại) thì bỏ qua.
+/// Không lấy được khóa vì lỗi DB: không xử lý khi không có khóa, request bị chuyển sang FAILED.
+/// Export bị panic thì request vẫn được chuyển sang FAILED và khóa vẫn được trả.
+pub async fn handle_request<D, F, N>(export_service: Arc<ExportService<D, F, N>>, request_id: Uuid, span: Span)
+where
+    D: DbStore,
+    F: FileExporter,
+    N: Notifier,
+{
+    match export_service.lock_request(request_id).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(lock_error) => {
+            if let Err(e) = export_service.handle_lock_failure(request_id, lock_error).await {
+                error!("Failed to mark unlocked request {} as FAILED: {:?}", request_id, e);
+            }
+            return;
+        }
+    }
+
+    // Chạy xử lý trong task riêng để bắt được panic qua JoinHandle.
+    let processing_service = Arc::clone(&export_service);
+    let processing = tokio::spawn(async move {
+        processing_service
+            .process_export_request(request_id, span)
+            .await
+    });
+
+    match processing.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            error!("❌ Error processing export request {}: {:?}", request_id, e);
+        }
+        Err(join_error) if join_error.is_panic() => {
+            let panic_message = panic_message(join_error.into_panic());
+            if let Err(e) = export_service.handle_panic(request_id, &panic_message).await {
+                error!("Failed to mark panicked request {} as FAILED: {:?}", request_id, e);
+            }
+        }
+        Err(join_error) => {
+            error!("Export task for request {} was cancelled: {:?}", request_id, join_error);
+        }
+    }
+    export_service.unlock_request(request_id).await;
+}
+
+/// Commit offset Kafka của message sau khi request đã được xử lý (hoặc bỏ qua).
+async fn commit_offset(consumer: &StreamConsumer, message: &OwnedMessage, request_id: Uuid) {
+    if let Err(e) = consumer
+        .commit_message(message, rdkafka::consumer::CommitMode::Async)
+        .await
+    {
+        error!("Failed to commit Kafka message offset for request {}: {:?}", request_id, e);
+    } else {
+        info!("🔗 Committed Kafka message for request {}.", request_id);
+    }
+}
+
+/// Lấy nội dung panic (thường là `&str` hoặc `String`) để ghi log.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ExportRequest, ExportStatus, NotificationStage};
+    use crate::services::file_exporter::LocalFileExporter;
+    use crate::services::mock_exporter::{ExportFault, MockFileExporter};
+    use crate::services::mock_store::MockDbStore;
+    use crate::services::notifier::RecordingNotifier;
+
+    type TestService = ExportService<MockDbStore, MockFileExporter, RecordingNotifier>;
+
+    /// Service ghi file vào một thư mục tạm riêng (trả về để test xóa khi xong).
+    fn service(
+        db_store: &Arc<MockDbStore>,
+        notifier: &Arc<RecordingNotifier>,
+        fault: Option<ExportFault>,
+    ) -> (Arc<TestService>, std::path::PathBuf) {
+        let export_dir = std::env::temp_dir().join(format!("excel-export-test-{}", Uuid::new_v4()));
+        let config = AppConfig::for_test(&export_dir.to_string_lossy());
+        let file_exporter = MockFileExporter::new(LocalFileExporter::new(
+            config.formula_escape,
+            config.excel_max_rows_per_sheet,
+            config.parquet,
+            config.html_max_rows,
+            config.pdf.clone(),
+        ));
+        if let Some(fault) = fault {
+            file_exporter.fault_next(fault);
+        }
+        let export_service =
+            ExportService::for_test(Arc::clone(db_store), Arc::new(file_exporter), Arc::clone(notifier), config);
+        (Arc::new(export_service), export_dir)
+    }
+
+    fn pending_request(db_store: &MockDbStore, extra: serde_json::Value) -> Uuid {
+        let mut payload = serde_json::json!({"start_date": "2024-01-01", "end_date": "2024-01-31", "format": "csv"});
+        if let (Some(payload), Some(extra)) = (payload.as_object_mut(), extra.as_object()) {
+            payload.extend(extra.clone());
+        }
+        db_store.insert(ExportRequest::for_test(payload), ExportStatus::Pending)
+    }
+
+    #[tokio::test]
+    async fn panicking_export_fails_the_request_and_releases_its_lock() {
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let (export_service, export_dir) = service(&db_store, &notifier, Some(ExportFault::Panic));
+        let request_id = pending_request(&db_store, serde_json::json!({}));
+
+        handle_request(Arc::clone(&export_service), request_id, Span::none()).await;
+        let _ = std::fs::remove_dir_all(&export_dir);
+
+        let request = db_store.request(request_id).unwrap();
+        assert_eq!(request.status, ExportStatus::Failed);
+        assert_eq!(request.error_code.as_deref(), Some("PANIC"));
+        assert_eq!(notifier.statuses(request_id), vec!["FAILED".to_string()]);
+        assert_eq!(db_store.calls("unlock_request"), 1);
+        // Khóa đã được trả: worker khác lấy lại được ngay.
+        assert!(export_service.lock_request(request_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn only_one_of_two_workers_processes_a_redelivered_request() {
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        // Thông báo PROCESSING chậm giữ worker đầu tiên trong lúc xử lý, khi worker thứ hai nhận cùng request.
+        let notifier = Arc::new(RecordingNotifier::with_delay(NotificationStage::Processing, Duration::from_millis(200)));
+        let (first, first_dir) = service(&db_store, &notifier, None);
+        let (second, second_dir) = service(&db_store, &notifier, None);
+        let request_id = pending_request(&db_store, serde_json::json!({"notify_on": ["processing", "completed"]}));
+
+        tokio::join!(
+            handle_request(Arc::clone(&first), request_id, Span::none()),
+            handle_request(Arc::clone(&second), request_id, Span::none()),
+        );
+        let _ = std::fs::remove_dir_all(&first_dir);
+        let _ = std::fs::remove_dir_all(&second_dir);
+
+        assert_eq!(db_store.calls("try_lock_request"), 2);
+        assert_eq!(db_store.calls("fetch_and_update_request_status"), 1);
+        assert_eq!(db_store.calls("unlock_request"), 1);
+        assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Completed);
+        assert_eq!(notifier.statuses(request_id), vec!["PROCESSING".to_string(), "COMPLETED".to_string()]);
+        assert!(first.lock_request(request_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn redelivery_after_a_panic_takes_the_lock_and_skips_the_failed_request() {
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let (panicking, panicking_dir) = service(&db_store, &notifier, Some(ExportFault::Panic));
+        let (redelivered, redelivered_dir) = service(&db_store, &notifier, None);
+        let request_id = pending_request(&db_store, serde_json::json!({}));
+
+        handle_request(panicking, request_id, Span::none()).await;
+        handle_request(Arc::clone(&redelivered), request_id, Span::none()).await;
+        let _ = std::fs::remove_dir_all(&panicking_dir);
+        let _ = std::fs::remove_dir_all(&redelivered_dir);
+
+        // Worker thứ hai lấy được khóa (đã được trả sau panic) nhưng request đã FAILED nên không xử lý lại.
+        assert_eq!(db_store.calls("fetch_and_update_request_status"), 2);
+        assert_eq!(db_store.calls("unlock_request"), 2);
+        assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Failed);
+        assert_eq!(notifier.statuses(request_id), vec!["FAILED".to_string()]);
+        assert!(redelivered.lock_request(request_id).await.unwrap());
+    }
+}
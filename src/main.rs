@@ -1,21 +1,42 @@
+mod clock;
 mod config;
+mod error;
 mod models;
 mod services;
 mod kafka_consumer;
+mod workers;
 
 use anyhow::{Context, Result};
 use metrics_exporter_prometheus::PrometheusBuilder;
-use sqlx::PgPool;
+use sqlx::pool::PoolOptions;
+use sqlx::postgres::{PgConnectOptions, PgConnection};
+use sqlx::{Connection, Database, Pool};
+use std::future::Future;
+use std::str::FromStr;
 use std::sync::Arc;
-use tracing::{info, error};
+use std::time::{Duration, Instant};
+use tracing::{info, error, warn};
+use uuid::Uuid;
 use tracing_subscriber::{self, fmt::format::FmtSpan, EnvFilter};
 use tracing_appender::rolling::{Rotation, daily};
 
-use crate::config::AppConfig;
-use crate::services::db_store::PostgresDbStore;
-use crate::services::file_exporter::LocalFileExporter;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{AppConfig, DbPoolSettings};
+use crate::models::ExportStatus;
+use crate::services::db_store::{DbStore, PostgresDbStore, PostgresStoreSettings};
+use crate::services::metered_store::MeteredDbStore;
+use crate::services::retrying_store::RetryingDbStore;
+#[cfg(feature = "mysql")]
+use crate::services::mysql_store::{MySqlDbStore, MySqlStoreSettings};
+use crate::services::file_exporter::{FileExporter, LocalFileExporter};
+#[cfg(feature = "s3")]
+use crate::services::s3_exporter::S3FileExporter;
 use crate::services::notifier::HttpNotifier;
+use crate::services::email_delivery::{EmailDelivery, SmtpEmailTransport};
+use crate::services::encryption::{FileEncryption, ENCRYPTED_FILE_EXTENSION};
+use crate::services::pgp_encryption::PgpEncryption;
 use crate::services::export_service::ExportService;
+use crate::services::hooks::build_hooks;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -34,40 +55,582 @@ async fn main() -> Result<()> {
 
     let config = AppConfig::load().context("Failed to load application configuration")?;
 
+    // Subcommand `migrate`: chạy migration rồi thoát, không khởi động consumer.
+    // Subcommand `decrypt`: giải mã một file export đã mã hóa khi lưu rồi thoát.
+    // Subcommand `requeue`: đưa các request về PENDING rồi thoát.
+    let command = match std::env::args().nth(1).as_deref() {
+        None => Command::Serve,
+        Some("migrate") => Command::Migrate,
+        Some("decrypt") => return decrypt_file(&config, std::env::args().skip(2).collect()).await,
+        Some("requeue") => Command::Requeue(RequeueArgs::parse(std::env::args().skip(2).collect())?),
+        Some(other) => anyhow::bail!("Unknown command '{}'. Supported commands: migrate, decrypt, requeue", other),
+    };
+    if matches!(command, Command::Migrate) || (config.run_migrations && matches!(command, Command::Serve)) {
+        run_migrations(&config).await?;
+        if matches!(command, Command::Migrate) {
+            return Ok(());
+        }
+    }
+
     // --- Khởi tạo Prometheus Exporter cho Metrics ---
-    info!("📊 Metrics will be exposed on: {}", config.metrics_listen_address);
-    PrometheusBuilder::new()
-        .listen_address(config.metrics_listen_address)
-        .install()
-        .context("Failed to install Prometheus metrics exporter")?;
+    // Lệnh `requeue` chạy cạnh service đang chạy, không được chiếm cổng metrics của nó.
+    if matches!(command, Command::Serve) {
+        info!("📊 Metrics will be exposed on: {}", config.metrics_listen_address);
+        PrometheusBuilder::new()
+            .listen_address(config.metrics_listen_address)
+            .install()
+            .context("Failed to install Prometheus metrics exporter")?;
+    }
     // --------------------------------------------------
 
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
+    // Chọn implementation DbStore theo scheme của DATABASE_URL
+    if is_mysql_url(&config.db_url) {
+        #[cfg(feature = "mysql")]
+        {
+            // Pool lazy: DB chưa sẵn sàng thì `wait_for_database` chờ thay vì thoát ngay.
+            let pool = pool_options::<sqlx::MySql>(&config.db_pool)
+                .connect_lazy(&config.db_url)
+                .context("DATABASE_URL is not a valid MySQL connection string")?;
+            spawn_pool_metrics(&config, &clock, &pool, "primary");
+            let db_store = MySqlDbStore::new(
+                pool,
+                Arc::clone(&clock),
+                MySqlStoreSettings {
+                    statement_timeout_ms: config.db_statement_timeout_ms,
+                },
+            );
+            let db_store = MeteredDbStore::new(db_store, Arc::clone(&clock));
+            let db_store = Arc::new(RetryingDbStore::new(db_store, config.db_retry.clone()));
+            return run(config, clock, db_store, command).await;
+        }
+        #[cfg(not(feature = "mysql"))]
+        anyhow::bail!("DATABASE_URL points to MySQL/MariaDB, but the service was built without the `mysql` feature");
+    }
+
     // Kết nối database
-    let pool = PgPool::connect(&config.db_url)
+    // Timeout mặc định của mọi connection là timeout ngắn cho các query trạng thái;
+    // query report ghi đè bằng DB_STATEMENT_TIMEOUT_MS trong transaction riêng.
+    let connect_options = PgConnectOptions::from_str(&config.db_url)
+        .context("DATABASE_URL is not a valid Postgres connection string")?
+        .options([("statement_timeout", config.db_status_statement_timeout_ms.to_string())]);
+    // Pool lazy: DB chưa sẵn sàng thì `wait_for_database` chờ thay vì thoát ngay.
+    let pool = pool_options(&config.db_pool).connect_lazy_with(connect_options);
+    spawn_pool_metrics(&config, &clock, &pool, "primary");
+
+    // Pool riêng cho query report (read replica), để query nặng không chiếm hết connection của primary.
+    let read_pool = match &config.db_read_url {
+        Some(read_url) => {
+            let read_options = PgConnectOptions::from_str(read_url)
+                .context("DATABASE_READ_URL is not a valid Postgres connection string")?
+                .options([("statement_timeout", config.db_status_statement_timeout_ms.to_string())]);
+            let read_pool = pool_options(&config.db_pool).connect_lazy_with(read_options);
+            info!("Read replica configured for report queries.");
+            spawn_pool_metrics(&config, &clock, &read_pool, "read");
+            Some(read_pool)
+        }
+        None => None,
+    };
+
+    let db_store = PostgresDbStore::new(
+        pool,
+        read_pool,
+        Arc::clone(&clock),
+        PostgresStoreSettings {
+            page_size: config.db_page_size,
+            page_snapshot: config.db_page_snapshot,
+            statement_timeout_ms: config.db_statement_timeout_ms,
+        },
+    );
+    // Đo từng lần gọi DB (kể cả từng lần thử lại), rồi thử lại lỗi tạm thời (failover, deadlock...)
+    // thay vì làm fail các export đang chạy.
+    let db_store = MeteredDbStore::new(db_store, Arc::clone(&clock));
+    let db_store = Arc::new(RetryingDbStore::new(db_store, config.db_retry.clone()));
+    run(config, clock, db_store, command).await
+}
+
+/// Lệnh được chọn bằng tham số đầu tiên của binary.
+enum Command {
+    Serve,
+    Migrate,
+    Requeue(RequeueArgs),
+}
+
+/// `requeue [--force] <request_id>...`: đưa các request về PENDING bằng `bulk_update_status`, để pending poller
+/// (PENDING_POLL_INTERVAL_SECS) xử lý lại. Request COMPLETED chỉ được đưa về khi có `--force`.
+#[derive(Debug, PartialEq)]
+struct RequeueArgs {
+    ids: Vec<Uuid>,
+    force: bool,
+}
+
+impl RequeueArgs {
+    fn parse(args: Vec<String>) -> Result<Self> {
+        let mut requeue = RequeueArgs { ids: Vec::new(), force: false };
+        for arg in args {
+            if arg == "--force" {
+                requeue.force = true;
+            } else {
+                requeue.ids.push(Uuid::parse_str(&arg).with_context(|| format!("'{}' is not a request id", arg))?);
+            }
+        }
+        anyhow::ensure!(!requeue.ids.is_empty(), "Usage: requeue [--force] <request_id>...");
+        Ok(requeue)
+    }
+}
+
+/// Chạy `requeue`, trả về số request đã thực sự được đưa về PENDING.
+async fn requeue_requests<D: DbStore>(db_store: &D, args: &RequeueArgs) -> Result<u64> {
+    let requeued = db_store
+        .bulk_update_status(&args.ids, ExportStatus::Pending, None, args.force)
+        .await
+        .context("Failed to requeue export requests")?;
+    info!("🔁 Requeued {} of {} request(s) (force: {}).", requeued, args.ids.len(), args.force);
+    println!("Requeued {} of {} request(s).", requeued, args.ids.len());
+    Ok(requeued)
+}
+
+/// Chạy các migration được nhúng vào binary trên một connection riêng,
+/// không bị giới hạn bởi statement_timeout ngắn của pool.
+async fn run_migrations(config: &AppConfig) -> Result<()> {
+    let db_url = config.db_url.as_str();
+    info!("Running database migrations...");
+    if is_mysql_url(db_url) {
+        #[cfg(feature = "mysql")]
+        {
+            let mut conn = wait_for_database(config, "migrations", || sqlx::MySqlConnection::connect(db_url))
+                .await
+                .context("Failed to connect to MySQL database for migrations")?;
+            sqlx::migrate!("./migrations/mysql")
+                .run(&mut conn)
+                .await
+                .context("Failed to run database migrations")?;
+            conn.close().await.ok();
+            info!("Database migrations applied. ✅");
+            return Ok(());
+        }
+        #[cfg(not(feature = "mysql"))]
+        anyhow::bail!("DATABASE_URL points to MySQL/MariaDB, but the service was built without the `mysql` feature");
+    }
+
+    let mut conn = wait_for_database(config, "migrations", || PgConnection::connect(db_url))
+        .await
+        .context("Failed to connect to database for migrations")?;
+    sqlx::migrate!("./migrations")
+        .run(&mut conn)
         .await
-        .context("Failed to connect to database")?;
+        .context("Failed to run database migrations")?;
+    conn.close().await.ok();
+    info!("Database migrations applied. ✅");
+    Ok(())
+}
+
+/// `decrypt <file.enc> [output]`: giải mã file bằng ENCRYPTION_KEY/ENCRYPTION_KEYS. Mặc định ghi cạnh file,
+/// bỏ đuôi `.enc`, và không ghi đè file đã có; output `-` ghi ra stdout (downloader stream thẳng cho người dùng,
+/// và phải bỏ kết quả khi lệnh thoát với lỗi).
+async fn decrypt_file(config: &AppConfig, args: Vec<String>) -> Result<()> {
+    let encryption = config
+        .encryption
+        .as_ref()
+        .map(FileEncryption::new)
+        .context("ENCRYPTION_KEY must be set to decrypt files")?;
+    let (input, output) = match args.as_slice() {
+        [input] => {
+            let output = input
+                .strip_suffix(&format!(".{}", ENCRYPTED_FILE_EXTENSION))
+                .context("Input file does not end with .enc; pass the output path explicitly")?;
+            (input.clone(), output.to_string())
+        }
+        [input, output] => (input.clone(), output.clone()),
+        _ => anyhow::bail!("Usage: decrypt <file.enc> [output|-]"),
+    };
+    tokio::task::spawn_blocking(move || {
+        if output == "-" {
+            let file = std::fs::File::open(&input).with_context(|| format!("Failed to open {}", input))?;
+            let key_id = encryption.decrypt(std::io::BufReader::new(file), std::io::stdout().lock())?;
+            info!("🔓 Decrypted {} to stdout (key '{}').", input, key_id);
+            return Ok(());
+        }
+        anyhow::ensure!(!std::path::Path::new(&output).exists(), "{} already exists", output);
+        let partial = format!("{}.partial", output);
+        let result = encryption
+            .decrypt_file(&input, &partial)
+            .and_then(|key_id| std::fs::rename(&partial, &output).context("Failed to move decrypted file into place").map(|()| key_id));
+        match result {
+            Ok(key_id) => {
+                info!("🔓 Decrypted {} to {} (key '{}').", input, output, key_id);
+                Ok(())
+            }
+            Err(e) => {
+                std::fs::remove_file(&partial).ok();
+                Err(e)
+            }
+        }
+    })
+    .await
+    .context("Decrypt task panicked")?
+}
+
+fn is_mysql_url(db_url: &str) -> bool {
+    db_url.starts_with("mysql://") || db_url.starts_with("mariadb://")
+}
+
+/// PoolOptions theo cấu hình DB_* (dùng chung cho Postgres, read replica và MySQL).
+fn pool_options<DB: Database>(settings: &DbPoolSettings) -> PoolOptions<DB> {
+    PoolOptions::new()
+        .max_connections(settings.max_connections)
+        .min_connections(settings.min_connections)
+        .acquire_timeout(Duration::from_secs(settings.acquire_timeout_secs))
+        .idle_timeout(Duration::from_secs(settings.idle_timeout_secs))
+        .max_lifetime(Duration::from_secs(settings.max_lifetime_secs))
+        .test_before_acquire(settings.test_before_acquire)
+}
+
+fn spawn_pool_metrics<DB: Database>(config: &AppConfig, clock: &Arc<dyn Clock>, pool: &Pool<DB>, role: &'static str) {
+    tokio::spawn(workers::pool_metrics::run_pool_metrics_worker(
+        Arc::clone(clock),
+        pool.clone(),
+        role,
+        config.db_pool.metrics_interval_secs,
+    ));
+}
+
+/// Khoảng chờ trước lần thử lại đầu tiên khi chờ DB lúc khởi động.
+const DB_STARTUP_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Chạy `run` cho đến khi thành công hoặc hết DB_STARTUP_TIMEOUT_SECS, với backoff tăng gấp đôi (tối đa
+/// DB_STARTUP_BACKOFF_MAX_SECS): DB khởi động chậm hơn service (deploy cùng lúc, Postgres đang restart)
+/// thì service chờ thay vì thoát và bị restart liên tục.
+async fn wait_for_database<T, E, F, Fut>(config: &AppConfig, operation: &str, run: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+    E: Into<anyhow::Error>,
+{
+    retry_until_deadline(
+        Duration::from_secs(config.db_startup_timeout_secs),
+        Duration::from_secs(config.db_startup_backoff_max_secs),
+        operation,
+        run,
+    )
+    .await
+}
+
+/// Vòng chờ của `wait_for_database`, với thời hạn và backoff tối đa truyền vào.
+async fn retry_until_deadline<T, E, F, Fut>(budget: Duration, max_backoff: Duration, operation: &str, mut run: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+    E: Into<anyhow::Error>,
+{
+    let started_at = Instant::now();
+    let mut backoff = DB_STARTUP_INITIAL_BACKOFF.min(max_backoff);
+    let mut attempt = 1;
+    loop {
+        let error = match run().await {
+            Ok(value) => return Ok(value),
+            Err(e) => e.into(),
+        };
+        let remaining = budget.saturating_sub(started_at.elapsed());
+        if remaining.is_zero() {
+            return Err(error.context(format!(
+                "Database is still unavailable for {} after {} attempts in {}s",
+                operation,
+                attempt,
+                budget.as_secs()
+            )));
+        }
+        let delay = backoff.min(remaining);
+        warn!(
+            "Database is not available yet for {} (attempt {}, {:.1}s of the {}s startup budget left): {:#}. Retrying in {:?}.",
+            operation,
+            attempt,
+            remaining.as_secs_f64(),
+            budget.as_secs(),
+            error,
+            delay
+        );
+        tokio::time::sleep(delay).await;
+        backoff = (backoff * 2).min(max_backoff);
+        attempt += 1;
+    }
+}
+
+/// Chọn FileExporter (S3 khi S3_BUCKET được set, nếu không là thư mục local) rồi chạy service với DbStore đã chọn.
+/// Lệnh `requeue` chỉ chờ DB sẵn sàng, cập nhật trạng thái rồi thoát.
+async fn run<D: DbStore>(config: AppConfig, clock: Arc<dyn Clock>, db_store: Arc<D>, command: Command) -> Result<()> {
+    #[cfg(not(feature = "s3"))]
+    anyhow::ensure!(config.s3.is_none(), "S3_BUCKET is set, but the service was built without the `s3` feature");
+    #[cfg(not(feature = "pgp"))]
+    anyhow::ensure!(
+        config.pgp_recipient_keys.is_empty(),
+        "PGP_RECIPIENT_KEYS is set, but the service was built without the `pgp` feature"
+    );
+
+    // Kafka chỉ được subscribe khi DB đã dùng được.
+    wait_for_database(&config, "ping", || db_store.ping()).await?;
     info!("Database connection established. 🎉");
+    if let Command::Requeue(args) = command {
+        return requeue_requests(db_store.as_ref(), &args).await.map(|_| ());
+    }
 
+    let local_exporter = LocalFileExporter::new(
+        config.formula_escape,
+        config.excel_max_rows_per_sheet,
+        config.parquet,
+        config.html_max_rows,
+        config.pdf.clone(),
+    );
+    // Với S3, EXCEL_EXPORT_PATH chỉ là thư mục tạm để tạo file trước khi upload.
+    #[cfg(feature = "s3")]
+    if let Some(s3) = &config.s3 {
+        let file_exporter = S3FileExporter::new(local_exporter, s3, &config.excel_export_path).await?;
+        return serve(config, clock, db_store, Arc::new(file_exporter)).await;
+    }
+    serve(config, clock, db_store, Arc::new(local_exporter)).await
+}
+
+/// Khởi tạo các service, worker nền và chạy Kafka consumer với DbStore và FileExporter đã chọn.
+async fn serve<D: DbStore, F: FileExporter>(
+    config: AppConfig,
+    clock: Arc<dyn Clock>,
+    db_store: Arc<D>,
+    file_exporter: Arc<F>,
+) -> Result<()> {
     // Khởi tạo các service implementation
-    let db_store = Arc::new(PostgresDbStore::new(pool));
-    let file_exporter = Arc::new(LocalFileExporter);
     let notifier = Arc::new(HttpNotifier::new(config.notification_service_url.clone()));
 
+    let email_delivery = match &config.smtp {
+        Some(smtp) => {
+            info!("📧 Email delivery enabled via SMTP host {}.", smtp.host);
+            let transport = SmtpEmailTransport::new(smtp)?;
+            let encryption = config.encryption.as_ref().map(FileEncryption::new);
+            Some(Arc::new(EmailDelivery::new(Box::new(transport), smtp, encryption)?))
+        }
+        None => None,
+    };
+
+    // Khóa PGP của người nhận được đọc khi khởi động để khóa sai hoặc hết hạn không đợi đến lúc export.
+    let pgp_encryption = if config.pgp_recipient_keys.is_empty() {
+        None
+    } else {
+        let pgp = PgpEncryption::load(&config.pgp_recipient_keys)?;
+        for key_id in config.pgp_recipient_keys.keys() {
+            info!("🔏 PGP recipient '{}' loaded (key {}).", key_id, pgp.fingerprint(key_id).unwrap_or_default());
+        }
+        Some(pgp)
+    };
+
+    // SQL của dataset tùy chỉnh được prepare ngay khi khởi động để lỗi cấu hình không đợi đến lúc export.
+    for (name, dataset) in &config.custom_datasets {
+        let columns = db_store
+            .prepare_dataset(&dataset.sql, &dataset.params)
+            .await
+            .with_context(|| format!("CUSTOM_DATASETS entry '{}' is invalid", name))?;
+        info!("📐 Custom dataset '{}' validated ({} columns).", name, columns.len());
+    }
+
+    // Giả định notification_service_url cũng là base URL cho file downloads
+    let config = Arc::new(config);
+
     // Khởi tạo ExportService với các dependency đã được inject
     let export_service = Arc::new(ExportService::new(
-        db_store,
-        file_exporter,
+        Arc::clone(&db_store),
+        Arc::clone(&file_exporter),
+        Arc::clone(&notifier),
+        email_delivery.clone(),
+        pgp_encryption,
+        Arc::clone(&config),
+        Arc::clone(&clock),
+        build_hooks(&config.export_hooks)?,
+    ));
+
+    // Worker nền gửi lại các thông báo thất bại trước đó
+    tokio::spawn(workers::notification_retry::run_notification_retry_worker(
+        Arc::clone(&config),
+        Arc::clone(&clock),
+        Arc::clone(&db_store),
+        Arc::clone(&file_exporter),
         notifier,
-        config.excel_export_path.clone(),
-        // Giả định notification_service_url cũng là base URL cho file downloads
-        config.notification_service_url.clone()
+        email_delivery,
     ));
 
+    // Gauge số request hiện có theo trạng thái (backlog), lấy từ DB
+    tokio::spawn(workers::status_gauges::run_status_gauges_worker(
+        Arc::clone(&db_store),
+        config.status_gauges_interval_secs,
+    ));
+
+    // Worker chuyển request cũ sang bảng archive (chỉ chạy khi EXPORT_ARCHIVE_AFTER_DAYS được set)
+    if let Some(archive_after_days) = config.export_archive_after_days {
+        tokio::spawn(workers::archive::run_archive_worker(
+            Arc::clone(&config),
+            Arc::clone(&clock),
+            archive_after_days,
+            Arc::clone(&db_store),
+        ));
+    } else {
+        info!("EXPORT_ARCHIVE_AFTER_DAYS not set, archive worker disabled.");
+    }
+
+    // Worker xử lý request PENDING lấy thẳng từ bảng (chỉ chạy khi PENDING_POLL_INTERVAL_SECS được set)
+    if let Some(interval_secs) = config.pending_poll_interval_secs {
+        tokio::spawn(workers::pending_poll::run_pending_poll_worker(
+            interval_secs,
+            config.pending_poll_batch_size,
+            Arc::clone(&db_store),
+            Arc::clone(&export_service),
+        ));
+    } else {
+        info!("PENDING_POLL_INTERVAL_SECS not set, pending request poller disabled.");
+    }
+
+    // Worker đưa request bị kẹt ở PROCESSING về PENDING (chỉ chạy khi STUCK_PROCESSING_AFTER_SECS được set)
+    if let Some(stuck_after_secs) = config.stuck_processing_after_secs {
+        tokio::spawn(workers::stuck_recovery::run_stuck_recovery_worker(
+            Arc::clone(&config),
+            stuck_after_secs,
+            Arc::clone(&db_store),
+        ));
+    } else {
+        info!("STUCK_PROCESSING_AFTER_SECS not set, stuck request recovery disabled.");
+    }
+
+    // Worker hủy multipart upload dở dang trên S3 (chỉ chạy khi S3_BUCKET được set)
+    if config.s3.is_some() {
+        tokio::spawn(workers::upload_cleanup::run_upload_cleanup_worker(
+            Arc::clone(&clock),
+            Arc::clone(&file_exporter),
+        ));
+    }
+
+    // Worker dọn dẹp file export quá hạn (chỉ chạy khi EXPORT_RETENTION_DAYS được set)
+    if let Some(retention_days) = config.export_retention_days {
+        tokio::spawn(workers::retention::run_retention_worker(
+            Arc::clone(&config),
+            clock,
+            retention_days,
+            db_store,
+            file_exporter,
+        ));
+    } else {
+        info!("EXPORT_RETENTION_DAYS not set, retention worker disabled.");
+    }
+
     // Chạy Kafka consumer (bây giờ nó chỉ tập trung vào việc nhận message và ủy quyền xử lý)
-    if let Err(e) = kafka_consumer::run_kafka_consumer(Arc::new(config), export_service).await {
+    if let Err(e) = kafka_consumer::run_kafka_consumer(config, export_service).await {
         error!("Fatal error in Kafka consumer: {:?}", e);
         return Err(e);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::db_store::DbError;
+    use crate::services::mock_store::MockDbStore;
+
+    fn unavailable() -> DbError {
+        DbError::Transient(anyhow::anyhow!("connection refused"))
+    }
+
+    #[tokio::test]
+    async fn startup_waits_until_the_database_answers() {
+        let db_store = MockDbStore::new();
+        db_store.fail_next("ping", unavailable());
+        db_store.fail_next("ping", unavailable());
+
+        let result = retry_until_deadline(Duration::from_secs(5), Duration::from_millis(5), "ping", || db_store.ping()).await;
+
+        assert!(result.is_ok());
+        assert_eq!(db_store.calls("ping"), 3);
+    }
+
+    #[tokio::test]
+    async fn startup_gives_up_after_the_deadline() {
+        let db_store = MockDbStore::new();
+        for _ in 0..1000 {
+            db_store.fail_next("ping", unavailable());
+        }
+
+        let started_at = Instant::now();
+        let error = retry_until_deadline(Duration::from_millis(100), Duration::from_millis(10), "ping", || db_store.ping())
+            .await
+            .unwrap_err();
+
+        assert!(started_at.elapsed() < Duration::from_secs(2));
+        assert!(db_store.calls("ping") > 1);
+        let message = format!("{:#}", error);
+        assert!(message.starts_with("Database is still unavailable for ping after "), "{}", message);
+        assert!(message.ends_with("connection refused"), "{}", message);
+    }
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn requeue_arguments_take_ids_and_an_optional_force_flag() {
+        let id = Uuid::new_v4();
+
+        assert_eq!(RequeueArgs::parse(args(&[&id.to_string()])).unwrap(), RequeueArgs { ids: vec![id], force: false });
+        assert_eq!(
+            RequeueArgs::parse(args(&["--force", &id.to_string()])).unwrap(),
+            RequeueArgs { ids: vec![id], force: true }
+        );
+        assert!(RequeueArgs::parse(args(&["--force"])).is_err());
+        assert!(RequeueArgs::parse(args(&["not-a-uuid"])).is_err());
+    }
+
+    fn request_with_status(db_store: &MockDbStore, status: ExportStatus) -> Uuid {
+        db_store.insert(crate::models::ExportRequest::for_test(serde_json::json!({})), status)
+    }
+
+    #[tokio::test]
+    async fn requeue_counts_only_requests_that_changed() {
+        let db_store = MockDbStore::new();
+        let failed = request_with_status(&db_store, ExportStatus::Failed);
+        let pending = request_with_status(&db_store, ExportStatus::Pending);
+        let missing = Uuid::new_v4();
+
+        let requeued = requeue_requests(&db_store, &RequeueArgs { ids: vec![failed, pending, missing], force: false })
+            .await
+            .unwrap();
+
+        assert_eq!(requeued, 1);
+        assert_eq!(db_store.request(failed).unwrap().status, ExportStatus::Pending);
+        assert!(db_store.request(missing).is_none());
+    }
+
+    #[tokio::test]
+    async fn completed_requests_are_requeued_only_with_force() {
+        let db_store = MockDbStore::new();
+        let completed = request_with_status(&db_store, ExportStatus::Completed);
+        let expired = request_with_status(&db_store, ExportStatus::Expired);
+
+        let without_force = requeue_requests(&db_store, &RequeueArgs { ids: vec![completed, expired], force: false })
+            .await
+            .unwrap();
+        assert_eq!(without_force, 1);
+        assert_eq!(db_store.request(completed).unwrap().status, ExportStatus::Completed);
+
+        let with_force = requeue_requests(&db_store, &RequeueArgs { ids: vec![completed, expired], force: true })
+            .await
+            .unwrap();
+        assert_eq!(with_force, 1);
+        assert_eq!(db_store.request(completed).unwrap().status, ExportStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn requeue_reports_database_errors() {
+        let db_store = MockDbStore::new();
+        let failed = request_with_status(&db_store, ExportStatus::Failed);
+        db_store.fail_next("bulk_update_status", unavailable());
+
+        assert!(requeue_requests(&db_store, &RequeueArgs { ids: vec![failed], force: false }).await.is_err());
+        assert_eq!(db_store.request(failed).unwrap().status, ExportStatus::Failed);
+    }
+}
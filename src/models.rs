@@ -1,6 +1,11 @@
-use chrono::{DateTime, NaiveDate, Utc};
+use chrono::{DateTime, Duration as ChronoDuration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::{OffsetName, Tz};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx;
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -9,27 +14,1833 @@ pub struct ExportRequest {
     pub user_id: i64,
     pub request_payload: serde_json::Value, // JSONB
     pub requested_at: DateTime<Utc>,
-    pub status: String,
+    pub status: ExportStatus,
     pub file_path: Option<String>,
     pub completed_at: Option<DateTime<Utc>>,
     pub error_message: Option<String>,
     pub notification_sent: bool,
+    pub webhook_sent: bool, // Webhook đã gửi được; notification_sent còn false vì email thất bại
+    pub notification_attempts: i32,
+    pub notification_next_retry_at: Option<DateTime<Utc>>,
+    pub expired_at: Option<DateTime<Utc>>,
+    pub error_code: Option<String>,
+    pub file_checksum: Option<String>,
+    pub file_size_bytes: Option<i64>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub params_hash: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub row_count: Option<i64>,
+    pub truncated_by_limit: Option<bool>, // File chỉ chứa một phần kết quả do `limit` của payload
+    pub original_file_name: Option<String>, // File đã được nén thành zip hoặc gzip: tên file gốc
+    pub original_size_bytes: Option<i64>, // và kích thước trước khi nén
+    pub is_protected: Option<bool>, // File được mã hóa bằng mật khẩu (`protection` của payload)
+    pub file_name: Option<String>, // Tên file hiển thị, dựng từ FILENAME_TEMPLATE
+    pub is_encrypted: Option<bool>, // File được mã hóa khi lưu (ENCRYPTION_KEY, file `.enc`)
+    pub encryption_key_id: Option<String>, // và key id đã dùng để mã hóa
+    pub pgp_key_fingerprint: Option<String>, // Fingerprint khóa PGP của người nhận (`encryption` của payload, file `.gpg`)
+    pub file_parts: Option<serde_json::Value>, // File bị chia theo MAX_FILE_SIZE_BYTES: mảng JSON đường dẫn các phần sau `file_path`
+    pub estimated_completion_at: Option<DateTime<Utc>>,
+    pub db_fetch_ms: Option<i64>,
+    pub db_query_ms: Option<i64>,
+    pub file_generation_ms: Option<i64>,
+    pub notify_ms: Option<i64>,
+    pub attempts: i32,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>, // Lỗi gần nhất, kể cả khi lần xử lý sau thành công
+    pub priority: i32, // Lớn hơn được claim trước khi poll từ bảng
+    pub next_retry_at: Option<DateTime<Utc>>, // Request PENDING chưa được claim trước thời điểm này
+    pub status_updated_at: DateTime<Utc>, // Lần chuyển trạng thái gần nhất (phát hiện request bị kẹt)
+}
+
+impl ExportRequest {
+    /// Tenant trong request_payload, dùng khi không cần parse toàn bộ ReportParams.
+    pub fn tenant(&self) -> Option<&str> {
+        self.request_payload.get("tenant").and_then(|value| value.as_str())
+    }
+
+    /// Cờ `skip_notification` trong request_payload (mặc định là vẫn gửi thông báo).
+    pub fn skip_notification(&self) -> bool {
+        self.request_payload
+            .get("skip_notification")
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false)
+    }
+
+    /// Đường dẫn các phần từ phần 2 trở đi của file bị chia (phần 1 là `file_path`).
+    pub fn part_paths(&self) -> Vec<String> {
+        self.file_parts
+            .as_ref()
+            .and_then(|parts| serde_json::from_value(parts.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn timings(&self) -> ExportTimings {
+        ExportTimings {
+            db_fetch_ms: self.db_fetch_ms,
+            db_query_ms: self.db_query_ms,
+            file_generation_ms: self.file_generation_ms,
+            notify_ms: self.notify_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+impl ExportRequest {
+    /// Request PROCESSING tối thiểu cho test, với `payload` làm request_payload.
+    pub fn for_test(payload: serde_json::Value) -> Self {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "user_id": 42,
+            "request_payload": payload,
+            "requested_at": Utc::now(),
+            "status": "PROCESSING",
+            "notification_sent": false,
+            "webhook_sent": false,
+            "notification_attempts": 0,
+            "attempts": 1,
+            "priority": 0,
+            "status_updated_at": Utc::now(),
+        }))
+        .expect("valid test export request")
+    }
+}
+
+/// Thời gian (ms) của từng giai đoạn xử lý một request; giai đoạn chưa chạy tới là `None`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportTimings {
+    pub db_fetch_ms: Option<i64>,
+    pub db_query_ms: Option<i64>,
+    pub file_generation_ms: Option<i64>,
+    pub notify_ms: Option<i64>,
+}
+
+/// Metadata của file đã tạo, ghi cùng câu UPDATE trạng thái cuối.
+/// Khi request lỗi tất cả là `None` và giá trị cũ trong DB được giữ nguyên.
+#[derive(Debug, Clone, Default)]
+pub struct ExportCompletion {
+    pub file_size_bytes: Option<i64>,
+    pub rows_exported: Option<i64>,
+    pub file_checksum: Option<String>,
+    pub truncated_by_limit: Option<bool>,
+    /// Tên và kích thước của file gốc khi file export đã được nén thành zip hoặc gzip.
+    pub original_file_name: Option<String>,
+    pub original_size_bytes: Option<i64>,
+    pub is_protected: Option<bool>,
+    /// Tên file hiển thị (FILENAME_TEMPLATE). File mã hóa khi lưu có thêm `.enc` trên đĩa.
+    pub file_name: Option<String>,
+    /// File được mã hóa khi lưu, và key id của khóa đã dùng.
+    pub is_encrypted: Option<bool>,
+    pub encryption_key_id: Option<String>,
+    /// Fingerprint khóa công khai PGP mà file `.gpg` được mã hóa tới.
+    pub pgp_key_fingerprint: Option<String>,
+    /// Đường dẫn đã lưu của các phần từ phần 2 trở đi khi file bị chia theo MAX_FILE_SIZE_BYTES.
+    pub file_parts: Option<Vec<String>>,
+}
+
+/// Request đã kết thúc nhưng chưa gửi được thông báo: đủ thông tin để dựng lại ExportNotification
+/// (trang danh sách cho admin và notification retry worker).
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct UnsentNotification {
+    pub request_id: Uuid,
+    pub user_id: i64,
+    pub status: ExportStatus,
+    pub file_path: Option<String>,
+    pub error_message: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub notification_attempts: i32,
+    pub notification_next_retry_at: Option<DateTime<Utc>>,
+}
+
+/// Một lần chuyển trạng thái của ExportRequest (bảng export_request_events).
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ExportRequestEvent {
+    pub id: i64,
+    pub request_id: Uuid,
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportParams {
+    #[serde(default = "default_report_type")]
+    pub report_type: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub product_category: Option<String>,
+    // Bộ lọc tùy chọn; bỏ qua khi serialize nếu không set để params hash của payload cũ không đổi.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_price: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_price: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_stock_quantity: Option<i32>, // Lọc hàng sắp hết: stock_quantity <= giá trị này
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_contains: Option<String>, // Tìm theo tên, không phân biệt hoa thường
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortSpec>, // Mặc định: created_at tăng dần
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub columns: Option<Vec<ProductColumn>>, // Cột xuất ra file, theo thứ tự; mặc định tất cả
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ExportFormat>, // Ghi đè `default_format` của loại report (`xlsx` hoặc `csv`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compress: bool, // Nén file thành zip bất kể COMPRESS_THRESHOLD_BYTES
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<OutputCompression>, // Nén gzip file CSV/JSON Lines, ghi đè CSV_COMPRESSION/JSONL_COMPRESSION
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protection: Option<ExportProtection>, // Mã hóa file bằng mật khẩu
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excel_style: Option<ExcelStyleOverrides>, // Ghi đè từng phần định dạng file Excel của config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>, // Chỉ xuất tối đa số dòng này (export xem trước)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>, // Bỏ qua số dòng đầu tiên theo thứ tự sắp xếp, dùng cùng `limit` để phân trang
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_status: Option<OrderStatus>, // Report đơn hàng: chỉ lấy đơn ở trạng thái này
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_user_id: Option<i64>, // Report đơn hàng: chỉ lấy đơn của user này
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_lifetime_value: Option<f64>, // Report khách hàng: tổng giá trị đơn hàng tối thiểu
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_totals: bool, // Thêm dòng tổng ở cuối file Excel
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_chart: bool, // Thêm sheet "Chart" có biểu đồ cột (chỉ report có `chart_for_report_type`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_by_sheet: Option<SheetGrouping>, // File Excel có một sheet cho mỗi giá trị của cột nhóm (report sản phẩm)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<HighlightOptions>, // Ghi đè ngưỡng tô màu dòng của file Excel (report sản phẩm)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>, // Tiêu đề của khối tiêu đề phía trên dữ liệu trong file Excel
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub anonymize: bool, // Che dữ liệu cá nhân (email...) khi bật hook pii_masking
+    #[serde(default)]
+    pub delivery: Option<Delivery>,
+    #[serde(default)]
+    pub expires_in_hours: Option<i64>, // Ghi đè TTL mặc định của link download
+    #[serde(default)]
+    pub notify_on: Option<Vec<NotificationStage>>, // Các giai đoạn cần gửi thông báo
+    #[serde(default)]
+    pub tenant: Option<String>, // Schema Postgres chứa dữ liệu của tenant (deployment nhiều tenant)
+    #[serde(default)]
+    pub skip_notification: bool, // Không gửi thông báo HTTP (job nội bộ lấy file trực tiếp từ storage)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<String>, // Report `dataset`: tên dataset trong CUSTOM_DATASETS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset_params: Option<serde_json::Map<String, serde_json::Value>>, // Giá trị các tham số của dataset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>, // Múi giờ IANA (ví dụ "Asia/Ho_Chi_Minh") của ngày giờ trong file và của khoảng ngày
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format_options: Option<FormatOptions>, // Ghi đè thiết lập của từng định dạng file (`csv`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<ExportEncryption>, // Mã hóa file tới khóa PGP của người nhận
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size_bytes: Option<u64>, // Chia file lớn hơn số byte này thành nhiều phần, ghi đè MAX_FILE_SIZE_BYTES
+    // Thêm các trường khác tùy theo yêu cầu của bạn
+}
+
+/// Đầu ngày `start_date` và cuối ngày `end_date` theo múi giờ `time_zone`, đổi sang UTC (dạng naive như khi bind).
+/// Giờ bị lặp khi lùi đồng hồ lấy lần sớm nhất cho đầu ngày và muộn nhất cho cuối ngày; giờ không tồn tại
+/// khi tiến đồng hồ (ngày bắt đầu lúc 01:00) lấy thời điểm sau khoảng trống.
+pub fn utc_day_range(start_date: NaiveDate, end_date: NaiveDate, time_zone: Option<Tz>) -> (NaiveDateTime, NaiveDateTime) {
+    let start = start_date.and_time(NaiveTime::MIN);
+    let end = end_date.and_time(NaiveTime::MAX);
+    let Some(time_zone) = time_zone else {
+        return (start, end);
+    };
+    let to_utc = |local: NaiveDateTime, earliest: bool| match time_zone.from_local_datetime(&local) {
+        LocalResult::Single(value) => value.naive_utc(),
+        LocalResult::Ambiguous(first, second) => (if earliest { first } else { second }).naive_utc(),
+        // Khoảng trống của DST không dài quá một giờ (trừ vài trường hợp lịch sử): đổi từ phía sau khoảng trống.
+        LocalResult::None => {
+            let shifted = if earliest { local + ChronoDuration::hours(1) } else { local - ChronoDuration::hours(1) };
+            time_zone
+                .from_local_datetime(&shifted)
+                .earliest()
+                .map_or(local, |value| value.naive_utc())
+        }
+    };
+    (to_utc(start, true), to_utc(end, false))
+}
+
+/// Loại report mặc định khi payload không chỉ định (giữ tương thích với producer cũ).
+pub const DEFAULT_REPORT_TYPE: &str = "products";
+/// Report lịch sử đơn hàng.
+pub const ORDERS_REPORT_TYPE: &str = "orders";
+/// Report danh sách khách hàng.
+pub const CUSTOMERS_REPORT_TYPE: &str = "customers";
+/// Report tổng hợp theo category (một sheet, có dòng tổng ở cuối).
+pub const CATEGORY_SUMMARY_REPORT_TYPE: &str = "category_summary";
+/// Report chạy một SQL template do admin định nghĩa trong CUSTOM_DATASETS.
+pub const DATASET_REPORT_TYPE: &str = "dataset";
+
+/// Giá trị lớn nhất của `limit` trong payload.
+pub const MAX_REQUEST_LIMIT: i64 = 1_000_000;
+/// Giá trị lớn nhất của `offset` trong payload: OFFSET lớn buộc DB đọc rồi bỏ đi từng ấy dòng.
+pub const MAX_REQUEST_OFFSET: i64 = 10_000_000;
+
+/// Giá trị nhỏ nhất của MAX_FILE_SIZE_BYTES và `max_file_size_bytes`: mỗi phần phải đủ chỗ cho header và vài dòng.
+pub const MIN_MAX_FILE_SIZE_BYTES: u64 = 16 * 1024;
+
+/// Ai được xuất dữ liệu của một loại report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportAccess {
+    /// Mọi user đều xuất được toàn bộ dữ liệu.
+    Public,
+    /// User chỉ xuất được bản ghi của chính mình.
+    OwnerOnly,
+    /// User trong EXPORT_ADMIN_USER_IDS xuất được toàn bộ, user khác chỉ xuất bản ghi của mình.
+    RoleBased,
+}
+
+impl ReportAccess {
+    /// Mặc định trong code khi REPORT_TYPE_SETTINGS không cấu hình `access`.
+    pub fn default_for(report_type: &str) -> Self {
+        match report_type {
+            ORDERS_REPORT_TYPE | CUSTOMERS_REPORT_TYPE => ReportAccess::OwnerOnly,
+            _ => ReportAccess::Public,
+        }
+    }
+
+    /// Chỉ report đơn hàng và khách hàng có cột chủ sở hữu để lọc.
+    pub fn supported_by(&self, report_type: &str) -> bool {
+        *self == ReportAccess::Public || matches!(report_type, ORDERS_REPORT_TYPE | CUSTOMERS_REPORT_TYPE)
+    }
+}
+
+fn default_report_type() -> String {
+    DEFAULT_REPORT_TYPE.to_string()
+}
+
+impl ReportParams {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let (Some(min_price), Some(max_price)) = (self.min_price, self.max_price) {
+            anyhow::ensure!(
+                min_price <= max_price,
+                "min_price ({}) must not be greater than max_price ({})",
+                min_price,
+                max_price
+            );
+        }
+        if let Some(columns) = &self.columns {
+            anyhow::ensure!(!columns.is_empty(), "columns must contain at least one column");
+            for (i, column) in columns.iter().enumerate() {
+                anyhow::ensure!(!columns[..i].contains(column), "column '{}' is selected more than once", column.sql());
+            }
+        }
+        if self.report_type != ORDERS_REPORT_TYPE {
+            anyhow::ensure!(
+                self.order_status.is_none() && self.order_user_id.is_none(),
+                "order_status and order_user_id are only supported for the '{}' report",
+                ORDERS_REPORT_TYPE
+            );
+        }
+        if self.report_type != CUSTOMERS_REPORT_TYPE {
+            anyhow::ensure!(
+                self.min_lifetime_value.is_none(),
+                "min_lifetime_value is only supported for the '{}' report",
+                CUSTOMERS_REPORT_TYPE
+            );
+        }
+        if let Some(limit) = self.limit {
+            anyhow::ensure!(
+                (1..=MAX_REQUEST_LIMIT).contains(&limit),
+                "limit must be between 1 and {}, got {}",
+                MAX_REQUEST_LIMIT,
+                limit
+            );
+        }
+        if let Some(offset) = self.offset {
+            anyhow::ensure!(
+                (0..=MAX_REQUEST_OFFSET).contains(&offset),
+                "offset must be between 0 and {}, got {}",
+                MAX_REQUEST_OFFSET,
+                offset
+            );
+        }
+        if !self.is_product_report() {
+            anyhow::ensure!(
+                self.limit.is_none() && self.offset.is_none(),
+                "limit and offset are only supported for the '{}' report",
+                DEFAULT_REPORT_TYPE
+            );
+        }
+        if let Some(style) = &self.excel_style {
+            for (field, color) in [
+                ("excel_style.header_font_color", &style.header_font_color),
+                ("excel_style.header_background_color", &style.header_background_color),
+            ] {
+                if let Some(color) = color {
+                    anyhow::ensure!(is_hex_color(color), "{} must be a '#RRGGBB' color, got '{}'", field, color);
+                }
+            }
+            if let Some(width) = style.max_column_width {
+                anyhow::ensure!(
+                    (1..=EXCEL_MAX_COLUMN_WIDTH).contains(&width),
+                    "excel_style.max_column_width must be between 1 and {}, got {}",
+                    EXCEL_MAX_COLUMN_WIDTH,
+                    width
+                );
+            }
+            if let Some(margins) = style.print.as_ref().and_then(|print| print.margins.as_ref()) {
+                margins.validate().map_err(|e| e.context("excel_style.print.margins is invalid"))?;
+            }
+        }
+        if let Some(title) = &self.title {
+            anyhow::ensure!(!sanitize_title(title).is_empty(), "title must contain visible characters");
+        }
+        if let Some(max_bytes) = self.max_file_size_bytes {
+            anyhow::ensure!(
+                max_bytes >= MIN_MAX_FILE_SIZE_BYTES,
+                "max_file_size_bytes must be at least {}, got {}",
+                MIN_MAX_FILE_SIZE_BYTES,
+                max_bytes
+            );
+        }
+        if let Some(password) = self.protection.as_ref().and_then(|protection| protection.password.as_deref()) {
+            anyhow::ensure!(!password.is_empty(), "protection.password must not be empty");
+        }
+        if self.format == Some(ExportFormat::Pdf) {
+            anyhow::ensure!(
+                self.report_type == CATEGORY_SUMMARY_REPORT_TYPE,
+                "format 'pdf' is only supported for the '{}' report",
+                CATEGORY_SUMMARY_REPORT_TYPE
+            );
+        }
+        // Report tổng hợp đã có dòng tổng (ROLLUP) ở cuối.
+        anyhow::ensure!(
+            !(self.include_totals && self.report_type == CATEGORY_SUMMARY_REPORT_TYPE),
+            "include_totals is not supported for the '{}' report, which already ends with a total row",
+            CATEGORY_SUMMARY_REPORT_TYPE
+        );
+        anyhow::ensure!(
+            !self.include_chart || chart_for_report_type(&self.report_type).is_some(),
+            "include_chart is not supported for the '{}' report",
+            self.report_type
+        );
+        if let Some(timezone) = &self.timezone {
+            anyhow::ensure!(
+                timezone.parse::<Tz>().is_ok(),
+                "timezone '{}' is not a valid IANA time zone name",
+                timezone
+            );
+        }
+        if self.report_type == DATASET_REPORT_TYPE {
+            anyhow::ensure!(self.dataset.is_some(), "dataset is required for the '{}' report", DATASET_REPORT_TYPE);
+        } else {
+            anyhow::ensure!(
+                self.dataset.is_none() && self.dataset_params.is_none(),
+                "dataset and dataset_params are only supported for the '{}' report",
+                DATASET_REPORT_TYPE
+            );
+        }
+        Ok(())
+    }
+
+    /// Múi giờ của payload (`None` là UTC). Tên đã được kiểm tra trong `validate`.
+    pub fn time_zone(&self) -> Option<Tz> {
+        self.timezone.as_deref().and_then(|timezone| timezone.parse().ok())
+    }
+
+    /// Khối tiêu đề của file Excel: `title` của payload (đã làm sạch), hoặc tên report kèm khoảng ngày và category,
+    /// ví dụ "Product Export — 2024-05-01 to 2024-05-31 (Category: Electronics)". Dòng phụ ghi thời điểm tạo file
+    /// theo múi giờ của payload.
+    pub fn report_title(&self, generated_at: DateTime<Utc>) -> ReportTitle {
+        let title = match &self.title {
+            Some(title) => sanitize_title(title),
+            None => {
+                let name = match self.report_type.as_str() {
+                    ORDERS_REPORT_TYPE => "Order Export".to_string(),
+                    CUSTOMERS_REPORT_TYPE => "Customer Export".to_string(),
+                    CATEGORY_SUMMARY_REPORT_TYPE => "Category Summary".to_string(),
+                    DATASET_REPORT_TYPE => format!("Dataset {}", self.dataset.as_deref().unwrap_or_default()),
+                    _ => "Product Export".to_string(),
+                };
+                let mut title = format!("{} — {} to {}", name, self.start_date, self.end_date);
+                if let Some(category) = &self.product_category {
+                    title.push_str(&format!(" (Category: {})", category));
+                }
+                sanitize_title(&title)
+            }
+        };
+        let generated_at = match self.time_zone() {
+            Some(time_zone) => generated_at.with_timezone(&time_zone).format("%Y-%m-%d %H:%M %Z").to_string(),
+            None => generated_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        };
+        ReportTitle { title, subtitle: format!("Generated {}", generated_at) }
+    }
+
+    /// Khoảng `start_date`..`end_date` (tính cả hai ngày) theo múi giờ của payload, đổi sang UTC để so với cột thời gian.
+    pub fn utc_range(&self) -> (NaiveDateTime, NaiveDateTime) {
+        utc_day_range(self.start_date, self.end_date, self.time_zone())
+    }
+
+    /// Tham số của report đơn hàng (dùng chung khoảng ngày và tenant với report sản phẩm).
+    /// `owner_user_id` (từ `ExportRequest.user_id`, không bao giờ từ payload) thay cho `order_user_id`.
+    pub fn order_params(&self, owner_user_id: Option<i64>) -> OrderReportParams {
+        OrderReportParams {
+            start_date: self.start_date,
+            end_date: self.end_date,
+            status: self.order_status,
+            user_id: owner_user_id.or(self.order_user_id),
+            tenant: self.tenant.clone(),
+            time_zone: self.time_zone(),
+        }
+    }
+
+    /// Tham số của report khách hàng; khoảng ngày được áp dụng cho ngày đăng ký.
+    pub fn customer_params(&self, owner_user_id: Option<i64>) -> CustomerReportParams {
+        CustomerReportParams {
+            signup_from: self.start_date,
+            signup_to: self.end_date,
+            min_lifetime_value: self.min_lifetime_value,
+            owner_user_id,
+            tenant: self.tenant.clone(),
+        }
+    }
+
+    /// Loại report không có query riêng dùng report sản phẩm (xem `ExportService::query_report_data`).
+    pub fn is_product_report(&self) -> bool {
+        !matches!(
+            self.report_type.as_str(),
+            ORDERS_REPORT_TYPE | CUSTOMERS_REPORT_TYPE | CATEGORY_SUMMARY_REPORT_TYPE | DATASET_REPORT_TYPE
+        )
+    }
+
+    /// Payload có giới hạn vùng dữ liệu bằng `limit`/`offset`.
+    pub fn has_row_window(&self) -> bool {
+        self.limit.is_some() || self.offset.is_some()
+    }
+
+    /// Giá trị OFFSET của query (0 khi payload không có `offset`).
+    pub fn query_offset(&self) -> i64 {
+        self.offset.unwrap_or(0)
+    }
+
+    /// Giá trị LIMIT của query: `limit` của payload, nhưng không quá `max_rows + 1` để caller
+    /// vẫn phát hiện được vượt giới hạn chung.
+    pub fn query_limit(&self, max_rows: usize) -> i64 {
+        let cap = i64::try_from(max_rows).unwrap_or(i64::MAX - 1) + 1;
+        self.limit.map_or(cap, |limit| limit.min(cap))
+    }
+
+    /// Số dòng sẽ được xuất khi có `total` dòng khớp bộ lọc, sau khi áp dụng `offset` và `limit`.
+    pub fn rows_in_window(&self, total: i64) -> i64 {
+        let remaining = (total - self.query_offset()).max(0);
+        self.limit.map_or(remaining, |limit| remaining.min(limit))
+    }
+
+    /// Còn dòng khớp bộ lọc sau vùng `offset..offset + limit` (khác với vượt MAX_EXPORT_ROWS).
+    pub fn truncated_by_limit(&self, total: i64) -> bool {
+        self.limit.is_some_and(|limit| total - self.query_offset() > limit)
+    }
+
+    /// Các cột cần xuất, theo thứ tự request yêu cầu.
+    pub fn selected_columns(&self) -> Vec<ProductColumn> {
+        self.columns.clone().unwrap_or_else(|| ProductColumn::ALL.to_vec())
+    }
+
+    /// Mệnh đề ORDER BY, chỉ ghép từ các chuỗi cố định của ProductColumn/SortDirection (không bao giờ
+    /// chứa input của người dùng). product_id luôn được thêm vào để thứ tự ổn định khi trùng giá trị.
+    /// Với `group_by_sheet`, cột nhóm đứng đầu để các dòng của một nhóm liền nhau và sheet được ghi lần lượt.
+    pub fn order_by_sql(&self) -> String {
+        let order_by = match &self.sort {
+            Some(SortSpec { by: ProductColumn::ProductId, dir }) => format!("product_id {}", dir.sql()),
+            Some(SortSpec { by, dir }) => format!("{} {}, product_id {}", by.sql(), dir.sql(), dir.sql()),
+            None => "created_at ASC, product_id ASC".to_string(),
+        };
+        match self.group_by_sheet {
+            Some(grouping) => format!("{} ASC, {}", grouping.column().sql(), order_by),
+            None => order_by,
+        }
+    }
+
+    /// Thứ tự mặc định (created_at, product_id) tăng dần, thứ tự mà keyset pagination hỗ trợ.
+    pub fn uses_default_sort(&self) -> bool {
+        self.group_by_sheet.is_none()
+            && matches!(
+                self.sort,
+                None | Some(SortSpec { by: ProductColumn::CreatedAt, dir: SortDirection::Asc })
+            )
+    }
+
+    /// Pattern ILIKE cho `name_contains`, escape `\`, `%` và `_` để người dùng không chèn được wildcard.
+    pub fn name_pattern(&self) -> Option<String> {
+        self.name_contains.as_deref().filter(|name| !name.is_empty()).map(|name| {
+            let escaped = name.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
+
+    /// Hash ổn định của (user_id, tham số ảnh hưởng tới nội dung file), dùng để phát hiện request trùng.
+    /// Các trường chỉ liên quan tới cách giao file (delivery, TTL) được loại bỏ trước khi hash.
+    /// Serialize qua `serde_json::Value` (map có khóa được sắp xếp) nên thứ tự field trong payload không ảnh hưởng.
+    pub fn content_hash(&self, user_id: i64) -> String {
+        let canonical = serde_json::Value::Object(self.content_parameters()).to_string();
+        let mut hasher = Sha256::new();
+        hasher.update(user_id.to_le_bytes());
+        hasher.update(canonical.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Các tham số ảnh hưởng tới nội dung file, như được hash (`content_hash`) và ghi vào metadata của file.
+    /// Chỉ giữ việc file có được bảo vệ hay không: mật khẩu không bao giờ nằm trong kết quả.
+    pub fn content_parameters(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut normalized = self.clone();
+        normalized.delivery = None;
+        normalized.expires_in_hours = None;
+        normalized.notify_on = None;
+        normalized.skip_notification = false;
+        normalized.protection = normalized.protection.map(|_| ExportProtection::default());
+        match serde_json::to_value(&normalized) {
+            Ok(serde_json::Value::Object(parameters)) => parameters,
+            _ => serde_json::Map::new(),
+        }
+    }
+}
+
+/// Thứ tự sắp xếp của dữ liệu export, ví dụ `{"by": "price", "dir": "desc"}`.
+/// Cột không nằm trong allowlist bị từ chối ngay khi parse payload.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SortSpec {
+    pub by: ProductColumn,
+    #[serde(default)]
+    pub dir: SortDirection,
+}
+
+/// `group_by_sheet` trong payload: cột của report sản phẩm mà mỗi giá trị có một worksheet riêng.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SheetGrouping {
+    Category,
+}
+
+impl SheetGrouping {
+    pub fn column(&self) -> ProductColumn {
+        match self {
+            SheetGrouping::Category => ProductColumn::Category,
+        }
+    }
+}
+
+/// Số ký tự tối đa của tiêu đề trong khối tiêu đề Excel.
+pub const MAX_TITLE_CHARS: usize = 255;
+
+/// Tiêu đề hiển thị được: ký tự điều khiển (kể cả xuống dòng) thành khoảng trắng, các khoảng trắng liền nhau
+/// gộp làm một, bỏ khoảng trắng hai đầu và cắt còn `MAX_TITLE_CHARS` ký tự.
+pub fn sanitize_title(value: &str) -> String {
+    let cleaned = value.chars().map(|c| if c.is_control() { ' ' } else { c }).collect::<String>();
+    cleaned.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(MAX_TITLE_CHARS).collect()
+}
+
+/// Khối tiêu đề phía trên header của mỗi sheet dữ liệu Excel: tiêu đề và dòng phụ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportTitle {
+    pub title: String,
+    pub subtitle: String,
+}
+
+/// `highlight` trong payload, ví dụ `{"stock_below": 10}`: ngưỡng của các cột có `ConditionalFormat::RowBelow`
+/// thay cho ngưỡng khai báo trong layout cột.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightOptions {
+    pub stock_below: i64,
+}
+
+impl HighlightOptions {
+    /// Cột được tô màu theo `highlight`.
+    pub const COLUMN: ProductColumn = ProductColumn::StockQuantity;
+
+    /// Ngưỡng của cột `field`; `None` khi payload không ghi đè ngưỡng của cột này.
+    pub fn threshold(&self, field: &str) -> Option<f64> {
+        (field == Self::COLUMN.sql()).then_some(self.stock_below as f64)
+    }
+}
+
+/// Các cột của report sản phẩm: dùng chung cho sort, chọn cột và layout của file export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductColumn {
+    ProductId,
+    Name,
+    Category,
+    Price,
+    StockQuantity,
+    CreatedAt,
+}
+
+impl ProductColumn {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            ProductColumn::ProductId => "product_id",
+            ProductColumn::Name => "name",
+            ProductColumn::Category => "category",
+            ProductColumn::Price => "price",
+            ProductColumn::StockQuantity => "stock_quantity",
+            ProductColumn::CreatedAt => "created_at",
+        }
+    }
+
+    /// Thứ tự cột mặc định khi request không chọn cột.
+    pub const ALL: [ProductColumn; 6] = [
+        ProductColumn::ProductId,
+        ProductColumn::Name,
+        ProductColumn::Category,
+        ProductColumn::Price,
+        ProductColumn::StockQuantity,
+        ProductColumn::CreatedAt,
+    ];
+
+    /// Khai báo của cột trong `PRODUCT_COLUMNS` (field trùng với `sql()`).
+    pub fn def(&self) -> &'static ColumnDef {
+        PRODUCT_COLUMNS
+            .iter()
+            .find(|column| column.field == self.sql())
+            .expect("every ProductColumn has an entry in PRODUCT_COLUMNS")
+    }
+
+    pub fn header(&self) -> &'static str {
+        self.def().header.as_ref()
+    }
+}
+
+/// Layout cột của report sản phẩm, theo thứ tự mặc định (`ProductColumn::ALL`).
+pub const PRODUCT_COLUMNS: &[ColumnDef] = &[
+    ColumnDef::new("product_id", "Product ID", ColumnFormat::Integer, Some(12.0)).with_aggregate(ColumnAggregate::Count),
+    ColumnDef::new("name", "Name", ColumnFormat::General, Some(40.0)).with_hyperlink(LinkDisplay::Field("name")),
+    ColumnDef::new("category", "Category", ColumnFormat::General, Some(20.0)),
+    ColumnDef::new("price", "Price", ColumnFormat::Decimal, Some(12.0)).with_aggregate(ColumnAggregate::Avg),
+    ColumnDef::new("stock_quantity", "Stock Quantity", ColumnFormat::Integer, Some(15.0))
+        .with_aggregate(ColumnAggregate::Sum)
+        .with_conditional_format(ConditionalFormat::RowBelow { threshold: LOW_STOCK_THRESHOLD }),
+    ColumnDef::new("created_at", "Created At", ColumnFormat::DateTime, Some(22.0)),
+];
+
+/// Dòng sản phẩm có tồn kho nhỏ hơn số này được tô đỏ trong file Excel (payload ghi đè bằng `highlight`).
+pub const LOW_STOCK_THRESHOLD: f64 = 10.0;
+
+impl ExportRow for ProductData {
+    fn cell(&self, field: &str) -> CellValue {
+        match field {
+            "product_id" => CellValue::Number(self.product_id as f64),
+            "name" => CellValue::Text(self.name.clone()),
+            "category" => CellValue::Text(self.category.clone()),
+            "price" => CellValue::Number(self.price),
+            "stock_quantity" => CellValue::Number(self.stock_quantity as f64),
+            "created_at" => CellValue::DateTime(self.created_at.with_timezone(&Tz::UTC)),
+            _ => CellValue::Text(String::new()),
+        }
+    }
+}
+
+/// Giá trị một ô trong file export.
+/// Ngày giờ được giữ nguyên kiểu để file Excel có ô ngày thật; CSV vẫn ghi dạng chuỗi như trước.
+/// Ngày giờ mang múi giờ hiển thị (UTC, hoặc `timezone` của payload): file ghi giờ địa phương của múi giờ đó.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellValue {
+    Number(f64),
+    Text(String),
+    DateTime(DateTime<Tz>),
+    Date(NaiveDate),
+    /// Link (`ColumnDef::hyperlink`): file Excel có ô hyperlink, HTML có thẻ `<a>`, CSV ghi URL;
+    /// các định dạng khác ghi `text`.
+    Link { text: String, url: String },
+}
+
+impl fmt::Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellValue::Number(value) => write!(f, "{}", value),
+            CellValue::Text(value) => write!(f, "{}", value),
+            CellValue::DateTime(value) => write!(f, "{}", value),
+            CellValue::Date(value) => write!(f, "{}", value),
+            CellValue::Link { text, .. } => write!(f, "{}", text),
+        }
+    }
+}
+
+impl CellValue {
+    /// Giá trị JSON của ô, cùng cách biểu diễn với `Serialize` của các struct dòng (ngày giờ dạng RFC 3339).
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            CellValue::Number(value) => {
+                serde_json::Number::from_f64(*value).map_or(serde_json::Value::Null, serde_json::Value::Number)
+            }
+            CellValue::Text(value) => serde_json::Value::String(value.clone()),
+            CellValue::DateTime(value) => {
+                serde_json::Value::String(value.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true))
+            }
+            CellValue::Date(value) => serde_json::Value::String(value.to_string()),
+            CellValue::Link { text, .. } => serde_json::Value::String(text.clone()),
+        }
+    }
+}
+
+/// Định dạng số của một cột trong file Excel, khai báo trong layout cột của từng report.
+/// Chuỗi định dạng tương ứng lấy từ `ExcelStyleOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnFormat {
+    /// Không định dạng; ô ngày giờ vẫn dùng định dạng theo kiểu của ô.
+    General,
+    Integer,
+    Decimal,
+    Date,
+    DateTime,
+}
+
+/// Giá trị của cột ở dòng tổng (`include_totals` của payload).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnAggregate {
+    None,
+    Sum,
+    Avg,
+    /// Số ô không trống của cột.
+    Count,
+}
+
+/// Định dạng có điều kiện của một cột trong file Excel, áp dụng cho vùng dữ liệu của từng sheet
+/// (không gồm header và dòng tổng).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConditionalFormat {
+    /// Tô nền đỏ cả dòng khi giá trị số của cột nhỏ hơn `threshold`; ô trống hoặc chữ không được tô.
+    RowBelow { threshold: f64 },
+    /// Thang 3 màu trên cột: đỏ ở giá trị nhỏ nhất, vàng ở phân vị 50, xanh ở giá trị lớn nhất của sheet.
+    ColorScale,
+}
+
+/// Khai báo một cột của file export: field đọc từ dòng dữ liệu (`ExportRow::cell`), header, định dạng và độ rộng.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub field: Cow<'static, str>,
+    pub header: Cow<'static, str>,
+    pub format: ColumnFormat,
+    /// Độ rộng cột trong file Excel (số ký tự) khi không bật autofit; `None` giữ độ rộng mặc định của Excel.
+    pub width: Option<f64>,
+    pub aggregate: ColumnAggregate,
+    pub conditional_format: Option<ConditionalFormat>,
+    /// Cột là link khi loại report có `link_url_template` (xem `ExportLayout::url_template`).
+    pub hyperlink: Option<LinkDisplay>,
+}
+
+/// Chữ hiển thị của ô link (`ColumnDef::hyperlink`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkDisplay {
+    /// Giá trị của một field của dòng.
+    Field(&'static str),
+}
+
+impl ColumnDef {
+    pub const fn new(field: &'static str, header: &'static str, format: ColumnFormat, width: Option<f64>) -> Self {
+        Self {
+            field: Cow::Borrowed(field),
+            header: Cow::Borrowed(header),
+            format,
+            width,
+            aggregate: ColumnAggregate::None,
+            conditional_format: None,
+            hyperlink: None,
+        }
+    }
+
+    pub const fn with_aggregate(mut self, aggregate: ColumnAggregate) -> Self {
+        self.aggregate = aggregate;
+        self
+    }
+
+    pub const fn with_conditional_format(mut self, conditional_format: ConditionalFormat) -> Self {
+        self.conditional_format = Some(conditional_format);
+        self
+    }
+
+    pub const fn with_hyperlink(mut self, display: LinkDisplay) -> Self {
+        self.hyperlink = Some(display);
+        self
+    }
+}
+
+/// Dòng dữ liệu ghi được ra file export. Field không có trong dòng trả về ô trống.
+pub trait ExportRow {
+    fn cell(&self, field: &str) -> CellValue;
+}
+
+/// Các cột của một file export, theo thứ tự ghi ra file.
+#[derive(Debug, Clone, Default)]
+pub struct ExportLayout {
+    pub columns: Vec<ColumnDef>,
+    /// URL của các cột có `hyperlink` (`link_url_template` của loại report); `None` thì các cột này ghi giá trị thường.
+    pub url_template: Option<String>,
+}
+
+impl ExportLayout {
+    pub fn new(columns: &[ColumnDef]) -> Self {
+        Self { columns: columns.to_vec(), url_template: None }
+    }
+
+    /// Layout của report sản phẩm với các cột (và thứ tự cột) được request chọn.
+    pub fn products(columns: &[ProductColumn]) -> Self {
+        Self { columns: columns.iter().map(|column| column.def().clone()).collect(), url_template: None }
+    }
+
+    pub fn headers(&self) -> Vec<&str> {
+        self.columns.iter().map(|column| column.header.as_ref()).collect()
+    }
+
+    /// Giá trị các ô của một dòng, theo đúng thứ tự cột. Cột có `hyperlink` cho ô `CellValue::Link` khi layout có URL template.
+    pub fn cells<R: ExportRow>(&self, row: &R) -> Vec<CellValue> {
+        self.columns
+            .iter()
+            .map(|column| match (column.hyperlink, &self.url_template) {
+                (Some(LinkDisplay::Field(field)), Some(template)) => CellValue::Link {
+                    text: row.cell(field).to_string(),
+                    url: render_link_url(template, row),
+                },
+                _ => row.cell(&column.field),
+            })
+            .collect()
+    }
+
+    /// Một dòng dạng JSON object chỉ gồm các cột của layout. Dùng `Serialize` của struct dòng
+    /// nên giữ nguyên kiểu dữ liệu (số nguyên, null, ngày giờ RFC 3339).
+    pub fn json_object<R: Serialize>(&self, row: &R) -> serde_json::Result<serde_json::Map<String, serde_json::Value>> {
+        let mut fields = match serde_json::to_value(row)? {
+            serde_json::Value::Object(fields) => fields,
+            _ => serde_json::Map::new(),
+        };
+        Ok(self
+            .columns
+            .iter()
+            .filter_map(|column| fields.remove(column.field.as_ref()).map(|value| (column.field.to_string(), value)))
+            .collect())
+    }
+}
+
+/// Kiểm tra `link_url_template` của loại report: URL `http://` hoặc `https://`, không có khoảng trắng
+/// hay ký tự điều khiển, mỗi `{field}` là tên field gồm `a-z`, `0-9` và `_`, và không có dấu `{`/`}` lẻ.
+pub fn validate_link_url_template(template: &str) -> anyhow::Result<()> {
+    let lower = template.to_ascii_lowercase();
+    anyhow::ensure!(
+        lower.starts_with("http://") || lower.starts_with("https://"),
+        "link URL template '{}' must start with http:// or https://",
+        template
+    );
+    anyhow::ensure!(
+        !template.chars().any(|c| c.is_whitespace() || c.is_control()),
+        "link URL template '{}' must not contain whitespace or control characters",
+        template
+    );
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        anyhow::ensure!(rest[start..].starts_with('{'), "link URL template '{}' has an unmatched '}}'", template);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow::anyhow!("link URL template '{}' has an unmatched '{{'", template))?;
+        let field = &rest[start + 1..start + end];
+        anyhow::ensure!(
+            !field.is_empty() && field.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+            "link URL template '{}' has an invalid placeholder '{{{}}}'",
+            template,
+            field
+        );
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// URL của một dòng: mỗi `{field}` của template (đã qua `validate_link_url_template`) được thay bằng giá trị
+/// của field đó đã percent-encode, nên giá trị chứa `/`, `?`, `#`... không đổi được đường dẫn của URL.
+/// Field không có trong dòng thành chuỗi rỗng.
+pub fn render_link_url<R: ExportRow>(template: &str, row: &R) -> String {
+    let mut url = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        url.push_str(&rest[..start]);
+        url.push_str(&encode_url_component(&row.cell(&rest[start + 1..start + end]).to_string()));
+        rest = &rest[start + end + 1..];
+    }
+    url.push_str(rest);
+    url
+}
+
+/// Percent-encode mọi byte ngoài các ký tự không dành riêng của URL (`A-Z a-z 0-9 - . _ ~`).
+fn encode_url_component(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Định dạng của file Excel: header in đậm chữ trắng trên nền tối, cố định dòng header và bật autofilter.
+/// Giá trị mặc định lấy từ config, payload có thể ghi đè từng trường qua `excel_style`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExcelStyleOptions {
+    /// Định dạng dòng header (đậm, màu chữ và màu nền bên dưới).
+    pub style_header: bool,
+    pub header_font_color: String,
+    pub header_background_color: String,
+    /// Cố định dòng header khi cuộn (`freeze_panes(1, 0)`).
+    pub freeze_header: bool,
+    /// Autofilter trên toàn bộ vùng dữ liệu của mỗi sheet (kể cả header).
+    pub autofilter: bool,
+    /// Đặt độ rộng cột theo nội dung dài nhất của cột (thay cho độ rộng khai báo trong layout cột).
+    pub autofit: bool,
+    /// Độ rộng tối đa (số ký tự) của cột khi autofit.
+    pub max_column_width: u16,
+    /// Chuỗi định dạng số của Excel cho các cột `ColumnFormat` tương ứng.
+    pub integer_format: String,
+    pub decimal_format: String,
+    pub date_format: String,
+    pub datetime_format: String,
+    /// Thêm dòng tổng (in đậm, viền trên) sau dòng dữ liệu cuối cùng, theo `ColumnDef::aggregate` (`include_totals` của payload).
+    pub totals_row: bool,
+    /// Dòng tổng dùng công thức SUBTOTAL thay cho giá trị đã tính sẵn.
+    pub totals_formulas: bool,
+    /// Thêm sheet "Chart" với biểu đồ của report (`ReportData::chart`, `include_chart` của payload).
+    pub chart: bool,
+    /// Mỗi giá trị của cột nhóm có một sheet riêng, kèm sheet "Index" (`group_by_sheet` của payload).
+    pub group_by_sheet: Option<SheetGrouping>,
+    /// Ngưỡng tô màu dòng thay cho ngưỡng trong layout cột (`highlight` của payload).
+    pub highlight: Option<HighlightOptions>,
+    /// Ghi ở cuối chuỗi bị cắt cho vừa giới hạn ký tự của một ô Excel.
+    pub truncation_marker: String,
+    /// Vùng dữ liệu của mỗi sheet là một Excel Table (thay cho `autofilter`).
+    pub table: bool,
+    /// Kiểu dựng sẵn của Excel Table.
+    pub table_style: ExcelTableStyle,
+    /// Thiết lập trang in của các sheet dữ liệu.
+    pub print: ExcelPrintOptions,
+    /// Thêm khối tiêu đề mặc định phía trên dữ liệu của mọi file Excel.
+    pub title_block: bool,
+    /// Khối tiêu đề của file (`title_block` hoặc `title` của payload); header và dữ liệu bắt đầu bên dưới.
+    pub title: Option<ReportTitle>,
+}
+
+impl Default for ExcelStyleOptions {
+    fn default() -> Self {
+        Self {
+            style_header: true,
+            header_font_color: "#FFFFFF".to_string(),
+            header_background_color: "#1F4E78".to_string(),
+            freeze_header: true,
+            autofilter: true,
+            autofit: false,
+            max_column_width: 60,
+            integer_format: "0".to_string(),
+            decimal_format: "#,##0.00".to_string(),
+            date_format: "yyyy-mm-dd".to_string(),
+            datetime_format: "yyyy-mm-dd hh:mm:ss".to_string(),
+            totals_row: false,
+            totals_formulas: false,
+            chart: false,
+            group_by_sheet: None,
+            highlight: None,
+            truncation_marker: "…".to_string(),
+            table: false,
+            table_style: ExcelTableStyle::Medium(9),
+            print: ExcelPrintOptions::default(),
+            title_block: false,
+            title: None,
+        }
+    }
+}
+
+impl ExcelStyleOptions {
+    /// Áp dụng các trường được set trong `excel_style` của payload lên định dạng mặc định.
+    pub fn with_overrides(&self, overrides: Option<&ExcelStyleOverrides>) -> Self {
+        let mut style = self.clone();
+        if let Some(overrides) = overrides {
+            if let Some(style_header) = overrides.style_header {
+                style.style_header = style_header;
+            }
+            if let Some(color) = &overrides.header_font_color {
+                style.header_font_color = color.clone();
+            }
+            if let Some(color) = &overrides.header_background_color {
+                style.header_background_color = color.clone();
+            }
+            if let Some(freeze_header) = overrides.freeze_header {
+                style.freeze_header = freeze_header;
+            }
+            if let Some(autofilter) = overrides.autofilter {
+                style.autofilter = autofilter;
+            }
+            if let Some(autofit) = overrides.autofit {
+                style.autofit = autofit;
+            }
+            if let Some(max_column_width) = overrides.max_column_width {
+                style.max_column_width = max_column_width;
+            }
+            if let Some(table) = overrides.table {
+                style.table = table;
+            }
+            if let Some(table_style) = overrides.table_style {
+                style.table_style = table_style;
+            }
+            if let Some(print) = &overrides.print {
+                style.print = style.print.with_overrides(print);
+            }
+        }
+        style
+    }
+}
+
+/// `protection` trong payload, ví dụ `{"password": "..."}`. Không có `password` thì mật khẩu được lấy
+/// từ export hook (`ExportHook::export_password`). Debug che mật khẩu để nó không bao giờ xuất hiện trong log.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportProtection {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for ExportProtection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExportProtection")
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+/// `excel_style` trong payload: trường nào bỏ trống thì dùng giá trị của config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExcelStyleOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style_header: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header_font_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header_background_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub freeze_header: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autofilter: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autofit: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_column_width: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table_style: Option<ExcelTableStyle>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub print: Option<ExcelPrintOverrides>,
+}
+
+/// Thiết lập trang in của các sheet dữ liệu Excel (EXCEL_PRINT_*, `excel_style.print` của payload).
+/// Mặc định giữ nguyên thiết lập in của Excel.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExcelPrintOptions {
+    /// In khổ ngang.
+    pub landscape: bool,
+    /// Thu nhỏ để mọi cột vừa chiều ngang của một trang (số trang theo chiều dọc không giới hạn).
+    pub fit_to_width: bool,
+    /// In lại dòng header ở đầu mỗi trang.
+    pub repeat_header: bool,
+    /// `None`: lề mặc định của Excel.
+    pub margins: Option<PageMargins>,
+    /// Footer "Page X of Y" kèm request id.
+    pub footer: bool,
+}
+
+impl ExcelPrintOptions {
+    /// Áp dụng các trường được set trong `excel_style.print` của payload.
+    pub fn with_overrides(&self, overrides: &ExcelPrintOverrides) -> Self {
+        Self {
+            landscape: overrides.landscape.unwrap_or(self.landscape),
+            fit_to_width: overrides.fit_to_width.unwrap_or(self.fit_to_width),
+            repeat_header: overrides.repeat_header.unwrap_or(self.repeat_header),
+            margins: overrides.margins.or(self.margins),
+            footer: overrides.footer.unwrap_or(self.footer),
+        }
+    }
+}
+
+/// `excel_style.print` trong payload: trường nào bỏ trống thì dùng giá trị của config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExcelPrintOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landscape: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fit_to_width: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_header: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margins: Option<PageMargins>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub footer: Option<bool>,
+}
+
+/// Lề trang in, tính bằng inch như trong Excel.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PageMargins {
+    pub left: f64,
+    pub right: f64,
+    pub top: f64,
+    pub bottom: f64,
+}
+
+/// Lề lớn nhất (inch): hai lề đối diện vẫn để lại chỗ cho dữ liệu trên trang A4 hoặc Letter.
+pub const MAX_PAGE_MARGIN_INCHES: f64 = 3.0;
+
+impl PageMargins {
+    /// Danh sách `left,right,top,bottom` trong config (EXCEL_PRINT_MARGINS), ví dụ `0.5,0.5,0.75,0.75`.
+    pub fn from_list(value: &str) -> Option<Self> {
+        let values: Vec<f64> = value.split(',').map(|part| part.trim().parse().ok()).collect::<Option<_>>()?;
+        match values[..] {
+            [left, right, top, bottom] => Some(Self { left, right, top, bottom }),
+            _ => None,
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (side, value) in [("left", self.left), ("right", self.right), ("top", self.top), ("bottom", self.bottom)] {
+            anyhow::ensure!(
+                (0.0..=MAX_PAGE_MARGIN_INCHES).contains(&value),
+                "{} margin must be between 0 and {} inches, got {}",
+                side,
+                MAX_PAGE_MARGIN_INCHES,
+                value
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Kiểu dựng sẵn của Excel Table (EXCEL_TABLE_STYLE, `table_style` trong `excel_style` của payload), theo tên
+/// trong Excel: `Light1`-`Light21`, `Medium1`-`Medium28`, `Dark1`-`Dark11`, hoặc `None` (không tô màu).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ExcelTableStyle {
+    None,
+    Light(u8),
+    Medium(u8),
+    Dark(u8),
+}
+
+impl ExcelTableStyle {
+    /// Tên trong config (không phân biệt hoa thường), có thể kèm tiền tố `TableStyle` như trong Excel (`TableStyleMedium9`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        let lower = name.to_ascii_lowercase();
+        let name = lower.strip_prefix("tablestyle").unwrap_or(&lower);
+        if name == "none" {
+            return Some(ExcelTableStyle::None);
+        }
+        let split = name.find(|c: char| c.is_ascii_digit())?;
+        let number: u8 = name[split..].parse().ok()?;
+        let style = match &name[..split] {
+            "light" => ExcelTableStyle::Light(number),
+            "medium" => ExcelTableStyle::Medium(number),
+            "dark" => ExcelTableStyle::Dark(number),
+            _ => return None,
+        };
+        (1..=style.max_number()).contains(&number).then_some(style)
+    }
+
+    /// Số lớn nhất của nhóm kiểu trong Excel.
+    fn max_number(self) -> u8 {
+        match self {
+            ExcelTableStyle::None => 0,
+            ExcelTableStyle::Light(_) => 21,
+            ExcelTableStyle::Medium(_) => 28,
+            ExcelTableStyle::Dark(_) => 11,
+        }
+    }
+}
+
+impl fmt::Display for ExcelTableStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExcelTableStyle::None => write!(f, "None"),
+            ExcelTableStyle::Light(number) => write!(f, "Light{}", number),
+            ExcelTableStyle::Medium(number) => write!(f, "Medium{}", number),
+            ExcelTableStyle::Dark(number) => write!(f, "Dark{}", number),
+        }
+    }
+}
+
+impl TryFrom<String> for ExcelTableStyle {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        ExcelTableStyle::from_name(&name).ok_or_else(|| {
+            format!("invalid table style '{}': expected Light1-Light21, Medium1-Medium28, Dark1-Dark11 or None", name)
+        })
+    }
+}
+
+impl From<ExcelTableStyle> for String {
+    fn from(style: ExcelTableStyle) -> Self {
+        style.to_string()
+    }
+}
+
+/// Độ rộng cột lớn nhất Excel cho phép.
+pub const EXCEL_MAX_COLUMN_WIDTH: u16 = 255;
+
+/// Màu dạng `#RRGGBB`.
+pub fn is_hex_color(value: &str) -> bool {
+    value.len() == 7 && value.starts_with('#') && value[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Định dạng file export: `format` trong payload, hoặc `default_format` của loại report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Xlsx,
+    Csv,
+    /// Chỉ tạo được khi build với feature `parquet`.
+    Parquet,
+    /// JSON Lines: một JSON object mỗi dòng (`.jsonl`, hoặc `.jsonl.gz` khi nén gzip).
+    Jsonl,
+    /// OpenDocument Spreadsheet (LibreOffice). Chỉ tạo được khi build với feature `ods`.
+    Ods,
+    /// Bảng HTML độc lập, tối đa HTML_MAX_ROWS dòng.
+    Html,
+    /// Báo cáo PDF khổ A4, chỉ cho report `category_summary`. Chỉ tạo được khi build với feature `pdf`.
+    Pdf,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 7] = [
+        ExportFormat::Xlsx,
+        ExportFormat::Csv,
+        ExportFormat::Parquet,
+        ExportFormat::Jsonl,
+        ExportFormat::Ods,
+        ExportFormat::Html,
+        ExportFormat::Pdf,
+    ];
+
+    /// Cargo feature cần có để tạo được định dạng này (`None`: luôn có trong mọi build).
+    pub fn required_feature(&self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Parquet => Some("parquet"),
+            ExportFormat::Ods => Some("ods"),
+            ExportFormat::Pdf => Some("pdf"),
+            ExportFormat::Xlsx | ExportFormat::Csv | ExportFormat::Jsonl | ExportFormat::Html => None,
+        }
+    }
+
+    /// Định dạng có trong build hiện tại.
+    pub fn is_available(&self) -> bool {
+        match self {
+            ExportFormat::Parquet => cfg!(feature = "parquet"),
+            ExportFormat::Ods => cfg!(feature = "ods"),
+            ExportFormat::Pdf => cfg!(feature = "pdf"),
+            ExportFormat::Xlsx | ExportFormat::Csv | ExportFormat::Jsonl | ExportFormat::Html => true,
+        }
+    }
+
+    /// Tên các định dạng có trong build hiện tại, theo thứ tự của `ALL`.
+    pub fn available_names() -> Vec<&'static str> {
+        ExportFormat::ALL.iter().filter(|format| format.is_available()).map(ExportFormat::extension).collect()
+    }
+
+    /// Tên trong config (không phân biệt hoa thường); `None` nếu định dạng chưa được hỗ trợ.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "xlsx" => Some(ExportFormat::Xlsx),
+            "csv" => Some(ExportFormat::Csv),
+            "parquet" => Some(ExportFormat::Parquet),
+            "jsonl" => Some(ExportFormat::Jsonl),
+            "ods" => Some(ExportFormat::Ods),
+            "html" => Some(ExportFormat::Html),
+            "pdf" => Some(ExportFormat::Pdf),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Xlsx => "xlsx",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Ods => "ods",
+            ExportFormat::Html => "html",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Ods => "application/vnd.oasis.opendocument.spreadsheet",
+            ExportFormat::Html => "text/html; charset=utf-8",
+            ExportFormat::Pdf => "application/pdf",
+        }
+    }
+}
+
+/// Thuật toán nén của file Parquet (PARQUET_COMPRESSION).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParquetCompression {
+    None,
+    #[default]
+    Snappy,
+    Zstd,
+}
+
+impl ParquetCompression {
+    /// Tên trong config (không phân biệt hoa thường).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(ParquetCompression::None),
+            "snappy" => Some(ParquetCompression::Snappy),
+            "zstd" => Some(ParquetCompression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Thiết lập ghi file Parquet: thuật toán nén và số dòng tối đa của một row group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParquetOptions {
+    pub compression: ParquetCompression,
+    pub row_group_size: usize,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self { compression: ParquetCompression::default(), row_group_size: 100_000 }
+    }
+}
+
+/// Nén luồng ghi của file CSV và JSON Lines (CSV_COMPRESSION, JSONL_COMPRESSION, `compression` của payload).
+/// Khác `compress` của payload: file gzip vẫn là file export (`.csv.gz`), không phải zip chứa file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl OutputCompression {
+    /// Tên trong config (không phân biệt hoa thường).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(OutputCompression::None),
+            "gzip" => Some(OutputCompression::Gzip),
+            _ => None,
+        }
+    }
+
+    /// Đuôi thêm vào sau phần mở rộng của định dạng (`.csv.gz`).
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            OutputCompression::None => "",
+            OutputCompression::Gzip => ".gz",
+        }
+    }
+
+    /// Định dạng ghi được dạng nén gzip.
+    pub fn supports(format: ExportFormat) -> bool {
+        matches!(format, ExportFormat::Csv | ExportFormat::Jsonl)
+    }
+}
+
+/// Cách giao file đã bị chia theo MAX_FILE_SIZE_BYTES (MAX_FILE_SIZE_DELIVERY).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitFileDelivery {
+    /// Mỗi phần là một file riêng; thông báo liệt kê link của từng phần (`file_part_urls`).
+    #[default]
+    Links,
+    /// Các phần được gộp vào một file zip (`{request_id}.zip`), mỗi phần là một entry.
+    Zip,
+}
+
+impl SplitFileDelivery {
+    /// Tên trong config (không phân biệt hoa thường).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "links" => Some(SplitFileDelivery::Links),
+            "zip" => Some(SplitFileDelivery::Zip),
+            _ => None,
+        }
+    }
+
+    /// Định dạng được chia thành nhiều file khi vượt MAX_FILE_SIZE_BYTES.
+    pub fn supports(format: ExportFormat) -> bool {
+        matches!(format, ExportFormat::Xlsx | ExportFormat::Csv | ExportFormat::Jsonl)
+    }
+}
+
+/// Khi nào giá trị CSV được đặt trong dấu nháy kép (CSV_QUOTE_STYLE).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvQuoteStyle {
+    /// Chỉ khi cần (chứa dấu phân cách, dấu nháy hoặc xuống dòng), giống `COPY ... WITH (FORMAT csv)` của Postgres.
+    #[default]
+    Necessary,
+    Always,
+    /// Mọi giá trị trừ số.
+    NonNumeric,
+}
+
+impl CsvQuoteStyle {
+    /// Tên trong config (không phân biệt hoa thường).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "necessary" => Some(CsvQuoteStyle::Necessary),
+            "always" => Some(CsvQuoteStyle::Always),
+            "non_numeric" => Some(CsvQuoteStyle::NonNumeric),
+            _ => None,
+        }
+    }
+}
+
+/// Ký tự kết thúc dòng của file CSV (CSV_LINE_TERMINATOR).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvLineTerminator {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl CsvLineTerminator {
+    /// Tên trong config (không phân biệt hoa thường).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "lf" => Some(CsvLineTerminator::Lf),
+            "crlf" => Some(CsvLineTerminator::Crlf),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CsvLineTerminator::Lf => "\n",
+            CsvLineTerminator::Crlf => "\r\n",
+        }
+    }
+}
+
+/// Dialect của file CSV. Giá trị mặc định lấy từ config (`CSV_*`), payload có thể ghi đè từng trường
+/// qua `format_options.csv`. Mặc định cho ra file giống hệt `COPY ... WITH (FORMAT csv, HEADER true)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub quote_style: CsvQuoteStyle,
+    pub line_terminator: CsvLineTerminator,
+    /// Label bảng mã theo chuẩn WHATWG (`utf-8`, `windows-1252`, `latin1`, `shift_jis`...), được chuyển mã bằng encoding_rs.
+    pub encoding: String,
+    /// Ghi UTF-8 BOM ở đầu file (chỉ với bảng mã UTF-8).
+    pub bom: bool,
+    pub header: bool,
+    /// Dấu thập phân của các ô số (`.` hoặc `,`, ví dụ cho Excel tiếng Đức cùng dấu phân cách `;`).
+    pub decimal_separator: char,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            quote_style: CsvQuoteStyle::default(),
+            line_terminator: CsvLineTerminator::default(),
+            encoding: "utf-8".to_string(),
+            bom: false,
+            header: true,
+            decimal_separator: '.',
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Áp dụng các trường được set trong `format_options.csv` của payload lên thiết lập mặc định.
+    pub fn with_overrides(&self, overrides: Option<&CsvOptionsOverrides>) -> Self {
+        let mut options = self.clone();
+        if let Some(overrides) = overrides {
+            if let Some(delimiter) = overrides.delimiter {
+                options.delimiter = delimiter;
+            }
+            if let Some(quote_style) = overrides.quote_style {
+                options.quote_style = quote_style;
+            }
+            if let Some(line_terminator) = overrides.line_terminator {
+                options.line_terminator = line_terminator;
+            }
+            if let Some(encoding) = &overrides.encoding {
+                options.encoding = encoding.clone();
+            }
+            if let Some(bom) = overrides.bom {
+                options.bom = bom;
+            }
+            if let Some(header) = overrides.header {
+                options.header = header;
+            }
+            if let Some(decimal_separator) = overrides.decimal_separator {
+                options.decimal_separator = decimal_separator;
+            }
+        }
+        options
+    }
+
+    /// Bảng mã của `encoding`. encoding_rs chỉ ghi được các bảng mã ASCII-compatible
+    /// (UTF-16 và `replacement` chỉ dùng để đọc).
+    pub fn output_encoding(&self) -> anyhow::Result<&'static encoding_rs::Encoding> {
+        let encoding = encoding_rs::Encoding::for_label(self.encoding.trim().as_bytes())
+            .ok_or_else(|| anyhow::anyhow!("unknown CSV encoding '{}'", self.encoding))?;
+        anyhow::ensure!(
+            encoding.output_encoding() == encoding,
+            "CSV encoding '{}' cannot be written, use an ASCII-compatible encoding such as utf-8 or windows-1252",
+            self.encoding
+        );
+        Ok(encoding)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let encoding = self.output_encoding()?;
+        anyhow::ensure!(
+            self.delimiter.is_ascii() && !matches!(self.delimiter, '"' | '\r' | '\n'),
+            "CSV delimiter must be an ASCII character other than a quote or line break, got {:?}",
+            self.delimiter
+        );
+        anyhow::ensure!(
+            matches!(self.decimal_separator, '.' | ','),
+            "CSV decimal separator must be '.' or ',', got {:?}",
+            self.decimal_separator
+        );
+        anyhow::ensure!(
+            self.delimiter != self.decimal_separator,
+            "CSV delimiter and decimal separator must differ, both are {:?}",
+            self.delimiter
+        );
+        anyhow::ensure!(
+            !self.bom || encoding == encoding_rs::UTF_8,
+            "a CSV byte order mark is only supported with utf-8, not {}",
+            encoding.name()
+        );
+        Ok(())
+    }
+
+    /// File giống hệt kết quả của `COPY ... WITH (FORMAT csv, HEADER true)` (BOM được ghi riêng).
+    pub fn is_copy_compatible(&self) -> bool {
+        let default = CsvOptions::default();
+        CsvOptions { bom: default.bom, encoding: default.encoding.clone(), ..self.clone() } == default
+            && self.output_encoding().is_ok_and(|encoding| encoding == encoding_rs::UTF_8)
+    }
+}
+
+/// `format_options.csv` trong payload: trường nào bỏ trống thì dùng giá trị của config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvOptionsOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<char>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_style: Option<CsvQuoteStyle>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_terminator: Option<CsvLineTerminator>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bom: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decimal_separator: Option<char>,
+}
+
+/// `format_options` trong payload: thiết lập riêng của từng định dạng file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csv: Option<CsvOptionsOverrides>,
+}
+
+/// Ký tự đầu khiến Excel/LibreOffice hiểu nội dung ô là công thức.
+const FORMULA_TRIGGERS: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Cách xử lý ô chữ bắt đầu bằng ký tự công thức trong file CSV, Excel và ODS (FORMULA_ESCAPE),
+/// chống chèn công thức qua dữ liệu người dùng nhập (ví dụ tên sản phẩm `=HYPERLINK(...)`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FormulaEscape {
+    /// Ghi nguyên giá trị (deployment chỉ có dữ liệu tin cậy).
+    Off,
+    /// Thêm `'` ở đầu để ô luôn là chữ.
+    #[default]
+    Prefix,
+    /// Bỏ các ký tự công thức ở đầu.
+    Strip,
+    /// Export thất bại với `UnsafeCellValue`.
+    Reject,
+}
+
+impl FormulaEscape {
+    /// Tên trong config (không phân biệt hoa thường).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Some(FormulaEscape::Off),
+            "prefix" => Some(FormulaEscape::Prefix),
+            "strip" => Some(FormulaEscape::Strip),
+            "reject" => Some(FormulaEscape::Reject),
+            _ => None,
+        }
+    }
+
+    /// Xử lý một ô. Chỉ ô chữ bị đổi; chữ là một số hợp lệ (ví dụ "-12.5") không chạy được công thức
+    /// nên được giữ nguyên; ô liên kết được xử lý theo chữ hiển thị. Trả về `None` khi ô bị từ chối.
+    pub fn apply(self, value: CellValue) -> Option<CellValue> {
+        if let CellValue::Link { text, url } = value {
+            return match self.apply(CellValue::Text(text))? {
+                CellValue::Text(text) => Some(CellValue::Link { text, url }),
+                other => Some(other),
+            };
+        }
+        let text = match &value {
+            CellValue::Text(text) if text.starts_with(FORMULA_TRIGGERS) && text.parse::<f64>().is_err() => text,
+            _ => return Some(value),
+        };
+        match self {
+            FormulaEscape::Off => Some(value),
+            FormulaEscape::Prefix => Some(CellValue::Text(format!("'{}", text))),
+            FormulaEscape::Strip => Some(CellValue::Text(text.trim_start_matches(FORMULA_TRIGGERS).to_string())),
+            FormulaEscape::Reject => None,
+        }
+    }
+}
+
+/// Ô chữ bắt đầu bằng ký tự công thức khi FORMULA_ESCAPE=reject.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsafeCellValue {
+    /// Số thứ tự dòng dữ liệu (từ 1, không tính header).
+    pub row: usize,
+    pub column: String,
+}
+
+impl fmt::Display for UnsafeCellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Value in row {} of column '{}' starts with a formula character", self.row, self.column)
+    }
+}
+
+impl std::error::Error for UnsafeCellValue {}
+
+/// Font của file PDF: thư mục chứa file TrueType và tên họ font
+/// (`{family}-Regular.ttf`, `-Bold.ttf`, `-Italic.ttf`, `-BoldItalic.ttf`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdfOptions {
+    pub font_dir: String,
+    pub font_family: String,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self {
+            font_dir: "/usr/share/fonts/truetype/liberation".to_string(),
+            font_family: "LiberationSans".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+/// Kênh giao file bổ sung ngoài thông báo HTTP, ví dụ `{"type": "email", "to": "..."}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Delivery {
+    Email { to: String },
+    /// Upload lên SFTP_HOST; `remote_dir` là thư mục con của SFTP_REMOTE_DIR.
+    Sftp {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        remote_dir: Option<String>,
+    },
+}
+
+/// `encryption` trong payload, ví dụ `{"type": "pgp", "recipient_key_id": "acme-health"}`:
+/// file được mã hóa tới khóa công khai `recipient_key_id` của PGP_RECIPIENT_KEYS.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ExportEncryption {
+    Pgp { recipient_key_id: String },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ReportParams {
-    pub start_date: NaiveDate,
-    pub end_date: NaiveDate,
-    pub product_category: Option<String>,
-    // Thêm các trường khác tùy theo yêu cầu của bạn
+/// Giai đoạn của export được thông báo. `processing` là thông báo trung gian,
+/// `completed`/`failed` là thông báo cuối (quyết định cột notification_sent).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationStage {
+    Processing,
+    Completed,
+    Failed,
+}
+
+impl NotificationStage {
+    /// Giai đoạn tương ứng với trạng thái cuối của request.
+    pub fn for_final_status(status: ExportStatus) -> Self {
+        if status == ExportStatus::Completed {
+            NotificationStage::Completed
+        } else {
+            NotificationStage::Failed
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct ExportNotification {
     pub request_id: Uuid,
     pub status: String,
+    pub stage: NotificationStage,
     pub file_url: Option<String>, // URL công khai của file Excel
     pub error_message: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>, // Thời điểm link download hết hạn
+    pub duration_ms: Option<i64>, // Tổng thời gian xử lý, chỉ có ở thông báo cuối
+    pub estimated_completion_at: Option<DateTime<Utc>>, // ETA, chỉ có ở thông báo PROCESSING
+    pub timings: Option<ExportTimings>, // Chỉ gửi khi bật NOTIFY_INCLUDE_TIMINGS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_by_limit: Option<bool>, // Còn dòng khớp bộ lọc nằm ngoài `limit` của payload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_file_name: Option<String>, // File tải về là zip hoặc gzip: tên file bên trong
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_size_bytes: Option<i64>, // và kích thước chưa nén
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_protected: Option<bool>, // File tải về cần mật khẩu để mở
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>, // Tên file tải về (FILENAME_TEMPLATE)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>, // Content type của file tải về
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_size_bytes: Option<i64>, // Kích thước file tải về
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows_exported: Option<i64>, // Số dòng dữ liệu trong file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_part_urls: Option<Vec<String>>, // File bị chia theo MAX_FILE_SIZE_BYTES: link của mọi phần, `file_url` là phần 1
 }
 
 #[derive(Debug, sqlx::FromRow, Serialize)]
@@ -42,20 +1853,1021 @@ pub struct ProductData {
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct OrderData {
+    pub order_id: i64,
+    pub user_id: i64,
+    pub product_name: String,
+    pub quantity: i32,
+    pub unit_price: f64,
+    pub total: f64,
+    pub ordered_at: DateTime<Utc>,
+    pub status: String,
+}
+
+/// Bộ lọc của report đơn hàng.
+#[derive(Debug, Clone)]
+pub struct OrderReportParams {
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub status: Option<OrderStatus>,
+    pub user_id: Option<i64>,
+    pub tenant: Option<String>,
+    pub time_zone: Option<Tz>,
+}
+
+impl OrderReportParams {
+    /// Khoảng ngày theo múi giờ của payload, đổi sang UTC (xem `utc_day_range`).
+    pub fn utc_range(&self) -> (NaiveDateTime, NaiveDateTime) {
+        utc_day_range(self.start_date, self.end_date, self.time_zone)
+    }
+}
+
+/// Các trạng thái hợp lệ của đơn hàng; giá trị khác bị từ chối ngay khi parse payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Refunded,
+}
+
+impl OrderStatus {
+    /// Giá trị lưu trong cột orders.status.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+        }
+    }
+}
+
+/// Layout cột của report đơn hàng.
+pub const ORDER_COLUMNS: &[ColumnDef] = &[
+    ColumnDef::new("order_id", "Order ID", ColumnFormat::Integer, Some(12.0)).with_aggregate(ColumnAggregate::Count),
+    ColumnDef::new("user_id", "User ID", ColumnFormat::Integer, Some(10.0)),
+    ColumnDef::new("product_name", "Product Name", ColumnFormat::General, Some(40.0)),
+    ColumnDef::new("quantity", "Quantity", ColumnFormat::Integer, Some(10.0)).with_aggregate(ColumnAggregate::Sum),
+    ColumnDef::new("unit_price", "Unit Price", ColumnFormat::Decimal, Some(12.0)).with_aggregate(ColumnAggregate::Avg),
+    ColumnDef::new("total", "Total", ColumnFormat::Decimal, Some(12.0)).with_aggregate(ColumnAggregate::Sum),
+    ColumnDef::new("ordered_at", "Ordered At", ColumnFormat::DateTime, Some(22.0)),
+    ColumnDef::new("status", "Status", ColumnFormat::General, Some(12.0)),
+];
+
+impl ExportRow for OrderData {
+    fn cell(&self, field: &str) -> CellValue {
+        match field {
+            "order_id" => CellValue::Number(self.order_id as f64),
+            "user_id" => CellValue::Number(self.user_id as f64),
+            "product_name" => CellValue::Text(self.product_name.clone()),
+            "quantity" => CellValue::Number(self.quantity as f64),
+            "unit_price" => CellValue::Number(self.unit_price),
+            "total" => CellValue::Number(self.total),
+            "ordered_at" => CellValue::DateTime(self.ordered_at.with_timezone(&Tz::UTC)),
+            "status" => CellValue::Text(self.status.clone()),
+            _ => CellValue::Text(String::new()),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct CustomerData {
+    pub customer_id: i64,
+    pub name: String,
+    pub email: String,
+    pub signup_date: NaiveDate,
+    pub total_orders: i64,
+    pub lifetime_value: f64,
+}
+
+/// Bộ lọc của report khách hàng.
+#[derive(Debug, Clone)]
+pub struct CustomerReportParams {
+    pub signup_from: NaiveDate,
+    pub signup_to: NaiveDate,
+    pub min_lifetime_value: Option<f64>,
+    /// Chỉ lấy khách hàng có customer_id này (report OwnerOnly).
+    pub owner_user_id: Option<i64>,
+    pub tenant: Option<String>,
+}
+
+/// Layout cột của report khách hàng.
+pub const CUSTOMER_COLUMNS: &[ColumnDef] = &[
+    ColumnDef::new("customer_id", "Customer ID", ColumnFormat::Integer, Some(12.0)).with_aggregate(ColumnAggregate::Count),
+    ColumnDef::new("name", "Name", ColumnFormat::General, Some(30.0)),
+    ColumnDef::new("email", "Email", ColumnFormat::General, Some(32.0)),
+    ColumnDef::new("signup_date", "Signup Date", ColumnFormat::Date, Some(12.0)),
+    ColumnDef::new("total_orders", "Total Orders", ColumnFormat::Integer, Some(12.0)).with_aggregate(ColumnAggregate::Sum),
+    ColumnDef::new("lifetime_value", "Lifetime Value", ColumnFormat::Decimal, Some(15.0))
+        .with_aggregate(ColumnAggregate::Sum)
+        .with_conditional_format(ConditionalFormat::ColorScale),
+];
+
+impl ExportRow for CustomerData {
+    fn cell(&self, field: &str) -> CellValue {
+        match field {
+            "customer_id" => CellValue::Number(self.customer_id as f64),
+            "name" => CellValue::Text(self.name.clone()),
+            "email" => CellValue::Text(self.email.clone()),
+            "signup_date" => CellValue::Date(self.signup_date),
+            "total_orders" => CellValue::Number(self.total_orders as f64),
+            "lifetime_value" => CellValue::Number(self.lifetime_value),
+            _ => CellValue::Text(String::new()),
+        }
+    }
+}
+
+/// Một dòng của report tổng hợp theo category. `category` là `None` ở dòng tổng (ROLLUP).
+/// Các giá trị giá là `None` khi không có sản phẩm nào khớp bộ lọc.
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct SalesSummaryRow {
+    pub category: Option<String>,
+    pub product_count: i64,
+    pub total_stock: i64,
+    pub min_price: Option<f64>,
+    pub avg_price: Option<f64>,
+    pub max_price: Option<f64>,
+}
+
+/// Layout cột của report tổng hợp theo category.
+pub const SUMMARY_COLUMNS: &[ColumnDef] = &[
+    ColumnDef::new("category", "Category", ColumnFormat::General, Some(24.0)),
+    ColumnDef::new("product_count", "Product Count", ColumnFormat::Integer, Some(14.0)),
+    ColumnDef::new("total_stock", "Total Stock", ColumnFormat::Integer, Some(14.0)),
+    ColumnDef::new("min_price", "Min Price", ColumnFormat::Decimal, Some(12.0)),
+    ColumnDef::new("avg_price", "Avg Price", ColumnFormat::Decimal, Some(12.0)),
+    ColumnDef::new("max_price", "Max Price", ColumnFormat::Decimal, Some(12.0)),
+];
+
+/// Biểu đồ cột của một report (`include_chart` của payload): nhãn lấy từ cột `category_field`,
+/// giá trị từ cột `value_field`; tên trục là header của hai cột đó.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChartSpec {
+    pub title: &'static str,
+    pub category_field: &'static str,
+    pub value_field: &'static str,
+    /// Dòng cuối là dòng tổng, không đưa vào biểu đồ.
+    pub skip_last_row: bool,
+}
+
+/// Tổng tồn kho theo category; bỏ dòng tổng của ROLLUP.
+pub const SUMMARY_CHART: ChartSpec = ChartSpec {
+    title: "Total Stock by Category",
+    category_field: "category",
+    value_field: "total_stock",
+    skip_last_row: true,
+};
+
+/// Biểu đồ của loại report; `None` khi layout của report không có chuỗi số liệu để vẽ.
+pub fn chart_for_report_type(report_type: &str) -> Option<ChartSpec> {
+    match report_type {
+        CATEGORY_SUMMARY_REPORT_TYPE => Some(SUMMARY_CHART),
+        _ => None,
+    }
+}
+
+impl ExportRow for SalesSummaryRow {
+    fn cell(&self, field: &str) -> CellValue {
+        let price = |value: Option<f64>| value.map_or_else(|| CellValue::Text(String::new()), CellValue::Number);
+        match field {
+            "category" => CellValue::Text(self.category.clone().unwrap_or_else(|| "Total".to_string())),
+            "product_count" => CellValue::Number(self.product_count as f64),
+            "total_stock" => CellValue::Number(self.total_stock as f64),
+            "min_price" => price(self.min_price),
+            "avg_price" => price(self.avg_price),
+            "max_price" => price(self.max_price),
+            _ => CellValue::Text(String::new()),
+        }
+    }
+}
+
+/// Dữ liệu đã query của một report, kèm layout cột để exporter ghi ra file.
+pub enum ReportData {
+    Products {
+        rows: Vec<ProductData>,
+        columns: Vec<ProductColumn>,
+    },
+    Orders(Vec<OrderData>),
+    Customers(Vec<CustomerData>),
+    /// Các dòng theo category, dòng tổng luôn nằm cuối.
+    CategorySummary(Vec<SalesSummaryRow>),
+    /// Kết quả của dataset tùy chỉnh; tên cột lấy từ metadata của kết quả.
+    Dataset(DatasetRows),
+    /// Dữ liệu có ngày giờ được đổi sang múi giờ `time_zone` của payload (xem `ReportData::in_time_zone`).
+    Zoned { data: Box<ReportData>, time_zone: Tz, zone_name: String },
+    /// Dữ liệu có các cột `hyperlink` thành link theo `url_template` (xem `ReportData::with_link_url_template`).
+    Linked { data: Box<ReportData>, url_template: String },
+}
+
+impl ReportData {
+    pub fn len(&self) -> usize {
+        match self {
+            ReportData::Products { rows, .. } => rows.len(),
+            ReportData::Orders(rows) => rows.len(),
+            ReportData::Customers(rows) => rows.len(),
+            ReportData::CategorySummary(rows) => rows.len(),
+            ReportData::Dataset(data) => data.rows.len(),
+            ReportData::Zoned { data, .. } => data.len(),
+            ReportData::Linked { data, .. } => data.len(),
+        }
+    }
+
+    /// Ghi các cột có `hyperlink` thành link tới `url_template` (`link_url_template` của loại report).
+    /// Được áp dụng sau các hook, như `in_time_zone`, để hook vẫn thấy đúng biến thể dữ liệu gốc.
+    pub fn with_link_url_template(self, url_template: String) -> ReportData {
+        ReportData::Linked { data: Box::new(self), url_template }
+    }
+
+    /// Đổi mọi ô ngày giờ sang múi giờ `time_zone`; header của các cột ngày giờ ghi thêm tên viết tắt
+    /// của múi giờ tại thời điểm `now`, ví dụ "Created At (ICT)".
+    pub fn in_time_zone(self, time_zone: Tz, now: DateTime<Utc>) -> ReportData {
+        let zone_name = time_zone.offset_from_utc_datetime(&now.naive_utc()).abbreviation().to_string();
+        ReportData::Zoned { data: Box::new(self), time_zone, zone_name }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Tách các dòng từ vị trí `at` trở đi thành dữ liệu mới cùng layout, như `Vec::split_off`
+    /// (chia file theo MAX_FILE_SIZE_BYTES). Panic khi `at > len()`.
+    pub fn split_off(&mut self, at: usize) -> ReportData {
+        match self {
+            ReportData::Products { rows, columns } => ReportData::Products { rows: rows.split_off(at), columns: columns.clone() },
+            ReportData::Orders(rows) => ReportData::Orders(rows.split_off(at)),
+            ReportData::Customers(rows) => ReportData::Customers(rows.split_off(at)),
+            ReportData::CategorySummary(rows) => ReportData::CategorySummary(rows.split_off(at)),
+            ReportData::Dataset(data) => {
+                ReportData::Dataset(DatasetRows { columns: data.columns.clone(), rows: data.rows.split_off(at) })
+            }
+            ReportData::Zoned { data, time_zone, zone_name } => ReportData::Zoned {
+                data: Box::new(data.split_off(at)),
+                time_zone: *time_zone,
+                zone_name: zone_name.clone(),
+            },
+            ReportData::Linked { data, url_template } => {
+                ReportData::Linked { data: Box::new(data.split_off(at)), url_template: url_template.clone() }
+            }
+        }
+    }
+
+    /// Layout cột của dữ liệu. Cột của dataset lấy tên từ kết quả query, định dạng `General`
+    /// (số giữ nguyên, ngày giờ định dạng theo kiểu của ô) và không có độ rộng khai báo.
+    pub fn layout(&self) -> ExportLayout {
+        match self {
+            ReportData::Products { columns, .. } => ExportLayout::products(columns),
+            ReportData::Orders(_) => ExportLayout::new(ORDER_COLUMNS),
+            ReportData::Customers(_) => ExportLayout::new(CUSTOMER_COLUMNS),
+            ReportData::CategorySummary(_) => ExportLayout::new(SUMMARY_COLUMNS),
+            ReportData::Dataset(data) => ExportLayout {
+                columns: data
+                    .columns
+                    .iter()
+                    .map(|name| ColumnDef {
+                        field: Cow::Owned(name.clone()),
+                        header: Cow::Owned(name.clone()),
+                        format: ColumnFormat::General,
+                        width: None,
+                        aggregate: ColumnAggregate::None,
+                        conditional_format: None,
+                        hyperlink: None,
+                    })
+                    .collect(),
+                url_template: None,
+            },
+            ReportData::Zoned { data, zone_name, .. } => {
+                let mut layout = data.layout();
+                let datetime_columns = data.datetime_columns();
+                for (column, is_datetime) in layout.columns.iter_mut().zip(datetime_columns) {
+                    if is_datetime {
+                        column.header = Cow::Owned(format!("{} ({})", column.header, zone_name));
+                    }
+                }
+                layout
+            }
+            ReportData::Linked { data, url_template } => ExportLayout { url_template: Some(url_template.clone()), ..data.layout() },
+        }
+    }
+
+    /// Biểu đồ khai báo cho loại dữ liệu này (xem `chart_for_report_type`).
+    pub fn chart(&self) -> Option<ChartSpec> {
+        match self {
+            ReportData::CategorySummary(_) => Some(SUMMARY_CHART),
+            ReportData::Zoned { data, .. } | ReportData::Linked { data, .. } => data.chart(),
+            _ => None,
+        }
+    }
+
+    /// Cột nào chứa ngày giờ: theo định dạng khai báo, với dataset thì theo giá trị của dòng đầu tiên.
+    fn datetime_columns(&self) -> Vec<bool> {
+        match self {
+            ReportData::Dataset(data) => match data.rows.first() {
+                Some(row) => row.iter().map(|value| matches!(value, CellValue::DateTime(_))).collect(),
+                None => vec![false; data.columns.len()],
+            },
+            ReportData::Zoned { data, .. } | ReportData::Linked { data, .. } => data.datetime_columns(),
+            data => data.layout().columns.iter().map(|column| column.format == ColumnFormat::DateTime).collect(),
+        }
+    }
+
+    /// Giá trị từng dòng, theo đúng thứ tự cột của `layout()`.
+    pub fn rows(&self) -> Box<dyn Iterator<Item = Vec<CellValue>> + Send + '_> {
+        self.rows_in(self.layout())
+    }
+
+    /// Giá trị từng dòng theo `layout` của lớp ngoài cùng: URL template của `Linked` phải tới được
+    /// các dòng gốc dù `Zoned` bọc bên ngoài.
+    fn rows_in(&self, layout: ExportLayout) -> Box<dyn Iterator<Item = Vec<CellValue>> + Send + '_> {
+        match self {
+            ReportData::Products { rows, .. } => Box::new(rows.iter().map(move |row| layout.cells(row))),
+            ReportData::Orders(rows) => Box::new(rows.iter().map(move |row| layout.cells(row))),
+            ReportData::Customers(rows) => Box::new(rows.iter().map(move |row| layout.cells(row))),
+            ReportData::CategorySummary(rows) => Box::new(rows.iter().map(move |row| layout.cells(row))),
+            ReportData::Dataset(data) => Box::new(data.rows.iter().cloned()),
+            ReportData::Zoned { data, time_zone, .. } => {
+                let time_zone = *time_zone;
+                Box::new(data.rows_in(layout).map(move |row| {
+                    row.into_iter()
+                        .map(|value| match value {
+                            CellValue::DateTime(value) => CellValue::DateTime(value.with_timezone(&time_zone)),
+                            value => value,
+                        })
+                        .collect()
+                }))
+            }
+            ReportData::Linked { data, .. } => data.rows_in(layout),
+        }
+    }
+
+    /// Như `rows()`, với các ô chữ đã được xử lý theo `escape` cho file bảng tính (CSV, Excel, ODS).
+    pub fn escaped_rows(
+        &self,
+        escape: FormulaEscape,
+    ) -> Box<dyn Iterator<Item = Result<Vec<CellValue>, UnsafeCellValue>> + Send + '_> {
+        if escape == FormulaEscape::Off {
+            return Box::new(self.rows().map(Ok));
+        }
+        let layout = self.layout();
+        Box::new(self.rows().enumerate().map(move |(index, row)| {
+            row.into_iter()
+                .zip(&layout.columns)
+                .map(|(value, column)| {
+                    escape.apply(value).ok_or_else(|| UnsafeCellValue { row: index + 1, column: column.header.to_string() })
+                })
+                .collect()
+        }))
+    }
+
+    /// Từng dòng dạng JSON object (JSON Lines), chỉ gồm các cột của `layout()`.
+    /// Dòng của dataset được dựng từ giá trị các ô, theo tên cột của kết quả query.
+    pub fn json_rows(&self) -> Box<dyn Iterator<Item = serde_json::Result<serde_json::Map<String, serde_json::Value>>> + Send + '_> {
+        let layout = self.layout();
+        match self {
+            ReportData::Products { rows, .. } => Box::new(rows.iter().map(move |row| layout.json_object(row))),
+            ReportData::Orders(rows) => Box::new(rows.iter().map(move |row| layout.json_object(row))),
+            ReportData::Customers(rows) => Box::new(rows.iter().map(move |row| layout.json_object(row))),
+            ReportData::CategorySummary(rows) => Box::new(rows.iter().map(move |row| layout.json_object(row))),
+            ReportData::Dataset(data) => Box::new(data.rows.iter().map(|row| {
+                Ok(data.columns.iter().cloned().zip(row.iter().map(CellValue::to_json)).collect())
+            })),
+            // JSON giữ giá trị gốc của cột link (ví dụ tên sản phẩm), không có URL.
+            ReportData::Linked { data, .. } => data.json_rows(),
+            // Giữ nguyên kiểu JSON của dữ liệu gốc, chỉ thay giá trị ngày giờ bằng giờ địa phương kèm offset.
+            ReportData::Zoned { data, .. } => {
+                let fields: Vec<String> = data.layout().columns.iter().map(|column| column.field.to_string()).collect();
+                Box::new(data.json_rows().zip(self.rows()).map(move |(object, cells)| {
+                    let mut object = object?;
+                    for (field, value) in fields.iter().zip(cells) {
+                        if let CellValue::DateTime(_) = value {
+                            object.insert(field.clone(), value.to_json());
+                        }
+                    }
+                    Ok(object)
+                }))
+            }
+        }
+    }
+}
+
+/// Kết quả query của dataset tùy chỉnh: tên cột và giá trị từng ô.
+#[derive(Debug, Clone, Default)]
+pub struct DatasetRows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<CellValue>>,
+}
+
+/// Kiểu của một tham số dataset, khai báo trong CUSTOM_DATASETS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatasetParamType {
+    Text,
+    Int,
+    Float,
+    Bool,
+    Date,
+    Timestamp,
+}
+
+/// Giá trị đã kiểm tra kiểu của một tham số dataset, được bind vào SQL template (không bao giờ ghép chuỗi).
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatasetValue {
+    Text(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Date(NaiveDate),
+    Timestamp(NaiveDateTime),
+}
+
+impl DatasetParamType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DatasetParamType::Text => "text",
+            DatasetParamType::Int => "int",
+            DatasetParamType::Float => "float",
+            DatasetParamType::Bool => "bool",
+            DatasetParamType::Date => "date",
+            DatasetParamType::Timestamp => "timestamp",
+        }
+    }
+
+    /// Chuyển giá trị JSON của payload sang kiểu đã khai báo. Ngày dạng `YYYY-MM-DD`,
+    /// thời điểm dạng `YYYY-MM-DDTHH:MM:SS`; số thực nhận cả số nguyên.
+    pub fn parse(&self, value: &serde_json::Value) -> anyhow::Result<DatasetValue> {
+        let parsed = match self {
+            DatasetParamType::Text => value.as_str().map(|v| DatasetValue::Text(v.to_string())),
+            DatasetParamType::Int => value.as_i64().map(DatasetValue::Int),
+            DatasetParamType::Float => value.as_f64().map(DatasetValue::Float),
+            DatasetParamType::Bool => value.as_bool().map(DatasetValue::Bool),
+            DatasetParamType::Date => value
+                .as_str()
+                .and_then(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d").ok())
+                .map(DatasetValue::Date),
+            DatasetParamType::Timestamp => value
+                .as_str()
+                .and_then(|v| v.parse::<NaiveDateTime>().ok())
+                .map(DatasetValue::Timestamp),
+        };
+        parsed.ok_or_else(|| anyhow::anyhow!("expected a {} value, got {}", self.name(), value))
+    }
+}
+
+/// Trạng thái của ExportRequest, lưu trong cột status dạng chuỗi in hoa (`PENDING`, `PROCESSING`...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ExportStatus {
     Pending,
     Processing,
     Completed,
     Failed,
+    Expired,
 }
 
 impl ExportStatus {
+    pub const ALL: [ExportStatus; 5] = [
+        ExportStatus::Pending,
+        ExportStatus::Processing,
+        ExportStatus::Completed,
+        ExportStatus::Failed,
+        ExportStatus::Expired,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             ExportStatus::Pending => "PENDING",
             ExportStatus::Processing => "PROCESSING",
             ExportStatus::Completed => "COMPLETED",
             ExportStatus::Failed => "FAILED",
+            ExportStatus::Expired => "EXPIRED",
+        }
+    }
+
+    /// Trạng thái cuối: request không được xử lý lại.
+    pub fn is_final(&self) -> bool {
+        matches!(self, ExportStatus::Completed | ExportStatus::Failed | ExportStatus::Expired)
+    }
+}
+
+impl fmt::Display for ExportStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Lỗi khi cột status chứa giá trị không thuộc `ExportStatus` (so khớp phân biệt hoa thường).
+#[derive(Debug)]
+pub struct UnknownExportStatus(pub String);
+
+impl fmt::Display for UnknownExportStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expected: Vec<&str> = ExportStatus::ALL.iter().map(|status| status.as_str()).collect();
+        write!(f, "unknown export status '{}', expected one of {}", self.0, expected.join(", "))
+    }
+}
+
+impl std::error::Error for UnknownExportStatus {}
+
+impl FromStr for ExportStatus {
+    type Err = UnknownExportStatus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ExportStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| UnknownExportStatus(s.to_string()))
+    }
+}
+
+// Cột status là TEXT/VARCHAR (không dùng enum type của DB), nên ExportStatus được encode/decode
+// như chuỗi; giá trị lạ trả về `UnknownExportStatus` khi decode.
+impl<DB: sqlx::Database> sqlx::Type<DB> for ExportStatus
+where
+    str: sqlx::Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <str as sqlx::Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <str as sqlx::Type<DB>>::compatible(ty)
+    }
+}
+
+impl<'q, DB: sqlx::Database> sqlx::Encode<'q, DB> for ExportStatus
+where
+    &'q str: sqlx::Encode<'q, DB>,
+{
+    fn encode_by_ref(&self, buf: &mut <DB as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+        <&'q str as sqlx::Encode<'q, DB>>::encode_by_ref(&self.as_str(), buf)
+    }
+}
+
+impl<'r, DB: sqlx::Database> sqlx::Decode<'r, DB> for ExportStatus
+where
+    &'r str: sqlx::Decode<'r, DB>,
+{
+    fn decode(value: <DB as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
+        let value = <&'r str as sqlx::Decode<'r, DB>>::decode(value)?;
+        Ok(value.parse()?)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(payload: serde_json::Value) -> ReportParams {
+        serde_json::from_value(payload).unwrap()
+    }
+
+    #[test]
+    fn content_hash_is_stable_across_field_order() {
+        let ordered: ReportParams = serde_json::from_str(
+            r#"{"report_type": "products", "start_date": "2024-05-01", "end_date": "2024-05-31",
+                "product_category": "Books", "min_price": 10.0, "sort": {"by": "price", "dir": "desc"},
+                "columns": ["name", "price"]}"#,
+        )
+        .unwrap();
+        let shuffled: ReportParams = serde_json::from_str(
+            r#"{"columns": ["name", "price"], "sort": {"dir": "desc", "by": "price"}, "min_price": 10.0,
+                "end_date": "2024-05-31", "product_category": "Books", "start_date": "2024-05-01",
+                "report_type": "products"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(ordered.content_hash(42), shuffled.content_hash(42));
+    }
+
+    #[test]
+    fn content_hash_ignores_delivery_and_notification_fields() {
+        let base = params(serde_json::json!({"start_date": "2024-05-01", "end_date": "2024-05-31"}));
+        let with_delivery = params(serde_json::json!({
+            "start_date": "2024-05-01",
+            "end_date": "2024-05-31",
+            "delivery": {"type": "email", "to": "jane@example.com"},
+            "expires_in_hours": 2,
+            "notify_on": ["processing", "completed"],
+            "skip_notification": true,
+        }));
+
+        assert_eq!(base.content_hash(42), with_delivery.content_hash(42));
+    }
+
+    #[test]
+    fn content_hash_keeps_protection_but_not_password() {
+        let base = params(serde_json::json!({"start_date": "2024-05-01", "end_date": "2024-05-31"}));
+        let protected = |password: &str| {
+            params(serde_json::json!({
+                "start_date": "2024-05-01",
+                "end_date": "2024-05-31",
+                "protection": {"password": password},
+            }))
+        };
+
+        assert_eq!(protected("secret").content_hash(42), protected("other").content_hash(42));
+        assert_ne!(base.content_hash(42), protected("secret").content_hash(42));
+        let parameters = serde_json::Value::Object(protected("secret").content_parameters()).to_string();
+        assert!(!parameters.contains("secret"));
+    }
+
+    #[test]
+    fn each_sort_key_maps_to_a_fixed_order_by() {
+        let order_by = |sort: serde_json::Value| {
+            params(serde_json::json!({"start_date": "2024-05-01", "end_date": "2024-05-31", "sort": sort})).order_by_sql()
+        };
+
+        assert_eq!(order_by(serde_json::json!({"by": "product_id", "dir": "desc"})), "product_id DESC");
+        assert_eq!(order_by(serde_json::json!({"by": "name"})), "name ASC, product_id ASC");
+        assert_eq!(order_by(serde_json::json!({"by": "category", "dir": "asc"})), "category ASC, product_id ASC");
+        assert_eq!(order_by(serde_json::json!({"by": "price", "dir": "desc"})), "price DESC, product_id DESC");
+        assert_eq!(
+            order_by(serde_json::json!({"by": "stock_quantity", "dir": "desc"})),
+            "stock_quantity DESC, product_id DESC"
+        );
+        assert_eq!(order_by(serde_json::json!({"by": "created_at", "dir": "desc"})), "created_at DESC, product_id DESC");
+    }
+
+    #[test]
+    fn default_sort_is_created_at_ascending() {
+        let params = params(serde_json::json!({"start_date": "2024-05-01", "end_date": "2024-05-31"}));
+
+        assert_eq!(params.order_by_sql(), "created_at ASC, product_id ASC");
+        assert!(params.uses_default_sort());
+    }
+
+    #[test]
+    fn unknown_sort_column_or_direction_is_rejected_at_parse_time() {
+        let parse = |sort: serde_json::Value| {
+            serde_json::from_value::<ReportParams>(serde_json::json!({
+                "start_date": "2024-05-01",
+                "end_date": "2024-05-31",
+                "sort": sort,
+            }))
+        };
+
+        assert!(parse(serde_json::json!({"by": "price; DROP TABLE products"})).is_err());
+        assert!(parse(serde_json::json!({"by": "description"})).is_err());
+        assert!(parse(serde_json::json!({"by": "price", "dir": "sideways"})).is_err());
+        assert!(parse(serde_json::json!({"by": "price", "nulls": "first"})).is_err());
+    }
+
+    #[test]
+    fn export_status_round_trips_every_variant() {
+        for status in ExportStatus::ALL {
+            assert_eq!(status.to_string().parse::<ExportStatus>().unwrap(), status);
+            let json = serde_json::to_value(status).unwrap();
+            assert_eq!(json, serde_json::Value::String(status.as_str().to_string()));
+            assert_eq!(serde_json::from_value::<ExportStatus>(json).unwrap(), status);
+        }
+    }
+
+    #[test]
+    fn unknown_export_status_is_rejected_with_a_descriptive_error() {
+        for garbage in ["completed", "DONE", "", " PENDING"] {
+            let error = garbage.parse::<ExportStatus>().unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "unknown export status '{}', expected one of PENDING, PROCESSING, COMPLETED, FAILED, EXPIRED",
+                    garbage
+                )
+            );
+        }
+        assert!(serde_json::from_value::<ExportStatus>(serde_json::json!("Completed")).is_err());
+    }
+
+    #[test]
+    fn content_hash_depends_on_user_and_content() {
+        let products = params(serde_json::json!({"start_date": "2024-05-01", "end_date": "2024-05-31"}));
+        let books = params(serde_json::json!({
+            "start_date": "2024-05-01",
+            "end_date": "2024-05-31",
+            "product_category": "Books",
+        }));
+
+        assert_ne!(products.content_hash(1), products.content_hash(2));
+        assert_ne!(products.content_hash(1), books.content_hash(1));
+    }
+
+    fn text(value: &str) -> CellValue {
+        CellValue::Text(value.to_string())
+    }
+
+    #[test]
+    fn every_formula_trigger_is_escaped() {
+        for value in ["=HYPERLINK(\"http://evil\")", "+1+1", "-2+3", "@SUM(A1)", "\t=1+1", "\r=1+1"] {
+            assert_eq!(FormulaEscape::Prefix.apply(text(value)), Some(text(&format!("'{}", value))), "{:?}", value);
+            assert_eq!(FormulaEscape::Reject.apply(text(value)), None, "{:?}", value);
+            assert_eq!(FormulaEscape::Off.apply(text(value)), Some(text(value)), "{:?}", value);
+        }
+        assert_eq!(FormulaEscape::Strip.apply(text("=HYPERLINK(\"x\")")), Some(text("HYPERLINK(\"x\")")));
+        assert_eq!(FormulaEscape::Strip.apply(text("\t\r+-=@cmd")), Some(text("cmd")));
+    }
+
+    #[test]
+    fn numbers_and_number_like_text_are_left_alone() {
+        for escape in [FormulaEscape::Prefix, FormulaEscape::Strip, FormulaEscape::Reject] {
+            for value in ["-12.5", "+7", "-0", "-1e5"] {
+                assert_eq!(escape.apply(text(value)), Some(text(value)), "{:?} {:?}", escape, value);
+            }
+            assert_eq!(escape.apply(CellValue::Number(-12.5)), Some(CellValue::Number(-12.5)));
+            assert_eq!(escape.apply(text("plain = text")), Some(text("plain = text")));
+        }
+        // Trông giống số âm nhưng là công thức.
+        assert_eq!(FormulaEscape::Prefix.apply(text("-1+cmd|' /C calc'!A0")), Some(text("'-1+cmd|' /C calc'!A0")));
+    }
+
+    #[test]
+    fn links_are_escaped_by_their_text_and_keep_their_url() {
+        let link = CellValue::Link { text: "=cmd".to_string(), url: "https://example.com/p/1".to_string() };
+
+        assert_eq!(
+            FormulaEscape::Prefix.apply(link.clone()),
+            Some(CellValue::Link { text: "'=cmd".to_string(), url: "https://example.com/p/1".to_string() })
+        );
+        assert_eq!(FormulaEscape::Reject.apply(link), None);
+    }
+
+    #[test]
+    fn rejected_cell_reports_its_row_and_column() {
+        let data = ReportData::Dataset(DatasetRows {
+            columns: vec!["name".to_string(), "note".to_string()],
+            rows: vec![vec![text("Tea"), text("ok")], vec![text("Cake"), text("@SUM(A1)")]],
+        });
+
+        let error = data.escaped_rows(FormulaEscape::Reject).collect::<Result<Vec<_>, _>>().unwrap_err();
+
+        assert_eq!(error, UnsafeCellValue { row: 2, column: "note".to_string() });
+    }
+
+    fn utc(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    fn day(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn day_range_without_time_zone_is_utc() {
+        let (start, end) = utc_day_range(day("2024-03-10"), day("2024-03-10"), None);
+
+        assert_eq!(start, utc("2024-03-10 00:00:00"));
+        assert_eq!(end, utc("2024-03-10 23:59:59.999999999"));
+    }
+
+    #[test]
+    fn day_range_on_a_spring_forward_day_is_23_hours_long() {
+        let (start, end) = utc_day_range(day("2024-03-10"), day("2024-03-10"), Some(Tz::America__New_York));
+
+        assert_eq!(start, utc("2024-03-10 05:00:00"));
+        assert_eq!(end, utc("2024-03-11 03:59:59.999999999"));
+    }
+
+    #[test]
+    fn day_starting_in_a_dst_gap_starts_after_the_gap() {
+        // Chile tiến đồng hồ lúc 00:00 ngày 2024-09-08: ngày đó bắt đầu lúc 01:00 (-03).
+        let (start, _) = utc_day_range(day("2024-09-08"), day("2024-09-08"), Some(Tz::America__Santiago));
+
+        assert_eq!(start, utc("2024-09-08 04:00:00"));
+    }
+
+    #[test]
+    fn day_ending_in_a_repeated_hour_ends_at_its_latest_instant() {
+        // Chile lùi đồng hồ lúc 00:00 ngày 2024-04-07 về 23:00 ngày 2024-04-06: giờ cuối ngày 04-06 lặp lại.
+        let (start, end) = utc_day_range(day("2024-04-06"), day("2024-04-06"), Some(Tz::America__Santiago));
+
+        assert_eq!(start, utc("2024-04-06 03:00:00"));
+        assert_eq!(end, utc("2024-04-07 03:59:59.999999999"));
+    }
+
+    #[test]
+    fn invalid_time_zone_is_rejected() {
+        let invalid = params(serde_json::json!({
+            "start_date": "2024-01-01", "end_date": "2024-01-31", "timezone": "Mars/Olympus"
+        }));
+        let valid = params(serde_json::json!({
+            "start_date": "2024-01-01", "end_date": "2024-01-31", "timezone": "Asia/Ho_Chi_Minh"
+        }));
+
+        assert_eq!(
+            invalid.validate().unwrap_err().to_string(),
+            "timezone 'Mars/Olympus' is not a valid IANA time zone name"
+        );
+        assert_eq!(invalid.time_zone(), None);
+        assert!(valid.validate().is_ok());
+        assert_eq!(valid.time_zone(), Some(Tz::Asia__Ho_Chi_Minh));
+    }
+
+    #[test]
+    fn zoned_timestamps_follow_the_offset_in_effect_on_their_own_date() {
+        let timestamp = |value: &str| CellValue::DateTime(Utc.from_utc_datetime(&utc(value)).with_timezone(&Tz::UTC));
+        let data = ReportData::Dataset(DatasetRows {
+            columns: vec!["created_at".to_string()],
+            rows: vec![vec![timestamp("2024-01-15 12:00:00")], vec![timestamp("2024-07-15 12:00:00")]],
+        });
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        let zoned = data.in_time_zone(Tz::Europe__Berlin, now);
+
+        assert_eq!(zoned.layout().headers(), vec!["created_at (CET)"]);
+        let rendered: Vec<String> = zoned
+            .rows()
+            .map(|row| match &row[0] {
+                CellValue::DateTime(value) => value.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+                other => panic!("expected a timestamp, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(rendered, vec!["2024-01-15T13:00:00+01:00", "2024-07-15T14:00:00+02:00"]);
+    }
+
+    #[test]
+    fn every_format_is_found_by_its_extension_in_any_case() {
+        for format in ExportFormat::ALL {
+            assert_eq!(ExportFormat::from_name(format.extension()), Some(format));
+            assert_eq!(ExportFormat::from_name(&format.extension().to_ascii_uppercase()), Some(format));
+        }
+        assert_eq!(ExportFormat::from_name("xls"), None);
+        assert_eq!(ExportFormat::from_name(""), None);
+    }
+
+    #[test]
+    fn available_formats_follow_the_build_features() {
+        let mut expected = vec!["xlsx", "csv"];
+        if cfg!(feature = "parquet") {
+            expected.push("parquet");
+        }
+        expected.push("jsonl");
+        if cfg!(feature = "ods") {
+            expected.push("ods");
+        }
+        expected.push("html");
+        if cfg!(feature = "pdf") {
+            expected.push("pdf");
+        }
+
+        assert_eq!(ExportFormat::available_names(), expected);
+        for format in ExportFormat::ALL {
+            assert_eq!(format.is_available(), format.required_feature().is_none() || expected.contains(&format.extension()));
+        }
+    }
+
+    #[test]
+    fn payload_format_is_optional_and_must_be_known() {
+        let payload = serde_json::json!({"start_date": "2024-01-01", "end_date": "2024-01-31"});
+        assert_eq!(params(payload.clone()).format, None);
+
+        let mut with_format = payload.clone();
+        with_format["format"] = serde_json::json!("jsonl");
+        assert_eq!(params(with_format).format, Some(ExportFormat::Jsonl));
+
+        let mut unknown = payload;
+        unknown["format"] = serde_json::json!("xls");
+        assert!(serde_json::from_value::<ReportParams>(unknown).is_err());
+    }
+
+    fn product(name: &str) -> ProductData {
+        ProductData {
+            product_id: 7,
+            name: name.to_string(),
+            category: "Books".to_string(),
+            price: 9.5,
+            stock_quantity: 3,
+            created_at: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+        }
+    }
+
+    #[test]
+    fn link_url_template_must_be_an_http_url_with_simple_placeholders() {
+        assert!(validate_link_url_template("https://shop.example.com/p/{product_id}?name={name}").is_ok());
+        assert!(validate_link_url_template("HTTP://shop.example.com/p").is_ok());
+
+        let error = |template: &str| validate_link_url_template(template).unwrap_err().to_string();
+        assert!(error("ftp://shop.example.com/{name}").contains("must start with http:// or https://"));
+        assert!(error("javascript:alert({name})").contains("must start with http:// or https://"));
+        assert!(error("https://shop.example.com/{name} x").contains("whitespace or control characters"));
+        assert!(error("https://shop.example.com/{name").contains("unmatched '{'"));
+        assert!(error("https://shop.example.com/name}").contains("unmatched '}'"));
+        assert!(error("https://shop.example.com/{}").contains("invalid placeholder '{}'"));
+        assert!(error("https://shop.example.com/{Name}").contains("invalid placeholder '{Name}'"));
+    }
+
+    #[test]
+    fn link_url_fills_each_placeholder_with_the_percent_encoded_value() {
+        let template = "https://shop.example.com/p/{product_id}?name={name}&x={missing}";
+
+        assert_eq!(
+            render_link_url(template, &product("A/B ?#&=é~_.-")),
+            "https://shop.example.com/p/7?name=A%2FB%20%3F%23%26%3D%C3%A9~_.-&x="
+        );
+        assert_eq!(render_link_url("https://shop.example.com/all", &product("x")), "https://shop.example.com/all");
+    }
+
+    #[test]
+    fn linked_report_writes_only_the_link_columns_as_links() {
+        let data = ReportData::Products { rows: vec![product("Tea")], columns: ProductColumn::ALL.to_vec() }
+            .with_link_url_template("https://shop.example.com/p/{product_id}".to_string());
+
+        let cells: Vec<Vec<CellValue>> = data.rows().collect();
+
+        assert_eq!(
+            cells[0][1],
+            CellValue::Link { text: "Tea".to_string(), url: "https://shop.example.com/p/7".to_string() }
+        );
+        assert_eq!(cells[0].iter().filter(|cell| matches!(cell, CellValue::Link { .. })).count(), 1, "{:?}", cells[0]);
+        let plain = ReportData::Products { rows: vec![product("Tea")], columns: ProductColumn::ALL.to_vec() };
+        assert_eq!(plain.rows().next().unwrap()[1], CellValue::Text("Tea".to_string()));
+    }
+
+    #[test]
+    fn table_style_names_are_checked_against_the_excel_styles() {
+        assert_eq!(ExcelTableStyle::from_name("TableStyleMedium9"), Some(ExcelTableStyle::Medium(9)));
+        assert_eq!(ExcelTableStyle::from_name("light21"), Some(ExcelTableStyle::Light(21)));
+        assert_eq!(ExcelTableStyle::from_name("DARK11"), Some(ExcelTableStyle::Dark(11)));
+        assert_eq!(ExcelTableStyle::from_name("None"), Some(ExcelTableStyle::None));
+        for invalid in ["Light22", "Medium29", "Dark0", "Medium", "Fancy1", ""] {
+            assert_eq!(ExcelTableStyle::from_name(invalid), None, "{}", invalid);
         }
+        assert_eq!(ExcelTableStyle::Medium(9).to_string(), "Medium9");
+        assert_eq!(ExcelTableStyle::from_name(&ExcelTableStyle::Dark(3).to_string()), Some(ExcelTableStyle::Dark(3)));
+    }
+
+    #[test]
+    fn page_margins_come_from_a_list_of_four_inches() {
+        assert_eq!(
+            PageMargins::from_list("0.5, 0.5,0.75 ,1"),
+            Some(PageMargins { left: 0.5, right: 0.5, top: 0.75, bottom: 1.0 })
+        );
+        assert_eq!(PageMargins::from_list("0.5,0.5,0.75"), None);
+        assert_eq!(PageMargins::from_list("0.5,0.5,0.75,0.75,1"), None);
+        assert_eq!(PageMargins::from_list("0.5,0.5,wide,0.75"), None);
+
+        assert!(PageMargins { left: 0.0, right: 3.0, top: 0.5, bottom: 0.5 }.validate().is_ok());
+        let error = PageMargins { left: 0.5, right: 0.5, top: 3.5, bottom: 0.5 }.validate().unwrap_err();
+        assert_eq!(error.to_string(), "top margin must be between 0 and 3 inches, got 3.5");
+        assert!(PageMargins { left: -0.1, right: 0.5, top: 0.5, bottom: 0.5 }.validate().is_err());
+    }
+
+    #[test]
+    fn print_overrides_replace_only_the_fields_they_set() {
+        let config = ExcelPrintOptions { landscape: true, footer: true, ..ExcelPrintOptions::default() };
+        let overrides: ExcelPrintOverrides = serde_json::from_value(serde_json::json!({
+            "landscape": false,
+            "fit_to_width": true,
+            "margins": {"left": 0.25, "right": 0.25, "top": 0.5, "bottom": 0.5},
+        }))
+        .unwrap();
+
+        assert_eq!(
+            config.with_overrides(&overrides),
+            ExcelPrintOptions {
+                landscape: false,
+                fit_to_width: true,
+                repeat_header: false,
+                margins: Some(PageMargins { left: 0.25, right: 0.25, top: 0.5, bottom: 0.5 }),
+                footer: true,
+            }
+        );
+        assert!(serde_json::from_value::<ExcelPrintOverrides>(serde_json::json!({"portrait": true})).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn report_title_names_the_report_dates_and_category() {
+        let generated_at = Utc.with_ymd_and_hms(2024, 5, 31, 12, 34, 0).unwrap();
+        let mut report = params(serde_json::json!({
+            "start_date": "2024-05-01", "end_date": "2024-05-31", "product_category": "Electronics",
+        }));
+
+        assert_eq!(
+            report.report_title(generated_at),
+            ReportTitle {
+                title: "Product Export — 2024-05-01 to 2024-05-31 (Category: Electronics)".to_string(),
+                subtitle: "Generated 2024-05-31 12:34 UTC".to_string(),
+            }
+        );
+
+        report.timezone = Some("Europe/Berlin".to_string());
+        report.report_type = ORDERS_REPORT_TYPE.to_string();
+        report.product_category = None;
+        let title = report.report_title(generated_at);
+        assert_eq!(title.title, "Order Export — 2024-05-01 to 2024-05-31");
+        assert_eq!(title.subtitle, "Generated 2024-05-31 14:34 CEST");
+    }
+
+    #[test]
+    fn payload_title_is_cleaned_and_capped() {
+        let mut report = params(serde_json::json!({"start_date": "2024-05-01", "end_date": "2024-05-31"}));
+        report.title = Some("  Quarterly\n\tstock   report\u{7}  ".to_string());
+        let generated_at = Utc.with_ymd_and_hms(2024, 5, 31, 12, 34, 0).unwrap();
+
+        assert_eq!(report.report_title(generated_at).title, "Quarterly stock report");
+        assert_eq!(sanitize_title(&"é".repeat(MAX_TITLE_CHARS + 10)).chars().count(), MAX_TITLE_CHARS);
+        assert_eq!(sanitize_title(" \r\n "), "");
+    }
+}
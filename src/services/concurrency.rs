@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use metrics::gauge;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+/// Giới hạn số export chạy đồng thời cho mỗi user.
+/// Mỗi user có một semaphore riêng, được tạo khi cần và dọn dẹp khi không còn ai sử dụng.
+pub struct UserConcurrencyLimiter {
+    per_user_limit: usize,
+    semaphores: DashMap<i64, Arc<Semaphore>>,
+}
+
+/// Permit của một user; trả lại slot và dọn dẹp semaphore rảnh khi bị drop.
+pub struct UserPermit<'a> {
+    limiter: &'a UserConcurrencyLimiter,
+    user_id: i64,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl UserConcurrencyLimiter {
+    pub fn new(per_user_limit: usize) -> Self {
+        Self {
+            per_user_limit,
+            semaphores: DashMap::new(),
+        }
+    }
+
+    pub async fn acquire(&self, user_id: i64) -> Result<UserPermit<'_>> {
+        // Clone Arc rồi thả entry guard trước khi await để không giữ lock của DashMap.
+        let semaphore = self
+            .semaphores
+            .entry(user_id)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_user_limit)))
+            .clone();
+
+        if semaphore.available_permits() == 0 {
+            debug!("User {} is at the concurrency cap ({}), waiting for a slot.", user_id, self.per_user_limit);
+        }
+
+        let permit = semaphore
+            .acquire_owned()
+            .await
+            .context("Per-user concurrency semaphore closed")?;
+        self.report_users_at_cap();
+
+        Ok(UserPermit {
+            limiter: self,
+            user_id,
+            permit: Some(permit),
+        })
+    }
+
+    fn release(&self, user_id: i64) {
+        // Chỉ xóa khi không còn task nào giữ hoặc đang chờ semaphore (strong_count == 1).
+        self.semaphores.remove_if(&user_id, |_, semaphore| {
+            Arc::strong_count(semaphore) == 1 && semaphore.available_permits() == self.per_user_limit
+        });
+        self.report_users_at_cap();
+    }
+
+    fn report_users_at_cap(&self) {
+        let users_at_cap = self
+            .semaphores
+            .iter()
+            .filter(|entry| entry.value().available_permits() == 0)
+            .count();
+        gauge!("excel_export_users_at_concurrency_cap", users_at_cap as f64);
+    }
+}
+
+impl Drop for UserPermit<'_> {
+    fn drop(&mut self) {
+        drop(self.permit.take());
+        self.limiter.release(self.user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    const WAIT: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    async fn third_request_of_same_user_waits_while_other_users_proceed() {
+        let limiter = UserConcurrencyLimiter::new(2);
+        let first = limiter.acquire(1).await.unwrap();
+        let _second = limiter.acquire(1).await.unwrap();
+
+        let mut third = Box::pin(limiter.acquire(1));
+        assert!(timeout(WAIT, &mut third).await.is_err(), "third request of user 1 must wait");
+
+        let other = timeout(WAIT, limiter.acquire(2)).await;
+        assert!(other.is_ok(), "user 2 must not be blocked by user 1");
+
+        drop(first);
+        let third = timeout(WAIT, third).await;
+        assert!(third.is_ok(), "third request must proceed once a slot is released");
+    }
+
+    #[tokio::test]
+    async fn idle_user_semaphores_are_removed() {
+        let limiter = UserConcurrencyLimiter::new(1);
+        let permit = limiter.acquire(7).await.unwrap();
+        assert!(limiter.semaphores.contains_key(&7));
+
+        drop(permit);
+        assert!(!limiter.semaphores.contains_key(&7));
+    }
+}
@@ -1,184 +1,2900 @@
 use anyhow::{Context, Result};
-use chrono::Utc;
-use sqlx::{Pool, Postgres, Transaction};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use metrics::increment;
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::query::{Query, QueryAs};
+use sqlx::{
+    Column, Connection, Either, Executor, PgConnection, PgExecutor, Pool, Postgres, Row, Statement, Transaction,
+    TypeInfo,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tracing::{info, instrument, warn};
 use uuid::Uuid;
 
-use crate::models::{ExportRequest, ExportStatus, ProductData, ReportParams};
+use crate::clock::Clock;
+use crate::config::{DatasetParam, ExportQuota};
+use crate::models::{
+    CellValue, CustomerData, CustomerReportParams, DatasetParamType, DatasetRows, DatasetValue, ExportCompletion, ExportRequest,
+    ExportRequestEvent, ExportStatus, ExportTimings, OrderData, OrderReportParams, ProductColumn, ProductData,
+    ReportParams, SalesSummaryRow, UnsentNotification,
+};
 
 /// Trait định nghĩa giao diện cho việc tương tác với database để lưu trữ/truy vấn ExportRequests.
 #[async_trait::async_trait]
 pub trait DbStore: Send + Sync + 'static {
+    /// Kiểm tra DB còn dùng được (`SELECT 1`, tối đa `PING_TIMEOUT`), cho probe readiness và lúc khởi động.
+    async fn ping(&self) -> DbResult<()>;
+
+    /// Claim request và chuyển sang `new_status`. Quota theo ngày của user được kiểm tra
+    /// trong cùng transaction; nếu vượt quota sẽ trả về lỗi `QuotaExceeded`.
     async fn fetch_and_update_request_status(
         &self,
         request_id: Uuid,
-        new_status: ExportStatus,
-    ) -> Result<ExportRequest>;
+        new_status: ExportStatus,
+        quota: &ExportQuota,
+    ) -> DbResult<ExportRequest>;
+
+    /// Giữ khóa xử lý của request (xuyên suốt nhiều transaction) để hai worker cùng nhận một message
+    /// không xử lý song song. Trả về `false` nếu worker khác đang giữ khóa.
+    /// Mặc định không khóa: backend không hỗ trợ luôn trả về `true`.
+    async fn try_lock_request(
+        &self,
+        _request_id: Uuid,
+    ) -> DbResult<bool> {
+        Ok(true)
+    }
+
+    /// Trả khóa đã lấy bằng `try_lock_request`; không làm gì nếu request không bị khóa bởi store này.
+    async fn unlock_request(
+        &self,
+        _request_id: Uuid,
+    ) -> DbResult<()> {
+        Ok(())
+    }
+
+    async fn update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        file_path: Option<String>,
+        error_message: Option<String>,
+        error_code: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+        timings: &ExportTimings,
+        completion: &ExportCompletion,
+    ) -> DbResult<()>;
+
+    async fn update_notification_sent_status(
+        &self,
+        request_id: Uuid,
+        sent: bool,
+    ) -> DbResult<()>;
+
+    /// Ghi nhận webhook đã gửi được khi email (`delivery.type = email`) thất bại, để lần gửi lại
+    /// chỉ gửi email. Được xóa khi request được đưa về PENDING.
+    async fn mark_webhook_sent(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()>;
+
+    /// Lấy tối đa `max_rows + 1` dòng để caller phát hiện vượt giới hạn mà không tải hết dữ liệu.
+    async fn query_product_data(
+        &self,
+        params: &ReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<ProductData>>;
+
+    /// Stream từng dòng dữ liệu để exporter không phải giữ toàn bộ kết quả trong bộ nhớ.
+    /// Mặc định dựa trên `query_product_data`; PostgresDbStore ghi đè bằng cursor thật.
+    fn stream_product_data<'a>(
+        &'a self,
+        params: &'a ReportParams,
+    ) -> BoxStream<'a, Result<ProductData>> {
+        stream::once(self.query_product_data(params, usize::MAX))
+            .map_err(anyhow::Error::from)
+            .map_ok(rows_to_stream)
+            .try_flatten()
+            .boxed()
+    }
+
+    /// Số dòng khớp bộ lọc của report sản phẩm, cùng điều kiện với `query_product_data`.
+    /// Dùng để kiểm tra giới hạn số dòng và ước lượng thời gian trước khi chạy query chính.
+    async fn count_product_data(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<i64>;
+
+    /// Backend có thể tự tạo file CSV của report sản phẩm (`copy_product_data_csv`) hay không.
+    fn supports_csv_copy(&self) -> bool {
+        false
+    }
+
+    /// Ghi thẳng report sản phẩm dạng CSV (header + tối đa `max_rows + 1` dòng) vào `writer`,
+    /// trả về số dòng dữ liệu. Chỉ được gọi khi `supports_csv_copy()` trả về true.
+    async fn copy_product_data_csv(
+        &self,
+        _params: &ReportParams,
+        _max_rows: usize,
+        _writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> DbResult<u64> {
+        Err(DbError::Fatal(anyhow::anyhow!("CSV COPY is not supported by this database backend")))
+    }
+
+    /// Kiểm tra SQL của một dataset tùy chỉnh khi khởi động bằng cách prepare câu lệnh trên DB
+    /// (cú pháp, bảng/cột, số và kiểu tham số, kiểu cột), trả về tên các cột.
+    async fn prepare_dataset(
+        &self,
+        _sql: &str,
+        _params: &[DatasetParam],
+    ) -> DbResult<Vec<String>> {
+        Err(DbError::Fatal(anyhow::anyhow!("Custom datasets are not supported by this database backend")))
+    }
+
+    /// Chạy dataset tùy chỉnh với các giá trị đã được kiểm tra kiểu, tối đa `max_rows + 1` dòng.
+    async fn query_dataset(
+        &self,
+        _sql: &str,
+        _values: &[DatasetValue],
+        _tenant: Option<&str>,
+        _max_rows: usize,
+    ) -> DbResult<DatasetRows> {
+        Err(DbError::Fatal(anyhow::anyhow!("Custom datasets are not supported by this database backend")))
+    }
+
+    /// Lấy tối đa `max_rows + 1` đơn hàng, giống `query_product_data`.
+    async fn query_order_data(
+        &self,
+        params: &OrderReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<OrderData>>;
+
+    /// Danh sách khách hàng kèm số đơn và tổng giá trị đơn hàng (tính bằng SQL), tối đa `max_rows + 1` dòng.
+    async fn query_customer_data(
+        &self,
+        params: &CustomerReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<CustomerData>>;
+
+    /// Thống kê theo category (số sản phẩm, tổng tồn kho, giá min/avg/max) kèm dòng tổng ở cuối.
+    /// Toàn bộ phép tổng hợp được thực hiện bằng SQL.
+    async fn query_category_summary(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<Vec<SalesSummaryRow>>;
+
+    /// Stream từng đơn hàng. Mặc định dựa trên `query_order_data`; PostgresDbStore ghi đè bằng cursor thật.
+    fn stream_order_data<'a>(
+        &'a self,
+        params: &'a OrderReportParams,
+    ) -> BoxStream<'a, Result<OrderData>> {
+        stream::once(self.query_order_data(params, usize::MAX))
+            .map_err(anyhow::Error::from)
+            .map_ok(|rows| stream::iter(rows.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    /// Claim tối đa `limit` request đã ở trạng thái cuối nhưng chưa gửi được thông báo.
+    /// Các row được claim sẽ bị "lease" một khoảng thời gian để replica khác không gửi trùng.
+    async fn claim_unsent_notifications(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>>;
+
+    /// Liệt kê (không claim) các request đã kết thúc nhưng chưa gửi được thông báo, theo completed_at.
+    /// Keyset pagination: `after` là request_id cuối cùng của trang trước.
+    async fn list_unsent_notifications(
+        &self,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> DbResult<Vec<UnsentNotification>>;
+
+    async fn record_notification_failure(
+        &self,
+        request_id: Uuid,
+        next_retry_at: DateTime<Utc>,
+    ) -> DbResult<()>;
+
+    /// Liệt kê các request COMPLETED vẫn còn file trên storage và đã hết hạn: ưu tiên `expires_at`
+    /// của từng request, chỉ dùng mốc `before` cho các request cũ chưa có `expires_at`.
+    async fn list_expired(
+        &self,
+        before: DateTime<Utc>,
+    ) -> DbResult<Vec<ExportRequest>>;
+
+    /// Đánh dấu request là EXPIRED sau khi file đã bị xóa.
+    async fn mark_expired(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()>;
+
+    /// Request COMPLETED khác (trừ `request_id`) có link chưa hết hạn vẫn trỏ tới `file_path`
+    /// (file dùng chung theo tên nội dung, CONTENT_ADDRESSED_FILE_NAMES).
+    async fn is_file_shared(
+        &self,
+        file_path: &str,
+        request_id: Uuid,
+    ) -> DbResult<bool>;
+
+    /// Lưu đường dẫn và checksum ngay sau khi tạo file, trước khi cập nhật trạng thái cuối,
+    /// để lần xử lý lại (redelivery) có thể dùng lại file thay vì tạo mới.
+    async fn record_generated_file(
+        &self,
+        request_id: Uuid,
+        file_path: &str,
+        file_checksum: &str,
+        file_size_bytes: i64,
+    ) -> DbResult<()>;
+
+    async fn record_params_hash(
+        &self,
+        request_id: Uuid,
+        params_hash: &str,
+    ) -> DbResult<()>;
+
+    /// Tìm request COMPLETED gần nhất (từ `since`) có cùng params hash và link chưa hết hạn.
+    async fn find_recent_by_params_hash(
+        &self,
+        params_hash: &str,
+        since: DateTime<Utc>,
+        exclude_request_id: Uuid,
+    ) -> DbResult<Option<ExportRequest>>;
+
+    /// Ghi ETA của request khi bắt đầu xử lý (và khi ước lượng lại sau lúc query).
+    async fn record_estimated_completion(
+        &self,
+        request_id: Uuid,
+        estimated_completion_at: DateTime<Utc>,
+    ) -> DbResult<()>;
+
+    /// Lưu tổng thời gian xử lý và số dòng (nếu đã query dữ liệu) khi request kết thúc.
+    async fn record_export_duration(
+        &self,
+        request_id: Uuid,
+        duration_ms: i64,
+        row_count: Option<i64>,
+    ) -> DbResult<()>;
+
+    /// Thời gian gửi thông báo (và email) cuối cùng, ghi sau khi gửi xong.
+    async fn record_notify_duration(
+        &self,
+        request_id: Uuid,
+        notify_ms: i64,
+    ) -> DbResult<()>;
+
+    /// (row_count, duration_ms) của các request COMPLETED gần nhất có tạo file, dùng để khởi tạo thống kê ETA.
+    async fn recent_export_durations(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<(i64, i64)>>;
+
+    /// Claim tối đa `limit` request PENDING (bỏ qua các request có `next_retry_at` chưa tới), ưu tiên
+    /// `priority` cao rồi tới request cũ hơn, và chuyển sang PROCESSING trong cùng một transaction.
+    /// Dùng FOR UPDATE SKIP LOCKED nên nhiều replica gọi đồng thời không bao giờ claim trùng một row.
+    async fn claim_next_pending(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>>;
+
+    /// Các request PROCESSING không đổi trạng thái trong `stuck_for` (ví dụ replica bị kill giữa chừng).
+    /// Row đang bị replica khác khóa sẽ được bỏ qua (SKIP LOCKED).
+    async fn fetch_stuck_processing(
+        &self,
+        stuck_for: chrono::Duration,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>>;
+
+    /// Đưa các request về PENDING và xóa các trường tạm của lần xử lý dở, chỉ với request vẫn còn
+    /// PROCESSING, nên hai lần recovery chạy đồng thời không reset một request hai lần.
+    /// Trả về id của các request đã thực sự được reset.
+    async fn reset_to_pending(
+        &self,
+        ids: &[Uuid],
+    ) -> DbResult<Vec<Uuid>>;
+
+    /// Chuyển nhiều request sang `new_status` trong một transaction (thao tác quản trị), ghi `error_message`
+    /// và audit log cho từng request. Id không tồn tại và request đã ở `new_status` bị bỏ qua; request
+    /// COMPLETED chỉ bị đổi trạng thái khi `force`. Trả về số request đã thực sự được cập nhật.
+    async fn bulk_update_status(
+        &self,
+        ids: &[Uuid],
+        new_status: ExportStatus,
+        error_message: Option<String>,
+        force: bool,
+    ) -> DbResult<u64>;
+
+    /// Ghi nhận một lần xử lý request. `None` khi bắt đầu xử lý: tăng `attempts` (trong cùng câu UPDATE
+    /// nên không mất lượt khi nhiều tiến trình cùng ghi) và cập nhật `last_attempt_at`.
+    /// `Some(error)` khi lần xử lý thất bại: chỉ ghi `last_error`.
+    async fn record_attempt(
+        &self,
+        request_id: Uuid,
+        error: Option<&str>,
+    ) -> DbResult<()>;
+
+    /// Chuyển các request FAILED/EXPIRED/COMPLETED có `status_updated_at` trước `before` sang bảng
+    /// export_requests_archive, mỗi lần tối đa `batch` dòng trong một transaction, cho đến khi hết.
+    /// Request chưa gửi được thông báo không bao giờ bị archive. Request COMPLETED chỉ bị archive khi không còn
+    /// file (`file_path` trống) hoặc link đã hết hạn trước `before`, để file không bị bỏ lại ngoài tầm của
+    /// retention job. Trả về tổng số dòng đã chuyển.
+    async fn archive_requests(
+        &self,
+        before: DateTime<Utc>,
+        batch: i64,
+    ) -> DbResult<i64>;
+
+    /// Số request hiện tại theo từng trạng thái (một query GROUP BY). Trạng thái không có request nào
+    /// không xuất hiện trong kết quả.
+    async fn count_by_status(&self) -> DbResult<HashMap<ExportStatus, i64>>;
+
+    /// Lịch sử chuyển trạng thái của request, theo thứ tự thời gian (kể cả request đã được archive).
+    async fn get_request_history(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<Vec<ExportRequestEvent>>;
+
+    /// Đếm số export của user từ `since` đang xử lý hoặc đã tạo file thành công.
+    async fn count_user_exports_since(
+        &self,
+        user_id: i64,
+        since: DateTime<Utc>,
+    ) -> DbResult<i64>;
+}
+
+/// Phần FROM/WHERE chung của các query trên bộ lọc report sản phẩm (tham số $1..$7, bind bởi
+/// `bind_product_filters`). Query dữ liệu, query đếm và query tổng hợp đều được ghép từ đây
+/// để điều kiện lọc không thể lệch nhau.
+macro_rules! product_filter_sql {
+    () => {
+        r#"
+    FROM products
+    WHERE created_at BETWEEN $1 AND $2
+    AND ($3::text IS NULL OR category = $3)
+    AND ($4::float8 IS NULL OR price >= $4)
+    AND ($5::float8 IS NULL OR price <= $5)
+    AND ($6::int4 IS NULL OR stock_quantity <= $6)
+    AND ($7::text IS NULL OR name ILIKE $7 ESCAPE '\')
+"#
+    };
+}
+
+/// Phần SELECT/WHERE chung của query report sản phẩm. ORDER BY được ghép từ `ReportParams::order_by_sql`.
+const PRODUCT_REPORT_QUERY: &str = concat!(
+    r#"
+    SELECT
+        product_id,
+        name,
+        category,
+        price,
+        stock_quantity,
+        created_at"#,
+    product_filter_sql!()
+);
+
+/// Đếm số dòng mà `PRODUCT_REPORT_QUERY` sẽ trả về (không tính LIMIT).
+const PRODUCT_COUNT_QUERY: &str = concat!("SELECT COUNT(*)", product_filter_sql!());
+
+fn bind_product_filters<'q, O>(
+    query: QueryAs<'q, Postgres, O, PgArguments>,
+    params: &'q ReportParams,
+) -> QueryAs<'q, Postgres, O, PgArguments> {
+    let (from, to) = params.utc_range();
+    query
+        .bind(from)
+        .bind(to)
+        .bind(params.product_category.as_deref())
+        .bind(params.min_price)
+        .bind(params.max_price)
+        .bind(params.max_stock_quantity)
+        .bind(params.name_pattern())
+}
+
+/// Biểu thức của từng cột trong file CSV tạo bằng COPY, cho ra đúng chuỗi mà exporter ghi từng dòng
+/// tạo ra từ `ProductColumn::value` (số nguyên không có phần thập phân, thời gian theo `Display` của chrono).
+fn product_csv_expression(column: ProductColumn) -> &'static str {
+    match column {
+        ProductColumn::ProductId => "product_id",
+        ProductColumn::Name => "name",
+        ProductColumn::Category => "category",
+        ProductColumn::Price => "price",
+        ProductColumn::StockQuantity => "stock_quantity",
+        ProductColumn::CreatedAt => {
+            r#"to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')
+                || CASE
+                    WHEN to_char(created_at, 'US') = '000000' THEN ''
+                    WHEN right(to_char(created_at, 'US'), 3) = '000' THEN '.' || left(to_char(created_at, 'US'), 3)
+                    ELSE '.' || to_char(created_at, 'US')
+                END
+                || ' UTC'"#
+        }
+    }
+}
+
+/// Câu lệnh COPY của report sản phẩm dưới dạng chuỗi định dạng cho `format()` của Postgres.
+/// COPY không nhận bind parameter, nên `$n` của `PRODUCT_REPORT_QUERY` được đổi thành `%n$L`
+/// và Postgres tự quote giá trị; `%8$L` và `%9$L` là LIMIT và OFFSET.
+fn product_copy_template(params: &ReportParams) -> String {
+    let filter_query = (1..=7).fold(PRODUCT_REPORT_QUERY.to_string(), |sql, n| {
+        sql.replace(&format!("${}", n), &format!("%{}$L", n))
+    });
+    let columns = params
+        .selected_columns()
+        .iter()
+        .map(|column| format!("{} AS \"{}\"", product_csv_expression(*column), column.header()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let order_by = params.order_by_sql();
+    format!(
+        "COPY (SELECT {} FROM ({} ORDER BY {} LIMIT %8$L OFFSET %9$L) AS products ORDER BY {}) TO STDOUT WITH (FORMAT csv, HEADER true)",
+        columns, filter_query, order_by, order_by
+    )
+}
+
+/// Đếm số bản ghi CSV trong dữ liệu COPY: mỗi bản ghi kết thúc bằng `\n` nằm ngoài dấu nháy
+/// (giá trị có xuống dòng được đặt trong nháy; nháy kép được escape thành `""` nên vẫn cân bằng).
+#[derive(Default)]
+struct CsvRecordCounter {
+    in_quotes: bool,
+    records: u64,
+}
+
+impl CsvRecordCounter {
+    fn feed(&mut self, chunk: &[u8]) {
+        for byte in chunk {
+            match byte {
+                b'"' => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => self.records += 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Query report tổng hợp theo category, cùng bộ lọc (và tham số $1..$7) với `PRODUCT_REPORT_QUERY`.
+/// ROLLUP tạo thêm dòng tổng (category NULL), được sắp xếp xuống cuối; trung bình của dòng tổng
+/// được tính trên toàn bộ sản phẩm chứ không phải trung bình của các category.
+const CATEGORY_SUMMARY_QUERY: &str = concat!(
+    r#"
+    SELECT
+        category,
+        COUNT(*) AS product_count,
+        COALESCE(SUM(stock_quantity), 0)::int8 AS total_stock,
+        MIN(price)::float8 AS min_price,
+        AVG(price)::float8 AS avg_price,
+        MAX(price)::float8 AS max_price"#,
+    product_filter_sql!(),
+    r#"
+    GROUP BY ROLLUP (category)
+    ORDER BY GROUPING(category), category
+"#
+);
+
+/// Query report đơn hàng. Tham số $1..$4 được bind bởi `bind_order_filters`.
+/// `total` được tính trong SQL để khớp với dữ liệu gốc (quantity * unit_price).
+const ORDER_REPORT_QUERY: &str = r#"
+    SELECT
+        o.order_id,
+        o.user_id,
+        p.name AS product_name,
+        o.quantity,
+        o.unit_price,
+        (o.quantity * o.unit_price)::float8 AS total,
+        o.ordered_at,
+        o.status
+    FROM orders o
+    JOIN products p ON p.product_id = o.product_id
+    WHERE o.ordered_at BETWEEN $1 AND $2
+    AND ($3::text IS NULL OR o.status = $3)
+    AND ($4::int8 IS NULL OR o.user_id = $4)
+    ORDER BY o.ordered_at, o.order_id
+"#;
+
+fn bind_order_filters<'q>(
+    query: QueryAs<'q, Postgres, OrderData, PgArguments>,
+    params: &'q OrderReportParams,
+) -> QueryAs<'q, Postgres, OrderData, PgArguments> {
+    let (from, to) = params.utc_range();
+    query
+        .bind(from)
+        .bind(to)
+        .bind(params.status.map(|status| status.as_str()))
+        .bind(params.user_id)
+}
+
+/// Query report khách hàng. Đơn bị hủy hoặc hoàn tiền không được tính vào total_orders/lifetime_value;
+/// khách hàng chưa có đơn nào vẫn xuất hiện với giá trị 0. $3 giới hạn theo chủ sở hữu (report OwnerOnly).
+const CUSTOMER_REPORT_QUERY: &str = r#"
+    SELECT
+        c.customer_id,
+        c.name,
+        c.email,
+        c.signup_date,
+        COUNT(o.order_id) AS total_orders,
+        COALESCE(SUM(o.quantity * o.unit_price), 0)::float8 AS lifetime_value
+    FROM customers c
+    LEFT JOIN orders o
+        ON o.user_id = c.customer_id
+        AND o.status NOT IN ('cancelled', 'refunded')
+    WHERE c.signup_date BETWEEN $1 AND $2
+    AND ($3::int8 IS NULL OR c.customer_id = $3)
+    GROUP BY c.customer_id, c.name, c.email, c.signup_date
+    HAVING ($4::float8 IS NULL OR COALESCE(SUM(o.quantity * o.unit_price), 0) >= $4)
+    ORDER BY c.signup_date, c.customer_id
+    LIMIT $5
+"#;
+
+/// Bọc SQL của dataset tùy chỉnh để giới hạn số dòng; tham số cuối (`$n+1`) là giới hạn.
+fn dataset_sql(sql: &str, param_count: usize) -> String {
+    format!("SELECT * FROM ({}) AS dataset LIMIT ${}", sql.trim(), param_count + 1)
+}
+
+fn bind_dataset_values<'q>(
+    mut query: Query<'q, Postgres, PgArguments>,
+    values: &'q [DatasetValue],
+) -> Query<'q, Postgres, PgArguments> {
+    for value in values {
+        query = match value {
+            DatasetValue::Text(value) => query.bind(value.as_str()),
+            DatasetValue::Int(value) => query.bind(*value),
+            DatasetValue::Float(value) => query.bind(*value),
+            DatasetValue::Bool(value) => query.bind(*value),
+            DatasetValue::Date(value) => query.bind(*value),
+            DatasetValue::Timestamp(value) => query.bind(*value),
+        };
+    }
+    query
+}
+
+/// Kiểu Postgres suy ra cho một tham số mà giá trị bind theo kiểu khai báo vẫn so sánh/gán được
+/// (`bind_dataset_values` bind int thành INT8, float thành FLOAT8, date thành DATE...).
+fn dataset_param_accepts(param_type: DatasetParamType, inferred: &str) -> bool {
+    let accepted: &[&str] = match param_type {
+        DatasetParamType::Text => &["TEXT", "VARCHAR", "BPCHAR", "NAME"],
+        DatasetParamType::Int => &["INT2", "INT4", "INT8"],
+        DatasetParamType::Float => &["FLOAT4", "FLOAT8", "NUMERIC"],
+        DatasetParamType::Bool => &["BOOL"],
+        DatasetParamType::Date => &["DATE", "TIMESTAMP", "TIMESTAMPTZ"],
+        DatasetParamType::Timestamp => &["TIMESTAMP", "TIMESTAMPTZ"],
+    };
+    accepted.contains(&inferred)
+}
+
+/// So các tham số khai báo trong CUSTOM_DATASETS với kiểu Postgres suy ra khi prepare SQL (không tính LIMIT),
+/// để template sai kiểu bị từ chối lúc khởi động thay vì lỗi ở mọi lần export.
+fn check_dataset_params(declared: &[DatasetParam], inferred: &[&str]) -> Result<()> {
+    if inferred.len() != declared.len() {
+        anyhow::bail!("Dataset SQL uses {} parameters but {} are declared", inferred.len(), declared.len());
+    }
+    for (index, (param, inferred)) in declared.iter().zip(inferred).enumerate() {
+        if !dataset_param_accepts(param.param_type, inferred) {
+            anyhow::bail!(
+                "Dataset parameter ${} ('{}') is declared as {} but the SQL uses it as {}; change its type or cast it in the SQL",
+                index + 1,
+                param.name,
+                param.param_type.name(),
+                inferred
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Kiểu cột mà dataset tùy chỉnh được phép trả về; kiểu khác (NUMERIC, JSON...) cần được cast trong SQL.
+const DATASET_COLUMN_TYPES: &[&str] = &[
+    "INT2", "INT4", "INT8", "FLOAT4", "FLOAT8", "BOOL", "TEXT", "VARCHAR", "BPCHAR", "NAME", "DATE", "TIMESTAMP",
+    "TIMESTAMPTZ", "UUID",
+];
+
+/// Giá trị một ô của dataset; NULL thành ô trống.
+fn dataset_cell(row: &PgRow, index: usize) -> Result<CellValue> {
+    let column = &row.columns()[index];
+    let number = |value: Option<f64>| value.map(CellValue::Number);
+    let text = |value: Option<String>| value.map(CellValue::Text);
+    let cell = match column.type_info().name() {
+        "INT2" => number(row.try_get::<Option<i16>, _>(index)?.map(f64::from)),
+        "INT4" => number(row.try_get::<Option<i32>, _>(index)?.map(f64::from)),
+        "INT8" => number(row.try_get::<Option<i64>, _>(index)?.map(|value| value as f64)),
+        "FLOAT4" => number(row.try_get::<Option<f32>, _>(index)?.map(f64::from)),
+        "FLOAT8" => number(row.try_get::<Option<f64>, _>(index)?),
+        "BOOL" => text(row.try_get::<Option<bool>, _>(index)?.map(|value| value.to_string())),
+        "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" => text(row.try_get::<Option<String>, _>(index)?),
+        "DATE" => row.try_get::<Option<NaiveDate>, _>(index)?.map(CellValue::Date),
+        // TIMESTAMP không có múi giờ vẫn ghi dạng chuỗi (CSV không đổi); cast sang TIMESTAMPTZ để có ô ngày giờ trong Excel.
+        "TIMESTAMP" => text(row.try_get::<Option<NaiveDateTime>, _>(index)?.map(|value| value.to_string())),
+        "TIMESTAMPTZ" => row
+            .try_get::<Option<DateTime<Utc>>, _>(index)?
+            .map(|value| CellValue::DateTime(value.with_timezone(&Tz::UTC))),
+        "UUID" => text(row.try_get::<Option<Uuid>, _>(index)?.map(|value| value.to_string())),
+        other => anyhow::bail!("Unsupported type {} of dataset column '{}'", other, column.name()),
+    };
+    Ok(cell.unwrap_or_else(|| CellValue::Text(String::new())))
+}
+
+/// `SELECT 1` trên một pool; quá `PING_TIMEOUT` (kể cả khi chờ connection) là lỗi tạm thời.
+async fn ping_pool(pool: &Pool<Postgres>, role: &str) -> DbResult<()> {
+    match tokio::time::timeout(PING_TIMEOUT, sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(result) => {
+            result.with_context(|| format!("Failed to ping {} database", role))?;
+            Ok(())
+        }
+        Err(_) => Err(DbError::Transient(anyhow::anyhow!(
+            "Ping of {} database timed out after {} ms",
+            role,
+            PING_TIMEOUT.as_millis()
+        ))),
+    }
+}
+
+/// Chuyển một Vec đã có sẵn thành stream, dùng cho implementation mặc định và các store giả lập.
+pub fn rows_to_stream(rows: Vec<ProductData>) -> BoxStream<'static, Result<ProductData>> {
+    stream::iter(rows.into_iter().map(Ok)).boxed()
+}
+
+/// Lỗi trả về khi user đã dùng hết quota export trong ngày.
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub limit: i64,
+    pub reset_at: DateTime<Utc>,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Daily export quota of {} exceeded. The quota resets at {}.",
+            self.limit,
+            self.reset_at.to_rfc3339()
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Thời gian chờ tối đa của `DbStore::ping`, kể cả thời gian chờ connection từ pool.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Query report bị Postgres hủy vì vượt statement_timeout (SQLSTATE 57014).
+/// Đây là lỗi tạm thời: request có thể được thử lại khi DB bớt tải.
+#[derive(Debug)]
+pub struct QueryTimeout {
+    pub timeout_ms: u64,
+}
+
+impl fmt::Display for QueryTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Report query was cancelled after exceeding the {} ms statement timeout.", self.timeout_ms)
+    }
+}
+
+impl std::error::Error for QueryTimeout {}
+
+/// Lỗi của `DbStore`, phân loại để caller quyết định bỏ qua, thử lại hay fail request.
+/// Chỉ các method của trait trả về `DbError`; ở tầng ứng dụng lỗi vẫn được gói bằng anyhow.
+#[derive(Debug)]
+pub enum DbError {
+    /// Không tìm thấy request.
+    NotFound(Uuid),
+    /// Request đã ở trạng thái cuối, không xử lý lại.
+    AlreadyFinal(ExportStatus),
+    /// User đã dùng hết quota export trong ngày.
+    QuotaExceeded(QuotaExceeded),
+    /// Vi phạm ràng buộc dữ liệu (unique, foreign key...): thử lại cũng không thành công.
+    Conflict(anyhow::Error),
+    /// Lỗi tạm thời (mất kết nối, hết connection, timeout, serialization failure, deadlock).
+    Transient(anyhow::Error),
+    /// Các lỗi còn lại, không nên thử lại.
+    Fatal(anyhow::Error),
+}
+
+pub type DbResult<T> = std::result::Result<T, DbError>;
+
+impl DbError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, DbError::Transient(_))
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::NotFound(request_id) => write!(f, "Export request {} not found", request_id),
+            DbError::AlreadyFinal(status) => write!(f, "Export request is already in final state {}", status),
+            DbError::QuotaExceeded(e) => write!(f, "{}", e),
+            DbError::Conflict(e) => write!(f, "Database conflict: {:#}", e),
+            DbError::Transient(e) => write!(f, "Transient database error: {:#}", e),
+            DbError::Fatal(e) => write!(f, "Database error: {:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for DbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbError::QuotaExceeded(e) => Some(e),
+            DbError::Conflict(e) | DbError::Transient(e) | DbError::Fatal(e) => Some(e.as_ref()),
+            DbError::NotFound(_) | DbError::AlreadyFinal(_) => None,
+        }
+    }
+}
+
+/// Phân loại theo lỗi sqlx gốc trong chuỗi context (nếu có).
+impl From<anyhow::Error> for DbError {
+    fn from(e: anyhow::Error) -> Self {
+        // Lỗi đã được phân loại (ví dụ lỗi của stream được gói lại bằng anyhow) giữ nguyên loại.
+        let e = match e.downcast::<DbError>() {
+            Ok(db_error) => return db_error,
+            Err(e) => e,
+        };
+        let sqlx_error = e.downcast_ref::<sqlx::Error>();
+        if e.is::<QueryTimeout>() || sqlx_error.is_some_and(is_transient_sqlx_error) {
+            DbError::Transient(e)
+        } else if sqlx_error.is_some_and(is_conflict_sqlx_error) {
+            DbError::Conflict(e)
+        } else {
+            DbError::Fatal(e)
+        }
+    }
+}
+
+impl From<sqlx::Error> for DbError {
+    fn from(e: sqlx::Error) -> Self {
+        anyhow::Error::new(e).into()
+    }
+}
+
+/// SQLSTATE của các lỗi tạm thời: serialization_failure, deadlock_detected, lock_not_available,
+/// query_canceled, admin_shutdown, cannot_connect_now, too_many_connections.
+const TRANSIENT_SQLSTATES: &[&str] = &["40001", "40P01", "55P03", "57014", "57P01", "57P03", "53300"];
+
+fn is_transient_sqlx_error(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(db_error) => db_error.code().is_some_and(|code| {
+            // Class 08: connection exception.
+            code.starts_with("08") || TRANSIENT_SQLSTATES.contains(&&*code)
+        }),
+        _ => false,
+    }
+}
+
+fn is_conflict_sqlx_error(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Database(db_error) => matches!(
+            db_error.kind(),
+            sqlx::error::ErrorKind::UniqueViolation | sqlx::error::ErrorKind::ForeignKeyViolation
+        ),
+        _ => false,
+    }
+}
+
+/// SQLSTATE của `query_canceled`, trả về khi vượt statement_timeout.
+const QUERY_CANCELED_SQLSTATE: &str = "57014";
+
+/// Thời gian một row bị giữ sau khi được claim bởi notification retry worker.
+const NOTIFICATION_CLAIM_LEASE_SECS: f64 = 300.0;
+
+/// Implementation cụ thể cho PostgreSQL.
+pub struct PostgresDbStore {
+    pool: Pool<Postgres>,
+    /// Pool cho query report (read replica). Trùng với `pool` khi không cấu hình DATABASE_READ_URL.
+    read_pool: Pool<Postgres>,
+    has_read_replica: bool,
+    clock: Arc<dyn Clock>,
+    settings: PostgresStoreSettings,
+    request_locks: tokio::sync::Mutex<RequestLocks>,
+}
+
+/// Advisory lock của các request mà worker này đang xử lý. Khóa cấp session nên được giữ trên một
+/// connection riêng ngoài pool (không chiếm connection của pool trong suốt quá trình export);
+/// connection bị đóng thì Postgres tự trả mọi khóa trên nó.
+#[derive(Default)]
+struct RequestLocks {
+    connection: Option<PgConnection>,
+    held: HashSet<Uuid>,
+}
+
+/// Tùy chọn của PostgresDbStore, lấy từ AppConfig.
+#[derive(Debug, Clone, Default)]
+pub struct PostgresStoreSettings {
+    /// Khi được set, `stream_product_data` đọc theo từng trang (keyset pagination), mỗi trang một
+    /// transaction ngắn, thay vì giữ một cursor mở trong suốt quá trình tạo file.
+    pub page_size: Option<usize>,
+    /// Đọc mọi trang trong một transaction REPEATABLE READ để các trang cùng nhìn thấy một snapshot.
+    pub page_snapshot: bool,
+    /// statement_timeout áp dụng cho query report (SET LOCAL trong transaction của report).
+    pub statement_timeout_ms: Option<u64>,
+}
+
+impl PostgresDbStore {
+    /// `read_pool` là pool của read replica; mọi thao tác ghi ExportRequests luôn dùng `pool` (primary).
+    pub fn new(
+        pool: Pool<Postgres>,
+        read_pool: Option<Pool<Postgres>>,
+        clock: Arc<dyn Clock>,
+        settings: PostgresStoreSettings,
+    ) -> Self {
+        let has_read_replica = read_pool.is_some();
+        let read_pool = read_pool.unwrap_or_else(|| pool.clone());
+        Self {
+            pool,
+            read_pool,
+            has_read_replica,
+            clock,
+            settings,
+            request_locks: tokio::sync::Mutex::new(RequestLocks::default()),
+        }
+    }
+
+    /// Ghi một sự kiện chuyển trạng thái. Luôn được gọi trong cùng transaction với câu UPDATE
+    /// tương ứng để lịch sử không bao giờ lệch với trạng thái hiện tại.
+    async fn insert_status_event(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        request_id: Uuid,
+        from_status: Option<&str>,
+        to_status: &str,
+        detail: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO export_request_events (request_id, from_status, to_status, detail, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            request_id,
+            from_status,
+            to_status,
+            detail,
+            self.clock.now_utc()
+        )
+        .execute(&mut **tx)
+        .await
+        .context("Failed to insert export request status event")?;
+        Ok(())
+    }
+
+    /// Đọc report sản phẩm trên `pool`: theo trang khi DB_PAGE_SIZE được set và thứ tự là mặc định,
+    /// ngược lại qua một cursor.
+    fn stream_product_rows<'a>(
+        &'a self,
+        pool: &'a Pool<Postgres>,
+        params: &'a ReportParams,
+    ) -> BoxStream<'a, Result<ProductData>> {
+        match self.settings.page_size {
+            Some(_) if params.has_row_window() => {
+                // OFFSET không kết hợp được với keyset pagination; limit/offset dùng cho export nhỏ.
+                info!("Explicit limit/offset requested, reading through a single cursor instead of pages.");
+                self.stream_product_cursor(pool, params)
+            }
+            Some(page_size) if params.uses_default_sort() => self.stream_product_pages(pool, params, page_size),
+            Some(_) => {
+                // Keyset pagination chỉ hỗ trợ thứ tự (created_at, product_id).
+                info!("Custom sort order requested, reading through a single cursor instead of pages.");
+                self.stream_product_cursor(pool, params)
+            }
+            None => self.stream_product_cursor(pool, params),
+        }
+    }
+
+    /// Đọc toàn bộ kết quả qua một cursor, trong một transaction duy nhất trên `pool`.
+    fn stream_product_cursor<'a>(
+        &'a self,
+        pool: &'a Pool<Postgres>,
+        params: &'a ReportParams,
+    ) -> BoxStream<'a, Result<ProductData>> {
+        info!("Streaming product data with parameters: {:?}", params);
+        Box::pin(async_stream::stream! {
+            let mut tx = match self.begin_report_transaction(pool, params.tenant.as_deref()).await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            let mut streamed: u64 = 0;
+            {
+                // LIMIT NULL không giới hạn số dòng.
+                let sql = format!("{} ORDER BY {} LIMIT $8 OFFSET $9", PRODUCT_REPORT_QUERY, params.order_by_sql());
+                let mut rows = bind_product_filters(sqlx::query_as(&sql), params)
+                    .bind(params.limit)
+                    .bind(params.query_offset())
+                    .fetch(&mut *tx);
+
+                while let Some(row) = rows.next().await {
+                    match row {
+                        Ok(row) => {
+                            streamed += 1;
+                            yield Ok(row);
+                        }
+                        Err(e) => {
+                            yield Err(self.map_report_query_error(e, "Failed to stream product data from database"));
+                            return;
+                        }
+                    }
+                }
+            }
+
+            if let Err(e) = tx.commit().await {
+                yield Err(anyhow::Error::new(e).context("Failed to finish product query transaction"));
+                return;
+            }
+            info!("Streamed {} records for export.", streamed);
+        })
+    }
+
+    /// Đọc toàn bộ đơn hàng qua một cursor, trong một transaction duy nhất.
+    fn stream_order_cursor<'a>(
+        &'a self,
+        params: &'a OrderReportParams,
+    ) -> BoxStream<'a, Result<OrderData>> {
+        info!("Streaming order data with parameters: {:?}", params);
+        Box::pin(async_stream::stream! {
+            let mut tx = match self.begin_report_transaction(&self.read_pool, params.tenant.as_deref()).await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            let mut streamed: u64 = 0;
+            {
+                let mut rows = bind_order_filters(sqlx::query_as(ORDER_REPORT_QUERY), params).fetch(&mut *tx);
+
+                while let Some(row) = rows.next().await {
+                    match row {
+                        Ok(row) => {
+                            streamed += 1;
+                            yield Ok(row);
+                        }
+                        Err(e) => {
+                            yield Err(self.map_report_query_error(e, "Failed to stream order data from database"));
+                            return;
+                        }
+                    }
+                }
+            }
+
+            if let Err(e) = tx.commit().await {
+                yield Err(anyhow::Error::new(e).context("Failed to finish order query transaction"));
+                return;
+            }
+            info!("Streamed {} orders for export.", streamed);
+        })
+    }
+
+    /// Đọc theo trang với keyset pagination trên (created_at, product_id): nhiều sản phẩm trùng
+    /// created_at vẫn không bị bỏ sót hay lặp lại vì product_id là duy nhất.
+    /// Khi `page_snapshot` bật, mọi trang dùng chung một transaction REPEATABLE READ; serialization
+    /// failure (kể cả xung đột recovery trên replica) là lỗi tạm thời, export sẽ được chạy lại từ đầu.
+    fn stream_product_pages<'a>(
+        &'a self,
+        pool: &'a Pool<Postgres>,
+        params: &'a ReportParams,
+        page_size: usize,
+    ) -> BoxStream<'a, Result<ProductData>> {
+        info!("Streaming product data in pages of {} rows with parameters: {:?}", page_size, params);
+        Box::pin(async_stream::stream! {
+            let mut snapshot = None;
+            if self.settings.page_snapshot {
+                match self.begin_snapshot_transaction(pool, params.tenant.as_deref()).await {
+                    Ok(tx) => snapshot = Some(tx),
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+
+            let mut after: Option<(DateTime<Utc>, i64)> = None;
+            let mut streamed: u64 = 0;
+            loop {
+                let page = match snapshot.as_mut() {
+                    Some(tx) => self.fetch_product_page(tx, params, after, page_size).await,
+                    None => self.fetch_product_page_in_own_transaction(pool, params, after, page_size).await,
+                };
+                let page = match page {
+                    Ok(page) => page,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+                let page_len = page.len();
+                after = page.last().map(|row| (row.created_at, row.product_id));
+                for row in page {
+                    streamed += 1;
+                    yield Ok(row);
+                }
+                if page_len < page_size {
+                    break;
+                }
+            }
+
+            if let Some(tx) = snapshot {
+                if let Err(e) = tx.commit().await {
+                    yield Err(anyhow::Error::new(e).context("Failed to finish product snapshot transaction"));
+                    return;
+                }
+            }
+            info!("Streamed {} records for export.", streamed);
+        })
+    }
+
+    /// Một trang dữ liệu sau khóa `after`, trong transaction riêng.
+    async fn fetch_product_page_in_own_transaction(
+        &self,
+        pool: &Pool<Postgres>,
+        params: &ReportParams,
+        after: Option<(DateTime<Utc>, i64)>,
+        page_size: usize,
+    ) -> Result<Vec<ProductData>> {
+        let mut tx = self.begin_report_transaction(pool, params.tenant.as_deref()).await?;
+        let page = self.fetch_product_page(&mut tx, params, after, page_size).await?;
+        tx.commit().await.context("Failed to finish product page transaction")?;
+        Ok(page)
+    }
+
+    /// Một trang dữ liệu sau khóa `after`, trong transaction `tx` của caller.
+    async fn fetch_product_page(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        params: &ReportParams,
+        after: Option<(DateTime<Utc>, i64)>,
+        page_size: usize,
+    ) -> Result<Vec<ProductData>> {
+        let (after_created_at, after_product_id) = after.unzip();
+        let (created_from, created_to) = params.utc_range();
+        sqlx::query_as!(
+            ProductData,
+            r#"
+            SELECT
+                product_id,
+                name,
+                category,
+                price,
+                stock_quantity,
+                created_at
+            FROM products
+            WHERE created_at BETWEEN $1 AND $2
+            AND ($3 IS NULL OR category = $3)
+            AND ($4::timestamptz IS NULL OR (created_at, product_id) > ($4, $5))
+            AND ($7::float8 IS NULL OR price >= $7)
+            AND ($8::float8 IS NULL OR price <= $8)
+            AND ($9::int4 IS NULL OR stock_quantity <= $9)
+            AND ($10::text IS NULL OR name ILIKE $10 ESCAPE '\')
+            ORDER BY created_at, product_id
+            LIMIT $6
+            "#,
+            created_from,
+            created_to,
+            params.product_category,
+            after_created_at,
+            after_product_id,
+            page_size as i64,
+            params.min_price,
+            params.max_price,
+            params.max_stock_quantity,
+            params.name_pattern(),
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| self.map_report_query_error(e, "Failed to fetch product data page from database"))
+    }
+
+    /// Chạy query report trên read pool. Replica có thể trễ so với primary: khi có replica riêng
+    /// và kết quả rỗng (ví dụ request tham chiếu dữ liệu vừa được ghi), chạy lại một lần trên primary.
+    async fn read_with_primary_fallback<T, F, Fut>(&self, report: &str, query: F) -> Result<Vec<T>>
+    where
+        F: Fn(Pool<Postgres>) -> Fut,
+        Fut: Future<Output = Result<Vec<T>>>,
+    {
+        let rows = query(self.read_pool.clone()).await?;
+        if !rows.is_empty() || !self.has_read_replica {
+            return Ok(rows);
+        }
+        info!("Read replica returned no {} rows, retrying on the primary.", report);
+        increment!("excel_export_read_replica_fallback_total", "report" => report.to_string());
+        query(self.pool.clone()).await
+    }
+
+    /// Lỗi do statement_timeout được chuyển thành `QueryTimeout` để caller nhận biết và thử lại.
+    fn map_report_query_error(&self, e: sqlx::Error, context: &'static str) -> anyhow::Error {
+        let timed_out = e
+            .as_database_error()
+            .and_then(|db_error| db_error.code())
+            .is_some_and(|code| code == QUERY_CANCELED_SQLSTATE);
+        match (timed_out, self.settings.statement_timeout_ms) {
+            (true, Some(timeout_ms)) => anyhow::Error::new(QueryTimeout { timeout_ms }),
+            _ => anyhow::Error::new(e).context(context),
+        }
+    }
+
+    /// Mở transaction cho query report. Deployment nhiều tenant: trỏ search_path vào schema của tenant,
+    /// chỉ trong transaction này. Tên schema được truyền qua bind parameter và quote_ident nên không thể chèn SQL.
+    async fn begin_report_transaction(
+        &self,
+        pool: &Pool<Postgres>,
+        tenant: Option<&str>,
+    ) -> Result<Transaction<'static, Postgres>> {
+        let tx = pool.begin().await.context("Failed to begin transaction for report query")?;
+        self.configure_report_transaction(tx, tenant).await
+    }
+
+    /// Như `begin_report_transaction`, nhưng ở mức REPEATABLE READ, READ ONLY: mọi query trong
+    /// transaction nhìn thấy cùng một snapshot. Mức cô lập phải được đặt trước mọi query khác.
+    async fn begin_snapshot_transaction(
+        &self,
+        pool: &Pool<Postgres>,
+        tenant: Option<&str>,
+    ) -> Result<Transaction<'static, Postgres>> {
+        let mut tx = pool.begin().await.context("Failed to begin snapshot transaction for report query")?;
+        sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await
+            .context("Failed to set REPEATABLE READ isolation for report query")?;
+        self.configure_report_transaction(tx, tenant).await
+    }
+
+    async fn configure_report_transaction(
+        &self,
+        mut tx: Transaction<'static, Postgres>,
+        tenant: Option<&str>,
+    ) -> Result<Transaction<'static, Postgres>> {
+        // Luôn ghi đè timeout ngắn mặc định của connection (dành cho các query cập nhật trạng thái);
+        // 0 nghĩa là không giới hạn khi DB_STATEMENT_TIMEOUT_MS không được set.
+        let timeout_ms = self.settings.statement_timeout_ms.unwrap_or(0);
+        sqlx::query!("SELECT set_config('statement_timeout', $1, true)", timeout_ms.to_string())
+            .execute(&mut *tx)
+            .await
+            .context("Failed to set statement_timeout for report query")?;
+        if let Some(tenant) = tenant {
+            sqlx::query!("SELECT set_config('search_path', quote_ident($1), true)", tenant)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("Failed to switch search_path to tenant '{}'", tenant))?;
+        }
+        Ok(tx)
+    }
+
+    /// Khóa row và trả về trạng thái hiện tại (dùng làm `from_status` cho audit log).
+    async fn lock_current_status(
+        tx: &mut Transaction<'_, Postgres>,
+        request_id: Uuid,
+    ) -> Result<String> {
+        sqlx::query_scalar!(
+            "SELECT status FROM ExportRequests WHERE id = $1 FOR UPDATE",
+            request_id
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to lock export request row")?
+        .context("Export request not found in DB")
+    }
+
+    /// Dùng chung cho trait method và cho kiểm tra quota bên trong transaction claim.
+    /// Chỉ đếm các request đang xử lý hoặc đã tạo file; request FAILED không bị tính.
+    async fn count_user_exports<'e>(
+        executor: impl PgExecutor<'e>,
+        user_id: i64,
+        since: DateTime<Utc>,
+        exclude_request_id: Option<Uuid>,
+    ) -> Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM ExportRequests
+            WHERE user_id = $1
+            AND requested_at >= $2
+            AND status IN ($3, $4, $5)
+            AND ($6::uuid IS NULL OR id <> $6)
+            "#,
+            user_id,
+            since,
+            ExportStatus::Processing.as_str(),
+            ExportStatus::Completed.as_str(),
+            ExportStatus::Expired.as_str(),
+            exclude_request_id,
+        )
+        .fetch_one(executor)
+        .await
+        .context("Failed to count user exports")?;
+        Ok(count)
+    }
+}
+
+#[async_trait::async_trait]
+impl DbStore for PostgresDbStore {
+    /// Ping cả read replica (nếu có), vì report không chạy được khi replica không dùng được.
+    async fn ping(&self) -> DbResult<()> {
+        ping_pool(&self.pool, "primary").await?;
+        if self.has_read_replica {
+            ping_pool(&self.read_pool, "read replica").await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self), fields(request_id = %request_id))]
+    async fn fetch_and_update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        quota: &ExportQuota,
+    ) -> DbResult<ExportRequest> {
+        let mut tx = self.pool.begin().await.context("Failed to begin database transaction")?;
+        info!("Starting transaction to fetch and update status to '{}'.", new_status.as_str());
+
+        let request = sqlx::query_as!(
+            ExportRequest,
+            r#"
+            SELECT
+                id, user_id, request_payload, requested_at, status AS "status: ExportStatus", file_path, completed_at, error_message, notification_sent, webhook_sent,
+                notification_attempts, notification_next_retry_at, expired_at, error_code, file_checksum, file_size_bytes,
+                expires_at, params_hash, duration_ms, row_count, truncated_by_limit, original_file_name, original_size_bytes, is_protected, file_name, is_encrypted, encryption_key_id, pgp_key_fingerprint, file_parts, estimated_completion_at,
+                db_fetch_ms, db_query_ms, file_generation_ms, notify_ms, attempts, last_attempt_at, last_error,
+                priority, next_retry_at, status_updated_at
+            FROM ExportRequests
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            request_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to fetch export request from DB")?
+        .ok_or(DbError::NotFound(request_id))?;
+
+        if request.status.is_final() {
+            warn!(
+                "Request {} already in final state: {}. Rolling back transaction and skipping processing.",
+                request_id, request.status
+            );
+            tx.rollback().await?;
+            return Err(DbError::AlreadyFinal(request.status));
+        }
+
+        if let Some(limit) = quota.limit_for(request.user_id) {
+            // Advisory lock theo user để các request song song của cùng user được đếm tuần tự.
+            sqlx::query!(
+                "SELECT pg_advisory_xact_lock(hashtextextended('export_quota:' || $1::text, 0))",
+                request.user_id
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to acquire quota lock for user")?;
+
+            let day_start = self.clock.now_utc().date_naive().and_time(NaiveTime::MIN).and_utc();
+            let used = Self::count_user_exports(&mut *tx, request.user_id, day_start, Some(request_id)).await?;
+            if used >= limit {
+                warn!(
+                    "User {} has used {}/{} exports today. Rejecting request {}.",
+                    request.user_id, used, limit, request_id
+                );
+                tx.rollback().await?;
+                return Err(DbError::QuotaExceeded(QuotaExceeded {
+                    limit,
+                    reset_at: day_start + chrono::Duration::days(1),
+                }));
+            }
+        }
+
+        sqlx::query!(
+            "UPDATE ExportRequests SET status = $1, status_updated_at = $2 WHERE id = $3",
+            new_status.as_str(),
+            self.clock.now_utc(),
+            request_id
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to update request status in DB")?;
+        self.insert_status_event(&mut tx, request_id, Some(request.status.as_str()), new_status.as_str(), None).await?;
+
+        tx.commit().await.context("Failed to commit database transaction")?;
+        info!("Successfully fetched and updated status to '{}' for request {}. Transaction committed.", new_status.as_str(), request_id);
+        Ok(request)
+    }
+
+    /// Khóa cấp session trên connection riêng của worker (xem `RequestLocks`), giữ đến khi `unlock_request`.
+    /// Khóa được lấy lại trên cùng session thì Postgres vẫn cho phép, nên request đã được khóa
+    /// trong chính worker này được kiểm tra bằng `held`.
+    #[instrument(skip(self))]
+    async fn try_lock_request(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        let mut locks = self.request_locks.lock().await;
+        if locks.held.contains(&request_id) {
+            return Ok(false);
+        }
+        let mut connection = match locks.connection.take() {
+            Some(connection) => connection,
+            None => PgConnection::connect_with(&self.pool.connect_options())
+                .await
+                .context("Failed to open connection for request locks")?,
+        };
+        let locked = sqlx::query_scalar!(
+            r#"SELECT pg_try_advisory_lock(hashtextextended($1::uuid::text, 0)) AS "locked!""#,
+            request_id
+        )
+        .fetch_one(&mut connection)
+        .await;
+        match locked {
+            Ok(locked) => {
+                locks.connection = Some(connection);
+                if locked {
+                    locks.held.insert(request_id);
+                }
+                Ok(locked)
+            }
+            // Connection có thể đã hỏng và được bỏ đi: các khóa khác trên nó cũng không còn.
+            Err(e) => {
+                locks.held.clear();
+                Err(anyhow::Error::new(e).context("Failed to take advisory lock for request").into())
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn unlock_request(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        let mut locks = self.request_locks.lock().await;
+        if !locks.held.remove(&request_id) {
+            return Ok(());
+        }
+        // Không còn connection nghĩa là khóa đã mất cùng session cũ.
+        let Some(mut connection) = locks.connection.take() else {
+            return Ok(());
+        };
+        let unlocked = sqlx::query_scalar!(
+            r#"SELECT pg_advisory_unlock(hashtextextended($1::uuid::text, 0)) AS "unlocked!""#,
+            request_id
+        )
+        .fetch_one(&mut connection)
+        .await;
+        match unlocked {
+            Ok(_) => {
+                locks.connection = Some(connection);
+                Ok(())
+            }
+            // Bỏ connection để Postgres giải phóng khóa cùng session.
+            Err(e) => {
+                locks.held.clear();
+                Err(anyhow::Error::new(e).context("Failed to release advisory lock for request").into())
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        file_path: Option<String>,
+        error_message: Option<String>,
+        error_code: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+        timings: &ExportTimings,
+        completion: &ExportCompletion,
+    ) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for status update")?;
+        info!("Updating final status to '{}' for request {}.", new_status.as_str(), request_id);
+        let previous_status = Self::lock_current_status(&mut tx, request_id).await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE ExportRequests
+            SET
+                status = $1,
+                status_updated_at = $3,
+                file_path = $2,
+                completed_at = $3,
+                error_message = $4,
+                error_code = $5,
+                expires_at = $6,
+                db_fetch_ms = $7,
+                db_query_ms = $8,
+                file_generation_ms = $9,
+                file_size_bytes = COALESCE($10, file_size_bytes),
+                row_count = COALESCE($11, row_count),
+                file_checksum = COALESCE($12, file_checksum),
+                truncated_by_limit = COALESCE($13, truncated_by_limit),
+                original_file_name = COALESCE($14, original_file_name),
+                original_size_bytes = COALESCE($15, original_size_bytes),
+                is_protected = COALESCE($16, is_protected),
+                file_name = COALESCE($17, file_name),
+                is_encrypted = COALESCE($18, is_encrypted),
+                encryption_key_id = COALESCE($19, encryption_key_id),
+                pgp_key_fingerprint = COALESCE($20, pgp_key_fingerprint),
+                file_parts = COALESCE($21, file_parts)
+            WHERE id = $22
+            "#,
+            new_status.as_str(),
+            file_path,
+            Some(self.clock.now_utc()),
+            error_message.as_deref(),
+            error_code.as_deref(),
+            expires_at,
+            timings.db_fetch_ms,
+            timings.db_query_ms,
+            timings.file_generation_ms,
+            completion.file_size_bytes,
+            completion.rows_exported,
+            completion.file_checksum.as_deref(),
+            completion.truncated_by_limit,
+            completion.original_file_name.as_deref(),
+            completion.original_size_bytes,
+            completion.is_protected,
+            completion.file_name.as_deref(),
+            completion.is_encrypted,
+            completion.encryption_key_id.as_deref(),
+            completion.pgp_key_fingerprint.as_deref(),
+            completion.file_parts.as_ref().map(|parts| serde_json::json!(parts)),
+            request_id
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to update export request final status in DB")?;
+        self.insert_status_event(
+            &mut tx,
+            request_id,
+            Some(&previous_status),
+            new_status.as_str(),
+            error_code.as_deref().or(error_message.as_deref()),
+        )
+        .await?;
+
+        tx.commit().await.context("Failed to commit final status update transaction")?;
+        info!("Final status updated successfully to '{}' for request {}.", new_status.as_str(), request_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn update_notification_sent_status(
+        &self,
+        request_id: Uuid,
+        sent: bool,
+    ) -> DbResult<()> {
+        sqlx::query!(
+            "UPDATE ExportRequests SET notification_sent = $1 WHERE id = $2",
+            sent,
+            request_id
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update notification_sent status")?;
+        info!("Notification sent status updated to {} for request {}.", sent, request_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn mark_webhook_sent(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        sqlx::query!(
+            "UPDATE ExportRequests SET webhook_sent = TRUE WHERE id = $1",
+            request_id
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update webhook_sent status")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self, params))]
+    async fn query_product_data(
+        &self,
+        params: &ReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<ProductData>> {
+        info!("Querying product data with parameters: {:?}", params);
+        let sql = format!("{} ORDER BY {} LIMIT $8 OFFSET $9", PRODUCT_REPORT_QUERY, params.order_by_sql());
+        let raw_data = self.read_with_primary_fallback("product", |pool| {
+            let sql = &sql;
+            async move {
+                let mut tx = self.begin_report_transaction(&pool, params.tenant.as_deref()).await?;
+                let rows = bind_product_filters(sqlx::query_as(sql), params)
+                    .bind(params.query_limit(max_rows))
+                    .bind(params.query_offset())
+                    .fetch_all(&mut *tx)
+                    .await
+                    .map_err(|e| self.map_report_query_error(e, "Failed to query product data from database"))?;
+                tx.commit().await.context("Failed to finish product query transaction")?;
+                Ok(rows)
+            }
+        })
+        .await?;
+
+        info!("Fetched {} records for export.", raw_data.len());
+        Ok(raw_data)
+    }
+
+    /// Như `query_product_data`: replica không trả về dòng nào thì đọc lại một lần trên primary.
+    fn stream_product_data<'a>(
+        &'a self,
+        params: &'a ReportParams,
+    ) -> BoxStream<'a, Result<ProductData>> {
+        if !self.has_read_replica {
+            return self.stream_product_rows(&self.read_pool, params);
+        }
+        Box::pin(async_stream::stream! {
+            let mut found = false;
+            let mut rows = self.stream_product_rows(&self.read_pool, params);
+            while let Some(row) = rows.next().await {
+                found = true;
+                yield row;
+            }
+            if found {
+                return;
+            }
+            info!("Read replica returned no product rows, retrying on the primary.");
+            increment!("excel_export_read_replica_fallback_total", "report" => "product");
+            let mut rows = self.stream_product_rows(&self.pool, params);
+            while let Some(row) = rows.next().await {
+                yield row;
+            }
+        })
+    }
+
+    /// Chạy trong transaction report nên chịu cùng statement_timeout và search_path của tenant.
+    #[instrument(skip(self, params))]
+    async fn count_product_data(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<i64> {
+        let mut tx = self.begin_report_transaction(&self.read_pool, params.tenant.as_deref()).await?;
+        let (count,): (i64,) = bind_product_filters(sqlx::query_as(PRODUCT_COUNT_QUERY), params)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to count product data"))?;
+        tx.commit().await.context("Failed to finish product count transaction")?;
+        Ok(count)
+    }
+
+    fn supports_csv_copy(&self) -> bool {
+        true
+    }
+
+    /// Chạy trên read pool, không chạy lại trên primary khi kết quả rỗng vì dữ liệu đã được ghi ra `writer`.
+    #[instrument(skip(self, params, writer))]
+    async fn copy_product_data_csv(
+        &self,
+        params: &ReportParams,
+        max_rows: usize,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> DbResult<u64> {
+        info!("Copying product data as CSV with parameters: {:?}", params);
+        let mut tx = self.begin_report_transaction(&self.read_pool, params.tenant.as_deref()).await?;
+        let copy_sql: String = bind_product_filters(
+            sqlx::query_as::<_, (String,)>("SELECT format($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)")
+                .bind(product_copy_template(params)),
+            params,
+        )
+        .bind(params.query_limit(max_rows))
+        .bind(params.query_offset())
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to build COPY statement for product data")?
+        .0;
+
+        let mut counter = CsvRecordCounter::default();
+        {
+            let mut chunks = tx
+                .copy_out_raw(&copy_sql)
+                .await
+                .map_err(|e| self.map_report_query_error(e, "Failed to copy product data from database"))?;
+            while let Some(chunk) = chunks.next().await {
+                let chunk =
+                    chunk.map_err(|e| self.map_report_query_error(e, "Failed to copy product data from database"))?;
+                counter.feed(&chunk);
+                writer.write_all(&chunk).await.context("Failed to write copied CSV data")?;
+            }
+        }
+        tx.commit().await.context("Failed to finish product copy transaction")?;
+
+        // Bản ghi đầu tiên là header.
+        let rows = counter.records.saturating_sub(1);
+        info!("Copied {} records for export.", rows);
+        Ok(rows)
+    }
+
+    /// Prepare trên read pool, cùng câu lệnh (đã bọc LIMIT) sẽ chạy khi export.
+    async fn prepare_dataset(
+        &self,
+        sql: &str,
+        params: &[DatasetParam],
+    ) -> DbResult<Vec<String>> {
+        let sql = dataset_sql(sql, params.len());
+        let mut conn = self.read_pool.acquire().await.context("Failed to acquire connection to prepare dataset")?;
+        let statement = (&mut *conn).prepare(&sql).await.context("Failed to prepare dataset SQL")?;
+        if let Some(Either::Left(parameters)) = statement.parameters() {
+            // Tham số cuối là LIMIT do service thêm vào.
+            let inferred: Vec<&str> = parameters.iter().map(|parameter| parameter.name()).collect();
+            check_dataset_params(params, &inferred[..inferred.len().saturating_sub(1)]).map_err(DbError::Fatal)?;
+        }
+        let mut columns = Vec::with_capacity(statement.columns().len());
+        for column in statement.columns() {
+            let type_name = column.type_info().name();
+            if !DATASET_COLUMN_TYPES.contains(&type_name) {
+                return Err(DbError::Fatal(anyhow::anyhow!(
+                    "Dataset column '{}' has unsupported type {}; cast it in the SQL (for example ::float8 or ::text)",
+                    column.name(),
+                    type_name
+                )));
+            }
+            columns.push(column.name().to_string());
+        }
+        Ok(columns)
+    }
+
+    #[instrument(skip(self, sql, values))]
+    async fn query_dataset(
+        &self,
+        sql: &str,
+        values: &[DatasetValue],
+        tenant: Option<&str>,
+        max_rows: usize,
+    ) -> DbResult<DatasetRows> {
+        let sql = dataset_sql(sql, values.len());
+        let mut tx = self.begin_report_transaction(&self.read_pool, tenant).await?;
+        let statement = (&mut *tx)
+            .prepare(&sql)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to prepare dataset query"))?;
+        // Tên cột lấy từ metadata của câu lệnh nên vẫn có header khi kết quả rỗng.
+        let columns: Vec<String> = statement.columns().iter().map(|column| column.name().to_string()).collect();
+        let rows = bind_dataset_values(statement.query(), values)
+            .bind(i64::try_from(max_rows).unwrap_or(i64::MAX - 1) + 1)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to query dataset from database"))?;
+        tx.commit().await.context("Failed to finish dataset query transaction")?;
+
+        let rows = rows
+            .iter()
+            .map(|row| (0..columns.len()).map(|index| dataset_cell(row, index)).collect::<Result<Vec<_>>>())
+            .collect::<Result<Vec<_>>>()?;
+        info!("Fetched {} dataset rows for export.", rows.len());
+        Ok(DatasetRows { columns, rows })
+    }
+
+    #[instrument(skip(self, params))]
+    async fn query_order_data(
+        &self,
+        params: &OrderReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<OrderData>> {
+        info!("Querying order data with parameters: {:?}", params);
+        let sql = format!("{} LIMIT $5", ORDER_REPORT_QUERY);
+        let raw_data = self.read_with_primary_fallback("order", |pool| {
+            let sql = &sql;
+            async move {
+                let mut tx = self.begin_report_transaction(&pool, params.tenant.as_deref()).await?;
+                let rows = bind_order_filters(sqlx::query_as(sql), params)
+                    .bind(i64::try_from(max_rows).unwrap_or(i64::MAX - 1) + 1)
+                    .fetch_all(&mut *tx)
+                    .await
+                    .map_err(|e| self.map_report_query_error(e, "Failed to query order data from database"))?;
+                tx.commit().await.context("Failed to finish order query transaction")?;
+                Ok(rows)
+            }
+        })
+        .await?;
+
+        info!("Fetched {} orders for export.", raw_data.len());
+        Ok(raw_data)
+    }
+
+    fn stream_order_data<'a>(
+        &'a self,
+        params: &'a OrderReportParams,
+    ) -> BoxStream<'a, Result<OrderData>> {
+        self.stream_order_cursor(params)
+    }
+
+    #[instrument(skip(self, params))]
+    async fn query_customer_data(
+        &self,
+        params: &CustomerReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<CustomerData>> {
+        info!("Querying customer data with parameters: {:?}", params);
+        let raw_data = self.read_with_primary_fallback("customer", |pool| async move {
+            let mut tx = self.begin_report_transaction(&pool, params.tenant.as_deref()).await?;
+            let rows = sqlx::query_as(CUSTOMER_REPORT_QUERY)
+                .bind(params.signup_from)
+                .bind(params.signup_to)
+                .bind(params.owner_user_id)
+                .bind(params.min_lifetime_value)
+                .bind(i64::try_from(max_rows).unwrap_or(i64::MAX - 1) + 1)
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(|e| self.map_report_query_error(e, "Failed to query customer data from database"))?;
+            tx.commit().await.context("Failed to finish customer query transaction")?;
+            Ok(rows)
+        })
+        .await?;
+
+        info!("Fetched {} customers for export.", raw_data.len());
+        Ok(raw_data)
+    }
+
+    #[instrument(skip(self, params))]
+    async fn query_category_summary(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<Vec<SalesSummaryRow>> {
+        info!("Querying category summary with parameters: {:?}", params);
+        // Luôn có dòng tổng nên không thể phát hiện replica trễ qua kết quả rỗng; chỉ đọc từ read pool.
+        let mut tx = self.begin_report_transaction(&self.read_pool, params.tenant.as_deref()).await?;
+        let rows = bind_product_filters(sqlx::query_as(CATEGORY_SUMMARY_QUERY), params)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to query category summary from database"))?;
+        tx.commit().await.context("Failed to finish category summary transaction")?;
+
+        info!("Fetched {} summary rows for export.", rows.len());
+        Ok(rows)
+    }
+
+    #[instrument(skip(self))]
+    async fn claim_unsent_notifications(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        // SKIP LOCKED + lease: hai replica chạy đồng thời sẽ không bao giờ claim cùng một row.
+        // Chỉ lấy các request đã kết thúc ít nhất 1 phút để tránh tranh chấp với luồng xử lý chính.
+        let requests = sqlx::query_as!(
+            ExportRequest,
+            r#"
+            UPDATE ExportRequests
+            SET notification_next_retry_at = NOW() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id
+                FROM ExportRequests
+                WHERE notification_sent = FALSE
+                AND status IN ($3, $4)
+                AND completed_at < NOW() - INTERVAL '1 minute'
+                AND (notification_next_retry_at IS NULL OR notification_next_retry_at <= NOW())
+                ORDER BY completed_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING
+                id, user_id, request_payload, requested_at, status AS "status: ExportStatus", file_path, completed_at, error_message, notification_sent, webhook_sent,
+                notification_attempts, notification_next_retry_at, expired_at, error_code, file_checksum, file_size_bytes,
+                expires_at, params_hash, duration_ms, row_count, truncated_by_limit, original_file_name, original_size_bytes, is_protected, file_name, is_encrypted, encryption_key_id, pgp_key_fingerprint, file_parts, estimated_completion_at,
+                db_fetch_ms, db_query_ms, file_generation_ms, notify_ms, attempts, last_attempt_at, last_error,
+                priority, next_retry_at, status_updated_at
+            "#,
+            limit,
+            NOTIFICATION_CLAIM_LEASE_SECS,
+            ExportStatus::Completed.as_str(),
+            ExportStatus::Failed.as_str(),
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to claim unsent notifications")?;
+
+        info!("Claimed {} request(s) with unsent notifications.", requests.len());
+        Ok(requests)
+    }
+
+    #[instrument(skip(self))]
+    async fn record_notification_failure(
+        &self,
+        request_id: Uuid,
+        next_retry_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE ExportRequests
+            SET
+                notification_attempts = notification_attempts + 1,
+                notification_next_retry_at = $1
+            WHERE id = $2
+            "#,
+            next_retry_at,
+            request_id
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to record notification failure")?;
+        info!("Notification retry for request {} scheduled at {}.", request_id, next_retry_at);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn list_expired(
+        &self,
+        before: DateTime<Utc>,
+    ) -> DbResult<Vec<ExportRequest>> {
+        let requests = sqlx::query_as!(
+            ExportRequest,
+            r#"
+            SELECT
+                id, user_id, request_payload, requested_at, status AS "status: ExportStatus", file_path, completed_at, error_message, notification_sent, webhook_sent,
+                notification_attempts, notification_next_retry_at, expired_at, error_code, file_checksum, file_size_bytes,
+                expires_at, params_hash, duration_ms, row_count, truncated_by_limit, original_file_name, original_size_bytes, is_protected, file_name, is_encrypted, encryption_key_id, pgp_key_fingerprint, file_parts, estimated_completion_at,
+                db_fetch_ms, db_query_ms, file_generation_ms, notify_ms, attempts, last_attempt_at, last_error,
+                priority, next_retry_at, status_updated_at
+            FROM ExportRequests
+            WHERE status = $1
+            AND file_path IS NOT NULL
+            AND (
+                (expires_at IS NOT NULL AND expires_at <= NOW())
+                OR (expires_at IS NULL AND completed_at < $2)
+            )
+            ORDER BY completed_at
+            "#,
+            ExportStatus::Completed.as_str(),
+            before
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list expired export requests")?;
+
+        info!("Found {} export(s) completed before {}.", requests.len(), before);
+        Ok(requests)
+    }
+
+    #[instrument(skip(self))]
+    async fn is_file_shared(
+        &self,
+        file_path: &str,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        let shared = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM ExportRequests
+                WHERE file_path = $1
+                AND id <> $2
+                AND status = $3
+                AND (expires_at IS NULL OR expires_at > NOW())
+            ) AS "shared!"
+            "#,
+            file_path,
+            request_id,
+            ExportStatus::Completed.as_str()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to check whether the export file is shared")?;
+        Ok(shared)
+    }
+
+    #[instrument(skip(self))]
+    async fn mark_expired(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for expiry")?;
+        let previous_status = Self::lock_current_status(&mut tx, request_id).await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE ExportRequests
+            SET
+                status = $1,
+                status_updated_at = $2,
+                file_path = NULL,
+                expired_at = $2
+            WHERE id = $3
+            "#,
+            ExportStatus::Expired.as_str(),
+            self.clock.now_utc(),
+            request_id
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to mark export request as expired")?;
+        self.insert_status_event(
+            &mut tx,
+            request_id,
+            Some(&previous_status),
+            ExportStatus::Expired.as_str(),
+            Some("file removed by retention job"),
+        )
+        .await?;
+
+        tx.commit().await.context("Failed to commit expiry transaction")?;
+        info!("Request {} marked as EXPIRED.", request_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn count_user_exports_since(
+        &self,
+        user_id: i64,
+        since: DateTime<Utc>,
+    ) -> DbResult<i64> {
+        Ok(Self::count_user_exports(&self.pool, user_id, since, None).await?)
+    }
+
+    #[instrument(skip(self))]
+    async fn record_generated_file(
+        &self,
+        request_id: Uuid,
+        file_path: &str,
+        file_checksum: &str,
+        file_size_bytes: i64,
+    ) -> DbResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE ExportRequests
+            SET
+                file_path = $1,
+                file_checksum = $2,
+                file_size_bytes = $3
+            WHERE id = $4
+            "#,
+            file_path,
+            file_checksum,
+            file_size_bytes,
+            request_id
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to record generated file metadata")?;
+        info!("Recorded generated file {} ({} bytes) for request {}.", file_path, file_size_bytes, request_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_request_history(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<Vec<ExportRequestEvent>> {
+        let events = sqlx::query_as!(
+            ExportRequestEvent,
+            r#"
+            SELECT id AS "id!", request_id AS "request_id!", from_status, to_status AS "to_status!", detail,
+                created_at AS "created_at!"
+            FROM (
+                SELECT id, request_id, from_status, to_status, detail, created_at
+                FROM export_request_events
+                WHERE request_id = $1
+                UNION ALL
+                SELECT id, request_id, from_status, to_status, detail, created_at
+                FROM export_request_events_archive
+                WHERE request_id = $1
+            ) AS events
+            ORDER BY created_at, id
+            "#,
+            request_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch export request history")?;
+        Ok(events)
+    }
+
+    #[instrument(skip(self))]
+    async fn record_params_hash(
+        &self,
+        request_id: Uuid,
+        params_hash: &str,
+    ) -> DbResult<()> {
+        sqlx::query!(
+            "UPDATE ExportRequests SET params_hash = $1 WHERE id = $2",
+            params_hash,
+            request_id
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to record params hash")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn find_recent_by_params_hash(
+        &self,
+        params_hash: &str,
+        since: DateTime<Utc>,
+        exclude_request_id: Uuid,
+    ) -> DbResult<Option<ExportRequest>> {
+        let request = sqlx::query_as!(
+            ExportRequest,
+            r#"
+            SELECT
+                id, user_id, request_payload, requested_at, status AS "status: ExportStatus", file_path, completed_at, error_message, notification_sent, webhook_sent,
+                notification_attempts, notification_next_retry_at, expired_at, error_code, file_checksum, file_size_bytes,
+                expires_at, params_hash, duration_ms, row_count, truncated_by_limit, original_file_name, original_size_bytes, is_protected, file_name, is_encrypted, encryption_key_id, pgp_key_fingerprint, file_parts, estimated_completion_at,
+                db_fetch_ms, db_query_ms, file_generation_ms, notify_ms, attempts, last_attempt_at, last_error,
+                priority, next_retry_at, status_updated_at
+            FROM ExportRequests
+            WHERE params_hash = $1
+            AND id <> $2
+            AND status = $3
+            AND file_path IS NOT NULL
+            AND completed_at >= $4
+            AND (expires_at IS NULL OR expires_at > NOW())
+            ORDER BY completed_at DESC
+            LIMIT 1
+            "#,
+            params_hash,
+            exclude_request_id,
+            ExportStatus::Completed.as_str(),
+            since
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to look up recent request by params hash")?;
+        Ok(request)
+    }
+
+    #[instrument(skip(self))]
+    async fn record_estimated_completion(
+        &self,
+        request_id: Uuid,
+        estimated_completion_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        sqlx::query!(
+            "UPDATE ExportRequests SET estimated_completion_at = $1 WHERE id = $2",
+            estimated_completion_at,
+            request_id
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to record estimated completion time")?;
+        Ok(())
+    }
 
-    async fn update_request_status(
+    #[instrument(skip(self))]
+    async fn record_export_duration(
         &self,
         request_id: Uuid,
-        new_status: ExportStatus,
-        file_path: Option<String>,
-        error_message: Option<String>,
-    ) -> Result<()>;
+        duration_ms: i64,
+        row_count: Option<i64>,
+    ) -> DbResult<()> {
+        sqlx::query!(
+            "UPDATE ExportRequests SET duration_ms = $1, row_count = $2 WHERE id = $3",
+            duration_ms,
+            row_count,
+            request_id
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to record export duration")?;
+        Ok(())
+    }
 
-    async fn update_notification_sent_status(
+    #[instrument(skip(self))]
+    async fn recent_export_durations(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<(i64, i64)>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT row_count AS "row_count!", duration_ms AS "duration_ms!"
+            FROM ExportRequests
+            WHERE status = $1
+            AND duration_ms IS NOT NULL
+            AND row_count IS NOT NULL
+            ORDER BY completed_at DESC
+            LIMIT $2
+            "#,
+            ExportStatus::Completed.as_str(),
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch recent export durations")?;
+        Ok(rows.into_iter().map(|row| (row.row_count, row.duration_ms)).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn record_notify_duration(
         &self,
         request_id: Uuid,
-        sent: bool,
-    ) -> Result<()>;
+        notify_ms: i64,
+    ) -> DbResult<()> {
+        sqlx::query!(
+            "UPDATE ExportRequests SET notify_ms = $1 WHERE id = $2",
+            notify_ms,
+            request_id
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to record notification duration")?;
+        Ok(())
+    }
 
-    async fn query_product_data(
+    #[instrument(skip(self, error))]
+    async fn record_attempt(
         &self,
-        params: &ReportParams,
-    ) -> Result<Vec<ProductData>>;
-}
+        request_id: Uuid,
+        error: Option<&str>,
+    ) -> DbResult<()> {
+        match error {
+            None => sqlx::query!(
+                "UPDATE ExportRequests SET attempts = attempts + 1, last_attempt_at = $1 WHERE id = $2",
+                self.clock.now_utc(),
+                request_id
+            )
+            .execute(&self.pool)
+            .await
+            .context("Failed to record export attempt")?,
+            Some(error) => sqlx::query!(
+                "UPDATE ExportRequests SET last_error = $1 WHERE id = $2",
+                error,
+                request_id
+            )
+            .execute(&self.pool)
+            .await
+            .context("Failed to record export attempt error")?,
+        };
+        Ok(())
+    }
 
-/// Implementation cụ thể cho PostgreSQL.
-pub struct PostgresDbStore {
-    pool: Pool<Postgres>,
-}
+    #[instrument(skip(self))]
+    async fn claim_next_pending(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for pending claim")?;
+        let requests = sqlx::query_as!(
+            ExportRequest,
+            r#"
+            UPDATE ExportRequests
+            SET status = $2, status_updated_at = NOW()
+            WHERE id IN (
+                SELECT id
+                FROM ExportRequests
+                WHERE status = $3
+                AND (next_retry_at IS NULL OR next_retry_at <= NOW())
+                ORDER BY priority DESC, requested_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING
+                id, user_id, request_payload, requested_at, status AS "status: ExportStatus", file_path, completed_at, error_message, notification_sent, webhook_sent,
+                notification_attempts, notification_next_retry_at, expired_at, error_code, file_checksum, file_size_bytes,
+                expires_at, params_hash, duration_ms, row_count, truncated_by_limit, original_file_name, original_size_bytes, is_protected, file_name, is_encrypted, encryption_key_id, pgp_key_fingerprint, file_parts, estimated_completion_at,
+                db_fetch_ms, db_query_ms, file_generation_ms, notify_ms, attempts, last_attempt_at, last_error,
+                priority, next_retry_at, status_updated_at
+            "#,
+            limit,
+            ExportStatus::Processing.as_str(),
+            ExportStatus::Pending.as_str(),
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .context("Failed to claim pending export requests")?;
 
-impl PostgresDbStore {
-    pub fn new(pool: Pool<Postgres>) -> Self {
-        Self { pool }
+        for request in &requests {
+            self.insert_status_event(
+                &mut tx,
+                request.id,
+                Some(ExportStatus::Pending.as_str()),
+                ExportStatus::Processing.as_str(),
+                Some("claimed by polling"),
+            )
+            .await?;
+        }
+        tx.commit().await.context("Failed to commit pending claim transaction")?;
+
+        info!("Claimed {} pending request(s).", requests.len());
+        Ok(requests)
     }
-}
 
-#[async_trait::async_trait]
-impl DbStore for PostgresDbStore {
-    #[instrument(skip(self), fields(request_id = %request_id))]
-    async fn fetch_and_update_request_status(
+    #[instrument(skip(self))]
+    async fn fetch_stuck_processing(
         &self,
-        request_id: Uuid,
-        new_status: ExportStatus,
-    ) -> Result<ExportRequest> {
-        let mut tx = self.pool.begin().await.context("Failed to begin database transaction")?;
-        info!("Starting transaction to fetch and update status to '{}'.", new_status.as_str());
-
-        let request = sqlx::query_as!(
+        stuck_for: chrono::Duration,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for stuck request lookup")?;
+        let requests = sqlx::query_as!(
             ExportRequest,
             r#"
             SELECT
-                id, user_id, request_payload, requested_at, status, file_path, completed_at, error_message, notification_sent
+                id, user_id, request_payload, requested_at, status AS "status: ExportStatus", file_path, completed_at, error_message, notification_sent, webhook_sent,
+                notification_attempts, notification_next_retry_at, expired_at, error_code, file_checksum, file_size_bytes,
+                expires_at, params_hash, duration_ms, row_count, truncated_by_limit, original_file_name, original_size_bytes, is_protected, file_name, is_encrypted, encryption_key_id, pgp_key_fingerprint, file_parts, estimated_completion_at,
+                db_fetch_ms, db_query_ms, file_generation_ms, notify_ms, attempts, last_attempt_at, last_error,
+                priority, next_retry_at, status_updated_at
             FROM ExportRequests
-            WHERE id = $1
-            FOR UPDATE
+            WHERE status = $1
+            AND status_updated_at < $2
+            ORDER BY status_updated_at
+            LIMIT $3
+            FOR UPDATE SKIP LOCKED
             "#,
-            request_id
+            ExportStatus::Processing.as_str(),
+            self.clock.now_utc() - stuck_for,
+            limit
         )
-        .fetch_optional(&mut *tx)
+        .fetch_all(&mut *tx)
         .await
-        .context("Failed to fetch export request from DB")?
-        .context("Export request not found in DB")?;
-
-        if request.status == ExportStatus::Completed.as_str() || request.status == ExportStatus::Failed.as_str() {
-            warn!(
-                "Request {} already in final state: {}. Rolling back transaction and skipping processing.",
-                request_id, request.status
-            );
-            tx.rollback().await?;
-            return Err(anyhow::anyhow!("Request already processed and in final state"));
-        }
+        .context("Failed to fetch stuck PROCESSING requests")?;
+        tx.commit().await.context("Failed to finish stuck request lookup")?;
+        Ok(requests)
+    }
 
-        sqlx::query!(
-            "UPDATE ExportRequests SET status = $1 WHERE id = $2",
-            new_status.as_str(),
-            request_id
+    #[instrument(skip(self, ids))]
+    async fn reset_to_pending(
+        &self,
+        ids: &[Uuid],
+    ) -> DbResult<Vec<Uuid>> {
+        // Một câu lệnh: reset các row còn PROCESSING (bỏ qua row đang bị khóa) và ghi audit log.
+        let reset_ids = sqlx::query_scalar!(
+            r#"
+            WITH reset AS (
+                UPDATE ExportRequests
+                SET
+                    status = $2,
+                    status_updated_at = $4,
+                    file_path = NULL,
+                    file_checksum = NULL,
+                    file_size_bytes = NULL,
+                    error_message = NULL,
+                    error_code = NULL,
+                    estimated_completion_at = NULL,
+                    duration_ms = NULL,
+                    row_count = NULL,
+                    truncated_by_limit = NULL,
+                    original_file_name = NULL,
+                    original_size_bytes = NULL,
+                    is_protected = NULL,
+                    file_name = NULL,
+                    is_encrypted = NULL,
+                    encryption_key_id = NULL,
+                    pgp_key_fingerprint = NULL,
+                    file_parts = NULL,
+                    webhook_sent = FALSE,
+                    db_fetch_ms = NULL,
+                    db_query_ms = NULL,
+                    file_generation_ms = NULL
+                WHERE id IN (
+                    SELECT id
+                    FROM ExportRequests
+                    WHERE id = ANY($1)
+                    AND status = $3
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id
+            )
+            INSERT INTO export_request_events (request_id, from_status, to_status, detail, created_at)
+            SELECT id, $3, $2, 'reset after being stuck in PROCESSING', $4
+            FROM reset
+            RETURNING request_id AS "request_id!"
+            "#,
+            ids,
+            ExportStatus::Pending.as_str(),
+            ExportStatus::Processing.as_str(),
+            self.clock.now_utc()
         )
-        .execute(&mut *tx)
+        .fetch_all(&self.pool)
         .await
-        .context("Failed to update request status in DB")?;
+        .context("Failed to reset stuck requests to PENDING")?;
 
-        tx.commit().await.context("Failed to commit database transaction")?;
-        info!("Successfully fetched and updated status to '{}' for request {}. Transaction committed.", new_status.as_str(), request_id);
-        Ok(request)
+        info!("Reset {} stuck request(s) to PENDING.", reset_ids.len());
+        Ok(reset_ids)
     }
 
-    #[instrument(skip(self))]
-    async fn update_request_status(
+    #[instrument(skip(self, ids, error_message), fields(requested = ids.len()))]
+    async fn bulk_update_status(
         &self,
-        request_id: Uuid,
+        ids: &[Uuid],
         new_status: ExportStatus,
-        file_path: Option<String>,
         error_message: Option<String>,
-    ) -> Result<()> {
-        let mut tx = self.pool.begin().await.context("Failed to begin transaction for status update")?;
-        info!("Updating final status to '{}' for request {}.", new_status.as_str(), request_id);
-
-        sqlx::query!(
+        force: bool,
+    ) -> DbResult<u64> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for bulk status update")?;
+        // Một câu lệnh: khóa các row cần đổi, cập nhật và ghi audit log với trạng thái cũ của từng row.
+        let updated_ids = sqlx::query_scalar!(
             r#"
-            UPDATE ExportRequests
-            SET
-                status = $1,
-                file_path = $2,
-                completed_at = $3,
-                error_message = $4
-            WHERE id = $5
+            WITH target AS (
+                SELECT id, status
+                FROM ExportRequests
+                WHERE id = ANY($1)
+                AND status <> $2
+                AND ($5 OR status <> $6)
+                FOR UPDATE
+            ),
+            updated AS (
+                UPDATE ExportRequests
+                SET
+                    status = $2,
+                    status_updated_at = $4,
+                    error_message = $3
+                FROM target
+                WHERE ExportRequests.id = target.id
+                RETURNING ExportRequests.id, target.status AS from_status
+            )
+            INSERT INTO export_request_events (request_id, from_status, to_status, detail, created_at)
+            SELECT id, from_status, $2, 'bulk status update', $4
+            FROM updated
+            RETURNING request_id AS "request_id!"
             "#,
+            ids,
             new_status.as_str(),
-            file_path,
-            Some(Utc::now()),
-            error_message,
-            request_id
+            error_message.as_deref(),
+            self.clock.now_utc(),
+            force,
+            ExportStatus::Completed.as_str()
         )
-        .execute(&mut *tx)
+        .fetch_all(&mut *tx)
         .await
-        .context("Failed to update export request final status in DB")?;
+        .context("Failed to bulk update export request status")?;
+        tx.commit().await.context("Failed to commit bulk status update")?;
 
-        tx.commit().await.context("Failed to commit final status update transaction")?;
-        info!("Final status updated successfully to '{}' for request {}.", new_status.as_str(), request_id);
-        Ok(())
+        let updated = updated_ids.len() as u64;
+        info!(
+            "Bulk status update to '{}': {} of {} request(s) updated (force: {}).",
+            new_status.as_str(),
+            updated,
+            ids.len(),
+            force
+        );
+        Ok(updated)
     }
 
     #[instrument(skip(self))]
-    async fn update_notification_sent_status(
-        &self,
-        request_id: Uuid,
-        sent: bool,
-    ) -> Result<()> {
-        sqlx::query!(
-            "UPDATE ExportRequests SET notification_sent = $1 WHERE id = $2",
-            sent,
-            request_id
+    async fn count_by_status(&self) -> DbResult<HashMap<ExportStatus, i64>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT status AS "status: ExportStatus", COUNT(*) AS "count!"
+            FROM ExportRequests
+            GROUP BY status
+            "#
         )
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await
-        .context("Failed to update notification_sent status")?;
-        info!("Notification sent status updated to {} for request {}.", sent, request_id);
-        Ok(())
+        .context("Failed to count export requests by status")?;
+        Ok(rows.into_iter().map(|row| (row.status, row.count)).collect())
     }
 
-    #[instrument(skip(self, params))]
-    async fn query_product_data(
+    #[instrument(skip(self))]
+    async fn archive_requests(
         &self,
-        params: &ReportParams,
-    ) -> Result<Vec<ProductData>> {
-        info!("Querying product data with parameters: {:?}", params);
-        let raw_data = sqlx::query_as!(
-            ProductData,
+        before: DateTime<Utc>,
+        batch: i64,
+    ) -> DbResult<i64> {
+        let mut total = 0;
+        loop {
+            // Mỗi batch là một statement (một transaction): DELETE khỏi bảng live và INSERT vào archive,
+            // cho cả request lẫn lịch sử trạng thái của nó. Khóa ngoại của export_request_events được kiểm tra
+            // cuối statement, khi lịch sử đã được chuyển đi.
+            let archived = sqlx::query_scalar!(
+                r#"
+                WITH archived AS (
+                    DELETE FROM ExportRequests
+                    WHERE id IN (
+                        SELECT id FROM ExportRequests
+                        WHERE (status IN ($1, $2) OR (status = $3 AND (file_path IS NULL OR expires_at < $4)))
+                        AND status_updated_at < $4
+                        AND notification_sent = TRUE
+                        ORDER BY status_updated_at
+                        LIMIT $5
+                        FOR UPDATE SKIP LOCKED
+                    )
+                    RETURNING *
+                ),
+                inserted AS (
+                    INSERT INTO export_requests_archive
+                    SELECT * FROM archived
+                    RETURNING 1
+                ),
+                archived_events AS (
+                    DELETE FROM export_request_events
+                    WHERE request_id IN (SELECT id FROM archived)
+                    RETURNING *
+                ),
+                inserted_events AS (
+                    INSERT INTO export_request_events_archive
+                    SELECT * FROM archived_events
+                )
+                SELECT COUNT(*) AS "count!" FROM inserted
+                "#,
+                ExportStatus::Failed.as_str(),
+                ExportStatus::Expired.as_str(),
+                ExportStatus::Completed.as_str(),
+                before,
+                batch
+            )
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to archive export requests")?;
+
+            total += archived;
+            if archived < batch {
+                break;
+            }
+        }
+
+        info!("Archived {} export request(s) last updated before {}.", total, before);
+        Ok(total)
+    }
+
+    #[instrument(skip(self))]
+    async fn list_unsent_notifications(
+        &self,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> DbResult<Vec<UnsentNotification>> {
+        // Cursor là (completed_at, id) của request `after`; id phân định các request cùng completed_at.
+        let notifications = sqlx::query_as!(
+            UnsentNotification,
             r#"
             SELECT
-                product_id,
-                name,
-                category,
-                price,
-                stock_quantity,
-                created_at
-            FROM products
-            WHERE created_at BETWEEN $1 AND $2
-            AND ($3 IS NULL OR category = $3)
+                id AS request_id, user_id, status AS "status: ExportStatus", file_path, error_message, expires_at, completed_at,
+                notification_attempts, notification_next_retry_at
+            FROM ExportRequests
+            WHERE notification_sent = FALSE
+            AND status IN ($2, $3)
+            AND (
+                $4::uuid IS NULL
+                OR (completed_at, id) > (SELECT completed_at, id FROM ExportRequests WHERE id = $4)
+            )
+            ORDER BY completed_at, id
+            LIMIT $1
             "#,
-            params.start_date.and_time(NaiveTime::MIN),
-            params.end_date.and_time(NaiveTime::MAX),
-            params.product_category,
+            limit,
+            ExportStatus::Completed.as_str(),
+            ExportStatus::Failed.as_str(),
+            after,
         )
         .fetch_all(&self.pool)
         .await
-        .context("Failed to query product data from database")?;
+        .context("Failed to list unsent notifications")?;
+        Ok(notifications)
+    }
+}
 
-        info!("Fetched {} records for export.", raw_data.len());
-        Ok(raw_data)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(declared: &[(&str, DatasetParamType)]) -> Vec<DatasetParam> {
+        declared.iter().map(|(name, param_type)| DatasetParam { name: name.to_string(), param_type: *param_type }).collect()
+    }
+
+    #[test]
+    fn sample_template_params_match_inferred_types() {
+        // README: SELECT name, lifetime_value::float8 FROM customer_totals WHERE signup_date >= $1
+        assert!(check_dataset_params(&params(&[("since", DatasetParamType::Date)]), &["DATE"]).is_ok());
+        // ... WHERE ordered_at >= $1 AND user_id = $2 AND status = $3 AND total > $4 AND paid = $5
+        let declared = params(&[
+            ("from", DatasetParamType::Timestamp),
+            ("user_id", DatasetParamType::Int),
+            ("status", DatasetParamType::Text),
+            ("min_total", DatasetParamType::Float),
+            ("paid", DatasetParamType::Bool),
+        ]);
+        assert!(check_dataset_params(&declared, &["TIMESTAMPTZ", "INT4", "VARCHAR", "NUMERIC", "BOOL"]).is_ok());
+    }
+
+    #[test]
+    fn date_param_is_accepted_against_a_timestamp_column() {
+        assert!(check_dataset_params(&params(&[("since", DatasetParamType::Date)]), &["TIMESTAMPTZ"]).is_ok());
+    }
+
+    #[test]
+    fn mismatched_param_type_is_rejected_with_its_name() {
+        // ... WHERE sku = $1 với sku là TEXT nhưng tham số khai báo là int.
+        let error = check_dataset_params(&params(&[("sku", DatasetParamType::Int)]), &["TEXT"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Dataset parameter $1 ('sku') is declared as int but the SQL uses it as TEXT; change its type or cast it in the SQL"
+        );
+
+        let declared = params(&[("since", DatasetParamType::Date), ("active", DatasetParamType::Text)]);
+        let error = check_dataset_params(&declared, &["DATE", "BOOL"]).unwrap_err();
+        assert!(error.to_string().starts_with("Dataset parameter $2 ('active') is declared as text"));
+    }
+
+    #[test]
+    fn parameter_count_mismatch_is_rejected() {
+        let error = check_dataset_params(&params(&[("since", DatasetParamType::Date)]), &["DATE", "INT4"]).unwrap_err();
+        assert_eq!(error.to_string(), "Dataset SQL uses 2 parameters but 1 are declared");
+        assert!(check_dataset_params(&params(&[("since", DatasetParamType::Date)]), &[]).is_err());
+    }
+
+    /// Test trên Postgres thật (`#[sqlx::test]` tạo database riêng cho mỗi test và chạy migration):
+    /// `DATABASE_URL=postgres://... cargo test -- --ignored`.
+    mod postgres {
+        use super::*;
+        use crate::clock::SystemClock;
+        use chrono::Duration as ChronoDuration;
+        use sqlx::PgPool;
+
+        fn store(pool: PgPool) -> PostgresDbStore {
+            PostgresDbStore::new(pool, None, Arc::new(SystemClock), PostgresStoreSettings::default())
+        }
+
+        fn paged_store(pool: PgPool, page_size: usize, page_snapshot: bool) -> PostgresDbStore {
+            let settings = PostgresStoreSettings { page_size: Some(page_size), page_snapshot, ..PostgresStoreSettings::default() };
+            PostgresDbStore::new(pool, None, Arc::new(SystemClock), settings)
+        }
+
+        /// Bảng products (dữ liệu nghiệp vụ, không thuộc migration của service) với các sản phẩm `(product_id, created_at)`.
+        async fn seed_products(pool: &PgPool, products: &[(i64, &str)]) {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS products (
+                    product_id BIGINT PRIMARY KEY, name TEXT NOT NULL, category TEXT NOT NULL,
+                    price FLOAT8 NOT NULL, stock_quantity INT4 NOT NULL, created_at TIMESTAMPTZ NOT NULL)",
+            )
+            .execute(pool)
+            .await
+            .unwrap();
+            for (product_id, created_at) in products {
+                sqlx::query(
+                    "INSERT INTO products VALUES ($1, 'Product ' || $1, 'Books', 10.0, 5, $2::timestamptz)",
+                )
+                .bind(product_id)
+                .bind(created_at)
+                .execute(pool)
+                .await
+                .unwrap();
+            }
+        }
+
+        fn january() -> ReportParams {
+            serde_json::from_value(serde_json::json!({"start_date": "2024-01-01", "end_date": "2024-01-31"})).unwrap()
+        }
+
+        async fn streamed_ids(db_store: &PostgresDbStore, params: &ReportParams) -> Vec<i64> {
+            db_store
+                .stream_product_data(params)
+                .map_ok(|row| row.product_id)
+                .try_collect()
+                .await
+                .unwrap()
+        }
+
+        /// Request ở trạng thái `status` từ `age` trước, thông báo đã gửi, không có file.
+        async fn seed(pool: &PgPool, status: ExportStatus, age: ChronoDuration) -> Uuid {
+            let id = Uuid::new_v4();
+            let updated_at = Utc::now() - age;
+            sqlx::query(
+                "INSERT INTO ExportRequests (id, user_id, request_payload, status, requested_at, status_updated_at, notification_sent)
+                 VALUES ($1, 42, '{}', $2, $3, $3, TRUE)",
+            )
+            .bind(id)
+            .bind(status.as_str())
+            .bind(updated_at)
+            .execute(pool)
+            .await
+            .unwrap();
+            id
+        }
+
+        async fn set(pool: &PgPool, id: Uuid, assignment: &str) {
+            sqlx::query(&format!("UPDATE ExportRequests SET {} WHERE id = $1", assignment))
+                .bind(id)
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+
+        async fn live_ids(pool: &PgPool) -> HashSet<Uuid> {
+            sqlx::query_scalar("SELECT id FROM ExportRequests").fetch_all(pool).await.unwrap().into_iter().collect()
+        }
+
+        async fn archived_ids(pool: &PgPool) -> HashSet<Uuid> {
+            sqlx::query_scalar("SELECT id FROM export_requests_archive").fetch_all(pool).await.unwrap().into_iter().collect()
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn archive_moves_every_batch_including_an_exact_multiple(pool: PgPool) {
+            let mut ids = HashSet::new();
+            for _ in 0..4 {
+                ids.insert(seed(&pool, ExportStatus::Failed, ChronoDuration::days(40)).await);
+            }
+            let db_store = store(pool.clone());
+            let before = Utc::now() - ChronoDuration::days(30);
+
+            assert_eq!(db_store.archive_requests(before, 2).await.unwrap(), 4);
+            assert_eq!(db_store.archive_requests(before, 2).await.unwrap(), 0);
+            assert!(live_ids(&pool).await.is_empty());
+            assert_eq!(archived_ids(&pool).await, ids);
+
+            ids.insert(seed(&pool, ExportStatus::Expired, ChronoDuration::days(40)).await);
+            assert_eq!(db_store.archive_requests(before, 2).await.unwrap(), 1);
+            assert_eq!(archived_ids(&pool).await, ids);
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn archive_keeps_unsent_recent_and_file_backed_requests(pool: PgPool) {
+            let old = ChronoDuration::days(40);
+            let failed = seed(&pool, ExportStatus::Failed, old).await;
+            let completed_without_file = seed(&pool, ExportStatus::Completed, old).await;
+            let completed_link_expired = seed(&pool, ExportStatus::Completed, old).await;
+            set(&pool, completed_link_expired, "file_path = '/exports/a.csv', expires_at = NOW() - INTERVAL '35 days'").await;
+            let completed_with_file = seed(&pool, ExportStatus::Completed, old).await;
+            set(&pool, completed_with_file, "file_path = '/exports/b.csv', expires_at = NOW() + INTERVAL '1 day'").await;
+            let failed_unsent = seed(&pool, ExportStatus::Failed, old).await;
+            set(&pool, failed_unsent, "notification_sent = FALSE").await;
+            let completed_unsent = seed(&pool, ExportStatus::Completed, old).await;
+            set(&pool, completed_unsent, "notification_sent = FALSE").await;
+            let recent = seed(&pool, ExportStatus::Failed, ChronoDuration::days(1)).await;
+            let processing = seed(&pool, ExportStatus::Processing, old).await;
+
+            let archived = store(pool.clone()).archive_requests(Utc::now() - ChronoDuration::days(30), 10).await.unwrap();
+
+            assert_eq!(archived, 3);
+            assert_eq!(archived_ids(&pool).await, HashSet::from([failed, completed_without_file, completed_link_expired]));
+            assert_eq!(
+                live_ids(&pool).await,
+                HashSet::from([completed_with_file, failed_unsent, completed_unsent, recent, processing])
+            );
+        }
+    
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn pages_return_every_row_once_across_shared_timestamps(pool: PgPool) {
+            // Nhiều sản phẩm trùng created_at, được chèn không theo thứ tự product_id.
+            seed_products(
+                &pool,
+                &[
+                    (7, "2024-01-10 08:00:00Z"),
+                    (3, "2024-01-10 08:00:00Z"),
+                    (5, "2024-01-10 08:00:00Z"),
+                    (10, "2024-01-11 09:00:00Z"),
+                    (1, "2024-01-11 09:00:00Z"),
+                    (8, "2024-01-11 09:00:00Z"),
+                    (2, "2024-01-11 09:00:00Z"),
+                    (4, "2024-01-05 00:00:00Z"),
+                    (9, "2024-01-20 12:30:00Z"),
+                    (6, "2024-01-31 23:59:59Z"),
+                    (11, "2024-02-01 00:00:00Z"),
+                ],
+            )
+            .await;
+            let expected = vec![4, 3, 5, 7, 1, 2, 8, 10, 9, 6];
+
+            for page_size in [1, 2, 3, 4, 5, 9, 10, 11, 100] {
+                let ids = streamed_ids(&paged_store(pool.clone(), page_size, false), &january()).await;
+                assert_eq!(ids, expected, "page size {}", page_size);
+            }
+            assert_eq!(streamed_ids(&store(pool.clone()), &january()).await, expected);
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn pages_of_an_empty_result_end_immediately(pool: PgPool) {
+            seed_products(&pool, &[(1, "2023-12-31 23:59:59Z")]).await;
+
+            assert!(streamed_ids(&paged_store(pool, 2, false), &january()).await.is_empty());
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn serialization_failure_is_transient(pool: PgPool) {
+            seed_products(&pool, &[(1, "2024-01-10 08:00:00Z")]).await;
+            let mut snapshot = pool.begin().await.unwrap();
+            sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ").execute(&mut *snapshot).await.unwrap();
+            sqlx::query("SELECT price FROM products").fetch_all(&mut *snapshot).await.unwrap();
+            sqlx::query("UPDATE products SET price = 11.0").execute(&pool).await.unwrap();
+
+            let error = sqlx::query("UPDATE products SET price = 12.0").execute(&mut *snapshot).await.unwrap_err();
+
+            assert_eq!(error.as_database_error().and_then(|e| e.code()).as_deref(), Some("40001"));
+            let error = DbError::from(anyhow::Error::new(error).context("Failed to fetch product data page from database"));
+            assert!(matches!(error, DbError::Transient(_)), "{:?}", error);
+        }
+
+        /// Đọc trang đầu, chèn thêm một sản phẩm thuộc các trang sau từ connection khác, rồi đọc nốt.
+        async fn ids_with_insert_between_pages(pool: &PgPool, page_snapshot: bool) -> Vec<i64> {
+            let db_store = paged_store(pool.clone(), 2, page_snapshot);
+            let params = january();
+            let mut rows = db_store.stream_product_data(&params);
+            let mut ids = vec![rows.next().await.unwrap().unwrap().product_id];
+            sqlx::query("INSERT INTO products VALUES (100, 'Late', 'Books', 10.0, 5, '2024-01-30 00:00:00Z')")
+                .execute(pool)
+                .await
+                .unwrap();
+            while let Some(row) = rows.next().await {
+                ids.push(row.unwrap().product_id);
+            }
+            ids
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn snapshot_pages_do_not_see_rows_inserted_mid_export(pool: PgPool) {
+            seed_products(&pool, &[(1, "2024-01-02 00:00:00Z"), (2, "2024-01-03 00:00:00Z"), (3, "2024-01-04 00:00:00Z")]).await;
+
+            assert_eq!(ids_with_insert_between_pages(&pool, true).await, vec![1, 2, 3]);
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn pages_without_snapshot_see_rows_inserted_mid_export(pool: PgPool) {
+            seed_products(&pool, &[(1, "2024-01-02 00:00:00Z"), (2, "2024-01-03 00:00:00Z"), (3, "2024-01-04 00:00:00Z")]).await;
+
+            assert_eq!(ids_with_insert_between_pages(&pool, false).await, vec![1, 2, 3, 100]);
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn concurrent_claims_never_return_the_same_request(pool: PgPool) {
+            let mut pending = HashSet::new();
+            for _ in 0..20 {
+                pending.insert(seed(&pool, ExportStatus::Pending, ChronoDuration::minutes(1)).await);
+            }
+            let later = seed(&pool, ExportStatus::Pending, ChronoDuration::minutes(1)).await;
+            set(&pool, later, "next_retry_at = NOW() + INTERVAL '1 hour'").await;
+            // Hai replica, mỗi replica một store trên connection riêng của pool.
+            let (first, second) = (store(pool.clone()), store(pool.clone()));
+
+            let (a, b) = tokio::join!(first.claim_next_pending(15), second.claim_next_pending(15));
+            let (a, b) = (a.unwrap(), b.unwrap());
+            let rest = first.claim_next_pending(15).await.unwrap();
+
+            let a: HashSet<Uuid> = a.iter().map(|request| request.id).collect();
+            let b: HashSet<Uuid> = b.iter().map(|request| request.id).collect();
+            assert!(a.is_disjoint(&b), "both replicas claimed {:?}", a.intersection(&b).collect::<Vec<_>>());
+            let claimed: HashSet<Uuid> = a.iter().chain(&b).copied().chain(rest.iter().map(|request| request.id)).collect();
+            assert_eq!(a.len() + b.len() + rest.len(), 20);
+            assert_eq!(claimed, pending);
+            let statuses: Vec<String> = sqlx::query_scalar("SELECT status FROM ExportRequests WHERE id = ANY($1)")
+                .bind(claimed.iter().copied().collect::<Vec<_>>())
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+            assert!(statuses.iter().all(|status| status == ExportStatus::Processing.as_str()));
+            let events: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM export_request_events WHERE detail = 'claimed by polling'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(events, 20);
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn only_aged_processing_requests_are_stuck_and_reset(pool: PgPool) {
+            let aged = seed(&pool, ExportStatus::Processing, ChronoDuration::hours(2)).await;
+            set(&pool, aged, "file_path = '/exports/partial.csv', error_message = 'interrupted', webhook_sent = TRUE").await;
+            let fresh = seed(&pool, ExportStatus::Processing, ChronoDuration::minutes(1)).await;
+            let aged_failed = seed(&pool, ExportStatus::Failed, ChronoDuration::hours(2)).await;
+            let db_store = store(pool.clone());
+
+            let stuck = db_store.fetch_stuck_processing(ChronoDuration::minutes(30), 10).await.unwrap();
+            assert_eq!(stuck.iter().map(|request| request.id).collect::<Vec<_>>(), vec![aged]);
+
+            // Request không còn PROCESSING không bị reset; tuổi của request do caller lọc qua `fetch_stuck_processing`.
+            let reset: HashSet<Uuid> = db_store.reset_to_pending(&[aged, fresh, aged_failed]).await.unwrap().into_iter().collect();
+            assert_eq!(reset, HashSet::from([aged, fresh]));
+            assert!(db_store.fetch_stuck_processing(ChronoDuration::zero(), 10).await.unwrap().is_empty());
+            let (status, file_path, error_message, webhook_sent): (String, Option<String>, Option<String>, bool) =
+                sqlx::query_as("SELECT status, file_path, error_message, webhook_sent FROM ExportRequests WHERE id = $1")
+                    .bind(aged)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            assert_eq!((status.as_str(), file_path, error_message, webhook_sent), ("PENDING", None, None, false));
+            assert!(db_store.reset_to_pending(&[aged]).await.unwrap().is_empty());
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn request_lock_held_by_another_replica_is_not_taken(pool: PgPool) {
+            let request_id = seed(&pool, ExportStatus::Processing, ChronoDuration::hours(2)).await;
+            let (worker, recovery) = (store(pool.clone()), store(pool.clone()));
+
+            assert!(worker.try_lock_request(request_id).await.unwrap());
+            assert!(!recovery.try_lock_request(request_id).await.unwrap());
+            worker.unlock_request(request_id).await.unwrap();
+            assert!(recovery.try_lock_request(request_id).await.unwrap());
+        }
+
+        async fn status_of(pool: &PgPool, id: Uuid) -> (String, Option<String>) {
+            sqlx::query_as("SELECT status, error_message FROM ExportRequests WHERE id = $1")
+                .bind(id)
+                .fetch_one(pool)
+                .await
+                .unwrap()
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn bulk_update_skips_unknown_unchanged_and_completed_requests(pool: PgPool) {
+            let failed = seed(&pool, ExportStatus::Failed, ChronoDuration::hours(1)).await;
+            let already_pending = seed(&pool, ExportStatus::Pending, ChronoDuration::hours(1)).await;
+            let completed = seed(&pool, ExportStatus::Completed, ChronoDuration::hours(1)).await;
+            let missing = Uuid::new_v4();
+            let db_store = store(pool.clone());
+
+            let updated = db_store
+                .bulk_update_status(&[failed, already_pending, completed, missing], ExportStatus::Pending, None, false)
+                .await
+                .unwrap();
+
+            assert_eq!(updated, 1);
+            assert_eq!(status_of(&pool, failed).await, ("PENDING".to_string(), None));
+            assert_eq!(status_of(&pool, completed).await.0, "COMPLETED");
+            let events: Vec<(Uuid, Option<String>, String)> = sqlx::query_as(
+                "SELECT request_id, from_status, to_status FROM export_request_events WHERE detail = 'bulk status update'",
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+            assert_eq!(events, vec![(failed, Some("FAILED".to_string()), "PENDING".to_string())]);
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn bulk_update_changes_completed_requests_only_with_force(pool: PgPool) {
+            let completed = seed(&pool, ExportStatus::Completed, ChronoDuration::hours(1)).await;
+            let processing = seed(&pool, ExportStatus::Processing, ChronoDuration::hours(1)).await;
+            let db_store = store(pool.clone());
+            let message = || Some("cancelled by operator".to_string());
+
+            assert_eq!(db_store.bulk_update_status(&[completed], ExportStatus::Failed, message(), false).await.unwrap(), 0);
+            assert_eq!(status_of(&pool, completed).await.0, "COMPLETED");
+
+            let updated = db_store.bulk_update_status(&[completed, processing], ExportStatus::Failed, message(), true).await.unwrap();
+            assert_eq!(updated, 2);
+            for id in [completed, processing] {
+                assert_eq!(status_of(&pool, id).await, ("FAILED".to_string(), message()));
+            }
+        }
+
+        /// Các lần chuyển trạng thái (from, to, detail) trong lịch sử của request.
+        async fn history(db_store: &PostgresDbStore, id: Uuid) -> Vec<(Option<String>, String, Option<String>)> {
+            let events = db_store.get_request_history(id).await.unwrap();
+            events.into_iter().map(|event| (event.from_status, event.to_status, event.detail)).collect()
+        }
+
+        #[sqlx::test(migrations = "./migrations")]
+        #[ignore = "needs a Postgres database (DATABASE_URL)"]
+        async fn status_changes_are_recorded_in_the_request_history(pool: PgPool) {
+            let succeeded = seed(&pool, ExportStatus::Pending, ChronoDuration::minutes(1)).await;
+            let failed = seed(&pool, ExportStatus::Pending, ChronoDuration::minutes(1)).await;
+            let db_store = store(pool.clone());
+            let finish = |id, status, error_code: Option<&str>| {
+                db_store.update_request_status(
+                    id,
+                    status,
+                    None,
+                    error_code.map(|_| "Export failed".to_string()),
+                    error_code.map(str::to_string),
+                    None,
+                    &ExportTimings::default(),
+                    &ExportCompletion::default(),
+                )
+            };
+
+            for id in [succeeded, failed] {
+                db_store.fetch_and_update_request_status(id, ExportStatus::Processing, &ExportQuota::default()).await.unwrap();
+            }
+            finish(succeeded, ExportStatus::Completed, None).await.unwrap();
+            finish(failed, ExportStatus::Failed, Some("EXPORT_FAILED")).await.unwrap();
+
+            let step = |from: &str, to: &str, detail: Option<&str>| {
+                (Some(from.to_string()), to.to_string(), detail.map(str::to_string))
+            };
+            assert_eq!(
+                history(&db_store, succeeded).await,
+                vec![step("PENDING", "PROCESSING", None), step("PROCESSING", "COMPLETED", None)]
+            );
+            assert_eq!(
+                history(&db_store, failed).await,
+                vec![step("PENDING", "PROCESSING", None), step("PROCESSING", "FAILED", Some("EXPORT_FAILED"))]
+            );
+            // Request đã ở trạng thái cuối không được chuyển lại nên không thêm sự kiện.
+            assert!(db_store.fetch_and_update_request_status(succeeded, ExportStatus::Processing, &ExportQuota::default()).await.is_err());
+            assert_eq!(history(&db_store, succeeded).await.len(), 2);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::models::ExportTimings;
+
+/// Thời gian của từng giai đoạn xử lý một export (cũng được dùng cho các histogram).
+/// Giai đoạn chưa chạy tới (do lỗi xảy ra trước đó) là `None`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PhaseDurations {
+    pub fetch: Option<Duration>,
+    pub query: Option<Duration>,
+    pub generation: Option<Duration>,
+    pub notify: Option<Duration>,
+}
+
+impl PhaseDurations {
+    pub fn to_timings(&self) -> ExportTimings {
+        let as_ms = |duration: Option<Duration>| duration.map(|d| d.as_millis() as i64);
+        ExportTimings {
+            db_fetch_ms: as_ms(self.fetch),
+            db_query_ms: as_ms(self.query),
+            file_generation_ms: as_ms(self.generation),
+            notify_ms: as_ms(self.notify),
+        }
+    }
+}
+
+/// Trung bình trượt thời gian xử lý export, chia bucket theo bậc độ lớn của số dòng
+/// (0, 1-9, 10-99, ...). Chỉ dùng để ước lượng ETA nên không cần chính xác tuyệt đối.
+pub struct DurationStats {
+    window: usize,
+    buckets: Mutex<HashMap<u32, VecDeque<u64>>>,
+}
+
+impl DurationStats {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, row_count: u64, duration_ms: u64) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let samples = buckets.entry(magnitude(row_count)).or_default();
+        samples.push_back(duration_ms);
+        while samples.len() > self.window {
+            samples.pop_front();
+        }
+    }
+
+    /// Ước lượng thời gian xử lý (ms). Khi chưa biết số dòng, hoặc bucket tương ứng chưa có mẫu,
+    /// dùng trung bình của tất cả các mẫu.
+    pub fn estimate_ms(&self, row_count: Option<u64>) -> Option<u64> {
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(samples) = row_count.and_then(|rows| buckets.get(&magnitude(rows))) {
+            if let Some(avg) = average(samples.iter()) {
+                return Some(avg);
+            }
+        }
+        average(buckets.values().flatten())
+    }
+}
+
+fn magnitude(row_count: u64) -> u32 {
+    row_count.checked_ilog10().map_or(0, |m| m + 1)
+}
+
+fn average<'a>(samples: impl Iterator<Item = &'a u64>) -> Option<u64> {
+    let (sum, count) = samples.fold((0u128, 0u128), |(sum, count), v| (sum + *v as u128, count + 1));
+    (count > 0).then(|| (sum / count) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_samples_means_no_estimate() {
+        let stats = DurationStats::new(10);
+
+        assert_eq!(stats.estimate_ms(None), None);
+        assert_eq!(stats.estimate_ms(Some(500)), None);
+    }
+
+    #[test]
+    fn estimate_uses_the_average_of_the_matching_magnitude() {
+        let stats = DurationStats::new(10);
+        stats.record(5, 100);
+        stats.record(9, 300);
+        stats.record(1_000, 10_000);
+        stats.record(9_999, 20_000);
+
+        assert_eq!(stats.estimate_ms(Some(1)), Some(200));
+        assert_eq!(stats.estimate_ms(Some(5_000)), Some(15_000));
+    }
+
+    #[test]
+    fn row_counts_are_bucketed_by_order_of_magnitude() {
+        assert_eq!(magnitude(0), 0);
+        assert_eq!(magnitude(1), 1);
+        assert_eq!(magnitude(9), 1);
+        assert_eq!(magnitude(10), 2);
+        assert_eq!(magnitude(99), 2);
+        assert_eq!(magnitude(100), 3);
+        assert_eq!(magnitude(u64::MAX), 20);
+    }
+
+    #[test]
+    fn unknown_or_unseen_row_count_falls_back_to_all_samples() {
+        let stats = DurationStats::new(10);
+        stats.record(5, 100);
+        stats.record(50, 500);
+
+        assert_eq!(stats.estimate_ms(None), Some(300));
+        assert_eq!(stats.estimate_ms(Some(1_000_000)), Some(300));
+    }
+
+    #[test]
+    fn rolling_window_keeps_only_the_most_recent_samples_per_bucket() {
+        let stats = DurationStats::new(3);
+        for duration_ms in [1_000, 2_000, 3_000, 4_000, 5_000] {
+            stats.record(20, duration_ms);
+        }
+        stats.record(2, 10);
+
+        // Bucket 10-99 chỉ còn 3 mẫu cuối; bucket khác không bị đẩy ra.
+        assert_eq!(stats.estimate_ms(Some(20)), Some(4_000));
+        assert_eq!(stats.estimate_ms(Some(2)), Some(10));
+        assert_eq!(stats.estimate_ms(None), Some((3_000 + 4_000 + 5_000 + 10) / 4));
+    }
+
+    #[test]
+    fn average_does_not_overflow_on_large_samples() {
+        let stats = DurationStats::new(10);
+        stats.record(1, u64::MAX);
+        stats.record(1, u64::MAX);
+
+        assert_eq!(stats.estimate_ms(Some(1)), Some(u64::MAX));
+    }
+
+    #[test]
+    fn phases_not_reached_stay_empty_in_timings() {
+        let phases = PhaseDurations {
+            fetch: Some(Duration::from_millis(12)),
+            query: Some(Duration::from_micros(1_500)),
+            generation: None,
+            notify: None,
+        };
+
+        let timings = phases.to_timings();
+
+        assert_eq!(timings.db_fetch_ms, Some(12));
+        assert_eq!(timings.db_query_ms, Some(1));
+        assert_eq!(timings.file_generation_ms, None);
+        assert_eq!(timings.notify_ms, None);
+    }
+}
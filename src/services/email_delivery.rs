@@ -0,0 +1,354 @@
+use anyhow::{Context, Result};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::path::Path;
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use crate::config::SmtpConfig;
+use crate::models::{Delivery, ExportFormat};
+use crate::services::encryption::{FileEncryption, ENCRYPTED_FILE_EXTENSION};
+use crate::services::pgp_encryption::PGP_FILE_EXTENSION;
+
+/// Trait định nghĩa giao diện gửi email, tách riêng để có thể thay bằng transport giả lập.
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync + 'static {
+    async fn send(&self, message: Message) -> Result<()>;
+}
+
+/// Implementation gửi email qua SMTP (STARTTLS).
+pub struct SmtpEmailTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpEmailTransport {
+    pub fn new(config: &SmtpConfig) -> Result<Self> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+            .context("Failed to create SMTP transport")?
+            .port(config.port);
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpEmailTransport {
+    async fn send(&self, message: Message) -> Result<()> {
+        self.transport
+            .send(message)
+            .await
+            .context("SMTP server rejected the message")?;
+        Ok(())
+    }
+}
+
+/// Gửi file export qua email: đính kèm nếu file nhỏ hơn giới hạn, ngược lại chỉ gửi link.
+pub struct EmailDelivery {
+    transport: Box<dyn EmailTransport>,
+    from: Mailbox,
+    max_attachment_bytes: u64,
+    subject_template: String,
+    body_template: String,
+    html_preview_rows: Option<usize>,
+    /// Giải mã file `.enc` (ENCRYPTION_KEY) trước khi đính kèm.
+    encryption: Option<FileEncryption>,
+}
+
+impl EmailDelivery {
+    pub fn new(transport: Box<dyn EmailTransport>, config: &SmtpConfig, encryption: Option<FileEncryption>) -> Result<Self> {
+        Ok(Self {
+            transport,
+            from: config.from.parse().context("SMTP_FROM is not a valid email address")?,
+            max_attachment_bytes: config.max_attachment_bytes,
+            subject_template: config.subject_template.clone(),
+            body_template: config.body_template.clone(),
+            html_preview_rows: config.html_preview_rows,
+            encryption,
+        })
+    }
+
+    /// Số dòng tối đa của bản xem trước HTML, `None` nếu không nhúng bản xem trước (EMAIL_HTML_PREVIEW_ROWS).
+    pub fn html_preview_rows(&self) -> Option<usize> {
+        self.html_preview_rows
+    }
+
+    /// `html_preview` (nếu có) được gửi làm phần HTML của email, song song với nội dung chữ thuần.
+    #[instrument(skip(self, to, error_message, html_preview), fields(recipient = %redact_email(to)))]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn deliver(
+        &self,
+        request_id: Uuid,
+        to: &str,
+        status: &str,
+        file_path: Option<&str>,
+        file_url: Option<&str>,
+        error_message: Option<&str>,
+        html_preview: Option<&str>,
+    ) -> Result<()> {
+        let recipient = parse_recipient(to)?;
+        let render = |template: &str| {
+            template
+                .replace("{request_id}", &request_id.to_string())
+                .replace("{status}", status)
+                .replace("{file_url}", file_url.unwrap_or_default())
+                .replace("{error_message}", error_message.unwrap_or_default())
+        };
+
+        let builder = Message::builder()
+            .from(self.from.clone())
+            .to(recipient)
+            .subject(render(&self.subject_template));
+        let text = render(&self.body_template);
+
+        let attachment = match file_path {
+            Some(path) => self.load_attachment(path).await?,
+            None => None,
+        };
+        let message = match attachment {
+            Some((file_name, content)) => {
+                let content_type = attachment_content_type(&file_name);
+                let body = match html_preview {
+                    Some(html) => MultiPart::mixed().multipart(MultiPart::alternative_plain_html(text, html.to_string())),
+                    None => MultiPart::mixed().singlepart(SinglePart::plain(text)),
+                };
+                builder.multipart(
+                    body.singlepart(
+                        Attachment::new(file_name)
+                            .body(content, ContentType::parse(content_type).context("Invalid attachment content type")?),
+                    ),
+                )
+            }
+            None => match html_preview {
+                Some(html) => builder.multipart(MultiPart::alternative_plain_html(text, html.to_string())),
+                None => builder.singlepart(SinglePart::plain(text)),
+            },
+        }
+        .context("Failed to build export email")?;
+
+        self.transport.send(message).await?;
+        info!("📧 Export email for request {} sent to {}.", request_id, redact_email(to));
+        Ok(())
+    }
+
+    /// Đọc file để đính kèm; trả về `None` nếu file vượt quá giới hạn (chỉ gửi link).
+    /// File mã hóa khi lưu được giải mã trong bộ nhớ và đính kèm với tên gốc (không có `.enc`).
+    async fn load_attachment(&self, file_path: &str) -> Result<Option<(String, Vec<u8>)>> {
+        let size = tokio::fs::metadata(file_path)
+            .await
+            .context("Failed to read export file metadata for email attachment")?
+            .len();
+        if size > self.max_attachment_bytes {
+            info!(
+                "Export file is {} bytes, above the {} byte attachment limit. Sending link only.",
+                size, self.max_attachment_bytes
+            );
+            return Ok(None);
+        }
+
+        let file_name = Path::new(file_path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("export.xlsx")
+            .to_string();
+        if let Some(file_name) = file_name.strip_suffix(&format!(".{}", ENCRYPTED_FILE_EXTENSION)) {
+            let encryption = self
+                .encryption
+                .clone()
+                .context("Export file is encrypted at rest, but ENCRYPTION_KEY is not set")?;
+            let path = file_path.to_string();
+            let content = tokio::task::spawn_blocking(move || {
+                let input = std::fs::File::open(&path).context("Failed to read export file for email attachment")?;
+                let mut content = Vec::new();
+                encryption.decrypt(std::io::BufReader::new(input), &mut content)?;
+                Ok::<_, anyhow::Error>(content)
+            })
+            .await
+            .context("Attachment decryption task panicked")??;
+            return Ok(Some((file_name.to_string(), content)));
+        }
+        let content = tokio::fs::read(file_path)
+            .await
+            .context("Failed to read export file for email attachment")?;
+        Ok(Some((file_name, content)))
+    }
+}
+
+/// Lấy cấu hình delivery trực tiếp từ request_payload (dùng cả khi không cần parse toàn bộ ReportParams).
+pub fn delivery_from_payload(payload: &serde_json::Value) -> Option<Delivery> {
+    payload
+        .get("delivery")
+        .and_then(|value| serde_json::from_value(value.clone()).ok())
+}
+
+pub fn parse_recipient(to: &str) -> Result<Mailbox> {
+    to.parse()
+        .with_context(|| format!("Invalid email recipient '{}'", redact_email(to)))
+}
+
+/// Che địa chỉ email trong log: `john.doe@example.com` -> `j***@example.com`.
+pub fn redact_email(address: &str) -> String {
+    match address.split_once('@') {
+        Some((local, domain)) => {
+            let first = local.chars().next().map(String::from).unwrap_or_default();
+            format!("{}***@{}", first, domain)
+        }
+        None => "***".to_string(),
+    }
+}
+
+/// Content type của file đính kèm theo phần mở rộng: file zip/gzip (export đã nén) hoặc định dạng export;
+/// file không rõ định dạng được coi là xlsx. File mã hóa khi lưu (`.enc`) lấy content type của file gốc;
+/// file mã hóa PGP (`.gpg`) chỉ người nhận giải mã được nên là `application/pgp-encrypted`.
+pub(crate) fn attachment_content_type(file_name: &str) -> &'static str {
+    let file_name = file_name
+        .strip_suffix(&format!(".{}", ENCRYPTED_FILE_EXTENSION))
+        .unwrap_or(file_name);
+    let extension = Path::new(file_name).extension().and_then(|extension| extension.to_str());
+    if extension.is_some_and(|extension| extension.eq_ignore_ascii_case("zip")) {
+        return "application/zip";
+    }
+    if extension.is_some_and(|extension| extension.eq_ignore_ascii_case("gz")) {
+        return "application/gzip";
+    }
+    if extension.is_some_and(|extension| extension.eq_ignore_ascii_case(PGP_FILE_EXTENSION)) {
+        return "application/pgp-encrypted";
+    }
+    extension
+        .and_then(ExportFormat::from_name)
+        .unwrap_or(ExportFormat::Xlsx)
+        .content_type()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Transport giả lập: giữ lại các message thay vì gửi qua SMTP.
+    #[derive(Clone, Default)]
+    struct MockTransport {
+        sent: Arc<Mutex<Vec<Message>>>,
+        fail: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl EmailTransport for MockTransport {
+        async fn send(&self, message: Message) -> Result<()> {
+            if self.fail {
+                anyhow::bail!("mock SMTP server unavailable");
+            }
+            self.sent.lock().unwrap().push(message);
+            Ok(())
+        }
+    }
+
+    fn smtp_config(max_attachment_bytes: u64) -> SmtpConfig {
+        SmtpConfig {
+            host: "smtp.example.com".to_string(),
+            port: 587,
+            username: None,
+            password: None,
+            from: "exports@example.com".to_string(),
+            max_attachment_bytes,
+            subject_template: "Export {request_id}: {status}".to_string(),
+            body_template: "Download: {file_url}".to_string(),
+            html_preview_rows: None,
+        }
+    }
+
+    fn delivery(transport: &MockTransport, max_attachment_bytes: u64) -> EmailDelivery {
+        EmailDelivery::new(Box::new(transport.clone()), &smtp_config(max_attachment_bytes), None).unwrap()
+    }
+
+    fn sent_messages(transport: &MockTransport) -> Vec<String> {
+        transport
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|message| String::from_utf8_lossy(&message.formatted()).into_owned())
+            .collect()
+    }
+
+    fn write_export_file(name: &str, size: usize) -> (std::path::PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("email-delivery-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, vec![b'x'; size]).unwrap();
+        let path_str = path.to_str().unwrap().to_string();
+        (dir, path_str)
+    }
+
+    #[tokio::test]
+    async fn small_file_is_attached() {
+        let transport = MockTransport::default();
+        let (dir, path) = write_export_file("report.csv", 16);
+        let request_id = Uuid::new_v4();
+
+        delivery(&transport, 1024)
+            .deliver(request_id, "john.doe@example.com", "COMPLETED", Some(&path), Some("https://files/report.csv"), None, None)
+            .await
+            .unwrap();
+
+        let sent = sent_messages(&transport);
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].contains("To: john.doe@example.com"));
+        assert!(sent[0].contains(&format!("Subject: Export {}: COMPLETED", request_id)));
+        assert!(sent[0].contains("Content-Disposition: attachment; filename=\"report.csv\""));
+        assert!(sent[0].contains("Content-Type: text/csv"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn large_file_is_sent_as_link_only() {
+        let transport = MockTransport::default();
+        let (dir, path) = write_export_file("report.xlsx", 2048);
+
+        delivery(&transport, 1024)
+            .deliver(Uuid::new_v4(), "jane@example.com", "COMPLETED", Some(&path), Some("https://files/report.xlsx"), None, None)
+            .await
+            .unwrap();
+
+        let sent = sent_messages(&transport);
+        assert_eq!(sent.len(), 1);
+        assert!(!sent[0].contains("Content-Disposition: attachment"));
+        assert!(sent[0].contains("Download: https://files/report.xlsx"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_recipient_is_rejected_without_sending() {
+        let transport = MockTransport::default();
+
+        let error = delivery(&transport, 1024)
+            .deliver(Uuid::new_v4(), "not-an-address", "FAILED", None, None, Some("boom"), None)
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains("Invalid email recipient '***'"));
+        assert!(sent_messages(&transport).is_empty());
+    }
+
+    #[tokio::test]
+    async fn transport_failure_is_returned() {
+        let transport = MockTransport { fail: true, ..Default::default() };
+
+        let result = delivery(&transport, 1024)
+            .deliver(Uuid::new_v4(), "jane@example.com", "FAILED", None, None, Some("boom"), None)
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn redacts_local_part_of_address() {
+        assert_eq!(redact_email("john.doe@example.com"), "j***@example.com");
+        assert_eq!(redact_email("no-at-sign"), "***");
+    }
+}
@@ -0,0 +1,284 @@
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::{OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::config::EncryptionConfig;
+
+/// Phần mở rộng thêm vào tên file đã mã hóa (`{request_id}.xlsx.enc`).
+pub const ENCRYPTED_FILE_EXTENSION: &str = "enc";
+
+/// Độ dài tối đa của key id (được ghi vào header của file, 1 byte độ dài).
+pub const MAX_KEY_ID_LEN: usize = 64;
+
+/// Mở đầu mọi file đã mã hóa, theo sau là version của định dạng.
+const MAGIC: &[u8; 6] = b"EXPENC";
+const FORMAT_VERSION: u8 = 1;
+
+/// Số byte dữ liệu gốc của một chunk; mỗi chunk mã hóa dài thêm `TAG_LEN` byte.
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+/// Nonce 12 byte của AES-GCM trừ 5 byte bộ đếm chunk và cờ chunk cuối của STREAM (BE32).
+const NONCE_PREFIX_LEN: usize = 7;
+
+/// Key id hợp lệ: 1 tới `MAX_KEY_ID_LEN` ký tự `A-Z`, `a-z`, `0-9`, `_` hoặc `-`.
+pub fn is_valid_key_id(key_id: &str) -> bool {
+    !key_id.is_empty()
+        && key_id.len() <= MAX_KEY_ID_LEN
+        && key_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+}
+
+/// Mã hóa file export khi lưu bằng AES-256-GCM theo từng chunk (cấu trúc STREAM của crate `aead`),
+/// nên file được mã hóa và giải mã mà không phải đọc hết vào bộ nhớ.
+///
+/// Định dạng file: `EXPENC`, version (1 byte), độ dài key id (1 byte), key id, nonce prefix (7 byte),
+/// rồi các chunk `CHUNK_SIZE` byte (chunk cuối ngắn hơn, có thể rỗng) kèm tag 16 byte.
+/// Header là associated data của mọi chunk: sửa header, sửa/đổi thứ tự chunk hoặc cắt cụt file đều làm giải mã thất bại.
+#[derive(Clone)]
+pub struct FileEncryption {
+    active_key_id: String,
+    keys: HashMap<String, Key<Aes256Gcm>>,
+}
+
+impl FileEncryption {
+    pub fn new(config: &EncryptionConfig) -> Self {
+        Self {
+            active_key_id: config.active_key_id.clone(),
+            keys: config
+                .keys
+                .iter()
+                .map(|(key_id, key)| (key_id.clone(), *Key::<Aes256Gcm>::from_slice(key)))
+                .collect(),
+        }
+    }
+
+    /// Key id dùng cho file mới, được lưu cùng request.
+    pub fn active_key_id(&self) -> &str {
+        &self.active_key_id
+    }
+
+    /// Mã hóa `source` vào `destination` bằng khóa đang dùng, trả về số byte dữ liệu gốc.
+    /// Hàm đồng bộ: gọi trong thread blocking.
+    pub fn encrypt_file(&self, source: &str, destination: &str) -> Result<u64> {
+        let input = std::fs::File::open(source).context("Failed to open file to encrypt")?;
+        let output = std::fs::File::create(destination).context("Failed to create encrypted file")?;
+        let mut writer = BufWriter::new(output);
+        let size = self.encrypt(BufReader::new(input), &mut writer)?;
+        let output = writer.into_inner().map_err(|e| e.into_error()).context("Failed to flush encrypted file")?;
+        output.sync_all().context("Failed to sync encrypted file")?;
+        Ok(size)
+    }
+
+    /// Giải mã `source` vào `destination`, trả về key id đã mã hóa file. Hàm đồng bộ: gọi trong thread blocking.
+    pub fn decrypt_file(&self, source: &str, destination: &str) -> Result<String> {
+        let input = std::fs::File::open(source).context("Failed to open encrypted file")?;
+        let output = std::fs::File::create(destination).context("Failed to create decrypted file")?;
+        let mut writer = BufWriter::new(output);
+        let key_id = self.decrypt(BufReader::new(input), &mut writer)?;
+        let output = writer.into_inner().map_err(|e| e.into_error()).context("Failed to flush decrypted file")?;
+        output.sync_all().context("Failed to sync decrypted file")?;
+        Ok(key_id)
+    }
+
+    pub fn encrypt(&self, mut reader: impl Read, mut writer: impl Write) -> Result<u64> {
+        let key = self
+            .keys
+            .get(&self.active_key_id)
+            .with_context(|| format!("Encryption key '{}' is not configured", self.active_key_id))?;
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        let mut header = Vec::with_capacity(MAGIC.len() + 2 + self.active_key_id.len() + NONCE_PREFIX_LEN);
+        header.extend_from_slice(MAGIC);
+        header.push(FORMAT_VERSION);
+        header.push(self.active_key_id.len() as u8);
+        header.extend_from_slice(self.active_key_id.as_bytes());
+        header.extend_from_slice(&nonce_prefix);
+        writer.write_all(&header).context("Failed to write encrypted file header")?;
+
+        let mut encryptor = EncryptorBE32::from_aead(Aes256Gcm::new(key), GenericArray::from_slice(&nonce_prefix));
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut size = 0;
+        loop {
+            let read = read_full(&mut reader, &mut buffer).context("Failed to read file to encrypt")?;
+            size += read as u64;
+            let payload = Payload { msg: &buffer[..read], aad: &header };
+            if read < CHUNK_SIZE {
+                let chunk = encryptor
+                    .encrypt_last(payload)
+                    .map_err(|_| anyhow::anyhow!("Failed to encrypt final chunk"))?;
+                writer.write_all(&chunk).context("Failed to write encrypted file")?;
+                writer.flush().context("Failed to write encrypted file")?;
+                return Ok(size);
+            }
+            let chunk = encryptor
+                .encrypt_next(payload)
+                .map_err(|_| anyhow::anyhow!("Failed to encrypt chunk"))?;
+            writer.write_all(&chunk).context("Failed to write encrypted file")?;
+        }
+    }
+
+    /// Giải mã và kiểm tra toàn bộ file; dữ liệu đã ghi vào `writer` trước khi gặp lỗi không được dùng.
+    pub fn decrypt(&self, mut reader: impl Read, mut writer: impl Write) -> Result<String> {
+        let mut fixed = [0u8; 8];
+        read_exact_or(&mut reader, &mut fixed, "File is not an encrypted export (too short)")?;
+        anyhow::ensure!(&fixed[..6] == MAGIC, "File is not an encrypted export");
+        anyhow::ensure!(fixed[6] == FORMAT_VERSION, "Unsupported encrypted file version {}", fixed[6]);
+        let mut key_id = vec![0u8; fixed[7] as usize];
+        read_exact_or(&mut reader, &mut key_id, "Encrypted file header is truncated")?;
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        read_exact_or(&mut reader, &mut nonce_prefix, "Encrypted file header is truncated")?;
+        let key_id = String::from_utf8(key_id).context("Encrypted file header has an invalid key id")?;
+        let key = self
+            .keys
+            .get(&key_id)
+            .with_context(|| format!("File was encrypted with key '{}', which is not configured", key_id))?;
+        let mut header = fixed.to_vec();
+        header.extend_from_slice(key_id.as_bytes());
+        header.extend_from_slice(&nonce_prefix);
+
+        let mut decryptor = DecryptorBE32::from_aead(Aes256Gcm::new(key), GenericArray::from_slice(&nonce_prefix));
+        let mut buffer = vec![0u8; CHUNK_SIZE + TAG_LEN];
+        loop {
+            let read = read_full(&mut reader, &mut buffer).context("Failed to read encrypted file")?;
+            anyhow::ensure!(read >= TAG_LEN, "Encrypted file is truncated");
+            let payload = Payload { msg: &buffer[..read], aad: &header };
+            if read < buffer.len() {
+                let chunk = decryptor
+                    .decrypt_last(payload)
+                    .map_err(|_| anyhow::anyhow!("Encrypted file failed authentication: it was modified or truncated"))?;
+                writer.write_all(&chunk).context("Failed to write decrypted data")?;
+                writer.flush().context("Failed to write decrypted data")?;
+                return Ok(key_id);
+            }
+            let chunk = decryptor
+                .decrypt_next(payload)
+                .map_err(|_| anyhow::anyhow!("Encrypted file failed authentication: it was modified or truncated"))?;
+            writer.write_all(&chunk).context("Failed to write decrypted data")?;
+        }
+    }
+}
+
+/// Đọc tới khi đầy `buffer` hoặc hết dữ liệu, trả về số byte đã đọc.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn read_exact_or(reader: &mut impl Read, buffer: &mut [u8], message: &'static str) -> Result<()> {
+    let read = read_full(reader, buffer).context("Failed to read encrypted file")?;
+    anyhow::ensure!(read == buffer.len(), message);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encryption(active_key_id: &str) -> FileEncryption {
+        FileEncryption::new(&EncryptionConfig {
+            active_key_id: active_key_id.to_string(),
+            keys: vec![("2024-01".to_string(), [1; 32]), ("2025-06".to_string(), [2; 32])],
+        })
+    }
+
+    fn encrypt(encryption: &FileEncryption, data: &[u8]) -> Vec<u8> {
+        let mut encrypted = Vec::new();
+        assert_eq!(encryption.encrypt(data, &mut encrypted).unwrap(), data.len() as u64);
+        encrypted
+    }
+
+    fn decrypt(encryption: &FileEncryption, encrypted: &[u8]) -> Result<(String, Vec<u8>)> {
+        let mut decrypted = Vec::new();
+        let key_id = encryption.decrypt(encrypted, &mut decrypted)?;
+        Ok((key_id, decrypted))
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trips_any_size_including_chunk_boundaries() {
+        let encryption = encryption("2025-06");
+        for len in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, 2 * CHUNK_SIZE + 123] {
+            let data = sample(len);
+            let encrypted = encrypt(&encryption, &data);
+
+            assert_eq!(decrypt(&encryption, &encrypted).unwrap(), ("2025-06".to_string(), data), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn files_stay_readable_after_key_rotation() {
+        let encrypted = encrypt(&encryption("2024-01"), b"old report");
+
+        assert_eq!(decrypt(&encryption("2025-06"), &encrypted).unwrap(), ("2024-01".to_string(), b"old report".to_vec()));
+    }
+
+    #[test]
+    fn same_data_never_encrypts_to_the_same_bytes() {
+        let encryption = encryption("2025-06");
+
+        assert_ne!(encrypt(&encryption, b"report"), encrypt(&encryption, b"report"));
+    }
+
+    #[test]
+    fn tampered_ciphertext_tag_or_header_is_rejected() {
+        let encryption = encryption("2025-06");
+        let encrypted = encrypt(&encryption, &sample(CHUNK_SIZE + 100));
+        let header_len = MAGIC.len() + 2 + "2025-06".len() + NONCE_PREFIX_LEN;
+        let flip = |index: usize| {
+            let mut tampered = encrypted.clone();
+            tampered[index] ^= 0x01;
+            tampered
+        };
+
+        for (what, tampered) in [
+            ("ciphertext", flip(header_len + 10)),
+            ("tag of the first chunk", flip(header_len + CHUNK_SIZE + 3)),
+            ("tag of the last chunk", flip(encrypted.len() - 1)),
+            ("nonce prefix", flip(header_len - 1)),
+        ] {
+            let error = decrypt(&encryption, &tampered).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Encrypted file failed authentication: it was modified or truncated",
+                "{}",
+                what
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_or_foreign_files_are_rejected() {
+        let encryption = encryption("2025-06");
+        let encrypted = encrypt(&encryption, &sample(CHUNK_SIZE + 100));
+        let header_len = MAGIC.len() + 2 + "2025-06".len() + NONCE_PREFIX_LEN;
+
+        // Bỏ chunk cuối: chunk đầu không được đánh dấu là chunk cuối nên không qua được kiểm tra.
+        assert!(decrypt(&encryption, &encrypted[..header_len + CHUNK_SIZE + TAG_LEN]).is_err());
+        assert!(decrypt(&encryption, &encrypted[..header_len + 5]).is_err());
+        assert_eq!(decrypt(&encryption, b"PK\x03\x04 a zip file").unwrap_err().to_string(), "File is not an encrypted export");
+
+        let unknown_key = FileEncryption::new(&EncryptionConfig {
+            active_key_id: "2023-01".to_string(),
+            keys: vec![("2023-01".to_string(), [3; 32])],
+        });
+        assert_eq!(
+            decrypt(&unknown_key, &encrypted).unwrap_err().to_string(),
+            "File was encrypted with key '2025-06', which is not configured"
+        );
+    }
+}
@@ -1,16 +1,47 @@
 use anyhow::{Context, Result};
-use chrono::{NaiveTime, Utc};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::{StreamExt, TryStreamExt};
 use metrics::{gauge, histogram, increment};
+use std::fmt;
 use std::path::Path;
 use std::sync::Arc;
-use std::time::Instant;
-use tracing::{error, info, instrument, Span};
+use std::time::Duration;
+use tokio::sync::{OnceCell, Semaphore};
+use tracing::{debug, error, info, instrument, warn, Span};
 use uuid::Uuid;
 
-use crate::models::{ExportRequest, ExportStatus, ReportParams};
-use crate::services::db_store::DbStore;
-use crate::services::file_exporter::FileExporter;
+use crate::clock::Clock;
+use crate::config::{AppConfig, ReportTypeSettings, OTHER_REPORT_TYPE_LABEL};
+use crate::error::{ErrorSanitizer, ExportError};
+use crate::models::{
+    CsvOptions, Delivery, ExportCompletion, ExportEncryption, ExportFormat, ExportNotification, ExportProtection, ExportRequest, ExportStatus,
+    ExportTimings, FormulaEscape, HighlightOptions, NotificationStage, OutputCompression, ProductData, ReportData, ReportParams,
+    SplitFileDelivery, UnsafeCellValue, CATEGORY_SUMMARY_REPORT_TYPE, CUSTOMERS_REPORT_TYPE, DATASET_REPORT_TYPE, DEFAULT_REPORT_TYPE, ORDERS_REPORT_TYPE,
+};
+use crate::services::concurrency::UserConcurrencyLimiter;
+use crate::services::db_store::{DbError, DbStore};
+use crate::services::duration_stats::{DurationStats, PhaseDurations};
+use crate::services::email_delivery::{attachment_content_type, parse_recipient, EmailDelivery};
+use crate::services::encryption::FileEncryption;
+use crate::services::pgp_encryption::PgpEncryption;
+use crate::services::file_exporter::{
+    file_extension, render_file_stem, CellAdjustments, CompressedFile, CsvEncodingError, ExcelTemplate, ExportMetadata,
+    FileChecksum, FileExporter, FileSizeLimitError, PdfReport, TemplateError,
+};
+use crate::services::hooks::{run_after_export, run_before_export, ExportHook, ExportResult, PII_MASKING_HOOK};
+use crate::services::html_exporter::HtmlExporter;
 use crate::services::notifier::Notifier;
+use crate::services::safe_path::{check_component, ensure_under_root, join_under_root, UnsafePath};
+use crate::services::sftp_delivery::{validate_remote_dir, SftpDelivery};
+
+/// Số mẫu thời gian giữ lại cho mỗi bucket khi ước lượng ETA.
+const DURATION_STATS_WINDOW: usize = 50;
+/// Số request đã hoàn thành được đọc từ DB để khởi tạo thống kê ETA sau khi khởi động.
+const DURATION_STATS_SEED_LIMIT: i64 = 500;
+/// Số lần đọc report sản phẩm khi gặp lỗi tạm thời giữa chừng (xem `read_product_rows`).
+const PRODUCT_READ_ATTEMPTS: u32 = 3;
+/// Tên và version của service, ghi vào metadata của file export.
+const SERVICE_VERSION: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
 
 /// ExportService đóng gói toàn bộ logic xử lý một yêu cầu xuất Excel.
 /// Nó nhận các dependency của nó (DbStore, FileExporter, Notifier) thông qua trait objects.
@@ -23,8 +54,20 @@ where
     db_store: Arc<D>,
     file_exporter: Arc<F>,
     notifier: Arc<N>,
-    excel_export_path: String,
-    notification_service_base_url: String, // Base URL để xây dựng public file URL
+    email_delivery: Option<Arc<EmailDelivery>>,
+    sftp_delivery: Option<SftpDelivery>,
+    /// Mã hóa file khi lưu, chỉ có khi ENCRYPTION_KEY được set.
+    encryption: Option<FileEncryption>,
+    /// Khóa PGP của người nhận (PGP_RECIPIENT_KEYS), chỉ có khi được cấu hình.
+    pgp_encryption: Option<PgpEncryption>,
+    config: Arc<AppConfig>,
+    clock: Arc<dyn Clock>,
+    hooks: Vec<Arc<dyn ExportHook>>,
+    global_limiter: Semaphore,
+    user_limiter: UserConcurrencyLimiter,
+    duration_stats: DurationStats,
+    duration_stats_seeded: OnceCell<()>,
+    error_sanitizer: ErrorSanitizer,
 }
 
 impl<D, F, N> ExportService<D, F, N>
@@ -37,15 +80,28 @@ where
         db_store: Arc<D>,
         file_exporter: Arc<F>,
         notifier: Arc<N>,
-        excel_export_path: String,
-        notification_service_base_url: String,
+        email_delivery: Option<Arc<EmailDelivery>>,
+        pgp_encryption: Option<PgpEncryption>,
+        config: Arc<AppConfig>,
+        clock: Arc<dyn Clock>,
+        hooks: Vec<Arc<dyn ExportHook>>,
     ) -> Self {
         Self {
             db_store,
             file_exporter,
             notifier,
-            excel_export_path,
-            notification_service_base_url,
+            email_delivery,
+            sftp_delivery: config.sftp.clone().map(SftpDelivery::new),
+            encryption: config.encryption.as_ref().map(FileEncryption::new),
+            pgp_encryption,
+            global_limiter: Semaphore::new(config.max_concurrent_exports),
+            user_limiter: UserConcurrencyLimiter::new(config.max_concurrent_exports_per_user),
+            duration_stats: DurationStats::new(DURATION_STATS_WINDOW),
+            duration_stats_seeded: OnceCell::new(),
+            error_sanitizer: ErrorSanitizer::new(config.error_secret_pattern.clone(), config.error_message_max_length),
+            config,
+            clock,
+            hooks,
         }
     }
 
@@ -61,94 +117,971 @@ where
         request_id: Uuid,
         current_span: Span, // Lấy span hiện tại để ghi thêm field
     ) -> Result<()> {
-        let start_time = Instant::now(); // Bắt đầu đo tổng thời gian xử lý request
+        let start_time = self.clock.now_instant(); // Bắt đầu đo tổng thời gian xử lý request
+        let started_at = self.clock.now_utc();
         gauge!("excel_export_requests_in_progress", 1.0, "request_id" => request_id.to_string()); // Tăng gauge
 
         let mut final_status = ExportStatus::Failed;
         let mut file_path: Option<String> = None;
         let mut error_message: Option<String> = None;
+        let mut delivery: Option<Delivery> = None;
+        let mut email_preview: Option<String> = None;
+        let mut expires_at: Option<DateTime<Utc>> = None;
+        let mut phases = PhaseDurations::default();
+        let mut row_count: Option<usize> = None;
+        // Ô bị sửa cho vừa giới hạn của Excel, ghi vào log khi request hoàn tất.
+        let mut adjusted_cells = CellAdjustments::default();
+        // Content type của file tải về (zip khi đã nén hoặc mã hóa), gửi kèm thông báo.
+        let mut content_type: Option<String> = None;
+        let mut truncated_by_limit: Option<bool> = None;
+        let mut completion = ExportCompletion::default();
+        let mut report_type_label = OTHER_REPORT_TYPE_LABEL.to_string();
+        let mut output_dir: Option<String> = None;
+        // File đã được đổi tên theo FILENAME_TEMPLATE: `remove_partial_output` chỉ biết tên `{request_id}.*`.
+        let mut named_file: Option<String> = None;
+        // Các phần từ phần 2 của file bị chia theo MAX_FILE_SIZE_BYTES, ở nơi lưu trữ cuối.
+        let mut file_parts: Vec<String> = Vec::new();
+        let mut tenant: Option<String> = None;
+        let mut skip_notification = false;
+
+        // 1. Fetch request and update status to PROCESSING
+        // Request không tồn tại hoặc đã ở trạng thái cuối thì không có gì để cập nhật hay thông báo.
+        let fetch_start_time = self.clock.now_instant();
+        let fetch_result = match self.db_store
+            .fetch_and_update_request_status(request_id, ExportStatus::Processing, &self.config.export_quota)
+            .await
+        {
+            Err(DbError::NotFound(_)) => {
+                warn!("Export request {} not found in DB. Skipping.", request_id);
+                gauge!("excel_export_requests_in_progress", -1.0, "request_id" => request_id.to_string());
+                increment!("excel_export_skipped_total", "reason" => "not_found");
+                return Ok(());
+            }
+            Err(DbError::AlreadyFinal(status)) => {
+                info!("Export request {} is already {}. Skipping.", request_id, status);
+                gauge!("excel_export_requests_in_progress", -1.0, "request_id" => request_id.to_string());
+                increment!("excel_export_skipped_total", "reason" => "already_final");
+                return Ok(());
+            }
+            result => result,
+        };
 
         // Use a dedicated block to capture processing results and ensure
         // cleanup (gauge decrement) and Kafka commit happen reliably.
-        let processing_result = async {
-            // 1. Fetch request and update status to PROCESSING
-            let fetch_start_time = Instant::now();
-            let export_request: ExportRequest = self.db_store
-                .fetch_and_update_request_status(request_id, ExportStatus::Processing)
-                .await
-                .context("Failed to fetch or update request status to PROCESSING")?;
-            histogram!("excel_export_db_fetch_duration_seconds", fetch_start_time.elapsed().as_secs_f64());
+        let processing_result: Result<(), ExportError> = async {
+            let export_request: ExportRequest = fetch_result.map_err(ExportError::from)?;
+            self.record_attempt(request_id, None).await;
+            let fetch_duration = self.clock.elapsed(fetch_start_time);
+            phases.fetch = Some(fetch_duration);
+            // Tên cũ, giữ thêm một release: thay bằng excel_export_db_operation_duration_seconds của MeteredDbStore.
+            histogram!("excel_export_db_fetch_duration_seconds", fetch_duration.as_secs_f64());
             
             // Record user_id on the current span
             current_span.record("user_id", export_request.user_id);
             info!("✅ Request fetched and status updated to PROCESSING for user_id: {}.", export_request.user_id);
 
-            // 2. Parse RequestPayload and query data
-            let parse_and_query_start_time = Instant::now();
-            let params: ReportParams = serde_json::from_value(export_request.request_payload)
-                .context("Failed to parse request_payload JSON")?;
+            // 2. Parse RequestPayload
+            let params: ReportParams = serde_json::from_value(export_request.request_payload.clone())
+                .map_err(|e| ExportError::InvalidParams(anyhow::anyhow!("request_payload is not valid: {}", e)))?;
+            params.validate().map_err(ExportError::InvalidParams)?;
             info!("🔍 Report parameters parsed: {:?}", params);
+            report_type_label = self.config.report_type_label(&params.report_type);
+            self.config
+                .check_tenant(params.tenant.as_deref())
+                .map_err(ExportError::InvalidParams)?;
+            tenant = params.tenant.clone();
+            skip_notification = params.skip_notification;
 
-            let raw_data = self.db_store.query_product_data(&params).await
-                .context("Failed to query product data")?;
-            histogram!("excel_export_db_query_duration_seconds", parse_and_query_start_time.elapsed().as_secs_f64());
+            // Report chỉ cho xem dữ liệu của chính mình: chủ sở hữu luôn lấy từ ExportRequest, không từ payload.
+            let owner_user_id = self.config.owner_filter(&params.report_type, export_request.user_id);
+            if let (Some(owner), Some(requested)) = (owner_user_id, params.order_user_id) {
+                if requested != owner {
+                    warn!(
+                        "Ignoring order_user_id {} in request {}: report '{}' only exports the requesting user's records.",
+                        requested, request_id, params.report_type
+                    );
+                    increment!("excel_export_owner_override_ignored_total", "report_type" => report_type_label.clone());
+                }
+            }
 
-            // 3. Generate Excel file
-            let excel_gen_start_time = Instant::now();
-            let exported_file_path = self.file_exporter.export_to_excel(
-                request_id,
-                raw_data,
-                &self.excel_export_path,
-            ).await.context("Failed to export data to Excel")?;
-            histogram!("excel_export_excel_generation_duration_seconds", excel_gen_start_time.elapsed().as_secs_f64());
-            
-            file_path = Some(exported_file_path);
+            let (report_settings, configured) = self.config.report_settings(&params.report_type);
+            if !configured {
+                warn!(
+                    "Report type '{}' has no dedicated settings. Falling back to default limits.",
+                    params.report_type
+                );
+            }
+            let format = params.format.unwrap_or_else(|| {
+                ExportFormat::from_name(&report_settings.default_format).unwrap_or_else(|| {
+                    warn!(
+                        "Report type '{}' is configured with unsupported format '{}'. Exporting as xlsx.",
+                        params.report_type, report_settings.default_format
+                    );
+                    ExportFormat::Xlsx
+                })
+            });
+            if !format.is_available() {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "format '{}' is not supported by this build (built without the `{}` feature); available formats: {}",
+                    format.extension(),
+                    format.required_feature().unwrap_or_default(),
+                    ExportFormat::available_names().join(", ")
+                )));
+            }
+            // `default_format` của loại report chi tiết có thể là `pdf` dù payload không ghi format.
+            if format == ExportFormat::Pdf && params.report_type != CATEGORY_SUMMARY_REPORT_TYPE {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "format 'pdf' is only supported for the '{}' report",
+                    CATEGORY_SUMMARY_REPORT_TYPE
+                )));
+            }
+            if params.include_totals && (format != ExportFormat::Xlsx || report_settings.template_path.is_some()) {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "include_totals is only supported for xlsx exports without an Excel template"
+                )));
+            }
+            if params.include_chart && (format != ExportFormat::Xlsx || report_settings.template_path.is_some()) {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "include_chart is only supported for xlsx exports without an Excel template"
+                )));
+            }
+            if let Some(grouping) = params.group_by_sheet {
+                if format != ExportFormat::Xlsx || report_settings.template_path.is_some() {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "group_by_sheet is only supported for xlsx exports without an Excel template"
+                    )));
+                }
+                if params.report_type != DEFAULT_REPORT_TYPE {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "group_by_sheet is only supported for the '{}' report",
+                        DEFAULT_REPORT_TYPE
+                    )));
+                }
+                if !params.selected_columns().contains(&grouping.column()) {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "group_by_sheet requires the '{}' column in columns",
+                        grouping.column().sql()
+                    )));
+                }
+                if params.include_totals || params.include_chart {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "group_by_sheet cannot be combined with include_totals or include_chart"
+                    )));
+                }
+            }
+            if params.highlight.is_some() {
+                if format != ExportFormat::Xlsx || report_settings.template_path.is_some() {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "highlight is only supported for xlsx exports without an Excel template"
+                    )));
+                }
+                if params.report_type != DEFAULT_REPORT_TYPE {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "highlight is only supported for the '{}' report",
+                        DEFAULT_REPORT_TYPE
+                    )));
+                }
+                if !params.selected_columns().contains(&HighlightOptions::COLUMN) {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "highlight requires the '{}' column in columns",
+                        HighlightOptions::COLUMN.sql()
+                    )));
+                }
+            }
+            if params.title.is_some() && (format != ExportFormat::Xlsx || report_settings.template_path.is_some()) {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "title is only supported for xlsx exports without an Excel template"
+                )));
+            }
+            if params.max_file_size_bytes.is_some()
+                && (!SplitFileDelivery::supports(format) || report_settings.template_path.is_some())
+            {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "max_file_size_bytes is only supported for csv, jsonl and xlsx exports without an Excel template"
+                )));
+            }
+            let max_file_size = self.max_file_size(&params, format, report_settings.template_path.is_some());
+            let csv_overrides = params.format_options.as_ref().and_then(|options| options.csv.as_ref());
+            if csv_overrides.is_some() && format != ExportFormat::Csv {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "format_options.csv is only supported for csv exports"
+                )));
+            }
+            if params.compression.is_some() && !OutputCompression::supports(format) {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "compression is only supported for csv and jsonl exports"
+                )));
+            }
+            let compression = params.compression.unwrap_or_else(|| self.config.default_compression(format));
+            let csv_options = self.config.csv.with_overrides(csv_overrides);
+            csv_options
+                .validate()
+                .map_err(|e| ExportError::InvalidParams(e.context("Invalid format_options.csv")))?;
+            let mut excel_style = self.config.excel_style.with_overrides(params.excel_style.as_ref());
+            excel_style.totals_row = params.include_totals;
+            excel_style.chart = params.include_chart;
+            excel_style.group_by_sheet = params.group_by_sheet;
+            excel_style.highlight = params.highlight;
+            if excel_style.title_block || params.title.is_some() {
+                excel_style.title = Some(params.report_title(self.clock.now_utc()));
+            }
+
+            let estimated_completion_at = self.estimate_completion(started_at, None).await;
+            if let Some(eta) = estimated_completion_at {
+                self.record_estimated_completion(request_id, eta).await;
+            }
+
+            if self.should_notify_processing(&params) {
+                self.send_processing_notification(request_id, estimated_completion_at).await;
+            }
+
+            // Kiểm tra người nhận email sớm để request lỗi trước khi tốn công query dữ liệu.
+            delivery = params.delivery.clone();
+            match &delivery {
+                Some(Delivery::Email { to }) => {
+                    parse_recipient(to).map_err(ExportError::InvalidParams)?;
+                }
+                Some(Delivery::Sftp { remote_dir }) => {
+                    if !cfg!(feature = "sftp") {
+                        return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                            "SFTP delivery is not supported by this build: the service was built without the `sftp` feature"
+                        )));
+                    }
+                    if self.sftp_delivery.is_none() {
+                        return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                            "SFTP delivery is not configured: SFTP_HOST is not set"
+                        )));
+                    }
+                    if let Some(remote_dir) = remote_dir {
+                        validate_remote_dir(remote_dir).map_err(ExportError::UnsafePath)?;
+                    }
+                }
+                None => {}
+            }
+            // Người nhận PGP phải được cấu hình; kiểm tra trước khi query dữ liệu.
+            if let Some(ExportEncryption::Pgp { recipient_key_id }) = &params.encryption {
+                if !cfg!(feature = "pgp") {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "PGP encryption is not supported by this build: the service was built without the `pgp` feature"
+                    )));
+                }
+                if self.pgp_encryption.as_ref().and_then(|pgp| pgp.fingerprint(recipient_key_id)).is_none() {
+                    return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                        "unknown PGP recipient key id '{}'",
+                        recipient_key_id
+                    )));
+                }
+            }
+
+            // Request được xử lý lại sau khi link đã hết hạn thì không tạo lại file.
+            let link_ttl = self.link_ttl(&params);
+            let deadline = export_request.expires_at.unwrap_or(export_request.requested_at + link_ttl);
+            if self.clock.now_utc() >= deadline {
+                return Err(ExportError::Expired(ExportExpired { expired_at: deadline }));
+            }
+
+            // Request bị gửi lại sau khi file đã được tạo: dùng lại file nếu checksum vẫn khớp.
+            let reuse_files = can_reuse_existing_file(delivery.as_ref(), max_file_size);
+            let reusable_file = if reuse_files { self.find_reusable_file(&export_request).await } else { None };
+            if let Some(existing_path) = reusable_file {
+                info!("♻️ Reusing previously generated file {} for request {}.", existing_path, request_id);
+                increment!("excel_export_file_reused_total");
+                completion.truncated_by_limit = export_request.truncated_by_limit;
+                completion.original_file_name = export_request.original_file_name.clone();
+                completion.original_size_bytes = export_request.original_size_bytes;
+                completion.is_protected = export_request.is_protected;
+                completion.file_name = export_request.file_name.clone();
+                completion.is_encrypted = export_request.is_encrypted;
+                completion.encryption_key_id = export_request.encryption_key_id.clone();
+                completion.pgp_key_fingerprint = export_request.pgp_key_fingerprint.clone();
+                content_type = Some(attachment_content_type(&existing_path).to_string());
+                file_path = Some(existing_path);
+                expires_at = Some(self.clock.now_utc() + link_ttl);
+                final_status = ExportStatus::Completed;
+                return Ok(());
+            }
+
+            // Request trùng lặp (ví dụ user bấm export hai lần): dùng lại file của request đã hoàn thành.
+            let params_hash = params.content_hash(export_request.user_id);
+            self.db_store.record_params_hash(request_id, &params_hash).await?;
+            // File được bảo vệ dùng mật khẩu riêng của request nên không bao giờ được dùng chung.
+            let duplicate = match (&params.protection, reuse_files) {
+                (None, true) => self.find_duplicate_file(request_id, &params_hash).await,
+                _ => None,
+            };
+            if let Some((duplicate_path, duplicate_expires_at)) = duplicate {
+                content_type = Some(attachment_content_type(&duplicate_path).to_string());
+                file_path = Some(duplicate_path);
+                // Không để link sống lâu hơn file gốc, vì retention sẽ xóa file khi request gốc hết hạn.
+                let own_expiry = self.clock.now_utc() + link_ttl;
+                expires_at = Some(duplicate_expires_at.map_or(own_expiry, |d| d.min(own_expiry)));
+                final_status = ExportStatus::Completed;
+                return Ok(());
+            }
+
+            // Không bao giờ xuất dữ liệu chưa được che khi người dùng yêu cầu ẩn danh.
+            if params.anonymize && !self.hooks.iter().any(|hook| hook.name() == PII_MASKING_HOOK) {
+                return Err(ExportError::InvalidParams(anyhow::anyhow!(
+                    "anonymize was requested but the '{}' export hook is not enabled",
+                    PII_MASKING_HOOK
+                )));
+            }
+
+            run_before_export(&self.hooks, &export_request, &params)
+                .await
+                .map_err(ExportError::HookFailed)?;
+            // Lấy mật khẩu trước khi query để request thiếu mật khẩu thất bại ngay.
+            let password = match &params.protection {
+                Some(protection) => Some(self.export_password(&export_request, &params, protection).await?),
+                None => None,
+            };
+
+            // Giới hạn theo user trước, rồi mới lấy permit toàn cục: request đang chờ slot của user
+            // không được chiếm permit toàn cục, nếu không một user vẫn có thể chặn tất cả.
+            let _user_permit = self.user_limiter.acquire(export_request.user_id).await?;
+            let _global_permit = self.global_limiter.acquire().await
+                .context("Global export concurrency semaphore closed")?;
+
+            // 3 + 4. Query data và tạo file, giới hạn bởi timeout của loại report
+            let timeout_secs = report_settings.timeout_secs;
+            let max_rows = report_settings.max_rows;
+            // Mỗi tenant có thư mục riêng để tránh trùng file giữa các tenant.
+            // Thư mục theo ngày tạo file (EXPORT_DIR_DATE_PATTERN), để một thư mục không chứa quá nhiều file.
+            // Mọi phần đều được kiểm tra và thư mục cuối phải nằm trong EXCEL_EXPORT_PATH trước khi ghi file.
+            let export_root = Path::new(&self.config.excel_export_path);
+            let date_dir = (!self.config.export_dir_date_pattern.is_empty())
+                .then(|| self.clock.now_utc().format(&self.config.export_dir_date_pattern).to_string());
+            let export_dir = join_under_root(
+                export_root,
+                params.tenant.as_deref().into_iter()
+                    .chain(report_settings.output_subdir.as_deref())
+                    .chain(date_dir.as_deref()),
+            )
+            .map_err(ExportError::UnsafePath)?;
+            ensure_under_root(export_root, &export_dir).await.map_err(ExportError::UnsafePath)?;
+            let export_path = export_dir.to_string_lossy().into_owned();
+            output_dir = Some(export_path.clone());
+            let exported_file = tokio::time::timeout(Duration::from_secs(timeout_secs), async {
+                // Đếm trước số dòng của report sản phẩm: vượt giới hạn thì dừng trước khi chạy query chính,
+                // và ETA được tính theo số dòng thật ngay từ đầu.
+                if params.is_product_report() {
+                    let count_start_time = self.clock.now_instant();
+                    let count = self.db_store.count_product_data(&params).await
+                        .map_err(|e| map_query_error(e, "Failed to count product data"))?;
+                    // Trùng với operation="count_product_data" của MeteredDbStore; sẽ bỏ ở release sau.
+                    histogram!(
+                        "excel_export_db_count_duration_seconds",
+                        self.clock.elapsed(count_start_time).as_secs_f64(),
+                        "report_type" => report_type_label.clone()
+                    );
+                    // `limit`/`offset` của payload thu hẹp kết quả trước khi so với giới hạn chung.
+                    let rows = params.rows_in_window(count);
+                    if rows > max_rows as i64 {
+                        return Err(ExportError::RowLimitExceeded { limit: max_rows });
+                    }
+                    if params.limit.is_some() {
+                        let truncated = params.truncated_by_limit(count);
+                        if truncated {
+                            info!(
+                                "✂️ Request {} exports {} of {} matching rows because of its explicit limit.",
+                                request_id, rows, count
+                            );
+                            increment!("excel_export_truncated_by_limit_total", "report_type" => report_type_label.clone());
+                        }
+                        truncated_by_limit = Some(truncated);
+                    }
+                    row_count = Some(rows as usize);
+                    if let Some(eta) = self.estimate_completion(started_at, row_count).await {
+                        self.record_estimated_completion(request_id, eta).await;
+                    }
+                }
+
+                // CSV của report sản phẩm được Postgres tạo trực tiếp bằng COPY, không qua struct Rust.
+                // COPY ghi vào một file duy nhất nên không dùng khi file có thể phải chia theo MAX_FILE_SIZE_BYTES.
+                if format == ExportFormat::Csv && max_file_size.is_none() && self.can_copy_csv(&params, &csv_options) {
+                    let copy_start_time = self.clock.now_instant();
+                    let streamed_output = if self.can_stream_upload(&params, report_settings, password.as_deref(), delivery.as_ref()) {
+                        self.file_exporter.create_streamed_csv_file(request_id, &csv_options, compression, &export_path).await
+                            .map_err(|e| ExportError::FileWriteFailed(e.context("Failed to start CSV upload")))?
+                    } else {
+                        None
+                    };
+                    let mut output = match streamed_output {
+                        Some(output) => output,
+                        None => self.file_exporter.create_csv_file(request_id, &csv_options, compression, &export_path).await
+                            .map_err(|e| ExportError::FileWriteFailed(e.context("Failed to create CSV file")))?,
+                    };
+                    let rows = self.db_store.copy_product_data_csv(&params, max_rows, output.writer()).await
+                        .map_err(|e| map_query_error(e, "Failed to copy product data"))?;
+                    let copy_duration = self.clock.elapsed(copy_start_time);
+                    phases.query = Some(copy_duration);
+                    histogram!(
+                        "excel_export_csv_copy_duration_seconds",
+                        copy_duration.as_secs_f64(),
+                        "report_type" => report_type_label.clone()
+                    );
+                    if rows > max_rows as u64 {
+                        return Err(ExportError::RowLimitExceeded { limit: max_rows });
+                    }
+                    row_count = Some(rows as usize);
+                    return output.finish(rows as usize).await
+                        .map_err(|e| ExportError::FileWriteFailed(e.context("Failed to finish CSV file")));
+                }
+
+                let parse_and_query_start_time = self.clock.now_instant();
+                let mut raw_data = self.query_report_data(&params, owner_user_id, max_rows).await?;
+                let query_duration = self.clock.elapsed(parse_and_query_start_time);
+                phases.query = Some(query_duration);
+                // Có label report_type mà metric theo operation không có; tên này sẽ bỏ ở release sau.
+                histogram!(
+                    "excel_export_db_query_duration_seconds",
+                    query_duration.as_secs_f64(),
+                    "report_type" => report_type_label.clone()
+                );
+
+                if raw_data.len() > max_rows {
+                    return Err(ExportError::RowLimitExceeded { limit: max_rows });
+                }
+
+                // Đã biết số dòng: ước lượng lại ETA theo bucket tương ứng (nếu khác kết quả đếm trước).
+                if row_count != Some(raw_data.len()) {
+                    row_count = Some(raw_data.len());
+                    if let Some(eta) = self.estimate_completion(started_at, row_count).await {
+                        self.record_estimated_completion(request_id, eta).await;
+                    }
+                }
+
+                for hook in &self.hooks {
+                    hook.transform_data(&export_request, &params, &mut raw_data).await.map_err(|e| {
+                        ExportError::HookFailed(e.context(format!("transform_data hook '{}' failed", hook.name())))
+                    })?;
+                }
+
+                // Liên kết được dựng từ dữ liệu đã qua hook (ví dụ field đã bị che thì URL cũng không chứa giá trị gốc).
+                if let Some(template) = &report_settings.link_url_template {
+                    raw_data = raw_data.with_link_url_template(template.clone());
+                }
+
+                // Ngày giờ được đổi sang múi giờ của payload sau các hook, để hook luôn thấy dữ liệu UTC.
+                if let Some(time_zone) = params.time_zone() {
+                    raw_data = raw_data.in_time_zone(time_zone, self.clock.now_utc());
+                }
+
+                // Bản xem trước trong email được render trước khi dữ liệu được chuyển cho exporter.
+                // File mã hóa PGP không có bản xem trước, vì email sẽ chứa dữ liệu chưa mã hóa.
+                if let (Some(Delivery::Email { .. }), Some(preview_rows), None) = (
+                    &delivery,
+                    self.email_delivery.as_ref().and_then(|email_delivery| email_delivery.html_preview_rows()),
+                    &params.encryption,
+                ) {
+                    email_preview = Some(HtmlExporter::new(preview_rows, &excel_style).render(&params.report_type, &raw_data));
+                }
+
+                let excel_gen_start_time = self.clock.now_instant();
+                let exported_file = match format {
+                    ExportFormat::Xlsx => {
+                        let excel_file = match &report_settings.template_path {
+                            Some(template_path) => {
+                                let template = self.excel_template(template_path, &params, raw_data.len());
+                                self.file_exporter
+                                    .export_to_template(request_id, raw_data, &template, &excel_style, &export_path)
+                                    .await
+                                    .map_err(|e| {
+                                        if e.downcast_ref::<TemplateError>().is_some() {
+                                            ExportError::TemplateInvalid(e)
+                                        } else {
+                                            map_file_write_error(e, "Failed to export data to Excel template")
+                                        }
+                                    })?
+                            }
+                            None => {
+                                let metadata = self.config.export_metadata.then(|| {
+                                    self.export_metadata(request_id, &export_request, &params, raw_data.len() as u64)
+                                });
+                                self.file_exporter
+                                    .export_to_excel(request_id, raw_data, &excel_style, metadata.as_ref(), max_file_size, &export_path)
+                                    .await
+                                    .map_err(|e| map_file_write_error(e, "Failed to export data to Excel"))?
+                            }
+                        };
+                        if excel_file.parts > 1 {
+                            info!("Request {} was split across {} worksheets", request_id, excel_file.parts);
+                        }
+                        excel_file
+                    }
+                    ExportFormat::Csv => self.file_exporter
+                        .export_to_csv(request_id, raw_data, &csv_options, compression, max_file_size, &export_path)
+                        .await
+                        .map_err(|e| map_file_write_error(e, "Failed to export data to CSV"))?,
+                    ExportFormat::Ods => self.file_exporter.export_to_ods(request_id, raw_data, &excel_style, &export_path).await
+                        .map_err(|e| map_file_write_error(e, "Failed to export data to ODS"))?,
+                    ExportFormat::Html => self.file_exporter
+                        .export_to_html(request_id, raw_data, &params.report_type, &excel_style, &export_path)
+                        .await
+                        .map_err(|e| ExportError::FileWriteFailed(e.context("Failed to export data to HTML")))?,
+                    ExportFormat::Pdf => {
+                        let report = self.pdf_report(request_id, &params, raw_data.len());
+                        self.file_exporter.export_to_pdf(request_id, raw_data, &report, &export_path).await
+                            .map_err(|e| ExportError::FileWriteFailed(e.context("Failed to export data to PDF")))?
+                    }
+                    ExportFormat::Jsonl => self.file_exporter
+                        .export_to_jsonl(request_id, raw_data, compression, max_file_size, &export_path)
+                        .await
+                        .map_err(|e| map_file_write_error(e, "Failed to export data to JSON Lines"))?,
+                    ExportFormat::Parquet => self.file_exporter.export_to_parquet(request_id, raw_data, &export_path).await
+                        .map_err(|e| ExportError::FileWriteFailed(e.context("Failed to export data to Parquet")))?,
+                };
+                let generation_duration = self.clock.elapsed(excel_gen_start_time);
+                phases.generation = Some(generation_duration);
+                histogram!(
+                    "excel_export_excel_generation_duration_seconds",
+                    generation_duration.as_secs_f64(),
+                    "report_type" => report_type_label.clone()
+                );
+                Ok(exported_file)
+            })
+            .await
+            .map_err(|_| ExportError::Timeout { seconds: timeout_secs })??;
+            adjusted_cells = exported_file.adjusted_cells;
+            // File CSV được upload trong lúc ghi đã nằm trên storage, với checksum tính lúc upload.
+            let stored_checksum = exported_file.stored.then(|| FileChecksum {
+                sha256: exported_file.checksum.clone().unwrap_or_default(),
+                size_bytes: exported_file.bytes_written,
+            });
+
+            let rows_written = exported_file.total_rows();
+            let first_part_rows = exported_file.rows_written;
+            let export_result = ExportResult {
+                file_path: exported_file.path.clone(),
+                row_count: rows_written as usize,
+                sheet_count: matches!(format, ExportFormat::Xlsx | ExportFormat::Ods).then_some(exported_file.parts),
+            };
+            run_after_export(&self.hooks, &export_request, &export_result, self.config.export_hook_after_fatal)
+                .await
+                .map_err(ExportError::HookFailed)?;
+
+            let mut extra_parts: Vec<(String, u64)> = exported_file
+                .next_parts
+                .iter()
+                .map(|part| (part.path.clone(), part.rows_written))
+                .collect();
+            if !extra_parts.is_empty() {
+                info!(
+                    "✂️ Request {} was split into {} files to stay under the maximum file size.",
+                    request_id,
+                    extra_parts.len() + 1
+                );
+                increment!("excel_export_split_total", "report_type" => report_type_label.clone());
+            }
+
+            // File có `protection` được mã hóa vào zip AES; file lớn (hoặc payload yêu cầu `compress`)
+            // được nén thành zip. Cả hai đều trước khi tính checksum, nên checksum luôn là của file được tải về.
+            // File đã nén gzip trong lúc ghi: tên và kích thước trước khi nén được báo như file bên trong zip.
+            let mut compressed = exported_file.uncompressed_bytes.map(|size| CompressedFile {
+                path: exported_file.path.clone(),
+                original_file_name: exported_file.file_name.trim_end_matches(".gz").to_string(),
+                original_size_bytes: size,
+            });
+            let mut exported_content_type = exported_file.content_type;
+            let mut exported_file_path = exported_file.path;
+            let compress_min_bytes = if params.compress { Some(0) } else { self.config.compress_threshold_bytes };
+            if !extra_parts.is_empty() && self.config.max_file_size_delivery == SplitFileDelivery::Zip {
+                // Các phần được gộp vào một zip (mã hóa AES khi có `protection`) và đi tiếp như một file.
+                let mut part_paths = vec![exported_file_path];
+                part_paths.extend(extra_parts.drain(..).map(|(path, _)| path));
+                let file = self.file_exporter
+                    .zip_parts(request_id, &part_paths, password.as_deref())
+                    .await
+                    .map_err(ExportError::FileWriteFailed)?;
+                if password.is_some() {
+                    increment!("excel_export_protected_total", "report_type" => report_type_label.clone());
+                }
+                exported_file_path = file.path.clone();
+                exported_content_type = attachment_content_type(&exported_file_path).to_string();
+                compressed = Some(file);
+            } else if let Some(password) = &password {
+                let file = self.file_exporter
+                    .encrypt_file(request_id, &exported_file_path, password)
+                    .await
+                    .map_err(ExportError::FileWriteFailed)?;
+                increment!("excel_export_protected_total", "report_type" => report_type_label.clone());
+                exported_file_path = file.path.clone();
+                exported_content_type = attachment_content_type(&exported_file_path).to_string();
+                compressed = Some(file);
+            } else if let Some(min_size_bytes) = compress_min_bytes {
+                if let Some(file) = self.file_exporter
+                    .compress_file(request_id, &exported_file_path, min_size_bytes)
+                    .await
+                    .map_err(ExportError::FileWriteFailed)?
+                {
+                    increment!("excel_export_compressed_total", "report_type" => report_type_label.clone());
+                    exported_file_path = file.path.clone();
+                    exported_content_type = attachment_content_type(&exported_file_path).to_string();
+                    compressed = Some(file);
+                }
+            }
+
+            // Tên file theo template được đặt sau cùng, để cả file zip cũng mang tên này.
+            let file_stem = report_settings.filename_template.as_ref().map(|template| {
+                let extension = file_extension(&exported_file_path).to_string();
+                render_file_stem(template, request_id, &[
+                    ("request_id", request_id.to_string()),
+                    ("report_type", params.report_type.clone()),
+                    ("user_id", export_request.user_id.to_string()),
+                    ("start_date", params.start_date.to_string()),
+                    ("end_date", params.end_date.to_string()),
+                    ("date", self.clock.now_utc().date_naive().to_string()),
+                    ("ext", extension),
+                ])
+            });
+            if let Some(stem) = &file_stem {
+                check_component(&format!("{}.{}", stem, file_extension(&exported_file_path))).map_err(ExportError::UnsafePath)?;
+                exported_file_path = self.file_exporter
+                    .rename_file(request_id, &exported_file_path, stem)
+                    .await
+                    .map_err(map_rename_error)?;
+                named_file = Some(exported_file_path.clone());
+            }
+
+            // Mã hóa PGP tới người nhận (`encryption` của payload) sau khi đặt tên: file `.gpg` thay cho bản gốc
+            // ở mọi nơi (SFTP, storage, email), và không được mã hóa khi lưu thêm lần nữa.
+            let mut pgp_key_fingerprint = None;
+            if let (Some(ExportEncryption::Pgp { recipient_key_id }), Some(pgp)) = (&params.encryption, &self.pgp_encryption) {
+                let (encrypted_path, fingerprint) = self.file_exporter
+                    .encrypt_for_recipient(&exported_file_path, pgp, recipient_key_id)
+                    .await
+                    .map_err(ExportError::FileWriteFailed)?;
+                exported_file_path = encrypted_path;
+                exported_content_type = attachment_content_type(&exported_file_path).to_string();
+                if named_file.is_some() {
+                    named_file = Some(exported_file_path.clone());
+                }
+                pgp_key_fingerprint = Some(fingerprint);
+                increment!("excel_export_pgp_encrypted_total", "report_type" => report_type_label.clone());
+            }
+            // Với tên theo nội dung (CONTENT_ADDRESSED_FILE_NAMES), `file_name` giữ tên hiển thị của file.
+            let file_name = named_file
+                .as_deref()
+                .or(self.config.content_addressed_file_names.then_some(exported_file_path.as_str()))
+                .and_then(|path| Path::new(path).file_name())
+                .map(|name| name.to_string_lossy().into_owned());
+
+            // SFTP nhận bản local của file, trước khi file được chuyển tới storage cuối.
+            // Lỗi upload làm request thất bại.
+            let sftp_upload = match (&delivery, &self.sftp_delivery) {
+                (Some(Delivery::Sftp { remote_dir }), Some(sftp_delivery)) => {
+                    let upload = sftp_delivery
+                        .upload(request_id, &exported_file_path, remote_dir.as_deref())
+                        .await
+                        .map_err(ExportError::SftpFailed)?;
+                    increment!("excel_export_sftp_uploaded_total", "report_type" => report_type_label.clone());
+                    Some(upload)
+                }
+                _ => None,
+            };
+
+            let mut encryption_key_id = None;
+            let (stored_file_path, file_size_bytes, file_checksum) = match sftp_upload {
+                // Bản local đã bị xóa sau khi upload (SFTP_DELETE_LOCAL): không còn file để tải về hoặc dùng lại.
+                Some(upload) if upload.local_deleted => (None, upload.size_bytes, None),
+                _ => {
+                    // Mã hóa khi lưu (ENCRYPTION_KEY) sau khi giao SFTP, nên đối tác vẫn nhận file gốc.
+                    // Checksum và kích thước bên dưới là của file `.enc`.
+                    let mut exported_file_path = exported_file_path;
+                    if let Some(encryption) = self.encryption.as_ref().filter(|_| pgp_key_fingerprint.is_none()) {
+                        exported_file_path = self.file_exporter
+                            .encrypt_at_rest(&exported_file_path, encryption)
+                            .await
+                            .map_err(ExportError::FileWriteFailed)?;
+                        if named_file.is_some() {
+                            named_file = Some(exported_file_path.clone());
+                        }
+                        encryption_key_id = Some(encryption.active_key_id().to_string());
+                        increment!("excel_export_encrypted_total", "report_type" => report_type_label.clone());
+                    }
+
+                    // Tên theo nội dung được đặt trên file cuối cùng (sau mã hóa). File cùng nội dung đã có sẵn
+                    // thì được dùng chung: không ghi lại, và không bị xóa nếu request này thất bại.
+                    let mut content_reused = false;
+                    if self.config.content_addressed_file_names {
+                        let (path, reused) = self.file_exporter
+                            .rename_by_content(request_id, &exported_file_path)
+                            .await
+                            .map_err(ExportError::FileWriteFailed)?;
+                        if reused {
+                            increment!("excel_export_content_name_reused_total", "report_type" => report_type_label.clone());
+                        }
+                        exported_file_path = path;
+                        named_file = (!reused).then(|| exported_file_path.clone());
+                        content_reused = reused;
+                    }
+
+                    // Với storage từ xa (S3), file được upload ở đây và đường dẫn trở thành object key.
+                    // File đã upload trong lúc ghi chỉ có object key; file metadata vẫn được ghi ở thư mục local của nó.
+                    let (local_file_path, exported_file_path, checksum) = match stored_checksum {
+                        Some(checksum) => {
+                            let file_name = Path::new(&exported_file_path).file_name().unwrap_or_default();
+                            let local_file_path = Path::new(&export_path).join(file_name).to_string_lossy().into_owned();
+                            (local_file_path, exported_file_path, checksum)
+                        }
+                        None => {
+                            let local_file_path = exported_file_path;
+                            let exported_file_path = self.file_exporter.store_file(request_id, &local_file_path).await
+                                .map_err(ExportError::FileWriteFailed)?;
+                            if named_file.is_some() {
+                                named_file = Some(exported_file_path.clone());
+                            }
+                            let checksum = self.file_exporter.file_checksum(&exported_file_path).await
+                                .map_err(ExportError::FileWriteFailed)?
+                                .context("Exported file not found right after generation")
+                                .map_err(ExportError::FileWriteFailed)?;
+                            (local_file_path, exported_file_path, checksum)
+                        }
+                    };
+                    // CSV và JSON Lines không có chỗ cho sheet "Info": thông tin về lần export (kèm checksum của file)
+                    // nằm trong `{tên file}.meta.json` cạnh file, được lưu cùng nơi với file.
+                    if self.config.export_metadata && !content_reused && matches!(format, ExportFormat::Csv | ExportFormat::Jsonl) {
+                        let file_name = Path::new(&local_file_path)
+                            .file_name()
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or_default();
+                        let metadata = ExportMetadata {
+                            checksum_note: format!("sha256 is the SHA-256 checksum of {}", file_name),
+                            file_name: Some(file_name),
+                            sha256: Some(checksum.sha256.clone()),
+                            ..self.export_metadata(request_id, &export_request, &params, first_part_rows)
+                        };
+                        let metadata_path = self.file_exporter.write_metadata_file(&local_file_path, &metadata).await
+                            .map_err(|e| ExportError::FileWriteFailed(e.context("Failed to write export metadata file")))?;
+                        self.file_exporter.store_file(request_id, &metadata_path).await
+                            .map_err(ExportError::FileWriteFailed)?;
+                    }
+                    self.db_store.record_generated_file(
+                        request_id,
+                        &exported_file_path,
+                        &checksum.sha256,
+                        checksum.size_bytes as i64,
+                    ).await?;
+                    (Some(exported_file_path), checksum.size_bytes, Some(checksum.sha256))
+                }
+            };
+
+            // Các phần sau (MAX_FILE_SIZE_DELIVERY=links) đi qua cùng các bước như phần 1,
+            // mỗi phần có link download riêng.
+            for (index, (part_path, part_rows)) in extra_parts.into_iter().enumerate() {
+                let stored_part = self.deliver_part(
+                    request_id,
+                    &export_request,
+                    &params,
+                    format,
+                    index as u32 + 2,
+                    part_path,
+                    part_rows,
+                    file_stem.as_deref(),
+                    password.as_deref(),
+                    compress_min_bytes,
+                    &delivery,
+                ).await?;
+                if let Some(path) = stored_part {
+                    file_parts.push(path);
+                }
+            }
+
+            completion = ExportCompletion {
+                file_size_bytes: Some(file_size_bytes as i64),
+                rows_exported: Some(rows_written as i64),
+                file_checksum,
+                truncated_by_limit,
+                original_file_name: compressed.as_ref().map(|file| file.original_file_name.clone()),
+                original_size_bytes: compressed.map(|file| file.original_size_bytes as i64),
+                is_protected: password.as_ref().map(|_| true),
+                file_name,
+                is_encrypted: encryption_key_id.as_ref().map(|_| true),
+                encryption_key_id,
+                pgp_key_fingerprint,
+                file_parts: Some(file_parts.clone()).filter(|parts| !parts.is_empty()),
+            };
+            expires_at = stored_file_path.as_ref().map(|_| self.clock.now_utc() + link_ttl);
+            file_path = stored_file_path;
+            content_type = Some(exported_content_type);
             final_status = ExportStatus::Completed;
             Ok(())
         }
         .await; // End of processing_result block
 
-        // 4. Update final status in DB and send notification
-        let update_notify_start_time = Instant::now();
+        // 5. Update final status in DB and send notification
+        let update_notify_start_time = self.clock.now_instant();
+        let timings = phases.to_timings();
+        let duration_ms = self.clock.elapsed(start_time).as_millis() as i64;
+        if let Err(e) = self.db_store.record_export_duration(request_id, duration_ms, row_count.map(|rows| rows as i64)).await {
+            warn!("Failed to record duration for request {}: {:?}", request_id, e);
+        }
         match processing_result {
             Ok(_) => {
-                info!("Export request {} completed successfully.", request_id);
+                if adjusted_cells.is_empty() {
+                    info!("Export request {} completed successfully.", request_id);
+                } else {
+                    info!("Export request {} completed successfully ({}).", request_id, adjusted_cells);
+                }
+                // Chỉ request thật sự tạo file mới được đưa vào thống kê ETA (bỏ qua file dùng lại).
+                if let Some(rows) = row_count {
+                    self.duration_stats.record(rows as u64, duration_ms as u64);
+                }
                 self.db_store.update_request_status(
                     request_id,
                     final_status,
                     file_path.clone(),
                     None,
+                    None,
+                    expires_at,
+                    &timings,
+                    &completion,
                 ).await?;
-                increment!("excel_export_completed_total");
+                increment!("excel_export_completed_total", "report_type" => report_type_label.clone());
             }
             Err(e) => {
-                error!("Export request {} failed: {:?}", request_id, e);
-                error_message = Some(format!("Error: {:?}", e));
+                // Log giữ toàn bộ chuỗi lỗi; DB và thông báo chỉ nhận mã lỗi và message an toàn.
+                let error_code = e.code();
+                error!("Export request {} failed [{}]: {:?}", request_id, error_code, e);
+                if let ExportError::UnsafePath(unsafe_path) = &e {
+                    warn!("🚨 Security: request {} was refused an unsafe export path: {}", request_id, unsafe_path);
+                    increment!("excel_export_unsafe_path_total", "report_type" => report_type_label.clone());
+                }
+                if let Some(dir) = &output_dir {
+                    self.remove_partial_output(request_id, dir).await;
+                }
+                if let Some(path) = &named_file {
+                    if let Err(e) = self.file_exporter.delete_file(path).await {
+                        warn!("Failed to remove renamed file {} of failed request {}: {:?}", path, request_id, e);
+                    }
+                }
+                for path in &file_parts {
+                    if let Err(e) = self.file_exporter.delete_file(path).await {
+                        warn!("Failed to remove file part {} of failed request {}: {:?}", path, request_id, e);
+                    }
+                }
+                error_message = Some(self.error_sanitizer.sanitize(&e.user_message()));
+                let last_error = format!("[{}] {}", error_code, error_message.as_deref().unwrap_or_default());
+                self.record_attempt(request_id, Some(&last_error)).await;
                 self.db_store.update_request_status(
                     request_id,
                     final_status,
                     None,
                     error_message.clone(),
+                    Some(error_code.to_string()),
+                    None,
+                    &timings,
+                    &ExportCompletion::default(),
                 ).await?;
-                increment!("excel_export_failed_total");
+                increment!(
+                    "excel_export_failed_total",
+                    "error_code" => error_code,
+                    "report_type" => report_type_label.clone(),
+                    "retryable" => if e.is_retryable() { "true" } else { "false" }
+                );
             }
         }
+        increment!(
+            "excel_export_finished_total",
+            "status" => final_status.as_str(),
+            "report_type" => report_type_label.clone()
+        );
 
         // Send notification
-        let public_file_url = file_path.map(|p| {
-            format!(
-                "{}/exports/{}",
-                self.notification_service_base_url,
-                Path::new(&p).file_name().unwrap_or_default().to_str().unwrap_or_default()
-            )
-        });
+        let public_file_url = match file_path.as_deref() {
+            Some(p) => {
+                file_download_url(
+                    self.file_exporter.as_ref(),
+                    &self.config.notification_service_url,
+                    &self.config.excel_export_path,
+                    tenant.as_deref(),
+                    p,
+                    expires_at,
+                    self.clock.now_utc(),
+                )
+                .await
+            }
+            None => None,
+        };
+        // File bị chia (MAX_FILE_SIZE_DELIVERY=links): link của mọi phần, phần 1 là `file_url`.
+        let file_part_urls = match (&public_file_url, &completion.file_parts) {
+            (Some(first_url), Some(parts)) => {
+                let mut urls = vec![first_url.clone()];
+                for part in parts {
+                    if let Some(url) = file_download_url(
+                        self.file_exporter.as_ref(),
+                        &self.config.notification_service_url,
+                        &self.config.excel_export_path,
+                        tenant.as_deref(),
+                        part,
+                        expires_at,
+                        self.clock.now_utc(),
+                    )
+                    .await
+                    {
+                        urls.push(url);
+                    }
+                }
+                Some(urls)
+            }
+            _ => None,
+        };
 
-        if let Err(e) = self.notifier.send_notification(
-            request_id,
-            final_status.as_str(),
-            public_file_url,
-            error_message,
-        ).await {
+        let notify_start_time = self.clock.now_instant();
+        let mut notify_result = if skip_notification {
+            info!("🔕 Skipping notification for request {}: skip_notification is set in the payload.", request_id);
+            increment!("excel_export_notification_skipped_total");
+            Ok(())
+        } else {
+            self.notifier.send_notification(&ExportNotification {
+                request_id,
+                status: final_status.as_str().to_string(),
+                stage: NotificationStage::for_final_status(final_status),
+                file_url: public_file_url.clone(),
+                error_message: error_message.clone(),
+                expires_at,
+                duration_ms: Some(duration_ms),
+                estimated_completion_at: None,
+                timings: self.config.notify_include_timings.then(|| timings.clone()),
+                truncated_by_limit: completion.truncated_by_limit,
+                original_file_name: completion.original_file_name.clone(),
+                original_size_bytes: completion.original_size_bytes,
+                is_protected: completion.is_protected,
+                file_name: completion.file_name.clone(),
+                content_type: content_type.clone(),
+                file_size_bytes: completion.file_size_bytes,
+                rows_exported: completion.rows_exported,
+                file_part_urls,
+            }).await
+        };
+        if notify_result.is_ok() {
+            if let Some(Delivery::Email { to }) = &delivery {
+                notify_result = self.send_email(
+                    request_id,
+                    to,
+                    final_status.as_str(),
+                    // File trên storage từ xa không đọc được để đính kèm: email chỉ có link.
+                    file_path.as_deref().filter(|_| self.file_exporter.stores_locally()),
+                    public_file_url.as_deref(),
+                    error_message.as_deref(),
+                    email_preview.as_deref().filter(|_| final_status == ExportStatus::Completed),
+                ).await;
+                // Webhook đã gửi được: lần gửi lại (notification retry worker) chỉ gửi email.
+                if notify_result.is_err() && !skip_notification {
+                    if let Err(e) = self.db_store.mark_webhook_sent(request_id).await {
+                        warn!("Failed to record webhook delivery for request {}: {:?}", request_id, e);
+                    }
+                }
+            }
+        }
+        phases.notify = Some(self.clock.elapsed(notify_start_time));
+        if let Some(notify_ms) = phases.to_timings().notify_ms {
+            if let Err(e) = self.db_store.record_notify_duration(request_id, notify_ms).await {
+                warn!("Failed to record notification duration for request {}: {:?}", request_id, e);
+            }
+        }
+
+        if let Err(e) = notify_result {
             error!(
                 "Failed to send notification for request {}: {:?}. Will mark as not sent.",
                 request_id, e
@@ -160,13 +1093,1382 @@ where
             self.db_store.update_notification_sent_status(request_id, true).await?;
             increment!("excel_export_notification_sent_total");
         }
-        histogram!("excel_export_update_notify_duration_seconds", update_notify_start_time.elapsed().as_secs_f64());
+        histogram!("excel_export_update_notify_duration_seconds", self.clock.elapsed(update_notify_start_time).as_secs_f64());
 
         // Final metrics and cleanup
         gauge!("excel_export_requests_in_progress", -1.0, "request_id" => request_id.to_string());
-        histogram!("excel_export_total_processing_duration_seconds", start_time.elapsed().as_secs_f64());
-        info!("🏁 Finished processing request {}. Total duration: {:.2}s", request_id, start_time.elapsed().as_secs_f64());
+        histogram!(
+            "excel_export_total_processing_duration_seconds",
+            self.clock.elapsed(start_time).as_secs_f64(),
+            "report_type" => report_type_label
+        );
+        info!("🏁 Finished processing request {}. Total duration: {:.2}s", request_id, self.clock.elapsed(start_time).as_secs_f64());
+        debug!("⏱️ Phase breakdown for request {}: {:?}", request_id, phases.to_timings());
+
+        Ok(())
+    }
+
+    /// Lấy khóa xử lý của request trước khi xử lý. Trả về `Ok(false)` (bỏ qua message) nếu worker khác
+    /// đang xử lý request này. Lỗi tạm thời đã được `RetryingDbStore` thử lại; nếu vẫn không lấy được khóa
+    /// thì trả về `DatabaseUnavailable` và request không được xử lý khi không có khóa.
+    pub async fn lock_request(&self, request_id: Uuid) -> Result<bool, ExportError> {
+        match self.db_store.try_lock_request(request_id).await {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                info!("🔒 Export request {} is being processed by another worker. Skipping.", request_id);
+                increment!("excel_export_skipped_total", "reason" => "locked");
+                Ok(false)
+            }
+            Err(e) => {
+                increment!("excel_export_request_lock_failed_total");
+                Err(ExportError::DatabaseUnavailable(
+                    anyhow::Error::new(e).context("Failed to take processing lock for request"),
+                ))
+            }
+        }
+    }
+
+    pub async fn unlock_request(&self, request_id: Uuid) {
+        if let Err(e) = self.db_store.unlock_request(request_id).await {
+            warn!("Failed to release processing lock for request {}: {:?}", request_id, e);
+        }
+    }
+
+    /// Được gọi khi task xử lý request bị panic: chuyển request sang FAILED với mã PANIC
+    /// và gửi thông báo lỗi, để request không bị kẹt ở PROCESSING.
+    #[instrument(skip(self, panic_message), fields(request_id = %request_id))]
+    pub async fn handle_panic(&self, request_id: Uuid, panic_message: &str) -> Result<()> {
+        increment!("excel_export_panics_total");
+        gauge!("excel_export_requests_in_progress", -1.0, "request_id" => request_id.to_string());
+
+        error!("💥 Export task for request {} panicked: {}", request_id, panic_message);
+        self.fail_unprocessed(request_id, ExportError::Panic(panic_message.to_string())).await
+    }
+
+    /// Được gọi khi không lấy được khóa xử lý (xem `lock_request`): chuyển request sang FAILED với mã
+    /// DB_UNAVAILABLE (retryable) thay vì xử lý khi không có khóa hoặc để request kẹt ở PENDING.
+    #[instrument(skip(self, error), fields(request_id = %request_id))]
+    pub async fn handle_lock_failure(&self, request_id: Uuid, error: ExportError) -> Result<()> {
+        error!("🔒 Could not take processing lock for request {}: {:?}. Failing it without processing.", request_id, error);
+        self.fail_unprocessed(request_id, error).await
+    }
 
+    /// Chuyển sang FAILED và gửi thông báo lỗi cho request mà `process_export_request` không xử lý xong.
+    async fn fail_unprocessed(&self, request_id: Uuid, error: ExportError) -> Result<()> {
+        let error_message = self.error_sanitizer.sanitize(&error.user_message());
+        self.record_attempt(request_id, Some(&format!("[{}] {}", error.code(), error_message))).await;
+        self.db_store.update_request_status(
+            request_id,
+            ExportStatus::Failed,
+            None,
+            Some(error_message.clone()),
+            Some(error.code().to_string()),
+            None,
+            &ExportTimings::default(),
+            &ExportCompletion::default(),
+        ).await?;
+        // Payload không được đọc lại ở đây nên loại report không xác định.
+        increment!(
+            "excel_export_failed_total",
+            "error_code" => error.code(),
+            "report_type" => OTHER_REPORT_TYPE_LABEL
+        );
+        increment!(
+            "excel_export_finished_total",
+            "status" => ExportStatus::Failed.as_str(),
+            "report_type" => OTHER_REPORT_TYPE_LABEL
+        );
+
+        let notification = ExportNotification {
+            request_id,
+            status: ExportStatus::Failed.as_str().to_string(),
+            stage: NotificationStage::Failed,
+            file_url: None,
+            error_message: Some(error_message),
+            expires_at: None,
+            duration_ms: None,
+            estimated_completion_at: None,
+            timings: None,
+            truncated_by_limit: None,
+            original_file_name: None,
+            original_size_bytes: None,
+            is_protected: None,
+            file_name: None,
+            content_type: None,
+            file_size_bytes: None,
+            rows_exported: None,
+            file_part_urls: None,
+        };
+        if let Err(e) = self.notifier.send_notification(&notification).await {
+            error!("Failed to send failure notification for request {}: {:?}. Will mark as not sent.", request_id, e);
+            self.db_store.update_notification_sent_status(request_id, false).await.ok();
+            increment!("excel_export_notification_failed_total");
+        } else {
+            self.db_store.update_notification_sent_status(request_id, true).await?;
+            increment!("excel_export_notification_sent_total");
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Thông báo PROCESSING được bật bởi config NOTIFY_ON_PROCESSING hoặc `notify_on` trong payload,
+    /// và luôn tắt khi payload có `skip_notification`.
+    fn should_notify_processing(&self, params: &ReportParams) -> bool {
+        if params.skip_notification {
+            return false;
+        }
+        match &params.notify_on {
+            Some(stages) => stages.contains(&NotificationStage::Processing),
+            None => self.config.notify_on_processing,
+        }
+    }
+
+    /// Gửi thông báo trung gian và chờ gửi xong trước khi export tiếp tục, để thông báo này không bao giờ tới
+    /// sau thông báo cuối. Lỗi chỉ được ghi log: không làm export thất bại và không ảnh hưởng tới cột notification_sent.
+    async fn send_processing_notification(&self, request_id: Uuid, estimated_completion_at: Option<DateTime<Utc>>) {
+        let notification = ExportNotification {
+            request_id,
+            status: ExportStatus::Processing.as_str().to_string(),
+            stage: NotificationStage::Processing,
+            file_url: None,
+            error_message: None,
+            expires_at: None,
+            duration_ms: None,
+            estimated_completion_at,
+            timings: None,
+            truncated_by_limit: None,
+            original_file_name: None,
+            original_size_bytes: None,
+            is_protected: None,
+            file_name: None,
+            content_type: None,
+            file_size_bytes: None,
+            rows_exported: None,
+            file_part_urls: None,
+        };
+        match self.notifier.send_notification(&notification).await {
+            Ok(_) => increment!("excel_export_intermediate_notification_sent_total"),
+            Err(e) => {
+                warn!("Failed to send PROCESSING notification for request {}: {:?}", request_id, e);
+                increment!("excel_export_intermediate_notification_failed_total");
+            }
+        }
+    }
+
+    /// attempts/last_error chỉ phục vụ retry và điều tra lỗi: lỗi ghi DB không làm export thất bại.
+    async fn record_attempt(&self, request_id: Uuid, error: Option<&str>) {
+        if let Err(e) = self.db_store.record_attempt(request_id, error).await {
+            warn!("Failed to record attempt for request {}: {:?}", request_id, e);
+        }
+    }
+
+    /// Mật khẩu của file được bảo vệ: lấy từ payload, nếu không có thì từ export hook đầu tiên cung cấp.
+    async fn export_password(
+        &self,
+        export_request: &ExportRequest,
+        params: &ReportParams,
+        protection: &ExportProtection,
+    ) -> Result<String, ExportError> {
+        if let Some(password) = &protection.password {
+            return Ok(password.clone());
+        }
+        for hook in &self.hooks {
+            let password = hook.export_password(export_request, params).await.map_err(|e| {
+                ExportError::HookFailed(e.context(format!("export_password hook '{}' failed", hook.name())))
+            })?;
+            if let Some(password) = password.filter(|password| !password.is_empty()) {
+                return Ok(password);
+            }
+        }
+        Err(ExportError::InvalidParams(anyhow::anyhow!(
+            "protection has no password and no export hook provides one"
+        )))
+    }
+
+    /// Template Excel của loại report cùng giá trị các placeholder:
+    /// `{{date_range}}`, `{{generated_at}}`, `{{report_type}}` và `{{row_count}}`.
+    fn excel_template(&self, template_path: &str, params: &ReportParams, row_count: usize) -> ExcelTemplate {
+        ExcelTemplate {
+            path: template_path.to_string(),
+            placeholders: vec![
+                ("date_range".to_string(), format!("{} - {}", params.start_date, params.end_date)),
+                ("generated_at".to_string(), self.clock.now_utc().format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+                ("report_type".to_string(), params.report_type.clone()),
+                ("row_count".to_string(), row_count.to_string()),
+            ],
+        }
+    }
+
+    /// Thông tin về lần export cho sheet "Info" của file Excel và file `.meta.json` (EXPORT_METADATA).
+    fn export_metadata(&self, request_id: Uuid, export_request: &ExportRequest, params: &ReportParams, row_count: u64) -> ExportMetadata {
+        ExportMetadata {
+            request_id,
+            user_id: export_request.user_id,
+            report_type: params.report_type.clone(),
+            parameters: params.content_parameters(),
+            generated_at: self.clock.now_utc(),
+            timezone: params.timezone.clone().unwrap_or_else(|| "UTC".to_string()),
+            row_count,
+            service_version: SERVICE_VERSION.to_string(),
+            file_name: None,
+            sha256: None,
+            checksum_note: "The SHA-256 checksum of the delivered file is recorded with the export request".to_string(),
+        }
+    }
+
+    /// Tiêu đề, tham số và thông tin tạo file của report PDF.
+    fn pdf_report(&self, request_id: Uuid, params: &ReportParams, row_count: usize) -> PdfReport {
+        PdfReport {
+            title: "Category Summary Report".to_string(),
+            parameters: vec![
+                ("Date range".to_string(), format!("{} - {}", params.start_date, params.end_date)),
+                (
+                    "Product category".to_string(),
+                    params.product_category.clone().unwrap_or_else(|| "All".to_string()),
+                ),
+            ],
+            metadata: vec![
+                ("Generated at".to_string(), self.clock.now_utc().format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+                ("Request ID".to_string(), request_id.to_string()),
+                ("Rows".to_string(), row_count.to_string()),
+            ],
+        }
+    }
+
+    /// Query dữ liệu theo loại report. Loại report không có query riêng dùng report sản phẩm
+    /// (giữ tương thích với producer cũ).
+    /// COPY bỏ qua `transform_data` của hook (ví dụ che PII), nên chỉ dùng khi không có hook nào được bật.
+    /// COPY ghi thời gian theo UTC, nên request có `timezone` cũng dùng exporter thông thường.
+    /// COPY ghi nguyên giá trị các ô, nên chỉ dùng khi FORMULA_ESCAPE=off, và chỉ cho ra được dialect mặc định.
+    /// COPY không dựng được URL của cột liên kết, nên loại report có `link_url_template` cũng không dùng COPY.
+    fn can_copy_csv(&self, params: &ReportParams, csv_options: &CsvOptions) -> bool {
+        csv_options.is_copy_compatible()
+            && self.db_store.supports_csv_copy()
+            && self.hooks.is_empty()
+            && params.is_product_report()
+            && params.timezone.is_none()
+            && self.config.formula_escape == FormulaEscape::Off
+            && self.config.report_settings(&params.report_type).0.link_url_template.is_none()
+    }
+
+    /// File CSV của COPY được upload thẳng lên storage từ xa trong lúc ghi (`create_streamed_csv_file`) khi không còn
+    /// bước nào cần bản local sau khi tạo file: nén zip, mật khẩu, đổi tên theo template hay theo nội dung,
+    /// mã hóa PGP hoặc khi lưu, giao qua SFTP.
+    fn can_stream_upload(
+        &self,
+        params: &ReportParams,
+        report_settings: &ReportTypeSettings,
+        password: Option<&str>,
+        delivery: Option<&Delivery>,
+    ) -> bool {
+        password.is_none()
+            && !params.compress
+            && self.config.compress_threshold_bytes.is_none()
+            && report_settings.filename_template.is_none()
+            && !self.config.content_addressed_file_names
+            && params.encryption.is_none()
+            && self.encryption.is_none()
+            && !matches!(delivery, Some(Delivery::Sftp { .. }))
+    }
+
+    /// `owner_user_id` giới hạn dữ liệu vào bản ghi của user này (report OwnerOnly), xem `AppConfig::owner_filter`.
+    async fn query_report_data(
+        &self,
+        params: &ReportParams,
+        owner_user_id: Option<i64>,
+        max_rows: usize,
+    ) -> Result<ReportData, ExportError> {
+        match params.report_type.as_str() {
+            ORDERS_REPORT_TYPE => {
+                let rows = self.db_store.query_order_data(&params.order_params(owner_user_id), max_rows).await
+                    .map_err(|e| map_query_error(e, "Failed to query order data"))?;
+                Ok(ReportData::Orders(rows))
+            }
+            CUSTOMERS_REPORT_TYPE => {
+                let rows = self.db_store.query_customer_data(&params.customer_params(owner_user_id), max_rows).await
+                    .map_err(|e| map_query_error(e, "Failed to query customer data"))?;
+                Ok(ReportData::Customers(rows))
+            }
+            // Số dòng chỉ bằng số category (+1 dòng tổng) nên không áp dụng max_rows vào query.
+            CATEGORY_SUMMARY_REPORT_TYPE => {
+                let rows = self.db_store.query_category_summary(params).await
+                    .map_err(|e| map_query_error(e, "Failed to query category summary"))?;
+                Ok(ReportData::CategorySummary(rows))
+            }
+            // SQL do admin khai báo trong CUSTOM_DATASETS; payload chỉ chọn tên dataset và giá trị tham số.
+            DATASET_REPORT_TYPE => {
+                let name = params.dataset.as_deref().unwrap_or_default();
+                let dataset = self.config.custom_datasets.get(name).ok_or_else(|| {
+                    ExportError::InvalidParams(anyhow::anyhow!("Unknown dataset '{}'", name))
+                })?;
+                let values = dataset
+                    .bind_values(params.dataset_params.as_ref())
+                    .map_err(|e| ExportError::InvalidParams(e.context(format!("Invalid parameters for dataset '{}'", name))))?;
+                let rows = self.db_store.query_dataset(&dataset.sql, &values, params.tenant.as_deref(), max_rows).await
+                    .map_err(|e| map_query_error(e, "Failed to query dataset"))?;
+                Ok(ReportData::Dataset(rows))
+            }
+            _ => {
+                let rows = self.read_product_rows(params, max_rows).await?;
+                Ok(ReportData::Products { rows, columns: params.selected_columns() })
+            }
+        }
+    }
+
+    /// Đọc report sản phẩm qua `stream_product_data` (theo trang khi DB_PAGE_SIZE được set),
+    /// dừng ở dòng thứ `max_rows + 1` để caller phát hiện vượt giới hạn mà không đọc hết kết quả.
+    /// Lỗi tạm thời giữa chừng (ví dụ serialization failure của snapshot REPEATABLE READ) bỏ các dòng đã đọc
+    /// và đọc lại từ đầu, tối đa `PRODUCT_READ_ATTEMPTS` lần.
+    async fn read_product_rows(&self, params: &ReportParams, max_rows: usize) -> Result<Vec<ProductData>, ExportError> {
+        let mut attempt = 1;
+        loop {
+            let result: Result<Vec<ProductData>> = self.db_store
+                .stream_product_data(params)
+                .take(max_rows.saturating_add(1))
+                .try_collect()
+                .await;
+            match result.map_err(DbError::from) {
+                Ok(rows) => return Ok(rows),
+                Err(DbError::Transient(e)) if attempt < PRODUCT_READ_ATTEMPTS => {
+                    warn!(
+                        "Transient error while reading product data (attempt {}/{}): {:?}. Restarting the read.",
+                        attempt, PRODUCT_READ_ATTEMPTS, e
+                    );
+                    increment!("excel_export_product_read_restarts_total");
+                    attempt += 1;
+                }
+                Err(e) => return Err(map_query_error(e, "Failed to query product data")),
+            }
+        }
+    }
+
+    /// ETA = thời điểm bắt đầu + thời gian ước lượng. Lần gọi đầu tiên nạp thống kê từ các request
+    /// đã hoàn thành gần đây trong DB, để ETA có giá trị ngay sau khi service khởi động lại.
+    async fn estimate_completion(&self, started_at: DateTime<Utc>, row_count: Option<usize>) -> Option<DateTime<Utc>> {
+        self.duration_stats_seeded
+            .get_or_init(|| async {
+                match self.db_store.recent_export_durations(DURATION_STATS_SEED_LIMIT).await {
+                    // Kết quả mới nhất trước; nạp từ cũ đến mới để cửa sổ giữ lại các mẫu gần nhất.
+                    Ok(samples) => {
+                        for (rows, duration_ms) in samples.into_iter().rev() {
+                            self.duration_stats.record(rows.max(0) as u64, duration_ms.max(0) as u64);
+                        }
+                    }
+                    Err(e) => warn!("Failed to load recent export durations for ETA estimates: {:?}", e),
+                }
+            })
+            .await;
+
+        let estimate_ms = self.duration_stats.estimate_ms(row_count.map(|rows| rows as u64))?;
+        Some(started_at + ChronoDuration::milliseconds(estimate_ms as i64))
+    }
+
+    /// Đưa một phần (từ phần 2) của file bị chia qua các bước sau khi ghi như phần 1: mã hóa hoặc nén,
+    /// đặt tên theo template (`{stem}.part{n}`), PGP, SFTP, mã hóa khi lưu, lưu trữ và file metadata.
+    /// Trả về đường dẫn đã lưu, hoặc `None` nếu bản local đã bị xóa sau khi upload SFTP.
+    #[allow(clippy::too_many_arguments)]
+    async fn deliver_part(
+        &self,
+        request_id: Uuid,
+        export_request: &ExportRequest,
+        params: &ReportParams,
+        format: ExportFormat,
+        part: u32,
+        mut part_path: String,
+        part_rows: u64,
+        file_stem: Option<&str>,
+        password: Option<&str>,
+        compress_min_bytes: Option<u64>,
+        delivery: &Option<Delivery>,
+    ) -> Result<Option<String>, ExportError> {
+        if let Some(password) = password {
+            part_path = self.file_exporter
+                .encrypt_file(request_id, &part_path, password)
+                .await
+                .map_err(ExportError::FileWriteFailed)?
+                .path;
+        } else if let Some(min_size_bytes) = compress_min_bytes {
+            if let Some(file) = self.file_exporter
+                .compress_file(request_id, &part_path, min_size_bytes)
+                .await
+                .map_err(ExportError::FileWriteFailed)?
+            {
+                part_path = file.path;
+            }
+        }
+        if let Some(stem) = file_stem {
+            part_path = self.file_exporter
+                .rename_file(request_id, &part_path, &format!("{}.part{}", stem, part))
+                .await
+                .map_err(map_rename_error)?;
+        }
+        let mut pgp_encrypted = false;
+        if let (Some(ExportEncryption::Pgp { recipient_key_id }), Some(pgp)) = (&params.encryption, &self.pgp_encryption) {
+            part_path = self.file_exporter
+                .encrypt_for_recipient(&part_path, pgp, recipient_key_id)
+                .await
+                .map_err(ExportError::FileWriteFailed)?
+                .0;
+            pgp_encrypted = true;
+        }
+        if let (Some(Delivery::Sftp { remote_dir }), Some(sftp_delivery)) = (delivery, &self.sftp_delivery) {
+            let upload = sftp_delivery
+                .upload(request_id, &part_path, remote_dir.as_deref())
+                .await
+                .map_err(ExportError::SftpFailed)?;
+            if upload.local_deleted {
+                return Ok(None);
+            }
+        }
+        if let Some(encryption) = self.encryption.as_ref().filter(|_| !pgp_encrypted) {
+            part_path = self.file_exporter
+                .encrypt_at_rest(&part_path, encryption)
+                .await
+                .map_err(ExportError::FileWriteFailed)?;
+        }
+        if self.config.content_addressed_file_names {
+            part_path = self.file_exporter
+                .rename_by_content(request_id, &part_path)
+                .await
+                .map_err(ExportError::FileWriteFailed)?
+                .0;
+        }
+
+        let stored_path = self.file_exporter.store_file(request_id, &part_path).await
+            .map_err(ExportError::FileWriteFailed)?;
+        if self.config.export_metadata && matches!(format, ExportFormat::Csv | ExportFormat::Jsonl) {
+            let checksum = self.file_exporter.file_checksum(&stored_path).await
+                .map_err(ExportError::FileWriteFailed)?
+                .context("Exported file part not found right after generation")
+                .map_err(ExportError::FileWriteFailed)?;
+            let file_name = Path::new(&part_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let metadata = ExportMetadata {
+                checksum_note: format!("sha256 is the SHA-256 checksum of {}", file_name),
+                file_name: Some(file_name),
+                sha256: Some(checksum.sha256),
+                ..self.export_metadata(request_id, export_request, params, part_rows)
+            };
+            let metadata_path = self.file_exporter.write_metadata_file(&part_path, &metadata).await
+                .map_err(|e| ExportError::FileWriteFailed(e.context("Failed to write export metadata file")))?;
+            self.file_exporter.store_file(request_id, &metadata_path).await
+                .map_err(ExportError::FileWriteFailed)?;
+        }
+        Ok(Some(stored_path))
+    }
+
+    /// Xóa file ghi dở của request thất bại. Chỉ ghi log khi lỗi, không được che lỗi gốc của export.
+    async fn remove_partial_output(&self, request_id: Uuid, export_path: &str) {
+        match self.file_exporter.remove_partial_output(request_id, export_path).await {
+            Ok(0) => {}
+            Ok(bytes) => {
+                info!("🧹 Removed partial output ({} bytes) of failed request {}.", bytes, request_id);
+                increment!("excel_export_partial_files_removed_total");
+            }
+            Err(e) => warn!("Failed to remove partial output of request {}: {:?}", request_id, e),
+        }
+    }
+
+    /// ETA chỉ mang tính tham khảo: lỗi ghi DB không làm export thất bại.
+    async fn record_estimated_completion(&self, request_id: Uuid, eta: DateTime<Utc>) {
+        if let Err(e) = self.db_store.record_estimated_completion(request_id, eta).await {
+            warn!("Failed to record ETA for request {}: {:?}", request_id, e);
+        }
+    }
+
+    /// Giới hạn kích thước file (payload ghi đè MAX_FILE_SIZE_BYTES). Không áp dụng cho định dạng
+    /// không chia được hoặc khi dùng template Excel.
+    fn max_file_size(&self, params: &ReportParams, format: ExportFormat, uses_template: bool) -> Option<u64> {
+        if !SplitFileDelivery::supports(format) || uses_template {
+            return None;
+        }
+        params.max_file_size_bytes.or(self.config.max_file_size_bytes)
+    }
+
+    /// Thời gian sống của link download: payload có thể ghi đè TTL mặc định trong config.
+    /// Tính theo giờ trên UTC nên không bị ảnh hưởng bởi chuyển đổi giờ mùa hè (DST).
+    fn link_ttl(&self, params: &ReportParams) -> ChronoDuration {
+        let hours = params
+            .expires_in_hours
+            .filter(|hours| *hours > 0)
+            .unwrap_or(self.config.export_link_ttl_hours);
+        ChronoDuration::hours(hours)
+    }
+
+    /// Gửi email kèm file (hoặc link nếu file quá lớn). Lỗi được xử lý như lỗi gửi thông báo
+    /// để notification retry worker thử lại.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_email(
+        &self,
+        request_id: Uuid,
+        to: &str,
+        status: &str,
+        file_path: Option<&str>,
+        file_url: Option<&str>,
+        error_message: Option<&str>,
+        html_preview: Option<&str>,
+    ) -> Result<()> {
+        match &self.email_delivery {
+            Some(email_delivery) => {
+                email_delivery
+                    .deliver(request_id, to, status, file_path, file_url, error_message, html_preview)
+                    .await
+            }
+            None => {
+                warn!("Request {} asked for email delivery but SMTP is not configured. Skipping email.", request_id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Tìm file của một request COMPLETED có cùng params hash trong cửa sổ DEDUP_WINDOW_SECS.
+    /// Ghi lại đường dẫn/checksum cho request hiện tại và trả về (đường dẫn, expires_at của request gốc).
+    async fn find_duplicate_file(
+        &self,
+        request_id: Uuid,
+        params_hash: &str,
+    ) -> Option<(String, Option<DateTime<Utc>>)> {
+        if self.config.dedup_window_secs == 0 {
+            return None;
+        }
+        let since = self.clock.now_utc() - ChronoDuration::seconds(self.config.dedup_window_secs as i64);
+        let duplicate = match self.db_store.find_recent_by_params_hash(params_hash, since, request_id).await {
+            Ok(Some(duplicate)) => duplicate,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("Duplicate lookup failed for request {}: {:?}. Generating a new file.", request_id, e);
+                return None;
+            }
+        };
+
+        let path = self.find_reusable_file(&duplicate).await?;
+        let checksum = duplicate.file_checksum.as_deref()?;
+        let size_bytes = duplicate.file_size_bytes.unwrap_or_default();
+        if let Err(e) = self.db_store.record_generated_file(request_id, &path, checksum, size_bytes).await {
+            warn!("Failed to record reused file for request {}: {:?}. Generating a new file.", request_id, e);
+            return None;
+        }
+
+        info!("♻️ Request {} duplicates completed request {}. Reusing file {}.", request_id, duplicate.id, path);
+        increment!("excel_export_deduplicated_total");
+        Some((path, duplicate.expires_at))
+    }
+
+    /// Kiểm tra file đã tạo ở lần xử lý trước: chỉ dùng lại khi file còn tồn tại và checksum khớp.
+    async fn find_reusable_file(&self, export_request: &ExportRequest) -> Option<String> {
+        let (Some(path), Some(expected_checksum)) = (
+            export_request.file_path.as_deref(),
+            export_request.file_checksum.as_deref(),
+        ) else {
+            return None;
+        };
+
+        match self.file_exporter.file_checksum(path).await {
+            Ok(Some(actual)) if actual.sha256 == expected_checksum => Some(path.to_string()),
+            Ok(Some(actual)) => {
+                warn!(
+                    "Checksum mismatch for existing file {} (expected {}, found {}). Regenerating.",
+                    path, expected_checksum, actual.sha256
+                );
+                None
+            }
+            Ok(None) => {
+                warn!("Previously generated file {} no longer exists. Regenerating.", path);
+                None
+            }
+            Err(e) => {
+                warn!("Failed to verify existing file {}: {:?}. Regenerating.", path, e);
+                None
+            }
+        }
+    }
+}
+
+/// File đã có (của lần xử lý trước hoặc của request trùng) có được dùng lại thay vì tạo file mới không.
+/// File có thể bị chia thành nhiều phần thì luôn được tạo lại: checksum chỉ có của phần đầu. Request giao qua
+/// SFTP cũng vậy, vì upload chỉ chạy khi tạo file: file dùng lại chưa chắc đã tới thư mục SFTP của request.
+fn can_reuse_existing_file(delivery: Option<&Delivery>, max_file_size: Option<u64>) -> bool {
+    max_file_size.is_none() && !matches!(delivery, Some(Delivery::Sftp { .. }))
+}
+
+/// Lỗi trả về khi request được xử lý lại sau thời điểm link download hết hạn.
+#[derive(Debug)]
+pub struct ExportExpired {
+    pub expired_at: DateTime<Utc>,
+}
+
+impl fmt::Display for ExportExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Export expired at {} and will not be regenerated.", self.expired_at.to_rfc3339())
+    }
+}
+
+impl std::error::Error for ExportExpired {}
+
+#[cfg(test)]
+impl<D, F, N> ExportService<D, F, N>
+where
+    D: DbStore,
+    F: FileExporter,
+    N: Notifier,
+{
+    /// Service cho test: không có email, PGP hay hook, dùng đồng hồ thật.
+    pub fn for_test(db_store: Arc<D>, file_exporter: Arc<F>, notifier: Arc<N>, config: AppConfig) -> Self {
+        Self::new(
+            db_store,
+            file_exporter,
+            notifier,
+            None,
+            None,
+            Arc::new(config),
+            Arc::new(crate::clock::SystemClock),
+            Vec::new(),
+        )
+    }
+}
+
+/// Xây dựng URL công khai của file Excel từ đường dẫn đã lưu trong DB. URL giữ đường dẫn tương đối
+/// so với EXCEL_EXPORT_PATH (thư mục tenant, `output_subdir`, thư mục ngày); đường dẫn nằm ngoài
+/// thư mục gốc chỉ dùng tên file, có tiền tố tenant nếu có.
+/// Trả về `None` nếu link đã hết hạn: không bao giờ phát hành link cho file quá hạn.
+pub fn build_public_file_url(
+    base_url: &str,
+    export_root: &str,
+    tenant: Option<&str>,
+    file_path: &str,
+    expires_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    if let Some(expires_at) = expires_at {
+        if now >= expires_at {
+            warn!("Refusing to build download URL for {}: link expired at {}.", file_path, expires_at);
+            return None;
+        }
+    }
+    if let Ok(relative) = Path::new(file_path).strip_prefix(export_root) {
+        let relative = relative
+            .components()
+            .filter_map(|component| match component {
+                std::path::Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        if !relative.is_empty() {
+            return Some(format!("{}/exports/{}", base_url, relative));
+        }
+    }
+    let file_name = Path::new(file_path).file_name().unwrap_or_default().to_str().unwrap_or_default();
+    Some(match tenant {
+        Some(tenant) => format!("{}/exports/{}/{}", base_url, tenant, file_name),
+        None => format!("{}/exports/{}", base_url, file_name),
+    })
+}
+
+/// Link download của file đã lưu: link do storage cấp (ví dụ presigned URL của S3) nếu có, nếu không là URL công khai.
+/// Trả về `None` nếu link đã hết hạn hoặc storage không tạo được link.
+pub async fn file_download_url<F: FileExporter>(
+    file_exporter: &F,
+    base_url: &str,
+    export_root: &str,
+    tenant: Option<&str>,
+    file_path: &str,
+    expires_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    let public_url = build_public_file_url(base_url, export_root, tenant, file_path, expires_at, now)?;
+    match file_exporter.download_url(file_path).await {
+        Ok(Some(url)) => Some(url),
+        Ok(None) => Some(public_url),
+        Err(e) => {
+            warn!("Failed to build download URL for {}: {:?}", file_path, e);
+            None
+        }
+    }
+}
+
+/// Ô bị FORMULA_ESCAPE=reject từ chối có mã lỗi riêng; ô không chuyển được sang bảng mã CSV được yêu cầu
+/// và dòng không vừa `max_file_size_bytes` là lỗi tham số. Các lỗi khác là lỗi ghi file.
+fn map_file_write_error(e: anyhow::Error, context: &'static str) -> ExportError {
+    if e.downcast_ref::<CsvEncodingError>().is_some() || e.downcast_ref::<FileSizeLimitError>().is_some() {
+        return ExportError::InvalidParams(e);
+    }
+    match e.downcast::<UnsafeCellValue>() {
+        Ok(unsafe_cell) => ExportError::UnsafeCellValue(unsafe_cell),
+        Err(e) => ExportError::FileWriteFailed(e.context(context)),
+    }
+}
+
+/// Tên file bị `safe_path` từ chối có mã UNSAFE_PATH; các lỗi đổi tên khác là lỗi ghi file.
+fn map_rename_error(e: anyhow::Error) -> ExportError {
+    match e.downcast::<UnsafePath>() {
+        Ok(unsafe_path) => ExportError::UnsafePath(unsafe_path),
+        Err(e) => ExportError::FileWriteFailed(e),
+    }
+}
+
+/// Lỗi tạm thời của DB giữ nguyên phân loại (retryable), các lỗi khác là lỗi query.
+fn map_query_error(e: DbError, context: &'static str) -> ExportError {
+    match e {
+        DbError::Transient(_) => ExportError::from(e),
+        e => ExportError::QueryFailed(anyhow::Error::new(e).context(context)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::models::OrderData;
+    use crate::services::file_exporter::LocalFileExporter;
+    use crate::services::mock_exporter::{ExportFault, MockFileExporter};
+    use crate::services::mock_store::MockDbStore;
+    use crate::services::notifier::RecordingNotifier;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    /// Thư mục export tạm, bị xóa khi test kết thúc.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("excel-export-test-{}", Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> String {
+            self.0.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn local_exporter(config: &AppConfig) -> LocalFileExporter {
+        LocalFileExporter::new(
+            config.formula_escape,
+            config.excel_max_rows_per_sheet,
+            config.parquet,
+            config.html_max_rows,
+            config.pdf.clone(),
+        )
+    }
+
+    fn service<F: FileExporter>(
+        db_store: Arc<MockDbStore>,
+        file_exporter: impl FnOnce(&AppConfig) -> F,
+        notifier: Arc<RecordingNotifier>,
+        export_dir: &TempDir,
+    ) -> ExportService<MockDbStore, F, RecordingNotifier> {
+        service_with_config(db_store, file_exporter, notifier, AppConfig::for_test(&export_dir.path()))
+    }
+
+    fn service_with_config<F: FileExporter>(
+        db_store: Arc<MockDbStore>,
+        file_exporter: impl FnOnce(&AppConfig) -> F,
+        notifier: Arc<RecordingNotifier>,
+        config: AppConfig,
+    ) -> ExportService<MockDbStore, F, RecordingNotifier> {
+        let file_exporter = Arc::new(file_exporter(&config));
+        ExportService::for_test(db_store, file_exporter, notifier, config)
+    }
+
+    /// Request CSV sản phẩm đang PENDING; `extra` được gộp vào payload.
+    fn pending_csv_request(db_store: &MockDbStore, extra: serde_json::Value) -> Uuid {
+        let mut payload = serde_json::json!({"start_date": "2024-01-01", "end_date": "2024-01-31", "format": "csv"});
+        if let (Some(payload), Some(extra)) = (payload.as_object_mut(), extra.as_object()) {
+            payload.extend(extra.clone());
+        }
+        db_store.insert(ExportRequest::for_test(payload), ExportStatus::Pending)
+    }
+
+    #[test]
+    fn sftp_requests_never_reuse_existing_files() {
+        let sftp = Delivery::Sftp { remote_dir: Some("inbound".to_string()) };
+        let email = Delivery::Email { to: "jane@example.com".to_string() };
+
+        assert!(!can_reuse_existing_file(Some(&sftp), None));
+        assert!(!can_reuse_existing_file(Some(&Delivery::Sftp { remote_dir: None }), None));
+        assert!(can_reuse_existing_file(Some(&email), None));
+        assert!(can_reuse_existing_file(None, None));
+        assert!(!can_reuse_existing_file(None, Some(10 * 1024 * 1024)));
+    }
+
+    #[test]
+    fn download_url_stops_at_link_expiry() {
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap());
+        let expires_at = Some(clock.now_utc() + ChronoDuration::hours(1));
+        let url = |clock: &MockClock| {
+            build_public_file_url(
+                "https://files.example.com",
+                "/exports",
+                None,
+                "/exports/2024/05/report.xlsx",
+                expires_at,
+                clock.now_utc(),
+            )
+        };
+
+        assert_eq!(url(&clock).as_deref(), Some("https://files.example.com/exports/2024/05/report.xlsx"));
+        clock.advance(Duration::from_secs(3599));
+        assert!(url(&clock).is_some());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(url(&clock), None);
+    }
+
+    #[tokio::test]
+    async fn processing_notification_is_sent_before_the_final_one() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        // Thông báo PROCESSING chậm hơn cả export: nếu không được chờ, thông báo COMPLETED sẽ tới trước.
+        let notifier = Arc::new(RecordingNotifier::with_delay(NotificationStage::Processing, Duration::from_millis(200)));
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::clone(&notifier), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({"notify_on": ["processing", "completed", "failed"]}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        assert_eq!(
+            notifier.stages(),
+            vec![(request_id, NotificationStage::Processing), (request_id, NotificationStage::Completed)]
+        );
+        assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn failed_export_removes_the_file_it_already_wrote() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let service = service(
+            Arc::clone(&db_store),
+            |config| MockFileExporter::new(local_exporter(config)),
+            Arc::clone(&notifier),
+            &export_dir,
+        );
+        service.file_exporter.fault_next(ExportFault::Fail);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Failed);
+        let leftovers: Vec<_> = walk_files(&export_dir.0);
+        assert!(leftovers.is_empty(), "partial output left behind: {:?}", leftovers);
+    }
+
+    /// Các lần chuyển trạng thái (from, to) trong lịch sử của request.
+    async fn transitions(db_store: &MockDbStore, request_id: Uuid) -> Vec<(Option<String>, String)> {
+        let history = db_store.get_request_history(request_id).await.unwrap();
+        history.into_iter().map(|event| (event.from_status, event.to_status)).collect()
+    }
+
+    fn transition(from_status: &str, to_status: &str) -> (Option<String>, String) {
+        (Some(from_status.to_string()), to_status.to_string())
+    }
+
+    #[tokio::test]
+    async fn request_without_a_format_uses_the_report_default() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::clone(&notifier), &export_dir);
+        let payload = serde_json::json!({"start_date": "2024-01-01", "end_date": "2024-01-31"});
+        let request_id = db_store.insert(ExportRequest::for_test(payload), ExportStatus::Pending);
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        assert_eq!(request.status, ExportStatus::Completed);
+        assert!(request.file_path.unwrap().ends_with(".xlsx"));
+        let notification = notifier.last_notification(request_id).unwrap();
+        assert_eq!(notification["content_type"], ExportFormat::Xlsx.content_type());
+        assert!(notification["file_name"].as_str().unwrap().ends_with(".xlsx"), "{}", notification);
+    }
+
+    #[tokio::test]
+    async fn payload_format_overrides_the_report_default() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::clone(&notifier), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        assert_eq!(request.status, ExportStatus::Completed);
+        assert!(request.file_path.unwrap().ends_with(".csv"));
+        let notification = notifier.last_notification(request_id).unwrap();
+        assert_eq!(notification["content_type"], "text/csv; charset=utf-8");
+        assert!(notification["file_name"].as_str().unwrap().ends_with(".csv"), "{}", notification);
+    }
+
+    #[tokio::test]
+    async fn completion_notification_describes_the_exported_file() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(4);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::clone(&notifier), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        let file_size = std::fs::metadata(request.file_path.unwrap()).unwrap().len();
+        let notification = notifier.last_notification(request_id).unwrap();
+        assert_eq!(notification["stage"], "completed");
+        assert_eq!(notification["file_size_bytes"], file_size);
+        assert_eq!(notification["rows_exported"], 4);
+    }
+
+    #[tokio::test]
+    async fn gzip_export_reports_the_uncompressed_file_and_checksums_the_download() {
+        use sha2::{Digest, Sha256};
+        use std::io::Read;
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(20);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::clone(&notifier), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({"compression": "gzip"}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        let path = request.file_path.unwrap();
+        assert!(path.ends_with(".csv.gz"), "{}", path);
+        let download = std::fs::read(&path).unwrap();
+        let mut csv = Vec::new();
+        flate2::read::GzDecoder::new(download.as_slice()).read_to_end(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv.clone()).unwrap().lines().count(), 21);
+        assert_eq!(request.original_file_name, Some(format!("{}.csv", request_id)));
+        assert_eq!(request.original_size_bytes, Some(csv.len() as i64));
+        assert_eq!(request.file_size_bytes, Some(download.len() as i64));
+        assert_eq!(request.file_checksum, Some(format!("{:x}", Sha256::digest(&download))));
+        let notification = notifier.last_notification(request_id).unwrap();
+        assert_eq!(notification["content_type"], "application/gzip");
+        assert_eq!(notification["original_file_name"], format!("{}.csv", request_id));
+        assert_eq!(notification["original_size_bytes"], csv.len());
+        assert_eq!(notification["file_size_bytes"], download.len());
+    }
+
+    #[tokio::test]
+    async fn csv_export_is_described_by_a_metadata_file_with_its_checksum() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        let file_path = request.file_path.unwrap();
+        let metadata: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(format!("{}.meta.json", file_path)).unwrap()).unwrap();
+        assert_eq!(metadata["request_id"], request_id.to_string());
+        assert_eq!(metadata["user_id"], request.user_id);
+        assert_eq!(metadata["row_count"], 3);
+        assert_eq!(metadata["timezone"], "UTC");
+        assert_eq!(metadata["parameters"]["start_date"], "2024-01-01");
+        assert_eq!(metadata["file_name"], format!("{}.csv", request_id));
+        assert_eq!(metadata["sha256"], request.file_checksum.unwrap());
+        assert_eq!(metadata["checksum_note"], format!("sha256 is the SHA-256 checksum of {}.csv", request_id));
+    }
+
+    #[tokio::test]
+    async fn export_metadata_can_be_turned_off() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let mut config = AppConfig::for_test(&export_dir.path());
+        config.export_metadata = false;
+        let service = service_with_config(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), config);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let file_path = db_store.request(request_id).unwrap().file_path.unwrap();
+        assert!(std::path::Path::new(&file_path).exists());
+        assert!(!std::path::Path::new(&format!("{}.meta.json", file_path)).exists());
+    }
+
+    #[tokio::test]
+    async fn identical_exports_of_different_users_share_a_content_named_file() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let mut config = AppConfig::for_test(&export_dir.path());
+        config.content_addressed_file_names = true;
+        let service = service_with_config(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), config);
+        let first_id = pending_csv_request(&db_store, serde_json::json!({}));
+        let mut other_user = ExportRequest::for_test(serde_json::json!({"start_date": "2024-01-01", "end_date": "2024-01-31", "format": "csv"}));
+        other_user.user_id = 43;
+        let second_id = db_store.insert(other_user, ExportStatus::Pending);
+
+        service.process_export_request(first_id, Span::none()).await.unwrap();
+        service.process_export_request(second_id, Span::none()).await.unwrap();
+
+        let first = db_store.request(first_id).unwrap();
+        let second = db_store.request(second_id).unwrap();
+        let file_path = first.file_path.unwrap();
+        let checksum = first.file_checksum.unwrap();
+        assert_eq!(
+            std::path::Path::new(&file_path).file_name().unwrap().to_string_lossy(),
+            format!("{}.csv", &checksum[..16])
+        );
+        assert_eq!(second.status, ExportStatus::Completed);
+        assert_eq!(second.file_path.as_deref(), Some(file_path.as_str()));
+        assert_eq!(second.file_checksum.as_deref(), Some(checksum.as_str()));
+        let csv_files: Vec<_> = walk_files(&export_dir.0)
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|extension| extension == "csv"))
+            .collect();
+        assert_eq!(csv_files, vec![std::path::PathBuf::from(&file_path)]);
+    }
+
+    #[tokio::test]
+    async fn split_export_notifies_a_link_for_every_part() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(1_000);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::clone(&notifier), &export_dir);
+        let max_file_size = crate::models::MIN_MAX_FILE_SIZE_BYTES;
+        let request_id = pending_csv_request(&db_store, serde_json::json!({"max_file_size_bytes": max_file_size}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        assert_eq!(request.status, ExportStatus::Completed);
+        let mut paths = vec![request.file_path.unwrap()];
+        let later_parts: Vec<String> = serde_json::from_value(request.file_parts.unwrap()).unwrap();
+        assert!(!later_parts.is_empty());
+        paths.extend(later_parts);
+        let mut rows = 0;
+        for path in &paths {
+            let csv = std::fs::read_to_string(path).unwrap();
+            assert!(csv.len() as u64 <= max_file_size, "{} has {} bytes", path, csv.len());
+            rows += csv.lines().count() - 1;
+        }
+        assert_eq!(rows, 1_000);
+        let notification = notifier.last_notification(request_id).unwrap();
+        let urls: Vec<String> = serde_json::from_value(notification["file_part_urls"].clone()).unwrap();
+        assert_eq!(urls.len(), paths.len());
+        assert_eq!(notification["file_url"], urls[0]);
+        for (url, path) in urls.iter().zip(&paths) {
+            let file_name = Path::new(path).file_name().unwrap().to_string_lossy();
+            assert!(url.contains(file_name.as_ref()), "{} does not link {}", url, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn split_export_can_be_delivered_as_one_zip() {
+        use std::io::Read;
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(1_000);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let mut config = AppConfig::for_test(&export_dir.path());
+        config.max_file_size_delivery = crate::models::SplitFileDelivery::Zip;
+        let service = service_with_config(Arc::clone(&db_store), local_exporter, Arc::clone(&notifier), config);
+        let max_file_size = crate::models::MIN_MAX_FILE_SIZE_BYTES;
+        let request_id = pending_csv_request(&db_store, serde_json::json!({"max_file_size_bytes": max_file_size}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        let path = request.file_path.unwrap();
+        assert!(path.ends_with(".zip"), "{}", path);
+        assert_eq!(request.file_parts, None);
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert!(archive.len() > 1);
+        let mut rows = 0;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index).unwrap();
+            let expected = if index == 0 { format!("{}.csv", request_id) } else { format!("{}.part{}.csv", request_id, index + 1) };
+            assert_eq!(entry.name(), expected);
+            let mut csv = String::new();
+            entry.read_to_string(&mut csv).unwrap();
+            assert!(csv.starts_with("Product ID,"), "{}", expected);
+            rows += csv.lines().count() - 1;
+        }
+        assert_eq!(rows, 1_000);
+        let notification = notifier.last_notification(request_id).unwrap();
+        assert!(notification.get("file_part_urls").is_none(), "{}", notification);
+        assert_eq!(notification["content_type"], "application/zip");
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    #[tokio::test]
+    async fn format_missing_from_the_build_fails_with_the_available_formats() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({"format": "parquet"}));
+
+        let _ = service.process_export_request(request_id, Span::none()).await;
+
+        let request = db_store.request(request_id).unwrap();
+        assert_eq!(request.status, ExportStatus::Failed);
+        assert_eq!(request.error_code.as_deref(), Some("INVALID_PARAMS"));
+        let message = request.error_message.unwrap();
+        assert!(message.contains("built without the `parquet` feature"), "{}", message);
+        let available = format!("available formats: {}", ExportFormat::available_names().join(", "));
+        assert!(message.contains(&available), "{}", message);
+        assert!(walk_files(&export_dir.0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn successful_export_records_its_status_history() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+        let other_request = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        assert_eq!(
+            transitions(&db_store, request_id).await,
+            vec![transition("PENDING", "PROCESSING"), transition("PROCESSING", "COMPLETED")]
+        );
+        let history = db_store.get_request_history(request_id).await.unwrap();
+        assert!(history.iter().all(|event| event.detail.is_none()));
+        assert!(transitions(&db_store, other_request).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_export_records_the_failure_in_its_status_history() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let service = service(
+            Arc::clone(&db_store),
+            |config| MockFileExporter::new(local_exporter(config)),
+            Arc::new(RecordingNotifier::new()),
+            &export_dir,
+        );
+        service.file_exporter.fault_next(ExportFault::Fail);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        assert_eq!(
+            transitions(&db_store, request_id).await,
+            vec![transition("PENDING", "PROCESSING"), transition("PROCESSING", "FAILED")]
+        );
+        let history = db_store.get_request_history(request_id).await.unwrap();
+        let request = db_store.request(request_id).unwrap();
+        // Chi tiết của sự kiện FAILED là mã lỗi (hoặc thông báo lỗi khi không có mã).
+        assert_eq!(history[1].detail, request.error_code.or(request.error_message));
+        assert!(history[1].detail.is_some());
+    }
+
+    #[tokio::test]
+    async fn successful_export_records_every_phase_timing() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let timings = db_store.timings(request_id).unwrap();
+        assert!(timings.db_fetch_ms.is_some(), "{:?}", timings);
+        assert!(timings.db_query_ms.is_some(), "{:?}", timings);
+        assert!(timings.file_generation_ms.is_some(), "{:?}", timings);
+        assert!(timings.notify_ms.is_some(), "{:?}", timings);
+    }
+
+    #[tokio::test]
+    async fn failed_export_records_the_phases_it_completed() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(3);
+        let service = service(
+            Arc::clone(&db_store),
+            |config| MockFileExporter::new(local_exporter(config)),
+            Arc::new(RecordingNotifier::new()),
+            &export_dir,
+        );
+        service.file_exporter.fault_next(ExportFault::Fail);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Failed);
+        let timings = db_store.timings(request_id).unwrap();
+        assert!(timings.db_fetch_ms.is_some(), "{:?}", timings);
+        assert!(timings.db_query_ms.is_some(), "{:?}", timings);
+        // Tạo file bị lỗi nên không có thời gian của giai đoạn này; thông báo lỗi vẫn được gửi và đo.
+        assert_eq!(timings.file_generation_ms, None);
+        assert!(timings.notify_ms.is_some(), "{:?}", timings);
+    }
+
+    /// Mọi file (không tính thư mục) nằm dưới `dir`.
+    fn walk_files(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|path| if path.is_dir() { walk_files(&path) } else { vec![path] })
+            .collect()
+    }
+
+    /// Ghi log của `future` (chạy trên thread hiện tại) vào một chuỗi, để test kiểm tra được cảnh báo.
+    async fn capture_logs<T>(future: impl std::future::Future<Output = T>) -> (T, String) {
+        #[derive(Clone)]
+        struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+        impl std::io::Write for LogBuffer {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(bytes);
+                Ok(bytes.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = LogBuffer(Arc::default());
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt().with_writer(move || writer.clone()).with_ansi(false).finish();
+        let guard = tracing::subscriber::set_default(subscriber);
+        let output = future.await;
+        drop(guard);
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        (output, logs)
+    }
+
+    fn order(order_id: i64, user_id: i64) -> OrderData {
+        OrderData {
+            order_id,
+            user_id,
+            product_name: format!("Ordered by user {}", user_id),
+            quantity: 1,
+            unit_price: 10.0,
+            total: 10.0,
+            ordered_at: Utc.with_ymd_and_hms(2024, 1, 15, 9, 0, 0).unwrap(),
+            status: "paid".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn owner_only_export_never_contains_other_users_rows() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_order_rows(vec![order(1, 42), order(2, 7), order(3, 42), order(4, 8)]);
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+        // ExportRequest::for_test thuộc về user 42; payload xin đơn hàng của user 7.
+        let request_id = pending_csv_request(&db_store, serde_json::json!({"report_type": "orders", "order_user_id": 7}));
+
+        let (result, logs) = capture_logs(service.process_export_request(request_id, Span::none())).await;
+        result.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        assert_eq!(request.status, ExportStatus::Completed);
+        let csv = std::fs::read_to_string(request.file_path.unwrap()).unwrap();
+        assert_eq!(csv.matches("Ordered by user 42").count(), 2, "{}", csv);
+        assert!(!csv.contains("Ordered by user 7"), "{}", csv);
+        assert!(!csv.contains("Ordered by user 8"), "{}", csv);
+        assert!(
+            logs.contains("WARN") && logs.contains(&format!("Ignoring order_user_id 7 in request {}", request_id)),
+            "{}",
+            logs
+        );
+    }
+
+    fn product_params() -> ReportParams {
+        serde_json::from_value(serde_json::json!({"start_date": "2024-01-01", "end_date": "2024-01-31"})).unwrap()
+    }
+
+    #[tokio::test]
+    async fn product_rows_are_read_lazily_up_to_one_past_the_limit() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(1_000);
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+
+        let rows = service.read_product_rows(&product_params(), 3).await.unwrap();
+
+        assert_eq!(rows.len(), 4);
+        assert_eq!(db_store.streamed_rows(), 4);
+        assert_eq!(db_store.calls("query_product_data"), 0);
+    }
+
+    #[tokio::test]
+    async fn product_export_reads_through_the_stream() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(25);
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+        let request_id = pending_csv_request(&db_store, serde_json::json!({}));
+
+        service.process_export_request(request_id, Span::none()).await.unwrap();
+
+        let request = db_store.request(request_id).unwrap();
+        assert_eq!(request.status, ExportStatus::Completed);
+        assert_eq!(db_store.calls("stream_product_data"), 1);
+        assert_eq!(db_store.calls("query_product_data"), 0);
+        assert_eq!(db_store.streamed_rows(), 25);
+        let csv = std::fs::read_to_string(request.file_path.unwrap()).unwrap();
+        assert_eq!(csv.lines().count(), 26);
+    }
+
+    #[tokio::test]
+    async fn transient_stream_error_restarts_the_product_read() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(5);
+        db_store.fail_next("stream_product_data", DbError::Transient(anyhow::anyhow!("could not serialize access")));
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+
+        let rows = service.read_product_rows(&product_params(), 100).await.unwrap();
+
+        assert_eq!(rows.len(), 5);
+        assert_eq!(db_store.calls("stream_product_data"), 2);
+    }
+
+    #[tokio::test]
+    async fn product_read_gives_up_after_repeated_transient_errors() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(5);
+        for _ in 0..PRODUCT_READ_ATTEMPTS {
+            db_store.fail_next("stream_product_data", DbError::Transient(anyhow::anyhow!("could not serialize access")));
+        }
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+
+        let error = service.read_product_rows(&product_params(), 100).await.unwrap_err();
+
+        assert!(matches!(error, ExportError::DatabaseUnavailable(_)), "{:?}", error);
+        assert_eq!(db_store.calls("stream_product_data"), PRODUCT_READ_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn fatal_stream_error_is_not_retried() {
+        let export_dir = TempDir::new();
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.fail_next("stream_product_data", DbError::Fatal(anyhow::anyhow!("relation \"products\" does not exist")));
+        let service = service(Arc::clone(&db_store), local_exporter, Arc::new(RecordingNotifier::new()), &export_dir);
+
+        let error = service.read_product_rows(&product_params(), 100).await.unwrap_err();
+
+        assert!(matches!(error, ExportError::QueryFailed(_)), "{:?}", error);
+        assert_eq!(db_store.calls("stream_product_data"), 1);
+    }
+}
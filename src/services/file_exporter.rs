@@ -1,87 +1,4893 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use async_compression::tokio::write::GzipEncoder;
+use std::fmt;
 use std::path::Path;
-use tracing::{info, warn};
+use std::pin::Pin;
+use std::task::Poll;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+use tracing::{info, instrument, warn};
 use uuid::Uuid;
 
-use crate::models::ProductData;
+use crate::models::{
+    CellValue, CsvOptions, CsvQuoteStyle, ExcelStyleOptions, ExportFormat, FormulaEscape, OutputCompression, ParquetOptions,
+    PdfOptions, ReportData,
+};
+use crate::services::encryption::{FileEncryption, ENCRYPTED_FILE_EXTENSION};
+use crate::services::pgp_encryption::{PgpEncryption, PGP_FILE_EXTENSION};
+use crate::services::safe_path::check_component;
+use crate::services::html_exporter::HtmlExporter;
+use crate::services::jsonl_exporter::JsonLinesExporter;
 
-/// Trait định nghĩa giao diện cho việc tạo và lưu file Excel.
+/// Trait định nghĩa giao diện cho việc tạo và lưu file export. Mọi định dạng nhận `ReportData`:
+/// dòng của từng dataset (products, orders, customers...) đi qua `ExportRow` và layout cột thành `Vec<CellValue>`,
+/// nên dataset mới không cần thêm method vào trait.
 #[async_trait::async_trait]
 pub trait FileExporter: Send + Sync + 'static {
+    /// Có `metadata` thì workbook bắt đầu bằng sheet "Info" chứa thông tin về lần export.
+    /// Có `max_file_size` thì dữ liệu được chia thành nhiều workbook không lớn hơn số byte này (xem `ExportedFile::next_parts`);
+    /// một dòng không vừa giới hạn trả về lỗi chứa `FileSizeLimitError`.
     async fn export_to_excel(
         &self,
         request_id: Uuid,
-        data: Vec<ProductData>,
+        data: ReportData,
+        style: &ExcelStyleOptions,
+        metadata: Option<&ExportMetadata>,
+        max_file_size: Option<u64>,
         export_path: &str,
-    ) -> Result<String>; // Trả về đường dẫn đầy đủ của file đã tạo
+    ) -> Result<ExportedFile>; // Trả về đường dẫn đầy đủ của file đã tạo, kích thước, số dòng và số worksheet
+
+    /// Ghi dữ liệu vào bản sao của template Excel: header ở ô `{{data}}`, các dòng ngay bên dưới,
+    /// và thay các placeholder còn lại (`{{date_range}}`, `{{generated_at}}`...). File template không bị sửa.
+    /// Template không tồn tại hoặc thiếu ô `{{data}}` trả về lỗi chứa `TemplateError`.
+    async fn export_to_template(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        template: &ExcelTemplate,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile>;
+
+    /// Tạo file CSV từ dữ liệu đã query, ghi từng dòng theo dialect `options` (`.csv.gz` khi nén gzip).
+    /// Giá trị không chuyển được sang bảng mã của `options` trả về lỗi chứa `CsvEncodingError`.
+    /// Có `max_file_size` thì file mới (có header riêng) được bắt đầu trước dòng làm file hiện tại vượt số byte này.
+    async fn export_to_csv(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile>;
+
+    /// Tạo file Parquet từ dữ liệu đã query (cần feature `parquet`).
+    async fn export_to_parquet(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        export_path: &str,
+    ) -> Result<ExportedFile>;
+
+    /// Tạo file ODS (cần feature `ods`) với cùng layout cột, cách tách sheet và màu header như file Excel.
+    async fn export_to_ods(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile>;
+
+    /// Tạo file HTML độc lập (bảng có CSS inline), chỉ gồm các dòng đầu tiên của dữ liệu.
+    async fn export_to_html(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        title: &str,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile>;
+
+    /// Tạo file PDF khổ A4 (cần feature `pdf`): tiêu đề, tóm tắt tham số, bảng dữ liệu và thông tin tạo file.
+    async fn export_to_pdf(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        report: &PdfReport,
+        export_path: &str,
+    ) -> Result<ExportedFile>;
+
+    /// Tạo file JSON Lines (`.jsonl`, hoặc `.jsonl.gz` khi bật gzip) từ dữ liệu đã query,
+    /// chia thành nhiều file như CSV khi có `max_file_size`.
+    async fn export_to_jsonl(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        compression: OutputCompression,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile>;
+
+    /// Mở file CSV để nội dung được ghi thẳng vào (ví dụ từ COPY của Postgres).
+    /// File chỉ xuất hiện ở đường dẫn cuối sau khi gọi `CsvFileWriter::finish`. BOM được ghi khi `options.bom` bật.
+    /// Với gzip, nội dung ghi vào `writer` được nén trong lúc ghi.
+    async fn create_csv_file(
+        &self,
+        request_id: Uuid,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        export_path: &str,
+    ) -> Result<CsvFileWriter>;
+
+    /// Như `create_csv_file`, nhưng nội dung được upload thẳng lên storage từ xa trong lúc ghi (S3 multipart upload)
+    /// thay vì ghi ra đĩa local trước: `CsvFileWriter::finish` hoàn tất upload, writer bị drop khi ghi lỗi thì upload bị hủy.
+    /// File trả về đã nằm ở nơi lưu trữ cuối (`ExportedFile::stored`). `None` khi storage không hỗ trợ (file local).
+    async fn create_streamed_csv_file(
+        &self,
+        _request_id: Uuid,
+        _options: &CsvOptions,
+        _compression: OutputCompression,
+        _export_path: &str,
+    ) -> Result<Option<CsvFileWriter>> {
+        Ok(None)
+    }
+
+    /// Xóa file đã export khỏi storage, trả về số byte được giải phóng.
+    /// File không còn tồn tại được coi là đã xóa (trả về 0).
+    async fn delete_file(&self, file_path: &str) -> Result<u64>;
+
+    /// Xóa tối đa `levels` thư mục cha của file đã xóa (thư mục ngày của EXPORT_DIR_DATE_PATTERN) nếu đã rỗng,
+    /// trả về số thư mục đã xóa. Storage không có thư mục (S3) không cần làm gì.
+    async fn remove_empty_dirs(&self, _file_path: &str, _levels: usize) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Xóa mọi file (kể cả file tạm, ở mọi định dạng) mà exporter có thể đã tạo cho request này.
+    /// Được gọi khi export thất bại, để file hỏng không bị dùng lại hoặc phục vụ cho người dùng.
+    async fn remove_partial_output(&self, request_id: Uuid, export_path: &str) -> Result<u64>;
+
+    /// Tính checksum SHA-256 và kích thước của file đã export.
+    /// Trả về `None` nếu file không còn tồn tại.
+    async fn file_checksum(&self, file_path: &str) -> Result<Option<FileChecksum>>;
+
+    /// File còn tồn tại hay không, không đọc nội dung như `file_checksum`.
+    async fn file_exists(&self, file_path: &str) -> Result<bool>;
+
+    /// Nén file đã export thành file zip cùng tên (`{request_id}.zip`) rồi xóa file gốc.
+    /// Trả về `None` và giữ nguyên file gốc khi file nhỏ hơn `min_size_bytes` hoặc bản nén không nhỏ hơn file gốc.
+    async fn compress_file(&self, request_id: Uuid, file_path: &str, min_size_bytes: u64) -> Result<Option<CompressedFile>>;
+
+    /// Gộp các phần của file bị chia (`part_paths`, theo thứ tự) vào một file zip `{request_id}.zip`, mỗi phần một entry,
+    /// rồi xóa các phần. Có `password` thì zip được mã hóa AES-256 như `encrypt_file`.
+    async fn zip_parts(&self, request_id: Uuid, part_paths: &[String], password: Option<&str>) -> Result<CompressedFile>;
+
+    /// Mã hóa file đã export vào file zip AES-256 (`{request_id}.zip`) bằng `password` rồi xóa file gốc.
+    /// Mật khẩu không được ghi log.
+    async fn encrypt_file(&self, request_id: Uuid, file_path: &str, password: &str) -> Result<CompressedFile>;
+
+    /// Mã hóa file đã tạo bằng khóa đang dùng của `encryption` (ENCRYPTION_KEY) thành `{file_path}.enc`
+    /// rồi xóa file gốc, trả về đường dẫn mới.
+    async fn encrypt_at_rest(&self, file_path: &str, encryption: &FileEncryption) -> Result<String>;
+
+    /// Mã hóa file đã tạo tới khóa PGP `recipient_key_id` thành `{file_path}.gpg` rồi xóa file gốc,
+    /// trả về đường dẫn mới và fingerprint của khóa đã dùng.
+    async fn encrypt_for_recipient(&self, file_path: &str, pgp: &PgpEncryption, recipient_key_id: &str) -> Result<(String, String)>;
+
+    /// Đổi tên file đã tạo thành `{stem}.{phần mở rộng hiện tại}` trong cùng thư mục, trả về đường dẫn mới.
+    /// Tên đã có file khác thì thêm hậu tố (`-{8 ký tự đầu của request_id}`, rồi `-2`, `-3`...), không ghi đè.
+    /// Tên không qua được `safe_path::check_component` trả về lỗi chứa `UnsafePath`.
+    async fn rename_file(&self, request_id: Uuid, file_path: &str, stem: &str) -> Result<String>;
+
+    /// Đổi tên file đã tạo thành tên theo nội dung (`{16 ký tự đầu của SHA-256}.{ext}`) trong cùng thư mục.
+    /// Đã có file cùng nội dung ở tên đó thì file vừa tạo bị xóa và file có sẵn được dùng (`true`);
+    /// tên đó là của file khác nội dung thì thử `{hash}-2.{ext}`, `{hash}-3.{ext}`...
+    async fn rename_by_content(&self, request_id: Uuid, file_path: &str) -> Result<(String, bool)>;
+
+    /// Ghi `metadata` (JSON) vào `{file_path}.meta.json` cạnh file đã tạo, trả về đường dẫn của file metadata.
+    async fn write_metadata_file(&self, file_path: &str, metadata: &ExportMetadata) -> Result<String>;
+
+    /// Đưa file đã tạo (sau khi nén/mã hóa) tới nơi lưu trữ cuối, trả về đường dẫn được lưu vào DB.
+    /// File local đã nằm đúng chỗ nên mặc định trả về nguyên đường dẫn.
+    async fn store_file(&self, _request_id: Uuid, file_path: &str) -> Result<String> {
+        Ok(file_path.to_string())
+    }
+
+    /// Hủy các upload dở dang (multipart upload của S3) bắt đầu trước `before`, trả về số upload đã hủy.
+    /// Storage ghi thẳng file không có upload dở dang.
+    async fn abort_stale_uploads(&self, _before: DateTime<Utc>) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Link download do storage cấp cho file đã lưu (ví dụ presigned URL của S3);
+    /// `None` thì dùng URL công khai dựng từ NOTIFICATION_SERVICE_URL.
+    async fn download_url(&self, _file_path: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// File đã lưu nằm trên filesystem local, đọc được để đính kèm email.
+    fn stores_locally(&self) -> bool {
+        true
+    }
+}
+
+/// Xóa file nếu còn tồn tại, trả về kích thước đã xóa; `None` khi file không tồn tại (không ghi log).
+async fn remove_file_if_exists(path: &str) -> Result<Option<u64>> {
+    let size = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context("Failed to read export file metadata"),
+    };
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => {
+            info!("🗑️ Deleted export file {} ({} bytes).", path, size);
+            Ok(Some(size))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context("Failed to delete export file"),
+    }
+}
+
+/// File export đã tạo, ở đường dẫn cuối. Kích thước và số dòng là của chính file này,
+/// trước khi service nén, mã hóa hoặc đổi tên.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedFile {
+    pub path: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub bytes_written: u64,
+    /// Kích thước trước khi nén khi exporter nén file trong lúc ghi (`.csv.gz`, `.jsonl.gz`).
+    pub uncompressed_bytes: Option<u64>,
+    /// Số dòng dữ liệu đã ghi (không tính header); HTML chỉ gồm các dòng đầu tiên.
+    pub rows_written: u64,
+    /// SHA-256 nếu exporter đã tính trong lúc ghi; `None` thì service tính trên file cuối cùng.
+    pub checksum: Option<String>,
+    /// Số worksheet của file Excel/ODS: dữ liệu vượt quá số dòng tối đa của một sheet được chia sang
+    /// `Data (2)`, `Data (3)`... Các định dạng khác luôn là 1.
+    pub parts: u32,
+    /// Số ô đã bị sửa cho vừa giới hạn của Excel.
+    pub adjusted_cells: CellAdjustments,
+    /// File bị chia theo MAX_FILE_SIZE_BYTES: file này là phần 1, các phần sau (`.part2`, `.part3`...) nằm ở đây.
+    pub next_parts: Vec<ExportedFile>,
+    /// File đã được upload lên nơi lưu trữ cuối trong lúc ghi (`create_streamed_csv_file`): `path` là đường dẫn
+    /// được lưu vào DB và service không gọi `store_file`.
+    pub stored: bool,
+}
+
+impl ExportedFile {
+    /// Đọc kích thước của file đã nằm ở `path`.
+    async fn new(path: String, content_type: impl Into<String>, rows_written: usize, parts: u32) -> Result<Self> {
+        let bytes_written = tokio::fs::metadata(&path)
+            .await
+            .context("Failed to read size of exported file")?
+            .len();
+        let file_name = Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Self {
+            path,
+            file_name,
+            content_type: content_type.into(),
+            bytes_written,
+            uncompressed_bytes: None,
+            rows_written: rows_written as u64,
+            checksum: None,
+            parts,
+            adjusted_cells: CellAdjustments::default(),
+            next_parts: Vec::new(),
+            stored: false,
+        })
+    }
+
+    /// Các phần của file đã bị chia, theo thứ tự: phần đầu mang các phần còn lại trong `next_parts`,
+    /// và số ô bị sửa của cả file.
+    fn from_parts(mut parts: Vec<ExportedFile>) -> Self {
+        let mut first = parts.remove(0);
+        for part in &parts {
+            first.adjusted_cells += part.adjusted_cells;
+        }
+        first.next_parts = parts;
+        first
+    }
+
+    /// Đường dẫn của mọi phần, bắt đầu bằng file này.
+    pub fn part_paths(&self) -> Vec<String> {
+        std::iter::once(self).chain(&self.next_parts).map(|part| part.path.clone()).collect()
+    }
+
+    /// Số dòng dữ liệu của mọi phần.
+    pub fn total_rows(&self) -> u64 {
+        self.rows_written + self.next_parts.iter().map(|part| part.rows_written).sum::<u64>()
+    }
+}
+
+/// Số ô có giá trị Excel không ghi được nguyên vẹn, theo cách đã xử lý (xem `fit_excel_cell`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CellAdjustments {
+    /// Chuỗi dài hơn `EXCEL_MAX_CELL_CHARS`, bị cắt và thêm marker.
+    pub truncated: u64,
+    /// Chuỗi chứa ký tự điều khiển không hợp lệ trong XML, đã được thay bằng U+FFFD.
+    pub invalid_chars: u64,
+    /// Số vô hạn hoặc vượt quá giới hạn của Excel, đã được giới hạn lại (NaN ghi thành ô trống).
+    pub clamped_numbers: u64,
+    /// Liên kết vượt giới hạn hyperlink của Excel (độ dài URL hoặc số liên kết trên một sheet), đã được ghi dạng chữ.
+    pub links_as_text: u64,
+}
+
+impl CellAdjustments {
+    pub fn is_empty(&self) -> bool {
+        *self == CellAdjustments::default()
+    }
+}
+
+impl std::ops::AddAssign for CellAdjustments {
+    fn add_assign(&mut self, other: Self) {
+        self.truncated += other.truncated;
+        self.invalid_chars += other.invalid_chars;
+        self.clamped_numbers += other.clamped_numbers;
+        self.links_as_text += other.links_as_text;
+    }
+}
+
+impl fmt::Display for CellAdjustments {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} truncated string(s), {} string(s) with invalid characters, {} clamped number(s), {} link(s) written as text",
+            self.truncated, self.invalid_chars, self.clamped_numbers, self.links_as_text
+        )
+    }
+}
+
+/// File zip đã thay thế file export, kèm tên và kích thước của file gốc (nằm bên trong zip).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedFile {
+    pub path: String,
+    pub original_file_name: String,
+    pub original_size_bytes: u64,
+}
+
+/// Template Excel của một loại report (`template_path` trong REPORT_TYPE_SETTINGS) và giá trị các placeholder.
+#[derive(Debug, Clone)]
+pub struct ExcelTemplate {
+    pub path: String,
+    /// Cặp (key, giá trị): mọi `{{key}}` trong các ô chữ của template được thay bằng giá trị.
+    pub placeholders: Vec<(String, String)>,
+}
+
+/// Phần chữ của file PDF ngoài bảng dữ liệu.
+#[derive(Debug, Clone)]
+pub struct PdfReport {
+    pub title: String,
+    /// Cặp (nhãn, giá trị) của tham số report, in dưới tiêu đề.
+    pub parameters: Vec<(String, String)>,
+    /// Cặp (nhãn, giá trị) về lần tạo file (thời điểm, request id, số dòng), in dưới bảng.
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Thông tin về lần export, ghi vào sheet "Info" của file Excel hoặc file `.meta.json` đi kèm file CSV/JSON Lines
+/// (EXPORT_METADATA). Không chứa mật khẩu của `protection`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportMetadata {
+    pub request_id: Uuid,
+    pub user_id: i64,
+    pub report_type: String,
+    /// Tham số ảnh hưởng tới nội dung file (`ReportParams::content_parameters`).
+    pub parameters: serde_json::Map<String, serde_json::Value>,
+    pub generated_at: DateTime<Utc>,
+    /// Múi giờ của ngày giờ trong file (`timezone` của payload, mặc định `UTC`).
+    pub timezone: String,
+    pub row_count: u64,
+    pub service_version: String,
+    /// Tên file được giao (đã nén/mã hóa), chỉ có trong file `.meta.json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    /// SHA-256 của file được giao, chỉ có trong file `.meta.json`: file Excel không chứa được checksum của chính nó.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    pub checksum_note: String,
+}
+
+/// Phần mở rộng của file metadata đi kèm file CSV/JSON Lines (`{tên file}.meta.json`).
+pub const METADATA_FILE_SUFFIX: &str = "meta.json";
+
+/// Ô đánh dấu vị trí ghi dữ liệu trong template.
+pub const TEMPLATE_DATA_MARKER: &str = "{{data}}";
+
+/// Template không dùng được: không tồn tại, không đọc được, thiếu ô `{{data}}` hoặc không đủ chỗ cho dữ liệu.
+#[derive(Debug)]
+pub struct TemplateError(pub String);
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Ô CSV có ký tự không biểu diễn được trong bảng mã của file (`encoding` của `CsvOptions`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvEncodingError {
+    /// Số thứ tự dòng dữ liệu (từ 1); 0 là dòng header.
+    pub row: usize,
+    pub column: String,
+    pub character: char,
+    pub encoding: &'static str,
+}
+
+impl fmt::Display for CsvEncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = if self.row == 0 { "the header".to_string() } else { format!("row {}", self.row) };
+        write!(
+            f,
+            "{:?} (U+{:04X}) in {} of column '{}' cannot be encoded as {}",
+            self.character, self.character as u32, row, self.column, self.encoding
+        )
+    }
+}
+
+impl std::error::Error for CsvEncodingError {}
+
+/// Một dòng dữ liệu (kèm header) đã lớn hơn kích thước tối đa của một file (`max_file_size_bytes`),
+/// nên file không chia được thành các phần vừa giới hạn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSizeLimitError {
+    /// Số thứ tự dòng dữ liệu (từ 1).
+    pub row: usize,
+    pub max_bytes: u64,
+}
+
+impl fmt::Display for FileSizeLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "row {} does not fit in a file of at most {} bytes", self.row, self.max_bytes)
+    }
+}
+
+impl std::error::Error for FileSizeLimitError {}
+
+/// Checksum và kích thước của một file đã export, được lưu lại để kiểm tra khi xử lý lại request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChecksum {
+    pub sha256: String,
+    pub size_bytes: u64,
+}
+
+/// File CSV đang được ghi vào file tạm `.partial`, hoặc upload thẳng lên storage từ xa.
+pub struct CsvFileWriter {
+    writer: CountingWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    target: CsvFileTarget,
+    compression: OutputCompression,
+    content_type: String,
+}
+
+enum CsvFileTarget {
+    Local {
+        /// Handle thứ hai của file tạm, để sync sau khi đã đóng luồng ghi (và luồng gzip).
+        file: tokio::fs::File,
+        partial_path: String,
+        full_path: String,
+    },
+    Streamed(Box<dyn StreamedUpload>),
+}
+
+/// Upload đang nhận nội dung của một `CsvFileWriter` (xem `FileExporter::create_streamed_csv_file`).
+/// Bị drop mà chưa gọi `complete` (file ghi lỗi) thì upload bị hủy.
+#[async_trait::async_trait]
+pub trait StreamedUpload: Send {
+    /// Hoàn tất upload sau khi luồng ghi đã đóng, trả về đường dẫn được lưu vào DB và checksum của nội dung đã upload.
+    async fn complete(self: Box<Self>) -> Result<(String, FileChecksum)>;
+}
+
+impl CsvFileWriter {
+    pub fn writer(&mut self) -> &mut (dyn AsyncWrite + Send + Unpin) {
+        &mut self.writer
+    }
+
+    /// Số byte đã ghi vào `writer` (trước khi nén khi bật gzip), kể cả BOM.
+    fn bytes_written(&self) -> u64 {
+        self.writer.bytes
+    }
+
+    /// Flush (và ghi phần cuối của luồng gzip) rồi đổi tên file tạm thành file cuối, hoặc hoàn tất upload.
+    /// `rows_written` là số dòng dữ liệu đã ghi vào `writer`. Khi lỗi, file tạm được dọn bởi `remove_partial_output`.
+    pub async fn finish(mut self, rows_written: usize) -> Result<ExportedFile> {
+        self.writer.shutdown().await.context("Failed to flush CSV file")?;
+        let uncompressed_bytes = (self.compression != OutputCompression::None).then_some(self.writer.bytes);
+        match self.target {
+            CsvFileTarget::Local { file, partial_path, full_path } => {
+                file.sync_all().await.context("Failed to sync CSV file")?;
+                tokio::fs::rename(&partial_path, &full_path)
+                    .await
+                    .context("Failed to move generated CSV file into place")?;
+                sync_parent_dir(&full_path).await;
+                info!("✅ CSV file successfully created at: {}", full_path);
+                let file = ExportedFile::new(full_path, self.content_type, rows_written, 1).await?;
+                Ok(ExportedFile { uncompressed_bytes, ..file })
+            }
+            CsvFileTarget::Streamed(upload) => {
+                let (path, checksum) = upload.complete().await?;
+                info!("✅ CSV file successfully uploaded to: {}", path);
+                let file_name = path.rsplit('/').next().unwrap_or_default().to_string();
+                Ok(ExportedFile {
+                    path,
+                    file_name,
+                    content_type: self.content_type,
+                    bytes_written: checksum.size_bytes,
+                    uncompressed_bytes,
+                    rows_written: rows_written as u64,
+                    checksum: Some(checksum.sha256),
+                    parts: 1,
+                    adjusted_cells: CellAdjustments::default(),
+                    next_parts: Vec::new(),
+                    stored: true,
+                })
+            }
+        }
+    }
+}
+
+/// Đếm số byte được ghi qua writer, tức kích thước trước khi nén khi writer bên trong nén gzip.
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.bytes += written as u64;
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
 }
 
+/// UTF-8 byte order mark. Excel chỉ đọc file CSV là UTF-8 khi có BOM, nếu không sẽ dùng bảng mã của máy.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Tên sheet chứa biểu đồ (`include_chart` của payload).
+#[cfg(feature = "xlsxwriter")]
+const CHART_SHEET_NAME: &str = "Chart";
+
+/// Số ký tự tối đa của một ô Excel.
+pub const EXCEL_MAX_CELL_CHARS: usize = 32_767;
+
+/// Giá trị tuyệt đối lớn nhất của một ô số Excel.
+#[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+const EXCEL_MAX_NUMBER: f64 = 9.999_999_999_999_99e307;
+
+/// Số dòng tối đa của một worksheet Excel (kể cả dòng header).
+pub const EXCEL_MAX_SHEET_ROWS: u32 = 1_048_576;
+
+/// Số hyperlink tối đa của một worksheet Excel; các ô liên kết sau đó được ghi dạng chữ.
+#[cfg(feature = "xlsxwriter")]
+const EXCEL_MAX_SHEET_HYPERLINKS: u32 = 65_530;
+
+/// Độ dài tối đa của URL trong một hyperlink Excel.
+#[cfg(feature = "xlsxwriter")]
+const EXCEL_MAX_URL_CHARS: usize = 2_079;
+
+/// Phần của MAX_FILE_SIZE_BYTES dùng khi ước lượng số dòng của một file xlsx, chừa chỗ cho sai số của ước lượng.
+#[cfg(feature = "xlsxwriter")]
+const XLSX_SPLIT_MARGIN: f64 = 0.95;
+
+/// Số dòng tối đa chờ trong kênh giữa task dựng dòng và thread ghi workbook: bên dựng dòng phải chờ
+/// (nhường worker của Tokio) khi thread ghi chậm hơn, thay vì dựng trước toàn bộ ô của report.
+#[cfg(feature = "xlsxwriter")]
+const XLSX_ROW_CHANNEL_CAPACITY: usize = 1024;
+
 /// Implementation cụ thể để tạo và lưu file Excel cục bộ.
-pub struct LocalFileExporter;
+pub struct LocalFileExporter {
+    /// Xử lý ô chữ bắt đầu bằng ký tự công thức trong file CSV, Excel và ODS.
+    formula_escape: FormulaEscape,
+    /// Số dòng dữ liệu tối đa của một worksheet (không tính header).
+    max_rows_per_sheet: u32,
+    /// Thuật toán nén và kích thước row group của file Parquet.
+    parquet: ParquetOptions,
+    /// Số dòng tối đa của file HTML.
+    html_max_rows: usize,
+    /// Font dùng cho file PDF.
+    pdf: PdfOptions,
+}
+
+impl LocalFileExporter {
+    pub fn new(
+        formula_escape: FormulaEscape,
+        max_rows_per_sheet: u32,
+        parquet: ParquetOptions,
+        html_max_rows: usize,
+        pdf: PdfOptions,
+    ) -> Self {
+        Self { formula_escape, max_rows_per_sheet, parquet, html_max_rows, pdf }
+    }
+}
 
 #[async_trait::async_trait]
 impl FileExporter for LocalFileExporter {
-    #[instrument(skip(self, data, export_path), fields(request_id = %request_id))]
+    #[instrument(skip(self, data, style, metadata, export_path), fields(request_id = %request_id))]
     async fn export_to_excel(
         &self,
         request_id: Uuid,
-        data: Vec<ProductData>,
+        data: ReportData,
+        style: &ExcelStyleOptions,
+        metadata: Option<&ExportMetadata>,
+        max_file_size: Option<u64>,
         export_path: &str,
-    ) -> Result<String> {
-        let filename = format!("{}.xlsx", request_id);
-        let full_path = format!("{}/{}", export_path, filename);
+    ) -> Result<ExportedFile> {
+        let full_path = output_file_path(export_path, request_id, ExportFormat::Xlsx);
+        let partial_path = partial_file_path(&full_path);
 
         tokio::fs::create_dir_all(export_path)
             .await
             .context("Failed to create export directory")?;
 
-        #[cfg(feature = "xlsxwriter")]
-        {
-            use xlsxwriter::Workbook;
-            info!("Creating Excel file at: {}", full_path);
-            let workbook = Workbook::new(&full_path)?;
-            let mut sheet = workbook.add_worksheet(None)?;
-
-            // Write header
-            sheet.write_string(0, 0, "Product ID", None)?;
-            sheet.write_string(0, 1, "Name", None)?;
-            sheet.write_string(0, 2, "Category", None)?;
-            sheet.write_string(0, 3, "Price", None)?;
-            sheet.write_string(0, 4, "Stock Quantity", None)?;
-            sheet.write_string(0, 5, "Created At", None)?;
-
-            // Write data
-            for (i, row) in data.iter().enumerate() {
-                let row_num = (i + 1) as u32;
-                sheet.write_number(row_num, 0, row.product_id as f64, None)?;
-                sheet.write_string(row_num, 1, &row.name, None)?;
-                sheet.write_string(row_num, 2, &row.category, None)?;
-                sheet.write_number(row_num, 3, row.price, None)?;
-                sheet.write_number(row_num, 4, row.stock_quantity as f64, None)?;
-                sheet.write_string(row_num, 5, &row.created_at.to_string(), None)?;
-            }
-
-            workbook.close().context("Failed to close Excel workbook")?;
-            info!("✅ Excel file successfully created at: {}", full_path);
-        }
-        #[cfg(not(feature = "xlsxwriter"))]
-        {
-            warn!("`xlsxwriter` feature not enabled. Using placeholder file creation. For full functionality, enable it in Cargo.toml.");
-            tokio::fs::write(&full_path, format!("Placeholder Excel content for request {}.\n", request_id))
-                .await
-                .context("Failed to write placeholder Excel file")?;
-            for row in data {
-                tokio::fs::OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&full_path)
-                    .await?
-                    .write_all(format!("{:?}\n", row).as_bytes())
-                    .await?;
+        if let Some(max_bytes) = max_file_size {
+            let write_result = write_workbook_parts(
+                request_id,
+                &full_path,
+                data,
+                style,
+                metadata,
+                self.formula_escape,
+                self.max_rows_per_sheet,
+                max_bytes,
+            )
+            .await;
+            let mut parts = Vec::new();
+            for (path, (rows, sheet_count, adjusted_cells)) in move_parts_into_place(write_result, &full_path).await? {
+                let file = ExportedFile::new(path, ExportFormat::Xlsx.content_type(), rows, sheet_count).await?;
+                parts.push(ExportedFile { adjusted_cells, ..file });
+            }
+            if parts.len() > 1 {
+                info!("✂️ Split Excel file for request {} into {} files of at most {} bytes.", request_id, parts.len(), max_bytes);
+            }
+            return Ok(ExportedFile::from_parts(parts));
+        }
+
+        // Ghi vào file tạm rồi mới đổi tên, để file `{request_id}.xlsx` không bao giờ ở trạng thái ghi dở.
+        let rows = data.len();
+        let write_result =
+            write_workbook(request_id, &partial_path, data, style, metadata, self.formula_escape, self.max_rows_per_sheet).await;
+        let (sheet_count, adjusted_cells) = move_into_place(write_result, &partial_path, &full_path).await?;
+
+        let file = ExportedFile::new(full_path, ExportFormat::Xlsx.content_type(), rows, sheet_count).await?;
+        Ok(ExportedFile { adjusted_cells, ..file })
+    }
+
+    #[instrument(skip(self, data, template, style, export_path), fields(request_id = %request_id))]
+    async fn export_to_template(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        template: &ExcelTemplate,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        let full_path = output_file_path(export_path, request_id, ExportFormat::Xlsx);
+        let partial_path = partial_file_path(&full_path);
+
+        tokio::fs::create_dir_all(export_path)
+            .await
+            .context("Failed to create export directory")?;
+
+        info!("Filling Excel template {} for request {} at: {}", template.path, request_id, partial_path);
+        // umya_spreadsheet chỉ có API đồng bộ: đọc và ghi template trong thread blocking như file Excel thường.
+        let rows = data.len();
+        let (path, template, style, escape) = (partial_path.clone(), template.clone(), style.clone(), self.formula_escape);
+        let write_result = tokio::task::spawn_blocking(move || fill_template(&path, &data, &template, &style, escape))
+            .await
+            .context("Excel template task panicked")
+            .and_then(|result| result);
+        let (sheet_count, adjusted_cells) = move_into_place(write_result, &partial_path, &full_path).await?;
+        info!("✅ Excel file successfully created from template at: {}", full_path);
+
+        let file = ExportedFile::new(full_path, ExportFormat::Xlsx.content_type(), rows, sheet_count).await?;
+        Ok(ExportedFile { adjusted_cells, ..file })
+    }
+
+    #[instrument(skip(self, data, options, export_path), fields(request_id = %request_id))]
+    async fn export_to_csv(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        info!("Creating CSV file for request {} in: {}", request_id, export_path);
+        let Some(max_bytes) = max_file_size else {
+            let mut output = self.create_csv_file(request_id, options, compression, export_path).await?;
+            write_csv(output.writer(), &data, options, self.formula_escape).await?;
+            return output.finish(data.len()).await;
+        };
+
+        // Mỗi phần bắt đầu bằng BOM và header của nó. Với gzip, giới hạn được so với số byte trước khi nén
+        // (`CsvFileWriter::bytes_written`), nên phần nén thường nhỏ hơn giới hạn nhiều.
+        tokio::fs::create_dir_all(export_path)
+            .await
+            .context("Failed to create export directory")?;
+        let full_path = csv_file_path(export_path, request_id, compression);
+        let (header, rows) = csv_lines(&data, options, self.formula_escape)?;
+        let header = header.unwrap_or_default();
+        let mut parts = Vec::new();
+        let mut output = open_csv_file(full_path.clone(), options, compression).await?;
+        output.writer().write_all(&header).await.context("Failed to write CSV header")?;
+        let mut rows_in_part = 0;
+        for (index, line) in rows.enumerate() {
+            let line = line?;
+            if rows_in_part > 0 && output.bytes_written() + line.len() as u64 > max_bytes {
+                parts.push(output.finish(rows_in_part).await?);
+                output = open_csv_file(part_file_path(&full_path, parts.len() as u32 + 1), options, compression).await?;
+                output.writer().write_all(&header).await.context("Failed to write CSV header")?;
+                rows_in_part = 0;
+            }
+            if output.bytes_written() + line.len() as u64 > max_bytes {
+                return Err(FileSizeLimitError { row: index + 1, max_bytes }.into());
+            }
+            output.writer().write_all(&line).await.context("Failed to write CSV row")?;
+            rows_in_part += 1;
+        }
+        parts.push(output.finish(rows_in_part).await?);
+        if parts.len() > 1 {
+            info!("✂️ Split CSV file for request {} into {} files of at most {} bytes.", request_id, parts.len(), max_bytes);
+        }
+        Ok(ExportedFile::from_parts(parts))
+    }
+
+    #[instrument(skip(self, data, export_path), fields(request_id = %request_id))]
+    async fn export_to_parquet(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        let full_path = output_file_path(export_path, request_id, ExportFormat::Parquet);
+        let partial_path = partial_file_path(&full_path);
+
+        tokio::fs::create_dir_all(export_path)
+            .await
+            .context("Failed to create export directory")?;
+
+        info!("Creating Parquet file for request {} at: {}", request_id, partial_path);
+        let rows = data.len();
+        let write_result = write_parquet(&partial_path, data, self.parquet).await;
+        move_into_place(write_result, &partial_path, &full_path).await?;
+        ExportedFile::new(full_path, ExportFormat::Parquet.content_type(), rows, 1).await
+    }
+
+    #[instrument(skip(self, data, style, export_path), fields(request_id = %request_id))]
+    async fn export_to_ods(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        let full_path = output_file_path(export_path, request_id, ExportFormat::Ods);
+        let partial_path = partial_file_path(&full_path);
+
+        tokio::fs::create_dir_all(export_path)
+            .await
+            .context("Failed to create export directory")?;
+
+        info!("Creating ODS file for request {} at: {}", request_id, partial_path);
+        let rows = data.len();
+        let write_result = write_ods_file(&partial_path, data, style, self.formula_escape, self.max_rows_per_sheet).await;
+        let sheet_count = move_into_place(write_result, &partial_path, &full_path).await?;
+        ExportedFile::new(full_path, ExportFormat::Ods.content_type(), rows, sheet_count).await
+    }
+
+    #[instrument(skip(self, data, style, export_path), fields(request_id = %request_id))]
+    async fn export_to_html(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        title: &str,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        let full_path = output_file_path(export_path, request_id, ExportFormat::Html);
+        let partial_path = partial_file_path(&full_path);
+
+        tokio::fs::create_dir_all(export_path)
+            .await
+            .context("Failed to create export directory")?;
+
+        let html = HtmlExporter::new(self.html_max_rows, style).render(title, &data);
+        let write_result = tokio::fs::write(&partial_path, html)
+            .await
+            .context("Failed to write HTML file");
+        move_into_place(write_result, &partial_path, &full_path).await?;
+        info!("✅ HTML file successfully created at: {}", full_path);
+        let rows = data.len().min(self.html_max_rows);
+        ExportedFile::new(full_path, ExportFormat::Html.content_type(), rows, 1).await
+    }
+
+    #[instrument(skip(self, data, report, export_path), fields(request_id = %request_id))]
+    async fn export_to_pdf(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        report: &PdfReport,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        let full_path = output_file_path(export_path, request_id, ExportFormat::Pdf);
+        let partial_path = partial_file_path(&full_path);
+
+        tokio::fs::create_dir_all(export_path)
+            .await
+            .context("Failed to create export directory")?;
+
+        info!("Creating PDF file for request {} at: {}", request_id, partial_path);
+        let rows = data.len();
+        let write_result = write_pdf(&partial_path, data, report, &self.pdf).await;
+        move_into_place(write_result, &partial_path, &full_path).await?;
+        ExportedFile::new(full_path, ExportFormat::Pdf.content_type(), rows, 1).await
+    }
+
+    #[instrument(skip(self, data, export_path), fields(request_id = %request_id))]
+    async fn export_to_jsonl(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        compression: OutputCompression,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        let exporter = JsonLinesExporter::new(compression);
+        let full_path = format!("{}/{}.{}", export_path, request_id, exporter.extension());
+        let partial_path = partial_file_path(&full_path);
+
+        tokio::fs::create_dir_all(export_path)
+            .await
+            .context("Failed to create export directory")?;
+
+        let content_type = compressed_content_type(ExportFormat::Jsonl, compression);
+        if let Some(max_bytes) = max_file_size {
+            info!("Creating JSON Lines files of at most {} bytes for request {} at: {}", max_bytes, request_id, partial_path);
+            let base_path = full_path.clone();
+            let write_result = tokio::task::spawn_blocking(move || {
+                exporter.write_parts(|part| partial_file_path(&part_file_path(&base_path, part)), &data, max_bytes)
+            })
+            .await
+            .context("JSON Lines task panicked")
+            .and_then(|result| result);
+            let mut parts = Vec::new();
+            for (path, (rows, bytes)) in move_parts_into_place(write_result, &full_path).await? {
+                let file = ExportedFile::new(path, content_type, rows, 1).await?;
+                let uncompressed_bytes = (compression != OutputCompression::None).then_some(bytes);
+                parts.push(ExportedFile { uncompressed_bytes, ..file });
             }
-            info!("✅ Placeholder file created at: {}", full_path);
+            return Ok(ExportedFile::from_parts(parts));
         }
 
-        Ok(full_path)
+        info!("Creating JSON Lines file for request {} at: {}", request_id, partial_path);
+        // Ghi trong thread blocking: encoder gzip chỉ có API đồng bộ.
+        let path = partial_path.clone();
+        let write_result = tokio::task::spawn_blocking(move || exporter.write(&path, &data))
+            .await
+            .context("JSON Lines task panicked")
+            .and_then(|result| result);
+        let (rows, bytes) = move_into_place(write_result, &partial_path, &full_path).await?;
+        let file = ExportedFile::new(full_path, content_type, rows, 1).await?;
+        let uncompressed_bytes = (compression != OutputCompression::None).then_some(bytes);
+        Ok(ExportedFile { uncompressed_bytes, ..file })
+    }
+
+    #[instrument(skip(self, options))]
+    async fn create_csv_file(
+        &self,
+        request_id: Uuid,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        export_path: &str,
+    ) -> Result<CsvFileWriter> {
+        tokio::fs::create_dir_all(export_path)
+            .await
+            .context("Failed to create export directory")?;
+        open_csv_file(csv_file_path(export_path, request_id, compression), options, compression).await
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_partial_output(&self, request_id: Uuid, export_path: &str) -> Result<u64> {
+        let mut removed_bytes = 0;
+        let extra_paths = ["zip", "csv.gz", "jsonl.gz"].map(|extension| format!("{}/{}.{}", export_path, request_id, extension));
+        let formats = [ExportFormat::Xlsx, ExportFormat::Csv, ExportFormat::Parquet, ExportFormat::Jsonl, ExportFormat::Ods, ExportFormat::Html, ExportFormat::Pdf];
+        let base_paths: Vec<String> = formats
+            .into_iter()
+            .map(|format| output_file_path(export_path, request_id, format))
+            .chain(extra_paths)
+            .collect();
+        // Các phần của file bị chia theo MAX_FILE_SIZE_BYTES được đánh số liên tục từ 2:
+        // dừng ở số phần đầu tiên không còn file nào.
+        for part in 1.. {
+            // Mỗi file còn có thể đã được mã hóa khi lưu (`.enc`) hoặc tới khóa PGP (`.gpg`), và có file `.meta.json` đi kèm.
+            let full_paths = base_paths
+                .iter()
+                .map(|path| part_file_path(path, part))
+                .flat_map(|path| {
+                    [
+                        format!("{}.{}", path, ENCRYPTED_FILE_EXTENSION),
+                        format!("{}.{}", path, PGP_FILE_EXTENSION),
+                        path,
+                    ]
+                })
+                .flat_map(|path| [format!("{}.{}", path, METADATA_FILE_SUFFIX), path]);
+            // Hầu hết các đường dẫn ứng viên không tồn tại: chỉ lỗi I/O thật mới được ghi log, và không làm dừng việc dọn các file còn lại.
+            let mut found = false;
+            for full_path in full_paths {
+                for path in [partial_file_path(&full_path), full_path] {
+                    match remove_file_if_exists(&path).await {
+                        Ok(Some(size)) => {
+                            found = true;
+                            removed_bytes += size;
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            found = true;
+                            warn!("Failed to remove partial output file {}: {:?}", path, e);
+                        }
+                    }
+                }
+            }
+            if !found && part > 1 {
+                break;
+            }
+        }
+        Ok(removed_bytes)
     }
-}
\ No newline at end of file
+
+    #[instrument(skip(self))]
+    async fn delete_file(&self, file_path: &str) -> Result<u64> {
+        let size = match tokio::fs::metadata(file_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("File {} no longer exists, treating as already deleted.", file_path);
+                return Ok(0);
+            }
+            Err(e) => return Err(e).context("Failed to read export file metadata"),
+        };
+
+        tokio::fs::remove_file(file_path)
+            .await
+            .context("Failed to delete export file")?;
+        info!("🗑️ Deleted export file {} ({} bytes).", file_path, size);
+        Ok(size)
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_empty_dirs(&self, file_path: &str, levels: usize) -> Result<u64> {
+        let mut removed = 0;
+        for dir in Path::new(file_path).ancestors().skip(1).take(levels) {
+            match tokio::fs::remove_dir(dir).await {
+                Ok(()) => {
+                    info!("🗑️ Removed empty export directory {}.", dir.display());
+                    removed += 1;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                // Thư mục còn file (hoặc không xóa được) thì các thư mục cha cũng không rỗng.
+                Err(_) => break,
+            }
+        }
+        Ok(removed)
+    }
+
+    #[instrument(skip(self))]
+    async fn file_exists(&self, file_path: &str) -> Result<bool> {
+        tokio::fs::try_exists(file_path)
+            .await
+            .context("Failed to check whether export file exists")
+    }
+
+    #[instrument(skip(self))]
+    async fn file_checksum(&self, file_path: &str) -> Result<Option<FileChecksum>> {
+        let mut file = match tokio::fs::File::open(file_path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Failed to open export file for checksum"),
+        };
+
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut size_bytes: u64 = 0;
+        loop {
+            let read = file
+                .read(&mut buffer)
+                .await
+                .context("Failed to read export file for checksum")?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            size_bytes += read as u64;
+        }
+
+        Ok(Some(FileChecksum {
+            sha256: format!("{:x}", hasher.finalize()),
+            size_bytes,
+        }))
+    }
+
+    #[instrument(skip(self), fields(request_id = %request_id))]
+    async fn compress_file(&self, request_id: Uuid, file_path: &str, min_size_bytes: u64) -> Result<Option<CompressedFile>> {
+        let original_size = tokio::fs::metadata(file_path)
+            .await
+            .context("Failed to read export file metadata")?
+            .len();
+        if original_size < min_size_bytes {
+            return Ok(None);
+        }
+        let zip = PendingZip::write(file_path, original_size, None).await?;
+        if zip.zip_size >= original_size {
+            info!(
+                "Keeping {} uncompressed: the zip would be {} bytes, the original is {} bytes.",
+                file_path, zip.zip_size, original_size
+            );
+            remove_file_best_effort(&zip.partial_path).await;
+            return Ok(None);
+        }
+        info!("🗜️ Compressing {} ({} bytes) into {} ({} bytes).", file_path, original_size, zip.zip_path, zip.zip_size);
+        zip.replace_original(file_path).await.map(Some)
+    }
+
+    #[instrument(skip(self, password), fields(request_id = %request_id))]
+    async fn zip_parts(&self, request_id: Uuid, part_paths: &[String], password: Option<&str>) -> Result<CompressedFile> {
+        let first_path = part_paths.first().context("No file parts to zip")?;
+        let mut entries = Vec::with_capacity(part_paths.len());
+        for path in part_paths {
+            let size = tokio::fs::metadata(path)
+                .await
+                .context("Failed to read export file metadata")?
+                .len();
+            let entry_name = Path::new(path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .context("Export file path has no file name")?
+                .to_string();
+            entries.push((path.clone(), entry_name, size));
+        }
+        let original_file_name = entries[0].1.clone();
+        let original_size_bytes = entries.iter().map(|(_, _, size)| size).sum();
+        let zip_path = Path::new(first_path)
+            .with_file_name(format!("{}.zip", request_id))
+            .to_string_lossy()
+            .into_owned();
+        let partial_path = partial_file_path(&zip_path);
+        let (destination, password) = (partial_path.clone(), password.map(str::to_string));
+        let write_result = tokio::task::spawn_blocking(move || write_zip(&entries, &destination, password.as_deref()))
+            .await
+            .context("Zip task panicked")
+            .and_then(|result| result);
+        move_into_place(write_result, &partial_path, &zip_path).await?;
+        for path in part_paths {
+            tokio::fs::remove_file(path)
+                .await
+                .context("Failed to remove zipped export file part")?;
+        }
+        info!("🗜️ Zipped {} file parts of request {} into {}.", part_paths.len(), request_id, zip_path);
+        Ok(CompressedFile { path: zip_path, original_file_name, original_size_bytes })
+    }
+
+    #[instrument(skip(self, password), fields(request_id = %request_id))]
+    async fn encrypt_file(&self, request_id: Uuid, file_path: &str, password: &str) -> Result<CompressedFile> {
+        let original_size = tokio::fs::metadata(file_path)
+            .await
+            .context("Failed to read export file metadata")?
+            .len();
+        let zip = PendingZip::write(file_path, original_size, Some(password.to_string())).await?;
+        info!("🔒 Encrypting {} into {}.", file_path, zip.zip_path);
+        zip.replace_original(file_path).await
+    }
+
+    #[instrument(skip(self, encryption))]
+    async fn encrypt_at_rest(&self, file_path: &str, encryption: &FileEncryption) -> Result<String> {
+        let encrypted_path = format!("{}.{}", file_path, ENCRYPTED_FILE_EXTENSION);
+        let partial_path = partial_file_path(&encrypted_path);
+        // Mã hóa từng chunk bằng API đồng bộ trong thread blocking, như khi nén zip.
+        let (source, destination, encryption) = (file_path.to_string(), partial_path.clone(), encryption.clone());
+        let write_result = tokio::task::spawn_blocking(move || encryption.encrypt_file(&source, &destination))
+            .await
+            .context("Encryption task panicked")
+            .and_then(|result| result);
+        move_into_place(write_result, &partial_path, &encrypted_path).await?;
+        tokio::fs::remove_file(file_path)
+            .await
+            .context("Failed to remove unencrypted export file")?;
+        info!("🔐 Encrypted {} at rest as {}.", file_path, encrypted_path);
+        Ok(encrypted_path)
+    }
+
+    #[instrument(skip(self, pgp))]
+    async fn encrypt_for_recipient(&self, file_path: &str, pgp: &PgpEncryption, recipient_key_id: &str) -> Result<(String, String)> {
+        let encrypted_path = format!("{}.{}", file_path, PGP_FILE_EXTENSION);
+        let partial_path = partial_file_path(&encrypted_path);
+        let (source, destination, pgp, key_id) =
+            (file_path.to_string(), partial_path.clone(), pgp.clone(), recipient_key_id.to_string());
+        let write_result = tokio::task::spawn_blocking(move || pgp.encrypt_file(&key_id, &source, &destination))
+            .await
+            .context("PGP encryption task panicked")
+            .and_then(|result| result);
+        let fingerprint = move_into_place(write_result, &partial_path, &encrypted_path).await?;
+        tokio::fs::remove_file(file_path)
+            .await
+            .context("Failed to remove unencrypted export file")?;
+        info!("🔏 Encrypted {} to PGP key {} as {}.", file_path, fingerprint, encrypted_path);
+        Ok((encrypted_path, fingerprint))
+    }
+
+    #[instrument(skip(self), fields(request_id = %request_id))]
+    async fn rename_file(&self, request_id: Uuid, file_path: &str, stem: &str) -> Result<String> {
+        let path = Path::new(file_path);
+        let dir = path.parent().context("Export file has no parent directory")?;
+        let current_name = path.file_name().and_then(|name| name.to_str()).context("Export file has no file name")?;
+        let extension = file_extension(current_name);
+        for candidate in file_name_candidates(request_id, stem, extension) {
+            if candidate == current_name {
+                return Ok(file_path.to_string());
+            }
+            check_component(&candidate)?;
+            let target = dir.join(&candidate).to_string_lossy().into_owned();
+            // Giữ chỗ tên file bằng `create_new` (thất bại nếu file đã tồn tại) rồi mới rename đè lên
+            // file rỗng của chính mình, để hai request cùng tên không ghi đè file của nhau.
+            match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&target).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to reserve file name {}", target)),
+            }
+            if let Err(e) = tokio::fs::rename(file_path, &target).await {
+                remove_file_best_effort(&target).await;
+                return Err(e).with_context(|| format!("Failed to rename {} to {}", file_path, target));
+            }
+            sync_parent_dir(&target).await;
+            info!("🏷️ Renamed {} to {}.", file_path, target);
+            return Ok(target);
+        }
+        anyhow::bail!("No free file name for '{}' in {}", stem, dir.display())
+    }
+
+    /// Tên được giữ chỗ bằng `create_new` rồi rename đè lên như `rename_file`, nên file chỉ xuất hiện
+    /// ở tên cuối khi đã ghi xong. Tên đã có file thì so checksum để biết là cùng nội dung hay trùng tên.
+    #[instrument(skip(self), fields(request_id = %request_id))]
+    async fn rename_by_content(&self, request_id: Uuid, file_path: &str) -> Result<(String, bool)> {
+        let checksum = self
+            .file_checksum(file_path)
+            .await?
+            .with_context(|| format!("Export file {} not found before naming by content", file_path))?;
+        let path = Path::new(file_path);
+        let dir = path.parent().context("Export file has no parent directory")?;
+        let current_name = path.file_name().and_then(|name| name.to_str()).context("Export file has no file name")?;
+        for candidate in content_file_names(&checksum.sha256, file_extension(current_name)) {
+            if candidate == current_name {
+                return Ok((file_path.to_string(), false));
+            }
+            let target = dir.join(&candidate).to_string_lossy().into_owned();
+            match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&target).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    match self.file_checksum(&target).await? {
+                        Some(existing) if existing.sha256 == checksum.sha256 => {
+                            tokio::fs::remove_file(file_path)
+                                .await
+                                .with_context(|| format!("Failed to remove {} after finding {}", file_path, target))?;
+                            info!("♻️ {} already exists with the same content, reusing it.", target);
+                            return Ok((target, true));
+                        }
+                        _ => {
+                            warn!("{} already exists with different content, trying the next name.", target);
+                            continue;
+                        }
+                    }
+                }
+                Err(e) => return Err(e).with_context(|| format!("Failed to reserve file name {}", target)),
+            }
+            if let Err(e) = tokio::fs::rename(file_path, &target).await {
+                remove_file_best_effort(&target).await;
+                return Err(e).with_context(|| format!("Failed to rename {} to {}", file_path, target));
+            }
+            sync_parent_dir(&target).await;
+            info!("🏷️ Renamed {} to {}.", file_path, target);
+            return Ok((target, false));
+        }
+        anyhow::bail!("No free content-addressed file name for {} in {}", file_path, dir.display())
+    }
+
+    #[instrument(skip(self, metadata))]
+    async fn write_metadata_file(&self, file_path: &str, metadata: &ExportMetadata) -> Result<String> {
+        let metadata_path = format!("{}.{}", file_path, METADATA_FILE_SUFFIX);
+        let partial_path = partial_file_path(&metadata_path);
+        let json = serde_json::to_vec_pretty(metadata).context("Failed to serialize export metadata")?;
+        let write_result = tokio::fs::write(&partial_path, json)
+            .await
+            .context("Failed to write export metadata file");
+        move_into_place(write_result, &partial_path, &metadata_path).await?;
+        info!("📝 Wrote export metadata {}.", metadata_path);
+        Ok(metadata_path)
+    }
+}
+
+/// Placeholder được phép trong FILENAME_TEMPLATE.
+pub const FILENAME_PLACEHOLDERS: [&str; 7] = ["request_id", "report_type", "user_id", "start_date", "end_date", "date", "ext"];
+
+/// Độ dài tối đa (byte) của phần tên trước phần mở rộng: dưới giới hạn 255 byte của filesystem,
+/// còn chỗ cho hậu tố chống trùng, phần mở rộng và `.partial`.
+pub const MAX_FILE_STEM_BYTES: usize = 200;
+
+/// Số tên thử tối đa khi tên file đã có người dùng.
+const MAX_FILE_NAME_ATTEMPTS: usize = 100;
+
+/// Số ký tự hex của SHA-256 trong tên file theo nội dung (CONTENT_ADDRESSED_FILE_NAMES).
+const CONTENT_HASH_CHARS: usize = 16;
+
+/// Kiểm tra template tên file lúc đọc config: chỉ dùng placeholder đã biết, không có dấu phân cách
+/// thư mục hoặc ký tự điều khiển, và kết thúc bằng `.{ext}` để phần mở rộng luôn đúng định dạng file.
+pub fn validate_filename_template(template: &str) -> Result<()> {
+    anyhow::ensure!(
+        !template.contains(['/', '\\']) && !template.chars().any(char::is_control),
+        "filename template '{}' must not contain path separators or control characters",
+        template
+    );
+    anyhow::ensure!(template.ends_with(".{ext}"), "filename template '{}' must end with '.{{ext}}'", template);
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        anyhow::ensure!(!rest[..start].contains('}'), "filename template '{}' has an unmatched '}}'", template);
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("filename template '{}' has an unclosed '{{'", template))?
+            + start;
+        let name = &rest[start + 1..end];
+        anyhow::ensure!(
+            FILENAME_PLACEHOLDERS.contains(&name),
+            "filename template '{}' uses unknown placeholder '{{{}}}' (allowed: {})",
+            template,
+            name,
+            FILENAME_PLACEHOLDERS.join(", ")
+        );
+        rest = &rest[end + 1..];
+    }
+    anyhow::ensure!(!rest.contains('}'), "filename template '{}' has an unmatched '}}'", template);
+    Ok(())
+}
+
+/// Dựng phần tên (không gồm phần mở rộng) từ template đã kiểm tra. Mọi ký tự ngoài `[A-Za-z0-9._-]`
+/// (kể cả trong giá trị placeholder) được thay bằng `_`, dấu chấm ở đầu bị bỏ để không tạo file ẩn,
+/// và tên dài quá `MAX_FILE_STEM_BYTES` bị cắt. Tên rỗng sau khi làm sạch thì dùng `request_id`.
+pub fn render_file_stem(template: &str, request_id: Uuid, values: &[(&str, String)]) -> String {
+    let mut stem = template.strip_suffix(".{ext}").unwrap_or(template).to_string();
+    for (name, value) in values {
+        stem = stem.replace(&format!("{{{}}}", name), value);
+    }
+    let mut stem: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .collect();
+    stem = stem.trim_start_matches('.').to_string();
+    // Chỉ còn ký tự ASCII nên cắt theo byte không làm hỏng UTF-8.
+    stem.truncate(MAX_FILE_STEM_BYTES);
+    if stem.trim_end_matches('.').is_empty() {
+        request_id.to_string()
+    } else {
+        stem
+    }
+}
+
+/// Phần mở rộng của file export: mọi thứ sau dấu chấm đầu tiên của tên file (`xlsx`, `jsonl.gz`...).
+/// File luôn được tạo với tên `{request_id}.{phần mở rộng}` nên không nhầm với dấu chấm trong tên.
+pub fn file_extension(file_name: &str) -> &str {
+    let file_name = Path::new(file_name).file_name().and_then(|name| name.to_str()).unwrap_or(file_name);
+    file_name.split_once('.').map(|(_, extension)| extension).unwrap_or_default()
+}
+
+/// Các tên thử lần lượt khi đổi tên: `{stem}.{ext}`, `{stem}-{request_id ngắn}.{ext}`, `{stem}-{request_id ngắn}-2.{ext}`...
+pub fn file_name_candidates(request_id: Uuid, stem: &str, extension: &str) -> impl Iterator<Item = String> {
+    let short_id = request_id.simple().to_string()[..8].to_string();
+    let stem = stem.to_string();
+    let extension = extension.to_string();
+    (0..MAX_FILE_NAME_ATTEMPTS).map(move |attempt| {
+        let suffixed = match attempt {
+            0 => stem.clone(),
+            1 => format!("{}-{}", stem, short_id),
+            n => format!("{}-{}-{}", stem, short_id, n),
+        };
+        if extension.is_empty() {
+            suffixed
+        } else {
+            format!("{}.{}", suffixed, extension)
+        }
+    })
+}
+
+/// Các tên theo nội dung thử lần lượt: `{hash}.{ext}`, `{hash}-2.{ext}`, `{hash}-3.{ext}`...
+/// với `hash` là 16 ký tự hex đầu của SHA-256.
+pub fn content_file_names(sha256: &str, extension: &str) -> impl Iterator<Item = String> {
+    let hash = sha256[..sha256.len().min(CONTENT_HASH_CHARS)].to_string();
+    let extension = extension.to_string();
+    (0..MAX_FILE_NAME_ATTEMPTS).map(move |attempt| {
+        let stem = match attempt {
+            0 => hash.clone(),
+            n => format!("{}-{}", hash, n + 1),
+        };
+        if extension.is_empty() {
+            stem
+        } else {
+            format!("{}.{}", stem, extension)
+        }
+    })
+}
+
+/// Content type của file đã nén gzip là `application/gzip`, như khi đính kèm email.
+fn compressed_content_type(format: ExportFormat, compression: OutputCompression) -> &'static str {
+    match compression {
+        OutputCompression::None => format.content_type(),
+        OutputCompression::Gzip => "application/gzip",
+    }
+}
+
+fn output_file_path(export_path: &str, request_id: Uuid, format: ExportFormat) -> String {
+    format!("{}/{}.{}", export_path, request_id, format.extension())
+}
+
+pub(crate) fn csv_file_path(export_path: &str, request_id: Uuid, compression: OutputCompression) -> String {
+    format!("{}{}", output_file_path(export_path, request_id, ExportFormat::Csv), compression.suffix())
+}
+
+/// Mở file tạm của file CSV `full_path` và ghi BOM khi `options.bom` bật (xem `FileExporter::create_csv_file`).
+async fn open_csv_file(full_path: String, options: &CsvOptions, compression: OutputCompression) -> Result<CsvFileWriter> {
+    let partial_path = partial_file_path(&full_path);
+    let file = tokio::fs::File::create(&partial_path)
+        .await
+        .context("Failed to create CSV file")?;
+    let sync_handle = file.try_clone().await.context("Failed to open CSV file for syncing")?;
+    let target = CsvFileTarget::Local { file: sync_handle, partial_path, full_path };
+    new_csv_file_writer(file, target, options, compression).await
+}
+
+/// `CsvFileWriter` ghi vào `output` của một upload (xem `FileExporter::create_streamed_csv_file`), với cùng BOM,
+/// bảng mã và nén gzip như file local.
+pub(crate) async fn open_streamed_csv_file(
+    output: impl AsyncWrite + Send + Unpin + 'static,
+    upload: Box<dyn StreamedUpload>,
+    options: &CsvOptions,
+    compression: OutputCompression,
+) -> Result<CsvFileWriter> {
+    new_csv_file_writer(output, CsvFileTarget::Streamed(upload), options, compression).await
+}
+
+async fn new_csv_file_writer(
+    output: impl AsyncWrite + Send + Unpin + 'static,
+    target: CsvFileTarget,
+    options: &CsvOptions,
+    compression: OutputCompression,
+) -> Result<CsvFileWriter> {
+    let inner: Box<dyn AsyncWrite + Send + Unpin> = match compression {
+        OutputCompression::None => Box::new(BufWriter::new(output)),
+        OutputCompression::Gzip => Box::new(GzipEncoder::new(BufWriter::new(output))),
+    };
+    let mut writer = CountingWriter { inner, bytes: 0 };
+    write_csv_bom(&mut writer, options).await?;
+    let content_type = match compression {
+        OutputCompression::None => {
+            format!("text/csv; charset={}", options.output_encoding()?.name().to_ascii_lowercase())
+        }
+        OutputCompression::Gzip => compressed_content_type(ExportFormat::Csv, compression).to_string(),
+    };
+    Ok(CsvFileWriter {
+        writer,
+        target,
+        compression,
+        content_type,
+    })
+}
+
+fn partial_file_path(full_path: &str) -> String {
+    format!("{}.partial", full_path)
+}
+
+/// Đường dẫn phần thứ `part` (từ 1) của file bị chia theo MAX_FILE_SIZE_BYTES: phần 1 giữ nguyên đường dẫn,
+/// các phần sau thêm `.part{n}` trước phần mở rộng (`{request_id}.part2.csv.gz`).
+fn part_file_path(full_path: &str, part: u32) -> String {
+    if part <= 1 {
+        return full_path.to_string();
+    }
+    let path = Path::new(full_path);
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or(full_path);
+    let part_name = match file_name.split_once('.') {
+        Some((stem, extension)) => format!("{}.part{}.{}", stem, part, extension),
+        None => format!("{}.part{}", file_name, part),
+    };
+    path.with_file_name(part_name).to_string_lossy().into_owned()
+}
+
+/// File zip đã ghi xong vào file tạm `.partial`, cạnh file export gốc.
+struct PendingZip {
+    zip_path: String,
+    partial_path: String,
+    original_file_name: String,
+    original_size: u64,
+    zip_size: u64,
+}
+
+impl PendingZip {
+    /// Ghi file export `file_path` vào `{tên file}.zip.partial`, mã hóa AES-256 khi có `password`.
+    async fn write(file_path: &str, original_size: u64, password: Option<String>) -> Result<Self> {
+        let source = Path::new(file_path);
+        let original_file_name = source
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("Export file path has no file name")?
+            .to_string();
+        let zip_path = source.with_extension("zip").to_string_lossy().into_owned();
+        let partial_path = partial_file_path(&zip_path);
+
+        // Thư viện zip chỉ có API đồng bộ: nén trong thread blocking để không chặn runtime.
+        let entries = vec![(file_path.to_string(), original_file_name.clone(), original_size)];
+        let destination = partial_path.clone();
+        let zip_result = tokio::task::spawn_blocking(move || write_zip(&entries, &destination, password.as_deref()))
+            .await
+            .context("Zip task panicked")
+            .and_then(|result| result);
+        match zip_result {
+            Ok(zip_size) => Ok(Self { zip_path, partial_path, original_file_name, original_size, zip_size }),
+            Err(e) => {
+                remove_file_best_effort(&partial_path).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Đưa file zip vào chỗ và xóa file export gốc.
+    async fn replace_original(self, file_path: &str) -> Result<CompressedFile> {
+        move_into_place(Ok(()), &self.partial_path, &self.zip_path).await?;
+        tokio::fs::remove_file(file_path)
+            .await
+            .context("Failed to remove original export file")?;
+        Ok(CompressedFile {
+            path: self.zip_path,
+            original_file_name: self.original_file_name,
+            original_size_bytes: self.original_size,
+        })
+    }
+}
+
+/// Nén từng file `(source, entry_name, source_size)` thành một entry của file zip tại `destination`;
+/// trả về kích thước file zip.
+fn write_zip(entries: &[(String, String, u64)], destination: &str, password: Option<&str>) -> Result<u64> {
+    let output = std::fs::File::create(destination).context("Failed to create zip file")?;
+    let mut zip = zip::ZipWriter::new(std::io::BufWriter::new(output));
+    for (source, entry_name, source_size) in entries {
+        let mut input = std::fs::File::open(source).context("Failed to open export file for compression")?;
+        // FileOptions (thay vì SimpleFileOptions, vốn cố định lifetime 'static) để mượn mật khẩu.
+        let mut options = zip::write::FileOptions::<()>::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(*source_size >= u32::MAX as u64);
+        if let Some(password) = password {
+            options = options.with_aes_encryption(zip::AesMode::Aes256, password);
+        }
+        zip.start_file(entry_name.as_str(), options).context("Failed to start zip entry")?;
+        std::io::copy(&mut input, &mut zip).context("Failed to write zip entry")?;
+    }
+    let output = zip.finish().context("Failed to finish zip file")?;
+    let file = output.into_inner().map_err(|e| e.into_error()).context("Failed to flush zip file")?;
+    file.sync_all().context("Failed to sync zip file")?;
+    Ok(file.metadata().context("Failed to read zip file metadata")?.len())
+}
+
+/// Đổi tên file tạm thành file cuối khi ghi thành công; khi lỗi thì xóa file tạm.
+/// File tạm được fsync trước khi đổi tên, và thư mục được fsync sau đó, để sau khi máy sập
+/// file cuối hoặc là file đầy đủ hoặc không tồn tại.
+async fn move_into_place<T>(write_result: Result<T>, partial_path: &str, full_path: &str) -> Result<T> {
+    let result = match write_result {
+        Ok(value) => publish_file(partial_path, full_path).await.map(|()| value),
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        remove_file_best_effort(partial_path).await;
+    }
+    result
+}
+
+/// Như `move_into_place` cho các phần của file bị chia: phần thứ n được ghi vào file tạm của `part_file_path(full_path, n)`.
+/// Trả về đường dẫn cuối của từng phần kèm kết quả ghi. File tạm của phần ghi dở được dọn bởi `remove_partial_output`.
+async fn move_parts_into_place<T>(write_result: Result<Vec<T>>, full_path: &str) -> Result<Vec<(String, T)>> {
+    let mut published = Vec::new();
+    for (index, value) in write_result?.into_iter().enumerate() {
+        let path = part_file_path(full_path, index as u32 + 1);
+        publish_file(&partial_file_path(&path), &path).await?;
+        published.push((path, value));
+    }
+    Ok(published)
+}
+
+async fn publish_file(partial_path: &str, full_path: &str) -> Result<()> {
+    // Writer của một số định dạng (xlsx, ods, parquet...) chỉ đóng file mà không fsync.
+    tokio::fs::File::open(partial_path)
+        .await
+        .context("Failed to open generated file")?
+        .sync_all()
+        .await
+        .context("Failed to sync generated file")?;
+    tokio::fs::rename(partial_path, full_path)
+        .await
+        .context("Failed to move generated file into place")?;
+    sync_parent_dir(full_path).await;
+    Ok(())
+}
+
+/// Fsync thư mục chứa file để phép đổi tên được ghi xuống đĩa. Chỉ có trên Unix; lỗi chỉ được ghi log
+/// vì file đã ở đúng chỗ.
+async fn sync_parent_dir(path: &str) {
+    #[cfg(unix)]
+    if let Some(dir) = Path::new(path).parent() {
+        let result = match tokio::fs::File::open(dir).await {
+            Ok(dir) => dir.sync_all().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Failed to sync export directory {}: {:?}", dir.display(), e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Dọn file tạm khi ghi lỗi; lỗi khi xóa chỉ được ghi log để không che lỗi gốc.
+async fn remove_file_best_effort(path: &str) {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => info!("🧹 Removed partial export file {}.", path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove partial export file {}: {:?}", path, e),
+    }
+}
+
+/// Khoảng trống thêm vào độ dài nội dung khi autofit, để chữ không chạm mép cột (và nút autofilter).
+#[cfg(feature = "xlsxwriter")]
+const AUTOFIT_PADDING: usize = 2;
+
+/// Độ rộng cột của sheet đang ghi. Autofit chỉ giữ độ dài lớn nhất của từng cột, không giữ lại dòng nào.
+#[cfg(feature = "xlsxwriter")]
+struct ColumnWidths {
+    declared: Vec<Option<f64>>,
+    header_lengths: Vec<usize>,
+    max_lengths: Vec<usize>,
+}
+
+#[cfg(feature = "xlsxwriter")]
+impl ColumnWidths {
+    fn new(declared: Vec<Option<f64>>, headers: &[&str]) -> Self {
+        let header_lengths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+        Self { declared, max_lengths: header_lengths.clone(), header_lengths }
+    }
+
+    fn track(&mut self, values: &[crate::models::CellValue], style: &ExcelStyleOptions) {
+        use crate::models::CellValue;
+        for (max_length, value) in self.max_lengths.iter_mut().zip(values) {
+            // Ô ngày giờ hiển thị theo chuỗi định dạng, có cùng độ dài với chuỗi đó (`yyyy-mm-dd` -> `2026-10-15`).
+            let length = match value {
+                CellValue::Number(value) => value.to_string().len(),
+                CellValue::Text(value) | CellValue::Link { text: value, .. } => value.chars().count(),
+                CellValue::DateTime(_) => style.datetime_format.chars().count(),
+                CellValue::Date(_) => style.date_format.chars().count(),
+            };
+            *max_length = (*max_length).max(length);
+        }
+    }
+
+    /// Độ rộng cần đặt cho từng cột của sheet vừa ghi xong; bắt đầu lại từ độ dài header cho sheet tiếp theo.
+    fn finish_sheet(&mut self, style: &ExcelStyleOptions) -> Vec<Option<f64>> {
+        let widths = if style.autofit {
+            self.max_lengths
+                .iter()
+                .map(|length| Some((length + AUTOFIT_PADDING).min(style.max_column_width as usize) as f64))
+                .collect()
+        } else {
+            self.declared.clone()
+        };
+        self.max_lengths.clone_from(&self.header_lengths);
+        widths
+    }
+}
+
+/// Giá trị dòng tổng của từng cột theo `ColumnDef::aggregate`, cộng dồn khi ghi từng dòng (không giữ lại dòng nào).
+#[cfg(feature = "xlsxwriter")]
+struct ColumnTotals {
+    aggregates: Vec<crate::models::ColumnAggregate>,
+    sums: Vec<f64>,
+    /// Số ô số của cột (mẫu số của `Avg`).
+    numbers: Vec<u64>,
+    /// Số ô không trống của cột (`Count`).
+    non_empty: Vec<u64>,
+}
+
+#[cfg(feature = "xlsxwriter")]
+impl ColumnTotals {
+    fn new(aggregates: Vec<crate::models::ColumnAggregate>) -> Self {
+        let columns = aggregates.len();
+        Self { aggregates, sums: vec![0.0; columns], numbers: vec![0; columns], non_empty: vec![0; columns] }
+    }
+
+    fn track(&mut self, values: &[crate::models::CellValue]) {
+        use crate::models::CellValue;
+        for (col, value) in values.iter().enumerate().take(self.aggregates.len()) {
+            match value {
+                CellValue::Number(number) => {
+                    self.sums[col] += number;
+                    self.numbers[col] += 1;
+                    self.non_empty[col] += 1;
+                }
+                CellValue::Text(text) if text.is_empty() => {}
+                _ => self.non_empty[col] += 1,
+            }
+        }
+    }
+
+    /// Giá trị đã tính của cột; `None` với cột không có aggregate, hoặc `Avg` của cột không có ô số nào.
+    fn value(&self, col: usize) -> Option<f64> {
+        use crate::models::ColumnAggregate;
+        match self.aggregates.get(col)? {
+            ColumnAggregate::None => None,
+            ColumnAggregate::Sum => Some(self.sums[col]),
+            ColumnAggregate::Avg => (self.numbers[col] > 0).then(|| self.sums[col] / self.numbers[col] as f64),
+            ColumnAggregate::Count => Some(self.non_empty[col] as f64),
+        }
+    }
+
+    /// Công thức SUBTOTAL của cột trên vùng dữ liệu của mọi sheet (`sheet_rows`: số dòng dữ liệu của từng sheet).
+    /// SUBTOTAL không nhận tham chiếu 3D, nên mỗi sheet có một SUBTOTAL riêng; `Avg` là tổng chia số ô số.
+    fn formula(&self, col: usize, header_row: u32, sheet_rows: &[u32]) -> Option<String> {
+        use crate::models::ColumnAggregate;
+        let column = rust_xlsxwriter::utility::column_number_to_name(col as u16);
+        let ranges: Vec<String> = sheet_rows
+            .iter()
+            .enumerate()
+            .filter(|(_, rows)| **rows > 0)
+            .map(|(index, rows)| {
+                // Địa chỉ A1 đánh số dòng từ 1: dữ liệu nằm từ dòng `header_row + 2` tới `header_row + rows + 1`.
+                let (first, last) = (header_row + 2, header_row + rows + 1);
+                format!("'{}'!{}{}:{}{}", sheet_name(index as u32), column, first, column, last)
+            })
+            .collect();
+        let subtotals = |function: u8| {
+            ranges
+                .iter()
+                .map(|range| format!("SUBTOTAL({},{})", function, range))
+                .collect::<Vec<_>>()
+                .join("+")
+        };
+        if ranges.is_empty() {
+            return None;
+        }
+        match self.aggregates.get(col)? {
+            ColumnAggregate::None => None,
+            ColumnAggregate::Sum => Some(format!("={}", subtotals(9))),
+            ColumnAggregate::Avg if ranges.len() == 1 => Some(format!("={}", subtotals(1))),
+            ColumnAggregate::Avg => Some(format!("=({})/({})", subtotals(9), subtotals(2))),
+            ColumnAggregate::Count => Some(format!("={}", subtotals(3))),
+        }
+    }
+}
+
+/// Sửa giá trị mà Excel không ghi được nguyên vẹn và đếm vào `adjustments`: ký tự điều khiển (trừ tab và
+/// xuống dòng) làm hỏng XML của workbook nên được thay bằng U+FFFD; chuỗi dài hơn `EXCEL_MAX_CELL_CHARS`
+/// bị cắt và thêm `marker`; số vô hạn hoặc quá lớn được giới hạn về ±`EXCEL_MAX_NUMBER`, NaN thành ô trống.
+#[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+fn fit_excel_cell(value: CellValue, marker: &str, adjustments: &mut CellAdjustments) -> CellValue {
+    match value {
+        CellValue::Text(mut text) => {
+            if text.chars().any(is_invalid_xml_char) {
+                text = text.chars().map(|c| if is_invalid_xml_char(c) { char::REPLACEMENT_CHARACTER } else { c }).collect();
+                adjustments.invalid_chars += 1;
+            }
+            // Số byte luôn không nhỏ hơn số ký tự: chỉ đếm ký tự khi chuỗi có thể vượt giới hạn.
+            if text.len() > EXCEL_MAX_CELL_CHARS && text.chars().count() > EXCEL_MAX_CELL_CHARS {
+                let keep = EXCEL_MAX_CELL_CHARS.saturating_sub(marker.chars().count());
+                text = text.chars().take(keep).chain(marker.chars()).collect();
+                adjustments.truncated += 1;
+            }
+            CellValue::Text(text)
+        }
+        // Chữ hiển thị được chỉnh như ô chữ thường; URL được kiểm tra khi ghi.
+        CellValue::Link { text, url } => match fit_excel_cell(CellValue::Text(text), marker, adjustments) {
+            CellValue::Text(text) => CellValue::Link { text, url },
+            other => other,
+        },
+        CellValue::Number(number) if number.is_nan() => {
+            adjustments.clamped_numbers += 1;
+            CellValue::Text(String::new())
+        }
+        CellValue::Number(number) if number.abs() > EXCEL_MAX_NUMBER => {
+            adjustments.clamped_numbers += 1;
+            CellValue::Number(number.clamp(-EXCEL_MAX_NUMBER, EXCEL_MAX_NUMBER))
+        }
+        value => value,
+    }
+}
+
+/// Ký tự không được phép trong XML 1.0.
+#[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+fn is_invalid_xml_char(c: char) -> bool {
+    matches!(c, '\u{0}'..='\u{8}' | '\u{B}' | '\u{C}' | '\u{E}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}')
+}
+
+/// Ngày đầu tiên Excel biểu diễn được (serial 1) trong hệ ngày 1900.
+#[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+const EXCEL_FIRST_DATE: (i32, u32, u32) = (1900, 1, 1);
+
+/// Serial date của Excel (hệ 1900): số ngày tính từ 1899-12-30, phần thập phân là thời gian trong ngày.
+/// Excel coi 1900 là năm nhuận (có ngày 1900-02-29), nên các ngày trước 1900-03-01 nhỏ hơn 1 đơn vị.
+/// Trả về `None` với thời điểm trước 1900-01-01, Excel không có serial cho các ngày này.
+#[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+fn excel_serial(value: chrono::NaiveDateTime) -> Option<f64> {
+    use chrono::NaiveDate;
+    let (year, month, day) = EXCEL_FIRST_DATE;
+    let first_date = NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(0, 0, 0)?;
+    if value < first_date {
+        return None;
+    }
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)?.and_hms_opt(0, 0, 0)?;
+    let leap_bug_end = NaiveDate::from_ymd_opt(1900, 3, 1)?.and_hms_opt(0, 0, 0)?;
+    let mut serial = (value - epoch).num_milliseconds() as f64 / 86_400_000.0;
+    if value < leap_bug_end {
+        serial -= 1.0;
+    }
+    Some(serial)
+}
+
+/// Tên worksheet thứ `index` (bắt đầu từ 0): `Data`, `Data (2)`, `Data (3)`...
+#[cfg(any(feature = "xlsxwriter", feature = "ods"))]
+pub(crate) fn sheet_name(index: u32) -> String {
+    if index == 0 {
+        "Data".to_string()
+    } else {
+        format!("Data ({})", index + 1)
+    }
+}
+
+/// Tên sheet mục lục của file có một sheet cho mỗi nhóm (`group_by_sheet`).
+#[cfg(feature = "xlsxwriter")]
+const INDEX_SHEET_NAME: &str = "Index";
+
+/// Màu nền và màu chữ của dòng được tô bởi `ConditionalFormat::RowBelow` (kiểu "Light Red Fill" của Excel).
+#[cfg(feature = "xlsxwriter")]
+const HIGHLIGHT_FILL_COLOR: u32 = 0xFFC7CE;
+#[cfg(feature = "xlsxwriter")]
+const HIGHLIGHT_FONT_COLOR: u32 = 0x9C0006;
+
+/// Số dòng của khối tiêu đề (tiêu đề và dòng phụ) phía trên header của sheet dữ liệu.
+#[cfg(feature = "xlsxwriter")]
+const TITLE_BLOCK_ROWS: u32 = 2;
+
+/// Cỡ chữ và chiều cao (point) của dòng tiêu đề, màu chữ của dòng phụ.
+#[cfg(feature = "xlsxwriter")]
+const TITLE_FONT_SIZE: f64 = 16.0;
+#[cfg(feature = "xlsxwriter")]
+const TITLE_ROW_HEIGHT: f64 = 24.0;
+#[cfg(feature = "xlsxwriter")]
+const SUBTITLE_FONT_COLOR: u32 = 0x595959;
+
+/// Tên sheet thông tin về lần export (EXPORT_METADATA), luôn là sheet đầu tiên.
+#[cfg(feature = "xlsxwriter")]
+const INFO_SHEET_NAME: &str = "Info";
+
+/// Độ dài tối đa (số ký tự) của tên worksheet trong Excel.
+#[cfg(feature = "xlsxwriter")]
+const EXCEL_MAX_SHEET_NAME_CHARS: usize = 31;
+
+/// Tên worksheet từ giá trị của một nhóm: ký tự Excel không cho phép (`[ ] : * ? / \` và ký tự điều khiển)
+/// thành `_`, bỏ khoảng trắng và dấu `'` ở hai đầu, cắt còn 31 ký tự. Giá trị rỗng thành `(blank)`,
+/// `History` (tên Excel giữ riêng) thành `History_`.
+#[cfg(feature = "xlsxwriter")]
+fn sanitize_sheet_name(value: &str) -> String {
+    let is_edge = |c: char| c == '\'' || c.is_whitespace();
+    let replaced: String = value
+        .chars()
+        .map(|c| if matches!(c, '[' | ']' | ':' | '*' | '?' | '/' | '\\') || c.is_control() { '_' } else { c })
+        .collect();
+    let truncated: String = replaced.trim_matches(is_edge).chars().take(EXCEL_MAX_SHEET_NAME_CHARS).collect();
+    match truncated.trim_end_matches(is_edge) {
+        "" => "(blank)".to_string(),
+        name if name.eq_ignore_ascii_case("History") => "History_".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Tên worksheet đã dùng trong workbook. Excel so sánh tên không phân biệt hoa thường.
+#[cfg(feature = "xlsxwriter")]
+#[derive(Default)]
+struct SheetNames(std::collections::HashSet<String>);
+
+#[cfg(feature = "xlsxwriter")]
+impl SheetNames {
+    /// `name` nếu chưa dùng, nếu không thì `name (2)`, `name (3)`... (cắt bớt `name` để vẫn vừa 31 ký tự).
+    fn unique(&mut self, name: &str) -> String {
+        (1u32..)
+            .map(|n| {
+                if n == 1 {
+                    return name.to_string();
+                }
+                let suffix = format!(" ({})", n);
+                let stem: String = name.chars().take(EXCEL_MAX_SHEET_NAME_CHARS - suffix.len()).collect();
+                format!("{}{}", stem.trim_end(), suffix)
+            })
+            .find(|candidate| self.0.insert(candidate.to_lowercase()))
+            .expect("sheet name candidates are unbounded")
+    }
+}
+
+/// Một worksheet dữ liệu của file có `group_by_sheet`, để ghi sheet mục lục.
+#[cfg(feature = "xlsxwriter")]
+struct GroupSheet {
+    name: String,
+    group: String,
+    rows: u32,
+}
+
+/// Tên Excel Table đã dùng trong workbook. Excel so sánh tên không phân biệt hoa thường.
+#[cfg(feature = "xlsxwriter")]
+#[derive(Default)]
+struct TableNames(std::collections::HashSet<String>);
+
+#[cfg(feature = "xlsxwriter")]
+impl TableNames {
+    /// `Table_` và tên sheet, mỗi chuỗi ký tự không phải chữ hoặc số thành một `_`: tên luôn bắt đầu bằng chữ
+    /// và không trùng địa chỉ ô (như `AB12`). Tên đã dùng thì thêm `_2`, `_3`...
+    fn unique(&mut self, sheet_name: &str) -> String {
+        let mut stem = String::from("Table");
+        for c in sheet_name.chars() {
+            if c.is_alphanumeric() {
+                if stem == "Table" {
+                    stem.push('_');
+                }
+                stem.push(c);
+            } else if !stem.ends_with('_') && stem != "Table" {
+                stem.push('_');
+            }
+        }
+        let stem = stem.trim_end_matches('_').to_string();
+        (1u32..)
+            .map(|n| if n == 1 { stem.clone() } else { format!("{}_{}", stem, n) })
+            .find(|candidate| self.0.insert(candidate.to_lowercase()))
+            .expect("table name candidates are unbounded")
+    }
+}
+
+/// Tên cột của Excel Table phải khác rỗng và khác nhau (không phân biệt hoa thường): cột không tên thành
+/// `Column{n}` như Excel, cột trùng tên thành `{tên} (2)`, `{tên} (3)`...
+#[cfg(feature = "xlsxwriter")]
+fn table_column_names(headers: &[&str]) -> Vec<String> {
+    let mut used = std::collections::HashSet::new();
+    headers
+        .iter()
+        .enumerate()
+        .map(|(col, header)| {
+            let header = if header.is_empty() { format!("Column{}", col + 1) } else { header.to_string() };
+            (1u32..)
+                .map(|n| if n == 1 { header.clone() } else { format!("{} ({})", header, n) })
+                .find(|candidate| used.insert(candidate.to_lowercase()))
+                .expect("column name candidates are unbounded")
+        })
+        .collect()
+}
+
+/// Excel Table của các sheet dữ liệu (`table` của `ExcelStyleOptions`).
+#[cfg(feature = "xlsxwriter")]
+struct DataTables {
+    style: rust_xlsxwriter::TableStyle,
+    /// Tên cột, trùng với chữ trong ô header của sheet.
+    columns: Vec<rust_xlsxwriter::TableColumn>,
+    names: TableNames,
+    /// Cột kèm nhãn và hàm của dòng tổng, cho sheet cuối khi dòng tổng nằm trong table.
+    total_row: Option<Vec<rust_xlsxwriter::TableColumn>>,
+}
+
+#[cfg(feature = "xlsxwriter")]
+impl DataTables {
+    fn new(style: crate::models::ExcelTableStyle, headers: &[&str]) -> Self {
+        use rust_xlsxwriter::TableColumn;
+        Self {
+            style: table_style(style),
+            columns: headers.iter().map(|header| TableColumn::new().set_header(*header)).collect(),
+            names: TableNames::default(),
+            total_row: None,
+        }
+    }
+
+    /// Dòng tổng của table: nhãn `Total` ở cột đầu tiên không có aggregate (như dòng tổng thường),
+    /// hàm SUBTOTAL của table theo `ColumnDef::aggregate` ở các cột khác.
+    fn set_total_row(&mut self, layout: &crate::models::ExportLayout) {
+        use crate::models::ColumnAggregate;
+        use rust_xlsxwriter::TableFunction;
+        let label_col = layout.columns.iter().position(|column| column.aggregate == ColumnAggregate::None);
+        let columns = self
+            .columns
+            .iter()
+            .zip(&layout.columns)
+            .enumerate()
+            .map(|(col, (table_column, column))| match column.aggregate {
+                _ if Some(col) == label_col => table_column.clone().set_total_label("Total"),
+                ColumnAggregate::Sum => table_column.clone().set_total_function(TableFunction::Sum),
+                ColumnAggregate::Avg => table_column.clone().set_total_function(TableFunction::Average),
+                ColumnAggregate::Count => table_column.clone().set_total_function(TableFunction::Count),
+                ColumnAggregate::None => table_column.clone(),
+            })
+            .collect();
+        self.total_row = Some(columns);
+    }
+}
+
+/// Kiểu Excel Table của rust_xlsxwriter. Số ngoài khoảng của nhóm (đã bị `ExcelTableStyle::from_name` loại) dùng `Medium9`.
+#[cfg(feature = "xlsxwriter")]
+fn table_style(style: crate::models::ExcelTableStyle) -> rust_xlsxwriter::TableStyle {
+    use crate::models::ExcelTableStyle;
+    use rust_xlsxwriter::TableStyle;
+    const LIGHT: [TableStyle; 21] = [
+        TableStyle::Light1, TableStyle::Light2, TableStyle::Light3, TableStyle::Light4, TableStyle::Light5, TableStyle::Light6, TableStyle::Light7, TableStyle::Light8, TableStyle::Light9, TableStyle::Light10, TableStyle::Light11, TableStyle::Light12, TableStyle::Light13, TableStyle::Light14, TableStyle::Light15, TableStyle::Light16, TableStyle::Light17, TableStyle::Light18, TableStyle::Light19, TableStyle::Light20, TableStyle::Light21,
+    ];
+    const MEDIUM: [TableStyle; 28] = [
+        TableStyle::Medium1, TableStyle::Medium2, TableStyle::Medium3, TableStyle::Medium4, TableStyle::Medium5, TableStyle::Medium6, TableStyle::Medium7, TableStyle::Medium8, TableStyle::Medium9, TableStyle::Medium10, TableStyle::Medium11, TableStyle::Medium12, TableStyle::Medium13, TableStyle::Medium14, TableStyle::Medium15, TableStyle::Medium16, TableStyle::Medium17, TableStyle::Medium18, TableStyle::Medium19, TableStyle::Medium20, TableStyle::Medium21, TableStyle::Medium22, TableStyle::Medium23, TableStyle::Medium24, TableStyle::Medium25, TableStyle::Medium26, TableStyle::Medium27, TableStyle::Medium28,
+    ];
+    const DARK: [TableStyle; 11] = [
+        TableStyle::Dark1, TableStyle::Dark2, TableStyle::Dark3, TableStyle::Dark4, TableStyle::Dark5, TableStyle::Dark6, TableStyle::Dark7, TableStyle::Dark8, TableStyle::Dark9, TableStyle::Dark10, TableStyle::Dark11,
+    ];
+    let (styles, number): (&[TableStyle], u8) = match style {
+        ExcelTableStyle::None => return TableStyle::None,
+        ExcelTableStyle::Light(number) => (&LIGHT, number),
+        ExcelTableStyle::Medium(number) => (&MEDIUM, number),
+        ExcelTableStyle::Dark(number) => (&DARK, number),
+    };
+    styles.get(usize::from(number).wrapping_sub(1)).copied().unwrap_or(TableStyle::Medium9)
+}
+
+/// Ghi workbook trong thread blocking: rust_xlsxwriter chỉ có API đồng bộ, và việc ghi một file lớn
+/// chạy thẳng trên worker của Tokio sẽ chặn các task khác (heartbeat của Kafka consumer...).
+/// Các dòng được dựng ở phía async và chuyển sang thread ghi qua kênh có giới hạn `XLSX_ROW_CHANNEL_CAPACITY`;
+/// khi thread ghi dừng vì lỗi, việc dựng dòng dừng theo và lỗi của thread ghi được trả về.
+#[cfg(feature = "xlsxwriter")]
+async fn write_workbook(
+    request_id: Uuid,
+    path: &str,
+    data: ReportData,
+    style: &ExcelStyleOptions,
+    metadata: Option<&ExportMetadata>,
+    escape: FormulaEscape,
+    max_rows_per_sheet: u32,
+) -> Result<(u32, CellAdjustments)> {
+    let path = path.to_string();
+    let style = style.clone();
+    let metadata = metadata.cloned();
+    let layout = data.layout();
+    let chart = data.chart();
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(XLSX_ROW_CHANNEL_CAPACITY);
+    let writer = tokio::task::spawn_blocking(move || {
+        let rows = std::iter::from_fn(|| receiver.blocking_recv());
+        write_xlsx(request_id, &path, &layout, chart, rows, &style, metadata.as_ref(), max_rows_per_sheet)
+    });
+    for row in data.escaped_rows(escape) {
+        let failed = row.is_err();
+        if sender.send(row).await.is_err() || failed {
+            break;
+        }
+    }
+    drop(sender);
+    writer.await.context("Excel task panicked")?
+}
+
+/// Ghi dữ liệu thành các workbook không lớn hơn `max_bytes` (`{request_id}.xlsx`, `{request_id}.part2.xlsx`...,
+/// mỗi phần ở file tạm `.partial` của nó), trả về số dòng, số worksheet và số ô bị sửa của từng phần.
+#[cfg(feature = "xlsxwriter")]
+#[allow(clippy::too_many_arguments)]
+async fn write_workbook_parts(
+    request_id: Uuid,
+    full_path: &str,
+    data: ReportData,
+    style: &ExcelStyleOptions,
+    metadata: Option<&ExportMetadata>,
+    escape: FormulaEscape,
+    max_rows_per_sheet: u32,
+    max_bytes: u64,
+) -> Result<Vec<(usize, u32, CellAdjustments)>> {
+    let full_path = full_path.to_string();
+    let style = style.clone();
+    let metadata = metadata.cloned();
+    tokio::task::spawn_blocking(move || {
+        write_xlsx_parts(request_id, &full_path, data, &style, metadata.as_ref(), escape, max_rows_per_sheet, max_bytes)
+    })
+    .await
+    .context("Excel task panicked")?
+}
+
+/// Kích thước của file xlsx chỉ biết được sau khi đóng file, nên số dòng của mỗi phần được ước lượng theo số byte
+/// trung bình của một dòng ở lần ghi trước. Lần đầu ghi toàn bộ dữ liệu, vì đa số file không vượt giới hạn;
+/// phần nào lớn hơn `max_bytes` thì bị xóa, ước lượng được đo lại trên chính phần đó và phần được chia lại.
+#[cfg(feature = "xlsxwriter")]
+#[allow(clippy::too_many_arguments)]
+fn write_xlsx_parts(
+    request_id: Uuid,
+    full_path: &str,
+    data: ReportData,
+    style: &ExcelStyleOptions,
+    metadata: Option<&ExportMetadata>,
+    escape: FormulaEscape,
+    max_rows_per_sheet: u32,
+    max_bytes: u64,
+) -> Result<Vec<(usize, u32, CellAdjustments)>> {
+    // Số dòng vừa `max_bytes` theo ước lượng, chừa `XLSX_SPLIT_MARGIN` cho sai số.
+    let rows_within = |bytes_per_row: f64| ((max_bytes as f64 * XLSX_SPLIT_MARGIN) / bytes_per_row).floor().max(1.0) as usize;
+
+    let mut parts = Vec::new();
+    let mut pending = std::collections::VecDeque::from([data]);
+    let mut bytes_per_row = None;
+    let mut rows_done = 0;
+    while let Some(mut chunk) = pending.pop_front() {
+        if let Some(bytes_per_row) = bytes_per_row {
+            let rows = rows_within(bytes_per_row);
+            if chunk.len() > rows {
+                pending.push_front(chunk.split_off(rows));
+            }
+        }
+        let path = partial_file_path(&part_file_path(full_path, parts.len() as u32 + 1));
+        let rows = chunk.escaped_rows(escape);
+        let (sheet_count, adjustments) =
+            write_xlsx(request_id, &path, &chunk.layout(), chunk.chart(), rows, style, metadata, max_rows_per_sheet)?;
+        let size = std::fs::metadata(&path).context("Failed to read size of Excel file")?.len();
+        if size <= max_bytes {
+            rows_done += chunk.len();
+            parts.push((chunk.len(), sheet_count, adjustments));
+            continue;
+        }
+        std::fs::remove_file(&path).context("Failed to remove oversized Excel file")?;
+        if chunk.len() <= 1 {
+            return Err(FileSizeLimitError { row: rows_done + 1, max_bytes }.into());
+        }
+        let estimate = size as f64 / chunk.len() as f64;
+        info!(
+            "Excel part {} of request {} is {} bytes for {} row(s), over the {} byte limit; splitting it again.",
+            parts.len() + 1, request_id, size, chunk.len(), max_bytes
+        );
+        bytes_per_row = Some(estimate);
+        let rows = rows_within(estimate).min(chunk.len() - 1);
+        pending.push_front(chunk.split_off(rows));
+        pending.push_front(chunk);
+    }
+    Ok(parts)
+}
+
+/// Ghi header và giá trị theo layout cột của report (và thứ tự cột được yêu cầu), trả về số worksheet
+/// (không tính sheet "Info" của `metadata`, sheet này luôn đứng đầu workbook).
+/// Khi một sheet đủ `max_rows_per_sheet` dòng dữ liệu, các dòng tiếp theo được ghi sang sheet mới có header riêng.
+/// Với `group_by_sheet`, mỗi giá trị của cột nhóm có sheet riêng mang tên giá trị đó, sau sheet "Index" liệt kê
+/// từng sheet (kèm link) và số dòng của nó. Các dòng phải đến theo thứ tự của cột nhóm (query ORDER BY cột này):
+/// một nhóm xuất hiện lại sau nhóm khác là lỗi, vì sheet đã ghi xong không thể ghi tiếp.
+/// Worksheet ở chế độ constant memory: mỗi dòng được flush ra file tạm ngay khi sang dòng mới, nên bộ nhớ
+/// không tăng theo số dòng. Chế độ này yêu cầu ghi theo thứ tự dòng tăng dần.
+/// `rows` là giá trị từng dòng theo `layout` (đã qua `escaped_rows`); `chart` là biểu đồ của loại dữ liệu.
+#[cfg(feature = "xlsxwriter")]
+#[allow(clippy::too_many_arguments)]
+fn write_xlsx(
+    request_id: Uuid,
+    path: &str,
+    layout: &crate::models::ExportLayout,
+    chart: Option<crate::models::ChartSpec>,
+    rows: impl Iterator<Item = Result<Vec<CellValue>, UnsafeCellValue>>,
+    style: &ExcelStyleOptions,
+    metadata: Option<&ExportMetadata>,
+    max_rows_per_sheet: u32,
+) -> Result<(u32, CellAdjustments)> {
+    use crate::models::{
+        ChartSpec, ColumnAggregate, ColumnFormat, ConditionalFormat, ExcelPrintOptions, ExportLayout, ReportTitle,
+    };
+    use rust_xlsxwriter::{
+        Chart, ChartType, Color, ConditionalFormat3ColorScale, ConditionalFormatFormula, Format, FormatBorder, Formula, Table,
+        Url, Workbook, Worksheet,
+    };
+
+    fn add_sheet<'a>(
+        workbook: &'a mut Workbook,
+        name: &str,
+        headers: &[&str],
+        header_format: &Format,
+        request_id: Uuid,
+        style: &ExcelStyleOptions,
+    ) -> Result<&'a mut Worksheet> {
+        let sheet = workbook.add_worksheet_with_constant_memory();
+        sheet.set_name(name)?;
+        let header_row = match &style.title {
+            Some(title) => {
+                write_title_block(sheet, title, headers.len())?;
+                TITLE_BLOCK_ROWS
+            }
+            None => 0,
+        };
+        for (col, header) in headers.iter().enumerate() {
+            sheet.write_string_with_format(header_row, col as u16, *header, header_format)?;
+        }
+        // Dòng cố định gồm cả khối tiêu đề ở trên header.
+        if style.freeze_header {
+            sheet.set_freeze_panes(header_row + 1, 0)?;
+        }
+        set_page_setup(sheet, request_id, header_row, &style.print)?;
+        Ok(sheet)
+    }
+
+    // Tiêu đề và dòng phụ, mỗi dòng gộp ô qua mọi cột dữ liệu (report một cột không cần gộp).
+    fn write_title_block(sheet: &mut Worksheet, title: &ReportTitle, column_count: usize) -> Result<()> {
+        let last_col = column_count.saturating_sub(1) as u16;
+        let title_format = Format::new().set_bold().set_font_size(TITLE_FONT_SIZE);
+        let subtitle_format = Format::new().set_italic().set_font_color(Color::RGB(SUBTITLE_FONT_COLOR));
+        sheet.set_row_height(0, TITLE_ROW_HEIGHT)?;
+        for (row, text, format) in [(0, &title.title, &title_format), (1, &title.subtitle, &subtitle_format)] {
+            if last_col > 0 {
+                sheet.merge_range(row, 0, row, last_col, text, format)?;
+            } else {
+                sheet.write_string_with_format(row, 0, text, format)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Thiết lập in là của từng worksheet, nên được đặt cho mỗi sheet dữ liệu (kể cả sheet của nhóm và sheet tách).
+    fn set_page_setup(sheet: &mut Worksheet, request_id: Uuid, header_row: u32, print: &ExcelPrintOptions) -> Result<()> {
+        if print.landscape {
+            sheet.set_landscape();
+        }
+        if print.fit_to_width {
+            sheet.set_print_fit_to_pages(1, 0);
+        }
+        if print.repeat_header {
+            sheet.set_repeat_rows(header_row, header_row)?;
+        }
+        if let Some(margins) = print.margins {
+            // Lề header và footer giữ giá trị mặc định của Excel.
+            sheet.set_margins(margins.left, margins.right, margins.top, margins.bottom, 0.3, 0.3);
+        }
+        if print.footer {
+            sheet.set_footer(&format!("&LRequest {}&RPage &P of &N", request_id));
+        }
+        Ok(())
+    }
+
+    // Autofilter phủ header và mọi dòng đã ghi của sheet, độ rộng autofit phụ thuộc mọi dòng của sheet,
+    // nên cả hai chỉ đặt được khi sheet đã ghi xong. Định dạng có điều kiện cũng phủ đúng `data_rows` dòng dữ liệu
+    // của sheet (từ dòng ngay dưới `header_row`), không gồm dòng tổng ghi sau dòng dữ liệu cuối.
+    // Excel Table thay cho autofilter: table có bộ lọc riêng, và phải có ít nhất một dòng dữ liệu nên sheet
+    // trống vẫn dùng autofilter. Dòng tổng nằm trong table (`DataTables::total_row`) là dòng ngay sau dòng dữ liệu cuối.
+    fn finish_sheet(
+        sheet: &mut Worksheet,
+        header_row: u32,
+        data_rows: u32,
+        widths: &mut ColumnWidths,
+        conditional_formats: &[(u16, ConditionalFormat)],
+        tables: Option<&mut DataTables>,
+        style: &ExcelStyleOptions,
+    ) -> Result<()> {
+        let column_count = widths.declared.len();
+        let last_row = header_row + data_rows;
+        match tables {
+            Some(tables) if data_rows > 0 && column_count > 0 => {
+                let name = tables.names.unique(&sheet.name());
+                let (columns, table_last_row) = match tables.total_row.take() {
+                    Some(columns) => (columns, last_row + 1),
+                    None => (tables.columns.clone(), last_row),
+                };
+                let table = Table::new()
+                    .set_name(name)
+                    .set_style(tables.style)
+                    .set_banded_rows(true)
+                    .set_total_row(table_last_row > last_row)
+                    .set_columns(&columns);
+                sheet.add_table(header_row, 0, table_last_row, (column_count - 1) as u16, &table)?;
+            }
+            _ if style.autofilter && column_count > 0 => {
+                sheet.autofilter(header_row, 0, last_row, (column_count - 1) as u16)?;
+            }
+            _ => {}
+        }
+        if data_rows > 0 && column_count > 0 {
+            for &(col, conditional_format) in conditional_formats {
+                match conditional_format {
+                    ConditionalFormat::RowBelow { threshold } => {
+                        // Tham chiếu tuyệt đối tới cột, tương đối tới dòng: mỗi dòng của vùng so sánh ô của chính nó.
+                        // Địa chỉ A1 đánh số dòng từ 1, nên dòng dữ liệu đầu tiên (`header_row + 1`) là `header_row + 2`.
+                        let cell = format!("${}{}", rust_xlsxwriter::utility::column_number_to_name(col), header_row + 2);
+                        let rule = ConditionalFormatFormula::new()
+                            .set_rule(format!("=AND(ISNUMBER({}),{}<{})", cell, cell, threshold))
+                            .set_format(
+                                Format::new()
+                                    .set_background_color(Color::RGB(HIGHLIGHT_FILL_COLOR))
+                                    .set_font_color(Color::RGB(HIGHLIGHT_FONT_COLOR)),
+                            );
+                        sheet.add_conditional_format(header_row + 1, 0, last_row, (column_count - 1) as u16, &rule)?;
+                    }
+                    ConditionalFormat::ColorScale => {
+                        let color_scale = ConditionalFormat3ColorScale::new();
+                        sheet.add_conditional_format(header_row + 1, col, last_row, col, &color_scale)?;
+                    }
+                }
+            }
+        }
+        for (col, width) in widths.finish_sheet(style).into_iter().enumerate() {
+            if let Some(width) = width {
+                sheet.set_column_width(col as u16, width)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sheet "Chart" với biểu đồ cột tham chiếu vùng dữ liệu của sheet đầu tiên (`data_rows` dòng dữ liệu dưới
+    /// header ở `header_row`). Không tạo biểu đồ khi request không chọn cột nhãn hoặc cột giá trị, hoặc không có dòng nào để vẽ.
+    fn add_chart_sheet(
+        workbook: &mut Workbook,
+        layout: &ExportLayout,
+        spec: &ChartSpec,
+        header_row: u32,
+        data_rows: u32,
+    ) -> Result<()> {
+        let position = |field: &str| layout.columns.iter().position(|column| column.field == field);
+        let (Some(category_col), Some(value_col)) = (position(spec.category_field), position(spec.value_field)) else {
+            return Ok(());
+        };
+        let last_row = if spec.skip_last_row { data_rows.saturating_sub(1) } else { data_rows };
+        if last_row == 0 {
+            return Ok(());
+        }
+        let data_sheet = sheet_name(0);
+        let (category_col_num, value_col_num) = (category_col as u16, value_col as u16);
+        let mut chart = Chart::new(ChartType::Column);
+        chart
+            .add_series()
+            .set_name((data_sheet.as_str(), header_row, value_col_num))
+            .set_categories((data_sheet.as_str(), header_row + 1, category_col_num, header_row + last_row, category_col_num))
+            .set_values((data_sheet.as_str(), header_row + 1, value_col_num, header_row + last_row, value_col_num));
+        chart.title().set_name(spec.title);
+        chart.x_axis().set_name(layout.columns[category_col].header.as_ref());
+        chart.y_axis().set_name(layout.columns[value_col].header.as_ref());
+        chart.legend().set_hidden();
+        let sheet = workbook.add_worksheet();
+        sheet.set_name(CHART_SHEET_NAME)?;
+        sheet.insert_chart(1, 1, &chart)?;
+        Ok(())
+    }
+
+    /// Sheet "Info": cặp (nhãn, giá trị) về lần export, rồi từng tham số của request. Giá trị giữ kiểu thật
+    /// (số, ngày, boolean) thay vì chữ; tham số dạng danh sách hoặc object được ghi dưới dạng JSON.
+    fn add_info_sheet(
+        workbook: &mut Workbook,
+        metadata: &ExportMetadata,
+        header_format: &Format,
+        formats: [&Format; 3],
+        style: &ExcelStyleOptions,
+        adjustments: &mut CellAdjustments,
+    ) -> Result<()> {
+        let [integer_format, date_format, datetime_format] = formats;
+        let mut text = |value: String| match fit_excel_cell(CellValue::Text(value), &style.truncation_marker, adjustments) {
+            CellValue::Text(value) => value,
+            other => other.to_string(),
+        };
+        let sheet = workbook.add_worksheet();
+        sheet.set_name(INFO_SHEET_NAME)?;
+        sheet.write_string_with_format(0, 0, "Field", header_format)?;
+        sheet.write_string_with_format(0, 1, "Value", header_format)?;
+        sheet.write_string(1, 0, "Request ID")?;
+        sheet.write_string(1, 1, metadata.request_id.to_string())?;
+        sheet.write_string(2, 0, "User ID")?;
+        sheet.write_number_with_format(2, 1, metadata.user_id as f64, integer_format)?;
+        sheet.write_string(3, 0, "Report type")?;
+        sheet.write_string(3, 1, text(metadata.report_type.clone()))?;
+        sheet.write_string(4, 0, "Generated at (UTC)")?;
+        match excel_serial(metadata.generated_at.naive_utc()) {
+            Some(serial) => sheet.write_number_with_format(4, 1, serial, datetime_format)?,
+            None => sheet.write_string(4, 1, metadata.generated_at.to_rfc3339())?,
+        };
+        sheet.write_string(5, 0, "Time zone")?;
+        sheet.write_string(5, 1, &metadata.timezone)?;
+        sheet.write_string(6, 0, "Rows")?;
+        sheet.write_number_with_format(6, 1, metadata.row_count as f64, integer_format)?;
+        sheet.write_string(7, 0, "Service version")?;
+        sheet.write_string(7, 1, &metadata.service_version)?;
+        sheet.write_string(8, 0, "Checksum")?;
+        sheet.write_string(8, 1, &metadata.checksum_note)?;
+
+        sheet.write_string_with_format(10, 0, "Parameter", header_format)?;
+        sheet.write_string_with_format(10, 1, "Value", header_format)?;
+        let mut row = 10;
+        for (key, value) in &metadata.parameters {
+            if value.is_null() {
+                continue;
+            }
+            row += 1;
+            sheet.write_string(row, 0, key)?;
+            match value {
+                serde_json::Value::Bool(value) => sheet.write_boolean(row, 1, *value)?,
+                serde_json::Value::Number(value) => match value.as_f64() {
+                    Some(value) => sheet.write_number(row, 1, value)?,
+                    None => sheet.write_string(row, 1, value.to_string())?,
+                },
+                // `start_date`, `end_date`: ngày của Excel.
+                serde_json::Value::String(value) if key.ends_with("_date") => {
+                    let serial = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                        .and_then(excel_serial);
+                    match serial {
+                        Some(serial) => sheet.write_number_with_format(row, 1, serial, date_format)?,
+                        None => sheet.write_string(row, 1, text(value.clone()))?,
+                    }
+                }
+                serde_json::Value::String(value) => sheet.write_string(row, 1, text(value.clone()))?,
+                other => sheet.write_string(row, 1, text(other.to_string()))?,
+            };
+        }
+        sheet.autofit();
+        Ok(())
+    }
+
+    fn hex_color(value: &str) -> Result<Color> {
+        let rgb = u32::from_str_radix(value.trim_start_matches('#'), 16)
+            .with_context(|| format!("Invalid Excel color '{}'", value))?;
+        Ok(Color::RGB(rgb))
+    }
+
+    info!("Creating Excel file for request {} at: {}", request_id, path);
+    // Với khối tiêu đề, header của mỗi sheet dữ liệu nằm dưới tiêu đề; dòng dữ liệu thứ `n` của sheet là
+    // dòng `header_row + n`, và một sheet chứa ít dòng dữ liệu hơn để vẫn vừa giới hạn dòng của Excel.
+    let header_row = if style.title.is_some() { TITLE_BLOCK_ROWS } else { 0 };
+    let max_rows_per_sheet = max_rows_per_sheet.min(EXCEL_MAX_SHEET_ROWS - 1 - header_row);
+    // Ô header của sheet dữ liệu phải trùng tên cột của table.
+    let table_headers = style.table.then(|| table_column_names(&layout.headers()));
+    let headers: Vec<&str> = match &table_headers {
+        Some(names) => names.iter().map(String::as_str).collect(),
+        None => layout.headers(),
+    };
+    let header_format = if style.style_header {
+        Format::new()
+            .set_bold()
+            .set_font_color(hex_color(&style.header_font_color)?)
+            .set_background_color(hex_color(&style.header_background_color)?)
+    } else {
+        Format::new()
+    };
+    // Header của table lấy màu theo kiểu của table; sheet "Info" và "Index" vẫn dùng định dạng header.
+    let data_header_format = if style.table { Format::new() } else { header_format.clone() };
+    let mut tables = style.table.then(|| DataTables::new(style.table_style, &headers));
+    let integer_format = Format::new().set_num_format(&style.integer_format);
+    let decimal_format = Format::new().set_num_format(&style.decimal_format);
+    let date_format = Format::new().set_num_format(&style.date_format);
+    let datetime_format = Format::new().set_num_format(&style.datetime_format);
+    let column_formats: Vec<ColumnFormat> = layout.columns.iter().map(|column| column.format).collect();
+    let group_col = match style.group_by_sheet {
+        Some(grouping) => Some(
+            layout
+                .columns
+                .iter()
+                .position(|column| column.field == grouping.column().sql())
+                .with_context(|| format!("group_by_sheet requires the '{}' column", grouping.column().sql()))?,
+        ),
+        None => None,
+    };
+    // Ngưỡng của `highlight` trong payload thay cho ngưỡng khai báo trong layout cột.
+    let conditional_formats: Vec<(u16, ConditionalFormat)> = layout
+        .columns
+        .iter()
+        .enumerate()
+        .filter_map(|(col, column)| {
+            let conditional_format = match column.conditional_format? {
+                ConditionalFormat::RowBelow { threshold } => ConditionalFormat::RowBelow {
+                    threshold: style.highlight.and_then(|highlight| highlight.threshold(&column.field)).unwrap_or(threshold),
+                },
+                other => other,
+            };
+            Some((col as u16, conditional_format))
+        })
+        .collect();
+    let mut workbook = Workbook::new();
+    let mut sheet_names = SheetNames::default();
+    let mut adjustments = CellAdjustments::default();
+    if let Some(metadata) = metadata {
+        let formats = [&integer_format, &date_format, &datetime_format];
+        add_info_sheet(&mut workbook, metadata, &header_format, formats, style, &mut adjustments)?;
+        sheet_names.unique(INFO_SHEET_NAME);
+    }
+    let mut group_sheets: Vec<GroupSheet> = Vec::new();
+    let mut finished_groups = std::collections::HashSet::new();
+    let mut sheet_index = 0;
+    let mut sheet_count = 0u32;
+    // Sheet của nhóm chỉ được tạo khi gặp dòng đầu tiên của nhóm, nên nhóm không có dòng nào không có sheet.
+    // Sheet "Index" đứng đầu (sau sheet "Info") và ở chế độ thường (giữ trong bộ nhớ), vì chỉ ghi được khi đã biết mọi sheet.
+    let mut sheet = match group_col {
+        Some(_) => {
+            workbook.add_worksheet().set_name(INDEX_SHEET_NAME)?;
+            sheet_names.unique(INDEX_SHEET_NAME);
+            None
+        }
+        None => {
+            sheet_count += 1;
+            Some(add_sheet(&mut workbook, &sheet_name(sheet_index), &headers, &data_header_format, request_id, style)?)
+        }
+    };
+    let mut row_num = 0u32;
+    // Số hyperlink đã ghi trên sheet hiện tại, bắt đầu lại ở mỗi sheet mới.
+    let mut sheet_links = 0u32;
+    let mut widths = ColumnWidths::new(layout.columns.iter().map(|column| column.width).collect(), &headers);
+    let mut totals = ColumnTotals::new(layout.columns.iter().map(|column| column.aggregate).collect());
+    // Số dòng dữ liệu của các sheet đã ghi xong, cho công thức của dòng tổng.
+    let mut sheet_rows = Vec::new();
+
+    // Tổng số dòng dữ liệu đã ghi, cho vùng dữ liệu của biểu đồ.
+    let mut data_rows = 0usize;
+    for values in rows {
+        data_rows += 1;
+        let values: Vec<CellValue> = values?
+            .into_iter()
+            .map(|value| fit_excel_cell(value, &style.truncation_marker, &mut adjustments))
+            .collect();
+        if let Some(group_col) = group_col {
+            let group = values[group_col].to_string();
+            if group_sheets.last().map(|current| &current.group) != Some(&group) {
+                anyhow::ensure!(
+                    !finished_groups.contains(&group),
+                    "Rows are not ordered by the '{}' column: group '{}' appears again after other groups",
+                    layout.columns[group_col].field,
+                    group
+                );
+                if let Some(sheet) = sheet.take() {
+                    finish_sheet(sheet, header_row, row_num, &mut widths, &conditional_formats, tables.as_mut(), style)?;
+                }
+                if let Some(previous) = group_sheets.last() {
+                    finished_groups.insert(previous.group.clone());
+                }
+                let name = sheet_names.unique(&sanitize_sheet_name(&group));
+                sheet = Some(add_sheet(&mut workbook, &name, &headers, &data_header_format, request_id, style)?);
+                sheet_count += 1;
+                group_sheets.push(GroupSheet { name, group, rows: 0 });
+                row_num = 0;
+                sheet_links = 0;
+            }
+        }
+        if row_num == max_rows_per_sheet {
+            if let Some(sheet) = sheet.take() {
+                finish_sheet(sheet, header_row, row_num, &mut widths, &conditional_formats, tables.as_mut(), style)?;
+            }
+            sheet_rows.push(row_num);
+            sheet_index += 1;
+            // Nhóm vượt quá số dòng tối đa của một sheet tiếp tục sang `{tên nhóm} (2)`...
+            let name = match group_sheets.last() {
+                Some(current) => sheet_names.unique(&sanitize_sheet_name(&current.group)),
+                None => sheet_name(sheet_index),
+            };
+            sheet = Some(add_sheet(&mut workbook, &name, &headers, &data_header_format, request_id, style)?);
+            sheet_count += 1;
+            if let Some(current) = group_sheets.last() {
+                let group = current.group.clone();
+                group_sheets.push(GroupSheet { name, group, rows: 0 });
+            }
+            row_num = 0;
+            sheet_links = 0;
+        }
+        row_num += 1;
+        if let Some(current) = group_sheets.last_mut() {
+            current.rows += 1;
+        }
+        let sheet = sheet.as_deref_mut().context("Excel workbook has no data sheet")?;
+        let row = header_row + row_num;
+        if style.autofit {
+            widths.track(&values, style);
+        }
+        if style.totals_row {
+            totals.track(&values);
+        }
+        for (col, value) in values.into_iter().enumerate() {
+            let column_format = column_formats.get(col).copied().unwrap_or(ColumnFormat::General);
+            let col = col as u16;
+            match value {
+                CellValue::Number(value) => match column_format {
+                    ColumnFormat::Integer => sheet.write_number_with_format(row, col, value, &integer_format)?,
+                    ColumnFormat::Decimal => sheet.write_number_with_format(row, col, value, &decimal_format)?,
+                    _ => sheet.write_number(row, col, value)?,
+                },
+                CellValue::Text(value) => sheet.write_string(row, col, &value)?,
+                CellValue::Link { text, url } => {
+                    if sheet_links < EXCEL_MAX_SHEET_HYPERLINKS && url.chars().count() <= EXCEL_MAX_URL_CHARS {
+                        sheet_links += 1;
+                        sheet.write_url_with_text(row, col, Url::new(url), &text)?
+                    } else {
+                        adjustments.links_as_text += 1;
+                        sheet.write_string(row, col, &text)?
+                    }
+                }
+                CellValue::DateTime(value) => {
+                    // Cột khai báo `Date` chỉ hiện ngày; các cột khác hiện đủ ngày giờ.
+                    let format = if column_format == ColumnFormat::Date { &date_format } else { &datetime_format };
+                    match excel_serial(value.naive_local()) {
+                        Some(serial) => sheet.write_number_with_format(row, col, serial, format)?,
+                        None => sheet.write_string(row, col, value.to_string())?,
+                    }
+                }
+                CellValue::Date(value) => {
+                    let format = if column_format == ColumnFormat::DateTime { &datetime_format } else { &date_format };
+                    match value.and_hms_opt(0, 0, 0).and_then(excel_serial) {
+                        Some(serial) => sheet.write_number_with_format(row, col, serial, format)?,
+                        None => sheet.write_string(row, col, value.to_string())?,
+                    }
+                }
+            };
+        }
+    }
+
+    // Report nằm gọn trong một sheet có table: dòng tổng là dòng tổng của table, với hàm của table (SUBTOTAL
+    // theo tên cột) để đi theo bộ lọc của table. Report nhiều sheet giữ dòng tổng tính trên mọi sheet.
+    let table_totals =
+        style.totals_row && sheet_rows.is_empty() && row_num > 0 && header_row + row_num + 1 < EXCEL_MAX_SHEET_ROWS;
+    if let (true, Some(tables)) = (table_totals, tables.as_mut()) {
+        tables.set_total_row(layout);
+    } else if style.totals_row {
+        sheet_rows.push(row_num);
+        // Dòng tổng nằm ngay dưới dòng dữ liệu cuối của sheet cuối; sheet đã đầy tới giới hạn của Excel
+        // thì dòng tổng sang sheet mới.
+        if header_row + row_num + 1 >= EXCEL_MAX_SHEET_ROWS {
+            if let Some(sheet) = sheet.take() {
+                finish_sheet(sheet, header_row, row_num, &mut widths, &conditional_formats, tables.as_mut(), style)?;
+            }
+            sheet_index += 1;
+            sheet = Some(add_sheet(&mut workbook, &sheet_name(sheet_index), &headers, &data_header_format, request_id, style)?);
+            sheet_count += 1;
+            row_num = 0;
+        }
+        let sheet = sheet.as_deref_mut().context("Excel workbook has no data sheet")?;
+        let totals_row = header_row + row_num + 1;
+        let totals_format = Format::new().set_bold().set_border_top(FormatBorder::Thin);
+        let label_col = layout.columns.iter().position(|column| column.aggregate == ColumnAggregate::None);
+        for (col, column) in layout.columns.iter().enumerate() {
+            let format = match (column.aggregate, column.format) {
+                (ColumnAggregate::Count, _) | (ColumnAggregate::Sum, ColumnFormat::Integer) => {
+                    totals_format.clone().set_num_format(&style.integer_format)
+                }
+                (ColumnAggregate::Avg, _) | (ColumnAggregate::Sum, ColumnFormat::Decimal) => {
+                    totals_format.clone().set_num_format(&style.decimal_format)
+                }
+                _ => totals_format.clone(),
+            };
+            let col_num = col as u16;
+            if Some(col) == label_col {
+                sheet.write_string_with_format(totals_row, col_num, "Total", &format)?;
+                continue;
+            }
+            match (totals.value(col), style.totals_formulas.then(|| totals.formula(col, header_row, &sheet_rows)).flatten()) {
+                // Kết quả tính sẵn để trình xem không tự tính lại công thức vẫn hiện đúng giá trị.
+                (Some(value), Some(formula)) => {
+                    let formula = Formula::new(formula).set_result(value.to_string());
+                    sheet.write_formula_with_format(totals_row, col_num, formula, &format)?;
+                }
+                (Some(value), None) => {
+                    sheet.write_number_with_format(totals_row, col_num, value, &format)?;
+                }
+                _ => {
+                    sheet.write_blank(totals_row, col_num, &format)?;
+                }
+            }
+        }
+    }
+
+    if let Some(sheet) = sheet.take() {
+        finish_sheet(sheet, header_row, row_num, &mut widths, &conditional_formats, tables.as_mut(), style)?;
+    }
+
+    if let Some(group_col) = group_col {
+        let index = workbook.worksheet_from_name(INDEX_SHEET_NAME)?;
+        for (col, header) in ["Sheet", headers[group_col], "Rows"].into_iter().enumerate() {
+            index.write_string_with_format(0, col as u16, header, &header_format)?;
+        }
+        for (row, group_sheet) in group_sheets.iter().enumerate() {
+            let row = row as u32 + 1;
+            let link = format!("internal:'{}'!A1", group_sheet.name.replace('\'', "''"));
+            index.write_url(row, 0, Url::new(link).set_text(&group_sheet.name))?;
+            index.write_string(row, 1, &group_sheet.group)?;
+            index.write_number_with_format(row, 2, group_sheet.rows as f64, &integer_format)?;
+        }
+        if style.freeze_header {
+            index.set_freeze_panes(1, 0)?;
+        }
+        index.autofit();
+        sheet_count += 1;
+    }
+
+    if let (true, Some(spec)) = (style.chart, chart) {
+        let first_sheet_rows = (data_rows as u64).min(max_rows_per_sheet as u64) as u32;
+        add_chart_sheet(&mut workbook, layout, &spec, header_row, first_sheet_rows)?;
+    }
+
+    workbook.save(path).context("Failed to save Excel workbook")?;
+    info!("✅ Excel file successfully created at: {} ({} sheet(s))", path, sheet_count);
+    if !adjustments.is_empty() {
+        warn!("⚠️ Cells of request {} were adjusted to fit Excel's limits: {}", request_id, adjustments);
+    }
+    Ok((sheet_count, adjustments))
+}
+
+#[cfg(not(feature = "xlsxwriter"))]
+async fn write_workbook(
+    request_id: Uuid,
+    path: &str,
+    data: ReportData,
+    style: &ExcelStyleOptions,
+    metadata: Option<&ExportMetadata>,
+    escape: FormulaEscape,
+    max_rows_per_sheet: u32,
+) -> Result<(u32, CellAdjustments)> {
+    warn!("`xlsxwriter` feature not enabled. Using placeholder file creation. For full functionality, enable it in Cargo.toml.");
+    let _ = (style, metadata, escape); // File placeholder không có định dạng và sheet "Info"
+    let layout = data.layout();
+    let header = layout.headers();
+    tokio::fs::write(
+        path,
+        format!("Placeholder Excel content for request {}.\n{}\n", request_id, header.join("\t")),
+    )
+    .await
+    .context("Failed to write placeholder Excel file")?;
+    for row in data.rows() {
+        let values: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+        tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?
+            .write_all(format!("{}\n", values.join("\t")).as_bytes())
+            .await?;
+    }
+    let sheet_count = (data.len() as u32).div_ceil(max_rows_per_sheet).max(1);
+    info!("✅ Placeholder file created at: {}", path);
+    Ok((sheet_count, CellAdjustments::default()))
+}
+
+/// File placeholder không chia được theo kích thước: ghi một file duy nhất.
+#[cfg(not(feature = "xlsxwriter"))]
+#[allow(clippy::too_many_arguments)]
+async fn write_workbook_parts(
+    request_id: Uuid,
+    full_path: &str,
+    data: ReportData,
+    style: &ExcelStyleOptions,
+    metadata: Option<&ExportMetadata>,
+    escape: FormulaEscape,
+    max_rows_per_sheet: u32,
+    _max_bytes: u64,
+) -> Result<Vec<(usize, u32, CellAdjustments)>> {
+    let rows = data.len();
+    let partial_path = partial_file_path(full_path);
+    let (sheet_count, adjustments) =
+        write_workbook(request_id, &partial_path, data, style, metadata, escape, max_rows_per_sheet).await?;
+    Ok(vec![(rows, sheet_count, adjustments)])
+}
+
+/// Đọc template, ghi dữ liệu từ ô `{{data}}` (một sheet, không tách sheet) và lưu bản sao vào `path`.
+/// Ô số và ngày giờ dùng định dạng của layout cột; header và các ô khác giữ nguyên style của template.
+#[cfg(feature = "templates")]
+fn fill_template(
+    path: &str,
+    data: &ReportData,
+    template: &ExcelTemplate,
+    style: &ExcelStyleOptions,
+    escape: FormulaEscape,
+) -> Result<(u32, CellAdjustments)> {
+    use crate::models::{CellValue, ColumnFormat};
+
+    if !Path::new(&template.path).is_file() {
+        return Err(TemplateError(format!("Excel template '{}' does not exist", template.path)).into());
+    }
+    let mut book = umya_spreadsheet::reader::xlsx::read(&template.path)
+        .map_err(|e| TemplateError(format!("Failed to read Excel template '{}': {}", template.path, e)))?;
+
+    // Tìm ô `{{data}}` và thay placeholder trong mọi ô chữ của mọi sheet.
+    let mut marker = None;
+    for (sheet_index, sheet) in book.get_sheet_collection_mut().iter_mut().enumerate() {
+        for cell in sheet.get_cell_collection_mut() {
+            let value = cell.get_value().into_owned();
+            if value.trim() == TEMPLATE_DATA_MARKER {
+                let coordinate = cell.get_coordinate();
+                marker.get_or_insert((sheet_index, *coordinate.get_col_num(), *coordinate.get_row_num()));
+                continue;
+            }
+            if value.contains("{{") {
+                let mut filled = value.clone();
+                for (key, replacement) in &template.placeholders {
+                    filled = filled.replace(&format!("{{{{{}}}}}", key), replacement);
+                }
+                if filled != value {
+                    cell.set_value(filled);
+                }
+            }
+        }
+    }
+    let (sheet_index, first_col, header_row) = marker.ok_or_else(|| {
+        TemplateError(format!("Excel template '{}' has no {} cell", template.path, TEMPLATE_DATA_MARKER))
+    })?;
+    if header_row as usize + data.len() > EXCEL_MAX_SHEET_ROWS as usize {
+        return Err(TemplateError(format!(
+            "{} rows do not fit below the {} cell of Excel template '{}'",
+            data.len(),
+            TEMPLATE_DATA_MARKER,
+            template.path
+        ))
+        .into());
+    }
+
+    let layout = data.layout();
+    let sheet = &mut book.get_sheet_collection_mut()[sheet_index];
+    for (offset, header) in layout.headers().into_iter().enumerate() {
+        sheet.get_cell_mut((first_col + offset as u32, header_row)).set_value(header.to_string());
+    }
+    let mut adjustments = CellAdjustments::default();
+    for (row_offset, values) in data.escaped_rows(escape).enumerate() {
+        let values = values?;
+        let row = header_row + 1 + row_offset as u32;
+        for (offset, value) in values.into_iter().enumerate() {
+            let value = fit_excel_cell(value, &style.truncation_marker, &mut adjustments);
+            let column_format = layout.columns.get(offset).map_or(ColumnFormat::General, |column| column.format);
+            let cell = sheet.get_cell_mut((first_col + offset as u32, row));
+            let number_format = match value {
+                CellValue::Number(value) => {
+                    cell.set_value_number(value);
+                    match column_format {
+                        ColumnFormat::Integer => Some(&style.integer_format),
+                        ColumnFormat::Decimal => Some(&style.decimal_format),
+                        _ => None,
+                    }
+                }
+                CellValue::Text(value) | CellValue::Link { text: value, .. } => {
+                    cell.set_value(value);
+                    None
+                }
+                CellValue::DateTime(value) => match excel_serial(value.naive_local()) {
+                    Some(serial) => {
+                        cell.set_value_number(serial);
+                        Some(if column_format == ColumnFormat::Date { &style.date_format } else { &style.datetime_format })
+                    }
+                    None => {
+                        cell.set_value(value.to_string());
+                        None
+                    }
+                },
+                CellValue::Date(value) => match value.and_hms_opt(0, 0, 0).and_then(excel_serial) {
+                    Some(serial) => {
+                        cell.set_value_number(serial);
+                        Some(if column_format == ColumnFormat::DateTime { &style.datetime_format } else { &style.date_format })
+                    }
+                    None => {
+                        cell.set_value(value.to_string());
+                        None
+                    }
+                },
+            };
+            if let Some(number_format) = number_format {
+                cell.get_style_mut().get_number_format_mut().set_format_code(number_format.as_str());
+            }
+        }
+    }
+
+    umya_spreadsheet::writer::xlsx::write(&book, path)
+        .map_err(|e| anyhow::anyhow!("Failed to save Excel file from template: {}", e))?;
+    if !adjustments.is_empty() {
+        warn!("⚠️ Cells of Excel file {} were adjusted to fit Excel's limits: {}", path, adjustments);
+    }
+    Ok((1, adjustments))
+}
+
+#[cfg(not(feature = "templates"))]
+fn fill_template(
+    _path: &str,
+    _data: &ReportData,
+    template: &ExcelTemplate,
+    _style: &ExcelStyleOptions,
+    _escape: FormulaEscape,
+) -> Result<(u32, CellAdjustments)> {
+    Err(TemplateError(format!(
+        "Excel template '{}' is configured but the service was built without the `templates` feature",
+        template.path
+    ))
+    .into())
+}
+
+/// Ghi file Parquet trong thread blocking: writer của parquet chỉ có API đồng bộ.
+#[cfg(feature = "parquet")]
+async fn write_parquet(path: &str, data: ReportData, options: ParquetOptions) -> Result<()> {
+    use crate::services::parquet_exporter::ParquetFileExporter;
+
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || ParquetFileExporter::new(options).write(&path, &data))
+        .await
+        .context("Parquet task panicked")??;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+async fn write_parquet(_path: &str, _data: ReportData, _options: ParquetOptions) -> Result<()> {
+    anyhow::bail!("Parquet output requires the service to be built with the `parquet` feature")
+}
+
+/// Ghi file ODS trong thread blocking: thư viện chỉ có API đồng bộ.
+#[cfg(feature = "ods")]
+async fn write_ods_file(
+    path: &str,
+    data: ReportData,
+    style: &ExcelStyleOptions,
+    escape: FormulaEscape,
+    max_rows_per_sheet: u32,
+) -> Result<u32> {
+    use crate::services::ods_exporter::OdsFileExporter;
+
+    let path = path.to_string();
+    let exporter = OdsFileExporter::new(style.clone(), escape, max_rows_per_sheet);
+    tokio::task::spawn_blocking(move || exporter.write(&path, &data))
+        .await
+        .context("ODS task panicked")?
+}
+
+/// Không có placeholder: request `ods` bị từ chối rõ ràng khi build không có feature.
+#[cfg(not(feature = "ods"))]
+async fn write_ods_file(
+    _path: &str,
+    _data: ReportData,
+    _style: &ExcelStyleOptions,
+    _escape: FormulaEscape,
+    _max_rows_per_sheet: u32,
+) -> Result<u32> {
+    anyhow::bail!("ODS output is not supported by this build: enable the `ods` feature")
+}
+
+/// Render file PDF trong thread blocking: genpdf chỉ có API đồng bộ.
+#[cfg(feature = "pdf")]
+async fn write_pdf(path: &str, data: ReportData, report: &PdfReport, options: &PdfOptions) -> Result<()> {
+    use crate::services::pdf_exporter::PdfExporter;
+
+    let path = path.to_string();
+    let report = report.clone();
+    let exporter = PdfExporter::new(options.font_dir.clone(), options.font_family.clone());
+    tokio::task::spawn_blocking(move || exporter.write(&path, &data, &report))
+        .await
+        .context("PDF task panicked")?
+}
+
+#[cfg(not(feature = "pdf"))]
+async fn write_pdf(_path: &str, _data: ReportData, _report: &PdfReport, _options: &PdfOptions) -> Result<()> {
+    anyhow::bail!("PDF output is not supported by this build: enable the `pdf` feature")
+}
+
+/// Ghi UTF-8 BOM ở đầu file khi `options.bom` bật (Excel trên Windows mới nhận ra file là UTF-8).
+async fn write_csv_bom(writer: &mut (dyn AsyncWrite + Send + Unpin), options: &CsvOptions) -> Result<()> {
+    if options.bom {
+        writer.write_all(UTF8_BOM).await.context("Failed to write CSV byte order mark")?;
+    }
+    Ok(())
+}
+
+/// Ghi header và các dòng dữ liệu dạng CSV theo dialect `options`. Với dialect mặc định, file theo đúng quy tắc
+/// của `COPY ... WITH (FORMAT csv)` của Postgres để giống hệt nhau dù được tạo bằng COPY hay từng dòng.
+async fn write_csv(
+    writer: &mut (dyn AsyncWrite + Send + Unpin),
+    data: &ReportData,
+    options: &CsvOptions,
+    escape: FormulaEscape,
+) -> Result<()> {
+    let (header, rows) = csv_lines(data, options, escape)?;
+    if let Some(header) = header {
+        writer.write_all(&header).await.context("Failed to write CSV header")?;
+    }
+    for line in rows {
+        writer.write_all(&line?).await.context("Failed to write CSV row")?;
+    }
+    Ok(())
+}
+
+/// Dòng header (`None` khi `options.header` tắt) và từng dòng dữ liệu CSV, đã chuyển sang bảng mã của file.
+fn csv_lines<'a>(
+    data: &'a ReportData,
+    options: &'a CsvOptions,
+    escape: FormulaEscape,
+) -> Result<(Option<Vec<u8>>, impl Iterator<Item = Result<Vec<u8>>> + Send + 'a)> {
+    let encoding = options.output_encoding()?;
+    let headers: Vec<String> = data.layout().headers().iter().map(|header| header.to_string()).collect();
+    let header = if options.header {
+        let fields: Vec<String> = headers.iter().map(|header| csv_field(header, false, options)).collect();
+        Some(encode_csv_line(&fields, options, encoding, 0, &headers)?)
+    } else {
+        None
+    };
+    let decimal_separator = options.decimal_separator.to_string();
+    let rows = data.escaped_rows(escape).enumerate().map(move |(index, values)| {
+        let fields: Vec<String> = values?
+            .iter()
+            .map(|value| match value {
+                CellValue::Number(_) => csv_field(&value.to_string().replace('.', &decimal_separator), true, options),
+                // CSV không có hyperlink: ghi URL, chữ hiển thị đã có ở cột của nó.
+                CellValue::Link { url, .. } => csv_field(url, false, options),
+                value => csv_field(&value.to_string(), false, options),
+            })
+            .collect();
+        encode_csv_line(&fields, options, encoding, index + 1, &headers)
+    });
+    Ok((header, rows))
+}
+
+/// Đặt giá trị trong dấu nháy kép (nhân đôi dấu nháy bên trong) theo `quote_style`. Khi chỉ đặt nháy lúc cần:
+/// giá trị chứa dấu phân cách, dấu nháy hoặc xuống dòng; chuỗi rỗng và `\.` cũng được đặt trong nháy, giống Postgres
+/// (để phân biệt với NULL và dấu kết thúc dữ liệu).
+fn csv_field(value: &str, is_number: bool, options: &CsvOptions) -> String {
+    let needs_quotes = match options.quote_style {
+        CsvQuoteStyle::Always => true,
+        CsvQuoteStyle::NonNumeric if !is_number => true,
+        _ => value.is_empty() || value == "\\." || value.contains([options.delimiter, '"', '\n', '\r']),
+    };
+    if needs_quotes {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Nối các ô thành một dòng CSV trong bảng mã của file. `row` là số thứ tự dòng dữ liệu (0 là header),
+/// dùng cho lỗi khi một ô có ký tự không biểu diễn được trong bảng mã.
+fn encode_csv_line(
+    fields: &[String],
+    options: &CsvOptions,
+    encoding: &'static encoding_rs::Encoding,
+    row: usize,
+    headers: &[String],
+) -> Result<Vec<u8>> {
+    let mut line = Vec::new();
+    let mut delimiter = [0; 4];
+    let delimiter = options.delimiter.encode_utf8(&mut delimiter).as_bytes();
+    for (col, field) in fields.iter().enumerate() {
+        if col > 0 {
+            line.extend_from_slice(delimiter);
+        }
+        let (bytes, _, had_errors) = encoding.encode(field);
+        if had_errors {
+            let character = field
+                .chars()
+                .find(|c| encoding.encode(c.encode_utf8(&mut [0; 4])).2)
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            return Err(CsvEncodingError {
+                row,
+                column: headers.get(col).map_or_else(|| (col + 1).to_string(), |header| header.to_string()),
+                character,
+                encoding: encoding.name(),
+            }
+            .into());
+        }
+        line.extend_from_slice(&bytes);
+    }
+    line.extend_from_slice(options.line_terminator.as_str().as_bytes());
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CsvLineTerminator, DatasetRows, ParquetCompression};
+
+    fn dataset(columns: &[&str], rows: Vec<Vec<CellValue>>) -> ReportData {
+        ReportData::Dataset(DatasetRows { columns: columns.iter().map(|column| column.to_string()).collect(), rows })
+    }
+
+    fn text(value: &str) -> CellValue {
+        CellValue::Text(value.to_string())
+    }
+
+    async fn csv_bytes(data: &ReportData, options: &CsvOptions) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        write_csv_bom(&mut buffer, options).await?;
+        write_csv(&mut buffer, data, options, FormulaEscape::Off).await?;
+        Ok(buffer)
+    }
+
+    #[tokio::test]
+    async fn csv_quotes_only_values_that_need_it() {
+        let data = dataset(
+            &["name", "note"],
+            vec![
+                vec![text("plain"), text("a,b")],
+                vec![text("say \"hi\""), text("two\nlines")],
+                vec![text(""), text("\\.")],
+            ],
+        );
+
+        let csv = csv_bytes(&data, &CsvOptions::default()).await.unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "name,note\nplain,\"a,b\"\n\"say \"\"hi\"\"\",\"two\nlines\"\n\"\",\"\\.\"\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn csv_quote_styles_always_and_non_numeric() {
+        let data = dataset(&["name", "price"], vec![vec![text("Tea"), CellValue::Number(2.5)]]);
+        let always = CsvOptions { quote_style: CsvQuoteStyle::Always, header: false, ..CsvOptions::default() };
+        let non_numeric = CsvOptions { quote_style: CsvQuoteStyle::NonNumeric, header: false, ..CsvOptions::default() };
+
+        assert_eq!(csv_bytes(&data, &always).await.unwrap(), b"\"Tea\",\"2.5\"\n");
+        assert_eq!(csv_bytes(&data, &non_numeric).await.unwrap(), b"\"Tea\",2.5\n");
+    }
+
+    #[tokio::test]
+    async fn csv_bom_is_written_only_when_enabled() {
+        let data = dataset(&["name"], vec![vec![text("Tea")]]);
+        let options = CsvOptions { bom: true, ..CsvOptions::default() };
+
+        assert_eq!(csv_bytes(&data, &options).await.unwrap(), b"\xEF\xBB\xBFname\nTea\n");
+        assert!(!csv_bytes(&data, &CsvOptions::default()).await.unwrap().starts_with(UTF8_BOM));
+    }
+
+    #[tokio::test]
+    async fn csv_crlf_ends_every_line_but_keeps_line_breaks_inside_values() {
+        let data = dataset(&["name"], vec![vec![text("Tea")], vec![text("two\nlines")]]);
+        let options = CsvOptions { line_terminator: CsvLineTerminator::Crlf, ..CsvOptions::default() };
+
+        assert_eq!(csv_bytes(&data, &options).await.unwrap(), b"name\r\nTea\r\n\"two\nlines\"\r\n");
+    }
+
+    #[tokio::test]
+    async fn csv_semicolon_with_decimal_comma() {
+        let data = dataset(&["name", "price"], vec![vec![text("1,5 kg"), CellValue::Number(1234.5)]]);
+        let options = CsvOptions { delimiter: ';', decimal_separator: ',', ..CsvOptions::default() };
+
+        assert_eq!(String::from_utf8(csv_bytes(&data, &options).await.unwrap()).unwrap(), "name;price\n1,5 kg;1234,5\n");
+    }
+
+    #[tokio::test]
+    async fn csv_latin1_encodes_accents_and_rejects_other_characters() {
+        let options = CsvOptions { encoding: "latin1".to_string(), ..CsvOptions::default() };
+        let data = dataset(&["name"], vec![vec![text("Café")]]);
+        assert_eq!(csv_bytes(&data, &options).await.unwrap(), b"name\nCaf\xE9\n");
+
+        let data = dataset(&["name"], vec![vec![text("Café")], vec![text("Phở")]]);
+        let error = csv_bytes(&data, &options).await.unwrap_err();
+        let error = error.downcast_ref::<CsvEncodingError>().expect("CsvEncodingError");
+        assert_eq!((error.row, error.column.as_str(), error.character), (2, "name", 'ở'));
+    }
+
+    /// Thư mục tạm riêng cho một test, bị xóa khi drop.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("excel-export-test-{}", Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn file(&self, name: &str) -> String {
+            self.0.join(name).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_write_leaves_no_final_file_and_removes_the_temp_file() {
+        let dir = TempDir::new();
+        let full_path = dir.file("report.xlsx");
+        let partial_path = partial_file_path(&full_path);
+        std::fs::write(&partial_path, b"PK\x03\x04half a workbook").unwrap();
+
+        let result = move_into_place(Err::<(), _>(anyhow::anyhow!("disk full")), &partial_path, &full_path).await;
+
+        assert!(result.is_err());
+        assert!(!Path::new(&full_path).exists());
+        assert!(!Path::new(&partial_path).exists());
+    }
+
+    #[tokio::test]
+    async fn failed_publish_leaves_no_final_file_and_removes_the_temp_file() {
+        let dir = TempDir::new();
+        let partial_path = partial_file_path(&dir.file("report.xlsx"));
+        std::fs::write(&partial_path, b"complete workbook").unwrap();
+        // Thư mục đích không tồn tại: đổi tên thất bại sau khi file tạm đã được ghi đủ.
+        let full_path = dir.file("missing/report.xlsx");
+
+        let result = move_into_place(Ok(()), &partial_path, &full_path).await;
+
+        assert!(result.is_err());
+        assert!(!Path::new(&full_path).exists());
+        assert!(!Path::new(&partial_path).exists());
+    }
+
+    #[tokio::test]
+    async fn successful_write_is_moved_into_place() {
+        let dir = TempDir::new();
+        let full_path = dir.file("report.xlsx");
+        let partial_path = partial_file_path(&full_path);
+        std::fs::write(&partial_path, b"complete workbook").unwrap();
+
+        move_into_place(Ok(()), &partial_path, &full_path).await.unwrap();
+
+        assert_eq!(std::fs::read(&full_path).unwrap(), b"complete workbook");
+        assert!(!Path::new(&partial_path).exists());
+    }
+
+    #[tokio::test]
+    async fn csv_that_fails_halfway_is_never_published_and_is_cleaned_up() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let exporter = local_exporter();
+        let request_id = Uuid::new_v4();
+        let options = CsvOptions { encoding: "latin1".to_string(), ..CsvOptions::default() };
+        // Dòng thứ hai không biểu diễn được bằng Latin-1: lỗi sau khi header và dòng đầu đã được ghi.
+        let data = dataset(&["name"], vec![vec![text("Café")], vec![text("Phở")]]);
+
+        let result = exporter
+            .export_to_csv(request_id, data, &options, OutputCompression::None, None, &export_path)
+            .await;
+
+        assert!(result.is_err());
+        let full_path = csv_file_path(&export_path, request_id, OutputCompression::None);
+        assert!(!Path::new(&full_path).exists());
+        assert!(Path::new(&partial_file_path(&full_path)).exists());
+
+        exporter.remove_partial_output(request_id, &export_path).await.unwrap();
+        assert!(!Path::new(&partial_file_path(&full_path)).exists());
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 0);
+    }
+
+    /// File CSV của report sản phẩm với các cột mặc định, từng byte như trước khi exporter dùng chung `ReportData`.
+    const GOLDEN_PRODUCTS_CSV: &str = "Product ID,Name,Category,Price,Stock Quantity,Created At\n\
+        1,Notebook,Stationery,2.5,120,2024-01-02 08:30:00 UTC\n\
+        2,\"Pens, blue (10)\",Stationery,4,0,2024-01-15 23:59:59 UTC\n\
+        3,\"The \"\"Rust\"\" Book\",Books,39.99,7,2024-01-31 00:00:00 UTC\n";
+
+    fn golden_products() -> ReportData {
+        use crate::models::{ProductColumn, ProductData};
+        let product = |product_id, name: &str, category: &str, price, stock_quantity, created_at: &str| ProductData {
+            product_id,
+            name: name.to_string(),
+            category: category.to_string(),
+            price,
+            stock_quantity,
+            created_at: created_at.parse().unwrap(),
+        };
+        ReportData::Products {
+            rows: vec![
+                product(1, "Notebook", "Stationery", 2.5, 120, "2024-01-02T08:30:00Z"),
+                product(2, "Pens, blue (10)", "Stationery", 4.0, 0, "2024-01-15T23:59:59Z"),
+                product(3, "The \"Rust\" Book", "Books", 39.99, 7, "2024-01-31T00:00:00Z"),
+            ],
+            columns: ProductColumn::ALL.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn products_csv_matches_the_golden_file() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let request_id = Uuid::new_v4();
+
+        let exported = local_exporter()
+            .export_to_csv(request_id, golden_products(), &CsvOptions::default(), OutputCompression::None, None, &export_path)
+            .await
+            .unwrap();
+
+        assert_eq!(String::from_utf8(std::fs::read(&exported.path).unwrap()).unwrap(), GOLDEN_PRODUCTS_CSV);
+        assert_eq!(csv_bytes(&golden_products(), &CsvOptions::default()).await.unwrap(), GOLDEN_PRODUCTS_CSV.as_bytes());
+    }
+
+    fn golden_orders() -> ReportData {
+        use crate::models::OrderData;
+        ReportData::Orders(vec![OrderData {
+            order_id: 501,
+            user_id: 42,
+            product_name: "Notebook".to_string(),
+            quantity: 3,
+            unit_price: 2.5,
+            total: 7.5,
+            ordered_at: "2024-01-05T10:00:00Z".parse().unwrap(),
+            status: "paid".to_string(),
+        }])
+    }
+
+    /// Sản phẩm của golden file với các cột (và thứ tự cột) được chọn.
+    fn selected_products(selected: &[crate::models::ProductColumn]) -> ReportData {
+        let ReportData::Products { rows, .. } = golden_products() else { unreachable!() };
+        ReportData::Products { rows, columns: selected.to_vec() }
+    }
+
+    #[tokio::test]
+    async fn csv_writer_renders_each_report_layout() {
+        use crate::models::ProductColumn;
+
+        let orders = csv_bytes(&golden_orders(), &CsvOptions::default()).await.unwrap();
+        let products = csv_bytes(&selected_products(&[ProductColumn::Price, ProductColumn::Name]), &CsvOptions::default()).await.unwrap();
+
+        assert_eq!(
+            String::from_utf8(orders).unwrap(),
+            "Order ID,User ID,Product Name,Quantity,Unit Price,Total,Ordered At,Status\n\
+             501,42,Notebook,3,2.5,7.5,2024-01-05 10:00:00 UTC,paid\n"
+        );
+        assert_eq!(
+            String::from_utf8(products).unwrap(),
+            "Price,Name\n2.5,Notebook\n4,\"Pens, blue (10)\"\n39.99,\"The \"\"Rust\"\" Book\"\n"
+        );
+    }
+
+    /// Đọc lại sheet "Data" của workbook bằng calamine.
+    #[cfg(feature = "xlsxwriter")]
+    fn read_data_sheet(path: &str) -> calamine::Range<calamine::Data> {
+        read_sheet(path, &sheet_name(0))
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    fn read_sheet(path: &str, name: &str) -> calamine::Range<calamine::Data> {
+        use calamine::Reader;
+        let mut workbook: calamine::Xlsx<_> = calamine::open_workbook(path).expect("workbook opens");
+        workbook.worksheet_range(name).unwrap_or_else(|e| panic!("sheet {}: {}", name, e))
+    }
+
+    /// Tên các sheet của workbook, theo thứ tự.
+    #[cfg(feature = "xlsxwriter")]
+    fn read_sheet_names(path: &str) -> Vec<String> {
+        use calamine::Reader;
+        let workbook: calamine::Xlsx<_> = calamine::open_workbook(path).expect("workbook opens");
+        workbook.sheet_names()
+    }
+
+    /// Nội dung XML của một phần trong file xlsx (ví dụ `xl/styles.xml`).
+    #[cfg(feature = "xlsxwriter")]
+    fn xlsx_part(path: &str, name: &str) -> String {
+        use std::io::Read;
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path).unwrap()).expect("xlsx is a zip file");
+        let mut xml = String::new();
+        archive.by_name(name).unwrap_or_else(|e| panic!("{}: {}", name, e)).read_to_string(&mut xml).unwrap();
+        xml
+    }
+
+    /// XML của worksheet thứ `index` (từ 1) theo thứ tự sheet của workbook.
+    #[cfg(feature = "xlsxwriter")]
+    fn sheet_xml(path: &str, index: usize) -> String {
+        xlsx_part(path, &format!("xl/worksheets/sheet{}.xml", index))
+    }
+
+    /// Giá trị thuộc tính `name` của mọi thẻ `tag` có thuộc tính đó trong `xml`, theo thứ tự.
+    #[cfg(feature = "xlsxwriter")]
+    fn xml_attributes(xml: &str, tag: &str, name: &str) -> Vec<String> {
+        let tag = regex::Regex::new(&format!(r"<{}\b[^>]*>", tag)).unwrap();
+        let attribute = regex::Regex::new(&format!(r#"\b{}="([^"]*)""#, name)).unwrap();
+        tag.find_iter(xml)
+            .filter_map(|element| attribute.captures(element.as_str()).map(|captures| captures[1].to_string()))
+            .collect()
+    }
+
+    /// Các phần tử `element` (cả thẻ mở lẫn nội dung) nằm trong phần tử `section` đầu tiên của `xml`.
+    #[cfg(feature = "xlsxwriter")]
+    fn xml_elements(xml: &str, section: &str, element: &str) -> Vec<String> {
+        let start = xml.find(&format!("<{}", section)).unwrap_or_else(|| panic!("no <{}>", section));
+        let end = start + xml[start..].find(&format!("</{}>", section)).unwrap_or(xml.len() - start);
+        let pattern = format!(r"(?s)<{0}\b[^>]*/>|<{0}\b[^>]*>.*?</{0}>", element);
+        let elements = regex::Regex::new(&pattern).unwrap();
+        elements.find_iter(&xml[start..end]).map(|found| found.as_str().to_string()).collect()
+    }
+
+    /// Phần tử `xf` trong `cellXfs` của `styles` mà ô `cell` (ví dụ `A1`) của worksheet dùng.
+    #[cfg(feature = "xlsxwriter")]
+    fn cell_xf(sheet_xml: &str, styles: &str, cell: &str) -> String {
+        let cell_tag = regex::Regex::new(&format!(r#"<c r="{}"[^>]*>"#, cell)).unwrap();
+        let tag = cell_tag.find(sheet_xml).unwrap_or_else(|| panic!("no cell {}", cell)).as_str();
+        let index: usize = xml_attributes(tag, "c", "s").first().map_or(0, |index| index.parse().unwrap());
+        xml_elements(styles, "cellXfs", "xf").swap_remove(index)
+    }
+
+    /// Phần tử thứ `attribute` của `xf` (ví dụ font theo `fontId`) trong phần `section` của `styles`.
+    #[cfg(feature = "xlsxwriter")]
+    fn xf_element(styles: &str, xf: &str, attribute: &str, section: &str, element: &str) -> String {
+        let index: usize = xml_attributes(xf, "xf", attribute)[0].parse().unwrap();
+        xml_elements(styles, section, element).swap_remove(index)
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    fn number(cell: &calamine::Data) -> f64 {
+        match cell {
+            calamine::Data::Float(value) => *value,
+            calamine::Data::Int(value) => *value as f64,
+            calamine::Data::DateTime(value) => value.as_f64(),
+            other => panic!("expected a number, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn products_workbook_reads_back_with_the_expected_cells() {
+        use calamine::Data;
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+
+        let exported = local_exporter()
+            .export_to_excel(Uuid::new_v4(), golden_products(), &ExcelStyleOptions::default(), None, None, &export_path)
+            .await
+            .unwrap();
+        let range = read_data_sheet(&exported.path);
+
+        assert_eq!(range.get_size(), (4, 6));
+        let headers: Vec<&Data> = (0..6).map(|col| range.get_value((0, col)).unwrap()).collect();
+        assert_eq!(
+            headers,
+            ["Product ID", "Name", "Category", "Price", "Stock Quantity", "Created At"]
+                .map(|header| Data::String(header.to_string()))
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        let names: Vec<&Data> = (1..4).map(|row| range.get_value((row, 1)).unwrap()).collect();
+        assert_eq!(
+            names,
+            ["Notebook", "Pens, blue (10)", "The \"Rust\" Book"].map(|name| Data::String(name.to_string())).iter().collect::<Vec<_>>()
+        );
+        let numbers: Vec<[f64; 3]> = (1..4)
+            .map(|row| [0, 3, 4].map(|col| number(range.get_value((row, col)).unwrap())))
+            .collect();
+        assert_eq!(numbers, vec![[1.0, 2.5, 120.0], [2.0, 4.0, 0.0], [3.0, 39.99, 7.0]]);
+        let created_at = number(range.get_value((1, 5)).unwrap());
+        let expected = excel_serial("2024-01-02T08:30:00".parse().unwrap()).unwrap();
+        assert!((created_at - expected).abs() < 1e-9, "{} != {}", created_at, expected);
+    }
+
+    /// Dataset `id`, `name` với `rows` dòng, id từ 1.
+    fn numbered_rows(rows: usize) -> ReportData {
+        let rows = (1..=rows).map(|id| vec![CellValue::Number(id as f64), text(&format!("product {}", id))]).collect();
+        dataset(&["id", "name"], rows)
+    }
+
+    /// Id các dòng dữ liệu của từng sheet dữ liệu, sau khi kiểm tra header của sheet.
+    #[cfg(feature = "xlsxwriter")]
+    fn ids_per_sheet(path: &str, sheets: u32) -> Vec<Vec<u32>> {
+        use calamine::Data;
+        (0..sheets)
+            .map(|index| {
+                let range = read_sheet(path, &sheet_name(index));
+                assert_eq!(range.get_value((0, 0)), Some(&Data::String("id".to_string())), "header of sheet {}", index);
+                assert_eq!(range.get_value((0, 1)), Some(&Data::String("name".to_string())), "header of sheet {}", index);
+                (1..range.height() as u32).map(|row| number(range.get_value((row, 0)).unwrap()) as u32).collect()
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn rows_over_the_sheet_limit_continue_on_new_sheets_with_their_own_header() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+
+        let exported = exporter_with_sheet_rows(10)
+            .export_to_excel(Uuid::new_v4(), numbered_rows(25), &ExcelStyleOptions::default(), None, None, &export_path)
+            .await
+            .unwrap();
+
+        assert_eq!((exported.parts, exported.rows_written), (3, 25));
+        assert_eq!(read_sheet_names(&exported.path), ["Data", "Data (2)", "Data (3)"]);
+        let ids = ids_per_sheet(&exported.path, 3);
+        assert_eq!(ids.iter().map(Vec::len).collect::<Vec<_>>(), [10, 10, 5]);
+        assert_eq!(ids.concat(), (1..=25).collect::<Vec<u32>>());
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn rows_filling_the_last_sheet_exactly_add_no_empty_sheet() {
+        let dir = TempDir::new();
+
+        for (rows, sheets) in [(9, 1), (10, 1), (11, 2), (20, 2)] {
+            let path = dir.file(&format!("{}.xlsx", rows));
+            let (sheet_count, _) =
+                write_workbook(Uuid::new_v4(), &path, numbered_rows(rows), &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10)
+                    .await
+                    .unwrap();
+
+            assert_eq!(sheet_count, sheets, "{} rows", rows);
+            assert_eq!(read_sheet_names(&path).len(), sheets as usize, "{} rows", rows);
+            assert_eq!(ids_per_sheet(&path, sheets).concat(), (1..=rows as u32).collect::<Vec<_>>(), "{} rows", rows);
+        }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn empty_report_still_has_a_data_sheet_with_the_header() {
+        let dir = TempDir::new();
+        let path = dir.file("empty.xlsx");
+
+        let (sheet_count, _) =
+            write_workbook(Uuid::new_v4(), &path, numbered_rows(0), &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10)
+                .await
+                .unwrap();
+
+        assert_eq!(sheet_count, 1);
+        assert_eq!(ids_per_sheet(&path, 1), vec![Vec::<u32>::new()]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn header_is_styled_and_frozen_and_filtered_on_every_sheet() {
+        let dir = TempDir::new();
+        let path = dir.file("styled.xlsx");
+
+        write_workbook(Uuid::new_v4(), &path, numbered_rows(25), &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10)
+            .await
+            .unwrap();
+
+        let styles = xlsx_part(&path, "xl/styles.xml");
+        // Vùng lọc phủ header và đúng số dòng dữ liệu của từng sheet (10, 10, 5).
+        for (index, filter) in [(1, "A1:B11"), (2, "A1:B11"), (3, "A1:B6")] {
+            let sheet = sheet_xml(&path, index);
+            assert_eq!(xml_attributes(&sheet, "autoFilter", "ref"), [filter], "sheet {}", index);
+            assert_eq!(xml_attributes(&sheet, "pane", "ySplit"), ["1"], "sheet {}", index);
+            assert_eq!(xml_attributes(&sheet, "pane", "topLeftCell"), ["A2"], "sheet {}", index);
+            assert_eq!(xml_attributes(&sheet, "pane", "state"), ["frozen"], "sheet {}", index);
+
+            for header in ["A1", "B1"] {
+                let xf = cell_xf(&sheet, &styles, header);
+                let font = xf_element(&styles, &xf, "fontId", "fonts", "font");
+                let fill = xf_element(&styles, &xf, "fillId", "fills", "fill");
+                assert!(font.contains("<b/>") && font.contains(r#"rgb="FFFFFFFF""#), "sheet {} {}: {}", index, header, font);
+                assert!(fill.contains(r#"rgb="FF1F4E78""#), "sheet {} {}: {}", index, header, fill);
+            }
+            let data_font = xf_element(&styles, &cell_xf(&sheet, &styles, "B2"), "fontId", "fonts", "font");
+            assert!(!data_font.contains("<b/>"), "sheet {}: {}", index, data_font);
+        }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn header_style_freeze_and_filter_can_be_turned_off() {
+        let dir = TempDir::new();
+        let path = dir.file("plain.xlsx");
+        let style = ExcelStyleOptions { style_header: false, freeze_header: false, autofilter: false, ..ExcelStyleOptions::default() };
+
+        write_workbook(Uuid::new_v4(), &path, numbered_rows(3), &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let sheet = sheet_xml(&path, 1);
+        let styles = xlsx_part(&path, "xl/styles.xml");
+        assert!(xml_attributes(&sheet, "autoFilter", "ref").is_empty());
+        assert!(xml_attributes(&sheet, "pane", "state").is_empty());
+        let font = xf_element(&styles, &cell_xf(&sheet, &styles, "A1"), "fontId", "fonts", "font");
+        assert!(!font.contains("<b/>"), "{}", font);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn header_colors_follow_the_style_options() {
+        let dir = TempDir::new();
+        let path = dir.file("colors.xlsx");
+        let style = ExcelStyleOptions {
+            header_font_color: "#000000".to_string(),
+            header_background_color: "#FFD966".to_string(),
+            ..ExcelStyleOptions::default()
+        };
+
+        write_workbook(Uuid::new_v4(), &path, numbered_rows(1), &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let sheet = sheet_xml(&path, 1);
+        let styles = xlsx_part(&path, "xl/styles.xml");
+        let xf = cell_xf(&sheet, &styles, "A1");
+        let font = xf_element(&styles, &xf, "fontId", "fonts", "font");
+        assert!(font.contains("<b/>") && font.contains(r#"rgb="FF000000""#), "{}", font);
+        assert!(xf_element(&styles, &xf, "fillId", "fills", "fill").contains(r#"rgb="FFFFD966""#));
+    }
+
+    /// Độ rộng của từng cột theo các phần tử `<col>` của worksheet (một phần tử có thể phủ nhiều cột liền nhau).
+    #[cfg(feature = "xlsxwriter")]
+    fn column_widths(sheet_xml: &str) -> Vec<f64> {
+        let [mins, maxs, widths] = ["min", "max", "width"].map(|name| xml_attributes(sheet_xml, "col", name));
+        let mut columns = Vec::new();
+        for ((min, max), width) in mins.iter().zip(&maxs).zip(&widths) {
+            let (min, max): (usize, usize) = (min.parse().unwrap(), max.parse().unwrap());
+            columns.extend(std::iter::repeat(width.parse::<f64>().unwrap()).take(max - min + 1));
+        }
+        columns
+    }
+
+    /// So độ rộng đã ghi với số ký tự mong đợi: rust_xlsxwriter ghi `<col width>` theo chiều rộng chữ số của
+    /// font mặc định, (số ký tự × 7px + 5px lề) / 7px, làm tròn xuống 1/256.
+    #[cfg(feature = "xlsxwriter")]
+    fn assert_column_widths(sheet_xml: &str, expected: &[f64]) {
+        let widths = column_widths(sheet_xml);
+        assert_eq!(widths.len(), expected.len(), "{:?}", widths);
+        for (col, (width, characters)) in widths.iter().zip(expected).enumerate() {
+            let written = ((characters * 7.0).round() + 5.0) / 7.0;
+            assert!(written - width >= 0.0 && written - width < 1.0 / 256.0, "column {}: {} for {} characters", col, width, characters);
+        }
+    }
+
+    /// Sản phẩm của golden file, sản phẩm thứ hai có tên dài hơn độ rộng tối đa của cột.
+    #[cfg(feature = "xlsxwriter")]
+    fn products_with_a_long_name() -> ReportData {
+        let ReportData::Products { mut rows, columns } = golden_products() else { unreachable!() };
+        rows[1].name = "Pens ".repeat(30);
+        ReportData::Products { rows, columns }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn declared_column_widths_are_written_without_autofit() {
+        let dir = TempDir::new();
+        let path = dir.file("declared.xlsx");
+
+        write_workbook(Uuid::new_v4(), &path, products_with_a_long_name(), &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10)
+            .await
+            .unwrap();
+
+        assert_column_widths(&sheet_xml(&path, 1), &[12.0, 40.0, 20.0, 12.0, 15.0, 22.0]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn autofit_uses_the_longest_value_of_each_column_up_to_the_maximum() {
+        let dir = TempDir::new();
+        let path = dir.file("autofit.xlsx");
+        let style = ExcelStyleOptions { autofit: true, ..ExcelStyleOptions::default() };
+
+        write_workbook(Uuid::new_v4(), &path, products_with_a_long_name(), &style, None, FormulaEscape::Off, 10)
+            .await
+            .unwrap();
+
+        // Độ dài lớn nhất (header hoặc giá trị) + 2: "Product ID", tên dài bị giới hạn ở 60, "Stationery", "39.99",
+        // "Stock Quantity", và chuỗi định dạng ngày giờ "yyyy-mm-dd hh:mm:ss".
+        assert_column_widths(&sheet_xml(&path, 1), &[12.0, 60.0, 12.0, 7.0, 16.0, 21.0]);
+
+        let narrow = ExcelStyleOptions { autofit: true, max_column_width: 15, ..ExcelStyleOptions::default() };
+        write_workbook(Uuid::new_v4(), &path, products_with_a_long_name(), &narrow, None, FormulaEscape::Off, 10)
+            .await
+            .unwrap();
+        assert_column_widths(&sheet_xml(&path, 1), &[12.0, 15.0, 12.0, 7.0, 15.0, 15.0]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn autofit_measures_each_sheet_on_its_own_rows() {
+        let dir = TempDir::new();
+        let path = dir.file("sheets.xlsx");
+        let mut rows: Vec<Vec<CellValue>> =
+            (1..=15).map(|id| vec![CellValue::Number(id as f64), text(&format!("product {}", id))]).collect();
+        rows[0][1] = text(&"x".repeat(45));
+        let style = ExcelStyleOptions { autofit: true, ..ExcelStyleOptions::default() };
+
+        write_workbook(Uuid::new_v4(), &path, dataset(&["id", "name"], rows), &style, None, FormulaEscape::Off, 10)
+            .await
+            .unwrap();
+
+        assert_column_widths(&sheet_xml(&path, 1), &[4.0, 47.0]);
+        assert_column_widths(&sheet_xml(&path, 2), &[4.0, 12.0]);
+    }
+
+    /// Chuỗi định dạng số của ô `cell`: `formatCode` của numFmt riêng, hoặc chuỗi của định dạng dựng sẵn
+    /// mà rust_xlsxwriter dùng thay cho chuỗi trùng với nó (ví dụ `0`, `#,##0.00`).
+    #[cfg(feature = "xlsxwriter")]
+    fn cell_number_format(sheet_xml: &str, styles: &str, cell: &str) -> String {
+        let xf = cell_xf(sheet_xml, styles, cell);
+        let id = xml_attributes(&xf, "xf", "numFmtId").swap_remove(0);
+        let ids = xml_attributes(styles, "numFmt", "numFmtId");
+        let codes = xml_attributes(styles, "numFmt", "formatCode");
+        match ids.iter().position(|custom| *custom == id) {
+            Some(index) => codes[index].clone(),
+            None => match id.as_str() {
+                "0" => "General",
+                "1" => "0",
+                "2" => "0.00",
+                "3" => "#,##0",
+                "4" => "#,##0.00",
+                other => panic!("unexpected built-in number format {} of cell {}", other, cell),
+            }
+            .to_string(),
+        }
+    }
+
+    #[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+    #[test]
+    fn excel_serial_follows_the_1900_date_system() {
+        let serial = |value: &str| excel_serial(value.parse().unwrap());
+
+        assert_eq!(serial("1900-01-01T00:00:00"), Some(1.0));
+        // Excel có ngày 1900-02-29 (serial 60) không tồn tại.
+        assert_eq!(serial("1900-02-28T00:00:00"), Some(59.0));
+        assert_eq!(serial("1900-03-01T00:00:00"), Some(61.0));
+        assert_eq!(serial("1970-01-01T00:00:00"), Some(25_569.0));
+        let moon_landing = serial("1969-07-20T20:17:40").unwrap();
+        assert!((moon_landing - 25_404.845_601_851_852).abs() < 1e-9, "{}", moon_landing);
+        assert_eq!(serial("1899-12-31T23:59:59"), None);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn product_cells_have_numeric_types_and_the_layout_formats() {
+        use calamine::Data;
+        let dir = TempDir::new();
+        let path = dir.file("formats.xlsx");
+
+        write_workbook(Uuid::new_v4(), &path, golden_products(), &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10)
+            .await
+            .unwrap();
+
+        let range = read_data_sheet(&path);
+        let types: Vec<&str> = (0..6)
+            .map(|col| match range.get_value((1, col)).unwrap() {
+                Data::Float(_) | Data::Int(_) => "number",
+                Data::DateTime(_) => "datetime",
+                Data::String(_) => "string",
+                other => panic!("unexpected cell {:?}", other),
+            })
+            .collect();
+        assert_eq!(types, ["number", "string", "string", "number", "number", "datetime"]);
+
+        let sheet = sheet_xml(&path, 1);
+        let styles = xlsx_part(&path, "xl/styles.xml");
+        let formats: Vec<String> =
+            ["A2", "B2", "C2", "D2", "E2", "F2"].iter().map(|cell| cell_number_format(&sheet, &styles, cell)).collect();
+        assert_eq!(formats, ["0", "General", "General", "#,##0.00", "0", "yyyy-mm-dd hh:mm:ss"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn number_formats_come_from_the_style_options() {
+        let dir = TempDir::new();
+        let path = dir.file("custom-formats.xlsx");
+        let style = ExcelStyleOptions {
+            integer_format: "#,##0".to_string(),
+            decimal_format: "0.000".to_string(),
+            datetime_format: "dd/mm/yyyy hh:mm".to_string(),
+            ..ExcelStyleOptions::default()
+        };
+
+        write_workbook(Uuid::new_v4(), &path, golden_products(), &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let sheet = sheet_xml(&path, 1);
+        let styles = xlsx_part(&path, "xl/styles.xml");
+        let formats: Vec<String> = ["A4", "D4", "E4", "F4"].iter().map(|cell| cell_number_format(&sheet, &styles, cell)).collect();
+        assert_eq!(formats, ["#,##0", "0.000", "#,##0", "dd/mm/yyyy hh:mm"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn datetimes_before_1970_are_dates_and_before_1900_are_text() {
+        use calamine::Data;
+        let dir = TempDir::new();
+        let path = dir.file("old-dates.xlsx");
+        let at = |value: &str| CellValue::DateTime(value.parse::<chrono::DateTime<chrono::Utc>>().unwrap().with_timezone(&chrono_tz::UTC));
+        let data = dataset(&["at"], vec![vec![at("1969-07-20T20:17:40Z")], vec![at("1899-12-31T00:00:00Z")]]);
+
+        write_workbook(Uuid::new_v4(), &path, data, &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10).await.unwrap();
+
+        let range = read_data_sheet(&path);
+        let Some(Data::DateTime(moon_landing)) = range.get_value((1, 0)) else {
+            panic!("expected a date cell, got {:?}", range.get_value((1, 0)));
+        };
+        assert!((moon_landing.as_f64() - 25_404.845_601_851_852).abs() < 1e-9, "{}", moon_landing.as_f64());
+        match range.get_value((2, 0)) {
+            Some(Data::String(value)) => assert!(value.starts_with("1899-12-31 00:00:00"), "{}", value),
+            other => panic!("expected text, got {:?}", other),
+        }
+        let sheet = sheet_xml(&path, 1);
+        let styles = xlsx_part(&path, "xl/styles.xml");
+        assert_eq!(cell_number_format(&sheet, &styles, "A2"), "yyyy-mm-dd hh:mm:ss");
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn workbook_writer_renders_each_report_layout() {
+        use crate::models::ProductColumn;
+        use calamine::Data;
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let (exporter, style) = (local_exporter(), ExcelStyleOptions::default());
+        let string = |value: &str| Data::String(value.to_string());
+
+        let orders = exporter.export_to_excel(Uuid::new_v4(), golden_orders(), &style, None, None, &export_path).await.unwrap();
+        let products = selected_products(&[ProductColumn::Price, ProductColumn::Name]);
+        let products = exporter.export_to_excel(Uuid::new_v4(), products, &style, None, None, &export_path).await.unwrap();
+
+        let range = read_data_sheet(&orders.path);
+        assert_eq!(range.get_size(), (2, 8));
+        let headers: Vec<&Data> = (0..8).map(|col| range.get_value((0, col)).unwrap()).collect();
+        let expected = ["Order ID", "User ID", "Product Name", "Quantity", "Unit Price", "Total", "Ordered At", "Status"].map(string);
+        assert_eq!(headers, expected.iter().collect::<Vec<_>>());
+        let numbers: Vec<f64> = [0, 1, 3, 4, 5].iter().map(|col| number(range.get_value((1, *col)).unwrap())).collect();
+        assert_eq!(numbers, [501.0, 42.0, 3.0, 2.5, 7.5]);
+        assert_eq!(range.get_value((1, 2)), Some(&string("Notebook")));
+        assert!(matches!(range.get_value((1, 6)), Some(Data::DateTime(_))));
+        assert_eq!(range.get_value((1, 7)), Some(&string("paid")));
+        let (sheet, styles) = (sheet_xml(&orders.path, 1), xlsx_part(&orders.path, "xl/styles.xml"));
+        assert_eq!(cell_number_format(&sheet, &styles, "E2"), "#,##0.00");
+
+        let range = read_data_sheet(&products.path);
+        assert_eq!(range.get_size(), (4, 2));
+        assert_eq!((range.get_value((0, 0)), range.get_value((0, 1))), (Some(&string("Price")), Some(&string("Name"))));
+        assert_eq!(number(range.get_value((3, 0)).unwrap()), 39.99);
+        assert_eq!(range.get_value((3, 1)), Some(&string("The \"Rust\" Book")));
+        let (sheet, styles) = (sheet_xml(&products.path, 1), xlsx_part(&products.path, "xl/styles.xml"));
+        assert_eq!(cell_number_format(&sheet, &styles, "A2"), "#,##0.00");
+        assert_column_widths(&sheet, &[12.0, 40.0]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[test]
+    fn column_totals_sum_average_and_count_the_tracked_rows() {
+        use crate::models::ColumnAggregate;
+        let mut totals = ColumnTotals::new(vec![ColumnAggregate::Count, ColumnAggregate::Sum, ColumnAggregate::Avg, ColumnAggregate::None]);
+
+        totals.track(&[CellValue::Number(7.0), CellValue::Number(10.0), CellValue::Number(2.5), text("a")]);
+        totals.track(&[text("x"), CellValue::Number(-4.0), text(""), text("b")]);
+        totals.track(&[text(""), CellValue::Number(1.5), CellValue::Number(4.0), text("c")]);
+
+        // Count đếm ô không trống; Avg chỉ tính các ô số.
+        assert_eq!((0..4).map(|col| totals.value(col)).collect::<Vec<_>>(), [Some(2.0), Some(7.5), Some(3.25), None]);
+        assert_eq!(ColumnTotals::new(vec![ColumnAggregate::Avg]).value(0), None);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[test]
+    fn column_totals_formulas_cover_every_sheet() {
+        use crate::models::ColumnAggregate;
+        let totals = ColumnTotals::new(vec![ColumnAggregate::None, ColumnAggregate::Sum, ColumnAggregate::Avg, ColumnAggregate::Count]);
+
+        assert_eq!(totals.formula(0, 0, &[2, 1]), None);
+        assert_eq!(totals.formula(1, 0, &[2, 1]).unwrap(), "=SUBTOTAL(9,'Data'!B2:B3)+SUBTOTAL(9,'Data (2)'!B2:B2)");
+        assert_eq!(
+            totals.formula(2, 0, &[2, 1]).unwrap(),
+            "=(SUBTOTAL(9,'Data'!C2:C3)+SUBTOTAL(9,'Data (2)'!C2:C2))/(SUBTOTAL(2,'Data'!C2:C3)+SUBTOTAL(2,'Data (2)'!C2:C2))"
+        );
+        assert_eq!(totals.formula(2, 0, &[3]).unwrap(), "=SUBTOTAL(1,'Data'!C2:C4)");
+        assert_eq!(totals.formula(3, 2, &[3, 0]).unwrap(), "=SUBTOTAL(3,'Data'!D4:D6)");
+        assert_eq!(totals.formula(1, 0, &[0]), None);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn totals_row_follows_the_last_data_row_in_bold_with_a_top_border() {
+        use calamine::Data;
+        let dir = TempDir::new();
+        let path = dir.file("totals.xlsx");
+        let style = ExcelStyleOptions { totals_row: true, ..ExcelStyleOptions::default() };
+
+        write_workbook(Uuid::new_v4(), &path, golden_products(), &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let range = read_data_sheet(&path);
+        assert_eq!(range.get_size(), (5, 6));
+        // Count của Product ID, nhãn ở cột đầu tiên không có aggregate, trung bình giá, tổng tồn kho.
+        assert_eq!(number(range.get_value((4, 0)).unwrap()), 3.0);
+        assert_eq!(range.get_value((4, 1)), Some(&Data::String("Total".to_string())));
+        assert!((number(range.get_value((4, 3)).unwrap()) - 46.49 / 3.0).abs() < 1e-9);
+        assert_eq!(number(range.get_value((4, 4)).unwrap()), 127.0);
+        assert!(matches!(range.get_value((4, 2)), None | Some(Data::Empty)));
+        assert!(matches!(range.get_value((4, 5)), None | Some(Data::Empty)));
+
+        let sheet = sheet_xml(&path, 1);
+        let styles = xlsx_part(&path, "xl/styles.xml");
+        for cell in ["A5", "B5", "E5"] {
+            let xf = cell_xf(&sheet, &styles, cell);
+            assert!(xf_element(&styles, &xf, "fontId", "fonts", "font").contains("<b/>"), "{}", cell);
+            assert!(xf_element(&styles, &xf, "borderId", "borders", "border").contains(r#"<top style="thin""#), "{}", cell);
+        }
+        assert_eq!(cell_number_format(&sheet, &styles, "D5"), "#,##0.00");
+        assert_eq!(cell_number_format(&sheet, &styles, "E5"), "0");
+        // Vùng lọc không gồm dòng tổng.
+        assert_eq!(xml_attributes(&sheet, "autoFilter", "ref"), ["A1:F4"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn totals_row_of_a_split_report_is_on_the_last_sheet_and_counts_every_sheet() {
+        use calamine::Reader;
+        let dir = TempDir::new();
+        let path = dir.file("split-totals.xlsx");
+        let style = ExcelStyleOptions { totals_row: true, totals_formulas: true, ..ExcelStyleOptions::default() };
+
+        let (sheet_count, _) = write_workbook(Uuid::new_v4(), &path, golden_products(), &style, None, FormulaEscape::Off, 2)
+            .await
+            .unwrap();
+
+        assert_eq!(sheet_count, 2);
+        assert_eq!(read_sheet(&path, "Data").get_size(), (3, 6));
+        let last = read_sheet(&path, "Data (2)");
+        assert_eq!(last.get_size(), (3, 6));
+        assert_eq!(number(last.get_value((2, 0)).unwrap()), 3.0);
+        assert_eq!(number(last.get_value((2, 4)).unwrap()), 127.0);
+        let mut workbook: calamine::Xlsx<_> = calamine::open_workbook(&path).unwrap();
+        let formulas = workbook.worksheet_formula("Data (2)").unwrap();
+        assert_eq!(
+            formulas.get_value((2, 4)).map(String::as_str),
+            Some("SUBTOTAL(9,'Data'!E2:E3)+SUBTOTAL(9,'Data (2)'!E2:E2)")
+        );
+    }
+
+    /// Benchmark 500k dòng: `cargo test --release --features xlsxwriter -- --ignored --nocapture benchmark_500k_row_workbook`.
+    /// In thời gian ghi và bộ nhớ đỉnh (VmHWM) của tiến trình; chạy riêng để số đo không lẫn với test khác.
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark; run alone with --release --ignored --nocapture"]
+    async fn benchmark_500k_row_workbook() {
+        use crate::models::{ProductColumn, ProductData};
+        const ROWS: usize = 500_000;
+        let memory = |field: &str| {
+            std::fs::read_to_string("/proc/self/status")
+                .ok()
+                .and_then(|status| status.lines().find(|line| line.starts_with(field)).map(str::to_string))
+                .unwrap_or_else(|| format!("{} unavailable", field))
+        };
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let created_at = chrono::Utc::now();
+        let rows = (0..ROWS)
+            .map(|index| ProductData {
+                product_id: index as i64 + 1,
+                name: format!("Product {}", index + 1),
+                category: ["Books", "Games", "Music"][index % 3].to_string(),
+                price: 10.0 + (index % 1000) as f64 / 100.0,
+                stock_quantity: (index % 500) as i32,
+                created_at,
+            })
+            .collect();
+        let data = ReportData::Products { rows, columns: ProductColumn::ALL.to_vec() };
+        let rss_before = memory("VmRSS");
+
+        let started = std::time::Instant::now();
+        let exported = local_exporter()
+            .export_to_excel(Uuid::new_v4(), data, &ExcelStyleOptions::default(), None, None, &export_path)
+            .await
+            .unwrap();
+        let elapsed = started.elapsed();
+
+        println!("rust_xlsxwriter (constant memory), {} rows:", ROWS);
+        println!("  wall time: {:.2?}", elapsed);
+        println!("  file size: {} bytes", exported.bytes_written);
+        println!("  before export: {}", rss_before);
+        println!("  peak: {}", memory("VmHWM"));
+        assert_eq!(read_data_sheet(&exported.path).get_size(), (ROWS + 1, 6));
+    }
+
+    /// Metadata được lập giống nhau cho file xlsx thật và file placeholder (build không có `xlsxwriter`).
+    #[tokio::test]
+    async fn exported_workbook_reports_its_own_path_size_rows_and_sheets() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let request_id = Uuid::new_v4();
+
+        let exported = exporter_with_sheet_rows(10)
+            .export_to_excel(request_id, numbered_rows(25), &ExcelStyleOptions::default(), None, None, &export_path)
+            .await
+            .unwrap();
+
+        assert_eq!(exported.path, dir.file(&format!("{}.xlsx", request_id)));
+        assert_eq!(exported.file_name, format!("{}.xlsx", request_id));
+        assert_eq!(exported.content_type, ExportFormat::Xlsx.content_type());
+        assert_eq!(exported.bytes_written, std::fs::metadata(&exported.path).unwrap().len());
+        assert_eq!((exported.rows_written, exported.parts), (25, 3));
+        assert_eq!((exported.uncompressed_bytes, exported.checksum.as_deref()), (None, None));
+        assert_eq!(exported.adjusted_cells, CellAdjustments::default());
+        assert!(exported.next_parts.is_empty());
+        assert!(!exported.stored);
+        assert_eq!(exported.total_rows(), 25);
+        assert!(!std::path::Path::new(&partial_file_path(&exported.path)).exists());
+    }
+
+    #[cfg(not(feature = "xlsxwriter"))]
+    #[tokio::test]
+    async fn placeholder_workbook_holds_the_header_and_rows_as_text() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let request_id = Uuid::new_v4();
+
+        let exported = local_exporter()
+            .export_to_excel(request_id, numbered_rows(2), &ExcelStyleOptions::default(), None, None, &export_path)
+            .await
+            .unwrap();
+
+        let content = std::fs::read_to_string(&exported.path).unwrap();
+        assert_eq!(
+            content,
+            format!("Placeholder Excel content for request {}.\nid\tname\n1\tproduct 1\n2\tproduct 2\n", request_id)
+        );
+        assert_eq!((exported.rows_written, exported.parts), (2, 1));
+        assert_eq!(exported.bytes_written, content.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn exported_csv_reports_its_own_path_size_and_rows() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let request_id = Uuid::new_v4();
+
+        let exported = local_exporter()
+            .export_to_csv(request_id, numbered_rows(3), &CsvOptions::default(), OutputCompression::None, None, &export_path)
+            .await
+            .unwrap();
+
+        assert_eq!(exported.file_name, format!("{}.csv", request_id));
+        assert_eq!(exported.content_type, ExportFormat::Csv.content_type());
+        assert_eq!(std::fs::read_to_string(&exported.path).unwrap(), "id,name\n1,product 1\n2,product 2\n3,product 3\n");
+        assert_eq!(exported.bytes_written, std::fs::metadata(&exported.path).unwrap().len());
+        assert_eq!((exported.rows_written, exported.parts), (3, 1));
+        assert!(exported.next_parts.is_empty());
+    }
+
+    fn gunzip(path: &str) -> Vec<u8> {
+        use std::io::Read;
+        let mut content = Vec::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(path).unwrap()).read_to_end(&mut content).unwrap();
+        content
+    }
+
+    #[tokio::test]
+    async fn gzip_csv_holds_the_same_content_as_the_plain_csv() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let exporter = local_exporter();
+        let export = |compression| {
+            exporter.export_to_csv(Uuid::new_v4(), numbered_rows(50), &CsvOptions::default(), compression, None, &export_path)
+        };
+
+        let plain = export(OutputCompression::None).await.unwrap();
+        let gzip = export(OutputCompression::Gzip).await.unwrap();
+
+        let content = std::fs::read(&plain.path).unwrap();
+        assert!(gzip.file_name.ends_with(".csv.gz"), "{}", gzip.file_name);
+        assert_eq!(gzip.content_type, "application/gzip");
+        assert_eq!(gunzip(&gzip.path), content);
+        assert_eq!(gzip.uncompressed_bytes, Some(content.len() as u64));
+        assert_eq!(gzip.bytes_written, std::fs::metadata(&gzip.path).unwrap().len());
+        assert_eq!(gzip.rows_written, 50);
+        assert_eq!(plain.uncompressed_bytes, None);
+    }
+
+    #[tokio::test]
+    async fn gzip_jsonl_holds_the_same_content_as_the_plain_jsonl() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let exporter = local_exporter();
+        let export = |compression| exporter.export_to_jsonl(Uuid::new_v4(), numbered_rows(50), compression, None, &export_path);
+
+        let plain = export(OutputCompression::None).await.unwrap();
+        let gzip = export(OutputCompression::Gzip).await.unwrap();
+
+        let content = std::fs::read(&plain.path).unwrap();
+        assert!(gzip.file_name.ends_with(".jsonl.gz"), "{}", gzip.file_name);
+        assert_eq!(gzip.content_type, "application/gzip");
+        assert_eq!(gunzip(&gzip.path), content);
+        assert_eq!(gzip.uncompressed_bytes, Some(content.len() as u64));
+        assert_eq!(gzip.bytes_written, std::fs::metadata(&gzip.path).unwrap().len());
+        assert_eq!(gzip.rows_written, 50);
+        assert_eq!(plain.uncompressed_bytes, None);
+    }
+
+    fn export_metadata() -> ExportMetadata {
+        let parameters = serde_json::json!({
+            "start_date": "2024-01-01",
+            "min_price": 10.5,
+            "columns": ["name", "price"],
+            "include_totals": true,
+            "product_category": null,
+        });
+        ExportMetadata {
+            request_id: Uuid::parse_str("0f8e4c1a-2b3d-4e5f-8a9b-0c1d2e3f4a5b").unwrap(),
+            user_id: 42,
+            report_type: "products".to_string(),
+            parameters: parameters.as_object().unwrap().clone(),
+            generated_at: "2024-02-03T04:05:06Z".parse().unwrap(),
+            timezone: "Asia/Ho_Chi_Minh".to_string(),
+            row_count: 2,
+            service_version: "1.2.3".to_string(),
+            file_name: None,
+            sha256: None,
+            checksum_note: "recorded with the export request".to_string(),
+        }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn info_sheet_comes_first_with_typed_metadata_and_parameters() {
+        use calamine::Data;
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let metadata = export_metadata();
+
+        let exported = local_exporter()
+            .export_to_excel(Uuid::new_v4(), numbered_rows(2), &ExcelStyleOptions::default(), Some(&metadata), None, &export_path)
+            .await
+            .unwrap();
+
+        assert_eq!(read_sheet_names(&exported.path), ["Info", "Data"]);
+        assert_eq!(exported.parts, 1);
+        let info = read_sheet(&exported.path, "Info");
+        let value = |row: u32| info.get_value((row, 1)).cloned().unwrap_or(Data::Empty);
+        let labels: Vec<_> = (0..9).map(|row| info.get_value((row, 0)).unwrap().to_string()).collect();
+        assert_eq!(
+            labels,
+            ["Field", "Request ID", "User ID", "Report type", "Generated at (UTC)", "Time zone", "Rows", "Service version", "Checksum"]
+        );
+        assert_eq!(value(1), Data::String("0f8e4c1a-2b3d-4e5f-8a9b-0c1d2e3f4a5b".to_string()));
+        assert_eq!(number(&value(2)), 42.0);
+        assert_eq!(value(3), Data::String("products".to_string()));
+        assert!(matches!(value(4), Data::DateTime(_)), "{:?}", value(4));
+        assert!((number(&value(4)) - excel_serial("2024-02-03T04:05:06".parse().unwrap()).unwrap()).abs() < 1e-9);
+        assert_eq!(value(5), Data::String("Asia/Ho_Chi_Minh".to_string()));
+        assert_eq!(number(&value(6)), 2.0);
+        assert_eq!(value(7), Data::String("1.2.3".to_string()));
+        assert_eq!(value(8), Data::String("recorded with the export request".to_string()));
+
+        assert_eq!(info.get_value((10, 0)), Some(&Data::String("Parameter".to_string())));
+        let parameters: std::collections::HashMap<String, Data> =
+            (11..info.height() as u32).map(|row| (info.get_value((row, 0)).unwrap().to_string(), value(row))).collect();
+        assert_eq!(parameters.len(), 4, "null parameters are skipped: {:?}", parameters);
+        assert!(matches!(parameters["start_date"], Data::DateTime(_)), "{:?}", parameters["start_date"]);
+        assert_eq!(number(&parameters["start_date"]), excel_serial("2024-01-01T00:00:00".parse().unwrap()).unwrap());
+        assert_eq!(number(&parameters["min_price"]), 10.5);
+        assert_eq!(parameters["columns"], Data::String(r#"["name","price"]"#.to_string()));
+        assert_eq!(parameters["include_totals"], Data::Bool(true));
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn workbook_without_metadata_has_no_info_sheet() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+
+        let exported = local_exporter()
+            .export_to_excel(Uuid::new_v4(), numbered_rows(2), &ExcelStyleOptions::default(), None, None, &export_path)
+            .await
+            .unwrap();
+
+        assert_eq!(read_sheet_names(&exported.path), ["Data"]);
+    }
+
+    #[tokio::test]
+    async fn metadata_file_sits_next_to_the_export_with_the_documented_fields() {
+        let dir = TempDir::new();
+        let file_path = dir.file("report.csv");
+        let metadata = ExportMetadata {
+            file_name: Some("report.csv".to_string()),
+            sha256: Some("ab".repeat(32)),
+            ..export_metadata()
+        };
+
+        let metadata_path = local_exporter().write_metadata_file(&file_path, &metadata).await.unwrap();
+
+        assert_eq!(metadata_path, dir.file("report.csv.meta.json"));
+        assert!(!std::path::Path::new(&partial_file_path(&metadata_path)).exists());
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&metadata_path).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "request_id": "0f8e4c1a-2b3d-4e5f-8a9b-0c1d2e3f4a5b",
+                "user_id": 42,
+                "report_type": "products",
+                "parameters": {
+                    "start_date": "2024-01-01",
+                    "min_price": 10.5,
+                    "columns": ["name", "price"],
+                    "include_totals": true,
+                    "product_category": null,
+                },
+                "generated_at": "2024-02-03T04:05:06Z",
+                "timezone": "Asia/Ho_Chi_Minh",
+                "row_count": 2,
+                "service_version": "1.2.3",
+                "file_name": "report.csv",
+                "sha256": "ab".repeat(32),
+                "checksum_note": "recorded with the export request",
+            })
+        );
+    }
+
+    #[test]
+    fn metadata_without_a_file_leaves_out_its_name_and_checksum() {
+        let json = serde_json::to_value(export_metadata()).unwrap();
+
+        assert!(json.get("file_name").is_none(), "{}", json);
+        assert!(json.get("sha256").is_none(), "{}", json);
+    }
+
+    /// Công thức của các rule định dạng có điều kiện trong XML của worksheet, theo thứ tự.
+    #[cfg(feature = "xlsxwriter")]
+    fn conditional_format_formulas(sheet_xml: &str) -> Vec<String> {
+        let formula = regex::Regex::new(r"(?s)<cfRule\b[^>]*>\s*<formula>(.*?)</formula>").unwrap();
+        formula.captures_iter(sheet_xml).map(|captures| captures[1].to_string()).collect()
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn low_stock_rows_are_highlighted_over_the_data_rows_only() {
+        let dir = TempDir::new();
+        let path = dir.file("highlight.xlsx");
+        let style = ExcelStyleOptions { totals_row: true, ..ExcelStyleOptions::default() };
+
+        write_workbook(Uuid::new_v4(), &path, golden_products(), &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let sheet = sheet_xml(&path, 1);
+        // Cả dòng dữ liệu, không gồm header (dòng 1) và dòng tổng (dòng 5).
+        assert_eq!(xml_attributes(&sheet, "conditionalFormatting", "sqref"), ["A2:F4"]);
+        assert_eq!(xml_attributes(&sheet, "cfRule", "type"), ["expression"]);
+        assert_eq!(conditional_format_formulas(&sheet), ["AND(ISNUMBER($E2),$E2&lt;10)"]);
+        let styles = xlsx_part(&path, "xl/styles.xml");
+        let highlight = xml_elements(&styles, "dxfs", "dxf").swap_remove(0);
+        assert!(highlight.contains("FFC7CE") && highlight.contains("9C0006"), "{}", highlight);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn highlight_threshold_and_column_follow_the_request() {
+        use crate::models::{HighlightOptions, ProductColumn};
+        let dir = TempDir::new();
+        let path = dir.file("highlight.xlsx");
+        let style = ExcelStyleOptions { highlight: Some(HighlightOptions { stock_below: 50 }), ..ExcelStyleOptions::default() };
+        let data = selected_products(&[ProductColumn::Name, ProductColumn::StockQuantity]);
+
+        write_workbook(Uuid::new_v4(), &path, data, &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let sheet = sheet_xml(&path, 1);
+        assert_eq!(xml_attributes(&sheet, "conditionalFormatting", "sqref"), ["A2:B4"]);
+        assert_eq!(conditional_format_formulas(&sheet), ["AND(ISNUMBER($B2),$B2&lt;50)"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn split_report_highlights_the_data_rows_of_each_sheet() {
+        let dir = TempDir::new();
+        let path = dir.file("highlight.xlsx");
+
+        let (sheet_count, _) =
+            write_workbook(Uuid::new_v4(), &path, golden_products(), &ExcelStyleOptions::default(), None, FormulaEscape::Off, 2)
+                .await
+                .unwrap();
+
+        assert_eq!(sheet_count, 2);
+        let ranges: Vec<_> =
+            (1..=2).map(|index| xml_attributes(&sheet_xml(&path, index), "conditionalFormatting", "sqref")).collect();
+        assert_eq!(ranges, [vec!["A2:F3"], vec!["A2:F2"]]);
+        for index in 1..=2 {
+            assert_eq!(conditional_format_formulas(&sheet_xml(&path, index)), ["AND(ISNUMBER($E2),$E2&lt;10)"]);
+        }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn customer_lifetime_value_gets_a_three_color_scale() {
+        use crate::models::CustomerData;
+        let dir = TempDir::new();
+        let path = dir.file("customers.xlsx");
+        let customer = |customer_id, lifetime_value| CustomerData {
+            customer_id,
+            name: format!("Customer {}", customer_id),
+            email: format!("c{}@example.com", customer_id),
+            signup_date: "2024-01-01".parse().unwrap(),
+            total_orders: 1,
+            lifetime_value,
+        };
+        let data = ReportData::Customers(vec![customer(1, 10.0), customer(2, 250.0), customer(3, 90.0)]);
+
+        write_workbook(Uuid::new_v4(), &path, data, &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10).await.unwrap();
+
+        let sheet = sheet_xml(&path, 1);
+        assert_eq!(xml_attributes(&sheet, "conditionalFormatting", "sqref"), ["F2:F4"]);
+        assert_eq!(xml_attributes(&sheet, "cfRule", "type"), ["colorScale"]);
+        assert_eq!(xml_attributes(&sheet, "cfvo", "type"), ["min", "percentile", "max"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn empty_report_has_no_conditional_format() {
+        let dir = TempDir::new();
+        let path = dir.file("empty.xlsx");
+        let ReportData::Products { columns, .. } = golden_products() else { unreachable!() };
+        let data = ReportData::Products { rows: Vec::new(), columns };
+
+        write_workbook(Uuid::new_v4(), &path, data, &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10).await.unwrap();
+
+        assert!(!sheet_xml(&path, 1).contains("<conditionalFormatting"));
+    }
+
+    /// Report sản phẩm `rows` dòng, tên sản phẩm là link tới trang của sản phẩm.
+    fn linked_products(rows: usize) -> ReportData {
+        use crate::models::{ProductColumn, ProductData};
+        let rows = (1..=rows as i64)
+            .map(|product_id| ProductData {
+                product_id,
+                name: format!("Product {}", product_id),
+                category: "Books".to_string(),
+                price: 1.0,
+                stock_quantity: 100,
+                created_at: "2024-01-02T08:30:00Z".parse().unwrap(),
+            })
+            .collect();
+        ReportData::Products { rows, columns: ProductColumn::ALL.to_vec() }
+            .with_link_url_template("https://shop.example.com/p/{product_id}".to_string())
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn link_cells_are_hyperlinks_showing_the_value() {
+        use calamine::Data;
+        let dir = TempDir::new();
+        let path = dir.file("links.xlsx");
+
+        let (_, adjustments) =
+            write_workbook(Uuid::new_v4(), &path, linked_products(2), &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10)
+                .await
+                .unwrap();
+
+        let range = read_data_sheet(&path);
+        assert_eq!(range.get_value((1, 1)), Some(&Data::String("Product 1".to_string())));
+        assert_eq!(xml_attributes(&sheet_xml(&path, 1), "hyperlink", "ref"), ["B2", "B3"]);
+        let rels = xlsx_part(&path, "xl/worksheets/_rels/sheet1.xml.rels");
+        assert_eq!(
+            xml_attributes(&rels, "Relationship", "Target"),
+            ["https://shop.example.com/p/1", "https://shop.example.com/p/2"]
+        );
+        assert_eq!(adjustments.links_as_text, 0);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn links_over_the_sheet_limit_are_written_as_text() {
+        let dir = TempDir::new();
+        let path = dir.file("links.xlsx");
+        let rows = EXCEL_MAX_SHEET_HYPERLINKS as usize + 2;
+
+        // Sheet đầu có một dòng vượt giới hạn; sheet thứ hai có giới hạn riêng.
+        let (sheet_count, adjustments) = write_workbook(
+            Uuid::new_v4(),
+            &path,
+            linked_products(rows),
+            &ExcelStyleOptions::default(),
+            None,
+            FormulaEscape::Off,
+            rows as u32 - 1,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(sheet_count, 2);
+        assert_eq!(adjustments.links_as_text, 1);
+        let links = |index| sheet_xml(&path, index).matches("<hyperlink ").count();
+        assert_eq!(links(1), EXCEL_MAX_SHEET_HYPERLINKS as usize);
+        assert_eq!(links(2), 1);
+        let range = read_data_sheet(&path);
+        let last = range.get_value((rows as u32 - 1, 1)).unwrap().to_string();
+        assert_eq!(last, format!("Product {}", rows - 1));
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn links_with_a_too_long_url_are_written_as_text() {
+        let dir = TempDir::new();
+        let path = dir.file("links.xlsx");
+        let ReportData::Linked { data, .. } = linked_products(1) else { unreachable!() };
+        let long_url = format!("https://shop.example.com/{}/{{product_id}}", "a".repeat(EXCEL_MAX_URL_CHARS));
+        let data = ReportData::Linked { data, url_template: long_url };
+
+        let (_, adjustments) =
+            write_workbook(Uuid::new_v4(), &path, data, &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10)
+                .await
+                .unwrap();
+
+        assert_eq!(adjustments.links_as_text, 1);
+        assert!(!sheet_xml(&path, 1).contains("<hyperlink "));
+        let range = read_data_sheet(&path);
+        assert_eq!(range.get_value((1, 1)).unwrap().to_string(), "Product 1");
+    }
+
+    #[tokio::test]
+    async fn csv_link_cells_hold_the_url() {
+        let data = linked_products(2);
+
+        let csv = String::from_utf8(csv_bytes(&data, &CsvOptions::default()).await.unwrap()).unwrap();
+
+        let names: Vec<_> = csv.lines().skip(1).map(|line| line.split(',').nth(1).unwrap()).collect();
+        assert_eq!(names, ["https://shop.example.com/p/1", "https://shop.example.com/p/2"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[test]
+    fn table_column_names_are_distinct_and_never_empty() {
+        assert_eq!(table_column_names(&["Name", "", "name", "Name"]), ["Name", "Column2", "name (2)", "Name (3)"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[test]
+    fn table_names_come_from_the_sheet_name_and_are_unique() {
+        let mut names = TableNames::default();
+
+        assert_eq!(names.unique("Data"), "Table_Data");
+        assert_eq!(names.unique("Data (2)"), "Table_Data_2");
+        assert_eq!(names.unique("data"), "Table_data_3");
+        assert_eq!(names.unique("Đồ dùng / học tập"), "Table_Đồ_dùng_học_tập");
+        assert_eq!(names.unique("!!!"), "Table");
+        assert_eq!(names.unique("???"), "Table_2");
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn data_sheets_become_tables_instead_of_autofilters() {
+        let dir = TempDir::new();
+        let path = dir.file("tables.xlsx");
+        let style = ExcelStyleOptions {
+            table: true,
+            table_style: crate::models::ExcelTableStyle::Light(15),
+            ..ExcelStyleOptions::default()
+        };
+
+        let (sheet_count, _) =
+            write_workbook(Uuid::new_v4(), &path, golden_products(), &style, None, FormulaEscape::Off, 2).await.unwrap();
+
+        assert_eq!(sheet_count, 2);
+        let headers: Vec<String> =
+            read_data_sheet(&path).rows().next().unwrap().iter().map(|cell| cell.to_string()).collect();
+        for (index, name, range) in [(1, "Table_Data", "A1:F3"), (2, "Table_Data_2", "A1:F2")] {
+            let table = xlsx_part(&path, &format!("xl/tables/table{}.xml", index));
+            assert_eq!(xml_attributes(&table, "table", "name"), [name]);
+            assert_eq!(xml_attributes(&table, "table", "ref"), [range]);
+            assert_eq!(xml_attributes(&table, "tableColumn", "name"), headers);
+            assert_eq!(xml_attributes(&table, "tableStyleInfo", "name"), ["TableStyleLight15"]);
+            assert_eq!(xml_attributes(&table, "tableStyleInfo", "showRowStripes"), ["1"]);
+            assert!(!sheet_xml(&path, index).contains("<autoFilter"), "sheet {}", index);
+        }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn totals_row_of_a_single_sheet_is_the_table_total_row() {
+        let dir = TempDir::new();
+        let path = dir.file("tables.xlsx");
+        let style = ExcelStyleOptions { table: true, totals_row: true, ..ExcelStyleOptions::default() };
+
+        write_workbook(Uuid::new_v4(), &path, golden_products(), &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let table = xlsx_part(&path, "xl/tables/table1.xml");
+        assert_eq!(xml_attributes(&table, "table", "ref"), ["A1:F5"]);
+        assert_eq!(xml_attributes(&table, "table", "totalsRowCount"), ["1"]);
+        assert_eq!(xml_attributes(&table, "tableColumn", "totalsRowLabel"), ["Total"]);
+        // Product ID, Price, Stock Quantity theo `ColumnDef::aggregate` của layout sản phẩm.
+        assert_eq!(xml_attributes(&table, "tableColumn", "totalsRowFunction"), ["count", "average", "sum"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn table_headers_are_renamed_to_be_distinct() {
+        let dir = TempDir::new();
+        let path = dir.file("tables.xlsx");
+        let style = ExcelStyleOptions { table: true, ..ExcelStyleOptions::default() };
+        let data = dataset(&["a", "A", ""], vec![vec![text("1"), text("2"), text("3")]]);
+
+        write_workbook(Uuid::new_v4(), &path, data, &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let headers: Vec<String> =
+            read_data_sheet(&path).rows().next().unwrap().iter().map(|cell| cell.to_string()).collect();
+        assert_eq!(headers, ["a", "A (2)", "Column3"]);
+        let table = xlsx_part(&path, "xl/tables/table1.xml");
+        assert_eq!(xml_attributes(&table, "tableColumn", "name"), headers);
+    }
+
+    /// Nội dung của mọi phần tử `tag` trong `xml`, theo thứ tự.
+    #[cfg(feature = "xlsxwriter")]
+    fn xml_texts(xml: &str, tag: &str) -> Vec<String> {
+        let element = regex::Regex::new(&format!(r"(?s)<{0}\b[^>]*>(.*?)</{0}>", tag)).unwrap();
+        element.captures_iter(xml).map(|captures| captures[1].to_string()).collect()
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn print_setup_is_applied_to_every_data_sheet() {
+        use crate::models::{ExcelPrintOptions, PageMargins};
+        let dir = TempDir::new();
+        let path = dir.file("print.xlsx");
+        let request_id = Uuid::new_v4();
+        let print = ExcelPrintOptions {
+            landscape: true,
+            fit_to_width: true,
+            repeat_header: true,
+            margins: Some(PageMargins { left: 0.5, right: 0.6, top: 0.7, bottom: 0.8 }),
+            footer: true,
+        };
+        let style = ExcelStyleOptions { print, ..ExcelStyleOptions::default() };
+
+        let (sheet_count, _) =
+            write_workbook(request_id, &path, golden_products(), &style, None, FormulaEscape::Off, 2).await.unwrap();
+
+        assert_eq!(sheet_count, 2);
+        for index in 1..=2 {
+            let sheet = sheet_xml(&path, index);
+            assert_eq!(xml_attributes(&sheet, "pageSetup", "orientation"), ["landscape"], "sheet {}", index);
+            assert_eq!(xml_attributes(&sheet, "pageSetup", "fitToHeight"), ["0"], "sheet {}", index);
+            assert_eq!(xml_attributes(&sheet, "pageSetUpPr", "fitToPage"), ["1"], "sheet {}", index);
+            let margins: Vec<_> = ["left", "right", "top", "bottom", "header", "footer"]
+                .iter()
+                .map(|side| xml_attributes(&sheet, "pageMargins", side).concat())
+                .collect();
+            assert_eq!(margins, ["0.5", "0.6", "0.7", "0.8", "0.3", "0.3"], "sheet {}", index);
+            assert_eq!(
+                xml_texts(&sheet, "oddFooter"),
+                [format!("&amp;LRequest {}&amp;RPage &amp;P of &amp;N", request_id)],
+                "sheet {}",
+                index
+            );
+        }
+        let workbook = xlsx_part(&path, "xl/workbook.xml");
+        assert_eq!(xml_attributes(&workbook, "definedName", "name"), ["_xlnm.Print_Titles", "_xlnm.Print_Titles"]);
+        assert_eq!(xml_texts(&workbook, "definedName"), ["Data!$1:$1", "'Data (2)'!$1:$1"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn default_print_setup_keeps_the_excel_defaults() {
+        let dir = TempDir::new();
+        let path = dir.file("print.xlsx");
+
+        write_workbook(Uuid::new_v4(), &path, golden_products(), &ExcelStyleOptions::default(), None, FormulaEscape::Off, 10)
+            .await
+            .unwrap();
+
+        let sheet = sheet_xml(&path, 1);
+        assert!(!sheet.contains(r#"orientation="landscape""#));
+        assert!(xml_attributes(&sheet, "pageSetUpPr", "fitToPage").is_empty());
+        assert!(!sheet.contains("<headerFooter"));
+        assert_eq!(xml_attributes(&sheet, "pageMargins", "left"), ["0.7"]);
+        assert!(!xlsx_part(&path, "xl/workbook.xml").contains("_xlnm.Print_Titles"));
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    fn report_title() -> crate::models::ReportTitle {
+        crate::models::ReportTitle {
+            title: "Product Export — 2024-01-01 to 2024-01-31".to_string(),
+            subtitle: "Generated 2024-02-01 08:00 UTC".to_string(),
+        }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn title_block_moves_the_header_and_every_range_below_it() {
+        use crate::models::ExcelPrintOptions;
+        use calamine::{Data, Reader};
+        let dir = TempDir::new();
+        let path = dir.file("title.xlsx");
+        let style = ExcelStyleOptions {
+            title: Some(report_title()),
+            totals_row: true,
+            totals_formulas: true,
+            print: ExcelPrintOptions { repeat_header: true, ..ExcelPrintOptions::default() },
+            ..ExcelStyleOptions::default()
+        };
+
+        write_workbook(Uuid::new_v4(), &path, golden_products(), &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        let range = read_data_sheet(&path);
+        assert_eq!(range.get_value((0, 0)), Some(&Data::String(report_title().title)));
+        assert_eq!(range.get_value((1, 0)), Some(&Data::String(report_title().subtitle)));
+        assert_eq!(range.get_value((2, 1)), Some(&Data::String("Name".to_string())));
+        assert_eq!(range.get_value((3, 1)), Some(&Data::String("Notebook".to_string())));
+        assert_eq!(range.get_value((6, 1)), Some(&Data::String("Total".to_string())));
+
+        let sheet = sheet_xml(&path, 1);
+        assert_eq!(xml_attributes(&sheet, "mergeCell", "ref"), ["A1:F1", "A2:F2"]);
+        assert_eq!(xml_attributes(&sheet, "pane", "ySplit"), ["3"]);
+        assert_eq!(xml_attributes(&sheet, "pane", "topLeftCell"), ["A4"]);
+        assert_eq!(xml_attributes(&sheet, "autoFilter", "ref"), ["A3:F6"]);
+        assert_eq!(xml_attributes(&sheet, "conditionalFormatting", "sqref"), ["A4:F6"]);
+        assert_eq!(conditional_format_formulas(&sheet), ["AND(ISNUMBER($E4),$E4&lt;10)"]);
+        let mut workbook: calamine::Xlsx<_> = calamine::open_workbook(&path).unwrap();
+        let formulas = workbook.worksheet_formula("Data").unwrap();
+        assert_eq!(formulas.get_value((6, 4)).map(String::as_str), Some("SUBTOTAL(9,'Data'!E4:E6)"));
+        assert_eq!(xml_texts(&xlsx_part(&path, "xl/workbook.xml"), "definedName"), ["Data!$3:$3"]);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn title_block_moves_the_table_of_every_sheet() {
+        let dir = TempDir::new();
+        let path = dir.file("title.xlsx");
+        let style = ExcelStyleOptions { title: Some(report_title()), table: true, ..ExcelStyleOptions::default() };
+
+        write_workbook(Uuid::new_v4(), &path, golden_products(), &style, None, FormulaEscape::Off, 2).await.unwrap();
+
+        assert_eq!(xml_attributes(&xlsx_part(&path, "xl/tables/table1.xml"), "table", "ref"), ["A3:F5"]);
+        assert_eq!(xml_attributes(&xlsx_part(&path, "xl/tables/table2.xml"), "table", "ref"), ["A3:F4"]);
+        for index in 1..=2 {
+            assert_eq!(xml_attributes(&sheet_xml(&path, index), "mergeCell", "ref"), ["A1:F1", "A2:F2"], "sheet {}", index);
+        }
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn title_block_of_a_single_column_report_is_not_merged() {
+        use calamine::Data;
+        let dir = TempDir::new();
+        let path = dir.file("title.xlsx");
+        let style = ExcelStyleOptions { title: Some(report_title()), ..ExcelStyleOptions::default() };
+        let data = dataset(&["name"], vec![vec![text("Tea")]]);
+
+        write_workbook(Uuid::new_v4(), &path, data, &style, None, FormulaEscape::Off, 10).await.unwrap();
+
+        assert!(!sheet_xml(&path, 1).contains("<mergeCell"));
+        let range = read_data_sheet(&path);
+        assert_eq!(range.get_value((0, 0)), Some(&Data::String(report_title().title)));
+        assert_eq!(range.get_value((3, 0)), Some(&Data::String("Tea".to_string())));
+    }
+
+    #[test]
+    fn part_paths_put_the_part_number_before_the_extensions() {
+        assert_eq!(part_file_path("/exports/abc.csv.gz", 1), "/exports/abc.csv.gz");
+        assert_eq!(part_file_path("/exports/abc.csv.gz", 2), "/exports/abc.part2.csv.gz");
+        assert_eq!(part_file_path("/exports/abc.xlsx", 12), "/exports/abc.part12.xlsx");
+        assert_eq!(part_file_path("/exports/abc", 3), "/exports/abc.part3");
+    }
+
+    #[tokio::test]
+    async fn csv_over_the_size_cap_is_split_into_parts_with_their_own_header() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let request_id = Uuid::new_v4();
+
+        let exported = local_exporter()
+            .export_to_csv(request_id, numbered_rows(30), &CsvOptions::default(), OutputCompression::None, Some(100), &export_path)
+            .await
+            .unwrap();
+
+        let paths = exported.part_paths();
+        assert!(paths.len() >= 5, "{:?}", paths);
+        for (index, path) in paths.iter().enumerate() {
+            assert_eq!(*path, part_file_path(&dir.file(&format!("{}.csv", request_id)), index as u32 + 1));
+        }
+        let mut ids = Vec::new();
+        for part in std::iter::once(&exported).chain(&exported.next_parts) {
+            let csv = std::fs::read_to_string(&part.path).unwrap();
+            assert!(csv.len() <= 100, "{} has {} bytes", part.path, csv.len());
+            assert_eq!(part.bytes_written, csv.len() as u64);
+            let mut lines = csv.lines();
+            assert_eq!(lines.next(), Some("id,name"));
+            let part_ids: Vec<u32> = lines.map(|line| line.split(',').next().unwrap().parse().unwrap()).collect();
+            assert_eq!(part.rows_written, part_ids.len() as u64);
+            ids.extend(part_ids);
+        }
+        assert_eq!(ids, (1..=30).collect::<Vec<u32>>());
+        assert_eq!(exported.total_rows(), 30);
+        assert_eq!(walk_partial_files(&dir), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn jsonl_over_the_size_cap_is_split_into_parts() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+
+        let exported = local_exporter()
+            .export_to_jsonl(Uuid::new_v4(), numbered_rows(30), OutputCompression::None, Some(150), &export_path)
+            .await
+            .unwrap();
+
+        assert!(!exported.next_parts.is_empty());
+        let mut ids = Vec::new();
+        for part in std::iter::once(&exported).chain(&exported.next_parts) {
+            let jsonl = std::fs::read_to_string(&part.path).unwrap();
+            assert!(jsonl.len() <= 150, "{} has {} bytes", part.path, jsonl.len());
+            for line in jsonl.lines() {
+                let row: serde_json::Value = serde_json::from_str(line).unwrap();
+                ids.push(row["id"].as_f64().unwrap() as u32);
+            }
+        }
+        assert_eq!(ids, (1..=30).collect::<Vec<u32>>());
+        assert_eq!(exported.total_rows(), 30);
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test]
+    async fn workbook_over_the_size_cap_is_split_into_smaller_workbooks() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let max_bytes = 16 * 1024;
+
+        let exported = local_exporter()
+            .export_to_excel(Uuid::new_v4(), numbered_rows(5_000), &ExcelStyleOptions::default(), None, Some(max_bytes), &export_path)
+            .await
+            .unwrap();
+
+        assert!(exported.next_parts.len() >= 2, "{} parts", exported.next_parts.len() + 1);
+        let mut ids = Vec::new();
+        for part in std::iter::once(&exported).chain(&exported.next_parts) {
+            assert!(std::fs::metadata(&part.path).unwrap().len() <= max_bytes, "{}", part.path);
+            let part_ids = ids_per_sheet(&part.path, 1).concat();
+            assert_eq!(part.rows_written, part_ids.len() as u64);
+            ids.extend(part_ids);
+        }
+        assert_eq!(ids, (1..=5_000).collect::<Vec<u32>>());
+        assert_eq!(walk_partial_files(&dir), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn row_larger_than_the_size_cap_is_reported_with_its_number() {
+        let dir = TempDir::new();
+        let export_path = dir.0.to_string_lossy().into_owned();
+        let data = dataset(&["name"], vec![vec![text("short")], vec![text(&"x".repeat(200))]]);
+
+        let error = local_exporter()
+            .export_to_csv(Uuid::new_v4(), data, &CsvOptions::default(), OutputCompression::None, Some(100), &export_path)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.downcast_ref::<FileSizeLimitError>(), Some(&FileSizeLimitError { row: 2, max_bytes: 100 }));
+    }
+
+    #[test]
+    fn content_file_names_start_with_the_short_hash_then_count_up() {
+        let sha256 = "0123456789abcdef".repeat(4);
+
+        let names: Vec<String> = content_file_names(&sha256, "csv.gz").take(3).collect();
+
+        assert_eq!(names, vec!["0123456789abcdef.csv.gz", "0123456789abcdef-2.csv.gz", "0123456789abcdef-3.csv.gz"]);
+        assert_eq!(content_file_names(&sha256, "").next().unwrap(), "0123456789abcdef");
+    }
+
+    #[tokio::test]
+    async fn identical_exports_renamed_by_content_share_one_file() {
+        let dir = TempDir::new();
+        let exporter = local_exporter();
+        std::fs::write(dir.file("first.csv"), "id\n1\n").unwrap();
+        std::fs::write(dir.file("second.csv"), "id\n1\n").unwrap();
+
+        let (first, first_reused) = exporter.rename_by_content(Uuid::new_v4(), &dir.file("first.csv")).await.unwrap();
+        let (second, second_reused) = exporter.rename_by_content(Uuid::new_v4(), &dir.file("second.csv")).await.unwrap();
+
+        let sha256 = exporter.file_checksum(&first).await.unwrap().unwrap().sha256;
+        assert_eq!(first, dir.file(&format!("{}.csv", &sha256[..16])));
+        assert!(!first_reused);
+        assert_eq!(second, first);
+        assert!(second_reused);
+        assert!(!Path::new(&dir.file("first.csv")).exists());
+        assert!(!Path::new(&dir.file("second.csv")).exists());
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "id\n1\n");
+    }
+
+    #[tokio::test]
+    async fn content_name_held_by_other_content_moves_to_the_next_name() {
+        let dir = TempDir::new();
+        let exporter = local_exporter();
+        std::fs::write(dir.file("export.csv"), "id\n1\n").unwrap();
+        let sha256 = exporter.file_checksum(&dir.file("export.csv")).await.unwrap().unwrap().sha256;
+        std::fs::write(dir.file(&format!("{}.csv", &sha256[..16])), "something else").unwrap();
+
+        let (renamed, reused) = exporter.rename_by_content(Uuid::new_v4(), &dir.file("export.csv")).await.unwrap();
+
+        assert_eq!(renamed, dir.file(&format!("{}-2.csv", &sha256[..16])));
+        assert!(!reused);
+        assert_eq!(std::fs::read_to_string(&renamed).unwrap(), "id\n1\n");
+        assert_eq!(std::fs::read_to_string(dir.file(&format!("{}.csv", &sha256[..16]))).unwrap(), "something else");
+    }
+
+    #[tokio::test]
+    async fn file_already_named_by_its_content_keeps_its_name() {
+        let dir = TempDir::new();
+        let exporter = local_exporter();
+        std::fs::write(dir.file("export.csv"), "id\n1\n").unwrap();
+        let (renamed, _) = exporter.rename_by_content(Uuid::new_v4(), &dir.file("export.csv")).await.unwrap();
+
+        let (again, reused) = exporter.rename_by_content(Uuid::new_v4(), &renamed).await.unwrap();
+
+        assert_eq!(again, renamed);
+        assert!(!reused);
+        assert!(Path::new(&renamed).exists());
+    }
+
+    /// Các file `.partial` còn lại trong thư mục export.
+    fn walk_partial_files(dir: &TempDir) -> Vec<String> {
+        std::fs::read_dir(&dir.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".partial"))
+            .collect()
+    }
+
+    fn local_exporter() -> LocalFileExporter {
+        exporter_with_sheet_rows(1_048_575)
+    }
+
+    fn exporter_with_sheet_rows(max_rows_per_sheet: u32) -> LocalFileExporter {
+        LocalFileExporter::new(
+            FormulaEscape::Off,
+            max_rows_per_sheet,
+            ParquetOptions { compression: ParquetCompression::None, row_group_size: 1024 },
+            100,
+            PdfOptions { font_dir: String::new(), font_family: String::new() },
+        )
+    }
+
+    #[test]
+    fn filename_template_renders_placeholders() {
+        let request_id = Uuid::parse_str("0f8e4c1a-2b3d-4e5f-8a9b-0c1d2e3f4a5b").unwrap();
+        let values = [
+            ("report_type", "products".to_string()),
+            ("start_date", "2024-05-01".to_string()),
+            ("end_date", "2024-05-31".to_string()),
+            ("user_id", "42".to_string()),
+            ("request_id", request_id.to_string()),
+        ];
+
+        assert_eq!(
+            render_file_stem("{report_type}_{start_date}_{end_date}_u{user_id}.{ext}", request_id, &values),
+            "products_2024-05-01_2024-05-31_u42"
+        );
+        assert_eq!(render_file_stem("{request_id}.{ext}", request_id, &values), request_id.to_string());
+    }
+
+    #[test]
+    fn rendered_file_stem_is_sanitized() {
+        let request_id = Uuid::new_v4();
+        let render = |value: &str| render_file_stem("{report_type}.{ext}", request_id, &[("report_type", value.to_string())]);
+
+        assert_eq!(render("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(render("Báo cáo tháng 5"), "B_o_c_o_th_ng_5");
+        assert_eq!(render(".hidden"), "hidden");
+        assert_eq!(render("..."), request_id.to_string());
+        assert_eq!(render(""), request_id.to_string());
+        assert_eq!(render(&"a".repeat(500)).len(), MAX_FILE_STEM_BYTES);
+    }
+
+    #[test]
+    fn invalid_filename_templates_are_rejected() {
+        assert!(validate_filename_template("{report_type}_{date}.{ext}").is_ok());
+
+        for template in [
+            "{report_type}.xlsx",
+            "reports/{report_type}.{ext}",
+            "..\\{report_type}.{ext}",
+            "{report_type}\t.{ext}",
+            "{tenant}.{ext}",
+            "{report_type.{ext}",
+            "report_type}.{ext}",
+        ] {
+            assert!(validate_filename_template(template).is_err(), "{}", template);
+        }
+        let error = validate_filename_template("{tenant}.{ext}").unwrap_err();
+        assert!(error.to_string().contains("unknown placeholder '{tenant}'"));
+    }
+
+    #[test]
+    fn file_name_candidates_add_a_short_request_id_then_a_counter() {
+        let request_id = Uuid::parse_str("0f8e4c1a-2b3d-4e5f-8a9b-0c1d2e3f4a5b").unwrap();
+        let names: Vec<String> = file_name_candidates(request_id, "products", "csv.gz").take(3).collect();
+
+        assert_eq!(names, ["products.csv.gz", "products-0f8e4c1a.csv.gz", "products-0f8e4c1a-2.csv.gz"]);
+        assert_eq!(file_name_candidates(request_id, "products", "csv.gz").count(), MAX_FILE_NAME_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn rename_never_overwrites_an_existing_file() {
+        let dir = TempDir::new();
+        let exporter = local_exporter();
+        std::fs::write(dir.file("products.csv"), b"someone else's export").unwrap();
+        let request_id = Uuid::parse_str("0f8e4c1a-2b3d-4e5f-8a9b-0c1d2e3f4a5b").unwrap();
+        let generated = dir.file(&format!("{}.csv", request_id));
+        std::fs::write(&generated, b"our export").unwrap();
+
+        let renamed = exporter.rename_file(request_id, &generated, "products").await.unwrap();
+
+        assert_eq!(renamed, dir.file("products-0f8e4c1a.csv"));
+        assert_eq!(std::fs::read(&renamed).unwrap(), b"our export");
+        assert_eq!(std::fs::read(dir.file("products.csv")).unwrap(), b"someone else's export");
+        assert!(!Path::new(&generated).exists());
+    }
+
+    #[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+    #[test]
+    fn overlong_text_is_cut_to_the_excel_limit_with_the_marker() {
+        let mut adjustments = CellAdjustments::default();
+        let long = "é".repeat(40_000);
+
+        let CellValue::Text(fitted) = fit_excel_cell(text(&long), "…", &mut adjustments) else { panic!("expected text") };
+
+        assert_eq!(fitted.chars().count(), EXCEL_MAX_CELL_CHARS);
+        assert!(fitted.ends_with("é…"));
+        assert_eq!(adjustments, CellAdjustments { truncated: 1, ..CellAdjustments::default() });
+
+        let exact = "a".repeat(EXCEL_MAX_CELL_CHARS);
+        assert_eq!(fit_excel_cell(text(&exact), "…", &mut adjustments), text(&exact));
+        assert_eq!(adjustments.truncated, 1);
+    }
+
+    #[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+    #[test]
+    fn control_characters_are_replaced_but_tabs_and_line_breaks_are_kept() {
+        let mut adjustments = CellAdjustments::default();
+
+        assert_eq!(fit_excel_cell(text("a\0b\u{1B}c"), "…", &mut adjustments), text("a\u{FFFD}b\u{FFFD}c"));
+        assert_eq!(fit_excel_cell(text("a\tb\r\nc"), "…", &mut adjustments), text("a\tb\r\nc"));
+        assert_eq!(adjustments, CellAdjustments { invalid_chars: 1, ..CellAdjustments::default() });
+    }
+
+    #[cfg(any(feature = "xlsxwriter", feature = "templates"))]
+    #[test]
+    fn infinite_and_nan_numbers_fit_in_a_cell() {
+        let mut adjustments = CellAdjustments::default();
+
+        assert_eq!(fit_excel_cell(CellValue::Number(f64::INFINITY), "…", &mut adjustments), CellValue::Number(EXCEL_MAX_NUMBER));
+        assert_eq!(fit_excel_cell(CellValue::Number(f64::NEG_INFINITY), "…", &mut adjustments), CellValue::Number(-EXCEL_MAX_NUMBER));
+        assert_eq!(fit_excel_cell(CellValue::Number(f64::MAX), "…", &mut adjustments), CellValue::Number(EXCEL_MAX_NUMBER));
+        assert_eq!(fit_excel_cell(CellValue::Number(f64::NAN), "…", &mut adjustments), text(""));
+        assert_eq!(fit_excel_cell(CellValue::Number(-12.5), "…", &mut adjustments), CellValue::Number(-12.5));
+        assert_eq!(adjustments, CellAdjustments { clamped_numbers: 4, ..CellAdjustments::default() });
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[test]
+    fn sheet_names_drop_characters_excel_forbids() {
+        assert_eq!(sanitize_sheet_name("Books/Comics"), "Books_Comics");
+        assert_eq!(sanitize_sheet_name("[Q1] 2024: *draft*?"), "_Q1_ 2024_ _draft__");
+        assert_eq!(sanitize_sheet_name("a\\b\tc"), "a_b_c");
+        assert_eq!(sanitize_sheet_name("  'Quoted'  "), "Quoted");
+        assert_eq!(sanitize_sheet_name(""), "(blank)");
+        assert_eq!(sanitize_sheet_name("  ''  "), "(blank)");
+        assert_eq!(sanitize_sheet_name("history"), "History_");
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[test]
+    fn long_sheet_names_are_cut_to_31_characters() {
+        assert_eq!(sanitize_sheet_name(&"z".repeat(40)), "z".repeat(EXCEL_MAX_SHEET_NAME_CHARS));
+        // Cắt theo ký tự, không theo byte; khoảng trắng còn lại ở cuối sau khi cắt cũng bị bỏ.
+        assert_eq!(sanitize_sheet_name(&"Đồ gia dụng ".repeat(5)), "Đồ gia dụng Đồ gia dụng Đồ gia");
+        assert_eq!(sanitize_sheet_name(&format!("{} tail", "x".repeat(30))), "x".repeat(30));
+    }
+
+    #[cfg(feature = "xlsxwriter")]
+    #[test]
+    fn duplicate_sheet_names_get_a_counter_ignoring_case() {
+        let mut names = SheetNames::default();
+        let long = "y".repeat(EXCEL_MAX_SHEET_NAME_CHARS);
+
+        assert_eq!(names.unique("Books"), "Books");
+        assert_eq!(names.unique("books"), "books (2)");
+        assert_eq!(names.unique("BOOKS"), "BOOKS (3)");
+        assert_eq!(names.unique(&long), long);
+        let second = names.unique(&long);
+        assert_eq!(second, format!("{} (2)", "y".repeat(EXCEL_MAX_SHEET_NAME_CHARS - 4)));
+        assert_eq!(second.chars().count(), EXCEL_MAX_SHEET_NAME_CHARS);
+    }
+
+    /// Runtime một thread: nếu việc ghi workbook chiếm worker, timer bên cạnh không được chạy đúng hạn.
+    #[cfg(feature = "xlsxwriter")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn large_workbook_does_not_starve_other_tasks() {
+        const ROWS: usize = 200_000;
+        const TICK: std::time::Duration = std::time::Duration::from_millis(10);
+        let dir = TempDir::new();
+        let path = dir.file("large.xlsx");
+        let rows = (0..ROWS)
+            .map(|row| vec![CellValue::Number(row as f64), text(&format!("product {}", row)), CellValue::Number(row as f64 * 1.5)])
+            .collect();
+        let data = dataset(&["id", "name", "price"], rows);
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let timer = tokio::spawn({
+            let done = done.clone();
+            async move {
+                let mut worst = std::time::Duration::ZERO;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let started = std::time::Instant::now();
+                    tokio::time::sleep(TICK).await;
+                    worst = worst.max(started.elapsed().saturating_sub(TICK));
+                }
+                worst
+            }
+        });
+
+        let (sheets, _) =
+            write_workbook(Uuid::new_v4(), &path, data, &ExcelStyleOptions::default(), None, FormulaEscape::Off, EXCEL_MAX_SHEET_ROWS - 1)
+                .await
+                .unwrap();
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        let worst = timer.await.unwrap();
+
+        assert_eq!(sheets, 1);
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        assert!(worst < std::time::Duration::from_millis(200), "timer was delayed by {:?}", worst);
+    }
+}
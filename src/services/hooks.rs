@@ -0,0 +1,285 @@
+use anyhow::{Context, Result};
+use metrics::increment;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::models::{ExportRequest, ReportData, ReportParams};
+
+/// Kết quả của bước tạo file, truyền cho `after_export`.
+#[derive(Debug, Clone)]
+pub struct ExportResult {
+    pub file_path: String,
+    pub row_count: usize,
+    /// Số worksheet của file Excel; `None` với file CSV.
+    pub sheet_count: Option<u32>,
+}
+
+/// Bước tùy biến chạy quanh việc tạo file (audit, hậu xử lý file...), không cần sửa ExportService.
+/// `before_export` lỗi sẽ làm request thất bại; lỗi của `after_export` là fatal hay chỉ ghi log
+/// tùy theo config EXPORT_HOOK_AFTER_FATAL.
+#[async_trait::async_trait]
+pub trait ExportHook: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    /// Chạy ngay trước khi query dữ liệu và tạo file.
+    async fn before_export(&self, _request: &ExportRequest, _params: &ReportParams) -> Result<()> {
+        Ok(())
+    }
+
+    /// Chạy sau khi query, trước khi ghi file; hook được phép sửa dữ liệu (ví dụ che dữ liệu cá nhân).
+    async fn transform_data(
+        &self,
+        _request: &ExportRequest,
+        _params: &ReportParams,
+        _data: &mut ReportData,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Chạy sau khi file đã được tạo, trước khi tính checksum (hook được phép sửa file).
+    async fn after_export(&self, _request: &ExportRequest, _result: &ExportResult) -> Result<()> {
+        Ok(())
+    }
+    /// Mật khẩu mã hóa file khi payload có `protection` nhưng không kèm `password` (ví dụ mật khẩu riêng của user).
+    /// Hook đầu tiên trả về `Some` được dùng; mật khẩu không được ghi log hay lưu lại.
+    async fn export_password(&self, _request: &ExportRequest, _params: &ReportParams) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// Hook không làm gì.
+pub struct NoopHook;
+
+#[async_trait::async_trait]
+impl ExportHook for NoopHook {
+    fn name(&self) -> &'static str {
+        "noop"
+    }
+}
+
+/// Hook ghi log trước và sau khi tạo file.
+pub struct LoggingHook;
+
+#[async_trait::async_trait]
+impl ExportHook for LoggingHook {
+    fn name(&self) -> &'static str {
+        "logging"
+    }
+
+    async fn before_export(&self, request: &ExportRequest, params: &ReportParams) -> Result<()> {
+        info!(
+            "🪝 Starting export {} for user {} (report type '{}').",
+            request.id, request.user_id, params.report_type
+        );
+        Ok(())
+    }
+
+    async fn after_export(&self, request: &ExportRequest, result: &ExportResult) -> Result<()> {
+        info!(
+            "🪝 Export {} generated {} with {} row(s).",
+            request.id, result.file_path, result.row_count
+        );
+        Ok(())
+    }
+}
+
+/// Tên của hook che dữ liệu cá nhân; request có `anonymize` chỉ được xử lý khi hook này được bật.
+pub const PII_MASKING_HOOK: &str = "pii_masking";
+
+/// Hook che dữ liệu cá nhân (email của khách hàng) khi payload yêu cầu `anonymize`.
+pub struct PiiMaskingHook;
+
+#[async_trait::async_trait]
+impl ExportHook for PiiMaskingHook {
+    fn name(&self) -> &'static str {
+        PII_MASKING_HOOK
+    }
+
+    async fn transform_data(
+        &self,
+        _request: &ExportRequest,
+        params: &ReportParams,
+        data: &mut ReportData,
+    ) -> Result<()> {
+        if !params.anonymize {
+            return Ok(());
+        }
+        if let ReportData::Customers(rows) = data {
+            for row in rows.iter_mut() {
+                row.email = mask_email(&row.email);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `john.doe@example.com` -> `j***@example.com`; chuỗi không phải email bị che toàn bộ.
+fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) if !local.is_empty() => {
+            let first: String = local.chars().take(1).collect();
+            format!("{}***@{}", first, domain)
+        }
+        _ => "***".to_string(),
+    }
+}
+
+/// Chạy `before_export` của các hook theo thứ tự đăng ký. Hook đầu tiên lỗi dừng chuỗi (các hook sau không chạy)
+/// và làm request thất bại.
+pub async fn run_before_export(
+    hooks: &[Arc<dyn ExportHook>],
+    request: &ExportRequest,
+    params: &ReportParams,
+) -> Result<()> {
+    for hook in hooks {
+        hook.before_export(request, params)
+            .await
+            .with_context(|| format!("before_export hook '{}' failed", hook.name()))?;
+    }
+    Ok(())
+}
+
+/// Chạy `after_export` của các hook theo thứ tự đăng ký. Với `fatal` (EXPORT_HOOK_AFTER_FATAL) hook đầu tiên lỗi
+/// dừng chuỗi và làm request thất bại; nếu không, lỗi chỉ được ghi log và các hook sau vẫn chạy.
+pub async fn run_after_export(
+    hooks: &[Arc<dyn ExportHook>],
+    request: &ExportRequest,
+    result: &ExportResult,
+    fatal: bool,
+) -> Result<()> {
+    for hook in hooks {
+        if let Err(e) = hook.after_export(request, result).await {
+            let e = e.context(format!("after_export hook '{}' failed", hook.name()));
+            if fatal {
+                return Err(e);
+            }
+            warn!("Ignoring failed hook for request {}: {:?}", request.id, e);
+            increment!("excel_export_hook_failed_total", "hook" => hook.name());
+        }
+    }
+    Ok(())
+}
+
+/// Tạo danh sách hook theo thứ tự cấu hình trong EXPORT_HOOKS.
+pub fn build_hooks(names: &[String]) -> Result<Vec<Arc<dyn ExportHook>>> {
+    names
+        .iter()
+        .map(|name| -> Result<Arc<dyn ExportHook>> {
+            match name.as_str() {
+                "noop" => Ok(Arc::new(NoopHook)),
+                "logging" => Ok(Arc::new(LoggingHook)),
+                PII_MASKING_HOOK => Ok(Arc::new(PiiMaskingHook)),
+                other => anyhow::bail!("Unknown export hook '{}' in EXPORT_HOOKS", other),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Hook ghi lại thứ tự được gọi vào `calls`; `fail_before`/`fail_after` làm bước tương ứng lỗi.
+    struct RecordingHook {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+        fail_before: bool,
+        fail_after: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl ExportHook for RecordingHook {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn before_export(&self, _request: &ExportRequest, _params: &ReportParams) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("before:{}", self.name));
+            anyhow::ensure!(!self.fail_before, "{} refused the export", self.name);
+            Ok(())
+        }
+
+        async fn after_export(&self, _request: &ExportRequest, _result: &ExportResult) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("after:{}", self.name));
+            anyhow::ensure!(!self.fail_after, "{} could not post-process the file", self.name);
+            Ok(())
+        }
+    }
+
+    fn hooks(calls: &Arc<Mutex<Vec<String>>>, specs: &[(&'static str, bool, bool)]) -> Vec<Arc<dyn ExportHook>> {
+        specs
+            .iter()
+            .map(|&(name, fail_before, fail_after)| -> Arc<dyn ExportHook> {
+                Arc::new(RecordingHook { name, calls: calls.clone(), fail_before, fail_after })
+            })
+            .collect()
+    }
+
+    fn fixtures() -> (ExportRequest, ReportParams, ExportResult) {
+        let payload = serde_json::json!({"start_date": "2024-05-01", "end_date": "2024-05-31"});
+        let params = serde_json::from_value(payload.clone()).unwrap();
+        let result = ExportResult { file_path: "/exports/report.xlsx".to_string(), row_count: 3, sheet_count: Some(1) };
+        (ExportRequest::for_test(payload), params, result)
+    }
+
+    #[tokio::test]
+    async fn hooks_run_in_registration_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let hooks = hooks(&calls, &[("audit", false, false), ("footer", false, false), ("archive", false, false)]);
+        let (request, params, result) = fixtures();
+
+        run_before_export(&hooks, &request, &params).await.unwrap();
+        run_after_export(&hooks, &request, &result, true).await.unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["before:audit", "before:footer", "before:archive", "after:audit", "after:footer", "after:archive"]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_before_export_stops_the_chain() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let hooks = hooks(&calls, &[("audit", false, false), ("footer", true, false), ("archive", false, false)]);
+        let (request, params, _) = fixtures();
+
+        let error = run_before_export(&hooks, &request, &params).await.unwrap_err();
+
+        assert_eq!(error.to_string(), "before_export hook 'footer' failed");
+        assert_eq!(*calls.lock().unwrap(), ["before:audit", "before:footer"]);
+    }
+
+    #[tokio::test]
+    async fn fatal_after_export_error_stops_the_chain() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let hooks = hooks(&calls, &[("audit", false, true), ("footer", false, false)]);
+        let (request, _, result) = fixtures();
+
+        let error = run_after_export(&hooks, &request, &result, true).await.unwrap_err();
+
+        assert_eq!(error.to_string(), "after_export hook 'audit' failed");
+        assert_eq!(*calls.lock().unwrap(), ["after:audit"]);
+    }
+
+    #[tokio::test]
+    async fn non_fatal_after_export_error_is_logged_and_later_hooks_run() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let hooks = hooks(&calls, &[("audit", false, true), ("footer", false, false)]);
+        let (request, _, result) = fixtures();
+
+        run_after_export(&hooks, &request, &result, false).await.unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), ["after:audit", "after:footer"]);
+    }
+
+    #[test]
+    fn build_hooks_keeps_configured_order_and_rejects_unknown_names() {
+        let names = ["logging", "noop", PII_MASKING_HOOK].map(String::from);
+        let hooks = build_hooks(&names).unwrap();
+        assert_eq!(hooks.iter().map(|hook| hook.name()).collect::<Vec<_>>(), ["logging", "noop", PII_MASKING_HOOK]);
+
+        let error = build_hooks(&["audit".to_string()]).err().unwrap();
+        assert_eq!(error.to_string(), "Unknown export hook 'audit' in EXPORT_HOOKS");
+    }
+}
@@ -0,0 +1,149 @@
+use crate::models::{CellValue, ColumnFormat, ExcelStyleOptions, ReportData};
+
+const TABLE_STYLE: &str = "border-collapse:collapse;font-family:Arial,Helvetica,sans-serif;font-size:13px;color:#222222;";
+const CELL_STYLE: &str = "padding:4px 8px;border:1px solid #D9D9D9;";
+/// Nền của các dòng chẵn (dòng kẻ sọc).
+const STRIPE_BACKGROUND: &str = "#F2F2F2";
+
+/// Render dữ liệu report thành một file HTML độc lập (CSS inline, không tải tài nguyên ngoài),
+/// dùng được làm file export (`format: "html"`) hoặc nhúng vào email làm bản xem trước.
+/// Chỉ `max_rows` dòng đầu tiên được render; khi bị cắt, footer ghi "Showing first N of M rows".
+#[derive(Debug, Clone)]
+pub struct HtmlExporter {
+    max_rows: usize,
+    header_font_color: String,
+    header_background_color: String,
+}
+
+impl HtmlExporter {
+    /// Màu header lấy theo định dạng header của file Excel.
+    pub fn new(max_rows: usize, style: &ExcelStyleOptions) -> Self {
+        Self {
+            max_rows,
+            header_font_color: style.header_font_color.clone(),
+            header_background_color: style.header_background_color.clone(),
+        }
+    }
+
+    pub fn render(&self, title: &str, data: &ReportData) -> String {
+        let layout = data.layout();
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str(&format!("<title>{}</title>\n</head>\n<body style=\"margin:0;padding:16px;\">\n", escape_html(title)));
+        html.push_str(&format!("<table style=\"{}\">\n<thead>\n<tr>", TABLE_STYLE));
+        for column in &layout.columns {
+            html.push_str(&format!(
+                "<th style=\"{}text-align:left;font-weight:bold;color:{};background-color:{};\">{}</th>",
+                CELL_STYLE,
+                self.header_font_color,
+                self.header_background_color,
+                escape_html(&column.header)
+            ));
+        }
+        html.push_str("</tr>\n</thead>\n<tbody>\n");
+
+        for (index, row) in data.rows().take(self.max_rows).enumerate() {
+            if index % 2 == 1 {
+                html.push_str(&format!("<tr style=\"background-color:{};\">", STRIPE_BACKGROUND));
+            } else {
+                html.push_str("<tr>");
+            }
+            for (value, column) in row.iter().zip(&layout.columns) {
+                let align = if matches!(value, CellValue::Number(_)) { "right" } else { "left" };
+                let content = match value {
+                    CellValue::Link { text, url } => format!("<a href=\"{}\">{}</a>", escape_html(url), escape_html(text)),
+                    value => escape_html(&cell_text(value, column.format)),
+                };
+                html.push_str(&format!("<td style=\"{}text-align:{};\">{}</td>", CELL_STYLE, align, content));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</tbody>\n</table>\n");
+
+        if let Some(footer) = truncation_footer(self.max_rows, data.len()) {
+            html.push_str(&format!(
+                "<p style=\"font-family:Arial,Helvetica,sans-serif;font-size:12px;color:#666666;\">{}</p>\n",
+                footer
+            ));
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+/// Footer khi dữ liệu bị cắt: `None` nếu mọi dòng đều được render.
+fn truncation_footer(max_rows: usize, total_rows: usize) -> Option<String> {
+    (total_rows > max_rows).then(|| format!("Showing first {} of {} rows.", max_rows, total_rows))
+}
+
+/// Giá trị hiển thị của ô: cột số thập phân giữ hai chữ số, ngày giờ không kèm múi giờ (luôn là UTC).
+fn cell_text(value: &CellValue, format: ColumnFormat) -> String {
+    match (value, format) {
+        (CellValue::Number(number), ColumnFormat::Decimal) => format!("{:.2}", number),
+        (CellValue::DateTime(value), _) => value.format("%Y-%m-%d %H:%M:%S").to_string(),
+        (value, _) => value.to_string(),
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DatasetRows;
+
+    fn dataset(columns: &[&str], rows: Vec<Vec<CellValue>>) -> ReportData {
+        ReportData::Dataset(DatasetRows { columns: columns.iter().map(|column| column.to_string()).collect(), rows })
+    }
+
+    fn text(value: &str) -> CellValue {
+        CellValue::Text(value.to_string())
+    }
+
+    #[test]
+    fn markup_in_values_headers_and_title_is_escaped() {
+        let data = dataset(
+            &["<b>name</b>"],
+            vec![
+                vec![text("<script>alert('x')</script>")],
+                vec![text("Fish & \"Chips\"")],
+                vec![CellValue::Link { text: "<i>docs</i>".to_string(), url: "https://example.com/?a=1&b=\"2\"".to_string() }],
+            ],
+        );
+
+        let html = HtmlExporter::new(10, &ExcelStyleOptions::default()).render("Q1 <draft> & 'final'", &data);
+
+        assert!(html.contains("<title>Q1 &lt;draft&gt; &amp; &#39;final&#39;</title>"));
+        assert!(html.contains(">&lt;b&gt;name&lt;/b&gt;</th>"));
+        assert!(html.contains(">&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;</td>"));
+        assert!(html.contains(">Fish &amp; &quot;Chips&quot;</td>"));
+        assert!(html.contains("<a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\">&lt;i&gt;docs&lt;/i&gt;</a>"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn footer_appears_only_when_rows_are_cut() {
+        let rows = |count: usize| (0..count).map(|index| vec![CellValue::Number(index as f64)]).collect();
+        let exporter = HtmlExporter::new(2, &ExcelStyleOptions::default());
+
+        let cut = exporter.render("report", &dataset(&["n"], rows(3)));
+        assert_eq!(cut.matches("<tr").count(), 3);
+        assert!(cut.contains("Showing first 2 of 3 rows."));
+
+        let whole = exporter.render("report", &dataset(&["n"], rows(2)));
+        assert!(!whole.contains("Showing first"));
+    }
+}
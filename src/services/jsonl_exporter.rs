@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{BufWriter, Write};
+use tracing::info;
+
+use crate::models::{OutputCompression, ReportData};
+use crate::services::file_exporter::FileSizeLimitError;
+
+/// Số dòng giữa hai lần flush, để dữ liệu ra đĩa dần thay vì dồn hết vào cuối.
+const JSONL_FLUSH_ROWS: usize = 10_000;
+
+/// Ghi dữ liệu report dạng JSON Lines: mỗi dòng một JSON object, chỉ gồm các cột được chọn.
+/// Xuống dòng trong chuỗi được escape (`\n`), nên mỗi dòng của file luôn là một dòng dữ liệu.
+#[derive(Debug, Clone, Copy)]
+pub struct JsonLinesExporter {
+    compression: OutputCompression,
+}
+
+impl JsonLinesExporter {
+    pub fn new(compression: OutputCompression) -> Self {
+        Self { compression }
+    }
+
+    /// Phần mở rộng của file: `jsonl`, hoặc `jsonl.gz` khi nén gzip.
+    pub fn extension(&self) -> &'static str {
+        match self.compression {
+            OutputCompression::None => "jsonl",
+            OutputCompression::Gzip => "jsonl.gz",
+        }
+    }
+
+    /// Ghi `data` vào `path`, từng dòng theo thứ tự, flush sau mỗi `JSONL_FLUSH_ROWS` dòng.
+    /// Trả về số dòng đã ghi và số byte JSON Lines trước khi nén.
+    pub fn write(&self, path: &str, data: &ReportData) -> Result<(usize, u64)> {
+        let mut file = JsonLinesFile::create(path, self.compression)?;
+        let (rows, bytes) = write_rows(&mut file, data)?;
+        file.finish()?;
+        info!("✅ JSON Lines file successfully created at: {} ({} row(s))", path, rows);
+        Ok((rows, bytes))
+    }
+
+    /// Như `write`, nhưng bắt đầu file mới (`part_path(2)`, `part_path(3)`...) trước dòng làm file hiện tại
+    /// vượt `max_bytes`; với gzip, giới hạn được so với số byte trước khi nén. Trả về số dòng và số byte
+    /// trước khi nén của từng phần. Một dòng lớn hơn `max_bytes` trả về lỗi chứa `FileSizeLimitError`.
+    pub fn write_parts(&self, part_path: impl Fn(u32) -> String, data: &ReportData, max_bytes: u64) -> Result<Vec<(usize, u64)>> {
+        let mut parts = Vec::new();
+        let mut file = JsonLinesFile::create(&part_path(1), self.compression)?;
+        let (mut rows, mut bytes) = (0, 0);
+        let mut line = Vec::new();
+        for (index, row) in data.json_rows().enumerate() {
+            encode_row(&mut line, row)?;
+            let line_bytes = line.len() as u64;
+            if rows > 0 && bytes + line_bytes > max_bytes {
+                file.finish()?;
+                parts.push((rows, bytes));
+                file = JsonLinesFile::create(&part_path(parts.len() as u32 + 1), self.compression)?;
+                (rows, bytes) = (0, 0);
+            }
+            if line_bytes > max_bytes {
+                return Err(FileSizeLimitError { row: index + 1, max_bytes }.into());
+            }
+            file.write_all(&line).context("Failed to write JSON Lines row")?;
+            bytes += line_bytes;
+            rows += 1;
+            if rows % JSONL_FLUSH_ROWS == 0 {
+                file.flush().context("Failed to flush JSON Lines file")?;
+            }
+        }
+        file.finish()?;
+        parts.push((rows, bytes));
+        info!("✅ JSON Lines file successfully created in {} part(s) of at most {} bytes", parts.len(), max_bytes);
+        Ok(parts)
+    }
+}
+
+/// File JSON Lines đang ghi, qua luồng gzip khi bật nén.
+enum JsonLinesFile {
+    Plain(BufWriter<std::fs::File>),
+    Gzip(GzEncoder<BufWriter<std::fs::File>>),
+}
+
+impl JsonLinesFile {
+    fn create(path: &str, compression: OutputCompression) -> Result<Self> {
+        let file = BufWriter::new(std::fs::File::create(path).context("Failed to create JSON Lines file")?);
+        Ok(match compression {
+            OutputCompression::None => JsonLinesFile::Plain(file),
+            OutputCompression::Gzip => JsonLinesFile::Gzip(GzEncoder::new(file, Compression::default())),
+        })
+    }
+
+    /// Ghi phần cuối của luồng gzip, flush và fsync file.
+    fn finish(self) -> Result<()> {
+        let file = match self {
+            JsonLinesFile::Plain(file) => file,
+            JsonLinesFile::Gzip(encoder) => encoder.finish().context("Failed to finish gzip stream")?,
+        };
+        let file = file.into_inner().map_err(|e| e.into_error()).context("Failed to flush JSON Lines file")?;
+        file.sync_all().context("Failed to sync JSON Lines file")
+    }
+}
+
+impl Write for JsonLinesFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            JsonLinesFile::Plain(file) => file.write(buf),
+            JsonLinesFile::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            JsonLinesFile::Plain(file) => file.flush(),
+            JsonLinesFile::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Thay `line` bằng dòng JSON của `row`, kèm ký tự xuống dòng.
+fn encode_row(line: &mut Vec<u8>, row: serde_json::Result<serde_json::Map<String, serde_json::Value>>) -> Result<()> {
+    let row = row.context("Failed to serialize row to JSON")?;
+    line.clear();
+    serde_json::to_writer(&mut *line, &row).context("Failed to serialize row to JSON")?;
+    line.push(b'\n');
+    Ok(())
+}
+
+fn write_rows(writer: &mut impl Write, data: &ReportData) -> Result<(usize, u64)> {
+    let mut rows = 0;
+    let mut bytes = 0;
+    let mut line = Vec::new();
+    for row in data.json_rows() {
+        encode_row(&mut line, row)?;
+        writer.write_all(&line).context("Failed to write JSON Lines row")?;
+        bytes += line.len() as u64;
+        rows += 1;
+        if rows % JSONL_FLUSH_ROWS == 0 {
+            writer.flush().context("Failed to flush JSON Lines file")?;
+        }
+    }
+    writer.flush().context("Failed to flush JSON Lines file")?;
+    Ok((rows, bytes))
+}
@@ -0,0 +1,578 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use metrics::histogram;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::io::AsyncWrite;
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::config::{DatasetParam, ExportQuota};
+use crate::models::{
+    CustomerData, CustomerReportParams, DatasetRows, DatasetValue, ExportCompletion, ExportRequest, ExportRequestEvent,
+    ExportStatus, ExportTimings, OrderData, OrderReportParams, ProductData, ReportParams, SalesSummaryRow, UnsentNotification,
+};
+use crate::services::db_store::{DbResult, DbStore};
+
+/// DbStore bọc một store khác và đo từng thao tác: `excel_export_db_operation_duration_seconds`
+/// (label `operation`, `outcome`) và số dòng trả về `excel_export_db_rows_returned` (label `operation`).
+/// Đặt bên trong `RetryingDbStore` nên mỗi lần thử lại được đo riêng; mọi caller của DbStore
+/// (service, worker nền) đều có metric mà không phải tự đo.
+pub struct MeteredDbStore<D> {
+    inner: D,
+    clock: Arc<dyn Clock>,
+}
+
+impl<D: DbStore> MeteredDbStore<D> {
+    pub fn new(inner: D, clock: Arc<dyn Clock>) -> Self {
+        Self { inner, clock }
+    }
+
+    async fn measure<T>(&self, operation: &'static str, run: impl Future<Output = DbResult<T>>) -> DbResult<T> {
+        let started_at = self.clock.now_instant();
+        let result = run.await;
+        histogram!(
+            "excel_export_db_operation_duration_seconds",
+            self.clock.elapsed(started_at).as_secs_f64(),
+            "operation" => operation,
+            "outcome" => if result.is_ok() { "ok" } else { "error" }
+        );
+        result
+    }
+
+    async fn measure_rows<T>(
+        &self,
+        operation: &'static str,
+        run: impl Future<Output = DbResult<Vec<T>>>,
+    ) -> DbResult<Vec<T>> {
+        let result = self.measure(operation, run).await;
+        if let Ok(rows) = &result {
+            record_rows(operation, rows.len() as u64);
+        }
+        result
+    }
+
+    /// Thời gian tính đến khi stream kết thúc; stream bị bỏ dở giữa chừng không được ghi nhận.
+    fn measure_stream<'a, T: Send + 'a>(
+        &'a self,
+        operation: &'static str,
+        mut rows: BoxStream<'a, Result<T>>,
+    ) -> BoxStream<'a, Result<T>> {
+        Box::pin(async_stream::stream! {
+            let started_at = self.clock.now_instant();
+            let mut streamed: u64 = 0;
+            let mut failed = false;
+            while let Some(row) = rows.next().await {
+                match &row {
+                    Ok(_) => streamed += 1,
+                    Err(_) => failed = true,
+                }
+                yield row;
+            }
+            histogram!(
+                "excel_export_db_operation_duration_seconds",
+                self.clock.elapsed(started_at).as_secs_f64(),
+                "operation" => operation,
+                "outcome" => if failed { "error" } else { "ok" }
+            );
+            if !failed {
+                record_rows(operation, streamed);
+            }
+        })
+    }
+}
+
+fn record_rows(operation: &'static str, rows: u64) {
+    histogram!("excel_export_db_rows_returned", rows as f64, "operation" => operation);
+}
+
+#[async_trait::async_trait]
+impl<D: DbStore> DbStore for MeteredDbStore<D> {
+    async fn ping(&self) -> DbResult<()> {
+        self.measure("ping", self.inner.ping()).await
+    }
+
+    async fn fetch_and_update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        quota: &ExportQuota,
+    ) -> DbResult<ExportRequest> {
+        self.measure(
+            "fetch_and_update_request_status",
+            self.inner.fetch_and_update_request_status(request_id, new_status, quota),
+        )
+        .await
+    }
+
+    async fn try_lock_request(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        self.measure("try_lock_request", self.inner.try_lock_request(request_id)).await
+    }
+
+    async fn unlock_request(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.measure("unlock_request", self.inner.unlock_request(request_id)).await
+    }
+
+    async fn update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        file_path: Option<String>,
+        error_message: Option<String>,
+        error_code: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+        timings: &ExportTimings,
+        completion: &ExportCompletion,
+    ) -> DbResult<()> {
+        self.measure(
+            "update_request_status",
+            self.inner.update_request_status(
+                request_id,
+                new_status,
+                file_path,
+                error_message,
+                error_code,
+                expires_at,
+                timings,
+                completion,
+            ),
+        )
+        .await
+    }
+
+    async fn update_notification_sent_status(
+        &self,
+        request_id: Uuid,
+        sent: bool,
+    ) -> DbResult<()> {
+        self.measure("update_notification_sent_status", self.inner.update_notification_sent_status(request_id, sent))
+            .await
+    }
+
+    async fn mark_webhook_sent(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.measure("mark_webhook_sent", self.inner.mark_webhook_sent(request_id)).await
+    }
+
+    async fn query_product_data(
+        &self,
+        params: &ReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<ProductData>> {
+        self.measure_rows("query_product_data", self.inner.query_product_data(params, max_rows)).await
+    }
+
+    fn stream_product_data<'a>(
+        &'a self,
+        params: &'a ReportParams,
+    ) -> BoxStream<'a, Result<ProductData>> {
+        self.measure_stream("stream_product_data", self.inner.stream_product_data(params))
+    }
+
+    async fn count_product_data(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<i64> {
+        self.measure("count_product_data", self.inner.count_product_data(params)).await
+    }
+
+    fn supports_csv_copy(&self) -> bool {
+        self.inner.supports_csv_copy()
+    }
+
+    async fn copy_product_data_csv(
+        &self,
+        params: &ReportParams,
+        max_rows: usize,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> DbResult<u64> {
+        let result = self
+            .measure("copy_product_data_csv", self.inner.copy_product_data_csv(params, max_rows, writer))
+            .await;
+        if let Ok(rows) = &result {
+            record_rows("copy_product_data_csv", *rows);
+        }
+        result
+    }
+
+    async fn prepare_dataset(
+        &self,
+        sql: &str,
+        params: &[DatasetParam],
+    ) -> DbResult<Vec<String>> {
+        self.measure("prepare_dataset", self.inner.prepare_dataset(sql, params)).await
+    }
+
+    async fn query_dataset(
+        &self,
+        sql: &str,
+        values: &[DatasetValue],
+        tenant: Option<&str>,
+        max_rows: usize,
+    ) -> DbResult<DatasetRows> {
+        let result = self.measure("query_dataset", self.inner.query_dataset(sql, values, tenant, max_rows)).await;
+        if let Ok(data) = &result {
+            record_rows("query_dataset", data.rows.len() as u64);
+        }
+        result
+    }
+
+    async fn query_order_data(
+        &self,
+        params: &OrderReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<OrderData>> {
+        self.measure_rows("query_order_data", self.inner.query_order_data(params, max_rows)).await
+    }
+
+    async fn query_customer_data(
+        &self,
+        params: &CustomerReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<CustomerData>> {
+        self.measure_rows("query_customer_data", self.inner.query_customer_data(params, max_rows)).await
+    }
+
+    async fn query_category_summary(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<Vec<SalesSummaryRow>> {
+        self.measure_rows("query_category_summary", self.inner.query_category_summary(params)).await
+    }
+
+    fn stream_order_data<'a>(
+        &'a self,
+        params: &'a OrderReportParams,
+    ) -> BoxStream<'a, Result<OrderData>> {
+        self.measure_stream("stream_order_data", self.inner.stream_order_data(params))
+    }
+
+    async fn claim_unsent_notifications(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.measure_rows("claim_unsent_notifications", self.inner.claim_unsent_notifications(limit)).await
+    }
+
+    async fn list_unsent_notifications(
+        &self,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> DbResult<Vec<UnsentNotification>> {
+        self.measure_rows("list_unsent_notifications", self.inner.list_unsent_notifications(limit, after)).await
+    }
+
+    async fn record_notification_failure(
+        &self,
+        request_id: Uuid,
+        next_retry_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        self.measure("record_notification_failure", self.inner.record_notification_failure(request_id, next_retry_at))
+            .await
+    }
+
+    async fn list_expired(
+        &self,
+        before: DateTime<Utc>,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.measure_rows("list_expired", self.inner.list_expired(before)).await
+    }
+
+    async fn is_file_shared(
+        &self,
+        file_path: &str,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        self.measure("is_file_shared", self.inner.is_file_shared(file_path, request_id)).await
+    }
+
+    async fn mark_expired(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.measure("mark_expired", self.inner.mark_expired(request_id)).await
+    }
+
+    async fn record_generated_file(
+        &self,
+        request_id: Uuid,
+        file_path: &str,
+        file_checksum: &str,
+        file_size_bytes: i64,
+    ) -> DbResult<()> {
+        self.measure(
+            "record_generated_file",
+            self.inner.record_generated_file(request_id, file_path, file_checksum, file_size_bytes),
+        )
+        .await
+    }
+
+    async fn record_params_hash(
+        &self,
+        request_id: Uuid,
+        params_hash: &str,
+    ) -> DbResult<()> {
+        self.measure("record_params_hash", self.inner.record_params_hash(request_id, params_hash)).await
+    }
+
+    async fn find_recent_by_params_hash(
+        &self,
+        params_hash: &str,
+        since: DateTime<Utc>,
+        exclude_request_id: Uuid,
+    ) -> DbResult<Option<ExportRequest>> {
+        self.measure(
+            "find_recent_by_params_hash",
+            self.inner.find_recent_by_params_hash(params_hash, since, exclude_request_id),
+        )
+        .await
+    }
+
+    async fn record_estimated_completion(
+        &self,
+        request_id: Uuid,
+        estimated_completion_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        self.measure(
+            "record_estimated_completion",
+            self.inner.record_estimated_completion(request_id, estimated_completion_at),
+        )
+        .await
+    }
+
+    async fn record_export_duration(
+        &self,
+        request_id: Uuid,
+        duration_ms: i64,
+        row_count: Option<i64>,
+    ) -> DbResult<()> {
+        self.measure("record_export_duration", self.inner.record_export_duration(request_id, duration_ms, row_count))
+            .await
+    }
+
+    async fn record_notify_duration(
+        &self,
+        request_id: Uuid,
+        notify_ms: i64,
+    ) -> DbResult<()> {
+        self.measure("record_notify_duration", self.inner.record_notify_duration(request_id, notify_ms)).await
+    }
+
+    async fn recent_export_durations(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<(i64, i64)>> {
+        self.measure_rows("recent_export_durations", self.inner.recent_export_durations(limit)).await
+    }
+
+    async fn claim_next_pending(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.measure_rows("claim_next_pending", self.inner.claim_next_pending(limit)).await
+    }
+
+    async fn fetch_stuck_processing(
+        &self,
+        stuck_for: chrono::Duration,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.measure_rows("fetch_stuck_processing", self.inner.fetch_stuck_processing(stuck_for, limit)).await
+    }
+
+    async fn reset_to_pending(
+        &self,
+        ids: &[Uuid],
+    ) -> DbResult<Vec<Uuid>> {
+        self.measure_rows("reset_to_pending", self.inner.reset_to_pending(ids)).await
+    }
+
+    async fn bulk_update_status(
+        &self,
+        ids: &[Uuid],
+        new_status: ExportStatus,
+        error_message: Option<String>,
+        force: bool,
+    ) -> DbResult<u64> {
+        self.measure("bulk_update_status", self.inner.bulk_update_status(ids, new_status, error_message, force)).await
+    }
+
+    async fn record_attempt(
+        &self,
+        request_id: Uuid,
+        error: Option<&str>,
+    ) -> DbResult<()> {
+        self.measure("record_attempt", self.inner.record_attempt(request_id, error)).await
+    }
+
+    async fn archive_requests(
+        &self,
+        before: DateTime<Utc>,
+        batch: i64,
+    ) -> DbResult<i64> {
+        self.measure("archive_requests", self.inner.archive_requests(before, batch)).await
+    }
+
+    async fn count_by_status(&self) -> DbResult<HashMap<ExportStatus, i64>> {
+        self.measure("count_by_status", self.inner.count_by_status()).await
+    }
+
+    async fn get_request_history(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<Vec<ExportRequestEvent>> {
+        self.measure_rows("get_request_history", self.inner.get_request_history(request_id)).await
+    }
+
+    async fn count_user_exports_since(
+        &self,
+        user_id: i64,
+        since: DateTime<Utc>,
+    ) -> DbResult<i64> {
+        self.measure("count_user_exports_since", self.inner.count_user_exports_since(user_id, since)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::services::db_store::DbError;
+    use crate::services::mock_store::MockDbStore;
+    use metrics::{Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+    use std::sync::Mutex;
+
+    /// Một giá trị histogram đã ghi: (tên metric, label đã sắp xếp, giá trị).
+    type Sample = (String, Vec<(String, String)>, f64);
+
+    /// Recorder giữ lại mọi giá trị histogram để test kiểm tra tên, label và số lần ghi.
+    #[derive(Default)]
+    struct CapturingRecorder {
+        samples: Arc<Mutex<Vec<Sample>>>,
+    }
+
+    struct CapturedHistogram {
+        key: Key,
+        samples: Arc<Mutex<Vec<Sample>>>,
+    }
+
+    impl HistogramFn for CapturedHistogram {
+        fn record(&self, value: f64) {
+            let mut labels: Vec<(String, String)> = self
+                .key
+                .labels()
+                .map(|label| (label.key().to_string(), label.value().to_string()))
+                .collect();
+            labels.sort();
+            self.samples.lock().unwrap().push((self.key.name().to_string(), labels, value));
+        }
+    }
+
+    impl Recorder for CapturingRecorder {
+        fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+        fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+        fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+        fn register_counter(&self, _key: &Key, _metadata: &Metadata<'_>) -> Counter {
+            Counter::noop()
+        }
+
+        fn register_gauge(&self, _key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(Arc::new(CapturedHistogram { key: key.clone(), samples: self.samples.clone() }))
+        }
+    }
+
+    fn labels(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    fn metered(inner: MockDbStore) -> MeteredDbStore<MockDbStore> {
+        MeteredDbStore::new(inner, Arc::new(MockClock::new(Utc::now())))
+    }
+
+    fn params() -> ReportParams {
+        serde_json::from_value(serde_json::json!({"start_date": "2024-05-01", "end_date": "2024-05-31"})).unwrap()
+    }
+
+    #[test]
+    fn records_duration_and_rows_of_a_successful_call() {
+        let recorder = CapturingRecorder::default();
+        let inner = MockDbStore::new();
+        inner.set_product_rows(3);
+        let store = metered(inner);
+        let params = params();
+
+        let rows = metrics::with_local_recorder(&recorder, || {
+            futures::executor::block_on(store.query_product_data(&params, 100))
+        })
+        .unwrap();
+
+        assert_eq!(rows.len(), 3);
+        let samples = recorder.samples.lock().unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].0, "excel_export_db_operation_duration_seconds");
+        assert_eq!(samples[0].1, labels(&[("operation", "query_product_data"), ("outcome", "ok")]));
+        assert_eq!(samples[1], ("excel_export_db_rows_returned".to_string(), labels(&[("operation", "query_product_data")]), 3.0));
+    }
+
+    #[test]
+    fn records_only_an_error_duration_for_a_failed_call() {
+        let recorder = CapturingRecorder::default();
+        let inner = MockDbStore::new();
+        inner.fail_next("query_product_data", DbError::Transient(anyhow::anyhow!("connection reset")));
+        let store = metered(inner);
+        let params = params();
+
+        let result = metrics::with_local_recorder(&recorder, || {
+            futures::executor::block_on(store.query_product_data(&params, 100))
+        });
+
+        assert!(result.is_err());
+        let samples = recorder.samples.lock().unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].0, "excel_export_db_operation_duration_seconds");
+        assert_eq!(samples[0].1, labels(&[("operation", "query_product_data"), ("outcome", "error")]));
+    }
+
+    #[test]
+    fn each_call_is_recorded_under_its_own_operation() {
+        let recorder = CapturingRecorder::default();
+        let inner = MockDbStore::new();
+        inner.fail_next("ping", DbError::Transient(anyhow::anyhow!("timed out")));
+        let store = metered(inner);
+
+        metrics::with_local_recorder(&recorder, || {
+            futures::executor::block_on(async {
+                assert!(store.ping().await.is_err());
+                assert!(store.ping().await.is_ok());
+                assert_eq!(store.count_by_status().await.unwrap().len(), 0);
+            })
+        });
+
+        let outcomes: Vec<Vec<(String, String)>> =
+            recorder.samples.lock().unwrap().iter().map(|(_, labels, _)| labels.clone()).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                labels(&[("operation", "ping"), ("outcome", "error")]),
+                labels(&[("operation", "ping"), ("outcome", "ok")]),
+                labels(&[("operation", "count_by_status"), ("outcome", "ok")]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::Result;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::models::{CsvOptions, ExcelStyleOptions, OutputCompression, ReportData};
+use crate::services::encryption::FileEncryption;
+use crate::services::file_exporter::{
+    CompressedFile, CsvFileWriter, ExcelTemplate, ExportMetadata, ExportedFile, FileChecksum, FileExporter,
+    LocalFileExporter, PdfReport,
+};
+use crate::services::pgp_encryption::PgpEncryption;
+
+/// Lỗi được giả lập ở lần export tiếp theo, sau khi file đã được ghi ra đĩa.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFault {
+    /// Export trả về lỗi (ví dụ ghi lỗi ở dòng cuối).
+    Fail,
+    /// Export panic giữa chừng.
+    Panic,
+}
+
+/// FileExporter giả lập cho test: ghi file thật qua `LocalFileExporter`, rồi làm lần export tiếp theo
+/// thất bại hoặc panic theo `fault_next`, để test thấy được file dở dang còn lại trên đĩa.
+pub struct MockFileExporter {
+    inner: LocalFileExporter,
+    fault: Mutex<Option<ExportFault>>,
+}
+
+impl MockFileExporter {
+    pub fn new(inner: LocalFileExporter) -> Self {
+        Self { inner, fault: Mutex::new(None) }
+    }
+
+    /// Lần export tiếp theo ghi file rồi gặp `fault`.
+    pub fn fault_next(&self, fault: ExportFault) {
+        *self.fault.lock().unwrap() = Some(fault);
+    }
+
+    fn after_write(&self, exported: Result<ExportedFile>) -> Result<ExportedFile> {
+        let exported = exported?;
+        match self.fault.lock().unwrap().take() {
+            Some(ExportFault::Fail) => Err(anyhow::anyhow!("simulated failure after writing {}", exported.path)),
+            Some(ExportFault::Panic) => panic!("simulated panic after writing {}", exported.path),
+            None => Ok(exported),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl FileExporter for MockFileExporter {
+    async fn export_to_excel(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        style: &ExcelStyleOptions,
+        metadata: Option<&ExportMetadata>,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.after_write(self.inner.export_to_excel(request_id, data, style, metadata, max_file_size, export_path).await)
+    }
+
+    async fn export_to_template(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        template: &ExcelTemplate,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.after_write(self.inner.export_to_template(request_id, data, template, style, export_path).await)
+    }
+
+    async fn export_to_csv(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.after_write(self.inner.export_to_csv(request_id, data, options, compression, max_file_size, export_path).await)
+    }
+
+    async fn export_to_parquet(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.after_write(self.inner.export_to_parquet(request_id, data, export_path).await)
+    }
+
+    async fn export_to_ods(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.after_write(self.inner.export_to_ods(request_id, data, style, export_path).await)
+    }
+
+    async fn export_to_html(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        title: &str,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.after_write(self.inner.export_to_html(request_id, data, title, style, export_path).await)
+    }
+
+    async fn export_to_pdf(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        report: &PdfReport,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.after_write(self.inner.export_to_pdf(request_id, data, report, export_path).await)
+    }
+
+    async fn export_to_jsonl(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        compression: OutputCompression,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.after_write(self.inner.export_to_jsonl(request_id, data, compression, max_file_size, export_path).await)
+    }
+
+    async fn create_csv_file(
+        &self,
+        request_id: Uuid,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        export_path: &str,
+    ) -> Result<CsvFileWriter> {
+        self.inner.create_csv_file(request_id, options, compression, export_path).await
+    }
+
+    async fn delete_file(&self, file_path: &str) -> Result<u64> {
+        self.inner.delete_file(file_path).await
+    }
+
+    async fn remove_empty_dirs(&self, file_path: &str, levels: usize) -> Result<u64> {
+        self.inner.remove_empty_dirs(file_path, levels).await
+    }
+
+    async fn remove_partial_output(&self, request_id: Uuid, export_path: &str) -> Result<u64> {
+        self.inner.remove_partial_output(request_id, export_path).await
+    }
+
+    async fn file_checksum(&self, file_path: &str) -> Result<Option<FileChecksum>> {
+        self.inner.file_checksum(file_path).await
+    }
+
+    async fn file_exists(&self, file_path: &str) -> Result<bool> {
+        self.inner.file_exists(file_path).await
+    }
+
+    async fn compress_file(&self, request_id: Uuid, file_path: &str, min_size_bytes: u64) -> Result<Option<CompressedFile>> {
+        self.inner.compress_file(request_id, file_path, min_size_bytes).await
+    }
+
+    async fn zip_parts(&self, request_id: Uuid, part_paths: &[String], password: Option<&str>) -> Result<CompressedFile> {
+        self.inner.zip_parts(request_id, part_paths, password).await
+    }
+
+    async fn encrypt_file(&self, request_id: Uuid, file_path: &str, password: &str) -> Result<CompressedFile> {
+        self.inner.encrypt_file(request_id, file_path, password).await
+    }
+
+    async fn encrypt_at_rest(&self, file_path: &str, encryption: &FileEncryption) -> Result<String> {
+        self.inner.encrypt_at_rest(file_path, encryption).await
+    }
+
+    async fn encrypt_for_recipient(&self, file_path: &str, pgp: &PgpEncryption, recipient_key_id: &str) -> Result<(String, String)> {
+        self.inner.encrypt_for_recipient(file_path, pgp, recipient_key_id).await
+    }
+
+    async fn rename_file(&self, request_id: Uuid, file_path: &str, stem: &str) -> Result<String> {
+        self.inner.rename_file(request_id, file_path, stem).await
+    }
+
+    async fn rename_by_content(&self, request_id: Uuid, file_path: &str) -> Result<(String, bool)> {
+        self.inner.rename_by_content(request_id, file_path).await
+    }
+
+    async fn write_metadata_file(&self, file_path: &str, metadata: &ExportMetadata) -> Result<String> {
+        self.inner.write_metadata_file(file_path, metadata).await
+    }
+}
@@ -0,0 +1,627 @@
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::config::ExportQuota;
+use crate::models::{
+    CustomerData, CustomerReportParams, ExportCompletion, ExportRequest, ExportRequestEvent, ExportStatus,
+    ExportTimings, OrderData, OrderReportParams, ProductData, ReportParams, SalesSummaryRow, UnsentNotification,
+};
+use crate::services::db_store::{DbError, DbResult, DbStore};
+
+/// DbStore giả lập cho test: giữ request trong bộ nhớ, đếm số lần gọi từng thao tác và trả về các lỗi
+/// đã được xếp trước bằng `fail_next` (theo thứ tự, mỗi lần gọi lấy một lỗi).
+#[derive(Default)]
+pub struct MockDbStore {
+    requests: Mutex<HashMap<Uuid, ExportRequest>>,
+    product_rows: Mutex<usize>,
+    streamed_rows: Mutex<usize>,
+    order_rows: Mutex<Vec<OrderData>>,
+    locks: Mutex<HashSet<Uuid>>,
+    events: Mutex<Vec<ExportRequestEvent>>,
+    timings: Mutex<HashMap<Uuid, ExportTimings>>,
+    calls: Mutex<HashMap<&'static str, usize>>,
+    failures: Mutex<HashMap<&'static str, VecDeque<DbError>>>,
+}
+
+impl MockDbStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Thêm request; `status` ghi đè trạng thái của `request`.
+    pub fn insert(&self, mut request: ExportRequest, status: ExportStatus) -> Uuid {
+        request.status = status;
+        let id = request.id;
+        self.requests.lock().unwrap().insert(id, request);
+        id
+    }
+
+    /// Bản sao của request đang lưu (ExportRequest không có Clone).
+    pub fn request(&self, id: Uuid) -> Option<ExportRequest> {
+        self.requests.lock().unwrap().get(&id).map(copy_request)
+    }
+
+    /// Report sản phẩm trả về `rows` dòng.
+    pub fn set_product_rows(&self, rows: usize) {
+        *self.product_rows.lock().unwrap() = rows;
+    }
+
+    /// Số dòng sản phẩm `stream_product_data` đã trả ra (stream chỉ tạo dòng khi được đọc tới).
+    pub fn streamed_rows(&self) -> usize {
+        *self.streamed_rows.lock().unwrap()
+    }
+
+    /// Đơn hàng của report đơn hàng; `query_order_data` lọc theo user như điều kiện `user_id` của SQL.
+    pub fn set_order_rows(&self, rows: Vec<OrderData>) {
+        *self.order_rows.lock().unwrap() = rows;
+    }
+
+    /// Thời gian các giai đoạn đã ghi cho request (`update_request_status` và `record_notify_duration`).
+    pub fn timings(&self, request_id: Uuid) -> Option<ExportTimings> {
+        self.timings.lock().unwrap().get(&request_id).cloned()
+    }
+
+    /// Lần gọi `operation` tiếp theo (chưa dùng lỗi nào) trả về `error`.
+    pub fn fail_next(&self, operation: &'static str, error: DbError) {
+        self.failures.lock().unwrap().entry(operation).or_default().push_back(error);
+    }
+
+    pub fn calls(&self, operation: &str) -> usize {
+        self.calls.lock().unwrap().get(operation).copied().unwrap_or_default()
+    }
+
+    fn enter(&self, operation: &'static str) -> DbResult<()> {
+        *self.calls.lock().unwrap().entry(operation).or_default() += 1;
+        match self.failures.lock().unwrap().get_mut(operation).and_then(VecDeque::pop_front) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn update<T>(&self, request_id: Uuid, change: impl FnOnce(&mut ExportRequest) -> T) -> DbResult<T> {
+        self.requests
+            .lock()
+            .unwrap()
+            .get_mut(&request_id)
+            .map(change)
+            .ok_or(DbError::NotFound(request_id))
+    }
+
+    /// Ghi một sự kiện chuyển trạng thái như `insert_status_event` của PostgresDbStore.
+    fn record_event(&self, request_id: Uuid, from_status: ExportStatus, to_status: ExportStatus, detail: Option<&str>) {
+        let mut events = self.events.lock().unwrap();
+        let id = events.len() as i64 + 1;
+        events.push(ExportRequestEvent {
+            id,
+            request_id,
+            from_status: Some(from_status.as_str().to_string()),
+            to_status: to_status.as_str().to_string(),
+            detail: detail.map(str::to_string),
+            created_at: Utc::now(),
+        });
+    }
+
+    fn select(&self, filter: impl Fn(&ExportRequest) -> bool) -> Vec<ExportRequest> {
+        self.requests.lock().unwrap().values().filter(|request| filter(request)).map(copy_request).collect()
+    }
+}
+
+fn copy_request(request: &ExportRequest) -> ExportRequest {
+    serde_json::from_value(serde_json::to_value(request).unwrap()).unwrap()
+}
+
+fn copy_order(order: &OrderData) -> OrderData {
+    OrderData {
+        order_id: order.order_id,
+        user_id: order.user_id,
+        product_name: order.product_name.clone(),
+        quantity: order.quantity,
+        unit_price: order.unit_price,
+        total: order.total,
+        ordered_at: order.ordered_at,
+        status: order.status.clone(),
+    }
+}
+
+fn product(index: usize) -> ProductData {
+    ProductData {
+        product_id: index as i64 + 1,
+        name: format!("Product {}", index + 1),
+        category: "Books".to_string(),
+        price: 10.0 + index as f64,
+        stock_quantity: 5,
+        created_at: Utc::now(),
+    }
+}
+
+#[async_trait::async_trait]
+impl DbStore for MockDbStore {
+    async fn ping(&self) -> DbResult<()> {
+        self.enter("ping")
+    }
+
+    async fn fetch_and_update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        _quota: &ExportQuota,
+    ) -> DbResult<ExportRequest> {
+        self.enter("fetch_and_update_request_status")?;
+        self.update(request_id, |request| {
+            if request.status.is_final() {
+                return Err(DbError::AlreadyFinal(request.status));
+            }
+            let previous_status = std::mem::replace(&mut request.status, new_status);
+            Ok((previous_status, copy_request(request)))
+        })?
+        .map(|(previous_status, request)| {
+            self.record_event(request_id, previous_status, new_status, None);
+            request
+        })
+    }
+
+    async fn try_lock_request(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        self.enter("try_lock_request")?;
+        Ok(self.locks.lock().unwrap().insert(request_id))
+    }
+
+    async fn unlock_request(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.enter("unlock_request")?;
+        self.locks.lock().unwrap().remove(&request_id);
+        Ok(())
+    }
+
+    async fn update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        file_path: Option<String>,
+        error_message: Option<String>,
+        error_code: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+        timings: &ExportTimings,
+        completion: &ExportCompletion,
+    ) -> DbResult<()> {
+        self.enter("update_request_status")?;
+        let detail = error_code.clone().or(error_message.clone());
+        let previous_status = self.update(request_id, |request| {
+            let previous_status = std::mem::replace(&mut request.status, new_status);
+            request.file_path = file_path.or(request.file_path.take());
+            request.error_message = error_message;
+            request.error_code = error_code;
+            request.expires_at = expires_at.or(request.expires_at);
+            // Như `COALESCE` của câu UPDATE: metadata `None` giữ nguyên giá trị cũ.
+            request.file_size_bytes = completion.file_size_bytes.or(request.file_size_bytes);
+            request.row_count = completion.rows_exported.or(request.row_count);
+            request.file_checksum = completion.file_checksum.clone().or(request.file_checksum.take());
+            request.truncated_by_limit = completion.truncated_by_limit.or(request.truncated_by_limit);
+            request.original_file_name = completion.original_file_name.clone().or(request.original_file_name.take());
+            request.original_size_bytes = completion.original_size_bytes.or(request.original_size_bytes);
+            request.is_protected = completion.is_protected.or(request.is_protected);
+            request.file_name = completion.file_name.clone().or(request.file_name.take());
+            request.is_encrypted = completion.is_encrypted.or(request.is_encrypted);
+            request.encryption_key_id = completion.encryption_key_id.clone().or(request.encryption_key_id.take());
+            request.pgp_key_fingerprint = completion.pgp_key_fingerprint.clone().or(request.pgp_key_fingerprint.take());
+            if let Some(parts) = &completion.file_parts {
+                request.file_parts = Some(serde_json::json!(parts));
+            }
+            request.completed_at = Some(Utc::now());
+            previous_status
+        })?;
+        self.timings.lock().unwrap().insert(request_id, timings.clone());
+        self.record_event(request_id, previous_status, new_status, detail.as_deref());
+        Ok(())
+    }
+
+    async fn update_notification_sent_status(
+        &self,
+        request_id: Uuid,
+        sent: bool,
+    ) -> DbResult<()> {
+        self.enter("update_notification_sent_status")?;
+        self.update(request_id, |request| request.notification_sent = sent)
+    }
+
+    async fn mark_webhook_sent(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.enter("mark_webhook_sent")?;
+        self.update(request_id, |request| request.webhook_sent = true)
+    }
+
+    async fn query_product_data(
+        &self,
+        _params: &ReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<ProductData>> {
+        self.enter("query_product_data")?;
+        let rows = *self.product_rows.lock().unwrap();
+        Ok((0..rows.min(max_rows.saturating_add(1))).map(product).collect())
+    }
+
+    fn stream_product_data<'a>(
+        &'a self,
+        _params: &'a ReportParams,
+    ) -> BoxStream<'a, anyhow::Result<ProductData>> {
+        Box::pin(async_stream::stream! {
+            if let Err(e) = self.enter("stream_product_data") {
+                yield Err(anyhow::Error::new(e));
+                return;
+            }
+            let rows = *self.product_rows.lock().unwrap();
+            for index in 0..rows {
+                // Mỗi dòng là một điểm chờ, như một lần đọc từ cursor.
+                tokio::task::yield_now().await;
+                *self.streamed_rows.lock().unwrap() += 1;
+                yield Ok(product(index));
+            }
+        })
+    }
+
+    async fn count_product_data(
+        &self,
+        _params: &ReportParams,
+    ) -> DbResult<i64> {
+        self.enter("count_product_data")?;
+        Ok(*self.product_rows.lock().unwrap() as i64)
+    }
+
+    async fn query_order_data(
+        &self,
+        params: &OrderReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<OrderData>> {
+        self.enter("query_order_data")?;
+        Ok(self
+            .order_rows
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|order| params.user_id.map_or(true, |user_id| order.user_id == user_id))
+            .take(max_rows.saturating_add(1))
+            .map(copy_order)
+            .collect())
+    }
+
+    async fn query_customer_data(
+        &self,
+        _params: &CustomerReportParams,
+        _max_rows: usize,
+    ) -> DbResult<Vec<CustomerData>> {
+        self.enter("query_customer_data")?;
+        Ok(Vec::new())
+    }
+
+    async fn query_category_summary(
+        &self,
+        _params: &ReportParams,
+    ) -> DbResult<Vec<SalesSummaryRow>> {
+        self.enter("query_category_summary")?;
+        Ok(Vec::new())
+    }
+
+    async fn claim_unsent_notifications(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.enter("claim_unsent_notifications")?;
+        let mut requests = self.select(|request| request.status.is_final() && !request.notification_sent);
+        requests.truncate(limit as usize);
+        Ok(requests)
+    }
+
+    async fn list_unsent_notifications(
+        &self,
+        _limit: i64,
+        _after: Option<Uuid>,
+    ) -> DbResult<Vec<UnsentNotification>> {
+        self.enter("list_unsent_notifications")?;
+        Ok(Vec::new())
+    }
+
+    async fn record_notification_failure(
+        &self,
+        request_id: Uuid,
+        next_retry_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        self.enter("record_notification_failure")?;
+        self.update(request_id, |request| {
+            request.notification_attempts += 1;
+            request.notification_next_retry_at = Some(next_retry_at);
+        })
+    }
+
+    async fn list_expired(
+        &self,
+        before: DateTime<Utc>,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.enter("list_expired")?;
+        Ok(self.select(|request| {
+            request.status == ExportStatus::Completed
+                && request.file_path.is_some()
+                && request.expires_at.map_or(request.requested_at < before, |expires_at| expires_at <= Utc::now())
+        }))
+    }
+
+    async fn mark_expired(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.enter("mark_expired")?;
+        self.update(request_id, |request| {
+            request.status = ExportStatus::Expired;
+            request.file_path = None;
+            request.expired_at = Some(Utc::now());
+        })
+    }
+
+    async fn is_file_shared(
+        &self,
+        file_path: &str,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        self.enter("is_file_shared")?;
+        Ok(!self
+            .select(|request| {
+                request.id != request_id
+                    && request.status == ExportStatus::Completed
+                    && request.file_path.as_deref() == Some(file_path)
+            })
+            .is_empty())
+    }
+
+    async fn record_generated_file(
+        &self,
+        request_id: Uuid,
+        file_path: &str,
+        file_checksum: &str,
+        file_size_bytes: i64,
+    ) -> DbResult<()> {
+        self.enter("record_generated_file")?;
+        self.update(request_id, |request| {
+            request.file_path = Some(file_path.to_string());
+            request.file_checksum = Some(file_checksum.to_string());
+            request.file_size_bytes = Some(file_size_bytes);
+        })
+    }
+
+    async fn record_params_hash(
+        &self,
+        request_id: Uuid,
+        params_hash: &str,
+    ) -> DbResult<()> {
+        self.enter("record_params_hash")?;
+        self.update(request_id, |request| request.params_hash = Some(params_hash.to_string()))
+    }
+
+    async fn find_recent_by_params_hash(
+        &self,
+        params_hash: &str,
+        since: DateTime<Utc>,
+        exclude_request_id: Uuid,
+    ) -> DbResult<Option<ExportRequest>> {
+        self.enter("find_recent_by_params_hash")?;
+        Ok(self
+            .select(|request| {
+                request.id != exclude_request_id
+                    && request.status == ExportStatus::Completed
+                    && request.params_hash.as_deref() == Some(params_hash)
+                    && request.file_path.is_some()
+                    && request.requested_at >= since
+            })
+            .into_iter()
+            .next())
+    }
+
+    async fn record_estimated_completion(
+        &self,
+        _request_id: Uuid,
+        _estimated_completion_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        self.enter("record_estimated_completion")
+    }
+
+    async fn record_export_duration(
+        &self,
+        _request_id: Uuid,
+        _duration_ms: i64,
+        _row_count: Option<i64>,
+    ) -> DbResult<()> {
+        self.enter("record_export_duration")
+    }
+
+    async fn record_notify_duration(
+        &self,
+        request_id: Uuid,
+        notify_ms: i64,
+    ) -> DbResult<()> {
+        self.enter("record_notify_duration")?;
+        self.timings.lock().unwrap().entry(request_id).or_default().notify_ms = Some(notify_ms);
+        Ok(())
+    }
+
+    async fn recent_export_durations(
+        &self,
+        _limit: i64,
+    ) -> DbResult<Vec<(i64, i64)>> {
+        self.enter("recent_export_durations")?;
+        Ok(Vec::new())
+    }
+
+    async fn claim_next_pending(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.enter("claim_next_pending")?;
+        // Chọn và chuyển trạng thái khi đang giữ mutex, như FOR UPDATE SKIP LOCKED trong một transaction.
+        let now = Utc::now();
+        let mut requests = self.requests.lock().unwrap();
+        let mut pending: Vec<&mut ExportRequest> = requests
+            .values_mut()
+            .filter(|request| {
+                request.status == ExportStatus::Pending && request.next_retry_at.map_or(true, |retry_at| retry_at <= now)
+            })
+            .collect();
+        pending.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.requested_at.cmp(&b.requested_at)));
+        Ok(pending
+            .into_iter()
+            .take(limit as usize)
+            .map(|request| {
+                request.status = ExportStatus::Processing;
+                request.status_updated_at = now;
+                copy_request(request)
+            })
+            .collect())
+    }
+
+    async fn fetch_stuck_processing(
+        &self,
+        stuck_for: chrono::Duration,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.enter("fetch_stuck_processing")?;
+        let before = Utc::now() - stuck_for;
+        let mut requests =
+            self.select(|request| request.status == ExportStatus::Processing && request.status_updated_at < before);
+        requests.sort_by_key(|request| request.status_updated_at);
+        requests.truncate(limit as usize);
+        Ok(requests)
+    }
+
+    async fn reset_to_pending(
+        &self,
+        ids: &[Uuid],
+    ) -> DbResult<Vec<Uuid>> {
+        self.enter("reset_to_pending")?;
+        let now = Utc::now();
+        let mut requests = self.requests.lock().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| requests.get_mut(id))
+            .filter(|request| request.status == ExportStatus::Processing)
+            .map(|request| {
+                // Các trường của lần xử lý dở, như câu UPDATE của PostgresDbStore.
+                *request = ExportRequest {
+                    status: ExportStatus::Pending,
+                    status_updated_at: now,
+                    file_path: None,
+                    file_checksum: None,
+                    file_size_bytes: None,
+                    error_message: None,
+                    error_code: None,
+                    estimated_completion_at: None,
+                    duration_ms: None,
+                    row_count: None,
+                    truncated_by_limit: None,
+                    original_file_name: None,
+                    original_size_bytes: None,
+                    is_protected: None,
+                    file_name: None,
+                    is_encrypted: None,
+                    encryption_key_id: None,
+                    pgp_key_fingerprint: None,
+                    file_parts: None,
+                    webhook_sent: false,
+                    db_fetch_ms: None,
+                    db_query_ms: None,
+                    file_generation_ms: None,
+                    ..copy_request(request)
+                };
+                request.id
+            })
+            .collect())
+    }
+
+    async fn bulk_update_status(
+        &self,
+        ids: &[Uuid],
+        new_status: ExportStatus,
+        error_message: Option<String>,
+        force: bool,
+    ) -> DbResult<u64> {
+        self.enter("bulk_update_status")?;
+        let now = Utc::now();
+        let mut requests = self.requests.lock().unwrap();
+        let mut updated = 0;
+        for id in ids.iter().collect::<HashSet<_>>() {
+            let Some(request) = requests.get_mut(id) else { continue };
+            if request.status == new_status || (request.status == ExportStatus::Completed && !force) {
+                continue;
+            }
+            request.status = new_status;
+            request.status_updated_at = now;
+            request.error_message = error_message.clone();
+            updated += 1;
+        }
+        Ok(updated)
+    }
+
+    async fn record_attempt(
+        &self,
+        request_id: Uuid,
+        error: Option<&str>,
+    ) -> DbResult<()> {
+        self.enter("record_attempt")?;
+        self.update(request_id, |request| match error {
+            Some(error) => request.last_error = Some(error.to_string()),
+            None => request.attempts += 1,
+        })
+    }
+
+    async fn archive_requests(
+        &self,
+        _before: DateTime<Utc>,
+        _batch: i64,
+    ) -> DbResult<i64> {
+        self.enter("archive_requests")?;
+        Ok(0)
+    }
+
+    async fn count_by_status(&self) -> DbResult<HashMap<ExportStatus, i64>> {
+        self.enter("count_by_status")?;
+        let mut counts = HashMap::new();
+        for request in self.requests.lock().unwrap().values() {
+            *counts.entry(request.status).or_default() += 1;
+        }
+        Ok(counts)
+    }
+
+    async fn get_request_history(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<Vec<ExportRequestEvent>> {
+        self.enter("get_request_history")?;
+        let events = self.events.lock().unwrap();
+        Ok(events
+            .iter()
+            .filter(|event| event.request_id == request_id)
+            .map(|event| ExportRequestEvent {
+                id: event.id,
+                request_id: event.request_id,
+                from_status: event.from_status.clone(),
+                to_status: event.to_status.clone(),
+                detail: event.detail.clone(),
+                created_at: event.created_at,
+            })
+            .collect())
+    }
+
+    async fn count_user_exports_since(
+        &self,
+        _user_id: i64,
+        _since: DateTime<Utc>,
+    ) -> DbResult<i64> {
+        self.enter("count_user_exports_since")?;
+        Ok(0)
+    }
+}
@@ -1,4 +1,30 @@
+pub mod concurrency;
 pub mod db_store;
+pub mod duration_stats;
+pub mod email_delivery;
+pub mod encryption;
 pub mod export_service;
 pub mod file_exporter;
-pub mod notifier;
\ No newline at end of file
+pub mod hooks;
+pub mod html_exporter;
+pub mod jsonl_exporter;
+pub mod metered_store;
+#[cfg(test)]
+pub mod mock_exporter;
+#[cfg(test)]
+pub mod mock_store;
+#[cfg(feature = "mysql")]
+pub mod mysql_store;
+pub mod notifier;
+#[cfg(feature = "ods")]
+pub mod ods_exporter;
+#[cfg(feature = "parquet")]
+pub mod parquet_exporter;
+#[cfg(feature = "pdf")]
+pub mod pdf_exporter;
+pub mod pgp_encryption;
+pub mod retrying_store;
+pub mod safe_path;
+#[cfg(feature = "s3")]
+pub mod s3_exporter;
+pub mod sftp_delivery;
\ No newline at end of file
@@ -0,0 +1,1243 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveTime, Utc};
+use sqlx::mysql::MySqlArguments;
+use sqlx::query::QueryAs;
+use sqlx::{MySql, MySqlExecutor, Pool, Transaction};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::config::ExportQuota;
+use crate::models::{
+    CustomerData, CustomerReportParams, ExportCompletion, ExportRequest, ExportRequestEvent, ExportStatus, ExportTimings,
+    OrderData, OrderReportParams, ProductData, ReportParams, SalesSummaryRow, UnsentNotification,
+};
+use crate::services::db_store::{DbError, DbResult, DbStore, QueryTimeout, QuotaExceeded, PING_TIMEOUT};
+
+/// Danh sách cột của ExportRequests, dùng chung cho mọi query trả về `ExportRequest`.
+const EXPORT_REQUEST_COLUMNS: &str = r#"
+    id, user_id, request_payload, requested_at, status, file_path, completed_at, error_message, notification_sent, webhook_sent,
+    notification_attempts, notification_next_retry_at, expired_at, error_code, file_checksum, file_size_bytes,
+    expires_at, params_hash, duration_ms, row_count, truncated_by_limit, original_file_name, original_size_bytes, is_protected, file_name, is_encrypted, encryption_key_id, pgp_key_fingerprint, file_parts, estimated_completion_at,
+    db_fetch_ms, db_query_ms, file_generation_ms, notify_ms, attempts, last_attempt_at, last_error,
+    priority, next_retry_at, status_updated_at
+"#;
+
+/// Phần FROM/WHERE chung của các query trên bộ lọc report sản phẩm, giống `product_filter_sql!` của Postgres.
+/// MySQL không cho dùng lại placeholder nên mỗi bộ lọc tùy chọn được bind hai lần (`bind_product_filters`).
+macro_rules! product_filter_sql {
+    () => {
+        r#"
+    FROM products
+    WHERE created_at BETWEEN ? AND ?
+    AND (? IS NULL OR category = ?)
+    AND (? IS NULL OR price >= ?)
+    AND (? IS NULL OR price <= ?)
+    AND (? IS NULL OR stock_quantity <= ?)
+    AND (? IS NULL OR name LIKE ?)
+"#
+    };
+}
+
+/// Phần SELECT/WHERE của query report sản phẩm, tương ứng với `PRODUCT_REPORT_QUERY` của Postgres.
+/// `{hint}` là optimizer hint giới hạn thời gian chạy (bỏ trống khi không cấu hình timeout).
+const PRODUCT_REPORT_QUERY: &str = concat!(
+    r#"
+    SELECT {hint}
+        product_id,
+        name,
+        category,
+        CAST(price AS DOUBLE) AS price,
+        stock_quantity,
+        created_at"#,
+    product_filter_sql!()
+);
+
+/// Đếm số dòng mà `PRODUCT_REPORT_QUERY` sẽ trả về (không tính LIMIT).
+const PRODUCT_COUNT_QUERY: &str = concat!("SELECT {hint} COUNT(*)", product_filter_sql!());
+
+/// Tương ứng với `CATEGORY_SUMMARY_QUERY` của Postgres. WITH ROLLUP tạo dòng tổng (category NULL).
+const CATEGORY_SUMMARY_QUERY: &str = concat!(
+    r#"
+    SELECT {hint}
+        category,
+        COUNT(*) AS product_count,
+        CAST(COALESCE(SUM(stock_quantity), 0) AS SIGNED) AS total_stock,
+        CAST(MIN(price) AS DOUBLE) AS min_price,
+        CAST(AVG(price) AS DOUBLE) AS avg_price,
+        CAST(MAX(price) AS DOUBLE) AS max_price"#,
+    product_filter_sql!(),
+    r#"
+    GROUP BY category WITH ROLLUP
+"#
+);
+
+const ORDER_REPORT_QUERY: &str = r#"
+    SELECT {hint}
+        o.order_id,
+        o.user_id,
+        p.name AS product_name,
+        o.quantity,
+        CAST(o.unit_price AS DOUBLE) AS unit_price,
+        CAST(o.quantity * o.unit_price AS DOUBLE) AS total,
+        o.ordered_at,
+        o.status
+    FROM orders o
+    JOIN products p ON p.product_id = o.product_id
+    WHERE o.ordered_at BETWEEN ? AND ?
+    AND (? IS NULL OR o.status = ?)
+    AND (? IS NULL OR o.user_id = ?)
+    ORDER BY o.ordered_at, o.order_id
+    LIMIT ?
+"#;
+
+const CUSTOMER_REPORT_QUERY: &str = r#"
+    SELECT {hint}
+        c.customer_id,
+        c.name,
+        c.email,
+        c.signup_date,
+        COUNT(o.order_id) AS total_orders,
+        CAST(COALESCE(SUM(o.quantity * o.unit_price), 0) AS DOUBLE) AS lifetime_value
+    FROM customers c
+    LEFT JOIN orders o
+        ON o.user_id = c.customer_id
+        AND o.status NOT IN ('cancelled', 'refunded')
+    WHERE c.signup_date BETWEEN ? AND ?
+    AND (? IS NULL OR c.customer_id = ?)
+    GROUP BY c.customer_id, c.name, c.email, c.signup_date
+    HAVING (? IS NULL OR COALESCE(SUM(o.quantity * o.unit_price), 0) >= ?)
+    ORDER BY c.signup_date, c.customer_id
+    LIMIT ?
+"#;
+
+/// Mã lỗi khi query bị hủy vì vượt MAX_EXECUTION_TIME (MySQL) hoặc max_statement_time (MariaDB).
+const QUERY_TIMEOUT_ERROR_CODES: [&str; 2] = ["3024", "1969"];
+
+/// Thời gian một row bị giữ sau khi được claim bởi notification retry worker.
+const NOTIFICATION_CLAIM_LEASE_SECS: i64 = 300;
+
+fn bind_product_filters<'q, O>(
+    query: QueryAs<'q, MySql, O, MySqlArguments>,
+    params: &'q ReportParams,
+) -> QueryAs<'q, MySql, O, MySqlArguments> {
+    let name_pattern = params.name_pattern();
+    let (from, to) = params.utc_range();
+    query
+        .bind(from)
+        .bind(to)
+        .bind(params.product_category.as_deref())
+        .bind(params.product_category.as_deref())
+        .bind(params.min_price)
+        .bind(params.min_price)
+        .bind(params.max_price)
+        .bind(params.max_price)
+        .bind(params.max_stock_quantity)
+        .bind(params.max_stock_quantity)
+        .bind(name_pattern.clone())
+        .bind(name_pattern)
+}
+
+/// Implementation cho MySQL/MariaDB (bật bằng cargo feature `mysql`).
+/// UUID được lưu dạng BINARY(16), request_payload dạng JSON. Schema nằm trong `migrations/mysql/`.
+/// Không hỗ trợ tenant schema và đọc theo trang; stream dữ liệu dùng implementation mặc định của trait.
+pub struct MySqlDbStore {
+    pool: Pool<MySql>,
+    clock: Arc<dyn Clock>,
+    settings: MySqlStoreSettings,
+}
+
+/// Tùy chọn của MySqlDbStore, lấy từ AppConfig.
+#[derive(Debug, Clone, Default)]
+pub struct MySqlStoreSettings {
+    /// Giới hạn thời gian của query report, áp dụng bằng optimizer hint MAX_EXECUTION_TIME.
+    pub statement_timeout_ms: Option<u64>,
+}
+
+impl MySqlDbStore {
+    pub fn new(pool: Pool<MySql>, clock: Arc<dyn Clock>, settings: MySqlStoreSettings) -> Self {
+        Self { pool, clock, settings }
+    }
+
+    /// Ghép optimizer hint vào query report. Giá trị timeout lấy từ config, không phải input người dùng.
+    fn report_sql(&self, template: &str) -> String {
+        let hint = self
+            .settings
+            .statement_timeout_ms
+            .map(|timeout_ms| format!("/*+ MAX_EXECUTION_TIME({}) */", timeout_ms))
+            .unwrap_or_default();
+        template.replace("{hint}", &hint)
+    }
+
+    fn ensure_no_tenant(tenant: Option<&str>) -> Result<()> {
+        if let Some(tenant) = tenant {
+            anyhow::bail!("Tenant schemas are not supported by the MySQL store (tenant '{}')", tenant);
+        }
+        Ok(())
+    }
+
+    fn map_report_query_error(&self, e: sqlx::Error, context: &'static str) -> anyhow::Error {
+        let timed_out = e
+            .as_database_error()
+            .and_then(|db_error| db_error.code())
+            .is_some_and(|code| QUERY_TIMEOUT_ERROR_CODES.contains(&code.as_ref()));
+        match (timed_out, self.settings.statement_timeout_ms) {
+            (true, Some(timeout_ms)) => anyhow::Error::new(QueryTimeout { timeout_ms }),
+            _ => anyhow::Error::new(e).context(context),
+        }
+    }
+
+    async fn insert_status_event(
+        &self,
+        tx: &mut Transaction<'_, MySql>,
+        request_id: Uuid,
+        from_status: Option<&str>,
+        to_status: &str,
+        detail: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO export_request_events (request_id, from_status, to_status, detail, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(request_id)
+        .bind(from_status)
+        .bind(to_status)
+        .bind(detail)
+        .bind(self.clock.now_utc())
+        .execute(&mut **tx)
+        .await
+        .context("Failed to insert export request status event")?;
+        Ok(())
+    }
+
+    async fn lock_current_status(tx: &mut Transaction<'_, MySql>, request_id: Uuid) -> Result<String> {
+        sqlx::query_scalar("SELECT status FROM ExportRequests WHERE id = ? FOR UPDATE")
+            .bind(request_id)
+            .fetch_optional(&mut **tx)
+            .await
+            .context("Failed to lock export request row")?
+            .context("Export request not found in DB")
+    }
+
+    async fn count_user_exports<'e>(
+        executor: impl MySqlExecutor<'e>,
+        user_id: i64,
+        since: DateTime<Utc>,
+        exclude_request_id: Option<Uuid>,
+    ) -> Result<i64> {
+        sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*)
+            FROM ExportRequests
+            WHERE user_id = ?
+            AND requested_at >= ?
+            AND status IN (?, ?, ?)
+            AND (? IS NULL OR id <> ?)
+            "#,
+        )
+        .bind(user_id)
+        .bind(since)
+        .bind(ExportStatus::Processing.as_str())
+        .bind(ExportStatus::Completed.as_str())
+        .bind(ExportStatus::Expired.as_str())
+        .bind(exclude_request_id)
+        .bind(exclude_request_id)
+        .fetch_one(executor)
+        .await
+        .context("Failed to count user exports")
+    }
+}
+
+#[async_trait::async_trait]
+impl DbStore for MySqlDbStore {
+    async fn ping(&self) -> DbResult<()> {
+        match tokio::time::timeout(PING_TIMEOUT, sqlx::query("SELECT 1").execute(&self.pool)).await {
+            Ok(result) => {
+                result.context("Failed to ping MySQL database")?;
+                Ok(())
+            }
+            Err(_) => Err(DbError::Transient(anyhow::anyhow!(
+                "Ping of MySQL database timed out after {} ms",
+                PING_TIMEOUT.as_millis()
+            ))),
+        }
+    }
+
+    #[instrument(skip(self), fields(request_id = %request_id))]
+    async fn fetch_and_update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        quota: &ExportQuota,
+    ) -> DbResult<ExportRequest> {
+        let mut tx = self.pool.begin().await.context("Failed to begin database transaction")?;
+        info!("Starting transaction to fetch and update status to '{}'.", new_status.as_str());
+
+        let sql = format!("SELECT {} FROM ExportRequests WHERE id = ? FOR UPDATE", EXPORT_REQUEST_COLUMNS);
+        let request: ExportRequest = sqlx::query_as(&sql)
+            .bind(request_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .context("Failed to fetch export request from DB")?
+            .ok_or(DbError::NotFound(request_id))?;
+
+        if request.status.is_final() {
+            warn!(
+                "Request {} already in final state: {}. Rolling back transaction and skipping processing.",
+                request_id, request.status
+            );
+            tx.rollback().await?;
+            return Err(DbError::AlreadyFinal(request.status));
+        }
+
+        if let Some(limit) = quota.limit_for(request.user_id) {
+            // Khóa các request của user (thay cho advisory lock của Postgres) để các claim song song
+            // của cùng user được đếm tuần tự.
+            sqlx::query("SELECT id FROM ExportRequests WHERE user_id = ? FOR UPDATE")
+                .bind(request.user_id)
+                .fetch_all(&mut *tx)
+                .await
+                .context("Failed to acquire quota lock for user")?;
+
+            let day_start = self.clock.now_utc().date_naive().and_time(NaiveTime::MIN).and_utc();
+            let used = Self::count_user_exports(&mut *tx, request.user_id, day_start, Some(request_id)).await?;
+            if used >= limit {
+                warn!(
+                    "User {} has used {}/{} exports today. Rejecting request {}.",
+                    request.user_id, used, limit, request_id
+                );
+                tx.rollback().await?;
+                return Err(DbError::QuotaExceeded(QuotaExceeded {
+                    limit,
+                    reset_at: day_start + chrono::Duration::days(1),
+                }));
+            }
+        }
+
+        sqlx::query("UPDATE ExportRequests SET status = ?, status_updated_at = ? WHERE id = ?")
+            .bind(new_status.as_str())
+            .bind(self.clock.now_utc())
+            .bind(request_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to update request status in DB")?;
+        self.insert_status_event(&mut tx, request_id, Some(request.status.as_str()), new_status.as_str(), None).await?;
+
+        tx.commit().await.context("Failed to commit database transaction")?;
+        info!("Successfully fetched and updated status to '{}' for request {}. Transaction committed.", new_status.as_str(), request_id);
+        Ok(request)
+    }
+
+    #[instrument(skip(self))]
+    async fn update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        file_path: Option<String>,
+        error_message: Option<String>,
+        error_code: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+        timings: &ExportTimings,
+        completion: &ExportCompletion,
+    ) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for status update")?;
+        info!("Updating final status to '{}' for request {}.", new_status.as_str(), request_id);
+        let previous_status = Self::lock_current_status(&mut tx, request_id).await?;
+        let completed_at = self.clock.now_utc();
+
+        sqlx::query(
+            r#"
+            UPDATE ExportRequests
+            SET
+                status = ?,
+                status_updated_at = ?,
+                file_path = ?,
+                completed_at = ?,
+                error_message = ?,
+                error_code = ?,
+                expires_at = ?,
+                db_fetch_ms = ?,
+                db_query_ms = ?,
+                file_generation_ms = ?,
+                file_size_bytes = COALESCE(?, file_size_bytes),
+                row_count = COALESCE(?, row_count),
+                file_checksum = COALESCE(?, file_checksum),
+                truncated_by_limit = COALESCE(?, truncated_by_limit),
+                original_file_name = COALESCE(?, original_file_name),
+                original_size_bytes = COALESCE(?, original_size_bytes),
+                is_protected = COALESCE(?, is_protected),
+                file_name = COALESCE(?, file_name),
+                is_encrypted = COALESCE(?, is_encrypted),
+                encryption_key_id = COALESCE(?, encryption_key_id),
+                pgp_key_fingerprint = COALESCE(?, pgp_key_fingerprint),
+                file_parts = COALESCE(?, file_parts)
+            WHERE id = ?
+            "#,
+        )
+        .bind(new_status.as_str())
+        .bind(completed_at)
+        .bind(file_path)
+        .bind(completed_at)
+        .bind(error_message.as_deref())
+        .bind(error_code.as_deref())
+        .bind(expires_at)
+        .bind(timings.db_fetch_ms)
+        .bind(timings.db_query_ms)
+        .bind(timings.file_generation_ms)
+        .bind(completion.file_size_bytes)
+        .bind(completion.rows_exported)
+        .bind(completion.file_checksum.as_deref())
+        .bind(completion.truncated_by_limit)
+        .bind(completion.original_file_name.as_deref())
+        .bind(completion.original_size_bytes)
+        .bind(completion.is_protected)
+        .bind(completion.file_name.as_deref())
+        .bind(completion.is_encrypted)
+        .bind(completion.encryption_key_id.as_deref())
+        .bind(completion.pgp_key_fingerprint.as_deref())
+        .bind(completion.file_parts.as_ref().map(|parts| serde_json::json!(parts)))
+        .bind(request_id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to update export request final status in DB")?;
+        self.insert_status_event(
+            &mut tx,
+            request_id,
+            Some(&previous_status),
+            new_status.as_str(),
+            error_code.as_deref().or(error_message.as_deref()),
+        )
+        .await?;
+
+        tx.commit().await.context("Failed to commit final status update transaction")?;
+        info!("Final status updated successfully to '{}' for request {}.", new_status.as_str(), request_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn update_notification_sent_status(
+        &self,
+        request_id: Uuid,
+        sent: bool,
+    ) -> DbResult<()> {
+        sqlx::query("UPDATE ExportRequests SET notification_sent = ? WHERE id = ?")
+            .bind(sent)
+            .bind(request_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to update notification_sent status")?;
+        info!("Notification sent status updated to {} for request {}.", sent, request_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn mark_webhook_sent(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        sqlx::query("UPDATE ExportRequests SET webhook_sent = TRUE WHERE id = ?")
+            .bind(request_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to update webhook_sent status")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self, params))]
+    async fn query_product_data(
+        &self,
+        params: &ReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<ProductData>> {
+        info!("Querying product data with parameters: {:?}", params);
+        Self::ensure_no_tenant(params.tenant.as_deref())?;
+        let sql = format!("{} ORDER BY {} LIMIT ? OFFSET ?", self.report_sql(PRODUCT_REPORT_QUERY), params.order_by_sql());
+        let raw_data = bind_product_filters(sqlx::query_as(&sql), params)
+            .bind(params.query_limit(max_rows))
+            .bind(params.query_offset())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to query product data from database"))?;
+
+        info!("Fetched {} records for export.", raw_data.len());
+        Ok(raw_data)
+    }
+
+    #[instrument(skip(self, params))]
+    async fn count_product_data(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<i64> {
+        Self::ensure_no_tenant(params.tenant.as_deref())?;
+        let sql = self.report_sql(PRODUCT_COUNT_QUERY);
+        let (count,): (i64,) = bind_product_filters(sqlx::query_as(&sql), params)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to count product data"))?;
+        Ok(count)
+    }
+
+    #[instrument(skip(self, params))]
+    async fn query_order_data(
+        &self,
+        params: &OrderReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<OrderData>> {
+        info!("Querying order data with parameters: {:?}", params);
+        Self::ensure_no_tenant(params.tenant.as_deref())?;
+        let status = params.status.map(|status| status.as_str());
+        let (from, to) = params.utc_range();
+        let raw_data = sqlx::query_as(&self.report_sql(ORDER_REPORT_QUERY))
+            .bind(from)
+            .bind(to)
+            .bind(status)
+            .bind(status)
+            .bind(params.user_id)
+            .bind(params.user_id)
+            .bind(i64::try_from(max_rows).unwrap_or(i64::MAX - 1) + 1)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to query order data from database"))?;
+
+        info!("Fetched {} orders for export.", raw_data.len());
+        Ok(raw_data)
+    }
+
+    #[instrument(skip(self, params))]
+    async fn query_customer_data(
+        &self,
+        params: &CustomerReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<CustomerData>> {
+        info!("Querying customer data with parameters: {:?}", params);
+        Self::ensure_no_tenant(params.tenant.as_deref())?;
+        let raw_data = sqlx::query_as(&self.report_sql(CUSTOMER_REPORT_QUERY))
+            .bind(params.signup_from)
+            .bind(params.signup_to)
+            .bind(params.owner_user_id)
+            .bind(params.owner_user_id)
+            .bind(params.min_lifetime_value)
+            .bind(params.min_lifetime_value)
+            .bind(i64::try_from(max_rows).unwrap_or(i64::MAX - 1) + 1)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to query customer data from database"))?;
+
+        info!("Fetched {} customers for export.", raw_data.len());
+        Ok(raw_data)
+    }
+
+    #[instrument(skip(self, params))]
+    async fn query_category_summary(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<Vec<SalesSummaryRow>> {
+        info!("Querying category summary with parameters: {:?}", params);
+        Self::ensure_no_tenant(params.tenant.as_deref())?;
+        let sql = self.report_sql(CATEGORY_SUMMARY_QUERY);
+        let rows = bind_product_filters(sqlx::query_as(&sql), params)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| self.map_report_query_error(e, "Failed to query category summary from database"))?;
+
+        info!("Fetched {} summary rows for export.", rows.len());
+        Ok(rows)
+    }
+
+    #[instrument(skip(self))]
+    async fn claim_unsent_notifications(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        // Không có UPDATE ... RETURNING: khóa bằng SKIP LOCKED, đặt lease rồi trả về các row đã khóa
+        // trong cùng transaction.
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for notification claim")?;
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM ExportRequests
+            WHERE notification_sent = FALSE
+            AND status IN (?, ?)
+            AND completed_at < NOW() - INTERVAL 1 MINUTE
+            AND (notification_next_retry_at IS NULL OR notification_next_retry_at <= NOW())
+            ORDER BY completed_at
+            LIMIT ?
+            FOR UPDATE SKIP LOCKED
+            "#,
+            EXPORT_REQUEST_COLUMNS
+        );
+        let mut requests: Vec<ExportRequest> = sqlx::query_as(&sql)
+            .bind(ExportStatus::Completed.as_str())
+            .bind(ExportStatus::Failed.as_str())
+            .bind(limit)
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to claim unsent notifications")?;
+
+        let lease_until = self.clock.now_utc() + chrono::Duration::seconds(NOTIFICATION_CLAIM_LEASE_SECS);
+        for request in &mut requests {
+            sqlx::query("UPDATE ExportRequests SET notification_next_retry_at = ? WHERE id = ?")
+                .bind(lease_until)
+                .bind(request.id)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to lease unsent notification")?;
+            request.notification_next_retry_at = Some(lease_until);
+        }
+        tx.commit().await.context("Failed to commit notification claim")?;
+
+        info!("Claimed {} request(s) with unsent notifications.", requests.len());
+        Ok(requests)
+    }
+
+    #[instrument(skip(self))]
+    async fn record_notification_failure(
+        &self,
+        request_id: Uuid,
+        next_retry_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        sqlx::query(
+            r#"
+            UPDATE ExportRequests
+            SET
+                notification_attempts = notification_attempts + 1,
+                notification_next_retry_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(next_retry_at)
+        .bind(request_id)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record notification failure")?;
+        info!("Notification retry for request {} scheduled at {}.", request_id, next_retry_at);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn list_expired(
+        &self,
+        before: DateTime<Utc>,
+    ) -> DbResult<Vec<ExportRequest>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM ExportRequests
+            WHERE status = ?
+            AND file_path IS NOT NULL
+            AND (
+                (expires_at IS NOT NULL AND expires_at <= NOW())
+                OR (expires_at IS NULL AND completed_at < ?)
+            )
+            ORDER BY completed_at
+            "#,
+            EXPORT_REQUEST_COLUMNS
+        );
+        let requests = sqlx::query_as(&sql)
+            .bind(ExportStatus::Completed.as_str())
+            .bind(before)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to list expired export requests")?;
+
+        info!("Found {} export(s) completed before {}.", requests.len(), before);
+        Ok(requests)
+    }
+
+    #[instrument(skip(self))]
+    async fn is_file_shared(
+        &self,
+        file_path: &str,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        let shared: bool = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM ExportRequests
+                WHERE file_path = ?
+                AND id <> ?
+                AND status = ?
+                AND (expires_at IS NULL OR expires_at > NOW())
+            )
+            "#,
+        )
+        .bind(file_path)
+        .bind(request_id)
+        .bind(ExportStatus::Completed.as_str())
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to check whether the export file is shared")?;
+        Ok(shared)
+    }
+
+    #[instrument(skip(self))]
+    async fn mark_expired(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for expiry")?;
+        let previous_status = Self::lock_current_status(&mut tx, request_id).await?;
+
+        let expired_at = self.clock.now_utc();
+        sqlx::query(
+            "UPDATE ExportRequests SET status = ?, status_updated_at = ?, file_path = NULL, expired_at = ? WHERE id = ?",
+        )
+        .bind(ExportStatus::Expired.as_str())
+        .bind(expired_at)
+        .bind(expired_at)
+        .bind(request_id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to mark export request as expired")?;
+        self.insert_status_event(
+            &mut tx,
+            request_id,
+            Some(&previous_status),
+            ExportStatus::Expired.as_str(),
+            Some("file removed by retention job"),
+        )
+        .await?;
+
+        tx.commit().await.context("Failed to commit expiry transaction")?;
+        info!("Request {} marked as EXPIRED.", request_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn count_user_exports_since(
+        &self,
+        user_id: i64,
+        since: DateTime<Utc>,
+    ) -> DbResult<i64> {
+        Ok(Self::count_user_exports(&self.pool, user_id, since, None).await?)
+    }
+
+    #[instrument(skip(self))]
+    async fn record_generated_file(
+        &self,
+        request_id: Uuid,
+        file_path: &str,
+        file_checksum: &str,
+        file_size_bytes: i64,
+    ) -> DbResult<()> {
+        sqlx::query("UPDATE ExportRequests SET file_path = ?, file_checksum = ?, file_size_bytes = ? WHERE id = ?")
+            .bind(file_path)
+            .bind(file_checksum)
+            .bind(file_size_bytes)
+            .bind(request_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to record generated file metadata")?;
+        info!("Recorded generated file {} ({} bytes) for request {}.", file_path, file_size_bytes, request_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_request_history(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<Vec<ExportRequestEvent>> {
+        sqlx::query_as(
+            r#"
+            SELECT id, request_id, from_status, to_status, detail, created_at
+            FROM export_request_events
+            WHERE request_id = ?
+            UNION ALL
+            SELECT id, request_id, from_status, to_status, detail, created_at
+            FROM export_request_events_archive
+            WHERE request_id = ?
+            ORDER BY created_at, id
+            "#,
+        )
+        .bind(request_id)
+        .bind(request_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch export request history")
+    }
+
+    #[instrument(skip(self))]
+    async fn record_params_hash(
+        &self,
+        request_id: Uuid,
+        params_hash: &str,
+    ) -> DbResult<()> {
+        sqlx::query("UPDATE ExportRequests SET params_hash = ? WHERE id = ?")
+            .bind(params_hash)
+            .bind(request_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to record params hash")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn find_recent_by_params_hash(
+        &self,
+        params_hash: &str,
+        since: DateTime<Utc>,
+        exclude_request_id: Uuid,
+    ) -> DbResult<Option<ExportRequest>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM ExportRequests
+            WHERE params_hash = ?
+            AND id <> ?
+            AND status = ?
+            AND file_path IS NOT NULL
+            AND completed_at >= ?
+            AND (expires_at IS NULL OR expires_at > NOW())
+            ORDER BY completed_at DESC
+            LIMIT 1
+            "#,
+            EXPORT_REQUEST_COLUMNS
+        );
+        sqlx::query_as(&sql)
+            .bind(params_hash)
+            .bind(exclude_request_id)
+            .bind(ExportStatus::Completed.as_str())
+            .bind(since)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to look up recent request by params hash")
+    }
+
+    #[instrument(skip(self))]
+    async fn record_estimated_completion(
+        &self,
+        request_id: Uuid,
+        estimated_completion_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        sqlx::query("UPDATE ExportRequests SET estimated_completion_at = ? WHERE id = ?")
+            .bind(estimated_completion_at)
+            .bind(request_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to record estimated completion time")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn record_export_duration(
+        &self,
+        request_id: Uuid,
+        duration_ms: i64,
+        row_count: Option<i64>,
+    ) -> DbResult<()> {
+        sqlx::query("UPDATE ExportRequests SET duration_ms = ?, row_count = ? WHERE id = ?")
+            .bind(duration_ms)
+            .bind(row_count)
+            .bind(request_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to record export duration")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn recent_export_durations(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<(i64, i64)>> {
+        sqlx::query_as(
+            r#"
+            SELECT row_count, duration_ms
+            FROM ExportRequests
+            WHERE status = ?
+            AND duration_ms IS NOT NULL
+            AND row_count IS NOT NULL
+            ORDER BY completed_at DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(ExportStatus::Completed.as_str())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch recent export durations")
+    }
+
+    #[instrument(skip(self))]
+    async fn record_notify_duration(
+        &self,
+        request_id: Uuid,
+        notify_ms: i64,
+    ) -> DbResult<()> {
+        sqlx::query("UPDATE ExportRequests SET notify_ms = ? WHERE id = ?")
+            .bind(notify_ms)
+            .bind(request_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to record notification duration")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self, error))]
+    async fn record_attempt(
+        &self,
+        request_id: Uuid,
+        error: Option<&str>,
+    ) -> DbResult<()> {
+        let query = match error {
+            None => sqlx::query("UPDATE ExportRequests SET attempts = attempts + 1, last_attempt_at = ? WHERE id = ?")
+                .bind(self.clock.now_utc()),
+            Some(error) => sqlx::query("UPDATE ExportRequests SET last_error = ? WHERE id = ?").bind(error),
+        };
+        query
+            .bind(request_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to record export attempt")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn claim_next_pending(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for pending claim")?;
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM ExportRequests
+            WHERE status = ?
+            AND (next_retry_at IS NULL OR next_retry_at <= NOW())
+            ORDER BY priority DESC, requested_at
+            LIMIT ?
+            FOR UPDATE SKIP LOCKED
+            "#,
+            EXPORT_REQUEST_COLUMNS
+        );
+        let mut requests: Vec<ExportRequest> = sqlx::query_as(&sql)
+            .bind(ExportStatus::Pending.as_str())
+            .bind(limit)
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to claim pending export requests")?;
+
+        for request in &mut requests {
+            sqlx::query("UPDATE ExportRequests SET status = ?, status_updated_at = NOW(6) WHERE id = ?")
+                .bind(ExportStatus::Processing.as_str())
+                .bind(request.id)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to mark claimed request as PROCESSING")?;
+            self.insert_status_event(
+                &mut tx,
+                request.id,
+                Some(ExportStatus::Pending.as_str()),
+                ExportStatus::Processing.as_str(),
+                Some("claimed by polling"),
+            )
+            .await?;
+            request.status = ExportStatus::Processing;
+        }
+        tx.commit().await.context("Failed to commit pending claim transaction")?;
+
+        info!("Claimed {} pending request(s).", requests.len());
+        Ok(requests)
+    }
+
+    #[instrument(skip(self))]
+    async fn fetch_stuck_processing(
+        &self,
+        stuck_for: chrono::Duration,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for stuck request lookup")?;
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM ExportRequests
+            WHERE status = ?
+            AND status_updated_at < ?
+            ORDER BY status_updated_at
+            LIMIT ?
+            FOR UPDATE SKIP LOCKED
+            "#,
+            EXPORT_REQUEST_COLUMNS
+        );
+        let requests: Vec<ExportRequest> = sqlx::query_as(&sql)
+            .bind(ExportStatus::Processing.as_str())
+            .bind(self.clock.now_utc() - stuck_for)
+            .bind(limit)
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to fetch stuck PROCESSING requests")?;
+        tx.commit().await.context("Failed to finish stuck request lookup")?;
+        Ok(requests)
+    }
+
+    #[instrument(skip(self, ids))]
+    async fn reset_to_pending(
+        &self,
+        ids: &[Uuid],
+    ) -> DbResult<Vec<Uuid>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for reset to PENDING")?;
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let select_sql = format!(
+            "SELECT id FROM ExportRequests WHERE id IN ({}) AND status = ? FOR UPDATE SKIP LOCKED",
+            placeholders
+        );
+        let mut select = sqlx::query_scalar(&select_sql);
+        for id in ids {
+            select = select.bind(*id);
+        }
+        let reset_ids: Vec<Uuid> = select
+            .bind(ExportStatus::Processing.as_str())
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to lock stuck requests")?;
+
+        for id in &reset_ids {
+            sqlx::query(
+                r#"
+                UPDATE ExportRequests
+                SET
+                    status = ?,
+                    status_updated_at = ?,
+                    file_path = NULL,
+                    file_checksum = NULL,
+                    file_size_bytes = NULL,
+                    error_message = NULL,
+                    error_code = NULL,
+                    estimated_completion_at = NULL,
+                    duration_ms = NULL,
+                    row_count = NULL,
+                    truncated_by_limit = NULL,
+                    original_file_name = NULL,
+                    original_size_bytes = NULL,
+                    is_protected = NULL,
+                    file_name = NULL,
+                    is_encrypted = NULL,
+                    encryption_key_id = NULL,
+                    pgp_key_fingerprint = NULL,
+                    file_parts = NULL,
+                    webhook_sent = FALSE,
+                    db_fetch_ms = NULL,
+                    db_query_ms = NULL,
+                    file_generation_ms = NULL
+                WHERE id = ?
+                "#,
+            )
+            .bind(ExportStatus::Pending.as_str())
+            .bind(self.clock.now_utc())
+            .bind(*id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to reset stuck request to PENDING")?;
+            self.insert_status_event(
+                &mut tx,
+                *id,
+                Some(ExportStatus::Processing.as_str()),
+                ExportStatus::Pending.as_str(),
+                Some("reset after being stuck in PROCESSING"),
+            )
+            .await?;
+        }
+        tx.commit().await.context("Failed to commit reset to PENDING")?;
+
+        info!("Reset {} stuck request(s) to PENDING.", reset_ids.len());
+        Ok(reset_ids)
+    }
+
+    #[instrument(skip(self, ids, error_message), fields(requested = ids.len()))]
+    async fn bulk_update_status(
+        &self,
+        ids: &[Uuid],
+        new_status: ExportStatus,
+        error_message: Option<String>,
+        force: bool,
+    ) -> DbResult<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction for bulk status update")?;
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let select_sql = format!(
+            "SELECT id, status FROM ExportRequests WHERE id IN ({}) AND status <> ? AND (? OR status <> ?) FOR UPDATE",
+            placeholders
+        );
+        let mut select = sqlx::query_as(&select_sql);
+        for id in ids {
+            select = select.bind(*id);
+        }
+        let targets: Vec<(Uuid, String)> = select
+            .bind(new_status.as_str())
+            .bind(force)
+            .bind(ExportStatus::Completed.as_str())
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to lock requests for bulk status update")?;
+
+        if !targets.is_empty() {
+            let update_sql = format!(
+                "UPDATE ExportRequests SET status = ?, status_updated_at = ?, error_message = ? WHERE id IN ({})",
+                vec!["?"; targets.len()].join(", ")
+            );
+            let mut update = sqlx::query(&update_sql)
+                .bind(new_status.as_str())
+                .bind(self.clock.now_utc())
+                .bind(error_message.as_deref());
+            for (id, _) in &targets {
+                update = update.bind(*id);
+            }
+            update.execute(&mut *tx).await.context("Failed to bulk update export request status")?;
+            for (id, from_status) in &targets {
+                self.insert_status_event(&mut tx, *id, Some(from_status), new_status.as_str(), Some("bulk status update"))
+                    .await?;
+            }
+        }
+        tx.commit().await.context("Failed to commit bulk status update")?;
+
+        let updated = targets.len() as u64;
+        info!(
+            "Bulk status update to '{}': {} of {} request(s) updated (force: {}).",
+            new_status.as_str(),
+            updated,
+            ids.len(),
+            force
+        );
+        Ok(updated)
+    }
+
+    #[instrument(skip(self))]
+    async fn count_by_status(&self) -> DbResult<HashMap<ExportStatus, i64>> {
+        let rows: Vec<(ExportStatus, i64)> =
+            sqlx::query_as("SELECT status, COUNT(*) FROM ExportRequests GROUP BY status")
+                .fetch_all(&self.pool)
+                .await
+                .context("Failed to count export requests by status")?;
+        Ok(rows.into_iter().collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn archive_requests(
+        &self,
+        before: DateTime<Utc>,
+        batch: i64,
+    ) -> DbResult<i64> {
+        let mut total = 0;
+        loop {
+            let mut tx = self.pool.begin().await.context("Failed to begin transaction for archiving")?;
+            let ids: Vec<Uuid> = sqlx::query_scalar(
+                r#"
+                SELECT id FROM ExportRequests
+                WHERE (status IN (?, ?) OR (status = ? AND (file_path IS NULL OR expires_at < ?)))
+                AND status_updated_at < ?
+                AND notification_sent = TRUE
+                ORDER BY status_updated_at
+                LIMIT ?
+                FOR UPDATE SKIP LOCKED
+                "#,
+            )
+            .bind(ExportStatus::Failed.as_str())
+            .bind(ExportStatus::Expired.as_str())
+            .bind(ExportStatus::Completed.as_str())
+            .bind(before)
+            .bind(before)
+            .bind(batch)
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to select export requests to archive")?;
+            if ids.is_empty() {
+                tx.rollback().await?;
+                break;
+            }
+
+            let placeholders = vec!["?"; ids.len()].join(", ");
+            let insert_sql = format!(
+                "INSERT INTO export_requests_archive SELECT * FROM ExportRequests WHERE id IN ({})",
+                placeholders
+            );
+            let mut insert = sqlx::query(&insert_sql);
+            for id in &ids {
+                insert = insert.bind(*id);
+            }
+            insert.execute(&mut *tx).await.context("Failed to copy export requests to archive")?;
+
+            // Lịch sử trạng thái đi cùng request; phải xóa trước request vì khóa ngoại không còn CASCADE.
+            let insert_events_sql = format!(
+                "INSERT INTO export_request_events_archive SELECT * FROM export_request_events WHERE request_id IN ({})",
+                placeholders
+            );
+            let mut insert_events = sqlx::query(&insert_events_sql);
+            for id in &ids {
+                insert_events = insert_events.bind(*id);
+            }
+            insert_events
+                .execute(&mut *tx)
+                .await
+                .context("Failed to copy export request events to archive")?;
+
+            let delete_events_sql = format!("DELETE FROM export_request_events WHERE request_id IN ({})", placeholders);
+            let mut delete_events = sqlx::query(&delete_events_sql);
+            for id in &ids {
+                delete_events = delete_events.bind(*id);
+            }
+            delete_events
+                .execute(&mut *tx)
+                .await
+                .context("Failed to delete archived export request events")?;
+
+            let delete_sql = format!("DELETE FROM ExportRequests WHERE id IN ({})", placeholders);
+            let mut delete = sqlx::query(&delete_sql);
+            for id in &ids {
+                delete = delete.bind(*id);
+            }
+            delete.execute(&mut *tx).await.context("Failed to delete archived export requests")?;
+            tx.commit().await.context("Failed to commit archive batch")?;
+
+            total += ids.len() as i64;
+            if (ids.len() as i64) < batch {
+                break;
+            }
+        }
+
+        info!("Archived {} export request(s) last updated before {}.", total, before);
+        Ok(total)
+    }
+
+    #[instrument(skip(self))]
+    async fn list_unsent_notifications(
+        &self,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> DbResult<Vec<UnsentNotification>> {
+        // MySQL hỗ trợ so sánh row constructor nên dùng cùng cursor (completed_at, id) như Postgres.
+        sqlx::query_as(
+            r#"
+            SELECT
+                id AS request_id, user_id, status, file_path, error_message, expires_at, completed_at,
+                notification_attempts, notification_next_retry_at
+            FROM ExportRequests
+            WHERE notification_sent = FALSE
+            AND status IN (?, ?)
+            AND (
+                ? IS NULL
+                OR (completed_at, id) > (SELECT completed_at, id FROM ExportRequests WHERE id = ?)
+            )
+            ORDER BY completed_at, id
+            LIMIT ?
+            "#,
+        )
+        .bind(ExportStatus::Completed.as_str())
+        .bind(ExportStatus::Failed.as_str())
+        .bind(after)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list unsent notifications")
+    }
+}
@@ -1,6 +1,5 @@
 use anyhow::{Context, Result};
 use tracing::{error, info, instrument};
-use uuid::Uuid;
 
 use crate::models::ExportNotification;
 
@@ -9,10 +8,7 @@ use crate::models::ExportNotification;
 pub trait Notifier: Send + Sync + 'static {
     async fn send_notification(
         &self,
-        request_id: Uuid,
-        status: &str,
-        file_url: Option<String>,
-        error_message: Option<String>,
+        notification: &ExportNotification,
     ) -> Result<()>;
 }
 
@@ -33,20 +29,13 @@ impl HttpNotifier {
 
 #[async_trait::async_trait]
 impl Notifier for HttpNotifier {
-    #[instrument(skip(self), fields(request_id = %request_id))]
+    #[instrument(skip(self, notification), fields(request_id = %notification.request_id))]
     async fn send_notification(
         &self,
-        request_id: Uuid,
-        status: &str,
-        file_url: Option<String>,
-        error_message: Option<String>,
+        notification: &ExportNotification,
     ) -> Result<()> {
-        let notification = ExportNotification {
-            request_id,
-            status: status.to_string(),
-            file_url,
-            error_message,
-        };
+        let request_id = notification.request_id;
+        let status = &notification.status;
 
         info!(
             "Attempting to send notification to {} for request {} with status '{}'. Payload: {:?}",
@@ -55,7 +44,7 @@ impl Notifier for HttpNotifier {
 
         let response = self.client
             .post(&self.notification_service_url)
-            .json(&notification)
+            .json(notification)
             .send()
             .await
             .context("Failed to send notification HTTP request")?;
@@ -81,4 +70,67 @@ impl Notifier for HttpNotifier {
             ))
         }
     }
+}
+
+/// Notifier giả lập cho test: ghi lại thân JSON của mọi thông báo theo thứ tự gửi. `delay` làm chậm các thông báo
+/// của một giai đoạn, để test thấy được thông báo nào có thể tới sau thông báo khác.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingNotifier {
+    sent: std::sync::Mutex<Vec<(uuid::Uuid, crate::models::NotificationStage, serde_json::Value)>>,
+    delay: Option<(crate::models::NotificationStage, std::time::Duration)>,
+}
+
+#[cfg(test)]
+impl RecordingNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_delay(stage: crate::models::NotificationStage, delay: std::time::Duration) -> Self {
+        Self { delay: Some((stage, delay)), ..Self::default() }
+    }
+
+    /// (request_id, stage) của các thông báo đã gửi, theo thứ tự.
+    pub fn stages(&self) -> Vec<(uuid::Uuid, crate::models::NotificationStage)> {
+        self.sent.lock().unwrap().iter().map(|(request_id, stage, _)| (*request_id, *stage)).collect()
+    }
+
+    /// Trạng thái của các thông báo đã gửi cho `request_id`, theo thứ tự.
+    pub fn statuses(&self, request_id: uuid::Uuid) -> Vec<String> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _, _)| *id == request_id)
+            .map(|(_, _, body)| body["status"].as_str().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    /// Thân JSON (như gửi tới notification service) của thông báo cuối cùng gửi cho `request_id`.
+    pub fn last_notification(&self, request_id: uuid::Uuid) -> Option<serde_json::Value> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|(id, _, _)| *id == request_id)
+            .map(|(_, _, body)| body.clone())
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Notifier for RecordingNotifier {
+    async fn send_notification(
+        &self,
+        notification: &ExportNotification,
+    ) -> Result<()> {
+        if let Some((_, delay)) = self.delay.filter(|(stage, _)| *stage == notification.stage) {
+            tokio::time::sleep(delay).await;
+        }
+        let body = serde_json::to_value(notification)?;
+        self.sent.lock().unwrap().push((notification.request_id, notification.stage, body));
+        Ok(())
+    }
 }
\ No newline at end of file
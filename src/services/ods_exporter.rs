@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use chrono::NaiveTime;
+use spreadsheet_ods::color::Rgb;
+use spreadsheet_ods::{
+    write_ods, CellStyle, CellStyleRef, Sheet, Value, ValueFormatDateTime, ValueFormatNumber, WorkBook,
+};
+use tracing::info;
+
+use crate::models::{CellValue, ColumnFormat, ExcelStyleOptions, FormulaEscape, ReportData};
+use crate::services::file_exporter::sheet_name;
+
+/// Ghi dữ liệu report ra file OpenDocument Spreadsheet (bật bằng cargo feature `ods`).
+/// Cùng layout cột, cách tách sheet và màu header với file Excel. Định dạng số và ngày giờ cố định
+/// (`EXCEL_*_FORMAT` là mã định dạng của Excel, không dùng được cho ODS).
+#[derive(Debug, Clone)]
+pub struct OdsFileExporter {
+    style: ExcelStyleOptions,
+    escape: FormulaEscape,
+    max_rows_per_sheet: u32,
+}
+
+impl OdsFileExporter {
+    pub fn new(style: ExcelStyleOptions, escape: FormulaEscape, max_rows_per_sheet: u32) -> Self {
+        Self { style, escape, max_rows_per_sheet }
+    }
+
+    /// Ghi `data` vào `path`, trả về số sheet. Toàn bộ workbook được giữ trong bộ nhớ cho tới khi lưu.
+    pub fn write(&self, path: &str, data: &ReportData) -> Result<u32> {
+        let mut workbook = WorkBook::new_empty();
+        let styles = OdsStyles::new(&mut workbook, &self.style);
+        let layout = data.layout();
+        let column_styles: Vec<Option<&CellStyleRef>> =
+            layout.columns.iter().map(|column| styles.for_format(column.format)).collect();
+
+        let mut sheet_index = 0;
+        let mut sheet = new_sheet(sheet_index, &layout.headers(), styles.header.as_ref());
+        let mut row_num: u32 = 1;
+        for row in data.escaped_rows(self.escape) {
+            let row = row?;
+            if row_num > self.max_rows_per_sheet {
+                workbook.push_sheet(sheet);
+                sheet_index += 1;
+                sheet = new_sheet(sheet_index, &layout.headers(), styles.header.as_ref());
+                row_num = 1;
+            }
+            for (col_num, (value, column_style)) in row.into_iter().zip(&column_styles).enumerate() {
+                let value = ods_value(value);
+                match column_style {
+                    Some(style) => sheet.set_styled_value(row_num, col_num as u32, value, style),
+                    None => sheet.set_value(row_num, col_num as u32, value),
+                }
+            }
+            row_num += 1;
+        }
+        workbook.push_sheet(sheet);
+
+        write_ods(&mut workbook, path).context("Failed to save ODS file")?;
+        let sheet_count = sheet_index + 1;
+        info!("✅ ODS file successfully created at: {} ({} sheet(s))", path, sheet_count);
+        Ok(sheet_count)
+    }
+}
+
+/// Style của dòng header và của các cột có định dạng số/ngày giờ.
+struct OdsStyles {
+    header: Option<CellStyleRef>,
+    integer: CellStyleRef,
+    decimal: CellStyleRef,
+    date: CellStyleRef,
+    datetime: CellStyleRef,
+}
+
+impl OdsStyles {
+    fn new(workbook: &mut WorkBook, style: &ExcelStyleOptions) -> Self {
+        let header = style.style_header.then(|| {
+            let mut header = CellStyle::new_empty();
+            header.set_font_bold();
+            header.set_color(hex_rgb(&style.header_font_color));
+            header.set_background_color(hex_rgb(&style.header_background_color));
+            workbook.add_cellstyle(header)
+        });
+
+        let mut integer = ValueFormatNumber::new_named("integer");
+        integer.part_number().decimal_places(0).build();
+        let integer = workbook.add_number_format(integer);
+
+        let mut decimal = ValueFormatNumber::new_named("decimal");
+        decimal.part_number().decimal_places(2).min_decimal_places(2).grouping().build();
+        let decimal = workbook.add_number_format(decimal);
+
+        let mut date = ValueFormatDateTime::new_named("date");
+        date.part_year().long_style().build();
+        date.part_text("-").build();
+        date.part_month().long_style().build();
+        date.part_text("-").build();
+        date.part_day().long_style().build();
+        let date = workbook.add_datetime_format(date);
+
+        let mut datetime = ValueFormatDateTime::new_named("datetime");
+        datetime.part_year().long_style().build();
+        datetime.part_text("-").build();
+        datetime.part_month().long_style().build();
+        datetime.part_text("-").build();
+        datetime.part_day().long_style().build();
+        datetime.part_text(" ").build();
+        datetime.part_hours().long_style().build();
+        datetime.part_text(":").build();
+        datetime.part_minutes().long_style().build();
+        datetime.part_text(":").build();
+        datetime.part_seconds().long_style().build();
+        let datetime = workbook.add_datetime_format(datetime);
+
+        Self {
+            header,
+            integer: workbook.add_cellstyle(CellStyle::new("integer", &integer.into())),
+            decimal: workbook.add_cellstyle(CellStyle::new("decimal", &decimal.into())),
+            date: workbook.add_cellstyle(CellStyle::new("date", &date.into())),
+            datetime: workbook.add_cellstyle(CellStyle::new("datetime", &datetime.into())),
+        }
+    }
+
+    fn for_format(&self, format: ColumnFormat) -> Option<&CellStyleRef> {
+        match format {
+            ColumnFormat::General => None,
+            ColumnFormat::Integer => Some(&self.integer),
+            ColumnFormat::Decimal => Some(&self.decimal),
+            ColumnFormat::Date => Some(&self.date),
+            ColumnFormat::DateTime => Some(&self.datetime),
+        }
+    }
+}
+
+fn new_sheet(index: u32, headers: &[&str], header_style: Option<&CellStyleRef>) -> Sheet {
+    let mut sheet = Sheet::new(sheet_name(index));
+    for (col_num, header) in headers.iter().enumerate() {
+        match header_style {
+            Some(style) => sheet.set_styled_value(0, col_num as u32, *header, style),
+            None => sheet.set_value(0, col_num as u32, *header),
+        }
+    }
+    sheet
+}
+
+/// Ô số và ngày giờ giữ kiểu của ODS (`float`, `date`), ngày giờ theo UTC.
+fn ods_value(value: CellValue) -> Value {
+    match value {
+        CellValue::Number(number) => Value::Number(number),
+        CellValue::Text(text) | CellValue::Link { text, .. } => Value::Text(text),
+        CellValue::DateTime(value) => Value::DateTime(value.naive_local()),
+        CellValue::Date(date) => Value::DateTime(date.and_time(NaiveTime::MIN)),
+    }
+}
+
+/// Màu `#RRGGBB` (đã được kiểm tra khi đọc config/payload).
+fn hex_rgb(color: &str) -> Rgb<u8> {
+    let channel = |range: std::ops::Range<usize>| u8::from_str_radix(&color[range], 16).unwrap_or_default();
+    Rgb::new(channel(1..3), channel(3..5), channel(5..7))
+}
@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use arrow::array::{
+    ArrayRef, Date32Builder, Float64Builder, Int64Builder, StringBuilder, TimestampMicrosecondBuilder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use chrono::{Datelike, NaiveDate, NaiveTime};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::models::{CellValue, ColumnFormat, ParquetCompression, ParquetOptions, ReportData};
+
+/// Múi giờ của cột timestamp: mọi thời điểm trong service đều là UTC.
+const TIMESTAMP_TIMEZONE: &str = "UTC";
+
+/// Ghi dữ liệu report ra file Parquet (bật bằng cargo feature `parquet`).
+/// Schema Arrow lấy từ layout cột của report, nên dataset mới không cần thêm mapping riêng.
+#[derive(Debug, Clone, Copy)]
+pub struct ParquetFileExporter {
+    options: ParquetOptions,
+}
+
+impl ParquetFileExporter {
+    pub fn new(options: ParquetOptions) -> Self {
+        Self { options }
+    }
+
+    /// Ghi `data` vào `path`. Mỗi row group (tối đa `row_group_size` dòng) được build thành một RecordBatch
+    /// và ghi xong trước khi sang row group tiếp theo, nên chỉ một row group dạng cột nằm trong bộ nhớ.
+    /// Trả về số row group đã ghi.
+    pub fn write(&self, path: &str, data: &ReportData) -> Result<usize> {
+        let layout = data.layout();
+        let first_row = data.rows().next();
+        let kinds: Vec<ColumnKind> = layout
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| ColumnKind::new(column.format, first_row.as_ref().and_then(|row| row.get(i))))
+            .collect();
+        let schema: SchemaRef = Arc::new(Schema::new(
+            layout
+                .columns
+                .iter()
+                .zip(&kinds)
+                .map(|(column, kind)| Field::new(column.header.as_ref(), kind.data_type(), true))
+                .collect::<Vec<_>>(),
+        ));
+
+        let compression = match self.options.compression {
+            ParquetCompression::None => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        };
+        let properties = WriterProperties::builder()
+            .set_compression(compression)
+            .set_max_row_group_size(self.options.row_group_size)
+            .build();
+        let file = std::fs::File::create(path).context("Failed to create Parquet file")?;
+        let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))
+            .context("Failed to create Parquet writer")?;
+
+        let mut builders = new_builders(&kinds, self.options.row_group_size);
+        let mut buffered_rows = 0;
+        let mut row_groups = 0;
+        for row in data.rows() {
+            for (builder, value) in builders.iter_mut().zip(&row) {
+                builder.append(value);
+            }
+            buffered_rows += 1;
+            if buffered_rows == self.options.row_group_size {
+                write_batch(&mut writer, &schema, &mut builders)?;
+                buffered_rows = 0;
+                row_groups += 1;
+            }
+        }
+        // Row group cuối (hoặc file chỉ có schema khi không có dòng nào).
+        if buffered_rows > 0 {
+            write_batch(&mut writer, &schema, &mut builders)?;
+            row_groups += 1;
+        }
+        writer.close().context("Failed to finish Parquet file")?;
+
+        info!("✅ Parquet file successfully created at: {} ({} row group(s))", path, row_groups);
+        Ok(row_groups)
+    }
+}
+
+fn new_builders(kinds: &[ColumnKind], capacity: usize) -> Vec<ColumnBuilder> {
+    kinds.iter().map(|kind| ColumnBuilder::new(*kind, capacity)).collect()
+}
+
+fn write_batch(writer: &mut ArrowWriter<std::fs::File>, schema: &SchemaRef, builders: &mut [ColumnBuilder]) -> Result<()> {
+    let columns = builders.iter_mut().map(ColumnBuilder::finish).collect();
+    let batch = RecordBatch::try_new(schema.clone(), columns).context("Failed to build Parquet record batch")?;
+    writer.write(&batch).context("Failed to write Parquet row group")?;
+    // Đóng row group ngay để bộ đệm của writer không giữ lại dữ liệu.
+    writer.flush().context("Failed to flush Parquet row group")
+}
+
+/// Kiểu Arrow của một cột.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnKind {
+    Int64,
+    Float64,
+    Utf8,
+    Date,
+    Timestamp,
+}
+
+impl ColumnKind {
+    /// Cột có định dạng khai báo dùng kiểu tương ứng; cột `General` (ví dụ cột của dataset)
+    /// lấy kiểu theo giá trị ở dòng đầu tiên, mặc định là chuỗi.
+    fn new(format: ColumnFormat, first_value: Option<&CellValue>) -> Self {
+        match format {
+            ColumnFormat::Integer => ColumnKind::Int64,
+            ColumnFormat::Decimal => ColumnKind::Float64,
+            ColumnFormat::Date => ColumnKind::Date,
+            ColumnFormat::DateTime => ColumnKind::Timestamp,
+            ColumnFormat::General => match first_value {
+                Some(CellValue::Number(_)) => ColumnKind::Float64,
+                Some(CellValue::Date(_)) => ColumnKind::Date,
+                Some(CellValue::DateTime(_)) => ColumnKind::Timestamp,
+                Some(CellValue::Text(_) | CellValue::Link { .. }) | None => ColumnKind::Utf8,
+            },
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            ColumnKind::Int64 => DataType::Int64,
+            ColumnKind::Float64 => DataType::Float64,
+            ColumnKind::Utf8 => DataType::Utf8,
+            ColumnKind::Date => DataType::Date32,
+            ColumnKind::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, Some(TIMESTAMP_TIMEZONE.into())),
+        }
+    }
+}
+
+/// Builder của một cột trong row group đang ghi.
+enum ColumnBuilder {
+    Int64(Int64Builder),
+    Float64(Float64Builder),
+    Utf8(StringBuilder),
+    Date(Date32Builder),
+    Timestamp(TimestampMicrosecondBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(kind: ColumnKind, capacity: usize) -> Self {
+        match kind {
+            ColumnKind::Int64 => ColumnBuilder::Int64(Int64Builder::with_capacity(capacity)),
+            ColumnKind::Float64 => ColumnBuilder::Float64(Float64Builder::with_capacity(capacity)),
+            ColumnKind::Utf8 => ColumnBuilder::Utf8(StringBuilder::new()),
+            ColumnKind::Date => ColumnBuilder::Date(Date32Builder::with_capacity(capacity)),
+            ColumnKind::Timestamp => ColumnBuilder::Timestamp(
+                TimestampMicrosecondBuilder::with_capacity(capacity).with_timezone(TIMESTAMP_TIMEZONE),
+            ),
+        }
+    }
+
+    /// Ô không khớp kiểu của cột (ví dụ ô trống của dataset) được ghi là null.
+    fn append(&mut self, value: &CellValue) {
+        match (self, value) {
+            (ColumnBuilder::Utf8(builder), value) => builder.append_value(value.to_string()),
+            (ColumnBuilder::Int64(builder), CellValue::Number(number)) => builder.append_value(*number as i64),
+            (ColumnBuilder::Float64(builder), CellValue::Number(number)) => builder.append_value(*number),
+            (ColumnBuilder::Date(builder), CellValue::Date(date)) => builder.append_value(days_since_epoch(*date)),
+            (ColumnBuilder::Date(builder), CellValue::DateTime(value)) => {
+                builder.append_value(days_since_epoch(value.date_naive()))
+            }
+            (ColumnBuilder::Timestamp(builder), CellValue::DateTime(value)) => builder.append_value(value.timestamp_micros()),
+            (ColumnBuilder::Timestamp(builder), CellValue::Date(date)) => {
+                builder.append_value(date.and_time(NaiveTime::MIN).and_utc().timestamp_micros())
+            }
+            (ColumnBuilder::Int64(builder), _) => builder.append_null(),
+            (ColumnBuilder::Float64(builder), _) => builder.append_null(),
+            (ColumnBuilder::Date(builder), _) => builder.append_null(),
+            (ColumnBuilder::Timestamp(builder), _) => builder.append_null(),
+        }
+    }
+
+    /// Lấy mảng của row group và làm rỗng builder cho row group tiếp theo.
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Int64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Utf8(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Date(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Timestamp(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// Số ngày từ 0001-01-01 (CE) tới 1970-01-01: Date32 của Arrow đếm ngày từ 1970-01-01.
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+fn days_since_epoch(date: NaiveDate) -> i32 {
+    date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE
+}
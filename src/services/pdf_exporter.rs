@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use genpdf::elements::{Break, FrameCellDecorator, Paragraph, TableLayout};
+use genpdf::style::{Style, StyledString};
+use genpdf::{Alignment, Document, Element, PaperSize, SimplePageDecorator};
+use tracing::info;
+
+use crate::models::{CellValue, ColumnFormat, ReportData};
+use crate::services::file_exporter::PdfReport;
+
+/// Lề trang (mm).
+const PAGE_MARGIN_MM: i32 = 15;
+
+/// Render report tổng hợp thành file PDF khổ A4 (bật bằng cargo feature `pdf`): tiêu đề, tóm tắt tham số,
+/// bảng theo layout cột của report và thông tin tạo file. Bảng tự sang trang khi không đủ chỗ,
+/// mỗi trang có số trang ở đầu trang.
+#[derive(Debug, Clone)]
+pub struct PdfExporter {
+    font_dir: String,
+    font_family: String,
+}
+
+impl PdfExporter {
+    pub fn new(font_dir: String, font_family: String) -> Self {
+        Self { font_dir, font_family }
+    }
+
+    pub fn write(&self, path: &str, data: &ReportData, report: &PdfReport) -> Result<()> {
+        let font_family = genpdf::fonts::from_files(&self.font_dir, &self.font_family, None)
+            .with_context(|| format!("Failed to load PDF font '{}' from {}", self.font_family, self.font_dir))?;
+        let mut document = Document::new(font_family);
+        document.set_title(report.title.clone());
+        document.set_paper_size(PaperSize::A4);
+        document.set_font_size(10);
+
+        let mut decorator = SimplePageDecorator::new();
+        decorator.set_margins(PAGE_MARGIN_MM);
+        decorator.set_header(|page| {
+            Paragraph::new(format!("Page {}", page))
+                .aligned(Alignment::Right)
+                .styled(Style::new().with_font_size(8))
+        });
+        document.set_page_decorator(decorator);
+
+        document.push(Paragraph::new(StyledString::new(report.title.clone(), Style::new().bold().with_font_size(16))));
+        document.push(Break::new(1));
+        for (label, value) in &report.parameters {
+            document.push(Paragraph::new(format!("{}: {}", label, value)));
+        }
+        document.push(Break::new(1));
+
+        let layout = data.layout();
+        // Cột chữ đầu tiên (tên category) rộng gấp đôi các cột số.
+        let weights = layout
+            .columns
+            .iter()
+            .map(|column| if column.format == ColumnFormat::General { 2 } else { 1 })
+            .collect();
+        let mut table = TableLayout::new(weights);
+        table.set_cell_decorator(FrameCellDecorator::new(true, true, false));
+        let mut header = table.row();
+        for column in &layout.columns {
+            header.push_element(
+                Paragraph::new(StyledString::new(column.header.to_string(), Style::new().bold())).padded(1),
+            );
+        }
+        header.push().context("Failed to add PDF table header")?;
+        for row in data.rows() {
+            let mut table_row = table.row();
+            for (value, column) in row.iter().zip(&layout.columns) {
+                let alignment = if matches!(value, CellValue::Number(_)) { Alignment::Right } else { Alignment::Left };
+                table_row.push_element(Paragraph::new(cell_text(value, column.format)).aligned(alignment).padded(1));
+            }
+            table_row.push().context("Failed to add PDF table row")?;
+        }
+        document.push(table);
+
+        document.push(Break::new(1));
+        for (label, value) in &report.metadata {
+            document.push(Paragraph::new(format!("{}: {}", label, value)).styled(Style::new().with_font_size(8)));
+        }
+
+        document.render_to_file(path).context("Failed to render PDF file")?;
+        info!("✅ PDF file successfully created at: {}", path);
+        Ok(())
+    }
+}
+
+/// Giá trị hiển thị của ô: cột số thập phân giữ hai chữ số, ngày giờ không kèm múi giờ (luôn là UTC).
+fn cell_text(value: &CellValue, format: ColumnFormat) -> String {
+    match (value, format) {
+        (CellValue::Number(number), ColumnFormat::Decimal) => format!("{:.2}", number),
+        (CellValue::DateTime(value), _) => value.format("%Y-%m-%d %H:%M:%S").to_string(),
+        (value, _) => value.to_string(),
+    }
+}
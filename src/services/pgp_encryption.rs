@@ -0,0 +1,280 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::PgpKeySource;
+
+/// Phần mở rộng thêm vào tên file đã mã hóa PGP (`{request_id}.xlsx.gpg`).
+pub const PGP_FILE_EXTENSION: &str = "gpg";
+
+/// Mã hóa file export tới khóa công khai PGP của người nhận (bật bằng cargo feature `pgp`): chỉ người giữ
+/// private key đọc được file, kể cả khi file nằm trên storage của service. Khóa của mọi người nhận
+/// (PGP_RECIPIENT_KEYS) được đọc và kiểm tra một lần khi khởi động.
+#[derive(Clone)]
+pub struct PgpEncryption {
+    recipients: Arc<HashMap<String, PgpRecipient>>,
+}
+
+#[cfg_attr(not(feature = "pgp"), allow(dead_code))]
+struct PgpRecipient {
+    /// Fingerprint của primary key, được lưu cùng request.
+    fingerprint: String,
+    #[cfg(feature = "pgp")]
+    cert: sequoia_openpgp::Cert,
+}
+
+impl PgpEncryption {
+    /// Khóa không đọc được, là secret key hoặc không còn subkey mã hóa hợp lệ đều làm service dừng khi khởi động.
+    pub fn load(keys: &HashMap<String, PgpKeySource>) -> Result<Self> {
+        let recipients = keys
+            .iter()
+            .map(|(key_id, source)| {
+                let recipient =
+                    load_recipient(source).with_context(|| format!("PGP_RECIPIENT_KEYS entry '{}' is invalid", key_id))?;
+                Ok((key_id.clone(), recipient))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        Ok(Self { recipients: Arc::new(recipients) })
+    }
+
+    /// Fingerprint khóa của người nhận; `None` khi key id không có trong PGP_RECIPIENT_KEYS.
+    pub fn fingerprint(&self, recipient_key_id: &str) -> Option<&str> {
+        self.recipients
+            .get(recipient_key_id)
+            .map(|recipient| recipient.fingerprint.as_str())
+    }
+
+    /// Mã hóa `source` tới người nhận vào `destination` (OpenPGP nhị phân, tên file gốc được ghi trong literal data),
+    /// trả về fingerprint khóa đã dùng. Hàm đồng bộ: gọi trong thread blocking.
+    pub fn encrypt_file(&self, recipient_key_id: &str, source: &str, destination: &str) -> Result<String> {
+        let recipient = self
+            .recipients
+            .get(recipient_key_id)
+            .with_context(|| format!("Unknown PGP recipient key id '{}'", recipient_key_id))?;
+        encrypt_to(recipient, source, destination)?;
+        Ok(recipient.fingerprint.clone())
+    }
+}
+
+#[cfg(feature = "pgp")]
+fn load_recipient(source: &PgpKeySource) -> Result<PgpRecipient> {
+    use sequoia_openpgp::parse::Parse;
+    use sequoia_openpgp::policy::StandardPolicy;
+    use sequoia_openpgp::Cert;
+
+    let cert = match source {
+        PgpKeySource::Path(path) => {
+            Cert::from_file(path).with_context(|| format!("Failed to read PGP public key {}", path))?
+        }
+        PgpKeySource::Armored(armored) => {
+            Cert::from_bytes(armored.as_bytes()).context("Failed to parse armored PGP public key")?
+        }
+    };
+    anyhow::ensure!(
+        !cert.is_tsk(),
+        "PGP key {} contains secret key material; configure the recipient's public key only",
+        cert.fingerprint()
+    );
+    let policy = StandardPolicy::new();
+    let usable = cert
+        .keys()
+        .with_policy(&policy, None)
+        .supported()
+        .alive()
+        .revoked(false)
+        .for_storage_encryption()
+        .for_transport_encryption()
+        .next()
+        .is_some();
+    anyhow::ensure!(usable, "PGP key {} has no valid encryption subkey (missing, expired or revoked)", cert.fingerprint());
+    Ok(PgpRecipient { fingerprint: cert.fingerprint().to_hex(), cert })
+}
+
+#[cfg(not(feature = "pgp"))]
+fn load_recipient(_source: &PgpKeySource) -> Result<PgpRecipient> {
+    anyhow::bail!("PGP encryption is not supported by this build: enable the `pgp` feature")
+}
+
+/// Subkey hết hạn hoặc bị thu hồi sau khi khởi động làm request thất bại thay vì mã hóa tới khóa không còn hợp lệ.
+#[cfg(feature = "pgp")]
+fn encrypt_to(recipient: &PgpRecipient, source: &str, destination: &str) -> Result<()> {
+    use sequoia_openpgp::policy::StandardPolicy;
+    use sequoia_openpgp::serialize::stream::{Encryptor2, LiteralWriter, Message};
+    use std::io::{BufReader, BufWriter};
+    use std::path::Path;
+
+    let policy = StandardPolicy::new();
+    let keys: Vec<_> = recipient
+        .cert
+        .keys()
+        .with_policy(&policy, None)
+        .supported()
+        .alive()
+        .revoked(false)
+        .for_storage_encryption()
+        .for_transport_encryption()
+        .collect();
+    anyhow::ensure!(!keys.is_empty(), "PGP key {} has no valid encryption subkey (expired or revoked)", recipient.fingerprint);
+
+    let file_name = Path::new(source)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("Export file has no file name")?;
+    let mut input = BufReader::new(std::fs::File::open(source).context("Failed to open file to encrypt")?);
+    let mut output = BufWriter::new(std::fs::File::create(destination).context("Failed to create PGP encrypted file")?);
+    let message = Message::new(&mut output);
+    let message = Encryptor2::for_recipients(message, keys)
+        .build()
+        .context("Failed to start PGP encryption")?;
+    let mut message = LiteralWriter::new(message)
+        .filename(file_name)?
+        .build()
+        .context("Failed to start PGP literal data")?;
+    std::io::copy(&mut input, &mut message).context("Failed to write PGP encrypted file")?;
+    message.finalize().context("Failed to finish PGP encrypted file")?;
+    let output = output.into_inner().map_err(|e| e.into_error()).context("Failed to flush PGP encrypted file")?;
+    output.sync_all().context("Failed to sync PGP encrypted file")?;
+    Ok(())
+}
+
+#[cfg(not(feature = "pgp"))]
+fn encrypt_to(_recipient: &PgpRecipient, _source: &str, _destination: &str) -> Result<()> {
+    anyhow::bail!("PGP encryption is not supported by this build: enable the `pgp` feature")
+}
+
+#[cfg(all(test, feature = "pgp"))]
+mod tests {
+    use super::*;
+    use sequoia_openpgp::cert::CertBuilder;
+    use sequoia_openpgp::crypto::SessionKey;
+    use sequoia_openpgp::packet::{PKESK, SKESK};
+    use sequoia_openpgp::parse::stream::{DecryptionHelper, DecryptorBuilder, MessageStructure, VerificationHelper};
+    use sequoia_openpgp::parse::Parse;
+    use sequoia_openpgp::policy::StandardPolicy;
+    use sequoia_openpgp::serialize::SerializeInto;
+    use sequoia_openpgp::types::SymmetricAlgorithm;
+    use sequoia_openpgp::{Cert, Fingerprint, KeyHandle};
+    use std::io::Read;
+    use uuid::Uuid;
+
+    /// Giải mã bằng secret key của người nhận, như phía đối tác.
+    struct Recipient {
+        secret: Cert,
+    }
+
+    impl VerificationHelper for Recipient {
+        fn get_certs(&mut self, _ids: &[KeyHandle]) -> sequoia_openpgp::Result<Vec<Cert>> {
+            Ok(Vec::new())
+        }
+
+        fn check(&mut self, _structure: MessageStructure) -> sequoia_openpgp::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl DecryptionHelper for Recipient {
+        fn decrypt<D>(
+            &mut self,
+            pkesks: &[PKESK],
+            _skesks: &[SKESK],
+            sym_algo: Option<SymmetricAlgorithm>,
+            mut decrypt: D,
+        ) -> sequoia_openpgp::Result<Option<Fingerprint>>
+        where
+            D: FnMut(SymmetricAlgorithm, &SessionKey) -> bool,
+        {
+            let policy = StandardPolicy::new();
+            let key = self
+                .secret
+                .keys()
+                .unencrypted_secret()
+                .with_policy(&policy, None)
+                .for_transport_encryption()
+                .next()
+                .expect("encryption subkey")
+                .key()
+                .clone();
+            let mut pair = key.into_keypair()?;
+            for pkesk in pkesks {
+                if let Some((algo, session_key)) = pkesk.decrypt(&mut pair, sym_algo) {
+                    if decrypt(algo, &session_key) {
+                        return Ok(Some(self.secret.fingerprint()));
+                    }
+                }
+            }
+            anyhow::bail!("no PKESK for the recipient's key")
+        }
+    }
+
+    fn generate_key() -> Cert {
+        let (cert, _revocation) = CertBuilder::general_purpose(None, Some("Partner <partner@example.com>"))
+            .generate()
+            .unwrap();
+        cert
+    }
+
+    fn armored(bytes: Vec<u8>) -> PgpKeySource {
+        PgpKeySource::Armored(String::from_utf8(bytes).unwrap())
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("pgp-test-{}-{}", Uuid::new_v4(), name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn encrypted_file_decrypts_with_the_recipients_secret_key() {
+        let secret = generate_key();
+        let keys = HashMap::from([("acme".to_string(), armored(secret.armored().to_vec().unwrap()))]);
+        let pgp = PgpEncryption::load(&keys).unwrap();
+        let source = temp_path("report.csv");
+        let destination = format!("{}.{}", source, PGP_FILE_EXTENSION);
+        std::fs::write(&source, b"name,price\nTea,2.5\n").unwrap();
+
+        let fingerprint = pgp.encrypt_file("acme", &source, &destination).unwrap();
+        let encrypted = std::fs::read(&destination).unwrap();
+        let mut decryptor = DecryptorBuilder::from_bytes(&encrypted)
+            .unwrap()
+            .with_policy(&StandardPolicy::new(), None, Recipient { secret: secret.clone() })
+            .unwrap();
+        let mut decrypted = Vec::new();
+        decryptor.read_to_end(&mut decrypted).unwrap();
+        let _ = std::fs::remove_file(&source);
+        let _ = std::fs::remove_file(&destination);
+
+        assert_eq!(decrypted, b"name,price\nTea,2.5\n");
+        assert_eq!(fingerprint, secret.fingerprint().to_hex());
+        assert_eq!(pgp.fingerprint("acme"), Some(fingerprint.as_str()));
+        assert!(!encrypted.windows(b"Tea,2.5".len()).any(|window| window == b"Tea,2.5"));
+    }
+
+    #[test]
+    fn file_is_unreadable_with_another_key() {
+        let keys = HashMap::from([("acme".to_string(), armored(generate_key().armored().to_vec().unwrap()))]);
+        let pgp = PgpEncryption::load(&keys).unwrap();
+        let source = temp_path("report.csv");
+        let destination = format!("{}.{}", source, PGP_FILE_EXTENSION);
+        std::fs::write(&source, b"secret report").unwrap();
+
+        pgp.encrypt_file("acme", &source, &destination).unwrap();
+        let encrypted = std::fs::read(&destination).unwrap();
+        let _ = std::fs::remove_file(&source);
+        let _ = std::fs::remove_file(&destination);
+
+        let other = Recipient { secret: generate_key() };
+        assert!(DecryptorBuilder::from_bytes(&encrypted).unwrap().with_policy(&StandardPolicy::new(), None, other).is_err());
+    }
+
+    #[test]
+    fn secret_keys_and_unknown_recipients_are_rejected() {
+        let secret = generate_key();
+        let keys = HashMap::from([("acme".to_string(), armored(secret.as_tsk().armored().to_vec().unwrap()))]);
+        let error = PgpEncryption::load(&keys).err().unwrap();
+        assert!(format!("{:#}", error).contains("contains secret key material"));
+
+        let keys = HashMap::from([("acme".to_string(), armored(secret.armored().to_vec().unwrap()))]);
+        let pgp = PgpEncryption::load(&keys).unwrap();
+        assert_eq!(pgp.fingerprint("other"), None);
+        let error = pgp.encrypt_file("other", "/nonexistent", "/nonexistent.gpg").unwrap_err();
+        assert_eq!(error.to_string(), "Unknown PGP recipient key id 'other'");
+    }
+}
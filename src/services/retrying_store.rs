@@ -0,0 +1,505 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use metrics::increment;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::config::{DatasetParam, DbRetrySettings, ExportQuota};
+use crate::models::{
+    CustomerData, CustomerReportParams, DatasetRows, DatasetValue, ExportCompletion, ExportRequest, ExportRequestEvent,
+    ExportStatus, ExportTimings, OrderData, OrderReportParams, ProductData, ReportParams, SalesSummaryRow, UnsentNotification,
+};
+use crate::services::db_store::{DbResult, DbStore};
+
+/// DbStore bọc một store khác và thử lại các lỗi tạm thời (`DbError::Transient`: mất kết nối, failover,
+/// serialization failure, deadlock) với exponential backoff và jitter.
+///
+/// Chỉ các thao tác an toàn khi chạy lại mới được thử lại. `fetch_and_update_request_status` mỗi lần chạy
+/// là một transaction mới và kiểm tra lại trạng thái của request, nên lần thử lại sẽ trả về `AlreadyFinal`
+/// nếu request đã kết thúc. Các thao tác tăng bộ đếm hoặc claim request (`record_attempt`,
+/// `record_notification_failure`, `claim_next_pending`) không được thử lại, vì lần trước có thể đã commit.
+pub struct RetryingDbStore<D> {
+    inner: D,
+    settings: DbRetrySettings,
+}
+
+impl<D: DbStore> RetryingDbStore<D> {
+    pub fn new(inner: D, settings: DbRetrySettings) -> Self {
+        Self { inner, settings }
+    }
+
+    async fn retry<T, F, Fut>(&self, operation: &'static str, mut run: F) -> DbResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = DbResult<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match run().await {
+                Err(e) if e.is_transient() && attempt < self.settings.max_attempts => {
+                    let delay = self.backoff(attempt);
+                    warn!(
+                        "Transient database error in {} (attempt {}/{}): {}. Retrying in {:?}.",
+                        operation, attempt, self.settings.max_attempts, e, delay
+                    );
+                    increment!("excel_export_db_retried_total", "operation" => operation);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// base * 2^(attempt - 1), chọn ngẫu nhiên trong nửa trên của khoảng để các replica không thử lại cùng lúc.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = (attempt - 1).min(16);
+        let delay_ms = self.settings.base_delay_ms.saturating_mul(1u64 << exponent);
+        let jitter_ms = Uuid::new_v4().as_u64_pair().0 % (delay_ms / 2 + 1);
+        Duration::from_millis(delay_ms - delay_ms / 2 + jitter_ms)
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: DbStore> DbStore for RetryingDbStore<D> {
+    /// Không thử lại: caller (vòng chờ lúc khởi động, probe readiness) tự quyết định khi nào ping lại.
+    async fn ping(&self) -> DbResult<()> {
+        self.inner.ping().await
+    }
+
+    async fn fetch_and_update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        quota: &ExportQuota,
+    ) -> DbResult<ExportRequest> {
+        self.retry("fetch_and_update_request_status", || {
+            self.inner.fetch_and_update_request_status(request_id, new_status, quota)
+        })
+        .await
+    }
+
+    /// Thử lại được: lần lấy khóa bị lỗi đã bỏ connection giữ khóa, lần sau mở connection mới.
+    /// Nếu vẫn lỗi, service không xử lý request (xem `ExportService::lock_request`).
+    async fn try_lock_request(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        self.retry("try_lock_request", || self.inner.try_lock_request(request_id)).await
+    }
+
+    async fn unlock_request(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.inner.unlock_request(request_id).await
+    }
+
+    async fn update_request_status(
+        &self,
+        request_id: Uuid,
+        new_status: ExportStatus,
+        file_path: Option<String>,
+        error_message: Option<String>,
+        error_code: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+        timings: &ExportTimings,
+        completion: &ExportCompletion,
+    ) -> DbResult<()> {
+        self.retry("update_request_status", || {
+            self.inner.update_request_status(
+                request_id,
+                new_status,
+                file_path.clone(),
+                error_message.clone(),
+                error_code.clone(),
+                expires_at,
+                timings,
+                completion,
+            )
+        })
+        .await
+    }
+
+    async fn update_notification_sent_status(
+        &self,
+        request_id: Uuid,
+        sent: bool,
+    ) -> DbResult<()> {
+        self.retry("update_notification_sent_status", || {
+            self.inner.update_notification_sent_status(request_id, sent)
+        })
+        .await
+    }
+
+    async fn mark_webhook_sent(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.retry("mark_webhook_sent", || self.inner.mark_webhook_sent(request_id)).await
+    }
+
+    async fn query_product_data(
+        &self,
+        params: &ReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<ProductData>> {
+        self.retry("query_product_data", || self.inner.query_product_data(params, max_rows)).await
+    }
+
+    /// Stream không được thử lại: các dòng đã đọc có thể đã được ghi vào file.
+    fn stream_product_data<'a>(
+        &'a self,
+        params: &'a ReportParams,
+    ) -> BoxStream<'a, Result<ProductData>> {
+        self.inner.stream_product_data(params)
+    }
+
+    async fn count_product_data(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<i64> {
+        self.retry("count_product_data", || self.inner.count_product_data(params)).await
+    }
+
+    fn supports_csv_copy(&self) -> bool {
+        self.inner.supports_csv_copy()
+    }
+
+    /// Không thử lại: một phần dữ liệu có thể đã được ghi vào `writer`.
+    async fn copy_product_data_csv(
+        &self,
+        params: &ReportParams,
+        max_rows: usize,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> DbResult<u64> {
+        self.inner.copy_product_data_csv(params, max_rows, writer).await
+    }
+
+    async fn prepare_dataset(
+        &self,
+        sql: &str,
+        params: &[DatasetParam],
+    ) -> DbResult<Vec<String>> {
+        self.retry("prepare_dataset", || self.inner.prepare_dataset(sql, params)).await
+    }
+
+    async fn query_dataset(
+        &self,
+        sql: &str,
+        values: &[DatasetValue],
+        tenant: Option<&str>,
+        max_rows: usize,
+    ) -> DbResult<DatasetRows> {
+        self.retry("query_dataset", || self.inner.query_dataset(sql, values, tenant, max_rows)).await
+    }
+
+    async fn query_order_data(
+        &self,
+        params: &OrderReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<OrderData>> {
+        self.retry("query_order_data", || self.inner.query_order_data(params, max_rows)).await
+    }
+
+    async fn query_customer_data(
+        &self,
+        params: &CustomerReportParams,
+        max_rows: usize,
+    ) -> DbResult<Vec<CustomerData>> {
+        self.retry("query_customer_data", || self.inner.query_customer_data(params, max_rows)).await
+    }
+
+    async fn query_category_summary(
+        &self,
+        params: &ReportParams,
+    ) -> DbResult<Vec<SalesSummaryRow>> {
+        self.retry("query_category_summary", || self.inner.query_category_summary(params)).await
+    }
+
+    fn stream_order_data<'a>(
+        &'a self,
+        params: &'a OrderReportParams,
+    ) -> BoxStream<'a, Result<OrderData>> {
+        self.inner.stream_order_data(params)
+    }
+
+    async fn claim_unsent_notifications(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        // Row đã claim ở lần trước (nếu commit thành công) chỉ bị giữ đến hết lease.
+        self.retry("claim_unsent_notifications", || self.inner.claim_unsent_notifications(limit)).await
+    }
+
+    async fn list_unsent_notifications(
+        &self,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> DbResult<Vec<UnsentNotification>> {
+        self.retry("list_unsent_notifications", || self.inner.list_unsent_notifications(limit, after)).await
+    }
+
+    async fn record_notification_failure(
+        &self,
+        request_id: Uuid,
+        next_retry_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        self.inner.record_notification_failure(request_id, next_retry_at).await
+    }
+
+    async fn list_expired(
+        &self,
+        before: DateTime<Utc>,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.retry("list_expired", || self.inner.list_expired(before)).await
+    }
+
+    async fn is_file_shared(
+        &self,
+        file_path: &str,
+        request_id: Uuid,
+    ) -> DbResult<bool> {
+        self.retry("is_file_shared", || self.inner.is_file_shared(file_path, request_id)).await
+    }
+
+    async fn mark_expired(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<()> {
+        self.retry("mark_expired", || self.inner.mark_expired(request_id)).await
+    }
+
+    async fn record_generated_file(
+        &self,
+        request_id: Uuid,
+        file_path: &str,
+        file_checksum: &str,
+        file_size_bytes: i64,
+    ) -> DbResult<()> {
+        self.retry("record_generated_file", || {
+            self.inner.record_generated_file(request_id, file_path, file_checksum, file_size_bytes)
+        })
+        .await
+    }
+
+    async fn record_params_hash(
+        &self,
+        request_id: Uuid,
+        params_hash: &str,
+    ) -> DbResult<()> {
+        self.retry("record_params_hash", || self.inner.record_params_hash(request_id, params_hash)).await
+    }
+
+    async fn find_recent_by_params_hash(
+        &self,
+        params_hash: &str,
+        since: DateTime<Utc>,
+        exclude_request_id: Uuid,
+    ) -> DbResult<Option<ExportRequest>> {
+        self.retry("find_recent_by_params_hash", || {
+            self.inner.find_recent_by_params_hash(params_hash, since, exclude_request_id)
+        })
+        .await
+    }
+
+    async fn record_estimated_completion(
+        &self,
+        request_id: Uuid,
+        estimated_completion_at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        self.retry("record_estimated_completion", || {
+            self.inner.record_estimated_completion(request_id, estimated_completion_at)
+        })
+        .await
+    }
+
+    async fn record_export_duration(
+        &self,
+        request_id: Uuid,
+        duration_ms: i64,
+        row_count: Option<i64>,
+    ) -> DbResult<()> {
+        self.retry("record_export_duration", || {
+            self.inner.record_export_duration(request_id, duration_ms, row_count)
+        })
+        .await
+    }
+
+    async fn record_notify_duration(
+        &self,
+        request_id: Uuid,
+        notify_ms: i64,
+    ) -> DbResult<()> {
+        self.retry("record_notify_duration", || self.inner.record_notify_duration(request_id, notify_ms)).await
+    }
+
+    async fn recent_export_durations(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<(i64, i64)>> {
+        self.retry("recent_export_durations", || self.inner.recent_export_durations(limit)).await
+    }
+
+    async fn claim_next_pending(
+        &self,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.inner.claim_next_pending(limit).await
+    }
+
+    async fn fetch_stuck_processing(
+        &self,
+        stuck_for: chrono::Duration,
+        limit: i64,
+    ) -> DbResult<Vec<ExportRequest>> {
+        self.retry("fetch_stuck_processing", || self.inner.fetch_stuck_processing(stuck_for, limit)).await
+    }
+
+    async fn reset_to_pending(
+        &self,
+        ids: &[Uuid],
+    ) -> DbResult<Vec<Uuid>> {
+        // Chỉ reset row còn PROCESSING nên chạy lại không reset request hai lần.
+        self.retry("reset_to_pending", || self.inner.reset_to_pending(ids)).await
+    }
+
+    async fn bulk_update_status(
+        &self,
+        ids: &[Uuid],
+        new_status: ExportStatus,
+        error_message: Option<String>,
+        force: bool,
+    ) -> DbResult<u64> {
+        // Row đã ở `new_status` bị bỏ qua nên chạy lại không ghi audit log hai lần.
+        self.retry("bulk_update_status", || {
+            self.inner.bulk_update_status(ids, new_status, error_message.clone(), force)
+        })
+        .await
+    }
+
+    async fn record_attempt(
+        &self,
+        request_id: Uuid,
+        error: Option<&str>,
+    ) -> DbResult<()> {
+        self.inner.record_attempt(request_id, error).await
+    }
+
+    async fn archive_requests(
+        &self,
+        before: DateTime<Utc>,
+        batch: i64,
+    ) -> DbResult<i64> {
+        self.retry("archive_requests", || self.inner.archive_requests(before, batch)).await
+    }
+
+    async fn count_by_status(&self) -> DbResult<HashMap<ExportStatus, i64>> {
+        self.retry("count_by_status", || self.inner.count_by_status()).await
+    }
+
+    async fn get_request_history(
+        &self,
+        request_id: Uuid,
+    ) -> DbResult<Vec<ExportRequestEvent>> {
+        self.retry("get_request_history", || self.inner.get_request_history(request_id)).await
+    }
+
+    async fn count_user_exports_since(
+        &self,
+        user_id: i64,
+        since: DateTime<Utc>,
+    ) -> DbResult<i64> {
+        self.retry("count_user_exports_since", || self.inner.count_user_exports_since(user_id, since)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::db_store::DbError;
+    use crate::services::mock_store::MockDbStore;
+
+    fn retrying(inner: MockDbStore, max_attempts: u32) -> RetryingDbStore<MockDbStore> {
+        RetryingDbStore::new(inner, DbRetrySettings { max_attempts, base_delay_ms: 1 })
+    }
+
+    fn transient() -> DbError {
+        DbError::Transient(anyhow::anyhow!("connection reset by peer"))
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried_until_success() {
+        let inner = MockDbStore::new();
+        inner.fail_next("count_by_status", transient());
+        inner.fail_next("count_by_status", transient());
+        let store = retrying(inner, 3);
+
+        assert!(store.count_by_status().await.is_ok());
+        assert_eq!(store.inner.calls("count_by_status"), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let inner = MockDbStore::new();
+        for _ in 0..3 {
+            inner.fail_next("count_by_status", transient());
+        }
+        let store = retrying(inner, 3);
+
+        assert!(matches!(store.count_by_status().await, Err(DbError::Transient(_))));
+        assert_eq!(store.inner.calls("count_by_status"), 3);
+    }
+
+    #[tokio::test]
+    async fn non_transient_errors_are_not_retried() {
+        let inner = MockDbStore::new();
+        inner.fail_next("count_by_status", DbError::Fatal(anyhow::anyhow!("syntax error")));
+        let store = retrying(inner, 3);
+
+        assert!(matches!(store.count_by_status().await, Err(DbError::Fatal(_))));
+        assert_eq!(store.inner.calls("count_by_status"), 1);
+    }
+
+    #[tokio::test]
+    async fn counter_updates_are_never_retried() {
+        let inner = MockDbStore::new();
+        let request_id = inner.insert(ExportRequest::for_test(serde_json::json!({})), ExportStatus::Processing);
+        inner.fail_next("record_attempt", transient());
+        let store = retrying(inner, 3);
+
+        assert!(store.record_attempt(request_id, None).await.is_err());
+        assert_eq!(store.inner.calls("record_attempt"), 1);
+    }
+
+    #[tokio::test]
+    async fn claim_is_rechecked_on_retry() {
+        let inner = MockDbStore::new();
+        let request_id = inner.insert(ExportRequest::for_test(serde_json::json!({})), ExportStatus::Completed);
+        inner.fail_next("fetch_and_update_request_status", transient());
+        let store = retrying(inner, 3);
+
+        let result = store
+            .fetch_and_update_request_status(request_id, ExportStatus::Processing, &ExportQuota::default())
+            .await;
+
+        assert!(matches!(result, Err(DbError::AlreadyFinal(ExportStatus::Completed))));
+        assert_eq!(store.inner.calls("fetch_and_update_request_status"), 2);
+    }
+
+    #[tokio::test]
+    async fn lock_is_retried_on_transient_errors() {
+        let inner = MockDbStore::new();
+        let request_id = inner.insert(ExportRequest::for_test(serde_json::json!({})), ExportStatus::Pending);
+        inner.fail_next("try_lock_request", transient());
+        let store = retrying(inner, 3);
+
+        assert!(store.try_lock_request(request_id).await.unwrap());
+        assert_eq!(store.inner.calls("try_lock_request"), 2);
+    }
+}
@@ -0,0 +1,888 @@
+use anyhow::{Context, Result};
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, Tag, Tagging};
+use aws_sdk_s3::Client;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, TryStreamExt};
+use metrics::{counter, increment};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, DuplexStream, ReadBuf};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+use crate::config::{S3Config, S3UrlMode};
+use crate::models::{CsvOptions, ExcelStyleOptions, OutputCompression, ReportData};
+use crate::services::email_delivery::attachment_content_type;
+use crate::services::encryption::FileEncryption;
+use crate::services::pgp_encryption::PgpEncryption;
+use crate::services::safe_path::check_component;
+use crate::services::file_exporter::{
+    content_file_names, csv_file_path, file_extension, file_name_candidates, open_streamed_csv_file, CompressedFile, CsvFileWriter,
+    ExcelTemplate, ExportMetadata, ExportedFile, FileChecksum, FileExporter, LocalFileExporter, PdfReport, StreamedUpload,
+};
+
+/// Metadata của object chứa checksum SHA-256 của file, để kiểm tra file khi xử lý lại request mà không phải tải về.
+const SHA256_METADATA_KEY: &str = "sha256";
+
+/// Số part tối đa của một multipart upload; file lớn hơn `part size × 10000` dùng part lớn hơn.
+const MAX_MULTIPART_PARTS: u64 = 10_000;
+
+/// Thời gian chờ trước lần thử lại đầu tiên của một part, tăng gấp đôi sau mỗi lần.
+const PART_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Lưu file export lên S3 (bật bằng cargo feature `s3`). File được tạo trong thư mục local
+/// (EXCEL_EXPORT_PATH, chỉ dùng làm thư mục tạm) bởi `LocalFileExporter`, rồi được upload
+/// lên `{prefix}/{đường dẫn tương đối}` (ví dụ `{prefix}/{request_id}.xlsx`) và xóa bản local.
+/// Đường dẫn được lưu vào DB là object key.
+pub struct S3FileExporter {
+    local: LocalFileExporter,
+    client: Client,
+    bucket: String,
+    prefix: String,
+    local_root: PathBuf,
+    url_mode: S3UrlMode,
+    presign_expiry: Duration,
+    public_base_url: String,
+    multipart_part_size: u64,
+    multipart_concurrency: usize,
+    multipart_part_attempts: u32,
+}
+
+impl S3FileExporter {
+    pub async fn new(local: LocalFileExporter, config: &S3Config, local_root: &str) -> Result<Self> {
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
+        if let Some(region) = &config.region {
+            loader = loader.region(aws_config::Region::new(region.clone()));
+        }
+        let shared_config = loader.load().await;
+        let region = shared_config
+            .region()
+            .map(|region| region.to_string())
+            .context("S3 region is not configured: set S3_REGION or AWS_REGION")?;
+
+        let mut builder = aws_sdk_s3::config::Builder::from(&shared_config);
+        if let Some(endpoint) = &config.endpoint {
+            // MinIO và các storage tương thích S3 thường không hỗ trợ virtual-hosted-style URL.
+            builder = builder.endpoint_url(endpoint).force_path_style(true);
+        }
+        let public_base_url = match (&config.public_base_url, &config.endpoint) {
+            (Some(url), _) => url.clone(),
+            (None, Some(endpoint)) => format!("{}/{}", endpoint.trim_end_matches('/'), config.bucket),
+            (None, None) => format!("https://{}.s3.{}.amazonaws.com", config.bucket, region),
+        };
+
+        info!("☁️ Storing exports in S3 bucket {} (prefix '{}', region {}).", config.bucket, config.prefix, region);
+        Ok(Self {
+            local,
+            client: Client::from_conf(builder.build()),
+            bucket: config.bucket.clone(),
+            prefix: config.prefix.clone(),
+            local_root: PathBuf::from(local_root),
+            url_mode: config.url_mode,
+            presign_expiry: Duration::from_secs(config.presign_expiry_secs),
+            public_base_url,
+            multipart_part_size: config.multipart_part_size_bytes,
+            multipart_concurrency: config.multipart_concurrency,
+            multipart_part_attempts: config.multipart_part_attempts,
+        })
+    }
+
+    /// Object key của file local: giữ đường dẫn tương đối so với thư mục gốc (thư mục tenant, `output_subdir`).
+    fn object_key(&self, file_path: &str) -> String {
+        let relative = Path::new(file_path).strip_prefix(&self.local_root).unwrap_or(Path::new(file_path));
+        let relative = relative
+            .components()
+            .filter_map(|component| match component {
+                std::path::Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        if self.prefix.is_empty() {
+            relative
+        } else {
+            format!("{}/{}", self.prefix, relative)
+        }
+    }
+
+    /// File lớn hơn S3_MULTIPART_PART_SIZE_BYTES được upload bằng multipart upload, còn lại bằng một PutObject.
+    async fn upload(&self, file_path: &str, key: &str, checksum: &FileChecksum) -> Result<()> {
+        let file_name = Path::new(file_path).file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if checksum.size_bytes > self.multipart_part_size {
+            return self.upload_multipart(file_path, key, attachment_content_type(file_name), checksum).await;
+        }
+        let body = ByteStream::from_path(file_path)
+            .await
+            .context("Failed to read export file for upload")?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(body)
+            .content_type(attachment_content_type(file_name))
+            .metadata(SHA256_METADATA_KEY, &checksum.sha256)
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload export file to s3://{}/{}", self.bucket, key))?;
+        Ok(())
+    }
+
+    /// Upload file local bằng multipart upload, đọc file theo từng part (xem `upload_parts`).
+    async fn upload_multipart(&self, file_path: &str, key: &str, content_type: &str, checksum: &FileChecksum) -> Result<()> {
+        let part_size = self.multipart_part_size.max(checksum.size_bytes.div_ceil(MAX_MULTIPART_PARTS));
+        let upload = self.create_multipart_upload(key, content_type, Some(&checksum.sha256)).await?;
+        let parts = match tokio::fs::File::open(file_path).await {
+            Ok(file) => upload_parts(&upload, file, part_size, self.multipart_concurrency).await,
+            Err(e) => Err(e).context("Failed to read export file for upload"),
+        };
+        let part_count = finish_multipart_upload(&upload, parts, None).await?;
+        info!("☁️ Uploaded s3://{}/{} in {} parts.", self.bucket, key, part_count);
+        Ok(())
+    }
+
+    /// Bắt đầu multipart upload tới `key`. `sha256` đã biết trước (file local) được ghi vào metadata của object.
+    async fn create_multipart_upload(&self, key: &str, content_type: &str, sha256: Option<&str>) -> Result<S3MultipartUpload> {
+        let mut request = self.client.create_multipart_upload().bucket(&self.bucket).key(key).content_type(content_type);
+        if let Some(sha256) = sha256 {
+            request = request.metadata(SHA256_METADATA_KEY, sha256);
+        }
+        let upload_id = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to start multipart upload to s3://{}/{}", self.bucket, key))?
+            .upload_id()
+            .map(str::to_string)
+            .context("S3 returned no multipart upload id")?;
+        Ok(self.multipart_upload(key, upload_id))
+    }
+
+    /// Checksum của object được upload trong lúc ghi: SHA-256 chưa biết lúc bắt đầu upload nên nằm trong tag của object.
+    async fn checksum_tag(&self, key: &str) -> Result<Option<String>> {
+        let output = self
+            .client
+            .get_object_tagging()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .with_context(|| format!("Failed to read tags of s3://{}/{}", self.bucket, key))?;
+        Ok(output
+            .tag_set()
+            .iter()
+            .find(|tag| tag.key() == SHA256_METADATA_KEY)
+            .map(|tag| tag.value().to_string()))
+    }
+
+    /// Multipart upload `upload_id` đã có của `key`.
+    fn multipart_upload(&self, key: &str, upload_id: String) -> S3MultipartUpload {
+        S3MultipartUpload {
+            client: self.client.clone(),
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            upload_id,
+            part_attempts: self.multipart_part_attempts,
+        }
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .with_context(|| format!("Failed to delete s3://{}/{}", self.bucket, key))?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl FileExporter for S3FileExporter {
+    async fn export_to_excel(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        style: &ExcelStyleOptions,
+        metadata: Option<&ExportMetadata>,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.local.export_to_excel(request_id, data, style, metadata, max_file_size, export_path).await
+    }
+
+    async fn export_to_template(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        template: &ExcelTemplate,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.local.export_to_template(request_id, data, template, style, export_path).await
+    }
+
+    async fn export_to_csv(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.local.export_to_csv(request_id, data, options, compression, max_file_size, export_path).await
+    }
+
+    async fn export_to_parquet(&self, request_id: Uuid, data: ReportData, export_path: &str) -> Result<ExportedFile> {
+        self.local.export_to_parquet(request_id, data, export_path).await
+    }
+
+    async fn export_to_ods(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.local.export_to_ods(request_id, data, style, export_path).await
+    }
+
+    async fn export_to_html(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        title: &str,
+        style: &ExcelStyleOptions,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.local.export_to_html(request_id, data, title, style, export_path).await
+    }
+
+    async fn export_to_pdf(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        report: &PdfReport,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.local.export_to_pdf(request_id, data, report, export_path).await
+    }
+
+    async fn export_to_jsonl(
+        &self,
+        request_id: Uuid,
+        data: ReportData,
+        compression: OutputCompression,
+        max_file_size: Option<u64>,
+        export_path: &str,
+    ) -> Result<ExportedFile> {
+        self.local.export_to_jsonl(request_id, data, compression, max_file_size, export_path).await
+    }
+
+    async fn create_csv_file(
+        &self,
+        request_id: Uuid,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        export_path: &str,
+    ) -> Result<CsvFileWriter> {
+        self.local.create_csv_file(request_id, options, compression, export_path).await
+    }
+
+    /// Nội dung được chia thành part S3_MULTIPART_PART_SIZE_BYTES và upload trong lúc ghi, tối đa
+    /// S3_MULTIPART_CONCURRENCY part cùng lúc: không có file local, và bộ nhớ dùng khoảng `part size × (concurrency + 1)`.
+    /// Kích thước file chưa biết trước nên file không được lớn hơn `part size × 10000`.
+    #[instrument(skip(self, options))]
+    async fn create_streamed_csv_file(
+        &self,
+        request_id: Uuid,
+        options: &CsvOptions,
+        compression: OutputCompression,
+        export_path: &str,
+    ) -> Result<Option<CsvFileWriter>> {
+        let key = self.object_key(&csv_file_path(export_path, request_id, compression));
+        let content_type = attachment_content_type(&key);
+        let upload = self.create_multipart_upload(&key, content_type, None).await?;
+        let (output, upload) = spawn_streamed_upload(upload, key, self.multipart_part_size, self.multipart_concurrency);
+        let writer = open_streamed_csv_file(output, Box::new(upload), options, compression).await?;
+        Ok(Some(writer))
+    }
+
+    /// Xóa object trên S3; object không còn tồn tại được coi là đã xóa (trả về 0).
+    #[instrument(skip(self))]
+    async fn delete_file(&self, file_path: &str) -> Result<u64> {
+        let size = match self.file_checksum(file_path).await? {
+            Some(checksum) => checksum.size_bytes,
+            None => {
+                warn!("S3 object {} no longer exists, treating as already deleted.", file_path);
+                return Ok(0);
+            }
+        };
+        self.delete_object(file_path).await?;
+        info!("🗑️ Deleted S3 object s3://{}/{} ({} bytes).", self.bucket, file_path, size);
+        Ok(size)
+    }
+
+    /// Xóa file tạm local, và cả object đã upload nếu request thất bại sau khi upload (ví dụ lỗi ghi DB).
+    #[instrument(skip(self))]
+    async fn remove_partial_output(&self, request_id: Uuid, export_path: &str) -> Result<u64> {
+        let mut removed_bytes = self.local.remove_partial_output(request_id, export_path).await?;
+        let key_prefix = self.object_key(&format!("{}/{}.", export_path, request_id));
+        let objects = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&key_prefix)
+            .send()
+            .await
+            .with_context(|| format!("Failed to list s3://{}/{}*", self.bucket, key_prefix))?;
+        for object in objects.contents() {
+            if let Some(key) = object.key() {
+                self.delete_object(key).await?;
+                removed_bytes += object.size().unwrap_or_default() as u64;
+            }
+        }
+        Ok(removed_bytes)
+    }
+
+    #[instrument(skip(self))]
+    async fn file_exists(&self, file_path: &str) -> Result<bool> {
+        match self.client.head_object().bucket(&self.bucket).key(file_path).send().await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("Failed to read metadata of s3://{}/{}", self.bucket, file_path)),
+        }
+    }
+
+    /// Checksum lấy từ metadata của object (ghi lúc upload), hoặc từ tag của object được upload trong lúc ghi;
+    /// kích thước từ `Content-Length`.
+    #[instrument(skip(self))]
+    async fn file_checksum(&self, file_path: &str) -> Result<Option<FileChecksum>> {
+        match self.client.head_object().bucket(&self.bucket).key(file_path).send().await {
+            Ok(output) => {
+                let sha256 = match output.metadata().and_then(|metadata| metadata.get(SHA256_METADATA_KEY)) {
+                    Some(sha256) => Some(sha256.clone()),
+                    None => self.checksum_tag(file_path).await?,
+                }
+                .with_context(|| format!("S3 object {} has no {} metadata or tag", file_path, SHA256_METADATA_KEY))?;
+                Ok(Some(FileChecksum { sha256, size_bytes: output.content_length().unwrap_or_default() as u64 }))
+            }
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read metadata of s3://{}/{}", self.bucket, file_path)),
+        }
+    }
+
+    async fn compress_file(&self, request_id: Uuid, file_path: &str, min_size_bytes: u64) -> Result<Option<CompressedFile>> {
+        self.local.compress_file(request_id, file_path, min_size_bytes).await
+    }
+
+    async fn zip_parts(&self, request_id: Uuid, part_paths: &[String], password: Option<&str>) -> Result<CompressedFile> {
+        self.local.zip_parts(request_id, part_paths, password).await
+    }
+
+    async fn encrypt_file(&self, request_id: Uuid, file_path: &str, password: &str) -> Result<CompressedFile> {
+        self.local.encrypt_file(request_id, file_path, password).await
+    }
+
+    async fn encrypt_at_rest(&self, file_path: &str, encryption: &FileEncryption) -> Result<String> {
+        self.local.encrypt_at_rest(file_path, encryption).await
+    }
+
+    async fn encrypt_for_recipient(&self, file_path: &str, pgp: &PgpEncryption, recipient_key_id: &str) -> Result<(String, String)> {
+        self.local.encrypt_for_recipient(file_path, pgp, recipient_key_id).await
+    }
+
+    /// Tên file phải trống cả trong thư mục tạm local lẫn trên bucket: chọn tên đầu tiên chưa có object
+    /// rồi để `LocalFileExporter` đổi tên file local.
+    #[instrument(skip(self), fields(request_id = %request_id))]
+    async fn rename_file(&self, request_id: Uuid, file_path: &str, stem: &str) -> Result<String> {
+        let path = Path::new(file_path);
+        let dir = path.parent().context("Export file has no parent directory")?;
+        let extension = file_extension(file_path);
+        for candidate in file_name_candidates(request_id, stem, extension) {
+            check_component(&candidate)?;
+            let key = self.object_key(&dir.join(&candidate).to_string_lossy());
+            match self.client.head_object().bucket(&self.bucket).key(&key).send().await {
+                Ok(_) => continue,
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => {
+                    let candidate_stem = candidate.strip_suffix(&format!(".{}", extension)).unwrap_or(&candidate);
+                    return self.local.rename_file(request_id, file_path, candidate_stem).await;
+                }
+                Err(e) => return Err(e).with_context(|| format!("Failed to check s3://{}/{}", self.bucket, key)),
+            }
+        }
+        anyhow::bail!("No free object key for '{}' under s3://{}/{}", stem, self.bucket, self.prefix)
+    }
+
+    /// Tên theo nội dung được chọn theo object trên bucket: object cùng tên và cùng checksum thì file
+    /// không cần upload lại (`store_file` bỏ qua), khác checksum thì thử tên tiếp theo.
+    #[instrument(skip(self), fields(request_id = %request_id))]
+    async fn rename_by_content(&self, request_id: Uuid, file_path: &str) -> Result<(String, bool)> {
+        let checksum = self
+            .local
+            .file_checksum(file_path)
+            .await?
+            .with_context(|| format!("Export file {} not found before naming by content", file_path))?;
+        let dir = Path::new(file_path).parent().context("Export file has no parent directory")?;
+        let extension = file_extension(file_path);
+        for candidate in content_file_names(&checksum.sha256, extension) {
+            let key = self.object_key(&dir.join(&candidate).to_string_lossy());
+            let existing = self.file_checksum(&key).await?;
+            if existing.as_ref().is_some_and(|existing| existing.sha256 != checksum.sha256) {
+                warn!("s3://{}/{} already exists with different content, trying the next name.", self.bucket, key);
+                continue;
+            }
+            let candidate_stem = candidate.strip_suffix(&format!(".{}", extension)).unwrap_or(&candidate);
+            let path = self.local.rename_file(request_id, file_path, candidate_stem).await?;
+            return Ok((path, existing.is_some()));
+        }
+        anyhow::bail!("No free content-addressed object key for {} under s3://{}/{}", file_path, self.bucket, self.prefix)
+    }
+
+    /// File metadata được ghi cạnh file local; service upload nó bằng `store_file` như file export.
+    async fn write_metadata_file(&self, file_path: &str, metadata: &ExportMetadata) -> Result<String> {
+        self.local.write_metadata_file(file_path, metadata).await
+    }
+
+    /// Upload file local lên S3 rồi xóa bản local (kể cả khi upload thất bại), trả về object key.
+    /// Object đã có cùng checksum (tên theo nội dung) thì không upload lại.
+    #[instrument(skip(self), fields(request_id = %request_id))]
+    async fn store_file(&self, request_id: Uuid, file_path: &str) -> Result<String> {
+        let key = self.object_key(file_path);
+        let upload_result = match self.local.file_checksum(file_path).await {
+            Ok(Some(checksum)) => match self.file_checksum(&key).await {
+                Ok(Some(existing)) if existing.sha256 == checksum.sha256 => {
+                    info!("☁️ s3://{}/{} already has the same content, skipping upload.", self.bucket, key);
+                    Ok(checksum.size_bytes)
+                }
+                _ => self.upload(file_path, &key, &checksum).await.map(|_| checksum.size_bytes),
+            },
+            Ok(None) => Err(anyhow::anyhow!("Exported file {} not found before upload", file_path)),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = self.local.delete_file(file_path).await {
+            warn!("Failed to remove local copy {} of request {}: {:?}", file_path, request_id, e);
+        }
+        let size = upload_result?;
+        info!("☁️ Uploaded export of request {} to s3://{}/{} ({} bytes).", request_id, self.bucket, key, size);
+        Ok(key)
+    }
+
+    /// Multipart upload mà process bị dừng giữa chừng (crash, deploy) không bao giờ được hoàn tất hay hủy:
+    /// hủy những upload dưới S3_PREFIX bắt đầu trước `before`.
+    #[instrument(skip(self))]
+    async fn abort_stale_uploads(&self, before: DateTime<Utc>) -> Result<u64> {
+        let mut aborted = 0;
+        let mut key_marker: Option<String> = None;
+        let mut upload_id_marker: Option<String> = None;
+        loop {
+            let output = self
+                .client
+                .list_multipart_uploads()
+                .bucket(&self.bucket)
+                .prefix(&self.prefix)
+                .set_key_marker(key_marker.take())
+                .set_upload_id_marker(upload_id_marker.take())
+                .send()
+                .await
+                .with_context(|| format!("Failed to list multipart uploads of s3://{}/{}", self.bucket, self.prefix))?;
+            for upload in output.uploads() {
+                let (Some(key), Some(upload_id), Some(initiated)) = (upload.key(), upload.upload_id(), upload.initiated()) else {
+                    continue;
+                };
+                if initiated.secs() >= before.timestamp() {
+                    continue;
+                }
+                self.multipart_upload(key, upload_id.to_string()).abort().await?;
+                info!("🧹 Aborted stale multipart upload {} of s3://{}/{}.", upload_id, self.bucket, key);
+                aborted += 1;
+            }
+            if !output.is_truncated().unwrap_or(false) {
+                break;
+            }
+            key_marker = output.next_key_marker().map(str::to_string);
+            upload_id_marker = output.next_upload_id_marker().map(str::to_string);
+        }
+        counter!("excel_export_s3_stale_uploads_aborted_total", aborted);
+        Ok(aborted)
+    }
+
+    async fn download_url(&self, file_path: &str) -> Result<Option<String>> {
+        match self.url_mode {
+            S3UrlMode::Public => Ok(Some(format!("{}/{}", self.public_base_url, file_path))),
+            S3UrlMode::Presigned => {
+                let presigning = PresigningConfig::expires_in(self.presign_expiry).context("Invalid presign expiry")?;
+                let request = self
+                    .client
+                    .get_object()
+                    .bucket(&self.bucket)
+                    .key(file_path)
+                    .presigned(presigning)
+                    .await
+                    .with_context(|| format!("Failed to presign s3://{}/{}", self.bucket, file_path))?;
+                Ok(Some(request.uri().to_string()))
+            }
+        }
+    }
+
+    fn stores_locally(&self) -> bool {
+        false
+    }
+}
+
+/// Kích thước buffer giữa `CsvFileWriter` và task upload của file được upload trong lúc ghi. Nội dung được gom
+/// thành part ở phía task upload; buffer nhỏ chỉ để người ghi phải chờ khi upload chậm hơn tốc độ tạo file.
+const STREAMED_UPLOAD_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Các bước của một multipart upload đã bắt đầu, tách khỏi `Client` để kiểm thử việc hoàn tất và hủy upload.
+#[async_trait::async_trait]
+trait MultipartUpload: Send + Sync {
+    async fn upload_part(&self, part_number: i32, body: Vec<u8>) -> Result<CompletedPart>;
+
+    /// Ghép các part thành object. `sha256` chưa biết lúc bắt đầu upload được ghi vào tag của object.
+    async fn complete(&self, parts: Vec<CompletedPart>, sha256: Option<&str>) -> Result<()>;
+
+    async fn abort(&self) -> Result<()>;
+}
+
+struct S3MultipartUpload {
+    client: Client,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    part_attempts: u32,
+}
+
+#[async_trait::async_trait]
+impl MultipartUpload for S3MultipartUpload {
+    /// Upload một part, thử lại tối đa S3_MULTIPART_PART_ATTEMPTS lần với thời gian chờ tăng dần.
+    async fn upload_part(&self, part_number: i32, body: Vec<u8>) -> Result<CompletedPart> {
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(&self.upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(body.clone()))
+                .send()
+                .await;
+            match result {
+                Ok(output) => {
+                    return Ok(CompletedPart::builder()
+                        .part_number(part_number)
+                        .set_e_tag(output.e_tag().map(str::to_string))
+                        .build());
+                }
+                Err(e) if attempt < self.part_attempts => {
+                    warn!(
+                        "Upload of part {} of s3://{}/{} failed (attempt {}/{}): {:?}",
+                        part_number, self.bucket, self.key, attempt, self.part_attempts, e
+                    );
+                    increment!("excel_export_s3_part_retries_total");
+                    tokio::time::sleep(PART_RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to upload part {} to s3://{}/{}", part_number, self.bucket, self.key)
+                    });
+                }
+            }
+        }
+    }
+
+    async fn complete(&self, parts: Vec<CompletedPart>, sha256: Option<&str>) -> Result<()> {
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send()
+            .await
+            .with_context(|| format!("Failed to complete multipart upload to s3://{}/{}", self.bucket, self.key))?;
+        if let Some(sha256) = sha256 {
+            let tag = Tag::builder().key(SHA256_METADATA_KEY).value(sha256).build().context("Invalid checksum tag")?;
+            let tagging = Tagging::builder().tag_set(tag).build().context("Invalid checksum tag")?;
+            self.client
+                .put_object_tagging()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .tagging(tagging)
+                .send()
+                .await
+                .with_context(|| format!("Failed to tag s3://{}/{} with its checksum", self.bucket, self.key))?;
+        }
+        Ok(())
+    }
+
+    async fn abort(&self) -> Result<()> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .send()
+            .await
+            .with_context(|| format!("Failed to abort multipart upload {} of s3://{}/{}", self.upload_id, self.bucket, self.key))?;
+        Ok(())
+    }
+}
+
+/// Đọc `reader` theo từng part `part_size` byte và upload tối đa `concurrency` part cùng lúc, nên bộ nhớ dùng
+/// không phụ thuộc kích thước file. Trả về các part theo thứ tự, sẵn sàng để hoàn tất upload.
+async fn upload_parts<U, R>(upload: &U, reader: R, part_size: u64, concurrency: usize) -> Result<Vec<CompletedPart>>
+where
+    U: MultipartUpload + ?Sized,
+    R: AsyncRead + Unpin + Send,
+{
+    stream::try_unfold((reader, 1), |(mut reader, part_number)| async move {
+        let mut buffer = Vec::with_capacity(part_size as usize);
+        (&mut reader)
+            .take(part_size)
+            .read_to_end(&mut buffer)
+            .await
+            .context("Failed to read export file for upload")?;
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(((part_number, buffer), (reader, part_number + 1))))
+    })
+    .map_ok(|(part_number, buffer)| upload.upload_part(part_number, buffer))
+    .try_buffered(concurrency)
+    .try_collect()
+    .await
+}
+
+/// Hoàn tất multipart upload khi mọi part đã được upload, trả về số part. Ngược lại (part lỗi sau khi hết lượt thử,
+/// file tạo lỗi) thì upload bị hủy để S3 không giữ (và tính phí) các part đã upload.
+async fn finish_multipart_upload<U>(upload: &U, parts: Result<Vec<CompletedPart>>, sha256: Option<&str>) -> Result<usize>
+where
+    U: MultipartUpload + ?Sized,
+{
+    let result = match parts {
+        Ok(parts) => {
+            let part_count = parts.len();
+            upload.complete(parts, sha256).await.map(|_| part_count)
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(part_count) => {
+            increment!("excel_export_s3_multipart_uploads_total");
+            Ok(part_count)
+        }
+        Err(e) => {
+            if let Err(abort_error) = upload.abort().await {
+                warn!("{:?}", abort_error);
+            }
+            increment!("excel_export_s3_multipart_aborted_total");
+            Err(e)
+        }
+    }
+}
+
+/// Bắt đầu task upload nội dung được ghi vào writer trả về. Task hoàn tất upload khi `S3StreamedUpload::complete`
+/// được gọi sau khi writer đã đóng, và hủy upload khi `S3StreamedUpload` bị drop (file tạo lỗi) hoặc part upload lỗi.
+fn spawn_streamed_upload<U>(upload: U, key: String, part_size: u64, concurrency: usize) -> (DuplexStream, S3StreamedUpload)
+where
+    U: MultipartUpload + 'static,
+{
+    let (writer, reader) = tokio::io::duplex(STREAMED_UPLOAD_BUFFER_BYTES);
+    let (commit, committed) = oneshot::channel();
+    let task = tokio::spawn(async move {
+        let mut reader = HashingReader { inner: reader, hasher: Sha256::new(), bytes: 0 };
+        let parts = match upload_parts(&upload, &mut reader, part_size, concurrency).await {
+            // Writer bị drop cũng làm task đọc hết nội dung: chỉ `complete` mới cho biết file đã được ghi đủ.
+            Ok(parts) => match committed.await {
+                // S3 không nhận multipart upload không có part: file rỗng được upload thành một part rỗng.
+                Ok(()) if parts.is_empty() => upload.upload_part(1, Vec::new()).await.map(|part| vec![part]),
+                Ok(()) => Ok(parts),
+                Err(_) => Err(anyhow::anyhow!("Export file generation failed, abandoning its upload")),
+            },
+            Err(e) => Err(e),
+        };
+        let checksum = FileChecksum { sha256: format!("{:x}", reader.hasher.finalize()), size_bytes: reader.bytes };
+        finish_multipart_upload(&upload, parts, Some(&checksum.sha256)).await?;
+        Ok(checksum)
+    });
+    (writer, S3StreamedUpload { key, commit, task })
+}
+
+/// Upload đang nhận nội dung của một `CsvFileWriter` (xem `spawn_streamed_upload`).
+struct S3StreamedUpload {
+    key: String,
+    /// Drop mà không gửi (file tạo lỗi) thì task hủy upload.
+    commit: oneshot::Sender<()>,
+    task: JoinHandle<Result<FileChecksum>>,
+}
+
+#[async_trait::async_trait]
+impl StreamedUpload for S3StreamedUpload {
+    async fn complete(self: Box<Self>) -> Result<(String, FileChecksum)> {
+        // Task đã dừng (part upload lỗi) thì không còn nhận tín hiệu: lỗi được trả về từ `task` bên dưới.
+        let _ = self.commit.send(());
+        let checksum = self.task.await.context("Streamed upload task panicked")??;
+        info!("☁️ Uploaded {} ({} bytes) while it was being written.", self.key, checksum.size_bytes);
+        Ok((self.key, checksum))
+    }
+}
+
+/// Tính SHA-256 và đếm số byte của nội dung được đọc qua reader.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let read = &buf.filled()[filled..];
+            self.hasher.update(read);
+            self.bytes += read.len() as u64;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncWriteExt;
+
+    #[derive(Default)]
+    struct Calls {
+        parts: Vec<(i32, Vec<u8>)>,
+        completed: Option<(Vec<i32>, Option<String>)>,
+        aborted: bool,
+    }
+
+    /// Multipart upload giả: ghi lại các part đã nhận, và upload đã được hoàn tất hay bị hủy.
+    struct MockUpload {
+        calls: Arc<Mutex<Calls>>,
+        failing_part: Option<i32>,
+    }
+
+    #[async_trait::async_trait]
+    impl MultipartUpload for MockUpload {
+        async fn upload_part(&self, part_number: i32, body: Vec<u8>) -> Result<CompletedPart> {
+            if self.failing_part == Some(part_number) {
+                anyhow::bail!("part {} failed", part_number);
+            }
+            self.calls.lock().unwrap().parts.push((part_number, body));
+            Ok(CompletedPart::builder().part_number(part_number).e_tag(format!("etag-{}", part_number)).build())
+        }
+
+        async fn complete(&self, parts: Vec<CompletedPart>, sha256: Option<&str>) -> Result<()> {
+            let part_numbers = parts.iter().filter_map(|part| part.part_number()).collect();
+            self.calls.lock().unwrap().completed = Some((part_numbers, sha256.map(str::to_string)));
+            Ok(())
+        }
+
+        async fn abort(&self) -> Result<()> {
+            self.calls.lock().unwrap().aborted = true;
+            Ok(())
+        }
+    }
+
+    fn mock_upload(failing_part: Option<i32>) -> (MockUpload, Arc<Mutex<Calls>>) {
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        (MockUpload { calls: calls.clone(), failing_part }, calls)
+    }
+
+    #[tokio::test]
+    async fn streamed_upload_is_completed_when_the_file_is_finished() {
+        let (upload, calls) = mock_upload(None);
+        let (mut writer, upload) = spawn_streamed_upload(upload, "exports/a.csv".to_string(), 4, 2);
+        writer.write_all(b"id,name\n1,a\n").await.unwrap();
+        writer.shutdown().await.unwrap();
+
+        let (key, checksum) = Box::new(upload).complete().await.unwrap();
+
+        assert_eq!(key, "exports/a.csv");
+        assert_eq!(checksum.size_bytes, 12);
+        assert_eq!(checksum.sha256, format!("{:x}", Sha256::digest(b"id,name\n1,a\n")));
+        let calls = calls.lock().unwrap();
+        let mut parts = calls.parts.clone();
+        parts.sort();
+        assert_eq!(parts, vec![(1, b"id,n".to_vec()), (2, b"ame\n".to_vec()), (3, b"1,a\n".to_vec())]);
+        assert_eq!(calls.completed, Some((vec![1, 2, 3], Some(checksum.sha256.clone()))));
+        assert!(!calls.aborted);
+    }
+
+    #[tokio::test]
+    async fn streamed_upload_is_aborted_when_generation_fails() {
+        let (upload, calls) = mock_upload(None);
+        let (mut writer, upload) = spawn_streamed_upload(upload, "exports/a.csv".to_string(), 4, 2);
+        writer.write_all(b"id,name\n1,").await.unwrap();
+        // File tạo lỗi giữa chừng: writer và upload bị drop mà không gọi `complete`.
+        let S3StreamedUpload { commit, task, .. } = upload;
+        drop(writer);
+        drop(commit);
+
+        let error = task.await.unwrap().unwrap_err();
+
+        assert!(error.to_string().contains("generation failed"), "{}", error);
+        let calls = calls.lock().unwrap();
+        assert!(calls.aborted);
+        assert_eq!(calls.completed, None);
+    }
+
+    #[tokio::test]
+    async fn failed_part_aborts_the_upload() {
+        let (upload, calls) = mock_upload(Some(2));
+        let (mut writer, upload) = spawn_streamed_upload(upload, "exports/a.csv".to_string(), 4, 2);
+        // Task upload dừng khi part 2 lỗi, nên ghi phần còn lại có thể lỗi vì pipe đã đóng.
+        let _ = writer.write_all(b"id,name\n1,a\n").await;
+        let _ = writer.shutdown().await;
+
+        let error = Box::new(upload).complete().await.unwrap_err();
+
+        assert!(error.to_string().contains("part 2 failed"), "{}", error);
+        let calls = calls.lock().unwrap();
+        assert!(calls.aborted);
+        assert_eq!(calls.completed, None);
+    }
+
+    #[tokio::test]
+    async fn empty_streamed_file_is_uploaded_as_one_empty_part() {
+        let (upload, calls) = mock_upload(None);
+        let (mut writer, upload) = spawn_streamed_upload(upload, "exports/a.csv".to_string(), 4, 2);
+        writer.shutdown().await.unwrap();
+
+        let (_, checksum) = Box::new(upload).complete().await.unwrap();
+
+        assert_eq!(checksum.size_bytes, 0);
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.parts, vec![(1, Vec::new())]);
+        assert_eq!(calls.completed.as_ref().map(|(parts, _)| parts.clone()), Some(vec![1]));
+    }
+
+    #[tokio::test]
+    async fn local_file_upload_is_aborted_when_the_file_cannot_be_read() {
+        let (upload, calls) = mock_upload(None);
+        let parts = match tokio::fs::File::open("/nonexistent/export.csv").await {
+            Ok(file) => upload_parts(&upload, file, 4, 2).await,
+            Err(e) => Err(e).context("Failed to read export file for upload"),
+        };
+
+        assert!(finish_multipart_upload(&upload, parts, None).await.is_err());
+        let calls = calls.lock().unwrap();
+        assert!(calls.aborted);
+        assert_eq!(calls.completed, None);
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Độ dài tối đa (byte) của một thành phần đường dẫn, theo giới hạn tên file của các filesystem phổ biến.
+pub const MAX_COMPONENT_BYTES: usize = 255;
+
+/// Tên thiết bị của Windows: không dùng được làm tên file, kể cả khi có phần mở rộng (`con.xlsx`).
+const RESERVED_WINDOWS_NAMES: [&str; 22] = [
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9", "lpt1", "lpt2",
+    "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Ký tự không được có trong tên file trên Windows (ngoài dấu phân cách thư mục).
+const RESERVED_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Ký tự Unicode trông giống dấu phân cách hoặc dấu chấm: có thể thành `/`, `\` hoặc `..` sau khi
+/// một hệ thống khác chuẩn hóa tên file (NFKC), hoặc đánh lừa người đọc log.
+const LOOKALIKE_CHARS: [char; 12] = [
+    '\u{2215}', // DIVISION SLASH
+    '\u{2044}', // FRACTION SLASH
+    '\u{29F8}', // BIG SOLIDUS
+    '\u{FF0F}', // FULLWIDTH SOLIDUS
+    '\u{29F9}', // BIG REVERSE SOLIDUS
+    '\u{2216}', // SET MINUS
+    '\u{FE68}', // SMALL REVERSE SOLIDUS
+    '\u{FF3C}', // FULLWIDTH REVERSE SOLIDUS
+    '\u{2024}', // ONE DOT LEADER
+    '\u{2025}', // TWO DOT LEADER
+    '\u{FE52}', // SMALL FULL STOP
+    '\u{FF0E}', // FULLWIDTH FULL STOP
+];
+
+/// Thành phần đường dẫn (tenant, `output_subdir`, thư mục ngày, tên file...) bị từ chối, hoặc đường dẫn
+/// cuối cùng nằm ngoài thư mục gốc. Request thất bại với mã UNSAFE_PATH.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsafePath {
+    pub component: String,
+    pub reason: &'static str,
+}
+
+impl UnsafePath {
+    fn new(component: &str, reason: &'static str) -> Self {
+        Self { component: component.escape_debug().to_string(), reason }
+    }
+}
+
+impl fmt::Display for UnsafePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unsafe path component \"{}\": {}", self.component, self.reason)
+    }
+}
+
+impl std::error::Error for UnsafePath {}
+
+/// Kiểm tra một thành phần đường dẫn (tên một thư mục hoặc một file), không chuẩn hóa hay sửa giá trị:
+/// giá trị không an toàn bị từ chối thay vì được đoán ý.
+pub fn check_component(component: &str) -> Result<(), UnsafePath> {
+    let reject = |reason| Err(UnsafePath::new(component, reason));
+    if component.is_empty() {
+        return reject("is empty");
+    }
+    if component.len() > MAX_COMPONENT_BYTES {
+        return reject("is longer than 255 bytes");
+    }
+    if component == "." || component == ".." {
+        return reject("is a relative directory reference");
+    }
+    if component.starts_with('.') {
+        return reject("starts with a dot");
+    }
+    if component.ends_with(['.', ' ']) {
+        return reject("ends with a dot or a space");
+    }
+    if component.contains(['/', '\\']) {
+        return reject("contains a path separator");
+    }
+    if component.chars().any(char::is_control) {
+        return reject("contains a control character");
+    }
+    if component.chars().any(is_invisible) {
+        return reject("contains an invisible or bidirectional formatting character");
+    }
+    if component.contains(LOOKALIKE_CHARS) {
+        return reject("contains a character that looks like a path separator or a dot");
+    }
+    if component.contains(RESERVED_CHARS) {
+        return reject("contains a character reserved on Windows");
+    }
+    let device = component.split('.').next().unwrap_or_default().trim_end().to_ascii_lowercase();
+    if RESERVED_WINDOWS_NAMES.contains(&device.as_str()) {
+        return reject("is a reserved Windows device name");
+    }
+    Ok(())
+}
+
+/// Kiểm tra đường dẫn tương đối nhiều cấp (`finance/monthly`, thư mục ngày `2026/10/15`): dấu phân cách
+/// duy nhất được chấp nhận là `/`, và mọi cấp phải qua `check_component`.
+pub fn check_relative(relative: &str) -> Result<(), UnsafePath> {
+    if relative.starts_with('/') {
+        return Err(UnsafePath::new(relative, "is an absolute path"));
+    }
+    relative.split('/').try_for_each(check_component)
+}
+
+/// Dựng đường dẫn `root/part/...` từ các phần tương đối (mỗi phần có thể nhiều cấp, xem `check_relative`).
+/// Mọi đường dẫn chứa giá trị từ payload hoặc config đều phải đi qua đây trước khi chạm tới filesystem.
+pub fn join_under_root<'a>(root: &Path, parts: impl IntoIterator<Item = &'a str>) -> Result<PathBuf, UnsafePath> {
+    let mut path = root.to_path_buf();
+    for part in parts {
+        check_relative(part)?;
+        path.extend(part.split('/'));
+    }
+    Ok(path)
+}
+
+/// Xác nhận `path` nằm hẳn bên trong `root` sau khi resolve symlink: phần đã tồn tại của đường dẫn được
+/// canonicalize, phần chưa tồn tại (đã qua `join_under_root`) được nối lại phía sau.
+pub async fn ensure_under_root(root: &Path, path: &Path) -> Result<PathBuf, UnsafePath> {
+    let display = path.to_string_lossy();
+    let canonical_root = canonicalize_existing(root)
+        .await
+        .ok_or_else(|| UnsafePath::new(&root.to_string_lossy(), "export root cannot be resolved"))?;
+    let resolved = canonicalize_existing(path)
+        .await
+        .ok_or_else(|| UnsafePath::new(&display, "cannot be resolved"))?;
+    if resolved == canonical_root || !resolved.starts_with(&canonical_root) {
+        return Err(UnsafePath::new(&display, "resolves outside the export root"));
+    }
+    Ok(resolved)
+}
+
+/// Canonicalize tổ tiên gần nhất đã tồn tại của `path` rồi nối lại phần chưa tồn tại.
+/// Phần chưa tồn tại không được kết thúc bằng `..` (`file_name` là `None`), vì không resolve được khi chưa có thư mục.
+async fn canonicalize_existing(path: &Path) -> Option<PathBuf> {
+    let mut missing = Vec::new();
+    let mut current = path;
+    loop {
+        match tokio::fs::canonicalize(current).await {
+            Ok(mut canonical) => {
+                for part in missing.iter().rev() {
+                    canonical.push(part);
+                }
+                return Some(canonical);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                missing.push(current.file_name()?);
+                current = current.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            }
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Ký tự không hiển thị hoặc đảo chiều hiển thị (zero-width, bidi override, BOM).
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn rejection(component: &str) -> &'static str {
+        check_component(component).unwrap_err().reason
+    }
+
+    /// Thư mục tạm riêng của một test, bị xóa khi drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("safe-path-test-{}", Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn traversal_is_rejected() {
+        assert_eq!(check_relative("../../etc/cron.d/x").unwrap_err().reason, "is a relative directory reference");
+        assert_eq!(check_relative("finance/../../x").unwrap_err().reason, "is a relative directory reference");
+        assert_eq!(check_relative("/etc/cron.d").unwrap_err().reason, "is an absolute path");
+        assert_eq!(rejection("..\\..\\x"), "starts with a dot");
+        assert_eq!(rejection("a\\b"), "contains a path separator");
+        assert!(join_under_root(Path::new("/exports"), ["acme", "../../etc/cron.d/x"]).is_err());
+    }
+
+    #[test]
+    fn lookalike_separators_and_dots_are_rejected() {
+        for c in LOOKALIKE_CHARS {
+            let component = format!("a{}b", c);
+            assert_eq!(
+                rejection(&component),
+                "contains a character that looks like a path separator or a dot",
+                "{:?}",
+                component
+            );
+        }
+    }
+
+    #[test]
+    fn invisible_and_bidi_characters_are_rejected() {
+        // Zero-width space, RIGHT-TO-LEFT OVERRIDE (`report\u{202E}xslx.exe` hiển thị như `reportexe.xlsx`), word joiner, BOM.
+        for c in ['\u{200B}', '\u{200F}', '\u{202E}', '\u{2066}', '\u{FEFF}'] {
+            assert_eq!(rejection(&format!("report{}xslx.exe", c)), "contains an invisible or bidirectional formatting character");
+        }
+        assert_eq!(rejection("report\u{0007}.csv"), "contains a control character");
+    }
+
+    #[test]
+    fn reserved_windows_names_are_rejected() {
+        for component in ["CON.xlsx", "con", "Nul.csv.gz", "com1.txt", "LPT9", "aux .csv"] {
+            assert_eq!(rejection(component), "is a reserved Windows device name", "{}", component);
+        }
+        assert_eq!(rejection("report?.xlsx"), "contains a character reserved on Windows");
+        check_component("console.xlsx").unwrap();
+        check_component("com10.csv").unwrap();
+    }
+
+    #[test]
+    fn component_length_is_limited_to_255_bytes() {
+        check_component(&"a".repeat(MAX_COMPONENT_BYTES)).unwrap();
+        assert_eq!(rejection(&"a".repeat(MAX_COMPONENT_BYTES + 1)), "is longer than 255 bytes");
+        // Giới hạn tính theo byte: 128 ký tự "đ" (2 byte UTF-8) là 256 byte.
+        assert_eq!(rejection(&"đ".repeat(128)), "is longer than 255 bytes");
+    }
+
+    #[test]
+    fn ordinary_names_are_accepted() {
+        for component in ["acme", "2026", "Báo cáo tháng 10.xlsx", "orders_2026-10-15.csv.gz"] {
+            check_component(component).unwrap();
+        }
+        let path = join_under_root(Path::new("/exports"), ["acme", "finance/monthly", "2026/10/15"]).unwrap();
+        assert_eq!(path, Path::new("/exports/acme/finance/monthly/2026/10/15"));
+    }
+
+    #[tokio::test]
+    async fn paths_inside_the_root_are_resolved() {
+        let root = TempDir::new();
+        std::fs::create_dir_all(root.0.join("acme")).unwrap();
+        let canonical_root = std::fs::canonicalize(&root.0).unwrap();
+
+        let resolved = ensure_under_root(&root.0, &root.0.join("acme/2026/10")).await.unwrap();
+
+        assert_eq!(resolved, canonical_root.join("acme/2026/10"));
+        assert_eq!(
+            ensure_under_root(&root.0, &root.0).await.unwrap_err().reason,
+            "resolves outside the export root"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlink_out_of_the_root_is_rejected() {
+        let root = TempDir::new();
+        let outside = TempDir::new();
+        std::os::unix::fs::symlink(&outside.0, root.0.join("acme")).unwrap();
+
+        let path = join_under_root(&root.0, ["acme", "2026/10"]).unwrap();
+        let error = ensure_under_root(&root.0, &path).await.unwrap_err();
+
+        assert_eq!(error.reason, "resolves outside the export root");
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+use std::path::Path;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+use crate::config::SftpConfig;
+use crate::services::safe_path::{check_relative, UnsafePath};
+
+/// Mã lỗi SFTP `SSH_FX_PERMISSION_DENIED`.
+#[cfg(feature = "sftp")]
+const SFTP_PERMISSION_DENIED: i32 = 3;
+
+/// Lỗi upload SFTP, phân loại để người vận hành biết cần sửa gì (host key, key đăng nhập, quyền ghi...).
+#[derive(Debug)]
+pub enum SftpError {
+    /// Không kết nối được (DNS, TCP, SSH handshake, timeout). Có thể thử lại.
+    Connect(anyhow::Error),
+    /// Host key của server khác với SFTP_KNOWN_HOSTS: server đã đổi key hoặc kết nối bị chặn giữa đường.
+    HostKeyMismatch { host: String },
+    /// Server không có trong SFTP_KNOWN_HOSTS (hoặc không đọc được file).
+    UnknownHostKey { host: String, reason: String },
+    /// Server từ chối username/private key.
+    Auth(anyhow::Error),
+    /// Không có quyền ghi vào thư mục đích.
+    PermissionDenied(anyhow::Error),
+    /// Lỗi khi ghi, đổi tên hoặc kiểm tra file trên server (kể cả kích thước không khớp).
+    Transfer(anyhow::Error),
+}
+
+impl SftpError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, SftpError::Connect(_))
+    }
+
+    pub fn user_message(&self) -> String {
+        match self {
+            SftpError::Connect(_) => "Could not connect to the SFTP server. Please try again later.".to_string(),
+            SftpError::HostKeyMismatch { .. } => {
+                "The SFTP server's host key does not match the known host key. The upload was refused.".to_string()
+            }
+            SftpError::UnknownHostKey { .. } => "The SFTP server's host key is not in the known hosts file.".to_string(),
+            SftpError::Auth(_) => "SFTP authentication failed. Check the username and private key.".to_string(),
+            SftpError::PermissionDenied(_) => {
+                "Permission denied on the SFTP server. Check write access to the remote directory.".to_string()
+            }
+            SftpError::Transfer(_) => "The file could not be uploaded to the SFTP server.".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SftpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SftpError::Connect(e) => write!(f, "SFTP connection failed: {:#}", e),
+            SftpError::HostKeyMismatch { host } => write!(f, "SFTP host key mismatch for {}", host),
+            SftpError::UnknownHostKey { host, reason } => write!(f, "SFTP host key of {} not verified: {}", host, reason),
+            SftpError::Auth(e) => write!(f, "SFTP authentication failed: {:#}", e),
+            SftpError::PermissionDenied(e) => write!(f, "SFTP permission denied: {:#}", e),
+            SftpError::Transfer(e) => write!(f, "SFTP upload failed: {:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for SftpError {}
+
+/// Kết quả upload: đường dẫn trên server, kích thước đã kiểm tra và bản local đã bị xóa hay chưa.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SftpUpload {
+    pub remote_path: String,
+    pub size_bytes: u64,
+    pub local_deleted: bool,
+}
+
+/// `remote_dir` của payload được nối vào SFTP_REMOTE_DIR: mọi cấp phải qua `safe_path::check_component`.
+pub fn validate_remote_dir(remote_dir: &str) -> Result<(), UnsafePath> {
+    check_relative(remote_dir.trim_matches('/'))
+}
+
+/// Giao file đã export lên SFTP (bật bằng cargo feature `sftp`). File được ghi vào `{tên file}.tmp`
+/// rồi đổi tên, nên đối tác không bao giờ thấy file ghi dở; kích thước trên server được so với file local.
+pub struct SftpDelivery {
+    config: SftpConfig,
+}
+
+impl SftpDelivery {
+    pub fn new(config: SftpConfig) -> Self {
+        Self { config }
+    }
+
+    #[instrument(skip(self), fields(request_id = %request_id, host = %self.config.host))]
+    pub async fn upload(&self, request_id: Uuid, file_path: &str, remote_dir: Option<&str>) -> Result<SftpUpload, SftpError> {
+        let remote_dir = match remote_dir {
+            Some(dir) => format!("{}/{}", self.config.remote_dir.trim_end_matches('/'), dir.trim_matches('/')),
+            None => self.config.remote_dir.clone(),
+        };
+        let config = self.config.clone();
+        let local_path = file_path.to_string();
+        let (remote_path, size_bytes) = tokio::task::spawn_blocking(move || upload_file(&config, &local_path, &remote_dir))
+            .await
+            .map_err(|e| SftpError::Transfer(anyhow::Error::new(e).context("SFTP upload task panicked")))??;
+        info!("📤 Uploaded export of request {} to sftp://{}{} ({} bytes).", request_id, self.config.host, remote_path, size_bytes);
+
+        let mut local_deleted = false;
+        if self.config.delete_local {
+            match tokio::fs::remove_file(file_path).await {
+                Ok(()) => local_deleted = true,
+                Err(e) => warn!("Failed to delete local copy {} after SFTP upload: {:?}", file_path, e),
+            }
+        }
+        Ok(SftpUpload { remote_path, size_bytes, local_deleted })
+    }
+}
+
+/// Kết nối, kiểm tra host key, đăng nhập rồi upload. Trả về đường dẫn trên server và kích thước file.
+#[cfg(feature = "sftp")]
+fn upload_file(config: &SftpConfig, local_path: &str, remote_dir: &str) -> Result<(String, u64), SftpError> {
+    use anyhow::Context;
+    use ssh2::{RenameFlags, Session};
+    use std::net::{TcpStream, ToSocketAddrs};
+    use std::time::Duration;
+
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let address = (config.host.as_str(), config.port)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {}", config.host))
+        .map_err(SftpError::Connect)?
+        .next()
+        .with_context(|| format!("{} has no address", config.host))
+        .map_err(SftpError::Connect)?;
+    let tcp = TcpStream::connect_timeout(&address, timeout)
+        .with_context(|| format!("Failed to connect to {}:{}", config.host, config.port))
+        .map_err(SftpError::Connect)?;
+
+    let mut session = Session::new().context("Failed to create SSH session").map_err(SftpError::Connect)?;
+    session.set_timeout(timeout.as_millis() as u32);
+    session.set_tcp_stream(tcp);
+    session.handshake().context("SSH handshake failed").map_err(SftpError::Connect)?;
+    verify_host_key(&session, config)?;
+
+    session
+        .userauth_pubkey_file(
+            &config.username,
+            None,
+            Path::new(&config.private_key_path),
+            config.private_key_passphrase.as_deref(),
+        )
+        .with_context(|| format!("Public key authentication failed for user '{}'", config.username))
+        .map_err(SftpError::Auth)?;
+    if !session.authenticated() {
+        return Err(SftpError::Auth(anyhow::anyhow!("Server did not accept the key of user '{}'", config.username)));
+    }
+
+    let sftp = session.sftp().context("Failed to start SFTP subsystem").map_err(SftpError::Transfer)?;
+    let file_name = Path::new(local_path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("Export file has no file name")
+        .map_err(SftpError::Transfer)?;
+    let remote_path = format!("{}/{}", remote_dir.trim_end_matches('/'), file_name);
+    let temp_path = format!("{}.tmp", remote_path);
+
+    let mut local = std::fs::File::open(local_path)
+        .context("Failed to open export file for SFTP upload")
+        .map_err(SftpError::Transfer)?;
+    let local_size = local
+        .metadata()
+        .context("Failed to read export file metadata")
+        .map_err(SftpError::Transfer)?
+        .len();
+    let mut remote = sftp
+        .create(Path::new(&temp_path))
+        .map_err(|e| sftp_error(e, format!("Failed to create {}", temp_path)))?;
+    std::io::copy(&mut local, &mut remote)
+        .with_context(|| format!("Failed to write {}", temp_path))
+        .map_err(SftpError::Transfer)?;
+    remote.close().map_err(|e| sftp_error(e, format!("Failed to close {}", temp_path)))?;
+
+    sftp.rename(
+        Path::new(&temp_path),
+        Path::new(&remote_path),
+        Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE),
+    )
+    .map_err(|e| sftp_error(e, format!("Failed to rename {} to {}", temp_path, remote_path)))?;
+
+    let remote_size = sftp
+        .stat(Path::new(&remote_path))
+        .map_err(|e| sftp_error(e, format!("Failed to stat {}", remote_path)))?
+        .size;
+    if remote_size != Some(local_size) {
+        return Err(SftpError::Transfer(anyhow::anyhow!(
+            "Size of {} on the server ({:?} bytes) does not match the local file ({} bytes)",
+            remote_path,
+            remote_size,
+            local_size
+        )));
+    }
+    Ok((remote_path, local_size))
+}
+
+#[cfg(not(feature = "sftp"))]
+fn upload_file(_config: &SftpConfig, _local_path: &str, _remote_dir: &str) -> Result<(String, u64), SftpError> {
+    Err(SftpError::Transfer(anyhow::anyhow!(
+        "SFTP delivery is not supported by this build: enable the `sftp` feature"
+    )))
+}
+
+/// Host key phải khớp một entry của SFTP_KNOWN_HOSTS; host lạ không được tự động tin cậy.
+#[cfg(feature = "sftp")]
+fn verify_host_key(session: &ssh2::Session, config: &SftpConfig) -> Result<(), SftpError> {
+    use ssh2::{CheckResult, KnownHostFileKind};
+
+    let unknown = |reason: String| SftpError::UnknownHostKey { host: config.host.clone(), reason };
+    let mut known_hosts = session.known_hosts().map_err(|e| unknown(e.to_string()))?;
+    known_hosts
+        .read_file(Path::new(&config.known_hosts_path), KnownHostFileKind::OpenSSH)
+        .map_err(|e| unknown(format!("failed to read {}: {}", config.known_hosts_path, e)))?;
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| unknown("server sent no host key".to_string()))?;
+    match known_hosts.check_port(&config.host, config.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(SftpError::HostKeyMismatch { host: config.host.clone() }),
+        CheckResult::NotFound => Err(unknown(format!("no entry in {}", config.known_hosts_path))),
+        CheckResult::Failure => Err(unknown("host key check failed".to_string())),
+    }
+}
+
+/// Lỗi "permission denied" của SFTP được tách riêng, các lỗi khác là lỗi upload.
+#[cfg(feature = "sftp")]
+fn sftp_error(e: ssh2::Error, context: String) -> SftpError {
+    if e.code() == ssh2::ErrorCode::SFTP(SFTP_PERMISSION_DENIED) {
+        SftpError::PermissionDenied(anyhow::Error::new(e).context(context))
+    } else {
+        SftpError::Transfer(anyhow::Error::new(e).context(context))
+    }
+}
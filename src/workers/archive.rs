@@ -0,0 +1,55 @@
+use anyhow::Result;
+use chrono::Duration as ChronoDuration;
+use metrics::{counter, histogram};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, instrument};
+
+use crate::clock::Clock;
+use crate::config::AppConfig;
+use crate::services::db_store::DbStore;
+
+/// Worker chạy định kỳ để chuyển các request cũ đã kết thúc sang bảng export_requests_archive
+/// (EXPORT_ARCHIVE_AFTER_DAYS).
+pub async fn run_archive_worker<D: DbStore>(
+    config: Arc<AppConfig>,
+    clock: Arc<dyn Clock>,
+    archive_after_days: i64,
+    db_store: Arc<D>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.export_archive_interval_secs));
+    info!(
+        "🗄️ Archive worker started. Archive after: {} day(s), batch size: {}, interval: {}s.",
+        archive_after_days, config.export_archive_batch_size, config.export_archive_interval_secs
+    );
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = archive_old_requests(
+            clock.as_ref(),
+            archive_after_days,
+            config.export_archive_batch_size,
+            db_store.as_ref(),
+        )
+        .await
+        {
+            error!("Archive run failed: {:?}", e);
+        }
+    }
+}
+
+#[instrument(skip(clock, db_store))]
+async fn archive_old_requests<D: DbStore>(
+    clock: &dyn Clock,
+    archive_after_days: i64,
+    batch_size: i64,
+    db_store: &D,
+) -> Result<()> {
+    let before = clock.now_utc() - ChronoDuration::days(archive_after_days);
+    let archived = db_store.archive_requests(before, batch_size).await?;
+
+    counter!("excel_export_archived_requests_total", archived as u64);
+    histogram!("excel_export_archived_requests_per_run", archived as f64);
+    info!("🏁 Archive run finished. Archived {} request(s).", archived);
+    Ok(())
+}
@@ -0,0 +1,8 @@
+pub mod archive;
+pub mod notification_retry;
+pub mod pending_poll;
+pub mod pool_metrics;
+pub mod retention;
+pub mod status_gauges;
+pub mod stuck_recovery;
+pub mod upload_cleanup;
@@ -0,0 +1,228 @@
+use anyhow::Result;
+use chrono::Duration as ChronoDuration;
+use metrics::increment;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+
+use crate::clock::Clock;
+use crate::config::AppConfig;
+use crate::models::{Delivery, ExportNotification, NotificationStage};
+use crate::services::db_store::DbStore;
+use crate::services::email_delivery::{attachment_content_type, delivery_from_payload, EmailDelivery};
+use crate::services::export_service::file_download_url;
+use crate::services::file_exporter::FileExporter;
+use crate::services::notifier::Notifier;
+
+/// Worker chạy định kỳ để gửi lại các thông báo chưa gửi được (notification_sent = false).
+pub async fn run_notification_retry_worker<D, F, N>(
+    config: Arc<AppConfig>,
+    clock: Arc<dyn Clock>,
+    db_store: Arc<D>,
+    file_exporter: Arc<F>,
+    notifier: Arc<N>,
+    email_delivery: Option<Arc<EmailDelivery>>,
+) where
+    D: DbStore,
+    F: FileExporter,
+    N: Notifier,
+{
+    let mut interval = tokio::time::interval(Duration::from_secs(config.notification_retry_interval_secs));
+    info!(
+        "🔁 Notification retry worker started. Interval: {}s, batch size: {}.",
+        config.notification_retry_interval_secs, config.notification_retry_batch_size
+    );
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = retry_unsent_notifications(
+            &config,
+            clock.as_ref(),
+            db_store.as_ref(),
+            file_exporter.as_ref(),
+            notifier.as_ref(),
+            email_delivery.as_deref(),
+        )
+        .await
+        {
+            error!("Notification retry run failed: {:?}", e);
+        }
+    }
+}
+
+#[instrument(skip_all)]
+async fn retry_unsent_notifications<D, F, N>(
+    config: &AppConfig,
+    clock: &dyn Clock,
+    db_store: &D,
+    file_exporter: &F,
+    notifier: &N,
+    email_delivery: Option<&EmailDelivery>,
+) -> Result<()>
+where
+    D: DbStore,
+    F: FileExporter,
+    N: Notifier,
+{
+    let requests = db_store
+        .claim_unsent_notifications(config.notification_retry_batch_size)
+        .await?;
+
+    for request in requests {
+        let public_file_url = match request.file_path.as_deref() {
+            Some(p) => {
+                file_download_url(
+                    file_exporter,
+                    &config.notification_service_url,
+                    &config.excel_export_path,
+                    request.tenant(),
+                    p,
+                    request.expires_at,
+                    clock.now_utc(),
+                )
+                .await
+            }
+            None => None,
+        };
+        let part_paths = request.part_paths();
+        let file_part_urls = match &public_file_url {
+            Some(first_url) if !part_paths.is_empty() => {
+                let mut urls = vec![first_url.clone()];
+                for part in &part_paths {
+                    if let Some(url) = file_download_url(
+                        file_exporter,
+                        &config.notification_service_url,
+                        &config.excel_export_path,
+                        request.tenant(),
+                        part,
+                        request.expires_at,
+                        clock.now_utc(),
+                    )
+                    .await
+                    {
+                        urls.push(url);
+                    }
+                }
+                Some(urls)
+            }
+            _ => None,
+        };
+
+        // Request có skip_notification, hoặc webhook đã gửi được ở lần trước (webhook_sent), chỉ còn email
+        // (nếu có) cần gửi lại: mỗi kênh chỉ được gửi lại khi chính kênh đó thất bại.
+        let send_webhook = !request.skip_notification() && !request.webhook_sent;
+        let mut notify_result = if !send_webhook {
+            Ok(())
+        } else {
+            notifier
+                .send_notification(&ExportNotification {
+                    request_id: request.id,
+                    status: request.status.as_str().to_string(),
+                    stage: NotificationStage::for_final_status(request.status),
+                    file_url: public_file_url.clone(),
+                    error_message: request.error_message.clone(),
+                    expires_at: request.expires_at,
+                    duration_ms: request.duration_ms,
+                    estimated_completion_at: None,
+                    timings: config.notify_include_timings.then(|| request.timings()),
+                    truncated_by_limit: request.truncated_by_limit,
+                    original_file_name: request.original_file_name.clone(),
+                    original_size_bytes: request.original_size_bytes,
+                    is_protected: request.is_protected,
+                    file_name: request.file_name.clone(),
+                    content_type: request
+                        .file_path
+                        .as_deref()
+                        .map(|path| attachment_content_type(path).to_string()),
+                    file_size_bytes: request.file_size_bytes,
+                    rows_exported: request.row_count,
+                    file_part_urls,
+                })
+                .await
+        };
+        if notify_result.is_ok() {
+            if let (Some(email_delivery), Some(Delivery::Email { to })) =
+                (email_delivery, delivery_from_payload(&request.request_payload))
+            {
+                notify_result = email_delivery
+                    .deliver(
+                        request.id,
+                        &to,
+                        request.status.as_str(),
+                        request.file_path.as_deref().filter(|_| file_exporter.stores_locally()),
+                        public_file_url.as_deref(),
+                        request.error_message.as_deref(),
+                        None,
+                    )
+                    .await;
+                if notify_result.is_err() && send_webhook {
+                    if let Err(e) = db_store.mark_webhook_sent(request.id).await {
+                        warn!("Failed to record webhook delivery for request {}: {:?}", request.id, e);
+                    }
+                }
+            }
+        }
+
+        match notify_result {
+            Ok(_) => {
+                db_store.update_notification_sent_status(request.id, true).await?;
+                increment!("excel_export_notification_recovered_total");
+                info!(
+                    "✅ Recovered notification for request {} after {} failed attempt(s).",
+                    request.id, request.notification_attempts
+                );
+            }
+            Err(e) => {
+                let backoff = notification_backoff(
+                    request.notification_attempts,
+                    config.notification_retry_interval_secs,
+                    config.notification_retry_max_backoff_secs,
+                );
+                warn!(
+                    "Retry of notification for request {} failed: {:?}. Next attempt in {}s.",
+                    request.id, e, backoff.as_secs()
+                );
+                let next_retry_at = clock.now_utc() + ChronoDuration::from_std(backoff)?;
+                db_store.record_notification_failure(request.id, next_retry_at).await?;
+                increment!("excel_export_notification_retry_failed_total");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Exponential backoff theo số lần thất bại: base * 2^attempts, giới hạn bởi max.
+fn notification_backoff(attempts: i32, base_secs: u64, max_secs: u64) -> Duration {
+    let exponent = attempts.clamp(0, 16) as u32;
+    let secs = base_secs.saturating_mul(1u64 << exponent).min(max_secs);
+    Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn backoff_doubles_per_attempt_up_to_the_cap() {
+        let delays: Vec<u64> = (0..6).map(|attempts| notification_backoff(attempts, 60, 600).as_secs()).collect();
+
+        assert_eq!(delays, vec![60, 120, 240, 480, 600, 600]);
+        assert_eq!(notification_backoff(-1, 60, 600), Duration::from_secs(60));
+        assert_eq!(notification_backoff(i32::MAX, 60, u64::MAX), Duration::from_secs(60 << 16));
+    }
+
+    #[test]
+    fn next_retry_is_due_exactly_after_the_backoff() {
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap());
+        let backoff = notification_backoff(2, 60, 3600);
+        let next_retry_at = clock.now_utc() + ChronoDuration::from_std(backoff).unwrap();
+
+        clock.advance(backoff - Duration::from_secs(1));
+        assert!(clock.now_utc() < next_retry_at);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.now_utc(), next_retry_at);
+    }
+}
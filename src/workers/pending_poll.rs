@@ -0,0 +1,177 @@
+use anyhow::Result;
+use metrics::counter;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, info_span, instrument};
+
+use crate::kafka_consumer::handle_request;
+use crate::services::db_store::DbStore;
+use crate::services::export_service::ExportService;
+use crate::services::file_exporter::FileExporter;
+use crate::services::notifier::Notifier;
+
+/// Worker chạy định kỳ để xử lý các request PENDING lấy thẳng từ bảng (PENDING_POLL_INTERVAL_SECS):
+/// request có message Kafka bị mất hoặc chưa tới khi Kafka không hoạt động, và request có `next_retry_at` đã tới.
+/// Request được claim sang PROCESSING trước khi xử lý, nên nhiều replica cùng chạy worker không xử lý trùng;
+/// message Kafka tới sau của cùng request sẽ bị bỏ qua nhờ khóa xử lý hoặc vì request đã ở trạng thái cuối.
+pub async fn run_pending_poll_worker<D, F, N>(
+    interval_secs: u64,
+    batch_size: i64,
+    db_store: Arc<D>,
+    export_service: Arc<ExportService<D, F, N>>,
+) where
+    D: DbStore,
+    F: FileExporter,
+    N: Notifier,
+{
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    info!("📥 Pending request poller started. Interval: {}s, batch size: {}.", interval_secs, batch_size);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll_pending_requests(batch_size, db_store.as_ref(), &export_service).await {
+            error!("Pending request poll failed: {:?}", e);
+        }
+    }
+}
+
+/// Claim tối đa `batch_size` request PENDING và xử lý chúng đồng thời như request nhận từ Kafka
+/// (`handle_request`: khóa xử lý, bắt panic). Trả về số request đã claim.
+#[instrument(skip(db_store, export_service))]
+async fn poll_pending_requests<D, F, N>(
+    batch_size: i64,
+    db_store: &D,
+    export_service: &Arc<ExportService<D, F, N>>,
+) -> Result<usize>
+where
+    D: DbStore,
+    F: FileExporter,
+    N: Notifier,
+{
+    let requests = db_store.claim_next_pending(batch_size).await?;
+    if requests.is_empty() {
+        return Ok(0);
+    }
+
+    counter!("excel_export_polled_requests_total", requests.len() as u64);
+    info!("Processing {} request(s) claimed from the table.", requests.len());
+    let handles = requests.iter().map(|request| {
+        let span = info_span!("pending_request", request_id = %request.id, user_id = tracing::field::Empty);
+        handle_request(Arc::clone(export_service), request.id, span)
+    });
+    futures::future::join_all(handles).await;
+    Ok(requests.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::models::{ExportRequest, ExportStatus};
+    use crate::services::db_store::DbError;
+    use crate::services::file_exporter::LocalFileExporter;
+    use crate::services::mock_store::MockDbStore;
+    use crate::services::notifier::RecordingNotifier;
+    use uuid::Uuid;
+
+    type TestService = ExportService<MockDbStore, LocalFileExporter, RecordingNotifier>;
+
+    /// Service ghi file vào một thư mục tạm riêng (trả về để test xóa khi xong).
+    fn service(db_store: &Arc<MockDbStore>, notifier: &Arc<RecordingNotifier>) -> (Arc<TestService>, std::path::PathBuf) {
+        let export_dir = std::env::temp_dir().join(format!("excel-export-test-{}", Uuid::new_v4()));
+        let config = AppConfig::for_test(&export_dir.to_string_lossy());
+        let file_exporter = LocalFileExporter::new(
+            config.formula_escape,
+            config.excel_max_rows_per_sheet,
+            config.parquet,
+            config.html_max_rows,
+            config.pdf.clone(),
+        );
+        let export_service =
+            ExportService::for_test(Arc::clone(db_store), Arc::new(file_exporter), Arc::clone(notifier), config);
+        (Arc::new(export_service), export_dir)
+    }
+
+    fn pending_request(db_store: &MockDbStore, priority: i32) -> Uuid {
+        let mut request = ExportRequest::for_test(serde_json::json!({
+            "start_date": "2024-01-01",
+            "end_date": "2024-01-31",
+            "format": "csv"
+        }));
+        request.priority = priority;
+        db_store.insert(request, ExportStatus::Pending)
+    }
+
+    #[tokio::test]
+    async fn claimed_requests_are_processed_and_not_claimed_again() {
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(2);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let (export_service, export_dir) = service(&db_store, &notifier);
+        let first = pending_request(&db_store, 0);
+        let second = pending_request(&db_store, 0);
+
+        let claimed = poll_pending_requests(10, db_store.as_ref(), &export_service).await.unwrap();
+        let claimed_again = poll_pending_requests(10, db_store.as_ref(), &export_service).await.unwrap();
+        let _ = std::fs::remove_dir_all(&export_dir);
+
+        assert_eq!((claimed, claimed_again), (2, 0));
+        for request_id in [first, second] {
+            assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Completed);
+            assert_eq!(notifier.statuses(request_id), vec!["COMPLETED".to_string()]);
+        }
+        assert_eq!(db_store.calls("unlock_request"), 2);
+    }
+
+    #[tokio::test]
+    async fn batch_size_limits_a_run_and_higher_priority_goes_first() {
+        let db_store = Arc::new(MockDbStore::new());
+        db_store.set_product_rows(1);
+        let notifier = Arc::new(RecordingNotifier::new());
+        let (export_service, export_dir) = service(&db_store, &notifier);
+        let normal = pending_request(&db_store, 0);
+        let urgent = pending_request(&db_store, 5);
+
+        let claimed = poll_pending_requests(1, db_store.as_ref(), &export_service).await.unwrap();
+
+        assert_eq!(claimed, 1);
+        assert_eq!(db_store.request(urgent).unwrap().status, ExportStatus::Completed);
+        assert_eq!(db_store.request(normal).unwrap().status, ExportStatus::Pending);
+        poll_pending_requests(1, db_store.as_ref(), &export_service).await.unwrap();
+        let _ = std::fs::remove_dir_all(&export_dir);
+        assert_eq!(db_store.request(normal).unwrap().status, ExportStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn request_whose_retry_time_has_not_come_is_left_pending() {
+        let db_store = Arc::new(MockDbStore::new());
+        let notifier = Arc::new(RecordingNotifier::new());
+        let (export_service, export_dir) = service(&db_store, &notifier);
+        let mut request = ExportRequest::for_test(serde_json::json!({"start_date": "2024-01-01", "end_date": "2024-01-31"}));
+        request.next_retry_at = Some(chrono::Utc::now() + chrono::Duration::minutes(5));
+        let request_id = db_store.insert(request, ExportStatus::Pending);
+
+        let claimed = poll_pending_requests(10, db_store.as_ref(), &export_service).await.unwrap();
+        let _ = std::fs::remove_dir_all(&export_dir);
+
+        assert_eq!(claimed, 0);
+        assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Pending);
+        assert!(notifier.stages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn claim_error_is_returned_without_processing_anything() {
+        let db_store = Arc::new(MockDbStore::new());
+        let notifier = Arc::new(RecordingNotifier::new());
+        let (export_service, export_dir) = service(&db_store, &notifier);
+        let request_id = pending_request(&db_store, 0);
+        db_store.fail_next("claim_next_pending", DbError::Transient(anyhow::anyhow!("connection refused")));
+
+        let result = poll_pending_requests(10, db_store.as_ref(), &export_service).await;
+        let _ = std::fs::remove_dir_all(&export_dir);
+
+        assert!(result.is_err());
+        assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Pending);
+        assert_eq!(db_store.calls("try_lock_request"), 0);
+    }
+}
@@ -0,0 +1,41 @@
+use metrics::{gauge, histogram, increment};
+use sqlx::{Database, Pool};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::clock::Clock;
+
+/// Worker lấy mẫu trạng thái connection pool định kỳ: số connection, số connection rảnh và thời gian
+/// chờ acquire (đo bằng một lần acquire thử), để theo dõi pool bị bão hòa.
+pub async fn run_pool_metrics_worker<DB: Database>(
+    clock: Arc<dyn Clock>,
+    pool: Pool<DB>,
+    role: &'static str,
+    interval_secs: u64,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    info!("📈 Pool metrics worker started for the {} pool. Interval: {}s.", role, interval_secs);
+
+    loop {
+        interval.tick().await;
+        gauge!("excel_export_db_pool_size", pool.size() as f64, "pool" => role);
+        gauge!("excel_export_db_pool_idle", pool.num_idle() as f64, "pool" => role);
+
+        let acquire_start = clock.now_instant();
+        match pool.acquire().await {
+            Ok(connection) => {
+                histogram!(
+                    "excel_export_db_pool_acquire_seconds",
+                    clock.elapsed(acquire_start).as_secs_f64(),
+                    "pool" => role
+                );
+                drop(connection);
+            }
+            Err(e) => {
+                warn!("Failed to acquire a connection from the {} pool: {:?}", role, e);
+                increment!("excel_export_db_pool_acquire_failed_total", "pool" => role);
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use chrono::Duration as ChronoDuration;
+use metrics::{counter, increment};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+
+use crate::clock::Clock;
+use crate::config::AppConfig;
+use crate::services::db_store::DbStore;
+use crate::services::file_exporter::{FileExporter, METADATA_FILE_SUFFIX};
+use uuid::Uuid;
+
+/// Worker chạy định kỳ để xóa các file export quá hạn lưu trữ (EXPORT_RETENTION_DAYS).
+pub async fn run_retention_worker<D, F>(
+    config: Arc<AppConfig>,
+    clock: Arc<dyn Clock>,
+    retention_days: i64,
+    db_store: Arc<D>,
+    file_exporter: Arc<F>,
+) where
+    D: DbStore,
+    F: FileExporter,
+{
+    let mut interval = tokio::time::interval(Duration::from_secs(config.export_retention_interval_secs));
+    info!(
+        "🧹 Retention worker started. Retention: {} day(s), interval: {}s, dry run: {}.",
+        retention_days, config.export_retention_interval_secs, config.export_retention_dry_run
+    );
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = cleanup_expired_exports(
+            clock.as_ref(),
+            retention_days,
+            config.export_retention_dry_run,
+            config.export_dir_date_levels(),
+            db_store.as_ref(),
+            file_exporter.as_ref(),
+        )
+        .await
+        {
+            error!("Retention run failed: {:?}", e);
+        }
+    }
+}
+
+#[instrument(skip(clock, db_store, file_exporter))]
+async fn cleanup_expired_exports<D, F>(
+    clock: &dyn Clock,
+    retention_days: i64,
+    dry_run: bool,
+    date_dir_levels: usize,
+    db_store: &D,
+    file_exporter: &F,
+) -> Result<()>
+where
+    D: DbStore,
+    F: FileExporter,
+{
+    let before = clock.now_utc() - ChronoDuration::days(retention_days);
+    let expired = db_store.list_expired(before).await?;
+
+    let mut reclaimed_bytes: u64 = 0;
+    for request in expired {
+        let Some(file_path) = request.file_path.as_deref() else {
+            continue;
+        };
+
+        if dry_run {
+            info!("[dry-run] Would delete {} for request {}.", file_path, request.id);
+            continue;
+        }
+
+        // File theo tên nội dung (CONTENT_ADDRESSED_FILE_NAMES) có thể được request khác còn hạn dùng chung:
+        // request này hết hạn nhưng file được giữ lại cho request kia.
+        // Lỗi DB chỉ bỏ qua request này (thử lại ở lần chạy sau), không dừng cả lần chạy.
+        match db_store.is_file_shared(file_path, request.id).await {
+            Ok(false) => {}
+            Ok(true) => {
+                info!("Keeping {} of expired request {}: still used by another request.", file_path, request.id);
+                mark_expired(db_store, request.id).await;
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to check whether {} is shared for request {}: {:?}. Will retry next run.", file_path, request.id, e);
+                increment!("excel_export_retention_db_failed_total");
+                continue;
+            }
+        }
+
+        // Xóa file trước, chỉ đánh dấu EXPIRED khi xóa thành công; lỗi sẽ được thử lại ở lần chạy sau.
+        match file_exporter.delete_file(file_path).await {
+            Ok(bytes) => {
+                mark_expired(db_store, request.id).await;
+                reclaimed_bytes += bytes;
+                increment!("excel_export_retention_files_deleted_total");
+                // Các phần sau của file bị chia (MAX_FILE_SIZE_BYTES) và file `.meta.json` đi kèm file CSV/JSON Lines
+                // hết hạn cùng file; lỗi xóa chỉ được ghi log.
+                let part_paths = request.part_paths();
+                for part_path in &part_paths {
+                    match file_exporter.delete_file(part_path).await {
+                        Ok(bytes) => reclaimed_bytes += bytes,
+                        Err(e) => warn!("Failed to delete {} for request {}: {:?}", part_path, request.id, e),
+                    }
+                }
+                for path in std::iter::once(file_path).chain(part_paths.iter().map(String::as_str)) {
+                    let metadata_path = format!("{}.{}", path, METADATA_FILE_SUFFIX);
+                    match file_exporter.file_exists(&metadata_path).await {
+                        Ok(true) => match file_exporter.delete_file(&metadata_path).await {
+                            Ok(bytes) => reclaimed_bytes += bytes,
+                            Err(e) => warn!("Failed to delete {} for request {}: {:?}", metadata_path, request.id, e),
+                        },
+                        Ok(false) => {}
+                        Err(e) => warn!("Failed to check {} for request {}: {:?}", metadata_path, request.id, e),
+                    }
+                }
+                // Thư mục ngày đã hết file thì xóa luôn, để thư mục export không đầy thư mục rỗng.
+                match file_exporter.remove_empty_dirs(file_path, date_dir_levels).await {
+                    Ok(0) => {}
+                    Ok(dirs) => counter!("excel_export_retention_dirs_removed_total", dirs),
+                    Err(e) => warn!("Failed to remove empty directories of {}: {:?}", file_path, e),
+                }
+            }
+            Err(e) => {
+                warn!("Failed to delete {} for request {}: {:?}. Will retry next run.", file_path, request.id, e);
+                increment!("excel_export_retention_delete_failed_total");
+            }
+        }
+    }
+
+    counter!("excel_export_retention_bytes_reclaimed_total", reclaimed_bytes);
+    info!("🏁 Retention run finished. Reclaimed {} bytes.", reclaimed_bytes);
+    Ok(())
+}
+
+/// Đánh dấu request EXPIRED; lỗi chỉ được ghi log. File đã xóa thì lần chạy sau `delete_file` trả về 0 byte
+/// và request được đánh dấu lại.
+async fn mark_expired<D: DbStore>(db_store: &D, request_id: Uuid) {
+    if let Err(e) = db_store.mark_expired(request_id).await {
+        warn!("Failed to mark request {} as expired: {:?}. Will retry next run.", request_id, e);
+        increment!("excel_export_retention_db_failed_total");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::models::{ExportRequest, ExportStatus, FormulaEscape, ParquetOptions, PdfOptions};
+    use crate::services::db_store::DbError;
+    use crate::services::file_exporter::LocalFileExporter;
+    use crate::services::mock_store::MockDbStore;
+
+    fn exporter() -> LocalFileExporter {
+        LocalFileExporter::new(FormulaEscape::Off, 1_048_576, ParquetOptions::default(), 100, PdfOptions::default())
+    }
+
+    /// Request COMPLETED từ 30 ngày trước, với file (và file `.meta.json`) đã tạo trong `dir`.
+    fn expired_request(db_store: &MockDbStore, dir: &std::path::Path, name: &str) -> (Uuid, std::path::PathBuf) {
+        let file_path = dir.join(name);
+        std::fs::write(&file_path, b"name\nTea\n").unwrap();
+        std::fs::write(dir.join(format!("{}.{}", name, METADATA_FILE_SUFFIX)), b"{}").unwrap();
+        let mut request = ExportRequest::for_test(serde_json::json!({}));
+        request.requested_at = chrono::Utc::now() - ChronoDuration::days(30);
+        request.file_path = Some(file_path.to_string_lossy().into_owned());
+        (db_store.insert(request, ExportStatus::Completed), file_path)
+    }
+
+    #[tokio::test]
+    async fn db_error_on_one_request_does_not_stop_the_run() {
+        let dir = std::env::temp_dir().join(format!("excel-export-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_store = MockDbStore::new();
+        let requests = [expired_request(&db_store, &dir, "first.csv"), expired_request(&db_store, &dir, "second.csv")];
+        // Request được xử lý đầu tiên (thứ tự không cố định) gặp lỗi DB.
+        db_store.fail_next("is_file_shared", DbError::Transient(anyhow::anyhow!("connection reset")));
+
+        let result = cleanup_expired_exports(&SystemClock, 7, false, 0, &db_store, &exporter()).await;
+
+        assert!(result.is_ok());
+        assert_eq!(db_store.calls("is_file_shared"), 2);
+        let (expired, kept): (Vec<_>, Vec<_>) = requests
+            .iter()
+            .partition(|(id, _)| db_store.request(*id).unwrap().status == ExportStatus::Expired);
+        assert_eq!((expired.len(), kept.len()), (1, 1));
+        let metadata_path = |path: &std::path::PathBuf| format!("{}.{}", path.display(), METADATA_FILE_SUFFIX);
+        assert!(!expired[0].1.exists());
+        assert!(!std::path::Path::new(&metadata_path(&expired[0].1)).exists());
+        // Request gặp lỗi giữ nguyên file và trạng thái, để lần chạy sau thử lại.
+        assert!(kept[0].1.exists());
+        assert!(std::path::Path::new(&metadata_path(&kept[0].1)).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
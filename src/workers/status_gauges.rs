@@ -0,0 +1,30 @@
+use metrics::gauge;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::models::ExportStatus;
+use crate::services::db_store::DbStore;
+
+/// Worker cập nhật định kỳ gauge `excel_export_requests{status=...}`: số request hiện có trong DB
+/// theo từng trạng thái (không chỉ các request mà process này đã xử lý).
+/// Khi query lỗi, gauge giữ nguyên giá trị của lần cập nhật trước.
+pub async fn run_status_gauges_worker<D: DbStore>(db_store: Arc<D>, interval_secs: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    info!("📊 Status gauges worker started. Interval: {}s.", interval_secs);
+
+    loop {
+        interval.tick().await;
+        let counts = match db_store.count_by_status().await {
+            Ok(counts) => counts,
+            Err(e) => {
+                warn!("Failed to count export requests by status: {:?}. Keeping previous values.", e);
+                continue;
+            }
+        };
+        for status in ExportStatus::ALL {
+            let count = counts.get(&status).copied().unwrap_or_default();
+            gauge!("excel_export_requests", count as f64, "status" => status.as_str().to_lowercase());
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::Result;
+use chrono::Duration as ChronoDuration;
+use metrics::{counter, increment};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::models::ExportStatus;
+use crate::services::db_store::DbStore;
+
+/// Worker chạy định kỳ để đưa các request bị kẹt ở PROCESSING (ví dụ replica bị kill giữa chừng) về PENDING
+/// (STUCK_PROCESSING_AFTER_SECS), để poller hoặc message Kafka được giao lại xử lý lại chúng. Request đã bị
+/// xử lý STUCK_RECOVERY_MAX_ATTEMPTS lần thì chuyển sang FAILED, vì nhiều khả năng chính nó làm replica bị kill.
+pub async fn run_stuck_recovery_worker<D: DbStore>(
+    config: Arc<AppConfig>,
+    stuck_after_secs: i64,
+    db_store: Arc<D>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.stuck_recovery_interval_secs));
+    info!(
+        "🩺 Stuck request recovery started. Stuck after: {}s, max attempts: {}, batch size: {}, interval: {}s.",
+        stuck_after_secs, config.stuck_recovery_max_attempts, config.stuck_recovery_batch_size, config.stuck_recovery_interval_secs
+    );
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = recover_stuck_requests(
+            ChronoDuration::seconds(stuck_after_secs),
+            config.stuck_recovery_batch_size,
+            config.stuck_recovery_max_attempts,
+            db_store.as_ref(),
+        )
+        .await
+        {
+            error!("Stuck request recovery run failed: {:?}", e);
+        }
+    }
+}
+
+/// Kết quả một lần recovery: số request đã đưa về PENDING và số request đã chuyển sang FAILED.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RecoveryRun {
+    reset: usize,
+    failed: u64,
+}
+
+/// Reset các request PROCESSING không đổi trạng thái trong `stuck_for`; request đã có `max_attempts` lần xử lý
+/// được chuyển sang FAILED bằng `bulk_update_status` (thông báo FAILED do notification retry worker gửi).
+/// Request mà khóa xử lý (advisory lock) vẫn đang bị giữ được bỏ qua: một replica vẫn đang xử lý nó,
+/// chỉ là lâu hơn `stuck_for`. Khóa được giữ trong lúc cập nhật để không replica nào bắt đầu xử lý request đó giữa chừng.
+#[instrument(skip(db_store))]
+async fn recover_stuck_requests<D: DbStore>(
+    stuck_for: ChronoDuration,
+    batch_size: i64,
+    max_attempts: i32,
+    db_store: &D,
+) -> Result<RecoveryRun> {
+    let stuck = db_store.fetch_stuck_processing(stuck_for, batch_size).await?;
+
+    let mut resettable: Vec<Uuid> = Vec::new();
+    let mut exhausted: Vec<Uuid> = Vec::new();
+    for request in &stuck {
+        match db_store.try_lock_request(request.id).await {
+            Ok(true) if request.attempts >= max_attempts => exhausted.push(request.id),
+            Ok(true) => resettable.push(request.id),
+            Ok(false) => {
+                increment!("excel_export_stuck_requests_skipped_total", "reason" => "locked");
+                info!("Request {} has been PROCESSING since {} but is still locked; leaving it.", request.id, request.status_updated_at);
+            }
+            Err(e) => {
+                increment!("excel_export_stuck_requests_skipped_total", "reason" => "lock_failed");
+                warn!("Failed to take the lock of stuck request {}: {:?}", request.id, e);
+            }
+        }
+    }
+
+    let result = update_stuck_requests(&resettable, &exhausted, max_attempts, db_store).await;
+    for request_id in resettable.iter().chain(&exhausted) {
+        if let Err(e) = db_store.unlock_request(*request_id).await {
+            warn!("Failed to release the lock of stuck request {}: {:?}", request_id, e);
+        }
+    }
+    let run = result?;
+
+    counter!("excel_export_stuck_requests_reset_total", run.reset as u64);
+    counter!("excel_export_stuck_requests_failed_total", run.failed);
+    info!(
+        "🏁 Stuck request recovery finished. Reset {} and failed {} of {} stuck request(s).",
+        run.reset, run.failed, stuck.len()
+    );
+    Ok(run)
+}
+
+async fn update_stuck_requests<D: DbStore>(
+    resettable: &[Uuid],
+    exhausted: &[Uuid],
+    max_attempts: i32,
+    db_store: &D,
+) -> Result<RecoveryRun> {
+    let mut run = RecoveryRun::default();
+    if !resettable.is_empty() {
+        run.reset = db_store.reset_to_pending(resettable).await?.len();
+    }
+    if !exhausted.is_empty() {
+        let message = format!("Request was stuck in PROCESSING after {} attempt(s)", max_attempts);
+        run.failed = db_store.bulk_update_status(exhausted, ExportStatus::Failed, Some(message), false).await?;
+    }
+    Ok(run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ExportRequest;
+    use crate::services::db_store::DbError;
+    use crate::services::mock_store::MockDbStore;
+
+    /// Request PROCESSING từ `age` trước, với các trường của lần xử lý dở.
+    fn processing(db_store: &MockDbStore, age: ChronoDuration) -> Uuid {
+        let mut request = ExportRequest::for_test(serde_json::json!({}));
+        request.status_updated_at = chrono::Utc::now() - age;
+        request.file_path = Some("/exports/partial.csv".to_string());
+        request.error_message = Some("interrupted".to_string());
+        db_store.insert(request, ExportStatus::Processing)
+    }
+
+    async fn recover(db_store: &MockDbStore, batch_size: i64) -> Result<RecoveryRun> {
+        recover_stuck_requests(ChronoDuration::minutes(30), batch_size, 3, db_store).await
+    }
+
+    fn reset(count: usize) -> RecoveryRun {
+        RecoveryRun { reset: count, failed: 0 }
+    }
+
+    #[tokio::test]
+    async fn only_requests_older_than_the_threshold_are_reset() {
+        let db_store = MockDbStore::new();
+        let aged = processing(&db_store, ChronoDuration::hours(2));
+        let fresh = processing(&db_store, ChronoDuration::minutes(1));
+
+        let run = recover(&db_store, 10).await.unwrap();
+
+        assert_eq!(run, reset(1));
+        let aged = db_store.request(aged).unwrap();
+        assert_eq!(aged.status, ExportStatus::Pending);
+        assert_eq!((aged.file_path, aged.error_message), (None, None));
+        assert_eq!(db_store.request(fresh).unwrap().status, ExportStatus::Processing);
+    }
+
+    #[tokio::test]
+    async fn request_whose_lock_is_held_is_left_processing() {
+        let db_store = MockDbStore::new();
+        let still_running = processing(&db_store, ChronoDuration::hours(2));
+        let abandoned = processing(&db_store, ChronoDuration::hours(2));
+        // Một worker vẫn đang xử lý request này.
+        assert!(db_store.try_lock_request(still_running).await.unwrap());
+
+        let run = recover(&db_store, 10).await.unwrap();
+
+        assert_eq!(run, reset(1));
+        assert_eq!(db_store.request(still_running).unwrap().status, ExportStatus::Processing);
+        assert_eq!(db_store.request(abandoned).unwrap().status, ExportStatus::Pending);
+        // Khóa lấy cho lần reset đã được trả, khóa của worker kia vẫn còn.
+        assert!(db_store.try_lock_request(abandoned).await.unwrap());
+        assert!(!db_store.try_lock_request(still_running).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn lock_error_skips_only_that_request() {
+        let db_store = MockDbStore::new();
+        let first = processing(&db_store, ChronoDuration::hours(3));
+        let second = processing(&db_store, ChronoDuration::hours(2));
+        db_store.fail_next("try_lock_request", DbError::Transient(anyhow::anyhow!("connection reset")));
+
+        let run = recover(&db_store, 10).await.unwrap();
+
+        // Request cũ nhất được thử khóa trước.
+        assert_eq!(run, reset(1));
+        assert_eq!(db_store.request(first).unwrap().status, ExportStatus::Processing);
+        assert_eq!(db_store.request(second).unwrap().status, ExportStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn failed_reset_still_releases_the_locks() {
+        let db_store = MockDbStore::new();
+        let request_id = processing(&db_store, ChronoDuration::hours(2));
+        db_store.fail_next("reset_to_pending", DbError::Transient(anyhow::anyhow!("connection reset")));
+
+        assert!(recover(&db_store, 10).await.is_err());
+
+        assert_eq!(db_store.request(request_id).unwrap().status, ExportStatus::Processing);
+        assert_eq!(db_store.calls("unlock_request"), 1);
+        assert!(db_store.try_lock_request(request_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn batch_size_limits_a_run() {
+        let db_store = MockDbStore::new();
+        for hours in 1..=3 {
+            processing(&db_store, ChronoDuration::hours(hours));
+        }
+
+        assert_eq!(recover(&db_store, 2).await.unwrap(), reset(2));
+        assert_eq!(recover(&db_store, 2).await.unwrap(), reset(1));
+        assert_eq!(recover(&db_store, 2).await.unwrap(), reset(0));
+    }
+
+    #[tokio::test]
+    async fn request_stuck_after_its_last_attempt_is_failed_instead_of_reset() {
+        let db_store = MockDbStore::new();
+        let retried = processing(&db_store, ChronoDuration::hours(2));
+        let exhausted = processing(&db_store, ChronoDuration::hours(2));
+        let mut request = db_store.request(exhausted).unwrap();
+        request.attempts = 3;
+        db_store.insert(request, ExportStatus::Processing);
+
+        let run = recover(&db_store, 10).await.unwrap();
+
+        assert_eq!(run, RecoveryRun { reset: 1, failed: 1 });
+        assert_eq!(db_store.request(retried).unwrap().status, ExportStatus::Pending);
+        let exhausted_request = db_store.request(exhausted).unwrap();
+        assert_eq!(exhausted_request.status, ExportStatus::Failed);
+        assert_eq!(
+            exhausted_request.error_message.as_deref(),
+            Some("Request was stuck in PROCESSING after 3 attempt(s)")
+        );
+        assert!(!exhausted_request.notification_sent);
+        assert!(db_store.try_lock_request(exhausted).await.unwrap());
+    }
+}
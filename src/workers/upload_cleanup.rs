@@ -0,0 +1,30 @@
+use chrono::Duration as ChronoDuration;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::clock::Clock;
+use crate::services::file_exporter::FileExporter;
+
+/// Upload dở dang cũ hơn thời gian này chắc chắn không còn process nào hoàn tất.
+const STALE_UPLOAD_AGE_HOURS: i64 = 24;
+
+/// Khoảng thời gian giữa hai lần dọn upload dở dang.
+const UPLOAD_CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Worker định kỳ hủy các upload dở dang (multipart upload của S3) bắt đầu từ hơn một ngày trước,
+/// ví dụ của process bị dừng giữa lúc upload. Lỗi chỉ được ghi log, lần chạy sau sẽ thử lại.
+pub async fn run_upload_cleanup_worker<F: FileExporter>(clock: Arc<dyn Clock>, file_exporter: Arc<F>) {
+    let mut interval = tokio::time::interval(UPLOAD_CLEANUP_INTERVAL);
+    info!("🧹 Upload cleanup worker started. Aborting uploads older than {}h.", STALE_UPLOAD_AGE_HOURS);
+
+    loop {
+        interval.tick().await;
+        let before = clock.now_utc() - ChronoDuration::hours(STALE_UPLOAD_AGE_HOURS);
+        match file_exporter.abort_stale_uploads(before).await {
+            Ok(0) => {}
+            Ok(aborted) => info!("🧹 Aborted {} stale upload(s).", aborted),
+            Err(e) => warn!("Failed to abort stale uploads: {:?}", e),
+        }
+    }
+}